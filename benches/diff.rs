@@ -0,0 +1,164 @@
+//! Timing the diff pipeline on generated projects, as a baseline for performance work
+//!
+//! Run with `cargo bench --bench diff`. Save a baseline before a change with
+//! `cargo bench --bench diff -- --save-baseline main`, then compare against it with
+//! `-- --baseline main`. Projects are generated rather than read from disk, so runs on different
+//! machines time the same work.
+
+use std::collections::BTreeMap;
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use serde_json::json;
+
+use pixelpioneers_core::diff::parse_script::{parse_sprite, Sprite};
+use pixelpioneers_core::diff::{self, structs::Diff};
+use pixelpioneers_core::git;
+use pixelpioneers_core::sb3::{Block, BlockEntry, Costume, Project, Target};
+use pixelpioneers_core::temp::TempWorkspace;
+
+/// Blocks in each generated script, counting its hat block
+const SCRIPT_LENGTH: usize = 10;
+
+/// Represents the size of the generated projects
+#[derive(Debug, Clone, Copy)]
+struct Corpus {
+    sprites: usize,
+    /// Blocks in each sprite
+    blocks: usize,
+    /// Costumes in each sprite
+    assets: usize,
+}
+
+impl Default for Corpus {
+    fn default() -> Self {
+        Corpus {
+            sprites: 50,
+            blocks: 500,
+            assets: 10,
+        }
+    }
+}
+
+impl Corpus {
+    /// Generate a revision of the project
+    ///
+    /// Later revisions add blocks to every other sprite and replace a costume in every third,
+    /// so two revisions have something to diff
+    fn project(&self, revision: usize) -> Project {
+        let stage = Target {
+            is_stage: true,
+            name: "Stage".into(),
+            ..Default::default()
+        };
+        let sprites = (0..self.sprites).map(|i| {
+            let blocks = self.blocks + revision * (i % 2) * SCRIPT_LENGTH;
+            let costumes = (0..self.assets)
+                .map(|k| {
+                    let changed = if i % 3 == 0 && k == 0 { revision } else { 0 };
+                    costume(&format!("Sprite{i} costume{k} {changed}"))
+                })
+                .collect();
+            Target {
+                name: format!("Sprite{i}"),
+                blocks: scripts(blocks),
+                costumes,
+                ..Default::default()
+            }
+        });
+
+        Project {
+            targets: std::iter::once(stage).chain(sprites).collect(),
+            ..Default::default()
+        }
+    }
+}
+
+/// Generate scripts of a green flag hat and `move (10) steps` blocks
+fn scripts(blocks: usize) -> BTreeMap<String, BlockEntry> {
+    (0..blocks)
+        .map(|j| {
+            let top_level = j % SCRIPT_LENGTH == 0;
+            let last = (j + 1) % SCRIPT_LENGTH == 0 || j + 1 == blocks;
+            let (opcode, inputs) = if top_level {
+                ("event_whenflagclicked", json!({}))
+            } else {
+                ("motion_movesteps", json!({ "STEPS": [1, [4, "10"]] }))
+            };
+            let block = Block {
+                opcode: opcode.into(),
+                next: (!last).then(|| format!("b{}", j + 1)),
+                parent: (!top_level).then(|| format!("b{}", j - 1)),
+                inputs: inputs.as_object().cloned().unwrap_or_default(),
+                top_level,
+                ..Default::default()
+            };
+            (format!("b{j}"), BlockEntry::Block(block))
+        })
+        .collect()
+}
+
+/// A costume named by the MD5 of `seed`, like a real asset is named by its contents
+fn costume(seed: &str) -> Costume {
+    let asset_id = format!("{:x}", md5::compute(seed));
+    Costume {
+        name: seed.into(),
+        md5ext: Some(format!("{asset_id}.svg")),
+        asset_id,
+        data_format: "svg".into(),
+        ..Default::default()
+    }
+}
+
+/// Time asset diffing, script parsing, script diffing and commit messages on a corpus
+///
+/// Scripts are diffed in a throwaway repository, since Git does the line diffs. The parsed
+/// script cache is cleared before each iteration, so every run parses from scratch
+fn diff_pipeline(c: &mut Criterion) {
+    let workspace = TempWorkspace::new("bench").unwrap();
+    let cwd = workspace.path();
+    git::run(vec!["init", "--quiet"], Some(cwd))
+        .output()
+        .unwrap();
+    let corpus = Corpus::default();
+    let old = Diff::new(corpus.project(0));
+    let new = Diff::new(corpus.project(1));
+
+    let mut group = c.benchmark_group("diff");
+    group.sample_size(10);
+    group.bench_function("assets", |b| {
+        b.iter_batched(
+            diff::clear_script_cache,
+            |()| old.assets(&new, None).unwrap(),
+            BatchSize::PerIteration,
+        )
+    });
+    group.bench_function("parse_sprite", |b| {
+        b.iter_batched(
+            diff::clear_script_cache,
+            |()| {
+                for target in &new.data.targets {
+                    parse_sprite(Sprite::of(target)).unwrap();
+                }
+            },
+            BatchSize::PerIteration,
+        )
+    });
+    group.bench_function("blocks", |b| {
+        b.iter_batched(
+            diff::clear_script_cache,
+            |()| old.blocks(cwd, &new).unwrap(),
+            BatchSize::PerIteration,
+        )
+    });
+    group.bench_function("commits", |b| {
+        b.iter_batched(
+            diff::clear_script_cache,
+            |()| old.commits(cwd, &new).unwrap(),
+            BatchSize::PerIteration,
+        )
+    });
+    group.finish();
+}
+
+criterion_group!(benches, diff_pipeline);
+criterion_main!(benches);
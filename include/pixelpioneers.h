@@ -0,0 +1,43 @@
+#ifndef PIXELPIONEERS_H
+#define PIXELPIONEERS_H
+
+/* Generated by cbindgen from src-server/ffi.rs, so don't edit it by hand */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+// Diff two project.json files, returning the report as JSON, or `NULL` if it failed
+//
+// Scripts are diffed in the Git repository at `repo`, with its settings. If `repo` is `NULL`,
+// a scratch repository is used with the default settings.
+//
+// # Safety
+// `old_json` and `new_json` have to be NUL-terminated strings, and `repo` one too or `NULL`.
+// The returned string has to be freed with [`pp_string_free`].
+char *pp_diff_report(const char *old_json, const char *new_json, const char *repo);
+
+// Diff two project.json files like [`pp_diff_report`], returning a Markdown summary instead,
+// like for a pull request description
+//
+// # Safety
+// Same as [`pp_diff_report`]
+char *pp_diff_markdown(const char *old_json, const char *new_json, const char *repo);
+
+// Why the calling thread's last call failed, or `NULL` if it didn't
+//
+// The string is owned by the library and only valid until the thread's next call, so it
+// mustn't be freed.
+const char *pp_last_error(void);
+
+// Version of the library, like `0.1.0`, which mustn't be freed
+const char *pp_version(void);
+
+// Free a string returned by the library
+//
+// # Safety
+// `string` has to be `NULL` or a string returned by this library that isn't freed yet.
+void pp_string_free(char *string);
+
+#endif  /* PIXELPIONEERS_H */
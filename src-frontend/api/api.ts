@@ -29,9 +29,169 @@ export interface CostumeChange {
   onStage: boolean;
   sprite: string;
   kind: "before" | "after";
-  contents: number[];
 }
 
+export interface ProjectStats {
+  sprites: number;
+  scripts: number;
+  /** Block counts by category, e.g. `motion` or `pen` */
+  blocks: Record<string, number>;
+  variables: number;
+  lists: number;
+  assets: number;
+  asset_bytes: number;
+}
+
+/** Blocks changed in a sprite since the last commit, by their IDs in the editor */
+export interface BlockChanges {
+  sprite: string;
+  onStage: boolean;
+  added: string[];
+  /** Blocks only the last commit has, which aren't in the editor anymore */
+  removed: string[];
+  modified: string[];
+}
+
+/** The shared Scratch project a project was started from */
+export interface ProjectSource {
+  projectId: number;
+  title: string;
+  author: string;
+  remixParent: number | null;
+  remixRoot: number | null;
+}
+
+/** A teammate's claim on a sprite, from `.scratch-locks.json` */
+export interface SpriteLock {
+  owner: { name: string; email: string };
+  /** Seconds since the Unix epoch when it was claimed */
+  since: number;
+  note?: string;
+}
+
+/** An idea being tried out on its own branch, which can be kept or thrown away */
+export interface Experiment {
+  name: string;
+  branch: string;
+  /** Branch the experiment was started from */
+  base: string;
+}
+
+/** A commit that changed a sprite, in the sprite's timeline */
+export interface TimelineNode {
+  commit: string;
+  subject: string;
+  author: { date: string; email: string; name: string };
+  /** Local branches whose latest change to the sprite is this commit */
+  branches: string[];
+  deleted: boolean;
+}
+
+/** Everything one person changed across the project's history */
+export interface Contributor {
+  name: string;
+  email: string;
+  commits: number;
+  /** Sprites they changed, where the stage ends with ` (stage)` */
+  sprites: string[];
+  blocksAdded: number;
+  blocksRemoved: number;
+  assetsAdded: number;
+  assetsRemoved: number;
+  variablesChanged: number;
+}
+
+/** A sprite's changes across every local branch, with edges to each commit's nearest earlier changes */
+export interface SpriteTimeline {
+  sprite: string;
+  nodes: TimelineNode[];
+  edges: { child: string; parent: string }[];
+}
+
+/** A costume or sound from an earlier commit that the project no longer has */
+export interface DeletedAsset {
+  revision: string;
+  sprite: string;
+  name: string;
+  path: string;
+  isSound: boolean;
+}
+
+/** Everything that changed in a sprite between two revisions */
+export interface SpriteChangelog {
+  blocksAdded: number;
+  blocksRemoved: number;
+  assetsAdded: string[];
+  assetsRemoved: string[];
+  assetsModified: string[];
+  variables: string[];
+}
+
+/** The changes between two revisions, grouped by sprite */
+export interface Changelog {
+  from: string;
+  to: string;
+  commits: number;
+  sprites: Record<string, SpriteChangelog>;
+  extensions: string[];
+}
+
+/** The commit that committing the saved project would make */
+export interface CommitPreview {
+  message: string;
+  report: any;
+  added_assets: string[];
+  removed_assets: string[];
+}
+
+/** A tagged version of a project and where its build was saved */
+export interface Release {
+  tag: string;
+  commit: string;
+  message: string;
+  build: string | null;
+}
+
+/** Someone commits can be attributed to */
+export interface Identity {
+  name: string;
+  email: string;
+}
+
+/** Assets only old commits have, from `AssetPurge` in src-server/ops.rs */
+export interface AssetPurge {
+  /** Largest first, with their sizes in bytes */
+  assets: { path: string; size: number }[];
+  savings: number;
+  confirmation: string;
+  /** Bytes freed, once purged */
+  freed: number | null;
+}
+
+/** Kinds of failures the server tells apart, from `Error::kind` in src-server/error.rs */
+export type ErrorKind =
+  | "git-unavailable"
+  | "revision-not-found"
+  | "malformed-project"
+  | "parse-failure"
+  | "too-large"
+  | "io-error";
+
+/** What to tell the user for failures they can do something about */
+const ERROR_HINTS: Partial<Record<ErrorKind, string>> = {
+  "git-unavailable":
+    "Git couldn't be found. Please install it from https://git-scm.com and restart scratch.git.",
+  "malformed-project":
+    "This project has a broken script. Try opening and saving it again in the editor.",
+  "too-large": "This project is too large or too deeply nested to read safely.",
+};
+
+/** A change from a diff to include in a partial commit */
+export type ChangeSelection =
+  | { kind: "sprite"; sprite: string }
+  | { kind: "script"; sprite: string; scriptId: string }
+  | { kind: "asset"; sprite: string; path: string };
+
 interface ProjectCreationDetails {
   username: string;
   email: string;
@@ -60,6 +220,10 @@ class Socket {
         try {
           console.debug("message", message.data);
           const json = JSON.parse(message.data);
+          if (json["unhandled-error"] && json.kind in ERROR_HINTS) {
+            alert(ERROR_HINTS[json.kind as ErrorKind]);
+            return;
+          }
           if (json["unhandled-error"]) {
             alert(
               `An unhandled error occurred. Please check the console for errors using Ctrl+Shift+I.`
@@ -99,6 +263,9 @@ class Socket {
 }
 
 export class Project extends Socket {
+  /** Display name of whoever's using the editor, sent with commits to attribute them */
+  user: string | null = null;
+
   /** Constructs a project */
   constructor(public projectName: string, protected ws: WebSocket) {
     super(ws);
@@ -163,6 +330,21 @@ export class Project extends Socket {
     ).data;
   }
 
+  /** Read a changed costume or sound, only once it's previewed */
+  // LINK src-server/handlers.rs#asset-contents
+  async getAssetContents(change: CostumeChange): Promise<number[]> {
+    return (
+      (
+        await this.request({
+          command: "asset-contents",
+          data: {
+            Asset: { project_name: this.projectName, path: change.path },
+          },
+        })
+      ).contents ?? []
+    );
+  }
+
   /** Get the current scripts of a project's JSON */
   // LINK src-server/handlers.rs#get-sprite-scripts
   async getCurrentScripts(sprite: string) {
@@ -189,17 +371,164 @@ export class Project extends Socket {
     );
   }
 
-  /** Commit the current project to Git */
+  /** Commit the current project to Git, optionally titled with the generated message as body */
   // LINK src-server/handlers.rs#commit
-  async commit(): Promise<string | number> {
+  async commit(title?: string): Promise<string | number> {
     return (
       await this.request({
         command: "commit",
-        data: { Project: { project_name: this.projectName } },
+        data:
+          title || this.user
+            ? {
+                Commit: {
+                  project_name: this.projectName,
+                  author: null,
+                  title,
+                  user: this.user,
+                },
+              }
+            : { Project: { project_name: this.projectName } },
       })
     ).message;
   }
 
+  /** Commit only some sprites' changes, leaving the rest uncommitted */
+  async commitSprites(sprites: string[]): Promise<string | number> {
+    return this.commitChanges(
+      sprites.map((sprite) => ({ kind: "sprite", sprite }))
+    );
+  }
+
+  /** Commit only the selected changes, like single scripts or costumes */
+  // LINK src-server/handlers.rs#commit
+  async commitChanges(changes: ChangeSelection[]): Promise<string | number> {
+    return (
+      await this.request({
+        command: "commit",
+        data: {
+          Commit: {
+            project_name: this.projectName,
+            author: null,
+            changes,
+            user: this.user,
+          },
+        },
+      })
+    ).message;
+  }
+
+  /** List who works on the project, who commits can be attributed to by name */
+  // LINK src-server/handlers.rs#get-roster
+  async getRoster(): Promise<Identity[]> {
+    return (
+      await this.request({
+        command: "get-roster",
+        data: { Project: { project_name: this.projectName } },
+      })
+    ).roster;
+  }
+
+  /** Set who works on the project, returning whether everyone had a name and email */
+  // LINK src-server/handlers.rs#set-roster
+  async setRoster(roster: Identity[]): Promise<boolean> {
+    return (
+      await this.request({
+        command: "set-roster",
+        data: { Roster: { project_name: this.projectName, roster } },
+      })
+    ).success;
+  }
+
+  /** Show the commit that committing would make, without committing */
+  // LINK src-server/handlers.rs#preview-commit
+  async previewCommit(
+    title?: string,
+    changes?: ChangeSelection[]
+  ): Promise<CommitPreview | number> {
+    const response = await this.request({
+      command: "preview-commit",
+      data: {
+        Commit: {
+          project_name: this.projectName,
+          author: null,
+          changes,
+          title,
+          user: this.user,
+        },
+      },
+    });
+    return response.preview ?? response.message;
+  }
+
+  /** Find which experiment the project is trying out, if any */
+  // LINK src-server/handlers.rs#get-experiment
+  async getExperiment(): Promise<Experiment | null> {
+    return (
+      await this.request({
+        command: "get-experiment",
+        data: { Experiment: { project_name: this.projectName, name: null, force: false } },
+      })
+    ).experiment;
+  }
+
+  /** Try out an idea on a new branch, which can be kept or thrown away later */
+  // LINK src-server/handlers.rs#start-experiment
+  async startExperiment(
+    name: string
+  ): Promise<{ status: "success" | "fail"; experiment?: Experiment; message?: string }> {
+    return await this.request({
+      command: "start-experiment",
+      data: { Experiment: { project_name: this.projectName, name, force: false } },
+    });
+  }
+
+  /** Keep the experiment, merging it back into the branch it was started from */
+  // LINK src-server/handlers.rs#keep-experiment
+  async keepExperiment(): Promise<
+    | { status: "kept"; message: string }
+    | { status: "conflicts"; files: string[] }
+    | { status: "uncommitted changes" }
+  > {
+    return await this.request({
+      command: "keep-experiment",
+      data: { Experiment: { project_name: this.projectName, name: null, force: false } },
+    });
+  }
+
+  /** Throw the experiment away, going back to how the project was before it */
+  // LINK src-server/handlers.rs#discard-experiment
+  async discardExperiment(
+    force = false
+  ): Promise<{ status: "success" | "uncommitted changes"; experiment?: Experiment }> {
+    return await this.request({
+      command: "discard-experiment",
+      data: { Experiment: { project_name: this.projectName, name: null, force } },
+    });
+  }
+
+  /** Mark the last commit as where a working session starts */
+  // LINK src-server/handlers.rs#start-session
+  async startSession(): Promise<string> {
+    return (
+      await this.request({
+        command: "start-session",
+        data: { Project: { project_name: this.projectName } },
+      })
+    ).start;
+  }
+
+  /** Squash every commit since the session started, like auto-commits, into one */
+  // LINK src-server/handlers.rs#finish-session
+  async finishSession(): Promise<{
+    status: "success" | "nothing to squash" | "fail";
+    message?: string;
+  }> {
+    return await this.request({
+      command: "finish-session",
+      data: { Project: { project_name: this.projectName } },
+    });
+  }
+
   /** Push the current project to the configured remote, unused right now */
   // LINK src-server/handlers.rs#push
   async push(): Promise<PushMsg> {
@@ -262,6 +591,419 @@ export class Project extends Socket {
       data: { Project: { project_name: this.projectName } },
     });
   }
+
+  /** Count the project's sprites, scripts, blocks, variables and assets */
+  // LINK src-server/handlers.rs#project-stats
+  async stats(): Promise<ProjectStats> {
+    return await this.request({
+      command: "project-stats",
+      data: { Project: { project_name: this.projectName } },
+    });
+  }
+
+  /** Get a picture of the stage at a commit, as an object URL */
+  // LINK src-server/handlers.rs#get-thumbnail
+  async getThumbnail(commit: string): Promise<string | undefined> {
+    const response = await this.request({
+      command: "get-thumbnail",
+      data: { Revision: { project_name: this.projectName, revision: commit } },
+    });
+    if (response.status !== "success") return;
+    return URL.createObjectURL(
+      new Blob([new Uint8Array(response.thumbnail)], { type: "image/png" })
+    );
+  }
+
+  /** Save a sprite as a .sprite3 file, optionally as it was in a past commit */
+  // LINK src-server/handlers.rs#export-sprite
+  async exportSprite(
+    sprite: string,
+    revision?: string
+  ): Promise<"success" | "cancelled"> {
+    return (
+      await this.request({
+        command: "export-sprite",
+        data: {
+          Sprite: {
+            project_name: this.projectName,
+            sprite_name: sprite,
+            revision: revision ?? null,
+          },
+        },
+      })
+    ).status;
+  }
+
+  /** Bring back a sprite as it was in a past commit, committing the restore */
+  // LINK src-server/handlers.rs#restore-sprite
+  async restoreSprite(
+    sprite: string,
+    revision: string
+  ): Promise<{ status: "success" | "unknown revision"; message?: string }> {
+    return await this.request({
+      command: "restore-sprite",
+      data: {
+        Sprite: {
+          project_name: this.projectName,
+          sprite_name: sprite,
+          revision,
+        },
+      },
+    });
+  }
+
+  /** Get who has claimed which sprites, by sprite name */
+  // LINK src-server/handlers.rs#get-locks
+  async getLocks(): Promise<Record<string, SpriteLock>> {
+    return (
+      await this.request({
+        command: "get-locks",
+        data: { Project: { project_name: this.projectName } },
+      })
+    ).locks;
+  }
+
+  /** Claim a sprite so teammates are warned before changing it */
+  // LINK src-server/handlers.rs#claim-sprite
+  async claimSprite(
+    sprite: string,
+    note?: string
+  ): Promise<{ status: "success" | "claimed"; lock?: SpriteLock; message?: string }> {
+    return await this.request({
+      command: "claim-sprite",
+      data: {
+        Lock: {
+          project_name: this.projectName,
+          sprite_name: sprite,
+          note: note ?? null,
+          force: false,
+        },
+      },
+    });
+  }
+
+  /** Give up a claim on a sprite, or someone else's with `force` */
+  // LINK src-server/handlers.rs#release-sprite
+  async releaseSprite(
+    sprite: string,
+    force = false
+  ): Promise<{ status: "success" | "not claimed" | "claimed"; message?: string }> {
+    return await this.request({
+      command: "release-sprite",
+      data: {
+        Lock: {
+          project_name: this.projectName,
+          sprite_name: sprite,
+          note: null,
+          force,
+        },
+      },
+    });
+  }
+
+  /** Get the commits that changed a sprite across every branch, for drawing its timeline */
+  // LINK src-server/handlers.rs#sprite-timeline
+  async spriteTimeline(sprite: string): Promise<SpriteTimeline> {
+    return (
+      await this.request({
+        command: "sprite-timeline",
+        data: {
+          Sprite: {
+            project_name: this.projectName,
+            sprite_name: sprite,
+            revision: null,
+          },
+        },
+      })
+    ).timeline;
+  }
+
+  /** Search history for deleted costumes and sounds by name or hash */
+  // LINK src-server/handlers.rs#find-deleted-assets
+  async findDeletedAssets(query: string): Promise<DeletedAsset[]> {
+    return (
+      await this.request({
+        command: "find-deleted-assets",
+        data: { AssetSearch: { project_name: this.projectName, query } },
+      })
+    ).assets;
+  }
+
+  /** Add a deleted costume or sound from a past commit back to a sprite */
+  // LINK src-server/handlers.rs#restore-asset
+  async restoreAsset(
+    asset: DeletedAsset,
+    sprite = asset.sprite
+  ): Promise<string> {
+    return (
+      await this.request({
+        command: "restore-asset",
+        data: {
+          RestoreAsset: {
+            project_name: this.projectName,
+            revision: asset.revision,
+            path: asset.path,
+            sprite_name: sprite,
+          },
+        },
+      })
+    ).name;
+  }
+
+  /** Summarize the changes between two revisions as Markdown and by sprite */
+  // LINK src-server/handlers.rs#changelog
+  async changelog(
+    from: string,
+    to?: string
+  ): Promise<{ markdown: string; changelog: Changelog }> {
+    return await this.request({
+      command: "changelog",
+      data: { Revisions: { project_name: this.projectName, from, to } },
+    });
+  }
+
+  /** Compare the projects on two branches, like before merging one into the other */
+  // LINK src-server/handlers.rs#diff-branches
+  async diffBranches(from: string, to?: string): Promise<any | undefined> {
+    const response = await this.request({
+      command: "diff-branches",
+      data: { Revisions: { project_name: this.projectName, from, to } },
+    });
+    if (response.status !== "success") return;
+    return response.report;
+  }
+
+  /** Save the whole project as it was at a revision as an .sb3 file */
+  // LINK src-server/handlers.rs#export-project
+  async exportProject(revision: string): Promise<"success" | "cancelled"> {
+    return (
+      await this.request({
+        command: "export-project",
+        data: { Revision: { project_name: this.projectName, revision } },
+      })
+    ).status;
+  }
+
+  /** Save the uncommitted changes as a standalone web page, for sharing */
+  // LINK src-server/handlers.rs#export-diff-html
+  async exportDiffHtml(): Promise<"success" | "cancelled"> {
+    return (
+      await this.request({
+        command: "export-diff-html",
+        data: { Project: { project_name: this.projectName } },
+      })
+    ).status;
+  }
+
+  /** Save every commit's changes, sprite by sprite, as CSV or JSON lines for analysis */
+  // LINK src-server/handlers.rs#export-history
+  async exportHistory(): Promise<"success" | "cancelled"> {
+    return (
+      await this.request({
+        command: "export-history",
+        data: { Project: { project_name: this.projectName } },
+      })
+    ).status;
+  }
+
+  /** Sum up the project's history by author, most commits first, for seeing who did what */
+  // LINK src-server/handlers.rs#get-contributors
+  async getContributors(): Promise<Contributor[]> {
+    return (
+      await this.request({
+        command: "get-contributors",
+        data: { Project: { project_name: this.projectName } },
+      })
+    ).contributors;
+  }
+
+  /** Summarize the uncommitted changes as Markdown, for pasting into a pull request */
+  // LINK src-server/handlers.rs#diff-markdown
+  async diffMarkdown(): Promise<string> {
+    return (
+      await this.request({
+        command: "diff-markdown",
+        data: { Project: { project_name: this.projectName } },
+      })
+    ).markdown;
+  }
+
+  /** Write the added and changed scripts in scratchblocks markup, for posting on the forums */
+  // LINK src-server/handlers.rs#forum-post
+  async forumPost(): Promise<string> {
+    return (
+      await this.request({
+        command: "forum-post",
+        data: { Project: { project_name: this.projectName } },
+      })
+    ).post;
+  }
+
+  /** Get the IDs of the blocks changed in each sprite since the last commit, for highlighting */
+  // LINK src-server/handlers.rs#changed-blocks
+  async getChangedBlocks(): Promise<BlockChanges[]> {
+    return (
+      await this.request({
+        command: "changed-blocks",
+        data: { Project: { project_name: this.projectName } },
+      })
+    ).sprites;
+  }
+
+  /** Add a sprite from a .sprite3 file to the project
+   *
+   * @returns the name the sprite was given, or undefined if the user cancelled
+   */
+  // LINK src-server/handlers.rs#import-sprite
+  async importSprite(): Promise<string | undefined> {
+    return (
+      await this.request({
+        command: "import-sprite",
+        data: { Project: { project_name: this.projectName } },
+      })
+    ).name;
+  }
+
+  /** Save the changes between two revisions as a patch another copy of the project can apply */
+  // LINK src-server/handlers.rs#export-patch
+  async exportPatch(
+    from: string,
+    to?: string
+  ): Promise<"success" | "cancelled"> {
+    return (
+      await this.request({
+        command: "export-patch",
+        data: { Revisions: { project_name: this.projectName, from, to } },
+      })
+    ).status;
+  }
+
+  /** Apply a patch from another copy of the project and commit it
+   *
+   * @returns the commit message, or undefined if the user cancelled
+   */
+  // LINK src-server/handlers.rs#apply-patch
+  async applyPatch(): Promise<string | undefined> {
+    return (
+      await this.request({
+        command: "apply-patch",
+        data: { Project: { project_name: this.projectName } },
+      })
+    ).message;
+  }
+
+  /** Tag the last commit as a version and build it, packaging it if a packager is set up */
+  // LINK src-server/handlers.rs#create-tag
+  async createTag(name: string, message?: string): Promise<Release> {
+    return (
+      await this.request({
+        command: "create-tag",
+        data: { Tag: { project_name: this.projectName, name, message } },
+      })
+    ).release;
+  }
+
+  /** Release the last commit as a version, with notes from the changes since the last tag */
+  // LINK src-server/handlers.rs#create-release
+  async createRelease(version: string, notes?: string): Promise<Release> {
+    return (
+      await this.request({
+        command: "create-release",
+        data: { Tag: { project_name: this.projectName, name: version, message: notes } },
+      })
+    ).release;
+  }
+
+  /** List the tagged versions of the project, newest first */
+  // LINK src-server/handlers.rs#get-tags
+  async getTags(): Promise<Release[]> {
+    return (
+      await this.request({
+        command: "get-tags",
+        data: { Project: { project_name: this.projectName } },
+      })
+    ).releases;
+  }
+
+  /** Find assets that are missing or don't match their MD5 hashes */
+  // LINK src-server/handlers.rs#verify-assets
+  async verifyAssets(): Promise<{ path: string; message: string }[]> {
+    return (
+      await this.request({
+        command: "verify-assets",
+        data: { Project: { project_name: this.projectName } },
+      })
+    ).problems;
+  }
+
+  /** Find likely mistakes like scripts that never run or deleted variables still in use */
+  // LINK src-server/handlers.rs#lint
+  async lint(): Promise<{ path: string; message: string }[]> {
+    return (
+      await this.request({
+        command: "lint",
+        data: { Project: { project_name: this.projectName } },
+      })
+    ).issues;
+  }
+
+  /** Delete assets the project no longer uses, returning their names */
+  // LINK src-server/handlers.rs#clean-unused-assets
+  async cleanUnusedAssets(): Promise<string[]> {
+    return (
+      await this.request({
+        command: "clean-unused-assets",
+        data: { Project: { project_name: this.projectName } },
+      })
+    ).removed;
+  }
+
+  /**
+   * List assets only old commits have, with how much space purging them would save, or purge
+   * them from history by passing back the listing's confirmation
+   */
+  // LINK src-server/handlers.rs#purge-assets
+  async purgeAssets(minSize?: number, confirmation?: string): Promise<AssetPurge> {
+    return (
+      await this.request({
+        command: "purge-assets",
+        data: {
+          Purge: { project_name: this.projectName, min_size: minSize, confirmation },
+        },
+      })
+    ).purge;
+  }
+
+  /** Get the shared Scratch project this project was started from, if it was */
+  // LINK src-server/handlers.rs#get-source
+  async getSource(): Promise<ProjectSource | null> {
+    return (
+      await this.request({
+        command: "get-source",
+        data: { Project: { project_name: this.projectName } },
+      })
+    ).source;
+  }
+
+  /** Diff the project against the current version of the shared project it was started from */
+  // LINK src-server/handlers.rs#diff-upstream
+  async diffUpstream(): Promise<any | undefined> {
+    const response = await this.request({
+      command: "diff-upstream",
+      data: { Project: { project_name: this.projectName } },
+    });
+    if (response.status !== "success") return;
+    return response.report;
+  }
+
+  /** Store the project in its repository as one file per sprite */
+  // LINK src-server/handlers.rs#enable-split-storage
+  async enableSplitStorage(): Promise<void> {
+    await this.request({
+      command: "enable-split-storage",
+      data: { Project: { project_name: this.projectName } },
+    });
+  }
 }
 
 /** Represents a connection to fetch and initialize projects */
@@ -318,6 +1060,59 @@ export class ProjectManager extends Socket {
     return new Project(response.project_name, this.ws);
   }
 
+  /**
+   * Start a new project from one shared on the Scratch website
+   *
+   * @param url - a link to the shared project, or its ID
+   * @throws {Error}
+   */
+  // LINK src-server/handlers.rs#remix-project
+  async remixProject(
+    url: string,
+    username: string,
+    email: string
+  ): Promise<Project> {
+    const response = await this.request({
+      command: "remix-project",
+      data: { Remix: { url, username, email } },
+    });
+    if (response.status === "exists") {
+      throw new Error("A project with this name already exists.");
+    } else if (response.status === "needs_info") {
+      throw new Error("Enter a name and email to commit with first.");
+    } else if (response.status) {
+      throw new Error(`Couldn't start a project from ${url}.`);
+    }
+    return new Project(response.project_name, this.ws);
+  }
+
+  /**
+   * Start versioning a project shared on the Scratch website, with the import as its first
+   * commit
+   *
+   * @param url - a link to the shared project, or its ID
+   * @throws {Error}
+   */
+  // LINK src-server/handlers.rs#import-project
+  async importProject(
+    url: string,
+    username: string,
+    email: string
+  ): Promise<Project> {
+    const response = await this.request({
+      command: "import-project",
+      data: { Remix: { url, username, email } },
+    });
+    if (response.status === "exists") {
+      throw new Error("A project with this name already exists.");
+    } else if (response.status === "needs_info") {
+      throw new Error("Enter a name and email to commit with first.");
+    } else if (response.status) {
+      throw new Error(`Couldn't import ${url}.`);
+    }
+    return new Project(response.project_name, this.ws);
+  }
+
   /** Get the current project based on the project name */
   getCurrentProject(): Project | undefined {
     return new Project(Redux.getState().scratchGui.projectTitle, this.ws);
@@ -391,3 +1186,35 @@ export const uninstall = async () => {
     data: { Project: { project_name: "" } },
   });
 };
+
+export interface ServerStatus {
+  version: string;
+  address: {
+    host: string;
+    port: number;
+    tls: boolean;
+    socket: string | null;
+  } | null;
+  projects: { name: string; loaded: boolean; watching: boolean }[];
+  git: { version: string | null; lfs: boolean };
+  caches: {
+    revisions: { hits: number; misses: number; entries: number };
+    scripts: { hits: number; misses: number; entries: number };
+  };
+  sessions: {
+    id: number;
+    projects: string[];
+    connected_secs: number;
+    idle_secs: number;
+  }[];
+}
+
+/** Get server health and diagnostics */
+// LINK src-server/handlers.rs#status
+export const getStatus = async (): Promise<ServerStatus> => {
+  const ws = new Socket(new WebSocket(SOCKET_URL));
+  return ws.request({
+    command: "status",
+    data: { Project: { project_name: "" } },
+  });
+};
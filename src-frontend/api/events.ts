@@ -0,0 +1,73 @@
+import { SOCKET_URL } from "./url";
+
+/** Version of the typed protocol this frontend speaks */
+export const PROTOCOL_VERSION = 1;
+
+/** Represents an event the server broadcasts, e.g. `progress` or `remote-updated` */
+export interface ServerEvent {
+  type: string;
+  [key: string]: any;
+}
+
+/** The event stream endpoint, on the same host and with the same token as the socket */
+const EVENTS_URL = (() => {
+  const url = new URL(SOCKET_URL.replace(/^ws/, "http"));
+  url.pathname = "/events";
+  return url.toString();
+})();
+
+/**
+ * Listen for server events over a WebSocket, falling back to server-sent events when
+ * WebSockets are blocked
+ *
+ * @returns a function that stops listening
+ */
+export const subscribeEvents = (
+  onevent: (event: ServerEvent) => any
+): (() => void) => {
+  let source: EventSource | undefined;
+  let opened = false;
+  const ws = new WebSocket(SOCKET_URL);
+
+  ws.onopen = () => {
+    opened = true;
+    // the server only sends events to clients that ask for them
+    ws.send(
+      JSON.stringify({
+        type: "hello",
+        id: 0,
+        protocol_version: PROTOCOL_VERSION,
+        features: ["events", "progress"],
+        locales: navigator.languages,
+      })
+    );
+  };
+  ws.onmessage = (message) => {
+    const data = JSON.parse(message.data);
+    // broadcast events have a type but no request ID
+    if (data.type && data.id === undefined) onevent(data);
+  };
+  ws.onerror = () => {
+    if (opened || source) return;
+    source = new EventSource(EVENTS_URL);
+    source.onmessage = (message) => onevent(JSON.parse(message.data));
+    for (const type of [
+      "auto-committed",
+      "changes-pending",
+      "diff-ready",
+      "progress",
+      "project-changed",
+      "remote-updated",
+      "settings-changed",
+    ]) {
+      source.addEventListener(type, (message) =>
+        onevent(JSON.parse((message as MessageEvent).data))
+      );
+    }
+  };
+
+  return () => {
+    ws.close();
+    source?.close();
+  };
+};
@@ -2,5 +2,6 @@ import { ProjectManager } from "./api";
 import { SOCKET_URL } from "./url";
 
 export * from "./auth";
+export * from "./events";
 export * from "./api";
 export default new ProjectManager(new WebSocket(SOCKET_URL));
@@ -1 +1,2 @@
-export const SOCKET_URL = "ws://localhost:8000";
+// the server fills in its address and session token when it installs the userscript
+export const SOCKET_URL = "ws://localhost:8000/?token=__SCRATCH_GIT_TOKEN__";
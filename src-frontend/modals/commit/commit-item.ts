@@ -1,7 +1,7 @@
-import type { Commit } from "@/api";
+import api, { type Commit } from "@/api";
 import van from "vanjs-core";
 
-const { div, br, span } = van.tags;
+const { div, br, span, img } = van.tags;
 
 const RANGES = {
   years: 3600 * 24 * 365,
@@ -42,13 +42,27 @@ const highlight = (fullText: string, search: string) => {
   return fullText;
 };
 
-export const CommitItem = (commit: Commit, search: string) =>
-  div(
-    { class: "commit" },
+export const CommitItem = (commit: Commit, search: string) => {
+  const thumbnail = img({ class: "commit-thumbnail", style: "display: none" });
+
+  // thumbnails are only fetched when asked for, since older ones may need rendering
+  const toggleThumbnail = async () => {
+    if (!thumbnail.src) {
+      const url = await api.getCurrentProject()?.getThumbnail(commit.commit);
+      if (!url) return;
+      thumbnail.src = url;
+    }
+    thumbnail.style.display = thumbnail.style.display === "none" ? "" : "none";
+  };
+
+  return div(
+    { class: "commit", onclick: toggleThumbnail },
     span({ style: "font-size: 1rem" }, highlight(commit.subject, search)),
     br(),
     span(
       { style: "font-size: 0.75rem", title: commit.author.date },
       `${commit.author.name} committed ${timeAgo(commit.author.date)}`
-    )
+    ),
+    thumbnail
   );
+};
@@ -643,40 +643,30 @@ export class DiffModal extends Base {
         costumeDiffs[btnRef.getAttribute("asset-name")!];
       const isSoundDiff = btnRef.getAttribute("diff-type") === "sound";
 
-      let previousAsset = { contents: "", size: 0 };
-      let currentAsset = { contents: "", size: 0 };
-      if (current && previous) {
-        previousAsset = {
-          contents: toDataURI(
-            previous.path.split(".").pop()!,
-            String.fromCharCode.apply(null, previous.contents)
-          ),
-          size: previous.contents.length,
-        };
-        currentAsset = {
+      // assets are only read once they're previewed
+      const load = async (change: CostumeChange) => {
+        const contents = await project!.getAssetContents(change);
+        return {
           contents: toDataURI(
-            current.path.split(".").pop()!,
-            String.fromCharCode.apply(null, current.contents)
+            change.path.split(".").pop()!,
+            String.fromCharCode.apply(null, contents)
           ),
-          size: current.contents.length,
+          size: contents.length,
         };
+      };
+
+      let previousAsset = { contents: "", size: 0 };
+      let currentAsset = { contents: "", size: 0 };
+      if (current && previous) {
+        [previousAsset, currentAsset] = await Promise.all([
+          load(previous),
+          load(current),
+        ]);
       } else {
         if (current && !previous && current.kind !== "before") {
-          currentAsset = {
-            contents: toDataURI(
-              current.path.split(".").pop()!,
-              String.fromCharCode.apply(null, current.contents)
-            ),
-            size: current.contents.length,
-          };
+          currentAsset = await load(current);
         } else if (current.kind === "before" && !previous) {
-          previousAsset = {
-            contents: toDataURI(
-              current.path.split(".").pop()!,
-              String.fromCharCode.apply(null, current.contents)
-            ),
-            size: current.contents.length,
-          };
+          previousAsset = await load(current);
         }
       }
 
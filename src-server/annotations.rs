@@ -0,0 +1,135 @@
+//! Reporting diff and lint findings as GitHub Actions workflow commands, so checks run on
+//! pushed projects show up as annotations on the commit
+//!
+//! Run with `scratch-git --annotations [project dir] --base <revision>` in a workflow step.
+
+use std::fmt;
+use std::fs;
+use std::path::PathBuf;
+
+use anyhow::Result;
+
+use crate::config;
+use crate::diff::label;
+use crate::diff::structs::{AssetChangeType, Diff};
+use crate::lint;
+use crate::observer::Quiet;
+use crate::ops;
+use crate::validate;
+
+/// The file every annotation points at, since scripts and assets all live in it
+const PROJECT_FILE: &str = "project.json";
+
+/// How an annotation is shown, with errors failing the check
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Level {
+    Notice,
+    Warning,
+    Error,
+}
+
+/// Represents a single workflow command, like `::warning file=project.json::...`
+#[derive(Debug, Clone)]
+pub struct Annotation {
+    pub level: Level,
+    pub title: String,
+    pub message: String,
+}
+
+impl Annotation {
+    fn new(level: Level, title: impl Into<String>, message: impl Into<String>) -> Self {
+        Annotation {
+            level,
+            title: title.into(),
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for Annotation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let level = match self.level {
+            Level::Notice => "notice",
+            Level::Warning => "warning",
+            Level::Error => "error",
+        };
+        write!(
+            f,
+            "::{level} file={},title={}::{}",
+            escape_property(PROJECT_FILE),
+            escape_property(&self.title),
+            escape_data(&self.message)
+        )
+    }
+}
+
+/// Escape a command's message, which ends at the first newline
+fn escape_data(text: &str) -> String {
+    text.replace('%', "%25")
+        .replace('\r', "%0D")
+        .replace('\n', "%0A")
+}
+
+/// Escape a command's property, which also ends at a comma or colon
+fn escape_property(text: &str) -> String {
+    escape_data(text).replace(':', "%3A").replace(',', "%2C")
+}
+
+/// Check a project's saved project.json against `base`, returning what to annotate
+///
+/// A broken project.json is reported as errors without diffing, since there's nothing to
+/// compare. Lint issues and diff warnings are warnings, and each changed sprite gets a notice
+/// summarizing its changes.
+pub fn check(pth: &PathBuf, base: &str) -> Result<Vec<Annotation>> {
+    let saved = fs::read_to_string(pth.join(PROJECT_FILE))?;
+    let violations = validate::validate_str(&saved);
+    if !violations.is_empty() {
+        let errors = violations.into_iter().map(|violation| {
+            let message = format!("{}: {}", violation.path, violation.message);
+            Annotation::new(Level::Error, "Broken project", message)
+        });
+        return Ok(errors.collect());
+    }
+
+    let new = ops::parse_project(&saved)?;
+    let options = config::settings(Some(pth)).lint.options();
+    let mut annotations = lint::lint(&new, options)
+        .into_iter()
+        .map(|issue| {
+            let message = format!("{}: {}", issue.path, issue.message);
+            Annotation::new(Level::Warning, "Lint", message)
+        })
+        .collect::<Vec<_>>();
+
+    // a first commit has nothing to diff against
+    let Ok(old) = Diff::from_revision(pth, &format!("{base}:{PROJECT_FILE}")) else {
+        return Ok(annotations);
+    };
+    let report = ops::compare(pth, &old, &Diff::new(new), &mut Quiet)?;
+    for warning in &report.warnings {
+        annotations.push(Annotation::new(Level::Warning, "Changes", warning));
+    }
+    for sprite in report.sprites() {
+        let is_sprite = |name: &str| label::name(name) == sprite;
+        let scripts = report.scripts.iter().filter(|s| is_sprite(&s.sprite));
+        let assets = report.assets.iter().filter(|a| is_sprite(&a.sprite));
+        let variables = report.variables.iter().filter(|v| is_sprite(&v.sprite));
+        let changes = scripts
+            .map(|script| script.describe())
+            .chain(assets.map(|asset| {
+                let action = match asset.kind {
+                    Some(AssetChangeType::Before) => "remove",
+                    _ => "add",
+                };
+                format!("{action} {}.{}", asset.name, asset.ext)
+            }))
+            .chain(variables.map(|variable| variable.format()))
+            .collect::<Vec<_>>();
+        annotations.push(Annotation::new(
+            Level::Notice,
+            format!("Changes to {sprite}"),
+            changes.join("\n"),
+        ));
+    }
+    Ok(annotations)
+}
@@ -0,0 +1,284 @@
+use std::collections::HashSet;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+
+use anyhow::{anyhow, Result};
+
+use crate::git;
+use crate::gitignore;
+use crate::mapped::{self, Contents};
+use crate::ops;
+use crate::sb3;
+
+const ASSET_STORE_PATH: &str = "projects/.assets";
+
+/// Ref assets are committed to in a project's repository, apart from its branches, so they're
+/// pushed and cloned with the project without making its history big
+pub const ASSETS_REF: &str = "refs/pixelpioneers/assets";
+
+/// Where the remote's assets ref is fetched to, before it's added to the project's own
+const REMOTE_ASSETS_REF: &str = "refs/pixelpioneers/remote-assets";
+
+/// Asset extensions kept out of project repositories when the asset store is enabled
+pub const STORED_EXTENSIONS: [&str; 8] = ["svg", "png", "jpg", "jpeg", "gif", "bmp", "wav", "mp3"];
+
+/// Content-addressed storage for binary assets
+///
+/// Scratch already names assets by their MD5, so project.json is the only reference
+/// a repository needs to keep: assets are copied in and out of the store by name. Each project
+/// keeps its assets in its own [`ASSETS_REF`], and a copy of every project's is kept in one
+/// folder so they're quick to find.
+#[derive(Debug)]
+pub struct AssetStore {
+    root: PathBuf,
+}
+
+impl AssetStore {
+    pub fn new(root: impl AsRef<Path>) -> Result<Self> {
+        fs::create_dir_all(root.as_ref())?;
+        Ok(AssetStore {
+            root: root.as_ref().to_path_buf(),
+        })
+    }
+
+    /// Default store next to the project config
+    pub fn default_store() -> Result<Self> {
+        Self::new(ASSET_STORE_PATH)
+    }
+
+    /// Copy assets from a project into the store and commit them to its assets ref, returning
+    /// how many were new
+    pub fn store(&self, cwd: &PathBuf, assets: &[String]) -> Result<usize> {
+        for asset in assets {
+            let target = self.root.join(asset);
+            if target.exists() || !cwd.join(asset).exists() {
+                continue;
+            }
+            fs::copy(cwd.join(asset), target)?;
+        }
+
+        let committed = committed(cwd)?;
+        let new = assets
+            .iter()
+            .filter(|asset| !committed.contains(*asset) && cwd.join(asset).exists())
+            .collect::<HashSet<_>>();
+        if !new.is_empty() {
+            commit(cwd, &new)?;
+        }
+        Ok(new.len())
+    }
+
+    /// Read an asset from the store, if it's there, mapping it if it's large
+    pub fn read(&self, cwd: &PathBuf, asset: &str) -> Option<Contents> {
+        mapped::read(&self.root.join(asset)).ok().or_else(|| {
+            git::show_file(cwd, ASSETS_REF, asset)
+                .ok()
+                .map(Contents::from)
+        })
+    }
+
+    /// Copy assets from the store into a project, returning assets that aren't in either
+    pub fn restore(&self, cwd: &PathBuf, assets: &[String]) -> Result<Vec<String>> {
+        let mut missing = vec![];
+        for asset in assets {
+            if cwd.join(asset).exists() {
+                continue;
+            }
+            let source = self.root.join(asset);
+            if source.exists() {
+                fs::copy(source, cwd.join(asset))?;
+            } else if let Ok(contents) = git::show_file(cwd, ASSETS_REF, asset) {
+                fs::write(cwd.join(asset), &contents)?;
+                sb3::write_asset(&source, &contents)?;
+            } else {
+                missing.push(asset.clone());
+            }
+        }
+        Ok(missing)
+    }
+
+    /// Move a project's assets out of Git history going forward and into the store
+    pub fn enable(&self, cwd: &PathBuf, assets: &[String]) -> Result<()> {
+        self.store(cwd, assets)?;
+
+        for ext in STORED_EXTENSIONS {
+            gitignore::add_pattern(cwd, &format!("*.{ext}"))?;
+        }
+
+        let mut args = vec!["rm", "--cached", "--quiet", "--ignore-unmatch", "--"];
+        args.extend(assets.iter().map(|a| a.as_str()));
+        if !git::run(args, Some(cwd)).status()?.success() {
+            return Err(anyhow!("failed to untrack assets"));
+        }
+        Ok(())
+    }
+}
+
+/// Check if a project keeps its assets in the store, having committed some to its assets ref
+pub fn is_used(cwd: &PathBuf) -> bool {
+    git::revision_exists(cwd, ASSETS_REF).unwrap_or(false)
+}
+
+/// Fetch the assets other copies of a project have pushed, returning whether there were any
+///
+/// They're added to the project's own, so assets that haven't been pushed yet are kept
+pub fn fetch(cwd: &PathBuf) -> Result<bool> {
+    let remote = git::run(vec!["remote", "get-url", "origin"], Some(cwd)).output()?;
+    let refspec = format!("+{ASSETS_REF}:{REMOTE_ASSETS_REF}");
+    let mut fetch = git::run(vec!["fetch", "--quiet", "origin", &refspec], Some(cwd));
+    ops::authenticate(&mut fetch, &String::from_utf8_lossy(&remote.stdout));
+    // remotes that never had assets pushed to them don't have the ref
+    if !fetch.status()?.success() {
+        return Ok(false);
+    }
+
+    let theirs = git::resolve(cwd, REMOTE_ASSETS_REF)?;
+    let Ok(ours) = git::resolve(cwd, ASSETS_REF) else {
+        return update_ref(cwd, &theirs, "").map(|_| true);
+    };
+    if is_ancestor(cwd, &theirs, &ours)? {
+        return Ok(true);
+    }
+    if is_ancestor(cwd, &ours, &theirs)? {
+        return update_ref(cwd, &theirs, &ours).map(|_| true);
+    }
+
+    // assets are named by their MD5, so ones with the same name are the same on both sides
+    let mut entries = tree_entries(cwd, &ours)?;
+    let names = entries
+        .iter()
+        .map(|(name, _)| name.clone())
+        .collect::<HashSet<_>>();
+    entries.extend(
+        tree_entries(cwd, &theirs)?
+            .into_iter()
+            .filter(|(name, _)| !names.contains(name)),
+    );
+    let tree = write_tree(cwd, &entries)?;
+    let merged = commit_tree(cwd, &tree, &[&ours, &theirs], "merge stored assets")?;
+    update_ref(cwd, &merged, &ours)?;
+    Ok(true)
+}
+
+/// Names of the assets committed to a project's assets ref
+fn committed(cwd: &PathBuf) -> Result<HashSet<String>> {
+    if !is_used(cwd) {
+        return Ok(HashSet::new());
+    }
+    Ok(tree_entries(cwd, ASSETS_REF)?
+        .into_iter()
+        .map(|(name, _)| name)
+        .collect())
+}
+
+/// Commit assets from a project's working directory to its assets ref, next to what's there
+fn commit(cwd: &PathBuf, assets: &HashSet<&String>) -> Result<()> {
+    let assets = assets
+        .iter()
+        .map(|asset| asset.as_str())
+        .collect::<Vec<_>>();
+    let mut args = vec!["hash-object", "-w", "--"];
+    args.extend(&assets);
+    let hashed = git::run(args, Some(cwd)).output()?;
+    if !hashed.status.success() {
+        return Err(anyhow!("failed to hash assets"));
+    }
+
+    let parent = git::resolve(cwd, ASSETS_REF).ok();
+    let mut entries = match &parent {
+        Some(parent) => tree_entries(cwd, parent)?,
+        None => vec![],
+    };
+    let ids = String::from_utf8_lossy(&hashed.stdout);
+    entries.extend(
+        assets
+            .iter()
+            .zip(ids.lines())
+            .map(|(asset, id)| (asset.to_string(), id.to_string())),
+    );
+
+    let tree = write_tree(cwd, &entries)?;
+    let parents = parent.iter().map(String::as_str).collect::<Vec<_>>();
+    let message = format!("store {} assets", assets.len());
+    let commit = commit_tree(cwd, &tree, &parents, &message)?;
+    update_ref(cwd, &commit, parent.as_deref().unwrap_or_default())
+}
+
+/// List the assets in a tree, with their blob IDs
+fn tree_entries(cwd: &PathBuf, tree: &str) -> Result<Vec<(String, String)>> {
+    let output = git::run(vec!["ls-tree", tree], Some(cwd)).output()?;
+    if !output.status.success() {
+        return Err(anyhow!("failed to list stored assets"));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| {
+            let (info, name) = line.split_once('\t')?;
+            let id = info.split(' ').nth(2)?;
+            Some((name.to_string(), id.to_string()))
+        })
+        .collect())
+}
+
+/// Write a tree of assets, returning its ID
+fn write_tree(cwd: &PathBuf, entries: &[(String, String)]) -> Result<String> {
+    let mut listing = vec![];
+    for (name, id) in entries {
+        writeln!(listing, "100644 blob {id}\t{name}")?;
+    }
+
+    let mut child = git::run(vec!["mktree"], Some(cwd))
+        .stdin(Stdio::piped())
+        .spawn()?;
+    let mut stdin = child
+        .stdin
+        .take()
+        .ok_or(anyhow!("could not receive stdin"))?;
+    std::thread::spawn(move || stdin.write_all(&listing));
+    let tree = child.wait_with_output()?;
+    if !tree.status.success() {
+        return Err(anyhow!("failed to write the assets tree"));
+    }
+    Ok(String::from_utf8_lossy(&tree.stdout).trim().to_string())
+}
+
+/// Commit a tree of assets, returning the commit's ID
+fn commit_tree(cwd: &PathBuf, tree: &str, parents: &[&str], message: &str) -> Result<String> {
+    let mut args = vec!["commit-tree", tree, "-m", message];
+    for parent in parents {
+        args.extend(["-p", parent]);
+    }
+    let commit = git::run(args, Some(cwd)).output()?;
+    if !commit.status.success() {
+        return Err(anyhow!(
+            "failed to commit assets: {}",
+            String::from_utf8_lossy(&commit.stderr).trim()
+        ));
+    }
+    Ok(String::from_utf8_lossy(&commit.stdout).trim().to_string())
+}
+
+/// Point the assets ref at a commit, if it's still at `old`, or doesn't exist for an empty `old`
+///
+/// Another commit storing assets at the same time would otherwise be lost
+fn update_ref(cwd: &PathBuf, commit: &str, old: &str) -> Result<()> {
+    if !git::run(vec!["update-ref", ASSETS_REF, commit, old], Some(cwd))
+        .status()?
+        .success()
+    {
+        return Err(anyhow!("failed to update {ASSETS_REF}"));
+    }
+    Ok(())
+}
+
+/// Check if one commit is in another's history
+fn is_ancestor(cwd: &PathBuf, ancestor: &str, commit: &str) -> Result<bool> {
+    Ok(git::run(
+        vec!["merge-base", "--is-ancestor", ancestor, commit],
+        Some(cwd),
+    )
+    .status()?
+    .success())
+}
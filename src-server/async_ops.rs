@@ -0,0 +1,109 @@
+//! Async versions of loading revisions, diffing and committing, for hosts that run on an async
+//! runtime like the server's
+//!
+//! Git is run as an async process where only its output is needed. Parsing, diffing and
+//! committing are CPU-heavy or touch the working directory as they go, so they run on the
+//! runtime's blocking pool, in the locale of the task that called them. Every function has to
+//! be called from inside a Tokio runtime.
+
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use anyhow::Result;
+use tokio::process::Command;
+use tokio::task;
+
+use crate::diff::structs::{CommitLine, Diff, DiffReport};
+use crate::error::Error;
+use crate::git::{self, Identity};
+use crate::i18n;
+use crate::observer::Observer;
+use crate::ops::{self, CommitOutcome};
+use crate::partial::Selection;
+
+/// Options for committing a project, like [`ops::CommitOptions`] but owning what they refer
+/// to, so they can be moved onto the blocking pool
+#[derive(Debug, Clone, Default)]
+pub struct CommitOptions {
+    /// Attribute the commit to someone other than the configured identity
+    pub author: Option<Identity>,
+    /// Keep binary assets in the shared asset store instead of the repository
+    pub asset_store: bool,
+    /// Only commit these changes, leaving the rest uncommitted
+    pub selection: Option<Vec<Selection>>,
+    /// Title the commit with this, keeping the generated changes as its body
+    pub title: Option<String>,
+    /// Who made the changes, credited with an `Edited-by:` trailer when they aren't the author
+    pub editor: Option<String>,
+    /// The editor's SB3, repacked when optimizing renames assets
+    pub sb3: Option<PathBuf>,
+}
+
+/// Run work on the blocking pool in the caller's locale
+async fn blocking<T: Send + 'static>(
+    work: impl FnOnce() -> Result<T> + Send + 'static,
+) -> Result<T> {
+    let locale = i18n::current_locale();
+    task::spawn_blocking(move || i18n::with_locale(&locale, work)).await?
+}
+
+/// Fetch a file as of a revision, like `HEAD:project.json`
+pub async fn show_revision(pth: &PathBuf, revision: &str) -> Result<String> {
+    let output = Command::from(git::run(vec!["show", revision], Some(pth)))
+        .output()
+        .await
+        .map_err(Error::git)?;
+    let contents = String::from_utf8_lossy(&output.stdout).into_owned();
+    if !contents.is_empty() {
+        return Ok(contents);
+    }
+
+    // projects stored one file per sprite are put back together from their sprites
+    let (pth, shown) = (pth.clone(), revision.to_string());
+    let contents = blocking(move || git::show_revision(&pth, &shown)).await?;
+    if contents.is_empty() {
+        return Err(Error::RevisionNotFound(revision.to_string()).into());
+    }
+    Ok(contents)
+}
+
+/// Load a project as of a revision, like `HEAD~1`, ready to diff
+pub async fn load_revision(pth: &PathBuf, revision: &str) -> Result<Diff> {
+    let contents = show_revision(pth, &format!("{revision}:project.json")).await?;
+    blocking(move || Ok(Diff::new(ops::parse_project(&contents)?))).await
+}
+
+/// Return the changes between two versions of a project, leaving out ignored sprites
+pub async fn compare(
+    pth: PathBuf,
+    current: Arc<Diff>,
+    new: Arc<Diff>,
+    mut observer: impl Observer + Send + 'static,
+) -> Result<DiffReport> {
+    blocking(move || ops::compare(&pth, &current, &new, &mut observer)).await
+}
+
+/// Generate the commit message lines for the changes between two versions of a project
+pub async fn commits(pth: PathBuf, current: Arc<Diff>, new: Arc<Diff>) -> Result<Vec<CommitLine>> {
+    blocking(move || current.commits(&pth, &new)).await
+}
+
+/// Commit a project's saved changes
+pub async fn commit(
+    pth: PathBuf,
+    options: CommitOptions,
+    mut observer: impl Observer + Send + 'static,
+) -> Result<CommitOutcome> {
+    blocking(move || {
+        let options = ops::CommitOptions {
+            author: options.author.as_ref(),
+            asset_store: options.asset_store,
+            selection: options.selection.as_deref(),
+            title: options.title.as_deref(),
+            editor: options.editor.as_deref(),
+            sb3: options.sb3.as_deref(),
+        };
+        ops::commit(&pth, options, &mut observer)
+    })
+    .await
+}
@@ -0,0 +1,92 @@
+use std::collections::hash_map::RandomState;
+use std::fs::{self, OpenOptions};
+use std::hash::{BuildHasher, Hasher};
+use std::io::{self, Write};
+use std::sync::OnceLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Replaced with the session token when the userscript is installed
+const TOKEN_PLACEHOLDER: &str = "__SCRATCH_GIT_TOKEN__";
+
+/// Where the session token is written for scripts and tools that aren't the userscript
+pub const TOKEN_PATH: &str = "projects/.session-token";
+
+/// Generate 128 random bits as hex, without pulling in a random number crate
+pub fn random_hex() -> String {
+    #[cfg(unix)]
+    {
+        use std::io::Read;
+        let mut bytes = [0u8; 16];
+        if std::fs::File::open("/dev/urandom")
+            .and_then(|mut f| f.read_exact(&mut bytes))
+            .is_ok()
+        {
+            return bytes.iter().map(|b| format!("{b:02x}")).collect();
+        }
+    }
+
+    // RandomState is seeded from the OS, so hashing with two of them gives unpredictable output
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or_default();
+    (0..2)
+        .map(|_| {
+            let mut hasher = RandomState::new().build_hasher();
+            hasher.write_u128(nanos);
+            hasher.write_u32(std::process::id());
+            format!("{:016x}", hasher.finish())
+        })
+        .collect()
+}
+
+/// The token clients need to talk to this server, generated once per run
+pub fn session_token() -> &'static str {
+    static TOKEN: OnceLock<String> = OnceLock::new();
+    TOKEN.get_or_init(random_hex)
+}
+
+/// Write the session token to [`TOKEN_PATH`], readable only by the current user
+pub fn write_token_file() -> io::Result<()> {
+    // a token file left by a crashed run may have looser permissions, and `mode` only applies
+    // to new files
+    match fs::remove_file(TOKEN_PATH) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+        _ => {}
+    }
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options
+        .open(TOKEN_PATH)?
+        .write_all(session_token().as_bytes())
+}
+
+/// Compare without exiting early, so timing doesn't leak how much of a token matched
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Check a token from a `token` query parameter or a `Bearer` authorization header
+pub fn is_authorized(query_token: Option<&str>, authorization: Option<&str>) -> bool {
+    let token = query_token.or_else(|| authorization.and_then(|a| a.strip_prefix("Bearer ")));
+    token.is_some_and(|token| constant_time_eq(token.trim().as_bytes(), session_token().as_bytes()))
+}
+
+/// Return the `token` parameter of a URL query string
+pub fn token_from_query(query: &str) -> Option<&str> {
+    query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(k, _)| *k == "token")
+        .map(|(_, v)| v)
+}
+
+/// Bake the session token into the userscript, which is how the editor extension learns it
+pub fn inject_token(script: &str) -> String {
+    script.replace(TOKEN_PLACEHOLDER, session_token())
+}
@@ -0,0 +1,137 @@
+//! Snapshots of projects kept outside of Git
+//!
+//! Every few minutes, each project's folder is zipped into an SB3 in its own backup folder, like
+//! `backups/Pong/1714521600-5d41402a.sb3`, named by when it was taken and the start of its
+//! project.json's hash. Snapshots open straight in the editor, so work can be recovered even when
+//! the repository can't be.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread::{sleep, spawn};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+use tracing::{debug, warn};
+
+use crate::config::{self, project_config, BackupSettings};
+use crate::sb3;
+
+/// Represents a snapshot of a project
+#[derive(Debug, Clone, Serialize)]
+pub struct Backup {
+    pub path: PathBuf,
+    /// Seconds since the Unix epoch when it was taken
+    pub created: u64,
+    /// The start of its project.json's MD5 hash
+    pub hash: String,
+}
+
+impl Backup {
+    fn from_path(path: PathBuf) -> Option<Self> {
+        let (created, hash) = path
+            .file_stem()?
+            .to_str()?
+            .split_once('-')
+            .and_then(|(created, hash)| Some((created.parse().ok()?, hash.to_string())))?;
+        (path.extension()? == "sb3").then_some(Backup {
+            path,
+            created,
+            hash,
+        })
+    }
+}
+
+/// The folder a project's snapshots are saved in
+pub fn directory(settings: &BackupSettings, project_name: &str) -> PathBuf {
+    settings.directory.join(project_name)
+}
+
+/// List a project's snapshots, oldest first
+pub fn list(settings: &BackupSettings, project_name: &str) -> Result<Vec<Backup>> {
+    let dir = directory(settings, project_name);
+    if !dir.exists() {
+        return Ok(vec![]);
+    }
+    let mut backups = fs::read_dir(&dir)?
+        .filter_map(|entry| Backup::from_path(entry.ok()?.path()))
+        .collect::<Vec<_>>();
+    backups.sort_by(|a, b| (a.created, &a.path).cmp(&(b.created, &b.path)));
+    Ok(backups)
+}
+
+/// Snapshot a project's folder, returning the new snapshot, or `None` if its project.json is
+/// the same as in the latest one
+///
+/// Snapshots past the number to keep are deleted afterwards, oldest first.
+pub fn snapshot(
+    settings: &BackupSettings,
+    project_name: &str,
+    pth: &Path,
+) -> Result<Option<Backup>> {
+    let json = fs::read(pth.join("project.json")).context("failed to read project.json")?;
+    let hash = format!("{:x}", md5::compute(json))[..8].to_string();
+
+    let backups = list(settings, project_name)?;
+    if backups.last().is_some_and(|latest| latest.hash == hash) {
+        return Ok(None);
+    }
+
+    let dir = directory(settings, project_name);
+    fs::create_dir_all(&dir)?;
+    let created = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    let path = dir.join(format!("{created}-{hash}.sb3"));
+    sb3::pack(pth, &path).context("failed to zip project")?;
+
+    prune(settings, project_name)?;
+    Ok(Some(Backup {
+        path,
+        created,
+        hash,
+    }))
+}
+
+/// Delete a project's oldest snapshots until only as many as should be kept are left
+pub fn prune(settings: &BackupSettings, project_name: &str) -> Result<()> {
+    let backups = list(settings, project_name)?;
+    let excess = backups.len().saturating_sub(settings.keep.max(1));
+    for backup in &backups[..excess] {
+        fs::remove_file(&backup.path)
+            .with_context(|| format!("failed to delete {}", backup.path.display()))?;
+    }
+    Ok(())
+}
+
+/// Unpack a snapshot over a project's folder, replacing its project.json and assets
+pub fn restore(backup: &Path, pth: &Path) -> Result<()> {
+    sb3::unpack(backup, pth).context("failed to unpack snapshot")?;
+    Ok(())
+}
+
+/// Snapshot every project in the background, as often as the global settings say
+pub fn start() {
+    let settings = config::settings(None).backup;
+    if settings.interval_minutes == 0 {
+        return;
+    }
+
+    spawn(move || loop {
+        sleep(Duration::from_secs(settings.interval_minutes * 60));
+
+        let project_names = project_config().lock().unwrap().project_names();
+        for name in project_names {
+            let pth = project_config().lock().unwrap().project_path(&name);
+            let settings = config::settings(Some(&pth)).backup;
+            if !settings.enabled {
+                continue;
+            }
+            match snapshot(&settings, &name, &pth) {
+                Ok(Some(backup)) => {
+                    debug!(project = %name, path = %backup.path.display(), "saved snapshot")
+                }
+                Ok(None) => {}
+                Err(e) => warn!(project = %name, "failed to snapshot: {e:#}"),
+            }
+        }
+    });
+}
@@ -0,0 +1,140 @@
+use std::path::PathBuf;
+
+use anyhow::{anyhow, Result};
+use regex_static::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::diff::parse_script::{parse_sprite, Sprite};
+use crate::diff::structs::Diff;
+use crate::git;
+use crate::sb3::Project;
+
+/// Represents a question asked of a project at each commit
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Predicate {
+    /// A sprite (or the stage, named "Stage") exists
+    SpriteExists { sprite: String },
+    /// A sprite has a script whose text matches a regular expression
+    ScriptMatches { sprite: String, pattern: String },
+    /// A sprite (or any sprite) uses an asset with this MD5 file name
+    HasAsset {
+        sprite: Option<String>,
+        md5ext: String,
+    },
+}
+
+impl Predicate {
+    /// Evaluate the predicate against a project.json
+    pub fn matches(&self, project: &Project) -> Result<bool> {
+        Ok(match self {
+            Predicate::SpriteExists { sprite } => project.target(sprite).is_some(),
+            Predicate::ScriptMatches { sprite, pattern } => {
+                let pattern = Regex::new(pattern)?;
+                let Some(target) = project.target(sprite) else {
+                    return Ok(false);
+                };
+                let scripts = parse_sprite(Sprite::of(target))?;
+                pattern.is_match(&scripts)
+            }
+            Predicate::HasAsset { sprite, md5ext } => project
+                .targets
+                .iter()
+                .filter(|t| sprite.is_none() || Some(&t.name) == sprite.as_ref())
+                .flat_map(|t| t.asset_paths())
+                .any(|path| &path == md5ext),
+        })
+    }
+}
+
+/// Represents the commit where a predicate first changed
+#[derive(Debug, Serialize)]
+pub struct BisectResult {
+    pub commit: String,
+    pub subject: String,
+    /// Whether the predicate started (`true`) or stopped (`false`) holding at this commit
+    pub now_matches: bool,
+    /// How many revisions were checked to find the commit
+    pub steps: usize,
+}
+
+fn evaluate(cwd: &PathBuf, commit: &str, predicate: &Predicate) -> Result<bool> {
+    let diff = Diff::from_revision(cwd, &format!("{commit}:project.json"))?;
+    predicate.matches(&diff.data)
+}
+
+/// Find the first commit after `good` (up to `bad`) where a predicate's result changed,
+/// a project-aware take on `git bisect`
+///
+/// `good` defaults to the first commit. Like `git bisect`, this assumes the predicate only
+/// changes once between the two commits.
+pub fn bisect(
+    cwd: &PathBuf,
+    good: Option<&str>,
+    bad: &str,
+    predicate: &Predicate,
+) -> Result<Option<BisectResult>> {
+    let range = match good {
+        Some(good) => format!("{good}..{bad}"),
+        None => bad.to_string(),
+    };
+    let output = git::run(
+        vec!["rev-list", "--first-parent", "--reverse", &range],
+        Some(cwd),
+    )
+    .output()?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "failed to list commits: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    let mut commits = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(|line| line.to_string())
+        .collect::<Vec<_>>();
+
+    // the starting point is the state we're looking for a change from
+    let start = match good {
+        Some(good) => good.to_string(),
+        None if !commits.is_empty() => commits.remove(0),
+        None => return Ok(None),
+    };
+    let initial = evaluate(cwd, &start, predicate)?;
+    let mut steps = 1;
+
+    let Some(last) = commits.last() else {
+        return Ok(None);
+    };
+    steps += 1;
+    if evaluate(cwd, last, predicate)? == initial {
+        return Ok(None);
+    }
+
+    // commits[hi] is known to differ from the initial state
+    let (mut lo, mut hi) = (0, commits.len() - 1);
+    while lo < hi {
+        let mid = (lo + hi) / 2;
+        steps += 1;
+        if evaluate(cwd, &commits[mid], predicate)? == initial {
+            lo = mid + 1;
+        } else {
+            hi = mid;
+        }
+    }
+
+    let commit = commits[hi].clone();
+    let subject = String::from_utf8_lossy(
+        &git::run(vec!["log", "-1", "--pretty=format:%s", &commit], Some(cwd))
+            .output()?
+            .stdout,
+    )
+    .to_string();
+
+    Ok(Some(BisectResult {
+        commit,
+        subject,
+        now_matches: !initial,
+        steps,
+    }))
+}
@@ -0,0 +1,97 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+
+use serde::Serialize;
+
+/// Represents how often a cache was able to answer a lookup
+#[derive(Debug, Default, Clone, Copy, Serialize)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub entries: usize,
+}
+
+/// A small least-recently-used cache
+#[derive(Debug)]
+pub struct LruCache<K, V> {
+    capacity: usize,
+    tick: u64,
+    entries: HashMap<K, (V, u64)>,
+    hits: u64,
+    misses: u64,
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> LruCache<K, V> {
+    pub fn new(capacity: usize) -> Self {
+        LruCache {
+            capacity,
+            tick: 0,
+            entries: HashMap::new(),
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    /// Return a copy of a cached value, marking it as recently used
+    pub fn get(&mut self, key: &K) -> Option<V> {
+        self.tick += 1;
+        match self.entries.get_mut(key) {
+            Some((value, used)) => {
+                *used = self.tick;
+                self.hits += 1;
+                Some(value.clone())
+            }
+            None => {
+                self.misses += 1;
+                None
+            }
+        }
+    }
+
+    /// Cache a value, evicting the least recently used entry if the cache is full
+    ///
+    /// Returns the evicted entry, so callers can keep it somewhere else
+    pub fn insert(&mut self, key: K, value: V) -> Option<(K, V)> {
+        self.tick += 1;
+        let mut evicted = None;
+        if self.entries.len() >= self.capacity && !self.entries.contains_key(&key) {
+            evicted = self.pop_oldest();
+        }
+        self.entries.insert(key, (value, self.tick));
+        evicted
+    }
+
+    /// Remove and return the least recently used entry
+    pub fn pop_oldest(&mut self) -> Option<(K, V)> {
+        let oldest = self
+            .entries
+            .iter()
+            .min_by_key(|(_, (_, used))| *used)
+            .map(|(k, _)| k.clone())?;
+        self.entries
+            .remove_entry(&oldest)
+            .map(|(k, (value, _))| (k, value))
+    }
+
+    /// Check for a value without counting a lookup or marking it as used
+    pub fn contains(&self, key: &K) -> bool {
+        self.entries.contains_key(key)
+    }
+
+    /// Remove every entry that doesn't match a predicate
+    pub fn retain(&mut self, mut f: impl FnMut(&K) -> bool) {
+        self.entries.retain(|k, _| f(k));
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits,
+            misses: self.misses,
+            entries: self.entries.len(),
+        }
+    }
+}
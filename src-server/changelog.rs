@@ -0,0 +1,189 @@
+//! Summarizing every change between two revisions, like two tagged releases, for release notes
+//!
+//! Each commit in the range is diffed against its parent and the changes are added up per
+//! sprite, so an asset added and deleted again in between doesn't show up at all.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+use std::path::PathBuf;
+
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+
+use crate::diff::label::{self, Labeler};
+use crate::diff::structs::{AssetChangeType, Diff};
+use crate::git;
+use crate::i18n::message;
+use crate::observer::Quiet;
+use crate::ops;
+
+/// Represents everything that changed in a sprite over a range of commits
+#[derive(Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SpriteChangelog {
+    pub blocks_added: usize,
+    pub blocks_removed: usize,
+    pub assets_added: Vec<String>,
+    pub assets_removed: Vec<String>,
+    /// Assets with the same name that were replaced by different ones
+    pub assets_modified: Vec<String>,
+    /// Variables added, removed or renamed, in the order it happened
+    pub variables: Vec<String>,
+    /// Whether each asset existed before the range, and whether it does after
+    #[serde(skip)]
+    assets: BTreeMap<String, (bool, bool)>,
+}
+
+/// Represents the changes between two revisions, grouped by sprite
+#[derive(Debug, Serialize)]
+pub struct Changelog {
+    pub from: String,
+    pub to: String,
+    pub commits: usize,
+    /// Changes by sprite, where the stage ends with ` (stage)`
+    pub sprites: BTreeMap<String, SpriteChangelog>,
+    pub extensions: Vec<String>,
+}
+
+impl Changelog {
+    /// Lay out the changelog as Markdown, with a section per sprite
+    pub fn markdown(&self, labeler: &Labeler) -> String {
+        let title = message("changelog-title", &[("from", &self.from), ("to", &self.to)]);
+        let mut markdown = format!("# {title}\n\n");
+        let commits = match self.commits {
+            1 => "changelog-commit",
+            _ => "changelog-commits",
+        };
+        let _ = writeln!(
+            markdown,
+            "{}",
+            message(commits, &[("count", &self.commits.to_string())])
+        );
+
+        for (sprite, changes) in &self.sprites {
+            let _ = writeln!(markdown, "\n## {}\n", labeler.label(sprite));
+            if changes.blocks_added > 0 || changes.blocks_removed > 0 {
+                let blocks = message(
+                    "changelog-blocks",
+                    &[
+                        ("added", &changes.blocks_added.to_string()),
+                        ("removed", &changes.blocks_removed.to_string()),
+                    ],
+                );
+                let _ = writeln!(markdown, "- {blocks}");
+            }
+            for (action, assets) in [
+                ("changelog-added", &changes.assets_added),
+                ("changelog-removed", &changes.assets_removed),
+                ("changelog-changed", &changes.assets_modified),
+            ] {
+                if !assets.is_empty() {
+                    let assets = message(action, &[("assets", &assets.join(", "))]);
+                    let _ = writeln!(markdown, "- {assets}");
+                }
+            }
+            for variable in &changes.variables {
+                let _ = writeln!(markdown, "- {variable}");
+            }
+        }
+
+        if !self.extensions.is_empty() {
+            let _ = write!(markdown, "\n## {}\n\n", message("report-extensions", &[]));
+            for extension in &self.extensions {
+                let _ = writeln!(markdown, "- {extension}");
+            }
+        }
+        markdown
+    }
+}
+
+/// Add up the changes made by each commit after `from`, up to and including `to`
+///
+/// Only the first parent of merges is followed, so merged changes count once
+pub fn changelog(pth: &PathBuf, from: &str, to: &str) -> Result<Changelog> {
+    let range = format!("{from}..{to}");
+    let output = git::run(
+        vec!["rev-list", "--reverse", "--first-parent", &range],
+        Some(pth),
+    )
+    .output()?;
+    if !output.status.success() {
+        return Err(anyhow!("{range} isn't a range of commits"));
+    }
+    let commits = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(str::to_string)
+        .collect::<Vec<_>>();
+
+    let mut sprites = BTreeMap::<String, SpriteChangelog>::new();
+    let mut extensions = HashMap::<String, String>::new();
+    for commit in &commits {
+        let (Ok(old), Ok(new)) = (
+            Diff::from_revision(pth, &format!("{commit}~1:project.json")),
+            Diff::from_revision(pth, &format!("{commit}:project.json")),
+        ) else {
+            // the first commit, or one without a readable project
+            continue;
+        };
+        let report = ops::compare(pth, &old, &new, &mut Quiet)?;
+
+        for script in report.scripts {
+            let sprite = sprites
+                .entry(label::key(&script.sprite, script.on_stage))
+                .or_default();
+            sprite.blocks_added += script.added;
+            sprite.blocks_removed += script.removed;
+        }
+        // an asset replaced by one with the same name is removed, then added
+        let (removed, added): (Vec<_>, Vec<_>) = report
+            .assets
+            .into_iter()
+            .partition(|asset| asset.kind == Some(AssetChangeType::Before));
+        for asset in removed.into_iter().chain(added) {
+            let sprite = sprites
+                .entry(label::key(&asset.sprite, asset.on_stage))
+                .or_default();
+            let exists = asset.kind == Some(AssetChangeType::After);
+            let name = format!("{}.{}", asset.name, asset.ext);
+            sprite.assets.entry(name).or_insert((!exists, exists)).1 = exists;
+        }
+        for variable in report.variables {
+            let change = variable.format();
+            let sprite = sprites.entry(variable.sprite).or_default();
+            if !sprite.variables.contains(&change) {
+                sprite.variables.push(change);
+            }
+        }
+        for extension in report.extensions {
+            extensions.insert(extension.id.clone(), extension.format());
+        }
+    }
+
+    for sprite in sprites.values_mut() {
+        for (name, state) in std::mem::take(&mut sprite.assets) {
+            match state {
+                (false, true) => sprite.assets_added.push(name),
+                (true, false) => sprite.assets_removed.push(name),
+                (true, true) => sprite.assets_modified.push(name),
+                (false, false) => {}
+            }
+        }
+    }
+    // sprites whose changes cancelled out are left out
+    sprites.retain(|_, changes| {
+        changes.blocks_added > 0
+            || changes.blocks_removed > 0
+            || !changes.assets_added.is_empty()
+            || !changes.assets_removed.is_empty()
+            || !changes.assets_modified.is_empty()
+            || !changes.variables.is_empty()
+    });
+
+    Ok(Changelog {
+        from: from.to_string(),
+        to: to.to_string(),
+        commits: commits.len(),
+        sprites,
+        extensions: extensions.into_values().collect(),
+    })
+}
@@ -0,0 +1,98 @@
+//! Posting a short message to Slack or Discord after each commit, so a team working on a
+//! project together sees what everyone changed without opening it
+//!
+//! Targets are incoming webhook URLs, set up in `pixelpioneers.toml` under `[[notifications]]`.
+//! Discord webhooks are told apart by their URL, and anything else is sent Slack's format, which
+//! Mattermost and Rocket.Chat also take.
+
+use std::path::PathBuf;
+use std::thread;
+
+use anyhow::{anyhow, Result};
+use serde_json::json;
+use tracing::{debug, warn};
+
+use crate::config::{self, NotificationSettings};
+use crate::git;
+use crate::hooks::WEBHOOK_TIMEOUT;
+
+/// Chat services notifications can be posted to, which take different payloads
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Service {
+    Slack,
+    Discord,
+}
+
+impl Service {
+    fn from_url(url: &str) -> Self {
+        if url.contains("discord.com/api/webhooks") || url.contains("discordapp.com/api/webhooks") {
+            Service::Discord
+        } else {
+            Service::Slack
+        }
+    }
+}
+
+/// Post the last commit to each notification target in the background, so a slow chat server
+/// doesn't hold up the commit
+pub fn commit(pth: &PathBuf) -> Result<()> {
+    let targets = config::settings(Some(pth)).notifications;
+    if targets.is_empty() {
+        return Ok(());
+    }
+    let output = git::run(vec!["log", "-1", "--format=%aN%x1f%h%x1f%s"], Some(pth)).output()?;
+    let output = String::from_utf8_lossy(&output.stdout);
+    let mut fields = output.trim_end().splitn(3, '\x1f');
+    let (author, commit, message) = (
+        fields.next().unwrap_or_default().to_string(),
+        fields.next().unwrap_or_default().to_string(),
+        fields.next().unwrap_or_default().to_string(),
+    );
+    let project = pth.file_name().unwrap_or_default().to_string_lossy();
+
+    let messages = targets
+        .into_iter()
+        .map(|target| {
+            let text = target
+                .template
+                .replace("{author}", &author)
+                .replace("{project}", &project)
+                .replace("{commit}", &commit)
+                .replace("{message}", &message);
+            (target, text)
+        })
+        .collect::<Vec<_>>();
+    thread::spawn(move || {
+        for (target, text) in messages {
+            if let Err(e) = send(&target, &text) {
+                warn!(url = target.url, "notification failed: {e:#}");
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Post a message to a single target
+fn send(target: &NotificationSettings, text: &str) -> Result<()> {
+    let payload = match Service::from_url(&target.url) {
+        Service::Discord => json!({ "content": text }),
+        Service::Slack => match &target.channel {
+            Some(channel) => json!({ "text": text, "channel": channel }),
+            None => json!({ "text": text }),
+        },
+    };
+    debug!(url = target.url, "sending notification");
+    let response = minreq::post(&target.url)
+        .with_header("User-Agent", "PixelPioneers")
+        .with_json(&payload)?
+        .with_timeout(WEBHOOK_TIMEOUT)
+        .send()?;
+    if !(200..300).contains(&response.status_code) {
+        return Err(anyhow!(
+            "{} {}",
+            response.status_code,
+            response.reason_phrase
+        ));
+    }
+    Ok(())
+}
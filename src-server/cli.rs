@@ -0,0 +1,521 @@
+//! `pixelpioneers`, the engine on the command line, so everything the server does can be
+//! scripted from a terminal or run in CI
+//!
+//! Projects made here are plain repositories, and aren't added to the server's project list.
+//! With `--json`, every command writes JSON instead, like the server's responses, and failures
+//! are written as `{"error": ..., "kind": ...}` objects.
+
+mod completions;
+mod live_diff;
+
+use std::collections::HashMap;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde_json::{json, Value};
+
+use pixelpioneers_core::config;
+use pixelpioneers_core::diff::builder::{Renderer, Source};
+use pixelpioneers_core::diff::structs::Diff;
+use pixelpioneers_core::error::Error;
+use pixelpioneers_core::git::{self, Identity};
+use pixelpioneers_core::history::{self, Format};
+use pixelpioneers_core::observer::{Observer, Quiet};
+use pixelpioneers_core::ops::{self, CommitOptions, CommitOutcome};
+use pixelpioneers_core::project::{self, InitOptions, InitOutcome};
+use pixelpioneers_core::sb2;
+
+use completions::{Command, SHELLS};
+
+const USAGE: &str = "\
+Usage: pixelpioneers <command> [options]
+
+Commands:
+  init <project.sb3> [dir]    Start versioning a project, in a directory named after it by default
+      --name <name> --email <email>  Commit as this identity
+      --lfs                          Track binary assets with Git LFS
+  diff [dir]                  Show what changed since the last commit
+      --from <revision>              Compare from this revision instead of HEAD
+      --to <revision>                Compare to this revision instead of the saved project.json
+      --format <markdown|html|json>  How to write the changes, Markdown by default
+  diff <a.sb3> <b.sb3>        Show what changed between two project files, without a repository
+  watch [dir]                 Show what changed since the last commit again after every save
+      --from <project.sb3>           Watch this project file instead of project.json
+  commit [dir]                Commit the saved project
+      --from <project.sb3>           Unpack this project file first
+      --title <title>                Title the commit, keeping the generated changes as its body
+      --asset-store                  Keep binary assets in the asset store, off the branches
+  log [dir]                   List commits, newest first
+      --limit <count>                Only list this many
+  restore <sprite> [dir]      Put a sprite back how it was in a revision
+      --revision <revision>          Revision to restore from, HEAD by default
+  export <file> [dir]         Export by the file's extension: .sb3 for the project, .sprite3 for a
+                              sprite, and .csv or .jsonl for the project's history
+      --revision <revision>          Export as of this revision instead of as saved
+      --sprite <name>                Sprite to export as a .sprite3
+  contributors [dir]          Sum up who changed what across the project's history
+  completions <shell>         Print the completion script for bash, zsh, fish or powershell
+
+Options for every command:
+  --json                             Write output as JSON, for piping into other tools
+
+Directories default to the current one.
+";
+
+/// Subcommands, for completing them in shells
+const COMMANDS: [Command; 10] = [
+    Command {
+        name: "init",
+        about: "Start versioning a project",
+        flags: &["--name", "--email", "--lfs"],
+        values: &[],
+    },
+    Command {
+        name: "diff",
+        about: "Show what changed since the last commit",
+        flags: &["--from", "--to", "--format"],
+        values: &[],
+    },
+    Command {
+        name: "watch",
+        about: "Show what changed after every save",
+        flags: &["--from"],
+        values: &[],
+    },
+    Command {
+        name: "commit",
+        about: "Commit the saved project",
+        flags: &["--from", "--title", "--asset-store"],
+        values: &[],
+    },
+    Command {
+        name: "log",
+        about: "List commits, newest first",
+        flags: &["--limit"],
+        values: &[],
+    },
+    Command {
+        name: "restore",
+        about: "Put a sprite back how it was in a revision",
+        flags: &["--revision"],
+        values: &[],
+    },
+    Command {
+        name: "export",
+        about: "Export the project, a sprite or the history",
+        flags: &["--revision", "--sprite"],
+        values: &[],
+    },
+    Command {
+        name: "contributors",
+        about: "Sum up who changed what",
+        flags: &[],
+        values: &[],
+    },
+    Command {
+        name: "completions",
+        about: "Print a shell completion script",
+        flags: &[],
+        values: &SHELLS,
+    },
+    Command {
+        name: "help",
+        about: "Show how to use pixelpioneers",
+        flags: &[],
+        values: &[],
+    },
+];
+
+/// Flags every command takes
+const GLOBAL_FLAGS: [&str; 1] = ["--json"];
+
+/// Flags that are followed by a value
+const VALUE_FLAGS: [&str; 9] = [
+    "--name",
+    "--email",
+    "--from",
+    "--to",
+    "--format",
+    "--title",
+    "--limit",
+    "--revision",
+    "--sprite",
+];
+
+/// Represents a command's arguments, split into positional ones and flags
+struct Args {
+    positional: Vec<String>,
+    flags: HashMap<String, Option<String>>,
+}
+
+impl Args {
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Self> {
+        let mut parsed = Args {
+            positional: vec![],
+            flags: HashMap::new(),
+        };
+        while let Some(arg) = args.next() {
+            if !arg.starts_with("--") {
+                parsed.positional.push(arg);
+                continue;
+            }
+            let value = match VALUE_FLAGS.contains(&arg.as_str()) {
+                true => Some(
+                    args.next()
+                        .with_context(|| format!("{arg} needs a value"))?,
+                ),
+                false => None,
+            };
+            parsed.flags.insert(arg, value);
+        }
+        Ok(parsed)
+    }
+
+    fn has(&self, flag: &str) -> bool {
+        self.flags.contains_key(flag)
+    }
+
+    fn value(&self, flag: &str) -> Option<&str> {
+        self.flags.get(flag)?.as_deref()
+    }
+
+    /// A positional argument, or `None` if there aren't that many
+    fn nth(&self, n: usize) -> Option<&str> {
+        self.positional.get(n).map(String::as_str)
+    }
+
+    /// The project directory, from a positional argument or else the current directory
+    fn dir(&self, n: usize) -> PathBuf {
+        PathBuf::from(self.nth(n).unwrap_or("."))
+    }
+
+    /// Output for people, or as JSON with `--json`
+    fn output(&self, text: impl Into<String>, json: Value) -> Output {
+        match self.has("--json") {
+            true => Output::Json(json),
+            false => Output::Text(text.into()),
+        }
+    }
+}
+
+/// Represents what a command writes out when it succeeds
+enum Output {
+    Text(String),
+    Json(Value),
+}
+
+/// Represents a failure with details worth keeping in `--json` output, like the problems that
+/// stopped a commit
+#[derive(Debug)]
+struct Failure {
+    message: String,
+    details: Value,
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for Failure {}
+
+/// Collects warnings as an operation goes, printing them straight away for people
+#[derive(Debug, Default)]
+struct Warnings {
+    print: bool,
+    collected: Vec<String>,
+}
+
+impl Observer for Warnings {
+    fn on_warning(&mut self, warning: &str) {
+        if self.print {
+            eprintln!("warning: {warning}");
+        }
+        self.collected.push(warning.to_string());
+    }
+}
+
+/// Turn an error into JSON, with its kind from [`Error::kind`] if it has one
+fn error_json(e: &anyhow::Error) -> Value {
+    let mut error = json!({ "error": format!("{e:#}"), "kind": Error::kind_of(e) });
+    if let Some(Value::Object(details)) = e.downcast_ref::<Failure>().map(|f| &f.details) {
+        error.as_object_mut().unwrap().extend(details.clone());
+    }
+    error
+}
+
+fn main() -> ExitCode {
+    let mut args = match Args::parse(env::args().skip(1)) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("error: {e:#}");
+            return ExitCode::FAILURE;
+        }
+    };
+    // the command is the first positional argument, so global flags can come before it
+    let command = (!args.positional.is_empty()).then(|| args.positional.remove(0));
+    let result = match command.as_deref() {
+        _ if args.has("--help") => Ok(Output::Text(USAGE.to_string())),
+        Some("init") => init(&args),
+        Some("diff") => diff(&args),
+        Some("watch") => watch(&args),
+        Some("commit") => commit(&args),
+        Some("log") => log(&args),
+        Some("restore") => restore(&args),
+        Some("export") => export(&args),
+        Some("contributors") => contributors(&args),
+        Some("completions") => completions(&args),
+        Some("help") | None => Ok(Output::Text(USAGE.to_string())),
+        Some(command) => Err(anyhow!("unknown command {command}, see pixelpioneers help")),
+    };
+
+    match result {
+        Ok(Output::Text(text)) => {
+            print!("{text}");
+            if !text.is_empty() && !text.ends_with('\n') {
+                println!();
+            }
+            ExitCode::SUCCESS
+        }
+        Ok(Output::Json(json)) => {
+            println!("{json:#}");
+            ExitCode::SUCCESS
+        }
+        Err(e) if args.has("--json") => {
+            println!("{:#}", error_json(&e));
+            ExitCode::FAILURE
+        }
+        Err(e) => {
+            eprintln!("error: {e:#}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn init(args: &Args) -> Result<Output> {
+    let file = PathBuf::from(args.nth(0).context("missing the project file to version")?);
+    let dir = match args.nth(1) {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(file.file_stem().context("project file has no name")?),
+    };
+
+    // Scratch 2 projects are converted once, then versioned as the SB3 next to them
+    let file = if file.extension().is_some_and(|ext| ext == "sb2") {
+        let sb3 = file.with_extension("sb3");
+        if sb3.exists() {
+            bail!(
+                "not overwriting {} with the converted project",
+                sb3.display()
+            );
+        }
+        for skipped in sb2::convert(&file, &sb3)? {
+            eprintln!("warning: left out {skipped}, which has no Scratch 3 equivalent");
+        }
+        sb3
+    } else {
+        file
+    };
+
+    let identity = match (args.value("--name"), args.value("--email")) {
+        (Some(name), Some(email)) => Some(Identity {
+            name: name.to_string(),
+            email: email.to_string(),
+        }),
+        (None, None) => None,
+        _ => bail!("--name and --email have to be passed together"),
+    };
+    let settings = config::settings(Some(dir.as_path()));
+    let options = InitOptions {
+        identity: identity.as_ref().or(settings.identity.as_ref()),
+        lfs: args.has("--lfs"),
+        ..Default::default()
+    };
+    match project::init_project(&file, &dir, options)? {
+        InitOutcome::Created => Ok(args.output(
+            format!("Versioning {} in {}", file.display(), dir.display()),
+            json!({ "project": file, "repository": dir }),
+        )),
+        InitOutcome::NeedsIdentity => {
+            bail!("no identity to commit as, pass --name and --email")
+        }
+    }
+}
+
+fn diff(args: &Args) -> Result<Output> {
+    let renderer = match args.value("--format").unwrap_or("markdown") {
+        "json" => Renderer::Json,
+        "markdown" | "md" => Renderer::Markdown,
+        "html" => Renderer::Html,
+        format => bail!("unknown format {format}, use json, markdown or html"),
+    };
+    if let (Some(a), Some(b)) = (args.nth(0), args.nth(1)) {
+        let report = Diff::from_sb3_files(a, b)?;
+        return match renderer {
+            _ if args.has("--json") => Ok(Output::Json(serde_json::to_value(report)?)),
+            Renderer::Json => Ok(Output::Text(serde_json::to_string_pretty(&report)?)),
+            Renderer::Markdown => Ok(Output::Text(report.to_markdown())),
+            Renderer::Html => bail!("HTML reports need a repository"),
+        };
+    }
+    let dir = args.dir(0);
+    let mut builder = Diff::builder()
+        .repo(&dir)
+        .before(Source::Revision(
+            args.value("--from").unwrap_or("HEAD").into(),
+        ))
+        .renderer(renderer);
+    if let Some(to) = args.value("--to") {
+        builder = builder.after(Source::Revision(to.into()));
+    }
+    let differ = builder.build()?;
+    if args.has("--json") {
+        return Ok(Output::Json(serde_json::to_value(
+            differ.report(&mut Quiet)?,
+        )?));
+    }
+    Ok(Output::Text(differ.render()?))
+}
+
+fn watch(args: &Args) -> Result<Output> {
+    let dir = args.dir(0);
+    let saved = match args.value("--from") {
+        Some(sb3) => PathBuf::from(sb3),
+        None => dir.join("project.json"),
+    };
+    live_diff::watch(saved, &dir, args.has("--json"))?;
+    Ok(Output::Text(String::new()))
+}
+
+fn commit(args: &Args) -> Result<Output> {
+    let dir = args.dir(0);
+    // commit messages describe changes since the last commit
+    fs::write(
+        dir.join("project.old.json"),
+        git::show_revision(&dir, "HEAD:project.json")?,
+    )?;
+    if let Some(sb3) = args.value("--from") {
+        pixelpioneers_core::sb3::unpack(Path::new(sb3), &dir)?;
+    }
+
+    let options = CommitOptions {
+        asset_store: args.has("--asset-store"),
+        title: args.value("--title"),
+        sb3: args.value("--from").map(Path::new),
+        ..Default::default()
+    };
+    let mut warnings = Warnings {
+        print: !args.has("--json"),
+        ..Default::default()
+    };
+    let outcome = ops::commit(&dir, options, &mut warnings)?;
+    let code = outcome.code();
+    let message = match outcome {
+        CommitOutcome::Committed(message) => {
+            let json = json!({ "message": message, "warnings": warnings.collected });
+            return Ok(args.output(message, json));
+        }
+        CommitOutcome::Invalid(violations) => {
+            let problems = violations
+                .iter()
+                .map(|violation| format!("\n  {}: {}", violation.path, violation.message));
+            return Err(Failure {
+                message: format!(
+                    "project.json is broken, so nothing was committed:{}",
+                    problems.collect::<String>()
+                ),
+                details: json!({ "code": code, "violations": violations }),
+            }
+            .into());
+        }
+        CommitOutcome::NeedsIdentity => "no identity to commit as, set one with git config",
+        CommitOutcome::CommitFailed => "Git refused to commit, maybe nothing changed",
+        CommitOutcome::StageFailed => "failed to stage the changes",
+        CommitOutcome::MessageFailed => "couldn't generate a message, so nothing was committed",
+        CommitOutcome::HookFailed => "a pre-commit hook failed, so nothing was committed",
+    };
+    Err(Failure {
+        message: message.to_string(),
+        details: json!({ "code": code }),
+    }
+    .into())
+}
+
+fn log(args: &Args) -> Result<Output> {
+    let limit = match args.value("--limit") {
+        Some(limit) => Some(limit.parse().context("--limit must be a number")?),
+        None => None,
+    };
+    let entries = ops::log(&args.dir(0), limit)?;
+    let lines = entries.iter().map(|entry| {
+        let short = entry.commit.get(..7).unwrap_or(&entry.commit);
+        format!(
+            "{short} {} ({}, {})\n",
+            entry.subject, entry.author.name, entry.author.date
+        )
+    });
+    Ok(args.output(lines.collect::<String>(), serde_json::to_value(&entries)?))
+}
+
+fn restore(args: &Args) -> Result<Output> {
+    let sprite = args.nth(0).context("missing the sprite to restore")?;
+    let revision = args.value("--revision").unwrap_or("HEAD");
+    let message = ops::restore_sprite(&args.dir(1), revision, sprite)?;
+    Ok(args.output(
+        message.clone(),
+        json!({ "sprite": sprite, "revision": revision, "message": message }),
+    ))
+}
+
+fn export(args: &Args) -> Result<Output> {
+    let file = PathBuf::from(args.nth(0).context("missing the file to export to")?);
+    let dir = args.dir(1);
+    let revision = args.value("--revision");
+    let extension = file
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+
+    match extension.as_str() {
+        "sb3" => ops::export_revision(&dir, revision.unwrap_or("HEAD"), &file)?,
+        "sprite3" => {
+            let sprite = args
+                .value("--sprite")
+                .context("--sprite is needed for a .sprite3")?;
+            ops::export_sprite(&dir, sprite, revision, &file)?
+        }
+        extension => match Format::from_extension(extension) {
+            Some(format) => {
+                let rows = history::history(&dir)?;
+                fs::write(&file, history::format(&rows, format)?)?
+            }
+            None => bail!("can't export to .{extension}, use .sb3, .sprite3, .csv or .jsonl"),
+        },
+    }
+    Ok(args.output(
+        format!("Exported to {}", file.display()),
+        json!({ "file": file }),
+    ))
+}
+
+fn contributors(args: &Args) -> Result<Output> {
+    let contributors = history::contributors(&history::history(&args.dir(0))?);
+    let lines = contributors
+        .iter()
+        .map(|contributor| contributor.format() + "\n");
+    Ok(args.output(
+        lines.collect::<String>(),
+        serde_json::to_value(&contributors)?,
+    ))
+}
+
+fn completions(args: &Args) -> Result<Output> {
+    let shell = args.nth(0).context("missing the shell to complete for")?;
+    let script = completions::generate(shell, &COMMANDS, &GLOBAL_FLAGS)
+        .with_context(|| format!("can't complete for {shell}, use {}", SHELLS.join(", ")))?;
+    Ok(args.output(script.clone(), json!({ "shell": shell, "script": script })))
+}
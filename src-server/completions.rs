@@ -0,0 +1,181 @@
+//! Completion scripts for the `pixelpioneers` command, generated from its subcommands so they
+//! can't drift apart
+//!
+//! Subcommands complete first, then each one's flags, then fixed values like shell names, or
+//! files where a command takes paths.
+
+use std::fmt::Write;
+
+/// Represents a subcommand, as far as completing it goes
+pub struct Command {
+    pub name: &'static str,
+    pub about: &'static str,
+    pub flags: &'static [&'static str],
+    /// Values its arguments can take, or none if it takes paths
+    pub values: &'static [&'static str],
+}
+
+/// Shells completions can be generated for
+pub const SHELLS: [&str; 4] = ["bash", "zsh", "fish", "powershell"];
+
+/// Generate the completion script for a shell, or `None` if it isn't one of [`SHELLS`]
+pub fn generate(shell: &str, commands: &[Command], global: &[&str]) -> Option<String> {
+    match shell {
+        "bash" => Some(bash(commands, global)),
+        "zsh" => Some(zsh(commands, global)),
+        "fish" => Some(fish(commands, global)),
+        "powershell" => Some(powershell(commands, global)),
+        _ => None,
+    }
+}
+
+/// A command's flags along with the global ones, separated by spaces
+fn flags(command: &Command, global: &[&str]) -> String {
+    command
+        .flags
+        .iter()
+        .chain(global)
+        .copied()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn names(commands: &[Command]) -> String {
+    commands
+        .iter()
+        .map(|c| c.name)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn bash(commands: &[Command], global: &[&str]) -> String {
+    let mut cases = String::new();
+    for command in commands {
+        let _ = writeln!(
+            cases,
+            "        {}) flags=\"{}\"; values=\"{}\" ;;",
+            command.name,
+            flags(command, global),
+            command.values.join(" ")
+        );
+    }
+    format!(
+        r#"_pixelpioneers() {{
+    local cur="${{COMP_WORDS[COMP_CWORD]}}" flags="" values=""
+    if [ "$COMP_CWORD" -eq 1 ]; then
+        COMPREPLY=($(compgen -W "{names}" -- "$cur"))
+        return
+    fi
+    case "${{COMP_WORDS[1]}}" in
+{cases}    esac
+    if [[ "$cur" == -* ]]; then
+        COMPREPLY=($(compgen -W "$flags" -- "$cur"))
+    elif [ -n "$values" ]; then
+        COMPREPLY=($(compgen -W "$values" -- "$cur"))
+    else
+        COMPREPLY=($(compgen -f -- "$cur"))
+    fi
+}}
+complete -o filenames -F _pixelpioneers pixelpioneers
+"#,
+        names = names(commands)
+    )
+}
+
+fn zsh(commands: &[Command], global: &[&str]) -> String {
+    let (mut described, mut cases) = (String::new(), String::new());
+    for command in commands {
+        let _ = writeln!(described, "        '{}:{}'", command.name, command.about);
+        let _ = writeln!(
+            cases,
+            "        {}) flags=({}); values=({}) ;;",
+            command.name,
+            flags(command, global),
+            command.values.join(" ")
+        );
+    }
+    format!(
+        r#"#compdef pixelpioneers
+
+_pixelpioneers() {{
+    local -a commands flags values
+    commands=(
+{described}    )
+    if (( CURRENT == 2 )); then
+        _describe 'command' commands
+        return
+    fi
+    case $words[2] in
+{cases}    esac
+    if [[ $PREFIX == -* ]]; then
+        compadd -a flags
+    elif (( $#values )); then
+        compadd -a values
+    else
+        _files
+    fi
+}}
+
+_pixelpioneers "$@"
+"#
+    )
+}
+
+fn fish(commands: &[Command], global: &[&str]) -> String {
+    let mut script = String::from("complete -c pixelpioneers -f\n");
+    for command in commands {
+        let _ = writeln!(
+            script,
+            "complete -c pixelpioneers -n __fish_use_subcommand -a {} -d '{}'",
+            command.name, command.about
+        );
+        let seen = format!("'__fish_seen_subcommand_from {}'", command.name);
+        for flag in command.flags.iter().chain(global) {
+            let _ = writeln!(
+                script,
+                "complete -c pixelpioneers -n {seen} -l {}",
+                flag.trim_start_matches('-')
+            );
+        }
+        let _ = match command.values {
+            [] => writeln!(script, "complete -c pixelpioneers -n {seen} -F"),
+            values => writeln!(
+                script,
+                "complete -c pixelpioneers -n {seen} -a '{}'",
+                values.join(" ")
+            ),
+        };
+    }
+    script
+}
+
+fn powershell(commands: &[Command], global: &[&str]) -> String {
+    let mut table = String::new();
+    for command in commands {
+        let words = command.flags.iter().chain(global).chain(command.values);
+        let quoted = words.map(|word| format!("'{word}'")).collect::<Vec<_>>();
+        let _ = writeln!(
+            table,
+            "        '{}' = @({})",
+            command.name,
+            quoted.join(", ")
+        );
+    }
+    format!(
+        r#"Register-ArgumentCompleter -Native -CommandName pixelpioneers -ScriptBlock {{
+    param($wordToComplete, $commandAst, $cursorPosition)
+    $commands = @{{
+{table}    }}
+    $words = @($commandAst.CommandElements | ForEach-Object {{ $_.ToString() }})
+    if ($words.Count -eq 1 -or ($words.Count -eq 2 -and $wordToComplete)) {{
+        $candidates = $commands.Keys
+    }} else {{
+        $candidates = $commands[$words[1]]
+    }}
+    $candidates | Where-Object {{ $_ -like "$wordToComplete*" }} | Sort-Object | ForEach-Object {{
+        [System.Management.Automation.CompletionResult]::new($_, $_, 'ParameterValue', $_)
+    }}
+}}
+"#
+    )
+}
@@ -1,10 +1,21 @@
 use std::{
+    collections::HashMap,
     fs,
     io::Read,
     path::{Path, PathBuf},
     sync::{Mutex, OnceLock},
 };
 
+use serde::Deserialize;
+use tracing::warn;
+
+use crate::diff::label::{self, StageLabel};
+use crate::diff::structs::CommitLine;
+use crate::git::Identity;
+use crate::i18n;
+use crate::ignore::IgnoreRules;
+use crate::lint;
+
 const PROJECT_CONFIG_PATH: &str = "projects/config.json";
 const TOKEN_PATH: &str = "projects/.ghtoken";
 
@@ -41,6 +52,71 @@ impl ProjectConfig {
         Path::new(&base_loc).to_path_buf()
     }
 
+    /// Returns whether an optional feature is turned on for a project
+    pub fn flag(&self, project_name: &str, key: &str) -> bool {
+        self.projects[project_name][key].as_bool().unwrap_or(false)
+    }
+
+    /// Returns the people who work on a project, who commits can be attributed to by name
+    pub fn roster(&self, project_name: &str) -> Vec<Identity> {
+        serde_json::from_value(self.projects[project_name]["roster"].clone()).unwrap_or_default()
+    }
+
+    /// Replaces the people who work on a project
+    pub fn set_roster(&mut self, project_name: &str, roster: &[Identity]) {
+        self.projects[project_name]["roster"] = serde_json::json!(roster);
+        self.save();
+    }
+
+    /// Works out who to credit for a commit from the display name the editor gives: the author
+    /// from the roster with that name, or else the name itself, for an `Edited-by:` trailer
+    ///
+    /// Names spanning several lines are ignored, since they could add trailers of their own
+    pub fn attribute(
+        &self,
+        project_name: &str,
+        user: Option<&str>,
+    ) -> (Option<Identity>, Option<String>) {
+        let Some(user) = user.filter(|user| !user.contains(['\n', '\r'])) else {
+            return (None, None);
+        };
+        let user = label::clean(user);
+        let user = user.trim();
+        if user.is_empty() {
+            return (None, None);
+        }
+        let author = self
+            .roster(project_name)
+            .into_iter()
+            .find(|person| person.name.trim().to_lowercase() == user.to_lowercase());
+        match author {
+            Some(author) => (Some(author), None),
+            None => (None, Some(user.to_string())),
+        }
+    }
+
+    /// Returns the names of every configured project
+    pub fn project_names(&self) -> Vec<String> {
+        self.projects
+            .as_object()
+            .map(|projects| projects.keys().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Returns the paths to every configured project
+    pub fn project_paths(&self) -> Vec<PathBuf> {
+        self.projects
+            .as_object()
+            .map(|projects| {
+                projects
+                    .values()
+                    .filter_map(|p| p["base"].as_str())
+                    .map(PathBuf::from)
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
     /// Returns the path to a project's SB3 by its name
     pub fn project_sb3(&self, project_name: &str) -> String {
         self.projects[project_name]["project_file"]
@@ -90,3 +166,584 @@ pub fn gh_token() -> &'static Mutex<GhToken> {
     static CONFIG: OnceLock<Mutex<GhToken>> = OnceLock::new();
     CONFIG.get_or_init(|| Mutex::new(GhToken::new(TOKEN_PATH)))
 }
+
+/// Name of the settings file, read from the working directory and from each project
+pub const SETTINGS_FILE: &str = "pixelpioneers.toml";
+
+/// Where the server listens, overridable with `--host` and `--port`
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct ServerSettings {
+    pub host: String,
+    pub port: u16,
+    /// Web origins allowed to connect besides the Scratch and TurboWarp editors, or `"*"`
+    pub allowed_origins: Vec<String>,
+}
+
+impl Default for ServerSettings {
+    fn default() -> Self {
+        ServerSettings {
+            host: "127.0.0.1".to_string(),
+            port: 8000,
+            allowed_origins: vec![],
+        }
+    }
+}
+
+/// How generated commit messages are laid out
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum CommitStyle {
+    /// Every change on one line, separated by commas
+    #[default]
+    List,
+    /// The first change as the subject, and the rest in the body, one per line
+    Lines,
+    /// Conventional Commits, like `feat(Sprite1): +3 blocks`, for changelog tools
+    Conventional,
+}
+
+/// How changes are grouped into the parts of a generated commit message
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum CommitGrouping {
+    /// One part per sprite, like `Sprite1: +3 blocks, add cat.svg`
+    #[default]
+    Sprite,
+    /// One part per kind of change, like `scripts: Sprite1 (+3 blocks), Stage (-1 blocks)`
+    Category,
+    /// Everything squashed into a single part
+    Squash,
+}
+
+/// Templates generated commit messages are written with, where each `{name}` is a placeholder
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct CommitTemplates {
+    /// Every change in a sprite, with `{sprite}`, `{changes}`, `{count}` and `{date}`
+    pub line: String,
+    /// Asset changes of one kind, with `{action}`, `{items}` and `{count}`
+    pub assets: String,
+    /// Script changes, with `{sprite}`, `{added}` and `{removed}`, or `+1, -2 blocks` if unset
+    pub blocks: Option<String>,
+    /// Words used in place of asset actions, like `add = "new"`
+    pub actions: HashMap<String, String>,
+}
+
+impl Default for CommitTemplates {
+    fn default() -> Self {
+        CommitTemplates {
+            line: "{sprite}: {changes}".to_string(),
+            assets: "{action} {items}".to_string(),
+            blocks: None,
+            actions: HashMap::new(),
+        }
+    }
+}
+
+/// Emoji put before each kind of change in generated commit messages, like `🧩 +3 blocks`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct CommitEmoji {
+    pub enabled: bool,
+    pub scripts: String,
+    pub costumes: String,
+    pub sounds: String,
+    pub extensions: String,
+    pub variables: String,
+}
+
+impl Default for CommitEmoji {
+    fn default() -> Self {
+        CommitEmoji {
+            enabled: false,
+            scripts: "🧩".to_string(),
+            costumes: "🎨".to_string(),
+            sounds: "🔊".to_string(),
+            extensions: "🔌".to_string(),
+            variables: "📦".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct CommitSettings {
+    pub style: CommitStyle,
+    pub grouping: CommitGrouping,
+    pub templates: CommitTemplates,
+    pub emoji: CommitEmoji,
+    /// Longest a message's first line can be before changes move to the body, where 0 is no limit
+    pub max_subject_length: usize,
+    /// Language of generated commit messages, like `es`, which is English if unset or unknown
+    pub locale: String,
+    /// How the stage is marked: `localized`, `none`, or a suffix of its own like ` [backdrop]`
+    pub stage_label: StageLabel,
+    /// Warn in diffs when cloud variables are added, removed or renamed
+    pub warn_cloud_variables: bool,
+}
+
+impl Default for CommitSettings {
+    fn default() -> Self {
+        CommitSettings {
+            style: CommitStyle::default(),
+            grouping: CommitGrouping::default(),
+            templates: CommitTemplates::default(),
+            emoji: CommitEmoji::default(),
+            max_subject_length: 72,
+            locale: String::new(),
+            stage_label: StageLabel::default(),
+            warn_cloud_variables: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct DiffSettings {
+    /// Lines of context around changes in script diffs
+    pub context: i32,
+}
+
+impl Default for DiffSettings {
+    fn default() -> Self {
+        DiffSettings { context: 2000 }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct RemoteSettings {
+    /// How often to check remotes for new commits, where 0 turns checking off
+    pub poll_minutes: u64,
+    /// Comment a summary of the pushed changes on the branch's pull or merge request
+    pub comment_on_push: bool,
+}
+
+impl Default for RemoteSettings {
+    fn default() -> Self {
+        RemoteSettings {
+            poll_minutes: 5,
+            comment_on_push: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct LintSettings {
+    /// Refuse to commit projects with lint issues
+    pub on_commit: bool,
+    /// Scripts with more blocks than this are reported as too long
+    pub max_script_length: usize,
+}
+
+impl Default for LintSettings {
+    fn default() -> Self {
+        LintSettings {
+            on_commit: false,
+            max_script_length: lint::Options::default().max_script_length,
+        }
+    }
+}
+
+impl LintSettings {
+    pub fn options(&self) -> lint::Options {
+        lint::Options {
+            max_script_length: self.max_script_length,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct AutoCommitSettings {
+    /// Commit after each save in the editor, without waiting to be asked
+    pub enabled: bool,
+    /// Seconds to wait after a save for more before committing
+    pub debounce_seconds: u64,
+    /// Blocks, assets, variables and extensions that have to change for a save to be committed
+    pub min_changes: usize,
+}
+
+impl Default for AutoCommitSettings {
+    fn default() -> Self {
+        AutoCommitSettings {
+            enabled: false,
+            debounce_seconds: 30,
+            min_changes: 1,
+        }
+    }
+}
+
+/// Snapshots of each project kept outside its repository, in case Git fails or the repository
+/// gets corrupted
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct BackupSettings {
+    pub enabled: bool,
+    /// Minutes between snapshots, which are skipped when nothing changed since the last one
+    pub interval_minutes: u64,
+    /// Snapshots kept per project, where the oldest are deleted first
+    pub keep: usize,
+    /// Where snapshots are saved, relative to the server's folder, in a folder per project
+    pub directory: PathBuf,
+}
+
+impl Default for BackupSettings {
+    fn default() -> Self {
+        BackupSettings {
+            enabled: true,
+            interval_minutes: 15,
+            keep: 20,
+            directory: PathBuf::from("backups"),
+        }
+    }
+}
+
+/// Shell commands run around commits and pushes, each given the changes as JSON on stdin, and
+/// webhooks sent them afterwards
+///
+/// Only read from the server's own settings file, never from a project's
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct HookSettings {
+    /// Run before committing, where a failing command stops the commit
+    pub pre_commit: Vec<String>,
+    pub post_commit: Vec<String>,
+    /// Run after a push, with the changes that were pushed
+    pub post_push: Vec<String>,
+    /// URLs sent the changes as JSON after each commit and push
+    pub webhooks: Vec<String>,
+}
+
+/// A Slack or Discord incoming webhook posted a message after each commit
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct NotificationSettings {
+    pub url: String,
+    /// Slack channel to post in, like `#game-team`, instead of the webhook's own. Discord
+    /// webhooks always post in their own channel
+    pub channel: Option<String>,
+    /// Message to post, where `{author}`, `{project}`, `{commit}` and `{message}` are replaced
+    /// with the commit's author, the project's name, the short commit hash and its subject
+    pub template: String,
+}
+
+impl Default for NotificationSettings {
+    fn default() -> Self {
+        NotificationSettings {
+            url: String::new(),
+            channel: None,
+            template: "{author} committed: {message}".into(),
+        }
+    }
+}
+
+/// Limits for running large projects on machines without much memory
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct MemorySettings {
+    /// Megabytes diffing may use, where 0 means no limit. Under a budget, fewer sprites are
+    /// diffed at once and parsed scripts that don't fit are kept on disk
+    pub budget_mb: usize,
+}
+
+impl MemorySettings {
+    /// The budget in bytes, if there is one
+    pub fn budget(&self) -> Option<usize> {
+        (self.budget_mb > 0).then_some(self.budget_mb * 1024 * 1024)
+    }
+}
+
+/// Where temporary workspaces go
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct TempSettings {
+    /// Folder workspaces are made in, instead of the system's temporary folder
+    pub root: Option<PathBuf>,
+    /// Minutes a workspace that isn't named after the process that made it has to be untouched
+    /// before it's removed at startup
+    pub stale_minutes: u64,
+}
+
+impl Default for TempSettings {
+    fn default() -> Self {
+        TempSettings {
+            root: None,
+            stale_minutes: 60,
+        }
+    }
+}
+
+/// What's built when a version is tagged
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ReleaseSettings {
+    /// Where builds are saved, relative to the server's folder
+    pub output_dir: PathBuf,
+    /// Command that packages the tagged SB3, like the TurboWarp Packager's CLI, with `{input}`
+    /// and `{output}` standing for the SB3 and the file to build. Without one, the SB3 is the
+    /// build, ready to load into the packager by hand. Only read from the server's settings
+    pub packager: Option<String>,
+    /// File extension of what the packager builds
+    pub packager_ext: String,
+}
+
+impl Default for ReleaseSettings {
+    fn default() -> Self {
+        ReleaseSettings {
+            output_dir: PathBuf::from("builds"),
+            packager: None,
+            packager_ext: "html".into(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct OptimizeSettings {
+    /// Optimize new assets before committing them, noting how much was saved in the commit body
+    pub on_commit: bool,
+    /// Strip comments, metadata and whitespace that don't change how SVGs look
+    pub svg: bool,
+    /// Recompress PNGs and strip their text chunks, keeping every pixel
+    pub png: bool,
+    /// Command that encodes a WAV as an MP3, with `{input}` and `{output}` standing for the two
+    /// files. MP3s lose some quality, so WAVs are only stripped of extra chunks without one.
+    /// Only read from the server's settings
+    pub wav_encoder: Option<String>,
+}
+
+impl Default for OptimizeSettings {
+    fn default() -> Self {
+        OptimizeSettings {
+            on_commit: false,
+            svg: true,
+            png: true,
+            wav_encoder: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct LogSettings {
+    /// Log filter directives like `debug`, overridden by `--debug` and `SCRATCH_GIT_LOG`
+    pub level: Option<String>,
+}
+
+/// Settings from `pixelpioneers.toml`, along with the rules in a project's `.pixelpioneersignore`
+///
+/// ```toml
+/// ignored_sprites = ["Debug"]
+///
+/// [server]
+/// port = 8000
+/// allowed_origins = ["http://localhost:3000"]
+///
+/// [commit]
+/// style = "lines"
+/// grouping = "category"
+/// locale = "es"
+/// max_subject_length = 50
+/// stage_label = "none"
+/// warn_cloud_variables = true
+///
+/// [commit.templates]
+/// line = "[{date}] {sprite}: {changes}"
+/// blocks = "{added} blocks in, {removed} out"
+/// actions = { add = "new", remove = "drop" }
+///
+/// [commit.emoji]
+/// enabled = true
+/// sounds = "🎵"
+///
+/// [diff]
+/// context = 3
+///
+/// [remote]
+/// poll_minutes = 10
+/// comment_on_push = true
+///
+/// [auto_commit]
+/// enabled = true
+/// debounce_seconds = 60
+/// min_changes = 5
+///
+/// [backup]
+/// interval_minutes = 30
+/// keep = 48
+///
+/// [lint]
+/// on_commit = true
+/// max_script_length = 100
+///
+/// [hooks]
+/// post_commit = ["python upload_build.py"]
+/// webhooks = ["https://example.com/submissions"]
+///
+/// [[notifications]]
+/// url = "https://hooks.slack.com/services/T000/B000/XXXX"
+/// channel = "#game-team"
+/// template = "{author} committed to {project}: {message}"
+///
+/// [memory]
+/// budget_mb = 512
+///
+/// [temp]
+/// root = "D:/scratch-temp"
+///
+/// [release]
+/// packager = "npx @turbowarp/packager-cli {input} --output {output}"
+///
+/// [optimize]
+/// on_commit = true
+/// wav_encoder = "ffmpeg -i {input} -q:a 2 {output}"
+///
+/// [log]
+/// level = "debug"
+///
+/// [identity]
+/// name = "Scratch Cat"
+/// email = "cat@example.com"
+/// ```
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    /// Sprites left out of diffs and commit messages
+    pub ignored_sprites: Vec<String>,
+    pub server: ServerSettings,
+    pub commit: CommitSettings,
+    pub diff: DiffSettings,
+    pub remote: RemoteSettings,
+    pub lint: LintSettings,
+    pub auto_commit: AutoCommitSettings,
+    pub backup: BackupSettings,
+    pub hooks: HookSettings,
+    pub notifications: Vec<NotificationSettings>,
+    pub memory: MemorySettings,
+    pub temp: TempSettings,
+    pub release: ReleaseSettings,
+    pub optimize: OptimizeSettings,
+    pub log: LogSettings,
+    /// Used for commits when no author is given
+    pub identity: Option<Identity>,
+    /// Rules from the project's `.pixelpioneersignore`
+    #[serde(skip)]
+    pub ignore: IgnoreRules,
+}
+
+impl Settings {
+    /// Check if a sprite is ignored, where stage names may end with ` (stage)`
+    pub fn is_ignored(&self, sprite: &str) -> bool {
+        let name = label::name(sprite);
+        self.ignored_sprites.iter().any(|ignored| ignored == name) || self.ignore.sprite(sprite)
+    }
+
+    /// Check if a kind of change, like `scripts` or `sounds`, is ignored
+    pub fn is_ignored_kind(&self, kind: &str) -> bool {
+        self.ignore.change(kind)
+    }
+
+    /// Check if a sprite's scripts are ignored
+    pub fn is_ignored_script(&self, sprite: &str) -> bool {
+        self.is_ignored(sprite) || self.is_ignored_kind("scripts")
+    }
+
+    /// Check if a costume or sound is ignored, by its sprite, its name or its kind
+    pub fn is_ignored_asset(&self, sprite: &str, name: &str, kind: &str) -> bool {
+        self.is_ignored(sprite) || self.ignore.asset(name) || self.is_ignored_kind(kind)
+    }
+
+    /// Check if a variable is ignored, by its sprite or its name
+    pub fn is_ignored_variable(&self, sprite: &str, name: &str) -> bool {
+        self.is_ignored(sprite) || self.ignore.variable(name) || self.is_ignored_kind("variables")
+    }
+
+    /// Check if a generated commit message line is for an ignored sprite
+    pub fn is_ignored_line(&self, line: &CommitLine) -> bool {
+        line.scope
+            .as_deref()
+            .is_some_and(|sprite| self.is_ignored(sprite))
+    }
+}
+
+/// Read a settings file as a table, treating a missing or broken file as empty
+fn read_settings(path: &Path) -> toml::Table {
+    let Ok(contents) = fs::read_to_string(path) else {
+        return toml::Table::new();
+    };
+    contents.parse::<toml::Table>().unwrap_or_else(|e| {
+        warn!("ignoring {}: {e}", path.display());
+        toml::Table::new()
+    })
+}
+
+/// Merge `overlay` into `base`, with tables merged key by key rather than replaced
+fn merge(base: &mut toml::Table, overlay: toml::Table) {
+    for (key, value) in overlay {
+        match (base.get_mut(&key), value) {
+            (Some(toml::Value::Table(base)), toml::Value::Table(overlay)) => merge(base, overlay),
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+/// Settings that run commands or send changes elsewhere, which are only read from the server's
+/// own file, since a project's file is committed and comes with every clone, pull and merge
+const SERVER_ONLY: &[&[&str]] = &[
+    &["hooks"],
+    &["optimize", "wav_encoder"],
+    &["release", "packager"],
+    &["release", "packager_ext"],
+];
+
+/// Remove the settings a project's file isn't allowed to set
+fn strip_server_only(table: &mut toml::Table, project: &Path) {
+    'settings: for path in SERVER_ONLY {
+        let Some((key, parents)) = path.split_last() else {
+            continue;
+        };
+        let mut table = &mut *table;
+        for parent in parents {
+            match table.get_mut(*parent) {
+                Some(toml::Value::Table(parent)) => table = parent,
+                _ => continue 'settings,
+            }
+        }
+        if table.remove(*key).is_some() {
+            warn!(
+                "ignoring {} in {}, which can only be set in the server's {SETTINGS_FILE}",
+                path.join("."),
+                project.join(SETTINGS_FILE).display()
+            );
+        }
+    }
+}
+
+/// Load settings, with a project's file taking precedence over the global one
+///
+/// Hooks and other settings that run commands are only taken from the global file
+pub fn settings(project: Option<&Path>) -> Settings {
+    let mut table = read_settings(Path::new(SETTINGS_FILE));
+    if let Some(project) = project {
+        let mut overlay = read_settings(&project.join(SETTINGS_FILE));
+        strip_server_only(&mut overlay, project);
+        merge(&mut table, overlay);
+    }
+    let mut settings: Settings = toml::Value::Table(table).try_into().unwrap_or_else(|e| {
+        warn!("invalid settings: {e}");
+        Settings::default()
+    });
+    if let Some(project) = project {
+        settings.ignore = IgnoreRules::load(project);
+    }
+    let locale = &settings.commit.locale;
+    if !locale.is_empty() && !i18n::is_supported(locale) {
+        warn!("no translations for locale {locale}, so commit messages stay in English");
+    }
+    settings
+}
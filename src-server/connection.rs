@@ -0,0 +1,195 @@
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+use std::sync::Arc;
+#[cfg(windows)]
+use std::sync::Mutex;
+use std::time::Duration;
+#[cfg(windows)]
+use std::time::Instant;
+
+use anyhow::Result;
+use rustls::{ServerConfig, ServerConnection, StreamOwned};
+
+/// Most a client can send before the end of its request headers
+const MAX_HEAD: usize = 16 * 1024;
+
+/// How long a client has to send its request headers, and then each part of its body
+const READ_TIMEOUT: Duration = Duration::from_secs(30);
+
+trait Stream: Read + Write + Send {}
+impl<T: Read + Write + Send> Stream for T {}
+
+/// Handle to the socket under a connection, for setting timeouts under TLS
+enum Socket {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+    /// Read timeout of a [`Pipe`], which is where it's kept since pipes don't have one
+    #[cfg(windows)]
+    Pipe(Arc<Mutex<Option<Duration>>>),
+}
+
+/// A client connection, plain, TLS or local, whose request head can be inspected before
+/// deciding whether it's a WebSocket handshake or an HTTP request
+pub struct Connection {
+    stream: Box<dyn Stream>,
+    socket: Socket,
+    peeked: Vec<u8>,
+    position: usize,
+}
+
+impl Connection {
+    pub fn plain(stream: TcpStream) -> Result<Self> {
+        Ok(Connection {
+            socket: Socket::Tcp(stream.try_clone()?),
+            stream: Box::new(stream),
+            peeked: vec![],
+            position: 0,
+        })
+    }
+
+    /// Wrap a connection in TLS. The handshake happens on the first read
+    pub fn tls(stream: TcpStream, config: Arc<ServerConfig>) -> Result<Self> {
+        Ok(Connection {
+            socket: Socket::Tcp(stream.try_clone()?),
+            stream: Box::new(StreamOwned::new(ServerConnection::new(config)?, stream)),
+            peeked: vec![],
+            position: 0,
+        })
+    }
+
+    #[cfg(unix)]
+    pub fn local(stream: UnixStream) -> Result<Self> {
+        Ok(Connection {
+            socket: Socket::Unix(stream.try_clone()?),
+            stream: Box::new(stream),
+            peeked: vec![],
+            position: 0,
+        })
+    }
+
+    #[cfg(windows)]
+    pub fn local(stream: interprocess::local_socket::LocalSocketStream) -> Result<Self> {
+        let timeout = Arc::new(Mutex::new(None));
+        Ok(Connection {
+            socket: Socket::Pipe(timeout.clone()),
+            stream: Box::new(Pipe { stream, timeout }),
+            peeked: vec![],
+            position: 0,
+        })
+    }
+
+    /// Read the request line and headers without consuming them
+    ///
+    /// Reads time out from here on, so a client that stops sending doesn't hold a thread forever
+    pub fn peek_head(&mut self) -> io::Result<&[u8]> {
+        self.set_read_timeout(Some(READ_TIMEOUT))?;
+        let mut buffer = [0; 1024];
+        while !self.peeked.windows(4).any(|w| w == b"\r\n\r\n") && self.peeked.len() < MAX_HEAD {
+            let read = self.stream.read(&mut buffer)?;
+            if read == 0 {
+                break;
+            }
+            self.peeked.extend_from_slice(&buffer[..read]);
+        }
+        Ok(&self.peeked)
+    }
+
+    /// Address of a TCP client, which local clients don't have
+    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+        match &self.socket {
+            Socket::Tcp(tcp) => tcp.peer_addr(),
+            #[allow(unreachable_patterns)]
+            _ => Err(io::Error::new(ErrorKind::Unsupported, "local client")),
+        }
+    }
+
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        match &self.socket {
+            Socket::Tcp(tcp) => tcp.set_read_timeout(timeout),
+            #[cfg(unix)]
+            Socket::Unix(unix) => unix.set_read_timeout(timeout),
+            #[cfg(windows)]
+            Socket::Pipe(pipe) => {
+                *pipe.lock().unwrap() = timeout;
+                Ok(())
+            }
+        }
+    }
+}
+
+/// A named pipe whose reads time out
+///
+/// Named pipes can't time out reads themselves, so with a timeout they're read without blocking
+/// until there's something to read or it runs out
+#[cfg(windows)]
+struct Pipe {
+    stream: interprocess::local_socket::LocalSocketStream,
+    timeout: Arc<Mutex<Option<Duration>>>,
+}
+
+#[cfg(windows)]
+impl Pipe {
+    /// How often a pipe is checked for something to read while waiting
+    const POLL_INTERVAL: Duration = Duration::from_millis(10);
+}
+
+#[cfg(windows)]
+impl Read for Pipe {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let Some(timeout) = *self.timeout.lock().unwrap() else {
+            return self.stream.read(buf);
+        };
+
+        // writes still block, so the pipe is only non-blocking while it's read
+        self.stream.set_nonblocking(true)?;
+        let start = Instant::now();
+        let read = loop {
+            match self.stream.read(buf) {
+                Err(e) if e.kind() == ErrorKind::WouldBlock && start.elapsed() < timeout => {
+                    std::thread::sleep(Self::POLL_INTERVAL);
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                    break Err(io::Error::new(ErrorKind::TimedOut, "pipe read timed out"));
+                }
+                read => break read,
+            }
+        };
+        self.stream.set_nonblocking(false)?;
+        read
+    }
+}
+
+#[cfg(windows)]
+impl Write for Pipe {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stream.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+impl Read for Connection {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.position < self.peeked.len() {
+            let read = (&self.peeked[self.position..]).read(buf)?;
+            self.position += read;
+            return Ok(read);
+        }
+        self.stream.read(buf)
+    }
+}
+
+impl Write for Connection {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stream.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
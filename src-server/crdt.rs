@@ -0,0 +1,458 @@
+//! An experimental merge engine that treats a project as a CRDT, a conflict-free replicated
+//! data type
+//!
+//! A project is split into registers: one for each sprite, each sprite property like where it
+//! is, each script, each variable, list, broadcast and comment, each costume and sound, and each
+//! extension. Every register remembers when it was last written and by which replica, and
+//! merging keeps the latest write of each, so documents merge the same way in any order and two
+//! people's edits to different scripts of the same sprite never conflict. When both changed the
+//! same register, the later edit wins instead.
+//!
+//! Scripts are registers as a whole rather than block by block, since a script's blocks point at
+//! each other and mixing blocks from two versions of a script could break it. Sprites are matched
+//! by name, so renaming one counts as deleting it and adding another.
+
+use std::collections::BTreeMap;
+
+use serde_json::{Map, Value};
+
+/// Sprite properties kept as registers of their own, like `variables`, keyed by ID
+const KEYED: [&str; 4] = ["variables", "lists", "broadcasts", "comments"];
+
+/// Sprite properties kept as registers of their own, keyed by name and kept in order
+const ORDERED: [&str; 2] = ["costumes", "sounds"];
+
+/// When and where a register was written, ordered by time and then by replica so every replica
+/// picks the same winner
+#[derive(Debug, Clone, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Stamp {
+    /// Seconds since the Unix epoch, like a commit's timestamp
+    pub time: u64,
+    pub replica: String,
+}
+
+impl Stamp {
+    pub fn new(time: u64, replica: impl Into<String>) -> Self {
+        Stamp {
+            time,
+            replica: replica.into(),
+        }
+    }
+}
+
+/// Represents the latest write to part of a project, where `None` means it was deleted
+#[derive(Debug, Clone, PartialEq)]
+pub struct Register {
+    pub value: Option<Value>,
+    pub stamp: Stamp,
+}
+
+/// Where a register is in a project, like `["targets", "sprite:Cat", "scripts", "a1b2"]`
+type Key = Vec<String>;
+
+/// Represents a project as registers that can be edited apart and merged back together
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Document {
+    registers: BTreeMap<Key, Register>,
+}
+
+/// Identify a target apart from a sprite that happens to be named like the stage
+fn target_key(target: &Value) -> String {
+    let kind = match target["isStage"] == true {
+        true => "stage",
+        false => "sprite",
+    };
+    format!("{kind}:{}", target["name"].as_str().unwrap_or_default())
+}
+
+/// Group a sprite's blocks into scripts, keyed by the ID of each script's top block
+fn scripts(blocks: &Map<String, Value>) -> BTreeMap<String, Map<String, Value>> {
+    let mut scripts = BTreeMap::<String, Map<String, Value>>::new();
+    for (id, block) in blocks {
+        let mut top = id.as_str();
+        // a broken project can have parents pointing in a loop
+        for _ in 0..blocks.len() {
+            match blocks[top]["parent"].as_str() {
+                Some(parent) if blocks.contains_key(parent) => top = parent,
+                _ => break,
+            }
+        }
+        scripts
+            .entry(top.to_string())
+            .or_default()
+            .insert(id.clone(), block.clone());
+    }
+    scripts
+}
+
+/// Split a project into the values of its registers
+fn registers(project: &Value) -> BTreeMap<Key, Value> {
+    let key = |parts: &[&str]| parts.iter().map(|part| part.to_string()).collect::<Key>();
+    let mut registers = BTreeMap::new();
+
+    for (field, value) in project.as_object().into_iter().flatten() {
+        if field != "targets" && field != "extensions" {
+            registers.insert(key(&["project", field.as_str()]), value.clone());
+        }
+    }
+    for extension in project["extensions"].as_array().into_iter().flatten() {
+        if let Some(extension) = extension.as_str() {
+            registers.insert(key(&["extensions", extension]), Value::Bool(true));
+        }
+    }
+
+    for target in project["targets"].as_array().into_iter().flatten() {
+        let target_key = target_key(target);
+        let tk = target_key.as_str();
+        registers.insert(key(&["targets", tk]), Value::Bool(true));
+
+        for (field, value) in target.as_object().into_iter().flatten() {
+            let field = field.as_str();
+            if field != "blocks" && !KEYED.contains(&field) && !ORDERED.contains(&field) {
+                registers.insert(key(&["targets", tk, "fields", field]), value.clone());
+            }
+        }
+        for kind in KEYED {
+            for (id, value) in target[kind].as_object().into_iter().flatten() {
+                registers.insert(key(&["targets", tk, kind, id.as_str()]), value.clone());
+            }
+        }
+        for kind in ORDERED {
+            let assets = target[kind].as_array().into_iter().flatten();
+            let mut order = vec![];
+            for asset in assets {
+                let name = asset["name"].as_str().unwrap_or_default();
+                registers.insert(key(&["targets", tk, kind, name]), asset.clone());
+                order.push(Value::from(name));
+            }
+            registers.insert(key(&["targets", tk, "order", kind]), Value::Array(order));
+        }
+        if let Some(blocks) = target["blocks"].as_object() {
+            for (id, script) in scripts(blocks) {
+                registers.insert(key(&["targets", tk, "scripts", id.as_str()]), script.into());
+            }
+        }
+    }
+    registers
+}
+
+/// The parts of a target gathered from its registers
+#[derive(Default)]
+struct TargetParts<'a> {
+    exists: bool,
+    fields: Map<String, Value>,
+    keyed: BTreeMap<&'a str, Map<String, Value>>,
+    order: BTreeMap<&'a str, &'a Value>,
+    scripts: Vec<(&'a Stamp, &'a Value)>,
+}
+
+impl TargetParts<'_> {
+    fn target(mut self) -> Value {
+        let mut target = self.fields;
+        for kind in KEYED {
+            let values = self.keyed.remove(kind).unwrap_or_default();
+            target.insert(kind.to_string(), values.into());
+        }
+
+        for kind in ORDERED {
+            let mut assets = self.keyed.remove(kind).unwrap_or_default();
+            let order = self.order.get(kind).and_then(|order| order.as_array());
+            let mut ordered = order
+                .into_iter()
+                .flatten()
+                .filter_map(|name| assets.remove(name.as_str()?))
+                .collect::<Vec<_>>();
+            // ones only the other side added go after the rest
+            ordered.extend(assets.into_iter().map(|(_, asset)| asset));
+            target.insert(kind.to_string(), ordered.into());
+        }
+        let costumes = target["costumes"].as_array().map_or(0, Vec::len) as u64;
+        if let Some(current) = target.get("currentCostume").and_then(Value::as_u64) {
+            target["currentCostume"] = current.min(costumes.saturating_sub(1)).into();
+        }
+
+        // a script that was moved under another block on one side is left out of the other
+        // side's older version of it
+        let mut blocks = Map::new();
+        self.scripts.sort_by(|a, b| b.0.cmp(a.0));
+        for (_, script) in self.scripts {
+            let Some(script) = script.as_object() else {
+                continue;
+            };
+            if script.keys().any(|id| blocks.contains_key(id)) {
+                continue;
+            }
+            blocks.extend(script.clone());
+        }
+        target.insert("blocks".to_string(), blocks.into());
+        target.into()
+    }
+}
+
+impl Document {
+    /// Start a document from a project, as if all of it was written at once
+    pub fn new(project: &Value, stamp: &Stamp) -> Self {
+        let mut document = Document::default();
+        document.update(project, stamp);
+        document
+    }
+
+    /// Record the edits that turned this document into a project, stamping only what changed
+    pub fn update(&mut self, project: &Value, stamp: &Stamp) {
+        let mut new = registers(project);
+        for (key, register) in &mut self.registers {
+            let value = new.remove(key);
+            if register.value != value {
+                *register = Register {
+                    value,
+                    stamp: stamp.clone(),
+                };
+            }
+        }
+        for (key, value) in new {
+            let register = Register {
+                value: Some(value),
+                stamp: stamp.clone(),
+            };
+            self.registers.insert(key, register);
+        }
+    }
+
+    /// Merge another replica's document into this one, keeping the latest write of each register
+    pub fn merge(&mut self, other: &Document) {
+        for (key, theirs) in &other.registers {
+            match self.registers.get_mut(key) {
+                Some(ours) if ours.stamp >= theirs.stamp => {}
+                Some(ours) => *ours = theirs.clone(),
+                None => {
+                    self.registers.insert(key.clone(), theirs.clone());
+                }
+            }
+        }
+    }
+
+    /// Put the document back together as a project.json
+    pub fn project(&self) -> Value {
+        let mut project = Map::new();
+        let mut extensions = vec![];
+        let mut targets = BTreeMap::<&str, TargetParts>::new();
+
+        for (key, register) in &self.registers {
+            let Some(value) = &register.value else {
+                continue;
+            };
+            let key = key.iter().map(String::as_str).collect::<Vec<_>>();
+            match key[..] {
+                ["project", field] => {
+                    project.insert(field.to_string(), value.clone());
+                }
+                ["extensions", extension] => extensions.push(Value::from(extension)),
+                ["targets", tk] => targets.entry(tk).or_default().exists = true,
+                ["targets", tk, "fields", field] => {
+                    let parts = targets.entry(tk).or_default();
+                    parts.fields.insert(field.to_string(), value.clone());
+                }
+                ["targets", tk, "order", kind] => {
+                    targets.entry(tk).or_default().order.insert(kind, value);
+                }
+                ["targets", tk, "scripts", _] => {
+                    let parts = targets.entry(tk).or_default();
+                    parts.scripts.push((&register.stamp, value));
+                }
+                ["targets", tk, kind, id] => {
+                    let parts = targets.entry(tk).or_default();
+                    let values = parts.keyed.entry(kind).or_default();
+                    values.insert(id.to_string(), value.clone());
+                }
+                _ => {}
+            }
+        }
+
+        // the stage comes first, then sprites from back to front
+        let mut targets = targets
+            .into_values()
+            .filter(|parts| parts.exists)
+            .map(TargetParts::target)
+            .collect::<Vec<_>>();
+        targets.sort_by_key(|target| {
+            let layer = target["layerOrder"].as_u64().unwrap_or(u64::MAX);
+            (target["isStage"] != true, layer)
+        });
+        project.insert("targets".to_string(), targets.into());
+        project.insert("extensions".to_string(), extensions.into());
+        project.into()
+    }
+}
+
+/// Merge two sides' changes to a project since a common version, like Git does, where each
+/// side's changes are stamped with when they were made
+///
+/// Nothing conflicts: where both sides changed the same thing, the side with the later stamp
+/// wins. A sprite one side deleted stays deleted, even if the other side changed it.
+pub fn merge(base: &Value, sides: [(&Value, Stamp); 2]) -> Value {
+    let documents = sides.map(|(side, stamp)| {
+        let mut document = Document::new(base, &Stamp::default());
+        document.update(side, &stamp);
+        document
+    });
+    let [mut ours, theirs] = documents;
+    ours.merge(&theirs);
+    ours.project()
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn block(opcode: &str, parent: Option<&str>) -> Value {
+        json!({"opcode": opcode, "parent": parent, "next": null, "topLevel": parent.is_none()})
+    }
+
+    fn project(blocks: Value, costumes: &[&str]) -> Value {
+        let costumes = costumes.iter().map(|name| json!({"name": name}));
+        let target = |name: &str, blocks: Value, costumes: Vec<Value>| {
+            json!({
+                "isStage": name == "Stage",
+                "name": name,
+                "layerOrder": if name == "Stage" { 0 } else { 1 },
+                "currentCostume": 0,
+                "blocks": blocks,
+                "costumes": costumes,
+                "sounds": [],
+                "variables": {},
+                "lists": {},
+                "broadcasts": {},
+                "comments": {},
+            })
+        };
+        let mut cat = target("Cat", blocks, costumes.collect());
+        cat["x"] = json!(0);
+        json!({
+            "targets": [target("Stage", json!({}), vec![]), cat],
+            "extensions": [],
+        })
+    }
+
+    fn cat(project: &Value) -> &Value {
+        &project["targets"][1]
+    }
+
+    fn base() -> Value {
+        let blocks = json!({
+            "a": block("event_whenflagclicked", None),
+            "a2": block("motion_movesteps", Some("a")),
+            "b": block("event_whenkeypressed", None),
+        });
+        project(blocks, &["walk", "run"])
+    }
+
+    #[test]
+    fn edits_to_different_scripts_both_stay() {
+        let base = base();
+        let mut ours = base.clone();
+        ours["targets"][1]["blocks"]["a2"]["opcode"] = json!("motion_turnright");
+        let mut theirs = base.clone();
+        theirs["targets"][1]["blocks"]["b"]["opcode"] = json!("event_whenthisspriteclicked");
+
+        let merged = merge(
+            &base,
+            [
+                (&ours, Stamp::new(1, "us")),
+                (&theirs, Stamp::new(2, "them")),
+            ],
+        );
+        assert_eq!(cat(&merged)["blocks"]["a2"]["opcode"], "motion_turnright");
+        assert_eq!(
+            cat(&merged)["blocks"]["b"]["opcode"],
+            "event_whenthisspriteclicked"
+        );
+    }
+
+    #[test]
+    fn later_edits_win_in_any_order() {
+        let base = base();
+        let mut ours = base.clone();
+        ours["targets"][1]["x"] = json!(10);
+        let mut theirs = base.clone();
+        theirs["targets"][1]["x"] = json!(20);
+
+        let (early, late) = (Stamp::new(1, "us"), Stamp::new(2, "them"));
+        let merged = merge(&base, [(&ours, early.clone()), (&theirs, late.clone())]);
+        assert_eq!(cat(&merged)["x"], 20);
+        let swapped = merge(&base, [(&theirs, late), (&ours, early)]);
+        assert_eq!(merged, swapped);
+
+        // the same time goes to the same replica everywhere
+        let merged = merge(
+            &base,
+            [(&ours, Stamp::new(1, "a")), (&theirs, Stamp::new(1, "b"))],
+        );
+        assert_eq!(cat(&merged)["x"], 20);
+    }
+
+    #[test]
+    fn deleted_sprites_stay_deleted() {
+        let base = base();
+        let mut ours = base.clone();
+        ours["targets"].as_array_mut().unwrap().pop();
+        let mut theirs = base.clone();
+        theirs["targets"][1]["x"] = json!(20);
+
+        let merged = merge(
+            &base,
+            [
+                (&ours, Stamp::new(1, "us")),
+                (&theirs, Stamp::new(2, "them")),
+            ],
+        );
+        assert_eq!(merged["targets"].as_array().unwrap().len(), 1);
+        assert_eq!(merged["targets"][0]["name"], "Stage");
+    }
+
+    #[test]
+    fn costumes_keep_their_order() {
+        let base = base();
+        let mut ours = base.clone();
+        ours["targets"][1]["costumes"] = json!([{"name": "run"}, {"name": "walk"}]);
+        let mut theirs = base.clone();
+        theirs["targets"][1]["costumes"] =
+            json!([{"name": "walk"}, {"name": "run"}, {"name": "sit"}]);
+
+        let merged = merge(
+            &base,
+            [
+                (&ours, Stamp::new(1, "us")),
+                (&theirs, Stamp::new(2, "them")),
+            ],
+        );
+        let names = cat(&merged)["costumes"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|c| &c["name"]);
+        assert_eq!(names.collect::<Vec<_>>(), ["walk", "run", "sit"]);
+
+        // the current costume can't be one that was deleted
+        let mut deleted = base.clone();
+        deleted["targets"][1]["costumes"] = json!([{"name": "walk"}]);
+        let mut current = base.clone();
+        current["targets"][1]["currentCostume"] = json!(1);
+        let merged = merge(
+            &base,
+            [
+                (&deleted, Stamp::new(1, "us")),
+                (&current, Stamp::new(2, "them")),
+            ],
+        );
+        assert_eq!(cat(&merged)["currentCostume"], 0);
+    }
+
+    #[test]
+    fn documents_round_trip() {
+        let base = base();
+        let document = Document::new(&base, &Stamp::default());
+        assert_eq!(document.project(), base);
+    }
+}
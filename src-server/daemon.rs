@@ -0,0 +1,125 @@
+use std::env;
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::sync::OnceLock;
+use std::thread::sleep;
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+
+use crate::discovery;
+use crate::shutdown::PID_PATH;
+
+/// Locked for as long as a server runs, so two can't manage the same repositories
+const LOCK_PATH: &str = "projects/.server.lock";
+
+/// Where a background server logs to unless `--log-file` says otherwise
+const DAEMON_LOG: &str = "projects/server.log";
+
+/// Set for the background process, which has no terminal to prompt on
+const DAEMON_ENV: &str = "SCRATCH_GIT_DAEMON";
+
+/// Check if this process is a background server started with `--daemon`
+pub fn is_daemon() -> bool {
+    env::var_os(DAEMON_ENV).is_some()
+}
+
+fn try_lock() -> Result<File> {
+    let _ = fs::create_dir("projects");
+    let file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(LOCK_PATH)?;
+
+    match file.try_lock() {
+        Ok(()) => Ok(file),
+        Err(TryLockError::WouldBlock) => {
+            let pid = fs::read_to_string(PID_PATH)
+                .map(|pid| format!(" (PID {})", pid.trim()))
+                .unwrap_or_default();
+            Err(anyhow!(
+                "another scratch.git server is already running in this folder{pid}"
+            ))
+        }
+        Err(TryLockError::Error(e)) => Err(e.into()),
+    }
+}
+
+/// Hold the workspace lock until the process exits, failing if another server holds it
+pub fn lock_workspace() -> Result<()> {
+    static LOCK: OnceLock<File> = OnceLock::new();
+    let file = try_lock()?;
+    let _ = LOCK.set(file);
+    Ok(())
+}
+
+/// Start the server again as a detached background process, then exit once it's listening
+pub fn daemonize(turbowarp_path: &Path) -> ! {
+    if let Err(e) = try_lock() {
+        eprintln!("{e}");
+        std::process::exit(1);
+    }
+
+    let mut args = env::args()
+        .skip(1)
+        .filter(|arg| arg != "--daemon")
+        .collect::<Vec<_>>();
+    if !args.iter().any(|arg| arg == "--turbowarp-path") {
+        args.extend(["--turbowarp-path".into(), turbowarp_path.display().to_string()]);
+    }
+    if !args.iter().any(|arg| arg == "--log-file") {
+        args.extend(["--log-file".into(), DAEMON_LOG.into()]);
+    }
+
+    let mut command = Command::new(env::current_exe().expect("failed to find executable"));
+    command
+        .args(&args)
+        .env(DAEMON_ENV, "1")
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+
+    // detach from the terminal so closing it or pressing Ctrl+C doesn't stop the server
+    #[cfg(unix)]
+    std::os::unix::process::CommandExt::process_group(&mut command, 0);
+    #[cfg(windows)]
+    {
+        const DETACHED_PROCESS: u32 = 0x0000_0008;
+        const CREATE_NEW_PROCESS_GROUP: u32 = 0x0000_0200;
+        std::os::windows::process::CommandExt::creation_flags(
+            &mut command,
+            DETACHED_PROCESS | CREATE_NEW_PROCESS_GROUP,
+        );
+    }
+
+    let mut child = match command.spawn() {
+        Ok(child) => child,
+        Err(e) => {
+            eprintln!("Failed to start scratch.git in the background: {e}");
+            std::process::exit(1);
+        }
+    };
+
+    for _ in 0..100 {
+        if let Some(address) = discovery::read().filter(|a| a.pid == child.id()) {
+            println!(
+                "scratch.git is running in the background (PID {}, port {})",
+                address.pid, address.port
+            );
+            std::process::exit(0);
+        }
+        if let Ok(Some(status)) = child.try_wait() {
+            eprintln!("scratch.git exited on startup ({status}), see {DAEMON_LOG}");
+            std::process::exit(1);
+        }
+        sleep(Duration::from_millis(100));
+    }
+
+    println!(
+        "Started scratch.git in the background (PID {}), but it hasn't started listening yet",
+        child.id()
+    );
+    std::process::exit(0);
+}
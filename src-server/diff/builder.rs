@@ -0,0 +1,213 @@
+//! Setting up a diff in one place, with where each version of the project comes from and the
+//! options to diff with, instead of passing them to each function separately
+//!
+//! ```ignore
+//! let report = Diff::builder()
+//!     .repo("path/to/project")
+//!     .before(Source::Revision("HEAD~1".into()))
+//!     .context(10)
+//!     .ignore_sprite("Debug")
+//!     .renderer(Renderer::Markdown)
+//!     .build()?
+//!     .render()?;
+//! ```
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+
+use super::structs::{CommitLine, Diff, DiffReport};
+use crate::cancel::CancelToken;
+use crate::config::{self, CommitGrouping};
+use crate::html_report;
+use crate::observer::Quiet;
+use crate::ops::{self, Progress};
+use crate::sb3::Project;
+
+/// Where a version of a project comes from
+#[derive(Debug, Clone)]
+pub enum Source {
+    /// A project that's already parsed
+    Project(Project),
+    /// project.json as of a commit, branch or tag, like `HEAD~1`
+    Revision(String),
+    /// A project.json file, relative to the repository unless absolute
+    File(PathBuf),
+}
+
+/// How [`Differ::render`] writes out a report
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Renderer {
+    #[default]
+    Json,
+    /// GitHub-flavored Markdown with each sprite's script diff, like for a pull request
+    Markdown,
+    /// A standalone HTML page
+    Html,
+}
+
+/// Sets up a [`Differ`], started by [`Diff::builder`]
+///
+/// Defaults to comparing a project's last unzip (project.old.json) to its current save, with the
+/// settings in the repository. Anything set here is used over those settings.
+#[derive(Debug, Clone)]
+pub struct DiffBuilder {
+    repo: PathBuf,
+    old: Source,
+    new: Source,
+    context: Option<i32>,
+    ignored_sprites: Vec<String>,
+    grouping: Option<CommitGrouping>,
+    renderer: Renderer,
+    title: String,
+    cancel: CancelToken,
+}
+
+impl Default for DiffBuilder {
+    fn default() -> Self {
+        DiffBuilder {
+            repo: PathBuf::from("."),
+            old: Source::File("project.old.json".into()),
+            new: Source::File("project.json".into()),
+            context: None,
+            ignored_sprites: vec![],
+            grouping: None,
+            renderer: Renderer::default(),
+            title: "Changes".into(),
+            cancel: CancelToken::new(),
+        }
+    }
+}
+
+impl DiffBuilder {
+    /// Repository the project is in, where revisions and settings are read from
+    pub fn repo(mut self, repo: impl Into<PathBuf>) -> Self {
+        self.repo = repo.into();
+        self
+    }
+
+    /// Version of the project to diff from
+    pub fn before(mut self, source: Source) -> Self {
+        self.old = source;
+        self
+    }
+
+    /// Version of the project to diff to
+    pub fn after(mut self, source: Source) -> Self {
+        self.new = source;
+        self
+    }
+
+    /// Lines of context around changes in script diffs
+    pub fn context(mut self, lines: i32) -> Self {
+        self.context = Some(lines);
+        self
+    }
+
+    /// Leave a sprite out of the report, on top of the ones ignored in the settings
+    pub fn ignore_sprite(mut self, sprite: impl Into<String>) -> Self {
+        self.ignored_sprites.push(sprite.into());
+        self
+    }
+
+    /// How changes are grouped into parts of the generated commit message
+    pub fn grouping(mut self, grouping: CommitGrouping) -> Self {
+        self.grouping = Some(grouping);
+        self
+    }
+
+    /// How the report is written out by [`Differ::render`]
+    pub fn renderer(mut self, renderer: Renderer) -> Self {
+        self.renderer = renderer;
+        self
+    }
+
+    /// Title of an HTML report
+    pub fn title(mut self, title: impl Into<String>) -> Self {
+        self.title = title.into();
+        self
+    }
+
+    /// Stop diffing early once a token is cancelled
+    pub fn cancel(mut self, cancel: CancelToken) -> Self {
+        self.cancel = cancel;
+        self
+    }
+
+    /// Read both versions of the project, ready to diff
+    pub fn build(self) -> Result<Differ> {
+        let mut settings = config::settings(Some(self.repo.as_path()));
+        if let Some(context) = self.context {
+            settings.diff.context = context;
+        }
+        if let Some(grouping) = self.grouping {
+            settings.commit.grouping = grouping;
+        }
+        settings.ignored_sprites.extend(self.ignored_sprites);
+
+        let read = |source: Source| -> Result<Diff> {
+            let mut diff = match source {
+                Source::Project(project) => Diff::new(project),
+                Source::Revision(revision) => {
+                    Diff::from_revision(&self.repo, &format!("{revision}:project.json"))?
+                }
+                Source::File(path) => {
+                    let contents = fs::read_to_string(self.repo.join(&path))
+                        .with_context(|| format!("failed to read {}", path.display()))?;
+                    Diff::new(ops::parse_project(&contents)?)
+                }
+            };
+            diff.settings = Some(settings.clone());
+            Ok(diff.with_cancel(self.cancel.clone()))
+        };
+        Ok(Differ {
+            old: read(self.old)?,
+            new: read(self.new)?,
+            repo: self.repo,
+            renderer: self.renderer,
+            title: self.title,
+        })
+    }
+}
+
+/// Two versions of a project set up to diff, made with [`DiffBuilder`]
+#[derive(Debug)]
+pub struct Differ {
+    pub old: Diff,
+    pub new: Diff,
+    repo: PathBuf,
+    renderer: Renderer,
+    title: String,
+}
+
+impl Differ {
+    /// Return the changes, leaving out ignored sprites
+    pub fn report(&self, progress: &mut Progress) -> Result<DiffReport> {
+        ops::compare(&self.repo, &self.old, &self.new, progress)
+    }
+
+    /// Generate the commit message lines for the changes
+    pub fn commits(&self) -> Result<Vec<CommitLine>> {
+        self.old.commits(&self.repo, &self.new)
+    }
+
+    /// Diff each changed sprite's scripts as text, keyed by the sprite's name
+    pub fn script_diffs(&self, report: &DiffReport) -> Result<BTreeMap<String, String>> {
+        ops::script_diffs(&self.repo, &self.old, &self.new, report)
+    }
+
+    /// Write out the changes with the chosen renderer
+    pub fn render(&self) -> Result<String> {
+        let report = self.report(&mut Quiet)?;
+        match self.renderer {
+            Renderer::Json => Ok(serde_json::to_string_pretty(&report)?),
+            Renderer::Markdown => Ok(report.to_markdown_with(&self.script_diffs(&report)?)),
+            Renderer::Html => {
+                let diffs = self.script_diffs(&report)?;
+                html_report::render(&self.repo, &self.title, &report, &diffs)
+            }
+        }
+    }
+}
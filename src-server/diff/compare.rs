@@ -0,0 +1,339 @@
+//! Comparing the parts of two projects that don't need Git, like their assets and extensions
+//!
+//! These are what `Diff` reports, and what the browser bindings report too, so
+//! both agree on what changed.
+
+use std::collections::{HashMap, HashSet};
+
+use serde::Serialize;
+
+use crate::sb3::{AssetPathError, Project};
+
+#[derive(Debug, Eq, Hash, PartialEq, Copy, Clone, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AssetChangeType {
+    Before,
+    After,
+}
+
+/// Represents a changed costume for a sprite or the stage
+#[derive(Debug, Eq, Hash, PartialEq, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AssetChange {
+    pub sprite: String,
+    pub name: String,
+    pub ext: String,
+    pub path: String,
+    pub on_stage: bool,
+    pub kind: Option<AssetChangeType>,
+}
+
+/// Represents an asset that moved to another sprite, with the same content or edited on the way
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AssetMove {
+    pub from_sprite: String,
+    pub change: AssetChange,
+}
+
+/// Represents an asset whose file changed format while keeping its name, like a costume
+/// converted from vector to bitmap
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AssetConversion {
+    /// The extension it had before, like `svg`
+    pub from_ext: String,
+    pub change: AssetChange,
+}
+
+/// Represents an asset with the same content that changed in several sprites at once
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AssetShare {
+    pub sprites: Vec<String>,
+    pub change: AssetChange,
+}
+
+/// Represents costumes that were added, removed, changed, or moved between sprites
+#[derive(Debug)]
+pub struct AssetChanges {
+    pub added: Vec<AssetChange>,
+    pub removed: Vec<AssetChange>,
+    pub merged: Vec<AssetChange>,
+    /// Assets changed to another format, which aren't in `merged`
+    pub converted: Vec<AssetConversion>,
+    pub moved: Vec<AssetMove>,
+    /// Assets added to more than one sprite, which are only stored once
+    pub shared_added: Vec<AssetShare>,
+    /// Assets removed from more than one sprite
+    pub shared_removed: Vec<AssetShare>,
+}
+
+impl AssetChanges {
+    /// Keep only the changes `keep` returns true for, whichever way they changed
+    pub fn retain(&mut self, keep: impl Fn(&AssetChange) -> bool) {
+        self.added.retain(&keep);
+        self.removed.retain(&keep);
+        self.merged.retain(&keep);
+        self.converted.retain(|c| keep(&c.change));
+        self.moved.retain(|m| keep(&m.change));
+        self.shared_added.retain(|s| keep(&s.change));
+        self.shared_removed.retain(|s| keep(&s.change));
+    }
+}
+
+/// How a project's use of an extension changed
+#[derive(Debug, Eq, PartialEq, Copy, Clone, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExtensionChangeType {
+    Added,
+    Removed,
+    /// A custom extension is loaded from a different URL
+    Moved,
+}
+
+/// Represents an extension a project started or stopped using
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExtensionChange {
+    pub id: String,
+    /// Where a custom extension is loaded from, which built-in extensions don't have
+    pub url: Option<String>,
+    pub kind: ExtensionChangeType,
+}
+
+impl ExtensionChange {
+    /// Git commit representation of an extension change
+    pub fn format(&self) -> String {
+        let action = match self.kind {
+            ExtensionChangeType::Added => "add",
+            ExtensionChangeType::Removed => "remove",
+            ExtensionChangeType::Moved => "move",
+        };
+        format!("{action} {}", self.id)
+    }
+}
+
+/// Return the costumes and sounds in `new` that aren't in `old`, by sprite and file
+///
+/// Fails if an asset's file name can't be worked out, since it can't be told apart from others
+pub fn assets(
+    old: &Project,
+    new: &Project,
+    kind: Option<AssetChangeType>,
+) -> Result<Vec<AssetChange>, AssetPathError> {
+    let old_assets = every_asset(old, kind)?.collect::<HashSet<_>>();
+    Ok(every_asset(new, kind)?
+        .filter(|asset| !old_assets.contains(asset))
+        .collect())
+}
+
+/// Return every costume and sound in a project as a change of a certain kind
+fn every_asset(
+    project: &Project,
+    kind: Option<AssetChangeType>,
+) -> Result<impl Iterator<Item = AssetChange>, AssetPathError> {
+    let assets = project
+        .targets
+        .iter()
+        .map(|sprite| {
+            let costumes = sprite.costumes.iter().map(|costume| {
+                costume.path().map(|path| {
+                    let ext = costume.data_format.clone();
+                    (costume.name.clone(), ext, path)
+                })
+            });
+            let sounds = sprite.sounds.iter().map(|sound| {
+                sound.path().map(|path| {
+                    let ext = sound.data_format.clone();
+                    (sound.name.clone(), ext, path)
+                })
+            });
+            let assets = costumes.chain(sounds).collect::<Result<Vec<_>, _>>()?;
+            Ok((sprite.display_name(), sprite.is_stage, assets))
+        })
+        .collect::<Result<Vec<_>, AssetPathError>>()?;
+
+    Ok(assets
+        .into_iter()
+        .flat_map(move |(sprite, on_stage, assets)| {
+            assets
+                .into_iter()
+                .map(move |(name, ext, path)| AssetChange {
+                    sprite: sprite.clone(),
+                    name,
+                    path,
+                    ext,
+                    on_stage,
+                    kind,
+                })
+        }))
+}
+
+/// Return the costumes and sounds added, removed, changed or moved between two projects
+pub fn asset_changes(old: &Project, new: &Project) -> Result<AssetChanges, AssetPathError> {
+    let mut added = assets(old, new, None)?;
+    let removed = assets(new, old, None)?;
+    // an asset with the same name in the same sprite on both sides was changed, not replaced,
+    // and paths are ignored since they change on save
+    let key = |asset: &AssetChange| (asset.sprite.clone(), asset.name.clone());
+    let added_keys = added.iter().map(key).collect::<HashSet<_>>();
+    let merged = removed
+        .iter()
+        .filter(|item| added_keys.contains(&key(item)))
+        .cloned()
+        .collect::<HashSet<_>>();
+    let merged_keys = merged.iter().map(key).collect::<HashSet<_>>();
+
+    // one whose format changed was converted, like a costume from vector to bitmap, which
+    // can lose detail, so it's told apart from an edit
+    let new_versions = added
+        .iter()
+        .map(|item| (key(item), item))
+        .collect::<HashMap<_, _>>();
+    let (converted, merged): (Vec<_>, Vec<_>) = merged.into_iter().partition(|item| {
+        new_versions
+            .get(&key(item))
+            .is_some_and(|new| new.ext != item.ext)
+    });
+    let converted = converted
+        .into_iter()
+        .map(|item| AssetConversion {
+            change: new_versions[&key(&item)].clone(),
+            from_ext: item.ext,
+        })
+        .collect::<Vec<_>>();
+    added.retain(|item| !merged_keys.contains(&key(item)));
+    let mut removed = removed
+        .into_iter()
+        .filter(|item| !merged_keys.contains(&key(item)))
+        .collect::<Vec<_>>();
+
+    // assets are named by their MD5, so the same path in another sprite is the same content
+    let mut by_path = HashMap::<String, Vec<usize>>::new();
+    for (i, item) in removed.iter().enumerate() {
+        by_path.entry(item.path.clone()).or_default().push(i);
+    }
+    let mut moved_from = vec![false; removed.len()];
+    let mut moved = vec![];
+    added.retain(|item| {
+        let candidates = by_path.get_mut(&item.path);
+        let Some(from) = candidates.and_then(|candidates| {
+            let pos = candidates
+                .iter()
+                .position(|&i| removed[i].sprite != item.sprite)?;
+            Some(candidates.remove(pos))
+        }) else {
+            return true;
+        };
+        moved_from[from] = true;
+        moved.push(AssetMove {
+            from_sprite: removed[from].sprite.clone(),
+            change: item.clone(),
+        });
+        false
+    });
+    let mut moved_from = moved_from.into_iter();
+    removed.retain(|_| !moved_from.next().unwrap_or(false));
+
+    let shared_added = group_shared(&mut added);
+    let shared_removed = group_shared(&mut removed);
+
+    Ok(AssetChanges {
+        added,
+        removed,
+        merged,
+        converted,
+        moved,
+        shared_added,
+        shared_removed,
+    })
+}
+
+/// Return the extensions added, removed or loaded from somewhere else in a newer project
+pub fn extensions(old: &Project, new: &Project) -> Vec<ExtensionChange> {
+    let change = |project: &Project, id: &String, kind| ExtensionChange {
+        id: id.clone(),
+        url: project.extension_url(id).map(str::to_string),
+        kind,
+    };
+
+    let mut changes = vec![];
+    for id in new.extensions() {
+        if !old.extensions().contains(id) {
+            changes.push(change(new, id, ExtensionChangeType::Added));
+        } else if old.extension_url(id) != new.extension_url(id) {
+            changes.push(change(new, id, ExtensionChangeType::Moved));
+        }
+    }
+    for id in old.extensions() {
+        if !new.extensions().contains(id) {
+            changes.push(change(old, id, ExtensionChangeType::Removed));
+        }
+    }
+    changes
+}
+
+/// Pull out assets with the same content that changed in more than one sprite, so each is
+/// reported once along with every sprite it changed in
+pub fn group_shared(changes: &mut Vec<AssetChange>) -> Vec<AssetShare> {
+    let mut sprites: HashMap<String, Vec<String>> = HashMap::new();
+    for change in changes.iter() {
+        sprites
+            .entry(change.path.clone())
+            .or_default()
+            .push(change.sprite.clone());
+    }
+    sprites.retain(|_, sprites| sprites.len() > 1);
+
+    let mut shared = vec![];
+    changes.retain(|change| {
+        let Some(sprites) = sprites.get_mut(&change.path) else {
+            return true;
+        };
+        // only the first change is kept, the rest are the same asset
+        if !sprites.is_empty() {
+            shared.push(AssetShare {
+                sprites: std::mem::take(sprites),
+                change: change.clone(),
+            });
+        }
+        false
+    });
+    shared
+}
+
+/// Lines added and removed to turn one text into another, like Git counts them for a diff
+///
+/// Lines that are in both but moved count as removed and added again.
+pub fn changed_lines(old: &str, new: &str) -> (usize, usize) {
+    let (old, new) = (
+        old.lines().collect::<Vec<_>>(),
+        new.lines().collect::<Vec<_>>(),
+    );
+    // what's the same at either end can't be part of a change
+    let prefix = old.iter().zip(&new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let old = &old[prefix..old.len() - suffix];
+    let new = &new[prefix..new.len() - suffix];
+
+    // the longest run of lines kept in order, a row at a time
+    let mut previous = vec![0; new.len() + 1];
+    for line in old {
+        let mut row = vec![0; new.len() + 1];
+        for (j, other) in new.iter().enumerate() {
+            row[j + 1] = match line == other {
+                true => previous[j] + 1,
+                false => row[j].max(previous[j + 1]),
+            };
+        }
+        previous = row;
+    }
+    let kept = previous[new.len()];
+    (new.len() - kept, old.len() - kept)
+}
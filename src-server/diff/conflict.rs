@@ -0,0 +1,129 @@
+use std::collections::{BTreeSet, HashMap};
+use std::path::PathBuf;
+
+use anyhow::Result;
+use serde::Serialize;
+
+use super::parse_script::{parse_sprite, Sprite};
+use crate::git;
+use crate::sb3::{Project, Target};
+
+/// Represents a script both sides of a merge changed differently, in project terms
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Conflict {
+    pub sprite: String,
+    pub on_stage: bool,
+    /// ID of the script's top block, which stays the same across edits
+    pub script_id: String,
+    /// Opcode of the script's hat block
+    pub hat: String,
+    /// First line of the script, for listing conflicts
+    pub preview: String,
+    /// Script contents on our side, or `None` if we deleted it
+    pub ours: Option<String>,
+    /// Script contents on their side, or `None` if they deleted it
+    pub theirs: Option<String>,
+}
+
+/// Return every top-level script in a sprite as (hat opcode, scratchblocks-like text), keyed by ID
+pub(crate) fn scripts(target: &Target) -> HashMap<String, (String, String)> {
+    target
+        .blocks()
+        .filter(|(_, block)| block.top_level)
+        .filter_map(|(id, block)| {
+            let text = parse_sprite(Sprite {
+                name: &target.name,
+                blocks: &target.blocks,
+                top_ids: vec![id.clone()],
+            })
+            .ok()?;
+            Some((id.clone(), (block.opcode.clone(), text)))
+        })
+        .collect()
+}
+
+/// Find a sprite (or the stage) in a project.json by name
+fn target<'a>(project: &'a Project, name: &str, on_stage: bool) -> Option<&'a Target> {
+    project
+        .targets
+        .iter()
+        .find(|t| t.name == name && t.is_stage == on_stage)
+}
+
+/// Compare both sides of a merge against their common base, returning scripts that
+/// were changed on both sides in different ways
+///
+/// Scripts only one side changed can be merged automatically, so they aren't reported
+pub fn conflicts(base: Option<&Project>, ours: &Project, theirs: &Project) -> Vec<Conflict> {
+    let names = [ours, theirs]
+        .iter()
+        .flat_map(|p| &p.targets)
+        .map(|t| (t.name.clone(), t.is_stage))
+        .collect::<BTreeSet<_>>();
+
+    let mut conflicts = vec![];
+    for (name, on_stage) in names {
+        let get = |project: &Project| {
+            target(project, &name, on_stage)
+                .map(scripts)
+                .unwrap_or_default()
+        };
+        let (our_scripts, their_scripts) = (get(ours), get(theirs));
+        let base_scripts = base.map(get);
+
+        let ids = our_scripts
+            .keys()
+            .chain(their_scripts.keys())
+            .collect::<BTreeSet<_>>();
+
+        for id in ids {
+            let our_script = our_scripts.get(id);
+            let their_script = their_scripts.get(id);
+            if our_script.map(|s| &s.1) == their_script.map(|s| &s.1) {
+                continue;
+            }
+            if let Some(base_scripts) = &base_scripts {
+                let base_script = base_scripts.get(id).map(|s| &s.1);
+                if our_script.map(|s| &s.1) == base_script
+                    || their_script.map(|s| &s.1) == base_script
+                {
+                    continue;
+                }
+            }
+
+            let (hat, text) = our_script.or(their_script).unwrap();
+            conflicts.push(Conflict {
+                sprite: name.clone(),
+                on_stage,
+                script_id: id.clone(),
+                hat: hat.clone(),
+                preview: text.trim().lines().next().unwrap_or_default().trim().to_string(),
+                ours: our_script.map(|s| s.1.clone()),
+                theirs: their_script.map(|s| s.1.clone()),
+            });
+        }
+    }
+
+    conflicts
+}
+
+/// Return the script conflicts in a repository's unmerged project.json, if there is one
+///
+/// During a `pull --rebase`, "ours" is the remote version and "theirs" is the local commit
+pub fn from_repo(cwd: &PathBuf) -> Result<Vec<Conflict>> {
+    if !git::conflicted_files(cwd)?.iter().any(|f| f == "project.json") {
+        return Ok(vec![]);
+    }
+
+    let stage = |n: u8| -> Option<Project> {
+        serde_json::from_str(&git::show_revision(cwd, &format!(":{n}:project.json")).ok()?).ok()
+    };
+
+    let (base, ours, theirs) = (stage(1), stage(2), stage(3));
+    Ok(conflicts(
+        base.as_ref(),
+        &ours.unwrap_or_default(),
+        &theirs.unwrap_or_default(),
+    ))
+}
@@ -0,0 +1,135 @@
+//! How sprites are named in what's generated, like commit messages and changelogs
+//!
+//! Diffs name the stage with a ` (stage)` suffix, so a sprite that happens to be called `Stage`
+//! isn't mixed up with it. That form is the key everything matches sprites by, and [`Labeler`]
+//! turns it into what's shown, which can be translated, customized, or left off.
+
+use serde::Deserialize;
+
+use crate::i18n::translate;
+pub use crate::sb3::STAGE_SUFFIX;
+
+/// A sprite's key, marking the stage unless its name is marked already
+pub fn key(sprite: &str, on_stage: bool) -> String {
+    if on_stage && !sprite.ends_with(STAGE_SUFFIX) {
+        format!("{sprite}{STAGE_SUFFIX}")
+    } else {
+        sprite.to_string()
+    }
+}
+
+/// A sprite's name from its key, without the stage's suffix
+pub fn name(key: &str) -> &str {
+    key.strip_suffix(STAGE_SUFFIX).unwrap_or(key)
+}
+
+/// Make a name safe to put in a line of generated text
+///
+/// Scratch allows any character in names, so line breaks and other control characters, which
+/// would split a commit message's lines, become spaces. Bidirectional overrides are dropped, since
+/// they make what's shown differ from what's there.
+pub fn clean(name: &str) -> String {
+    name.chars()
+        .filter(|c| !matches!(c, '\u{202a}'..='\u{202e}' | '\u{2066}'..='\u{2069}'))
+        .map(|c| if c.is_control() { ' ' } else { c })
+        .collect()
+}
+
+/// How the stage is marked in generated text
+///
+/// Read from a string: `localized`, `none`, or the suffix itself, like ` [backdrop]`.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(from = "String")]
+pub enum StageLabel {
+    /// ` (stage)`, with `stage` in the commit locale's language
+    #[default]
+    Localized,
+    /// The stage's name alone
+    None,
+    /// The stage's name followed by this
+    Suffix(String),
+}
+
+impl From<String> for StageLabel {
+    fn from(label: String) -> Self {
+        match label.as_str() {
+            "localized" => StageLabel::Localized,
+            "none" => StageLabel::None,
+            _ => StageLabel::Suffix(label),
+        }
+    }
+}
+
+/// Turns sprite keys into the names shown in generated text
+#[derive(Debug, Clone, Default)]
+pub struct Labeler {
+    pub stage: StageLabel,
+    /// Language the stage is marked in with [`StageLabel::Localized`]
+    pub locale: String,
+}
+
+impl Labeler {
+    pub fn new(stage: StageLabel, locale: impl Into<String>) -> Self {
+        Labeler {
+            stage,
+            locale: locale.into(),
+        }
+    }
+
+    /// Name a sprite by its key, marking the stage however it's set to be
+    pub fn label(&self, key: &str) -> String {
+        let key = clean(key);
+        let Some(name) = key.strip_suffix(STAGE_SUFFIX) else {
+            return key;
+        };
+        match &self.stage {
+            StageLabel::Localized => format!("{name} ({})", translate(&self.locale, "stage")),
+            StageLabel::None => name.to_string(),
+            StageLabel::Suffix(suffix) => format!("{name}{suffix}"),
+        }
+    }
+
+    /// Name a sprite, marking it if it's the stage
+    pub fn label_sprite(&self, sprite: &str, on_stage: bool) -> String {
+        self.label(&key(sprite, on_stage))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clean_keeps_names_on_one_line() {
+        assert_eq!(clean("Cat\nDog\r\tBird"), "Cat Dog  Bird");
+        assert_eq!(clean("Cat\u{0}"), "Cat ");
+    }
+
+    #[test]
+    fn clean_drops_bidi_overrides() {
+        assert_eq!(clean("\u{202e}gnp.exe"), "gnp.exe");
+        assert_eq!(clean("a\u{2066}b\u{2069}c"), "abc");
+    }
+
+    #[test]
+    fn clean_keeps_everything_else() {
+        assert_eq!(clean("Cat: 🐱 (v2) — été"), "Cat: 🐱 (v2) — été");
+        // joined emoji are made with a zero width joiner, which isn't a control character
+        assert_eq!(clean("👩‍🚀"), "👩‍🚀");
+    }
+
+    #[test]
+    fn label_cleans_and_marks_the_stage() {
+        let labeler = Labeler::new(StageLabel::None, "");
+        assert_eq!(labeler.label("Cat\n🐱"), "Cat 🐱");
+        assert_eq!(labeler.label_sprite("Stage\u{202e}", true), "Stage");
+
+        let labeler = Labeler::new(StageLabel::Suffix(" [backdrop]".into()), "");
+        assert_eq!(
+            labeler.label_sprite("Stage: 1", true),
+            "Stage: 1 [backdrop]"
+        );
+        // a sprite can be called `Stage` without being mixed up with the stage
+        assert_eq!(labeler.label_sprite("Stage", false), "Stage");
+    }
+}
@@ -1,382 +1,1477 @@
+pub mod builder;
+pub mod compare;
+pub mod conflict;
+pub mod label;
 pub mod parse_script;
 pub mod structs;
+pub mod template;
 pub mod vec_utils;
 
-use parse_script::{parse_sprite, Sprite};
+use builder::DiffBuilder;
+use parse_script::{count_blocks, parse_sprite, Sprite};
 use structs::*;
 
-use std::path::PathBuf;
+use std::borrow::Cow;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::channel;
+use std::sync::{Mutex, OnceLock};
+use std::thread;
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{BTreeMap, HashMap, HashSet},
     vec,
 };
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 use itertools::EitherOrBoth::{Both, Left, Right};
 use itertools::Itertools;
-use serde_json::{Map, Value};
+use serde_json::{json, Value};
+use tracing::instrument;
 
+use crate::cache::{CacheStats, LruCache};
+use crate::cancel::CancelToken;
+use crate::config::{self, CommitGrouping, CommitSettings, Settings};
+use crate::error::Error;
+use crate::extensions;
 use crate::git;
-use vec_utils::{group_items, intersect_costumes};
+use crate::observer::{Observer, Quiet};
+use crate::ops;
+use crate::sb3::{self, AssetPathError, Block, BlockEntry, Costume, Project, Target, Variable};
+use crate::temp::TempWorkspace;
+use template::asset_kind;
+use vec_utils::group_items;
+
+/// Most parsed scripts kept on disk, past which the least recently spilled are deleted
+const SPILLED_SCRIPTS: usize = 4096;
+
+/// Parsed scripts of recently diffed sprites, keyed by a hash of their blocks
+struct ScriptCache {
+    parsed: LruCache<[u8; 16], String>,
+    /// Bytes of parsed scripts held in memory
+    bytes: usize,
+    /// Where scripts that don't fit in memory are spilled, made the first time one is
+    spill: Option<TempWorkspace>,
+    /// Scripts spilled to disk
+    spilled: LruCache<[u8; 16], ()>,
+}
+
+impl ScriptCache {
+    /// Look up a parsed script, moving it back into memory if it was spilled to disk
+    fn get(&mut self, hash: &[u8; 16], limit: Option<usize>) -> Option<String> {
+        if let Some(parsed) = self.parsed.get(hash) {
+            return Some(parsed);
+        }
+        let spill = self.spill.as_ref()?;
+        if !self.spilled.contains(hash) {
+            return None;
+        }
+        let path = spill.path().join(hex(hash));
+        let parsed = fs::read_to_string(&path).ok();
+        let _ = fs::remove_file(path);
+        self.spilled.retain(|spilled| spilled != hash);
+        let parsed = parsed?;
+        self.insert(*hash, parsed.clone(), limit);
+        Some(parsed)
+    }
+
+    /// Cache a parsed script, spilling the least recently used ones to disk while more than
+    /// `limit` bytes are held in memory
+    fn insert(&mut self, hash: [u8; 16], parsed: String, limit: Option<usize>) {
+        self.bytes += parsed.len();
+        if let Some((_, evicted)) = self.parsed.insert(hash, parsed) {
+            self.bytes -= evicted.len();
+        }
+        let Some(limit) = limit else {
+            return;
+        };
+        while self.bytes > limit {
+            let Some((hash, evicted)) = self.parsed.pop_oldest() else {
+                break;
+            };
+            self.bytes -= evicted.len();
+            // a script that can't be spilled is just parsed again when it's needed
+            let _ = self.spill(hash, &evicted);
+        }
+    }
+
+    /// Write a parsed script to disk, deleting the oldest spilled one if there are too many
+    fn spill(&mut self, hash: [u8; 16], parsed: &str) -> Result<()> {
+        let spill = match &self.spill {
+            Some(spill) => spill,
+            None => self.spill.insert(TempWorkspace::new("scripts")?),
+        };
+        fs::write(spill.path().join(hex(&hash)), parsed)?;
+        if let Some((oldest, ())) = self.spilled.insert(hash, ()) {
+            fs::remove_file(spill.path().join(hex(&oldest)))?;
+        }
+        Ok(())
+    }
+}
+
+fn script_cache() -> &'static Mutex<ScriptCache> {
+    static CACHE: OnceLock<Mutex<ScriptCache>> = OnceLock::new();
+    CACHE.get_or_init(|| {
+        Mutex::new(ScriptCache {
+            parsed: LruCache::new(256),
+            bytes: 0,
+            spill: None,
+            spilled: LruCache::new(SPILLED_SCRIPTS),
+        })
+    })
+}
+
+/// Hit rate of the parsed script cache
+pub fn script_cache_stats() -> CacheStats {
+    script_cache().lock().unwrap().parsed.stats()
+}
+
+/// Drop every parsed script, in memory and spilled to disk
+pub fn clear_script_cache() {
+    let mut cache = script_cache().lock().unwrap();
+    cache.parsed.clear();
+    cache.bytes = 0;
+    cache.spilled.clear();
+    cache.spill = None;
+}
+
+fn hex(hash: &[u8; 16]) -> String {
+    hash.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+/// Rough bytes needed to diff one block, counting its parsed text on both sides and the diff
+const BLOCK_BYTES: usize = 1024;
+
+/// Hash a sprite's blocks, which is much cheaper than parsing and diffing them
+fn blocks_hash(target: &Target) -> Result<[u8; 16]> {
+    Ok(md5::compute(serde_json::to_vec(&target.blocks)?).0)
+}
+
+/// Rename the variables a sprite's blocks use to their names in a newer project, so a renamed
+/// variable is reported once rather than as a change to every block that uses it
+///
+/// `renames` is from [`Diff::variable_renames`]. The sprite is only copied when something was
+/// renamed
+pub fn rename_variables<'a>(
+    target: &'a Target,
+    renames: &HashMap<String, String>,
+) -> Cow<'a, Target> {
+    if renames.is_empty() {
+        return Cow::Borrowed(target);
+    }
+    // variables are referenced as [12, name, ID] in inputs and on the workspace
+    let rename_primitive = |primitive: &mut Vec<Value>| {
+        if primitive.first() != Some(&json!(12)) {
+            return;
+        }
+        if let Some(name) = primitive
+            .get(2)
+            .and_then(Value::as_str)
+            .and_then(|id| renames.get(id))
+        {
+            primitive[1] = Value::String(name.clone());
+        }
+    };
+
+    let mut target = target.clone();
+    for entry in target.blocks.values_mut() {
+        match entry {
+            BlockEntry::Block(block) => {
+                for input in block.inputs.values_mut().filter_map(Value::as_array_mut) {
+                    input
+                        .iter_mut()
+                        .filter_map(Value::as_array_mut)
+                        .for_each(rename_primitive);
+                }
+                // and as [name, ID] in variable fields
+                let field = block
+                    .fields
+                    .get_mut("VARIABLE")
+                    .and_then(Value::as_array_mut);
+                if let Some(field) = field {
+                    let renamed = field
+                        .get(1)
+                        .and_then(Value::as_str)
+                        .and_then(|id| renames.get(id));
+                    if let Some(name) = renamed {
+                        field[0] = Value::String(name.clone());
+                    }
+                }
+            }
+            BlockEntry::Primitive(primitive) => rename_primitive(primitive),
+            BlockEntry::Other(_) => {}
+        }
+    }
+    Cow::Owned(target)
+}
+
+/// A custom block's argument IDs and names, from its prototype's mutation, where the names are
+/// in the same order as the inputs in its proccode
+fn prototype_arguments(block: &Block) -> Option<(&str, Vec<String>, Vec<String>)> {
+    let mutation = block.mutation.as_ref()?;
+    let list = |key: &str| -> Option<Vec<String>> {
+        serde_json::from_str(mutation.get(key)?.as_str()?).ok()
+    };
+    let proccode = mutation.get("proccode")?.as_str()?;
+    Some((proccode, list("argumentids")?, list("argumentnames")?))
+}
+
+/// The custom blocks a sprite defines, by proccode, with their argument names by ID
+fn procedures(target: &Target) -> HashMap<&str, HashMap<String, String>> {
+    target
+        .blocks
+        .values()
+        .filter_map(|entry| match entry {
+            BlockEntry::Block(block) if block.opcode == "procedures_prototype" => {
+                prototype_arguments(block)
+            }
+            _ => None,
+        })
+        .map(|(proccode, ids, names)| (proccode, ids.into_iter().zip(names).collect()))
+        .collect()
+}
+
+/// Custom block arguments renamed between two versions of a sprite, as old names to new ones
+/// by the proccode of the block they're in
+///
+/// Arguments keep their IDs when they're renamed, and renaming one doesn't change the proccode.
+pub fn renamed_arguments(old: &Target, new: &Target) -> BTreeMap<String, BTreeMap<String, String>> {
+    let old = procedures(old);
+    procedures(new)
+        .into_iter()
+        .filter_map(|(proccode, new_names)| {
+            let old_names = old.get(proccode)?;
+            let renames = old_names
+                .iter()
+                .filter_map(|(id, old_name)| {
+                    let name = new_names.get(id)?;
+                    (name != old_name).then(|| (old_name.clone(), name.clone()))
+                })
+                .collect::<BTreeMap<_, _>>();
+            (!renames.is_empty()).then(|| (proccode.to_string(), renames))
+        })
+        .collect()
+}
+
+/// Rename the custom block arguments a sprite's blocks use to their names in a newer version of
+/// it, so a renamed argument is reported once rather than as a change to every block that uses it
+///
+/// The sprite is only copied when something was renamed
+pub fn rename_arguments<'a>(target: Cow<'a, Target>, new: &Target) -> Cow<'a, Target> {
+    let renames = renamed_arguments(&target, new);
+    if renames.is_empty() {
+        return target;
+    }
+    let mut target = target.into_owned();
+
+    // argument reporters only say which argument they are by name, so they're renamed by the
+    // custom block whose definition they're in
+    let block_of = |id: &str| match target.blocks.get(id) {
+        Some(BlockEntry::Block(block)) => Some(block),
+        _ => None,
+    };
+    let mut reporters = vec![];
+    for (id, entry) in &target.blocks {
+        let BlockEntry::Block(block) = entry else {
+            continue;
+        };
+        if !block.opcode.starts_with("argument_reporter_") {
+            continue;
+        }
+        let mut top = block;
+        // a broken project can have parents pointing in a loop
+        for _ in 0..target.blocks.len() {
+            match top.parent.as_deref().and_then(block_of) {
+                Some(parent) => top = parent,
+                None => break,
+            }
+        }
+        let proccode = match top.opcode.as_str() {
+            "procedures_definition" => top
+                .input_block("custom_block")
+                .and_then(block_of)
+                .and_then(prototype_arguments)
+                .map(|(proccode, ..)| proccode),
+            // the reporters shown on a prototype being edited
+            "procedures_prototype" => prototype_arguments(top).map(|(proccode, ..)| proccode),
+            _ => None,
+        };
+        if let Some(renames) = proccode.and_then(|proccode| renames.get(proccode)) {
+            reporters.push((id.clone(), renames));
+        }
+    }
+    for (id, renames) in reporters {
+        let Some(BlockEntry::Block(reporter)) = target.blocks.get_mut(&id) else {
+            continue;
+        };
+        let value = reporter
+            .fields
+            .get_mut("VALUE")
+            .and_then(Value::as_array_mut);
+        if let Some(value) = value {
+            let renamed = value
+                .first()
+                .and_then(Value::as_str)
+                .and_then(|name| renames.get(name));
+            if let Some(name) = renamed {
+                value[0] = Value::String(name.clone());
+            }
+        }
+    }
+
+    for entry in target.blocks.values_mut() {
+        let BlockEntry::Block(block) = entry else {
+            continue;
+        };
+        let Some((proccode, _, names)) = prototype_arguments(block) else {
+            continue;
+        };
+        let Some(renames) = renames.get(proccode) else {
+            continue;
+        };
+        let names = names
+            .iter()
+            .map(|name| renames.get(name).unwrap_or(name))
+            .collect::<Vec<_>>();
+        let names = serde_json::to_string(&names).unwrap_or_default();
+        if let Some(mutation) = block.mutation.as_mut() {
+            mutation["argumentnames"] = Value::String(names);
+        }
+    }
+    Cow::Owned(target)
+}
+
+/// Parse a sprite's scripts into text, reusing the last parse of the same blocks
+///
+/// With a memory budget, a quarter of it is kept for parsed scripts and the rest are spilled to
+/// disk, where they're read back from instead of being parsed again
+fn parse_cached(target: &Target, hash: [u8; 16], budget: Option<usize>) -> Result<String> {
+    let limit = budget.map(|budget| budget / 4);
+    if let Some(parsed) = script_cache().lock().unwrap().get(&hash, limit) {
+        return Ok(parsed);
+    }
+
+    let parsed = parse_sprite(Sprite::of(target))
+        .with_context(|| format!("failed to parse {}", target.name))?;
+    script_cache()
+        .lock()
+        .unwrap()
+        .insert(hash, parsed.clone(), limit);
+    Ok(parsed)
+}
 
 impl Diff {
     /// Construct a new diff from a project.json
     ///
+    /// ```ignore
+    /// let project = serde_json::from_str::<Project>(r#"{"targets":[{"isStage":true,"name":"Stage", ... }]}"#)?;
+    /// Diff::new(project);
     /// ```
-    /// let project = json!({"targets":[{"isStage":true,"name":"Stage","variables": ... "monitors":[],"extensions":[]}});
-    /// Diff::new(&project);
-    /// ```
-    pub fn new(data: &Value) -> Self {
-        Diff { data: data.clone() }
+    pub fn new(data: Project) -> Self {
+        Diff {
+            data,
+            cancel: CancelToken::new(),
+            settings: None,
+        }
+    }
+
+    /// Start setting up a diff between two versions of a project
+    pub fn builder() -> DiffBuilder {
+        DiffBuilder::default()
+    }
+
+    /// Settings the diff is worked out with, which are the project's unless overridden
+    pub fn settings(&self, cwd: &Path) -> Settings {
+        match &self.settings {
+            Some(settings) => settings.clone(),
+            None => config::settings(Some(cwd)),
+        }
     }
 
     /// Construct a new diff from a project.json located in a certain Git revision
     ///
-    /// ```
+    /// ```ignore
     /// let pth: PathBuf = "path/to/project".into();
     /// // diff for previous commit
     /// Diff::from_revision(&pth, "HEAD~1:project.json");
     /// ```
     pub fn from_revision(pth: &PathBuf, commit: &str) -> Result<Self> {
-        let json = git::show_revision(pth, commit);
-        let data = serde_json::from_str::<serde_json::Value>(&json?)?;
-        Ok(Diff { data: data.clone() })
-    }
-
-    /// Attempt to return the MD5 extension of a costume item (project.json)
-    pub fn get_asset_path(costume: Value) -> String {
-        costume["md5ext"]
-            .as_str()
-            .map(|md5| md5.to_string())
-            .unwrap_or(format!(
-                "{}.{}",
-                costume["assetId"].as_str().unwrap(),
-                costume["dataFormat"].as_str().unwrap()
-            ))
-    }
-
-    /// Return costumes that have changed between projects, but not added or removed
-    fn _merged_costumes<'a>(&'a self, new: &'a Self) -> AssetChanges {
-        let mut added = self.assets(new, None);
-        let mut removed = new.assets(self, None);
-
-        let _m1 = added.iter().map(|x| x.to_owned()).collect::<HashSet<_>>();
-        let _m2 = removed
-            .iter()
-            .map(|x| x.to_owned())
-            .collect::<HashSet<_>>()
-            .to_owned();
+        // parsed from the cached copy, or streamed from Git when it's too big to cache
+        let project = git::parse_revision::<Project>(pth, commit).map_err(|e| {
+            match Error::find(&e) {
+                // parsed again loosely to point at what's broken
+                Some(Error::ParseFailure { .. }) => {
+                    git::parse_revision::<serde_json::Value>(pth, commit)
+                        .ok()
+                        .and_then(|project| sb3::malformed(&project))
+                        .map_or(e, Into::into)
+                }
+                _ => e,
+            }
+        })?;
+        Ok(Diff::new(project))
+    }
 
-        let merged = intersect_costumes(vec![_m1, _m2]);
+    /// Compare two SB3 files without a repository, like a downloaded remix against the original,
+    /// with the default settings
+    ///
+    /// Only project.json is read from each, since assets are named by their hashes.
+    ///
+    /// ```ignore
+    /// let report = Diff::from_sb3_files("original.sb3", "remix.sb3")?;
+    /// println!("{}", report.to_markdown());
+    /// ```
+    pub fn from_sb3_files(a: impl AsRef<Path>, b: impl AsRef<Path>) -> Result<DiffReport> {
+        let read = |path: &Path| {
+            sb3::read_project_json(path)
+                .with_context(|| format!("failed to read {}", path.display()))
+        };
+        let old = Diff::new(read(a.as_ref())?);
+        let new = Diff::new(read(b.as_ref())?);
+        ops::compare_detached(&old, &new, &mut Quiet)
+    }
 
-        let they_match =
-            |a: &AssetChange, b: &AssetChange| a.name == b.name && a.sprite == b.sprite;
+    /// Compare the projects at the tips of two branches, or any two revisions, with the
+    /// project's settings
+    ///
+    /// The tips are compared directly rather than from where the branches split, so anything
+    /// `from` has that `to` doesn't shows up as removed.
+    ///
+    /// ```ignore
+    /// let report = Diff::between_refs(&pth, "main", "experiment")?;
+    /// ```
+    pub fn between_refs(pth: &PathBuf, from: &str, to: &str) -> Result<DiffReport> {
+        let project = |rev: &str| -> Result<Diff> {
+            let commit = git::resolve(pth, rev)?;
+            Diff::from_revision(pth, &format!("{commit}:project.json"))
+                .with_context(|| format!("failed to read project on {rev}"))
+        };
+        ops::compare(pth, &project(from)?, &project(to)?, &mut Quiet)
+    }
 
-        for item in &merged {
-            if let Some(pos) = added.iter().position(|x| they_match(x, item)) {
-                added.remove(pos);
-            }
-        }
-        for item in &merged {
-            if let Some(pos) = removed.iter().position(|x| they_match(x, item)) {
-                removed.remove(pos);
-            }
+    /// Stop script diffing early once a token is cancelled
+    pub fn with_cancel(mut self, cancel: CancelToken) -> Self {
+        self.cancel = cancel;
+        self
+    }
+
+    /// Return the costumes and sounds added, removed, changed or moved in a newer project
+    ///
+    /// Besides assets with the same name or content, ones Git detects as renamed between the last
+    /// commit and the working directory are paired up, like a costume edited and moved to another
+    /// sprite at once
+    pub fn asset_changes(&self, cwd: &PathBuf, new: &Self) -> Result<AssetChanges> {
+        let mut changes = compare::asset_changes(&self.data, &new.data)?;
+        if !changes.added.is_empty() && !changes.removed.is_empty() {
+            // a repository without commits has nothing to rename from
+            let renames = git::renames(cwd, "HEAD", None).unwrap_or_default();
+            Self::apply_renames(&mut changes, &renames);
         }
+        Ok(changes)
+    }
 
-        AssetChanges {
-            added,
-            removed,
-            merged: Vec::from_iter(merged),
+    /// Pair removed and added assets that Git detected as renamed
+    pub fn apply_renames(changes: &mut AssetChanges, renames: &[git::Rename]) {
+        for rename in renames.iter().filter(|r| !r.copy) {
+            let Some(to) = changes.added.iter().position(|x| x.path == rename.to) else {
+                continue;
+            };
+            let Some(from) = changes.removed.iter().position(|x| x.path == rename.from) else {
+                continue;
+            };
+            let from = changes.removed.remove(from);
+            let to = changes.added.remove(to);
+            if from.sprite == to.sprite {
+                changes.merged.push(to);
+            } else {
+                changes.moved.push(AssetMove {
+                    from_sprite: from.sprite,
+                    change: to,
+                });
+            }
         }
     }
 
     /// Return the costume differences between each sprite in two projects
     // `kind` is used to mark changes as a certain type for frontend purposes
-    pub fn assets(&self, new: &Self, kind: Option<AssetChangeType>) -> Vec<AssetChange> {
-        let new_assets: Vec<AssetChange> = new
-            ._assets()
-            .into_iter()
-            .map(|(sprite, changes)| {
-                changes
-                    .iter()
-                    .map(|costume| AssetChange {
-                        sprite: sprite.clone(),
-                        name: costume.0.clone(),
-                        path: costume.2.clone(),
-                        ext: costume.1.clone(),
-                        on_stage: costume.3,
-                        contents: None,
-                        kind,
-                    })
-                    .collect::<Vec<AssetChange>>()
-            })
-            .flatten()
-            .collect();
+    ///
+    /// Fails if an asset's file name can't be worked out, since it can't be told apart from others
+    pub fn assets(
+        &self,
+        new: &Self,
+        kind: Option<AssetChangeType>,
+    ) -> Result<Vec<AssetChange>, AssetPathError> {
+        compare::assets(&self.data, &new.data, kind)
+    }
 
-        let old_assets: Vec<AssetChange> = self
-            ._assets()
+    /// Group and format a set of asset changes into proper commits
+    pub fn format_assets(
+        &self,
+        changes: Vec<AssetChange>,
+        action: &'static str,
+        commit: &CommitSettings,
+    ) -> Vec<(String, String)> {
+        // kinds come from the asset's file, since a name can have anything in it, even an extension
+        let mut groups = BTreeMap::<String, (Vec<String>, Vec<&str>)>::new();
+        for change in &changes {
+            let sprite = label::key(&change.sprite, change.on_stage);
+            let (items, kinds) = groups.entry(sprite).or_default();
+            items.push(label::clean(&format!("{}.{}", change.name, change.ext)));
+            let kind = asset_kind(&change.path);
+            if !kinds.contains(&kind) {
+                kinds.push(kind);
+            }
+        }
+        groups
             .into_iter()
-            .map(|(sprite, changes)| {
-                changes
-                    .iter()
-                    .map(|costume| AssetChange {
-                        sprite: sprite.clone(),
-                        name: costume.0.clone(),
-                        path: costume.2.clone(),
-                        ext: costume.1.clone(),
-                        on_stage: costume.3,
-                        contents: None,
-                        kind,
-                    })
-                    .collect::<Vec<AssetChange>>()
+            .map(|(sprite, (items, mut kinds))| {
+                // costumes come before sounds
+                kinds.sort();
+                (sprite, commit.emoji(&kinds, commit.assets(action, &items)))
             })
-            .flatten()
-            .collect();
-
-        let _old_set = HashSet::from_iter(old_assets);
-        let _new_set = HashSet::<AssetChange>::from_iter(new_assets.clone());
-        let difference: _ = Vec::from_iter(_new_set.difference(&_old_set));
-        new_assets
-            .into_iter()
-            .filter(|x| difference.contains(&x))
             .collect()
     }
 
-    /// Return the path to every costume being used
-    fn _assets(&self) -> HashMap<String, Vec<(String, String, String, bool)>> {
-        let mut assets: HashMap<String, Vec<(String, String, String, bool)>> = HashMap::new();
-        if let Some(sprites) = self.data["targets"].as_array() {
-            for sprite in sprites {
-                if let Some(sprite_costumes) = sprite["costumes"].as_array() {
-                    assets.insert(
-                        sprite["name"].as_str().unwrap().to_string()
-                            + if sprite["isStage"].as_bool().unwrap() {
-                                " (stage)"
-                            } else {
-                                ""
-                            },
-                        sprite_costumes
-                            .iter()
-                            .map(|costume| {
-                                (
-                                    costume["name"].as_str().unwrap().to_string(),
-                                    costume["dataFormat"].as_str().unwrap().to_string(),
-                                    Diff::get_asset_path(costume.clone()),
-                                    sprite["isStage"].as_bool().unwrap(),
-                                )
-                            })
-                            .collect(),
-                    );
+    /// Return the extensions added, removed or loaded from somewhere else in a newer project
+    pub fn extensions(&self, new: &Self) -> Vec<ExtensionChange> {
+        compare::extensions(&self.data, &new.data)
+    }
+
+    /// Return the extensions a newer project's added and changed scripts use blocks from that
+    /// aren't built into the editor that saved it, most used first
+    pub fn unknown_extensions(&self, new: &Self) -> Vec<UnknownExtension> {
+        // mods have extensions of their own built in
+        let format = new.data.format();
+        let mut found = BTreeMap::<&str, UnknownExtension>::new();
+        for target in &new.data.targets {
+            let old_scripts = self
+                .data
+                .targets
+                .iter()
+                .find(|old| old.name == target.name && old.is_stage == target.is_stage)
+                .map(|old| old.scripts())
+                .unwrap_or_default();
+            for (id, script) in target.scripts() {
+                if old_scripts.get(id) == Some(&script) {
+                    continue;
                 }
-                if let Some(sprite_sounds) = sprite["sounds"].as_array() {
-                    assets
-                        .get_mut(
-                            &(sprite["name"].as_str().unwrap().to_string()
-                                + if sprite["isStage"].as_bool().unwrap() {
-                                    " (stage)"
-                                } else {
-                                    ""
-                                }),
-                        )
-                        .unwrap()
-                        .extend(
-                            sprite_sounds
-                                .iter()
-                                .map(|sound| {
-                                    (
-                                        sound["name"].as_str().unwrap().to_string(),
-                                        sound["dataFormat"].as_str().unwrap().to_string(),
-                                        Diff::get_asset_path(sound.clone()),
-                                        sprite["isStage"].as_bool().unwrap(),
-                                    )
-                                })
-                                .collect::<Vec<_>>(),
-                        );
+                for entry in script.into_values() {
+                    let BlockEntry::Block(block) = entry else {
+                        continue;
+                    };
+                    if format.is_known(&block.opcode) {
+                        continue;
+                    }
+                    let prefix = extensions::prefix(&block.opcode);
+                    let extension = found.entry(prefix).or_insert_with(|| UnknownExtension {
+                        id: prefix.to_string(),
+                        url: new.data.extension_url(prefix).map(str::to_string),
+                        blocks: 0,
+                        sprites: vec![],
+                    });
+                    extension.blocks += 1;
+                    let sprite = target.display_name();
+                    if !extension.sprites.contains(&sprite) {
+                        extension.sprites.push(sprite);
+                    }
                 }
             }
         }
-        assets
+        let mut found = found.into_values().collect::<Vec<_>>();
+        found.sort_by_key(|found| std::cmp::Reverse(found.blocks));
+        found
     }
 
-    /// Group and format a set of asset changes into proper commits
-    pub fn format_assets(
-        &self,
-        changes: Vec<AssetChange>,
-        action: &'static str,
-    ) -> Vec<(String, String)> {
-        let _changes: Vec<(String, String)> = changes
-            .iter()
-            .map(|change| {
-                (
-                    change.sprite.to_owned() + if change.on_stage { " (stage)" } else { "" },
-                    format!("{} {}.{}", action, change.name, change.ext),
-                )
-            })
-            .collect();
-        let mut commits: HashMap<String, String> = HashMap::new();
-        for (sprite, actions) in group_items(_changes) {
-            let split_ = actions
+    /// Return the broadcasts added, removed or renamed in a newer project, with the scripts that
+    /// send and receive each one
+    ///
+    /// Broadcasts are matched by ID, so renaming one isn't counted as removing it and adding
+    /// another
+    pub fn broadcasts(&self, new: &Self) -> Vec<BroadcastChange> {
+        fn broadcasts(project: &Project) -> BTreeMap<&str, &str> {
+            project
+                .targets
                 .iter()
-                .map(|a| {
-                    let parts = a.split_at(a.match_indices(" ").nth(0).unwrap().0);
-                    (parts.0.to_string(), parts.1.to_string())
-                })
-                .collect::<Vec<_>>();
+                .flat_map(|target| target.broadcasts())
+                .collect()
+        }
+        fn uses(project: &Project, id: &str) -> (Vec<BroadcastUse>, Vec<BroadcastUse>) {
+            let mut uses = (vec![], vec![]);
+            for target in &project.targets {
+                for (block_id, block) in target.blocks() {
+                    if block.broadcast() != Some(id) {
+                        continue;
+                    }
+                    let script = BroadcastUse {
+                        sprite: target.display_name(),
+                        script_id: target.script_of(block_id).to_string(),
+                    };
+                    match block.opcode.as_str() {
+                        "event_whenbroadcastreceived" => uses.1.push(script),
+                        _ => uses.0.push(script),
+                    }
+                }
+            }
+            uses
+        }
+        let change = |project, id, name: &str, old_name: Option<&str>, kind| {
+            let (senders, receivers) = uses(project, id);
+            BroadcastChange {
+                name: name.to_string(),
+                old_name: old_name.map(str::to_string),
+                kind,
+                senders,
+                receivers,
+            }
+        };
 
-            let binding = group_items(split_);
-            let act: Vec<(&String, &Vec<String>)> = binding
-                .iter()
-                .map(|(sprite, changes)| (sprite, changes))
-                .collect();
-            commits.insert(sprite, format!("{} {}", act[0].0, act[0].1.join(", ")));
+        let (old, new) = (&self.data, &new.data);
+        let (old_broadcasts, new_broadcasts) = (broadcasts(old), broadcasts(new));
+        let mut changes = vec![];
+        for (&id, &name) in &new_broadcasts {
+            match old_broadcasts.get(id) {
+                None => changes.push(change(new, id, name, None, BroadcastChangeType::Added)),
+                Some(&old_name) if old_name != name => changes.push(change(
+                    new,
+                    id,
+                    name,
+                    Some(old_name),
+                    BroadcastChangeType::Renamed,
+                )),
+                Some(_) => {}
+            }
+        }
+        for (&id, &name) in &old_broadcasts {
+            if !new_broadcasts.contains_key(id) {
+                changes.push(change(old, id, name, None, BroadcastChangeType::Removed));
+            }
         }
-        commits.into_iter().map(|(x, y)| (x, y)).collect()
+        changes
     }
 
-    /// Return all script changes given a newer project
-    pub fn blocks<'a>(&'a self, cwd: &PathBuf, new: &'a Diff) -> Result<Vec<ScriptChanges>> {
-        fn _count_blocks(blocks: &Map<String, Value>) -> i32 {
-            blocks
+    /// Return the variables added, removed or renamed in each sprite of a newer project
+    ///
+    /// Values aren't compared, since they change whenever the project runs, and cloud
+    /// variables' values belong to the cloud server rather than the project
+    pub fn variables(&self, new: &Self) -> Vec<VariableChange> {
+        fn targets(project: &Project) -> HashMap<String, &Target> {
+            project
+                .targets
                 .iter()
-                .filter(|block| {
-                    block.1["opcode"]
-                        .as_str()
-                        .is_some_and(|op| !op.ends_with("_menu"))
-                })
-                .collect::<Vec<_>>()
-                .len() as i32
+                .map(|target| (target.display_name(), target))
+                .collect()
         }
+        let (old_targets, new_targets) = (targets(&self.data), targets(&new.data));
+        let mut changes = vec![];
+        let change = |sprite: &String, variable: Variable, old_name, kind| VariableChange {
+            sprite: sprite.clone(),
+            name: variable.name.to_string(),
+            old_name,
+            cloud: variable.cloud,
+            kind,
+        };
 
-        let sprites = self.data["targets"]
-            .as_array()
-            .unwrap()
-            .iter()
-            .zip_longest(new.data["targets"].as_array().unwrap())
-            .map(|x| match x {
-                Both(a, b) => (a, b),
-                Left(a) => (a, &Value::Null),
-                Right(b) => (&Value::Null, b),
-            });
+        for (sprite, target) in new_targets.iter().sorted_by_key(|(sprite, _)| *sprite) {
+            let old: HashMap<_, _> = old_targets
+                .get(sprite)
+                .map(|old| old.variables().map(|v| (v.id, v)).collect())
+                .unwrap_or_default();
+            for variable in target.variables() {
+                match old.get(variable.id) {
+                    None => changes.push(change(sprite, variable, None, VariableChangeType::Added)),
+                    Some(before) if before.name != variable.name => changes.push(change(
+                        sprite,
+                        variable,
+                        Some(before.name.to_string()),
+                        VariableChangeType::Renamed,
+                    )),
+                    Some(_) => {}
+                }
+            }
+        }
+        for (sprite, target) in old_targets.iter().sorted_by_key(|(sprite, _)| *sprite) {
+            let new_ids = new_targets
+                .get(sprite)
+                .map(|new| new.variables().map(|v| v.id).collect::<HashSet<_>>())
+                .unwrap_or_default();
+            for variable in target.variables().filter(|v| !new_ids.contains(v.id)) {
+                changes.push(change(sprite, variable, None, VariableChangeType::Removed));
+            }
+        }
+        changes
+    }
 
-        let mut error = None;
+    /// Return the costumes whose rotation center or resolution changed in a newer project, while
+    /// their files stayed the same
+    ///
+    /// Costumes are matched by name within each sprite, and changes to their files are left to
+    /// [`Diff::assets`]
+    pub fn costumes(&self, new: &Self) -> Vec<CostumeChange> {
+        let property =
+            |costume: &Costume, key: &str| costume.extra.get(key).cloned().unwrap_or(Value::Null);
+        let center = |costume: &Costume| {
+            json!([
+                property(costume, "rotationCenterX"),
+                property(costume, "rotationCenterY")
+            ])
+        };
+        // vector costumes don't always say, and are drawn at 1
+        let resolution = |costume: &Costume| match property(costume, "bitmapResolution") {
+            Value::Null => json!(1),
+            resolution => resolution,
+        };
 
-        let changes = sprites
-            .filter_map(|(&ref old, &ref new)| {
-                if old["blocks"].as_object() == new["blocks"].as_object() {
-                    return None;
-                }
-                if old.is_null() {
-                    return Some(ScriptChanges {
-                        sprite: new["name"].as_str().unwrap().to_string(),
-                        added: _count_blocks(&new["blocks"].as_object().unwrap()) as usize,
-                        removed: 0,
-                        on_stage: new["isStage"].as_bool().unwrap(),
-                    });
+        let mut changes = vec![];
+        for target in &new.data.targets {
+            let Some(old) = self.data.target(&target.name) else {
+                continue;
+            };
+            for costume in &target.costumes {
+                let Some(before) = old.costumes.iter().find(|c| c.name == costume.name) else {
+                    continue;
+                };
+                if before.path() != costume.path() {
+                    continue;
                 }
-                if new.is_null() {
-                    return Some(ScriptChanges {
-                        sprite: old["name"].as_str().unwrap().to_string(),
-                        added: 0,
-                        removed: _count_blocks(old["blocks"].as_object().unwrap()) as usize,
-                        on_stage: old["isStage"].as_bool().unwrap(),
-                    });
+                let properties = [
+                    (
+                        CostumeProperty::RotationCenter,
+                        center(before),
+                        center(costume),
+                    ),
+                    (
+                        CostumeProperty::BitmapResolution,
+                        resolution(before),
+                        resolution(costume),
+                    ),
+                ];
+                for (property, before, after) in properties {
+                    if before != after {
+                        changes.push(CostumeChange {
+                            sprite: target.display_name(),
+                            costume: costume.name.clone(),
+                            on_stage: target.is_stage,
+                            property,
+                            before,
+                            after,
+                        });
+                    }
                 }
+            }
+        }
+        changes
+    }
 
-                let old_blocks = old["blocks"].as_object().unwrap();
-                let old_top_ids = old_blocks
-                    .iter()
-                    .filter_map(|(k, v)| {
-                        if v["topLevel"].as_bool().is_some_and(|b| b) {
-                            Some(k.to_owned())
-                        } else {
-                            None
-                        }
-                    })
-                    .collect();
-                let old_content = parse_sprite(Sprite {
-                    blocks: old_blocks,
-                    top_ids: old_top_ids,
-                }).unwrap();
-
-                let new_blocks = new["blocks"].as_object().unwrap();
-                let new_top_ids = new_blocks
-                    .iter()
-                    .filter_map(|(k, v)| {
-                        if v["topLevel"].as_bool().is_some_and(|b| b) {
-                            Some(k.to_owned())
-                        } else {
-                            None
-                        }
-                    })
-                    .collect();
-                let new_content = parse_sprite(Sprite {
-                    blocks: new_blocks,
-                    top_ids: new_top_ids,
-                }).unwrap();
+    /// Return the custom block inputs renamed in each sprite of a newer project
+    ///
+    /// Custom blocks are matched by proccode within each sprite
+    pub fn argument_renames(&self, new: &Self) -> Vec<ArgumentRename> {
+        let mut changes = vec![];
+        for target in &new.data.targets {
+            let Some(old) = self
+                .data
+                .targets
+                .iter()
+                .find(|old| old.name == target.name && old.is_stage == target.is_stage)
+            else {
+                continue;
+            };
+            for (proccode, renames) in renamed_arguments(old, target) {
+                changes.extend(renames.into_iter().map(|(old_name, name)| ArgumentRename {
+                    sprite: target.display_name(),
+                    proccode: proccode.clone(),
+                    old_name,
+                    name,
+                }));
+            }
+        }
+        changes
+    }
 
-                let diff = git::diff(cwd, old_content, new_content, 2000);
+    /// Return the variables renamed in a newer project, from their IDs to their new names
+    pub fn variable_renames(&self, new: &Self) -> HashMap<String, String> {
+        let old = self
+            .data
+            .targets
+            .iter()
+            .flat_map(|target| target.variables())
+            .map(|variable| (variable.id, variable.name))
+            .collect::<HashMap<_, _>>();
+        new.data
+            .targets
+            .iter()
+            .flat_map(|target| target.variables())
+            .filter(|variable| {
+                old.get(variable.id)
+                    .is_some_and(|&name| name != variable.name)
+            })
+            .map(|variable| (variable.id.to_string(), variable.name.to_string()))
+            .collect()
+    }
 
-                if diff.is_err() {
-                    error = Some(diff.unwrap_err());
-                    return None;
+    /// Return each script added, removed or changed in sprites both projects have
+    ///
+    /// Scripts are matched by their top block's ID, so moving a script counts as changing it.
+    /// Renaming a variable or a custom block's argument doesn't count as changing the scripts
+    /// that use it
+    pub fn changed_scripts(&self, new: &Self) -> Vec<ChangedScript> {
+        let renames = self.variable_renames(new);
+        let mut changes = vec![];
+        for old in &self.data.targets {
+            let Some(new) = new
+                .data
+                .targets
+                .iter()
+                .find(|target| target.name == old.name && target.is_stage == old.is_stage)
+            else {
+                continue;
+            };
+            let renamed = rename_arguments(rename_variables(old, &renames), new);
+            let (old_scripts, new_scripts) = (renamed.scripts(), new.scripts());
+            for id in old_scripts.keys().merge(new_scripts.keys()).dedup() {
+                let kind = match (old_scripts.get(id), new_scripts.get(id)) {
+                    (Some(before), Some(after)) if before == after => continue,
+                    (Some(_), Some(_)) => ScriptChangeType::Modified,
+                    (None, _) => ScriptChangeType::Added,
+                    (_, None) => ScriptChangeType::Removed,
+                };
+                let opcode = match new.blocks.get(*id).or(old.blocks.get(*id)) {
+                    Some(BlockEntry::Block(block)) => block.opcode.clone(),
+                    _ => String::new(),
                 };
+                changes.push(ChangedScript {
+                    sprite: old.display_name(),
+                    on_stage: old.is_stage,
+                    script_id: id.to_string(),
+                    opcode,
+                    kind,
+                });
+            }
+        }
+        changes
+    }
 
-                let diff = diff.unwrap();
-
-                if diff.added != 0 || diff.removed != 0 {
-                    let name = [
-                        old["name"].as_str().unwrap(),
-                        if old["isStage"].as_bool().unwrap() {
-                            " (stage)"
-                        } else {
-                            ""
-                        },
-                    ];
-                    Some(ScriptChanges {
-                        sprite: name.join(""),
-                        added: diff.added as usize,
-                        removed: diff.removed.abs() as usize,
-                        on_stage: new["isStage"].as_bool().unwrap(),
-                    })
-                } else {
-                    None
+    /// Return the blocks added, removed or changed in each sprite a newer project has, leaving out
+    /// sprites with no changed blocks
+    ///
+    /// Every block in a new sprite counts as added. Blocks are compared after undoing renamed
+    /// variables and custom block arguments, like scripts are, but always reported by their own
+    /// IDs rather than ones from the renamed copy
+    pub fn changed_blocks(&self, new: &Self) -> Vec<BlockChanges> {
+        let renames = self.variable_renames(new);
+        let mut changes = vec![];
+        for target in &new.data.targets {
+            let old = self
+                .data
+                .targets
+                .iter()
+                .find(|old| old.name == target.name && old.is_stage == target.is_stage);
+            let mut change = BlockChanges {
+                sprite: target.display_name(),
+                on_stage: target.is_stage,
+                added: vec![],
+                removed: vec![],
+                modified: vec![],
+            };
+            match old {
+                Some(old) => {
+                    let renamed = rename_arguments(rename_variables(old, &renames), target);
+                    for id in renamed.blocks.keys().merge(target.blocks.keys()).dedup() {
+                        match (renamed.blocks.get(id), target.blocks.get(id)) {
+                            (Some(before), Some(after)) if before == after => {}
+                            (Some(_), Some(_)) => change.modified.push(id.clone()),
+                            (None, _) => change.added.push(id.clone()),
+                            (_, None) => change.removed.push(id.clone()),
+                        }
+                    }
                 }
+                None => change.added = target.blocks.keys().cloned().collect(),
+            }
+            let changed = [&change.added, &change.removed, &change.modified];
+            if changed.iter().any(|ids| !ids.is_empty()) {
+                changes.push(change);
+            }
+        }
+        changes
+    }
+
+    /// Return all script changes given a newer project
+    pub fn blocks<'a>(&'a self, cwd: &PathBuf, new: &'a Diff) -> Result<Vec<ScriptChanges>> {
+        self.blocks_with_progress(cwd, new, &mut Quiet)
+    }
+
+    /// Return all script changes given a newer project, reporting each sprite diffed and `diff`
+    /// progress out of the total
+    ///
+    /// Sprites are parsed and diffed on a thread for each core, but come back in project order
+    #[instrument(skip_all)]
+    pub fn blocks_with_progress<'a>(
+        &'a self,
+        cwd: &PathBuf,
+        new: &'a Diff,
+        observer: &mut dyn Observer,
+    ) -> Result<Vec<ScriptChanges>> {
+        let sprites = self
+            .data
+            .targets
+            .iter()
+            .zip_longest(&new.data.targets)
+            .map(|x| match x {
+                Both(a, b) => (Some(a), Some(b)),
+                Left(a) => (Some(a), None),
+                Right(b) => (None, Some(b)),
             })
             .collect::<Vec<_>>();
+        let total = sprites.len();
+        let mut workers = thread::available_parallelism()
+            .map_or(1, |cores| cores.get())
+            .clamp(1, total.max(1));
 
-        if let Some(error) = error {
-            return Err(error);
+        // with a memory budget, only as many sprites are diffed at once as fit in half of it
+        let settings = self.settings(cwd);
+        let budget = settings.memory.budget();
+        let context = settings.diff.context;
+        let renames = self.variable_renames(new);
+        if let Some(budget) = budget {
+            let largest = sprites
+                .iter()
+                .flat_map(|&(old, new)| old.into_iter().chain(new))
+                .map(|target| target.blocks.len())
+                .max()
+                .unwrap_or(0);
+            let batch = budget / 2 / (largest * BLOCK_BYTES).max(1);
+            workers = workers.min(batch.max(1));
         }
 
-        Ok(changes)
+        let next = AtomicUsize::new(0);
+        let (tx, rx) = channel();
+        let changes = thread::scope(|scope| {
+            for _ in 0..workers {
+                let (tx, next, sprites, renames) = (tx.clone(), &next, &sprites, &renames);
+                scope.spawn(move || loop {
+                    let i = next.fetch_add(1, Ordering::Relaxed);
+                    let Some(&(old, new)) = sprites.get(i) else {
+                        break;
+                    };
+                    let changes = match self.cancel.check() {
+                        Ok(()) => self.sprite_blocks(cwd, old, new, renames, budget, context),
+                        Err(cancelled) => Err(cancelled.into()),
+                    };
+                    let failed = changes.is_err();
+                    // the receiver is dropped once any sprite fails, which stops the rest
+                    if tx.send((i, changes)).is_err() || failed {
+                        break;
+                    }
+                });
+            }
+            drop(tx);
+
+            let mut changes = (0..total).map(|_| None).collect::<Vec<_>>();
+            let _ = observer.on_progress("diff", 0, total);
+            for (done, (i, sprite_changes)) in rx.into_iter().enumerate() {
+                changes[i] = sprite_changes?;
+                let (old, new) = sprites[i];
+                if let Some(sprite) = new.or(old) {
+                    observer.on_sprite_parsed(&sprite.name);
+                }
+                let _ = observer.on_progress("diff", done + 1, total);
+            }
+            Ok::<_, anyhow::Error>(changes)
+        })?;
+
+        Ok(changes.into_iter().flatten().collect())
+    }
+
+    /// Return the script changes to one sprite, given its old and new versions
+    fn sprite_blocks(
+        &self,
+        cwd: &PathBuf,
+        old: Option<&Target>,
+        new: Option<&Target>,
+        renames: &HashMap<String, String>,
+        budget: Option<usize>,
+        context: i32,
+    ) -> Result<Option<ScriptChanges>> {
+        let (old, new) = match (old, new) {
+            (Some(old), Some(new)) => (old, new),
+            (None, Some(new)) => {
+                return Ok(Some(ScriptChanges {
+                    sprite: new.name.clone(),
+                    added: count_blocks(new),
+                    removed: 0,
+                    on_stage: new.is_stage,
+                }))
+            }
+            (Some(old), None) => {
+                return Ok(Some(ScriptChanges {
+                    sprite: old.name.clone(),
+                    added: 0,
+                    removed: count_blocks(old),
+                    on_stage: old.is_stage,
+                }))
+            }
+            (None, None) => return Ok(None),
+        };
+
+        let renamed = rename_arguments(rename_variables(old, renames), new);
+        let old = renamed.as_ref();
+
+        // sprites with the same blocks hash the same, so they're skipped without parsing
+        let (old_hash, new_hash) = (blocks_hash(old)?, blocks_hash(new)?);
+        if old_hash == new_hash {
+            return Ok(None);
+        }
+        let old_content = parse_cached(old, old_hash, budget)?;
+        let new_content = parse_cached(new, new_hash, budget)?;
+        let diff = git::diff(cwd, old_content, new_content, context)?;
+
+        if diff.added == 0 && diff.removed == 0 {
+            return Ok(None);
+        }
+        Ok(Some(ScriptChanges {
+            sprite: old.display_name(),
+            added: diff.added as usize,
+            removed: diff.removed.abs() as usize,
+            on_stage: new.is_stage,
+        }))
     }
 
     /// Create commits for changes from the current project to a newer one
-    pub fn commits(&self, cwd: &PathBuf, new: &Diff) -> Result<Vec<String>> {
-        let costume_changes = self._merged_costumes(&new);
-        let blocks: Vec<_> = self
-            .blocks(cwd, &new)?
+    pub fn commits(&self, cwd: &PathBuf, new: &Diff) -> Result<Vec<CommitLine>> {
+        self.commits_with_progress(cwd, new, &mut Quiet)
+    }
+
+    /// Create commits for changes from the current project to a newer one, reporting each sprite
+    /// diffed and `diff` progress out of the total
+    #[instrument(skip_all)]
+    pub fn commits_with_progress(
+        &self,
+        cwd: &PathBuf,
+        new: &Diff,
+        observer: &mut dyn Observer,
+    ) -> Result<Vec<CommitLine>> {
+        let settings = self.settings(cwd);
+        let commit = &settings.commit;
+
+        let mut costume_changes = self.asset_changes(cwd, new)?;
+        costume_changes.retain(|change| {
+            !settings.is_ignored_asset(&change.sprite, &change.name, asset_kind(&change.path))
+        });
+        // the rest of what's in the ignore file is left out below, alongside ignored sprites
+        let blocks: Vec<(String, String, ChangeKind)> = match settings.is_ignored_kind("scripts") {
+            true => vec![],
+            false => self
+                .blocks_with_progress(cwd, &new, observer)?
+                .iter()
+                .map(|s| {
+                    let change = commit.emoji(&["scripts"], commit.blocks(s));
+                    let kind = match s.added > 0 {
+                        true => ChangeKind::Addition,
+                        false => ChangeKind::Script,
+                    };
+                    (s.sprite.clone(), change, kind)
+                })
+                .chain(self.argument_renames(new).iter().map(|rename| {
+                    let change = commit.emoji(&["scripts"], rename.format());
+                    (rename.sprite.clone(), change, ChangeKind::Script)
+                }))
+                .collect(),
+        };
+
+        let of_kind = |changes: Vec<(String, String)>, kind: ChangeKind| {
+            changes
+                .into_iter()
+                .map(|(sprite, change)| (sprite, change, kind))
+                .collect::<Vec<_>>()
+        };
+        let added = of_kind(
+            self.format_assets(costume_changes.added, "add", commit),
+            ChangeKind::Addition,
+        );
+        let removed = of_kind(
+            self.format_assets(costume_changes.removed, "remove", commit),
+            ChangeKind::Other,
+        );
+        let merged = of_kind(
+            self.format_assets(costume_changes.merged, "modify", commit),
+            ChangeKind::Other,
+        );
+        let converted = costume_changes
+            .converted
+            .iter()
+            .map(|c| {
+                let change = format!(
+                    "{} {} ({} → {})",
+                    commit.action("convert"),
+                    c.change.name,
+                    c.from_ext,
+                    c.change.ext
+                );
+                let kind = asset_kind(&c.change.path);
+                let sprite = label::key(&c.change.sprite, c.change.on_stage);
+                (sprite, commit.emoji(&[kind], change), ChangeKind::Other)
+            })
+            .collect::<Vec<_>>();
+        let moved = costume_changes
+            .moved
             .iter()
-            .map(|s| {
-                s.format()
-                    .split(": ")
-                    .map(|x| x.to_string())
-                    .collect::<Vec<_>>()
+            .map(|m| {
+                let change = format!(
+                    "{} {}.{} {} {}",
+                    commit.action("move"),
+                    m.change.name,
+                    m.change.ext,
+                    commit.word("from"),
+                    m.from_sprite
+                );
+                let kind = asset_kind(&m.change.path);
+                (
+                    m.change.sprite.clone(),
+                    commit.emoji(&[kind], change),
+                    ChangeKind::Other,
+                )
+            })
+            .collect::<Vec<_>>();
+
+        let costumes = self
+            .costumes(new)
+            .iter()
+            .filter(|change| !settings.ignore.asset(&change.costume))
+            .filter(|_| !settings.is_ignored_kind("costumes"))
+            .map(|change| {
+                let sprite = change.sprite.clone();
+                let change = commit.emoji(&["costumes"], change.format());
+                (sprite, change, ChangeKind::Other)
             })
-            .map(|v| (v[0].clone(), v[1].clone()))
-            .collect::<Vec<(String, String)>>();
+            .collect::<Vec<_>>();
 
-        let added = self.format_assets(costume_changes.added, "add");
-        let removed = self.format_assets(costume_changes.removed, "remove");
-        let merged = self.format_assets(costume_changes.merged, "modify");
+        let shared = [
+            (costume_changes.shared_added, "add", ChangeKind::Addition),
+            (costume_changes.shared_removed, "remove", ChangeKind::Other),
+        ]
+        .into_iter()
+        .flat_map(|(shares, action, change_kind)| {
+            let action = commit.action(action);
+            shares.into_iter().map(move |s| {
+                let change = format!("{action} {}.{}", s.change.name, s.change.ext);
+                let kind = asset_kind(&s.change.path);
+                (s.sprites.join(", "), commit.emoji(&[kind], change), change_kind)
+            })
+        })
+        .collect::<Vec<_>>();
 
-        let _commits = [blocks, added, removed, merged].concat();
+        let extensions = self
+            .extensions(new)
+            .iter()
+            .filter(|_| !settings.is_ignored_kind("extensions"))
+            .map(|change| {
+                let kind = match change.kind {
+                    ExtensionChangeType::Added => ChangeKind::Addition,
+                    _ => ChangeKind::Other,
+                };
+                let change = commit.emoji(&["extensions"], change.format());
+                ("extensions".to_string(), change, kind)
+            })
+            .collect::<Vec<_>>();
+
+        // broadcasts are kept on the stage, so they're listed with its variables
+        let stage = new.data.targets.iter().find(|target| target.is_stage);
+        let broadcasts = self.broadcasts(new).into_iter().filter_map(|change| {
+            if settings.is_ignored_kind("broadcasts") {
+                return None;
+            }
+            let stage = stage?.display_name();
+            let kind = match change.kind {
+                BroadcastChangeType::Added => ChangeKind::Addition,
+                _ => ChangeKind::Other,
+            };
+            Some((stage, commit.emoji(&["variables"], change.format()), kind))
+        });
+        let variables = self
+            .variables(new)
+            .iter()
+            .filter(|change| !settings.is_ignored_variable(&change.sprite, &change.name))
+            .map(|change| {
+                let sprite = change.sprite.clone();
+                let kind = match change.kind {
+                    VariableChangeType::Added => ChangeKind::Addition,
+                    _ => ChangeKind::Other,
+                };
+                (sprite, commit.emoji(&["variables"], change.format()), kind)
+            })
+            .chain(broadcasts)
+            .collect::<Vec<_>>();
 
-        let commits =
-            Vec::from_iter(group_items(_commits).iter().map(|(sprite, changes)| {
-                format!("{}: {}", sprite, changes.join(", ").to_string())
-            }));
+        // ignored sprites are left out here, since templates may not start lines with the sprite
+        let categories = [
+            ("scripts", blocks),
+            (
+                "assets",
+                [added, removed, merged, converted, moved, shared, costumes].concat(),
+            ),
+            ("extensions", extensions),
+            ("variables", variables),
+        ]
+        .map(|(category, changes)| {
+            // names go into changes as they are, so they're cleaned before anything's laid out
+            let changes = changes
+                .into_iter()
+                .filter(|(sprite, _, _)| !settings.is_ignored(sprite))
+                .map(|(sprite, change, kind)| (sprite, label::clean(&change), kind))
+                .collect::<Vec<_>>();
+            (category, changes)
+        });
+        let line = |scope: &str, changes: Vec<String>, kind: ChangeKind| CommitLine {
+            scope: Some(scope.to_string()),
+            text: commit.line(scope, &changes),
+            changes,
+            kind,
+        };
+        let without_kinds = |changes: Vec<(String, String, ChangeKind)>| {
+            changes
+                .into_iter()
+                .map(|(sprite, change, _)| (sprite, change))
+                .collect::<Vec<_>>()
+        };
+
+        let commits = match commit.grouping {
+            CommitGrouping::Sprite | CommitGrouping::Squash => {
+                let changes = categories
+                    .into_iter()
+                    .flat_map(|(_, changes)| changes)
+                    .collect::<Vec<_>>();
+                let mut kinds = HashMap::<String, Vec<ChangeKind>>::new();
+                for (sprite, _, kind) in &changes {
+                    kinds.entry(sprite.clone()).or_default().push(*kind);
+                }
+                let lines = group_items(without_kinds(changes))
+                    .into_iter()
+                    .map(|(sprite, changes)| {
+                        let kind =
+                            ChangeKind::most_significant(kinds.remove(&sprite).unwrap_or_default());
+                        line(&sprite, changes, kind)
+                    })
+                    .collect::<Vec<_>>();
+                match commit.grouping {
+                    CommitGrouping::Squash if !lines.is_empty() => {
+                        let kind = ChangeKind::most_significant(lines.iter().map(|line| line.kind));
+                        let changes = lines.into_iter().map(|line| line.text).collect::<Vec<_>>();
+                        vec![CommitLine {
+                            scope: None,
+                            text: changes.join("; "),
+                            changes,
+                            kind,
+                        }]
+                    }
+                    _ => lines,
+                }
+            }
+            CommitGrouping::Category => categories
+                .into_iter()
+                .filter(|(_, changes)| !changes.is_empty())
+                .map(|(category, changes)| {
+                    let kind =
+                        ChangeKind::most_significant(changes.iter().map(|(_, _, kind)| *kind));
+                    let sprites = group_items(without_kinds(changes))
+                        .iter()
+                        .map(|(sprite, changes)| match sprite == category {
+                            // extension changes aren't in any sprite
+                            true => changes.join(", "),
+                            false => format!("{} ({})", commit.label(sprite), changes.join(", ")),
+                        })
+                        .collect::<Vec<_>>();
+                    line(category, sprites, kind)
+                })
+                .collect(),
+        };
 
         Ok(commits)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A costume or sound in a sprite, named by its MD5 like Scratch does
+    fn asset(sprite: &str, on_stage: bool, name: &str, path: &str) -> AssetChange {
+        let (_, ext) = path.split_once('.').unwrap();
+        AssetChange {
+            sprite: sprite.to_string(),
+            name: name.to_string(),
+            ext: ext.to_string(),
+            path: path.to_string(),
+            on_stage,
+            kind: None,
+        }
+    }
+
+    /// A diff of a project with a stage and one sprite, which has these costumes
+    fn diff(sprite: &str, costumes: &[(&str, &str)]) -> Diff {
+        let costumes = costumes
+            .iter()
+            .map(|(name, md5)| json!({"name": name, "assetId": md5, "dataFormat": "svg"}))
+            .collect::<Vec<_>>();
+        let project = json!({
+            "targets": [
+                {"isStage": true, "name": "Stage"},
+                {"isStage": false, "name": sprite, "costumes": costumes},
+            ]
+        });
+        let mut diff = Diff::new(serde_json::from_value(project).unwrap());
+        diff.settings = Some(Settings::default());
+        diff
+    }
+
+    const CAT: &str = "b7853f557e4426412e64bb3da6531a99";
+    const DOG: &str = "bcf454acf82e4504149f7ffe07081dbc";
+
+    #[test]
+    fn format_assets_groups_by_sprite() {
+        let commit = CommitSettings::default();
+        let changes = vec![
+            asset("Cat: 1", false, "meow", &format!("{CAT}.wav")),
+            asset("Cat: 1", false, "walk\n1", &format!("{DOG}.svg")),
+            asset("Stage", true, "backdrop", &format!("{DOG}.svg")),
+            asset("Stage", false, "🐱", &format!("{CAT}.svg")),
+        ];
+        let formatted = Diff::new(Project::default()).format_assets(changes, "add", &commit);
+        assert_eq!(
+            formatted,
+            [
+                ("Cat: 1", "add meow.wav, walk 1.svg"),
+                ("Stage", "add 🐱.svg"),
+                ("Stage (stage)", "add backdrop.svg"),
+            ]
+            .map(|(sprite, change)| (sprite.to_string(), change.to_string()))
+        );
+    }
+
+    #[test]
+    fn format_assets_emoji_come_from_files() {
+        let mut commit = CommitSettings::default();
+        commit.emoji.enabled = true;
+        // a costume named like a sound is still a costume
+        let changes = vec![
+            asset("Cat", false, "meow.wav", &format!("{CAT}.svg")),
+            asset("Cat", false, "meow", &format!("{DOG}.mp3")),
+        ];
+        let formatted = Diff::new(Project::default()).format_assets(changes, "add", &commit);
+        assert_eq!(formatted[0].1, "🎨🔊 add meow.wav.svg, meow.mp3");
+    }
+
+    #[test]
+    fn commit_lines_keep_names_apart_from_changes() {
+        let cwd = PathBuf::from(".");
+        for sprite in ["Cat: 1", "Cat\n(1)", "\u{202e}Cat", "🐱"] {
+            let old = diff(sprite, &[]);
+            let new = diff(sprite, &[("walk: 1", CAT)]);
+            let lines = old.commits(&cwd, &new).unwrap();
+
+            assert_eq!(lines.len(), 1);
+            let [line] = &lines[..] else { unreachable!() };
+            assert_eq!(line.scope.as_deref(), Some(sprite));
+            assert_eq!(line.changes, ["add walk: 1.svg"]);
+            assert_eq!(line.kind, ChangeKind::Addition);
+            assert_eq!(
+                line.text,
+                format!("{}: add walk: 1.svg", label::clean(sprite))
+            );
+            assert!(!line.text.contains(['\n', '\u{202e}']));
+        }
+    }
+
+    #[test]
+    fn commit_lines_clean_changes() {
+        let cwd = PathBuf::from(".");
+        let old = diff("Cat", &[("walk", CAT)]);
+        let new = diff("Cat", &[("walk", CAT), ("run\n\u{2066}fast", DOG)]);
+        let lines = old.commits(&cwd, &new).unwrap();
+        assert_eq!(lines[0].changes, ["add run fast.svg"]);
+        assert_eq!(lines[0].text, "Cat: add run fast.svg");
+    }
+
+    #[test]
+    fn spilled_scripts_come_back_and_are_deleted() {
+        let mut cache = ScriptCache {
+            parsed: LruCache::new(256),
+            bytes: 0,
+            spill: None,
+            spilled: LruCache::new(1),
+        };
+        let (a, b, c) = ([1; 16], [2; 16], [3; 16]);
+        cache.insert(a, "when flag clicked".to_string(), Some(25));
+        cache.insert(b, "say [hi]".to_string(), Some(25));
+        assert!(cache.spill.is_none());
+
+        // only the latest script fits, so the others are spilled, and only one is kept spilled
+        cache.insert(c, "move (10) steps".to_string(), Some(16));
+        let spill = cache.spill.as_ref().unwrap().path().clone();
+        assert!(!spill.join(hex(&a)).exists());
+        assert!(spill.join(hex(&b)).exists());
+
+        assert_eq!(cache.get(&b, Some(25)).as_deref(), Some("say [hi]"));
+        assert!(!spill.join(hex(&b)).exists());
+        assert_eq!(cache.get(&a, Some(25)), None);
+
+        cache.spill = None;
+        assert!(!spill.exists());
+    }
+
+    #[test]
+    fn assets_git_finds_renamed_are_moves() {
+        let workspace = TempWorkspace::new("diff-test").unwrap();
+        let cwd = workspace.path();
+        let git = |args: &[&str]| {
+            let identity = ["-c", "user.name=test", "-c", "user.email=test@example.com"];
+            let output = git::run([&identity[..], args].concat(), Some(cwd))
+                .output()
+                .unwrap();
+            assert!(output.status.success());
+            String::from_utf8(output.stdout).unwrap()
+        };
+        let svg = |fill: &str| {
+            let shapes = (0..40).map(|i| format!("<rect x=\"{i}\" width=\"{i}\"/>\n"));
+            format!(
+                "<svg fill=\"{fill}\">\n{}</svg>\n",
+                shapes.collect::<String>()
+            )
+        };
+        git(&["init", "--quiet"]);
+        fs::write(cwd.join(format!("{CAT}.svg")), svg("red")).unwrap();
+        git(&["add", "--all"]);
+        git(&["commit", "--quiet", "-m", "add cat"]);
+
+        // the costume is edited and moved to another sprite, so its name and path both change
+        fs::remove_file(cwd.join(format!("{CAT}.svg"))).unwrap();
+        fs::write(cwd.join(format!("{DOG}.svg")), svg("blue")).unwrap();
+        let project = |sprite: &str, costume: &str, md5: &str| {
+            let costumes = json!([{"name": costume, "assetId": md5, "dataFormat": "svg"}]);
+            let project = json!({
+                "targets": [
+                    {"isStage": true, "name": "Stage"},
+                    {"name": "Cat", "costumes": if sprite == "Cat" { costumes.clone() } else { json!([]) }},
+                    {"name": "Dog", "costumes": if sprite == "Dog" { costumes } else { json!([]) }},
+                ]
+            });
+            Diff::new(serde_json::from_value(project).unwrap())
+        };
+        let old = project("Cat", "walk", CAT);
+        let new = project("Dog", "run", DOG);
+
+        let changes = old.asset_changes(cwd, &new).unwrap();
+        assert!(changes.added.is_empty() && changes.removed.is_empty());
+        let [moved] = &changes.moved[..] else {
+            panic!("expected one move, got {changes:?}");
+        };
+        assert_eq!(moved.from_sprite, "Cat");
+        assert_eq!(
+            (moved.change.sprite.as_str(), moved.change.name.as_str()),
+            ("Dog", "run")
+        );
+
+        // the new file is only added to a copy of the index
+        let status = git(&["status", "--porcelain"]);
+        assert!(status.contains(&format!("?? {DOG}.svg")), "{status}");
+    }
+}
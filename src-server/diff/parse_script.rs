@@ -1,10 +1,20 @@
-use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+
+use crate::error::{Error, Result};
+use crate::limits::MAX_SCRIPT_DEPTH;
+use crate::sb3::{BlockEntry, Target};
 
 struct Script<'a> {
-    blocks: &'a Map<String, Value>,
+    /// Name of the sprite the script is in, for errors
+    sprite: &'a str,
+    blocks: &'a BTreeMap<String, BlockEntry>,
     start_id: &'a str,
     depth: i32,
     else_clause: bool,
+    /// How many blocks this script is inside of, counting conditions as well as C blocks
+    nesting: usize,
 }
 
 fn some(string: String) -> String {
@@ -15,88 +25,155 @@ fn some(string: String) -> String {
     }
 }
 
-fn parse_script(script: Script) -> Result<String, Box<dyn std::error::Error>> {
+/// Write part of a block as JSON, which can't fail for anything read from a project.json
+fn to_string(value: &impl Serialize) -> String {
+    serde_json::to_string(value).unwrap_or_default()
+}
+
+fn parse_script(script: Script) -> Result<String> {
+    if script.nesting > MAX_SCRIPT_DEPTH {
+        return Err(Error::TooLarge {
+            what: script.sprite.into(),
+            detail: format!("blocks are nested more than {MAX_SCRIPT_DEPTH} deep"),
+        });
+    }
     let mut current_id = Some(script.start_id);
     let mut output: String = String::new();
+    // a broken project can have blocks pointing back at each other in a loop
+    let mut remaining = script.blocks.len();
 
     while let Some(id) = current_id {
-        let block = &script.blocks[id];
+        if remaining == 0 {
+            return Err(Error::MalformedProject {
+                sprite: script.sprite.into(),
+                field: format!("blocks.{id}.next"),
+            });
+        }
+        remaining -= 1;
+        let block = match script.blocks.get(id) {
+            Some(BlockEntry::Block(block)) => block,
+            // blocks from mods that don't follow the usual structure are diffed as they are
+            Some(entry) => {
+                output += &format!(
+                    "{}{}\n",
+                    "\t".repeat((script.depth + 1) as usize),
+                    to_string(entry)
+                );
+                break;
+            }
+            None => {
+                return Err(Error::MalformedProject {
+                    sprite: script.sprite.into(),
+                    field: format!("blocks.{id}"),
+                })
+            }
+        };
         if script.else_clause {
             output += &format!("{}else\n", "\t".repeat(script.depth as usize));
         }
 
         let mut info = format!(
             "{} {} {}",
-            some(serde_json::to_string(&block["inputs"])?),
-            some(serde_json::to_string(&block["fields"])?),
-            some(serde_json::to_string(&block["mutation"])?),
+            some(to_string(&block.inputs)),
+            some(to_string(&block.fields)),
+            some(to_string(&block.mutation)),
         );
 
-        for key in (&script.blocks).keys() {
+        for key in script.blocks.keys() {
             info = info.replace(&format!("\"{key}\""), "\"id\"");
         }
 
+        if block.opcode.is_empty() {
+            return Err(Error::MalformedProject {
+                sprite: script.sprite.into(),
+                field: format!("blocks.{id}.opcode"),
+            });
+        }
         output += &format!(
             "{}{} {}\n",
             "\t".repeat((script.depth + 1) as usize),
-            block["opcode"].as_str().ok_or("no opcode")?,
+            block.opcode,
             info.trim()
         );
 
-        if let Some(condition) = block["inputs"]["CONDITION"].as_array() {
+        if let Some(condition) = block.input_block("CONDITION") {
             output = output.trim_end().into();
             output += &parse_script(Script {
-                blocks: &script.blocks,
-                start_id: condition[1].as_str().ok_or("no condition id")?,
+                sprite: script.sprite,
+                blocks: script.blocks,
+                start_id: condition,
                 depth: 0,
                 else_clause: false,
+                nesting: script.nesting + 1,
             })?;
         }
 
-        if let Some(substack) = block["inputs"]["SUBSTACK"].as_array() {
-            if let Some(id) = substack[1].as_str() {
-                output += &parse_script(Script {
-                    blocks: script.blocks,
-                    start_id: id,
-                    depth: script.depth + 1,
-                    else_clause: false,
-                })?;
-            }
+        if let Some(id) = block.input_block("SUBSTACK") {
+            output += &parse_script(Script {
+                sprite: script.sprite,
+                blocks: script.blocks,
+                start_id: id,
+                depth: script.depth + 1,
+                else_clause: false,
+                nesting: script.nesting + 1,
+            })?;
         }
 
-        if let Some(substack2) = block["inputs"]["SUBSTACK2"].as_array() {
-            if let Some(id) = substack2[1].as_str() {
-                output += &parse_script(Script {
-                    blocks: script.blocks,
-                    start_id: id,
-                    depth: script.depth + 1,
-                    else_clause: true,
-                })?;
-            }
+        if let Some(id) = block.input_block("SUBSTACK2") {
+            output += &parse_script(Script {
+                sprite: script.sprite,
+                blocks: script.blocks,
+                start_id: id,
+                depth: script.depth + 1,
+                else_clause: true,
+                nesting: script.nesting + 1,
+            })?;
         }
 
-        current_id = block["next"].as_str();
+        current_id = block.next.as_deref();
     }
 
     Ok(output)
 }
 
 pub struct Sprite<'a> {
-    pub blocks: &'a Map<String, Value>,
+    pub name: &'a str,
+    pub blocks: &'a BTreeMap<String, BlockEntry>,
     pub top_ids: Vec<String>,
 }
 
-pub fn parse_sprite(sprite: Sprite) -> Result<String, Box<dyn std::error::Error>> {
+impl<'a> Sprite<'a> {
+    /// Every script in a sprite or the stage
+    pub fn of(target: &'a Target) -> Self {
+        Sprite {
+            name: &target.name,
+            blocks: &target.blocks,
+            top_ids: target.top_ids(),
+        }
+    }
+}
+
+pub fn parse_sprite(sprite: Sprite) -> Result<String> {
     let mut output = vec![];
     for id in sprite.top_ids {
         output.push(parse_script(Script {
-            blocks: &sprite.blocks,
+            sprite: sprite.name,
+            blocks: sprite.blocks,
             start_id: &id,
             depth: -1,
             else_clause: false,
+            nesting: 0,
         })?);
     }
     output.sort_by_key(|script| script.to_lowercase());
 
     Ok(output.join("\n").trim_end().into())
 }
+
+/// Count the blocks in a sprite, leaving out menus since they're part of another block
+pub fn count_blocks(target: &Target) -> usize {
+    target
+        .blocks()
+        .filter(|(_, block)| !block.opcode.ends_with("_menu"))
+        .count()
+}
@@ -1,36 +1,294 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+use std::path::PathBuf;
+
+use itertools::Itertools;
 use serde::Serialize;
 use serde_json::Value;
 
-#[derive(Debug, Eq, Hash, PartialEq, Copy, Clone, Serialize)]
+pub use crate::diff::compare::{
+    AssetChange, AssetChangeType, AssetChanges, AssetConversion, AssetMove, AssetShare,
+    ExtensionChange, ExtensionChangeType,
+};
+
+use crate::asset_store::AssetStore;
+use crate::cancel::CancelToken;
+use crate::config::Settings;
+use crate::diff::label;
+use crate::diff::template::asset_kind;
+use crate::i18n::message;
+use crate::mapped::Contents;
+use crate::ops;
+use crate::sb3::Project;
+
+impl AssetChange {
+    /// Read the asset's contents, only once a preview of it is asked for
+    ///
+    /// Assets are named by their MD5, so any copy will do: the working tree's, the asset store's,
+    /// or the one in `revision`
+    pub fn contents(&self, pth: &PathBuf, revision: &str) -> Option<Contents> {
+        let store = AssetStore::default_store().ok()?;
+        ops::read_asset(pth, revision, &self.path, &store)
+    }
+}
+
+/// A costume property kept in project.json rather than in the costume's file
+#[derive(Debug, Eq, PartialEq, Copy, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum CostumeProperty {
+    /// `rotationCenterX` and `rotationCenterY`, as `[x, y]`
+    RotationCenter,
+    /// `bitmapResolution`, which is 2 for bitmaps drawn at double size
+    BitmapResolution,
+}
+
+/// Represents a costume whose rotation center or resolution changed without its file changing
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CostumeChange {
+    pub sprite: String,
+    pub costume: String,
+    pub on_stage: bool,
+    pub property: CostumeProperty,
+    pub before: Value,
+    pub after: Value,
+}
+
+impl CostumeChange {
+    /// Git commit representation of a costume property change
+    pub fn format(&self) -> String {
+        match self.property {
+            CostumeProperty::RotationCenter => {
+                let center = |value: &Value| match value.as_array().map(Vec::as_slice) {
+                    Some([x, y]) => format!("({x}, {y})"),
+                    _ => value.to_string(),
+                };
+                format!(
+                    "move {} rotation center to {}",
+                    self.costume,
+                    center(&self.after)
+                )
+            }
+            CostumeProperty::BitmapResolution => {
+                format!("set {} resolution to {}", self.costume, self.after)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone, Serialize)]
 #[serde(rename_all = "lowercase")]
-pub enum AssetChangeType {
-    Before,
-    After,
+pub enum BroadcastChangeType {
+    Added,
+    Removed,
+    Renamed,
 }
 
-/// Represents a changed costume for a sprite or the stage
-#[derive(Debug, Eq, Hash, PartialEq, Clone, Serialize)]
+/// Represents a script that sends or receives a broadcast
+#[derive(Debug, Clone, Serialize)]
 #[serde(rename_all = "camelCase")]
-pub struct AssetChange {
+pub struct BroadcastUse {
+    /// Where the stage ends with ` (stage)`
     pub sprite: String,
+    pub script_id: String,
+}
+
+/// Represents a broadcast that was added, removed or renamed, with every script that uses it
+///
+/// Uses are from the newer project, or the older one for a removed broadcast, so they show
+/// what the change affects.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BroadcastChange {
     pub name: String,
-    pub ext: String,
-    pub path: String,
-    pub on_stage: bool,
-    pub kind: Option<AssetChangeType>,
-    pub contents: Option<Box<[u8]>>,
+    /// The broadcast's name before it was renamed
+    pub old_name: Option<String>,
+    pub kind: BroadcastChangeType,
+    pub senders: Vec<BroadcastUse>,
+    pub receivers: Vec<BroadcastUse>,
 }
 
-/// Represents costumes that were added, removed, or changed
-#[derive(Debug)]
-pub struct AssetChanges {
-    pub added: Vec<AssetChange>,
-    pub removed: Vec<AssetChange>,
-    pub merged: Vec<AssetChange>,
+impl BroadcastChange {
+    /// Git commit representation of a broadcast change
+    pub fn format(&self) -> String {
+        match (self.kind, &self.old_name) {
+            (BroadcastChangeType::Added, _) => format!("add broadcast {}", self.name),
+            (BroadcastChangeType::Removed, _) => format!("remove broadcast {}", self.name),
+            (BroadcastChangeType::Renamed, Some(old_name)) => {
+                format!("rename broadcast {old_name} to {}", self.name)
+            }
+            (BroadcastChangeType::Renamed, None) => format!("rename broadcast {}", self.name),
+        }
+    }
+
+    /// Describe which sprites send and receive the broadcast, like
+    /// `sent by Player (2 scripts); received by Enemy, Stage`
+    pub fn describe_uses(&self) -> String {
+        let sprites = |uses: &[BroadcastUse]| {
+            uses.iter()
+                .counts_by(|script| label::name(&script.sprite).to_string())
+                .into_iter()
+                .sorted()
+                .map(|(sprite, scripts)| match scripts {
+                    1 => sprite,
+                    n => format!("{sprite} ({n} scripts)"),
+                })
+                .join(", ")
+        };
+        let sent = match self.senders.is_empty() {
+            true => "never sent".to_string(),
+            false => format!("sent by {}", sprites(&self.senders)),
+        };
+        let received = match self.receivers.is_empty() {
+            true => "never received".to_string(),
+            false => format!("received by {}", sprites(&self.receivers)),
+        };
+        format!("{sent}; {received}")
+    }
+}
+
+/// Represents blocks from an extension that isn't built into Scratch or a known mod, which
+/// can only be shown by their raw opcodes
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UnknownExtension {
+    /// The ID the blocks' opcodes start with
+    pub id: String,
+    /// Where the project loads it from, if it's a custom extension the project lists
+    pub url: Option<String>,
+    /// Number of its blocks in added or changed scripts
+    pub blocks: usize,
+    /// Sprites with those scripts, where the stage ends with ` (stage)`
+    pub sprites: Vec<String>,
+}
+
+impl UnknownExtension {
+    /// Describe the extension's blocks, like `uses 3 blocks from unknown extension lmsUtils`
+    pub fn format(&self) -> String {
+        let blocks = match self.blocks {
+            1 => "1 block".to_string(),
+            n => format!("{n} blocks"),
+        };
+        format!("uses {blocks} from unknown extension {}", self.id)
+    }
+}
+
+/// How a variable's definition changed
+#[derive(Debug, Eq, PartialEq, Copy, Clone, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum VariableChangeType {
+    Added,
+    Removed,
+    Renamed,
+}
+
+/// Represents a variable that was added, removed or renamed, without its value
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VariableChange {
+    pub sprite: String,
+    pub name: String,
+    /// The variable's name before it was renamed
+    pub old_name: Option<String>,
+    pub cloud: bool,
+    pub kind: VariableChangeType,
+}
+
+impl VariableChange {
+    /// Git commit representation of a variable change
+    pub fn format(&self) -> String {
+        let variable = if self.cloud {
+            "cloud variable"
+        } else {
+            "variable"
+        };
+        match (self.kind, &self.old_name) {
+            (VariableChangeType::Added, _) => format!("add {variable} {}", self.name),
+            (VariableChangeType::Removed, _) => format!("remove {variable} {}", self.name),
+            (VariableChangeType::Renamed, Some(old_name)) => {
+                format!("rename {variable} {old_name} to {}", self.name)
+            }
+            (VariableChangeType::Renamed, None) => format!("rename {variable} {}", self.name),
+        }
+    }
+}
+
+/// Represents an input of a custom block that was renamed, which renames it in every call too
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ArgumentRename {
+    pub sprite: String,
+    /// The custom block's proccode, like `jump %s`
+    pub proccode: String,
+    pub old_name: String,
+    pub name: String,
+}
+
+impl ArgumentRename {
+    /// The custom block's name, without its inputs
+    pub fn block(&self) -> String {
+        self.proccode
+            .split_whitespace()
+            .filter(|word| !word.starts_with('%'))
+            .join(" ")
+    }
+
+    /// Git commit representation of an argument rename
+    pub fn format(&self) -> String {
+        format!(
+            "rename input {} to {} in {}",
+            self.old_name,
+            self.name,
+            self.block()
+        )
+    }
+}
+
+/// Represents a line of a generated commit message, keeping what it's about apart from its text
+/// so nothing has to be parsed back out of it
+///
+/// Serialized as its text alone.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(into = "String")]
+pub struct CommitLine {
+    /// Key of the sprite the line is about, or its category with `commit.grouping = "category"`,
+    /// or `None` for lines about everything
+    pub scope: Option<String>,
+    /// Each change in the line, without the sprite
+    pub changes: Vec<String>,
+    pub text: String,
+    /// The most significant kind of change in the line
+    pub kind: ChangeKind,
+}
+
+/// What a change in a commit message does, most significant first, so it can be told without
+/// reading the message's text, which may be translated or templated
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ChangeKind {
+    /// Something new, like blocks, assets, variables or extensions
+    Addition,
+    /// Scripts changed without anything being added to them
+    Script,
+    /// Anything else, like removed or converted assets
+    Other,
+}
+
+impl ChangeKind {
+    /// The most significant of some kinds of change, or [`ChangeKind::Other`] for none
+    pub fn most_significant(kinds: impl IntoIterator<Item = ChangeKind>) -> ChangeKind {
+        kinds.into_iter().min().unwrap_or(ChangeKind::Other)
+    }
+}
+
+impl From<CommitLine> for String {
+    fn from(line: CommitLine) -> Self {
+        line.text
+    }
 }
 
 /// Represents a changed script for a sprite or stage, and how many blocks were added or removed
-#[derive(Debug)]
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
 pub struct ScriptChanges {
     pub sprite: String,
     pub added: usize,
@@ -41,7 +299,17 @@ pub struct ScriptChanges {
 impl ScriptChanges {
     /// Git commit representation of a script change
     pub fn format(&self) -> String {
-        let mut commit = format!("{}: ", self.sprite);
+        format!("{}: {}", self.sprite, self.describe())
+    }
+
+    /// How many blocks were added and removed, like `+1, -2 blocks`
+    pub fn describe(&self) -> String {
+        self.describe_as("blocks")
+    }
+
+    /// How many blocks were added and removed, with another word for blocks
+    pub fn describe_as(&self, blocks: &str) -> String {
+        let mut commit = String::new();
         if self.added > 0 {
             commit += &format!("+{}", self.added);
             if self.removed > 0 {
@@ -51,13 +319,230 @@ impl ScriptChanges {
         if self.removed > 0 {
             commit += &format!("-{}", self.removed)
         }
-        commit += " blocks";
-        commit
+        commit + " " + blocks
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ScriptChangeType {
+    Added,
+    Removed,
+    Modified,
+}
+
+/// Represents one script that changed in a sprite both versions have, so it can be committed
+/// on its own
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChangedScript {
+    pub sprite: String,
+    pub on_stage: bool,
+    /// ID of the script's top block, which stays the same across edits
+    pub script_id: String,
+    /// Opcode of the script's top block
+    pub opcode: String,
+    pub kind: ScriptChangeType,
+}
+
+/// Represents the blocks that changed in a sprite, by the IDs project.json and the editor give
+/// them, so the editor can highlight each one
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BlockChanges {
+    pub sprite: String,
+    pub on_stage: bool,
+    pub added: Vec<String>,
+    /// Blocks only the older version has, which the editor can't highlight but can list
+    pub removed: Vec<String>,
+    pub modified: Vec<String>,
+}
+
+/// Represents every change between two versions of a project
+#[derive(Debug, Serialize)]
+pub struct DiffReport {
+    pub scripts: Vec<ScriptChanges>,
+    /// Each changed script, for committing changes one at a time
+    pub changed_scripts: Vec<ChangedScript>,
+    pub assets: Vec<AssetChange>,
+    /// Assets in `assets` that were moved to another sprite rather than removed and added
+    pub moved_assets: Vec<AssetMove>,
+    /// Costumes whose rotation center or resolution changed, which doesn't change their files
+    pub costumes: Vec<CostumeChange>,
+    pub extensions: Vec<ExtensionChange>,
+    pub broadcasts: Vec<BroadcastChange>,
+    /// Extensions that changed scripts use blocks from which can't be described, also listed in
+    /// `warnings`
+    pub unknown_extensions: Vec<UnknownExtension>,
+    pub variables: Vec<VariableChange>,
+    /// Custom block inputs that were renamed, which aren't counted as changing the scripts that
+    /// use them
+    pub argument_renames: Vec<ArgumentRename>,
+    /// Things to check before committing, like changes to cloud variables
+    pub warnings: Vec<String>,
+    /// Generated commit message parts, one per sprite
+    pub commits: Vec<CommitLine>,
+    /// Asset files the project no longer uses, only found for the working directory
+    pub unused_assets: Vec<String>,
+}
+
+impl DiffReport {
+    /// Number of changes, counting each block, asset, variable and extension
+    pub fn size(&self) -> usize {
+        let blocks = self
+            .scripts
+            .iter()
+            .map(|script| script.added + script.removed)
+            .sum::<usize>();
+        blocks
+            + self.assets.len()
+            + self.costumes.len()
+            + self.variables.len()
+            + self.argument_renames.len()
+            + self.extensions.len()
+            + self.broadcasts.len()
+    }
+
+    /// Names of the sprites with changes, naming the stage without ` (stage)`
+    pub fn sprites(&self) -> BTreeSet<String> {
+        let scripts = self.scripts.iter().map(|script| &script.sprite);
+        let assets = self.assets.iter().map(|asset| &asset.sprite);
+        let costumes = self.costumes.iter().map(|costume| &costume.sprite);
+        let variables = self.variables.iter().map(|variable| &variable.sprite);
+        let arguments = self.argument_renames.iter().map(|rename| &rename.sprite);
+        scripts
+            .chain(arguments)
+            .chain(assets)
+            .chain(costumes)
+            .chain(variables)
+            .map(|sprite| label::name(sprite).to_string())
+            .collect()
+    }
+
+    /// Summarize the report in GitHub-flavored Markdown, like for a pull request description
+    pub fn to_markdown(&self) -> String {
+        self.to_markdown_with(&BTreeMap::new())
+    }
+
+    /// Summarize the report in Markdown, with the script diffs from
+    /// [`ops::script_diffs`](crate::ops::script_diffs) under each sprite's table
+    pub fn to_markdown_with(&self, script_diffs: &BTreeMap<String, String>) -> String {
+        // pipes would end a table cell early
+        let cell = |text: &str| text.replace('|', "\\|");
+        let summary = message(
+            "report-summary",
+            &[
+                ("changes", &self.size().to_string()),
+                ("sprites", &self.sprites().len().to_string()),
+            ],
+        );
+        let mut markdown = format!("**{summary}**\n");
+
+        for sprite in self.sprites() {
+            let is_sprite = |name: &str| label::name(name) == sprite;
+            let _ = write!(
+                markdown,
+                "\n### {sprite}\n\n| {} | {} |\n| --- | --- |\n",
+                message("report-change", &[]),
+                message("report-details", &[])
+            );
+            for script in self.scripts.iter().filter(|s| is_sprite(&s.sprite)) {
+                let kind = message("report-scripts", &[]);
+                let _ = writeln!(markdown, "| {kind} | {} |", script.describe());
+            }
+            for rename in self
+                .argument_renames
+                .iter()
+                .filter(|r| is_sprite(&r.sprite))
+            {
+                let kind = message("report-scripts", &[]);
+                let _ = writeln!(markdown, "| {kind} | {} |", cell(&rename.format()));
+            }
+            for asset in self.assets.iter().filter(|a| is_sprite(&a.sprite)) {
+                let action = match asset.kind {
+                    Some(AssetChangeType::Before) => "report-asset-removed",
+                    _ => "report-asset-added",
+                };
+                let name = cell(&format!("{}.{}", asset.name, asset.ext));
+                let kind = match asset_kind(&name) {
+                    "sounds" => message("report-sounds", &[]),
+                    _ => message("report-costumes", &[]),
+                };
+                let change = message(action, &[("name", &name)]);
+                let _ = writeln!(markdown, "| {kind} | {change} |");
+            }
+            for costume in self.costumes.iter().filter(|c| is_sprite(&c.sprite)) {
+                let kind = message("report-costumes", &[]);
+                let _ = writeln!(markdown, "| {kind} | {} |", cell(&costume.format()));
+            }
+            for variable in self.variables.iter().filter(|v| is_sprite(&v.sprite)) {
+                let kind = message("report-variables", &[]);
+                let _ = writeln!(markdown, "| {kind} | {} |", cell(&variable.format()));
+            }
+
+            let scripts = self
+                .changed_scripts
+                .iter()
+                .filter(|script| is_sprite(&script.sprite))
+                .collect::<Vec<_>>();
+            let diff = script_diffs.get(&sprite).filter(|diff| !diff.is_empty());
+            if scripts.is_empty() && diff.is_none() {
+                continue;
+            }
+            let _ = write!(
+                markdown,
+                "\n<details>\n<summary>{}</summary>\n\n",
+                message("report-script-changes", &[])
+            );
+            for script in scripts {
+                let id = match script.kind {
+                    ScriptChangeType::Added => "report-script-added",
+                    ScriptChangeType::Removed => "report-script-removed",
+                    ScriptChangeType::Modified => "report-script-changed",
+                };
+                let change = message(id, &[("opcode", &script.opcode)]);
+                let _ = writeln!(markdown, "- {change}");
+            }
+            if let Some(diff) = diff {
+                let _ = write!(markdown, "\n```diff\n{}\n```\n", diff.trim_end());
+            }
+            markdown += "\n</details>\n";
+        }
+
+        if !self.extensions.is_empty() {
+            let _ = write!(markdown, "\n### {}\n\n", message("report-extensions", &[]));
+            for extension in &self.extensions {
+                let _ = writeln!(markdown, "- {}", extension.format());
+            }
+        }
+        if !self.broadcasts.is_empty() {
+            let _ = write!(markdown, "\n### {}\n\n", message("report-broadcasts", &[]));
+            for broadcast in &self.broadcasts {
+                let _ = writeln!(
+                    markdown,
+                    "- {} ({})",
+                    broadcast.format(),
+                    broadcast.describe_uses()
+                );
+            }
+        }
+        if !self.warnings.is_empty() {
+            let _ = write!(markdown, "\n### {}\n\n", message("report-warnings", &[]));
+            for warning in &self.warnings {
+                let _ = writeln!(markdown, "- ⚠️ {warning}");
+            }
+        }
+        markdown
     }
 }
 
 /// Commit generation methods for Scratch project assets and code
 #[derive(Debug)]
 pub struct Diff {
-    pub data: Value,
+    pub data: Project,
+    /// Checked between sprites so a cancelled request stops early
+    pub cancel: CancelToken,
+    /// Used instead of the settings in the project's directory, like when set up by
+    /// [`DiffBuilder`](crate::diff::builder::DiffBuilder)
+    pub settings: Option<Settings>,
 }
@@ -0,0 +1,136 @@
+//! Filling in the commit message templates from `pixelpioneers.toml`
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::config::CommitSettings;
+use crate::i18n::translate;
+
+use super::label::Labeler;
+use super::structs::ScriptChanges;
+
+/// Replace each `{name}` in a template with its value, leaving unknown placeholders as they are
+pub fn fill(template: &str, values: &[(&str, &str)]) -> String {
+    let mut filled = template.to_string();
+    for (name, value) in values {
+        filled = filled.replace(&format!("{{{name}}}"), value);
+    }
+    filled
+}
+
+/// Today's date in UTC, like `2024-05-01`
+pub fn today() -> String {
+    let days = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs() / 86400) as i64;
+
+    // days since 1970-01-01 to a civil date, counting years from March so leap days come last
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month + 2) / 5 + 1;
+    let month = if month < 10 { month + 3 } else { month - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    format!("{year:04}-{month:02}-{day:02}")
+}
+
+/// The kind of asset a file is by its extension, either `costumes` or `sounds`
+pub fn asset_kind(file: &str) -> &'static str {
+    match file.rsplit_once('.') {
+        Some((_, "wav" | "mp3")) => "sounds",
+        _ => "costumes",
+    }
+}
+
+impl CommitSettings {
+    /// The word used for an asset action like `add`, from `templates.actions` or the locale
+    pub fn action<'a>(&'a self, action: &'a str) -> &'a str {
+        match self.templates.actions.get(action) {
+            Some(word) => word.as_str(),
+            None => self.word(action),
+        }
+    }
+
+    /// A word from the commit vocabulary in the locale's language
+    pub fn word<'a>(&self, word: &'a str) -> &'a str {
+        translate(&self.locale, word)
+    }
+
+    /// Describe asset changes of one kind in a sprite
+    pub fn assets(&self, action: &str, items: &[String]) -> String {
+        fill(
+            &self.templates.assets,
+            &[
+                ("action", self.action(action)),
+                ("items", &items.join(", ")),
+                ("count", &items.len().to_string()),
+            ],
+        )
+    }
+
+    /// Describe script changes in a sprite
+    pub fn blocks(&self, changes: &ScriptChanges) -> String {
+        let Some(template) = &self.templates.blocks else {
+            return changes.describe_as(self.word("blocks"));
+        };
+        fill(
+            template,
+            &[
+                ("sprite", &changes.sprite),
+                ("added", &changes.added.to_string()),
+                ("removed", &changes.removed.to_string()),
+            ],
+        )
+    }
+
+    /// Put the emoji for each kind of change in it, like `scripts`, before a change
+    ///
+    /// Changes are left as they are unless `emoji.enabled` is set
+    pub fn emoji(&self, kinds: &[&str], change: String) -> String {
+        if !self.emoji.enabled {
+            return change;
+        }
+        let emoji = kinds
+            .iter()
+            .map(|kind| match *kind {
+                "scripts" => self.emoji.scripts.as_str(),
+                "costumes" => self.emoji.costumes.as_str(),
+                "sounds" => self.emoji.sounds.as_str(),
+                "extensions" => self.emoji.extensions.as_str(),
+                "variables" => self.emoji.variables.as_str(),
+                _ => "",
+            })
+            .collect::<String>();
+        match emoji.is_empty() {
+            true => change,
+            false => format!("{emoji} {change}"),
+        }
+    }
+
+    /// Names sprites in commit messages, marking the stage as `stage_label` says
+    pub fn labeler(&self) -> Labeler {
+        Labeler::new(self.stage_label.clone(), self.locale.as_str())
+    }
+
+    /// A sprite's name, marking the stage as `stage_label` says
+    pub fn label(&self, sprite: &str) -> String {
+        self.labeler().label(sprite)
+    }
+
+    /// Put together every change in a sprite into a line of the message
+    pub fn line(&self, sprite: &str, changes: &[String]) -> String {
+        fill(
+            &self.templates.line,
+            &[
+                ("sprite", &self.label(sprite)),
+                ("changes", &changes.join(", ")),
+                ("count", &changes.len().to_string()),
+                ("date", &today()),
+            ],
+        )
+    }
+}
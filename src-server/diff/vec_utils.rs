@@ -1,5 +1,5 @@
 use super::AssetChange;
-use std::collections::{HashMap, HashSet};
+use std::collections::HashMap;
 
 pub trait ItemGrouping {
     fn method(&self) -> HashMap<String, Vec<String>>;
@@ -37,28 +37,6 @@ impl ItemGrouping for Vec<Vec<String>> {
     }
 }
 
-/// Set-intersection of costume changes because HashSet::intersection sucks
-///
-/// Returns costumes present in all sets that have the same costume name and original sprite.
-/// Paths are ignored since they can change on save
-pub fn intersect_costumes(mut sets: Vec<HashSet<AssetChange>>) -> HashSet<AssetChange> {
-    if sets.is_empty() {
-        return HashSet::new();
-    }
-    if sets.len() == 1 {
-        return sets.pop().unwrap();
-    }
-
-    let mut result = sets.pop().unwrap();
-    result.retain(|item| {
-        sets.iter().all(|set| {
-            set.iter()
-                .any(|x| x.name == item.name && x.sprite == item.sprite)
-        })
-    });
-    result
-}
-
 pub fn group_items<T: ItemGrouping>(items: T) -> HashMap<String, Vec<String>> {
     ItemGrouping::method(&items)
 }
@@ -0,0 +1,70 @@
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::net::TcpListener;
+use std::sync::OnceLock;
+
+use serde::{Deserialize, Serialize};
+
+/// Written on startup so scripts can find the server without guessing its port
+const DISCOVERY_PATH: &str = "projects/.server.json";
+
+/// How many ports after the configured one to try before giving up
+const MAX_FALLBACK: u16 = 20;
+
+/// Where the server ended up listening
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ServerAddress {
+    pub host: String,
+    pub port: u16,
+    pub tls: bool,
+    /// Unix socket path or named pipe the server also listens on, if any
+    #[serde(default)]
+    pub socket: Option<String>,
+    /// Process ID of the server
+    pub pid: u32,
+}
+
+impl ServerAddress {
+    /// Base WebSocket URL for the frontend to connect to
+    pub fn socket_url(&self) -> String {
+        let scheme = if self.tls { "wss" } else { "ws" };
+        // a wildcard address can't be connected to, but localhost always reaches it
+        let host = match self.host.as_str() {
+            "127.0.0.1" | "0.0.0.0" | "::" | "::1" => "localhost",
+            host => host,
+        };
+        format!("{scheme}://{host}:{}", self.port)
+    }
+}
+
+/// Bind to a port, moving on to the next one if it's already taken
+pub fn bind(host: &str, port: u16) -> io::Result<(TcpListener, u16)> {
+    let mut last_error = None;
+    for port in (port..=port.saturating_add(MAX_FALLBACK)).filter(|p| *p != 0) {
+        match TcpListener::bind((host, port)) {
+            Ok(listener) => return Ok((listener, port)),
+            Err(e) if e.kind() == ErrorKind::AddrInUse => last_error = Some(e),
+            Err(e) => return Err(e),
+        }
+    }
+    Err(last_error.unwrap_or(ErrorKind::AddrInUse.into()))
+}
+
+static ADDRESS: OnceLock<ServerAddress> = OnceLock::new();
+
+/// Record the chosen address, both in memory for the `/server` endpoint and in the discovery file
+pub fn publish(address: ServerAddress) -> io::Result<()> {
+    fs::write(DISCOVERY_PATH, serde_json::to_string(&address)?)?;
+    let _ = ADDRESS.set(address);
+    Ok(())
+}
+
+/// The address published on startup
+pub fn address() -> Option<&'static ServerAddress> {
+    ADDRESS.get()
+}
+
+/// Read the discovery file a running server wrote
+pub fn read() -> Option<ServerAddress> {
+    serde_json::from_str(&fs::read_to_string(DISCOVERY_PATH).ok()?).ok()
+}
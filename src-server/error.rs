@@ -0,0 +1,82 @@
+//! Failures clients can tell apart, so they can show what to do about them instead of a
+//! generic message
+//!
+//! Most of the server passes errors around as [`anyhow::Error`] with context added on the way
+//! up. Where one of these is at the bottom of the chain, [`Error::find`] digs it out, and
+//! responses name its [kind](Error::kind) next to the message.
+
+use std::io;
+
+use thiserror::Error;
+
+/// Result of the library functions that fail with an [`Error`]
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Represents a failure worth handling on its own
+#[derive(Debug, Error)]
+pub enum Error {
+    /// Git isn't installed, or isn't on the `PATH` the server was started with
+    #[error("Git isn't installed or couldn't be run")]
+    GitUnavailable(#[source] io::Error),
+    /// A branch, tag or commit that doesn't exist, or a file that isn't in it
+    #[error("{0} doesn't exist")]
+    RevisionNotFound(String),
+    /// A project.json that parses but doesn't hold together, like a script pointing at a block
+    /// that isn't there
+    #[error("{sprite} has a broken {field}")]
+    MalformedProject { sprite: String, field: String },
+    /// JSON that couldn't be parsed, like a project.json cut off partway through
+    #[error("failed to parse {what}: {source}")]
+    ParseFailure {
+        what: String,
+        #[source]
+        source: serde_json::Error,
+    },
+    /// A project past one of the [limits](crate::limits), like a ZIP that inflates to gigabytes
+    #[error("{what} is too large: {detail}")]
+    TooLarge { what: String, detail: String },
+    #[error(transparent)]
+    IoError(#[from] io::Error),
+}
+
+impl Error {
+    /// Git failing to start, where it not being found is told apart from other IO errors
+    pub fn git(e: io::Error) -> Self {
+        match e.kind() {
+            io::ErrorKind::NotFound => Error::GitUnavailable(e),
+            _ => Error::IoError(e),
+        }
+    }
+
+    /// JSON that failed to parse, described as what it was like `HEAD:project.json`
+    pub fn parse(what: impl Into<String>, source: serde_json::Error) -> Self {
+        Error::ParseFailure {
+            what: what.into(),
+            source,
+        }
+    }
+
+    /// Name of the kind of failure, for clients to branch on
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Error::GitUnavailable(_) => "git-unavailable",
+            Error::RevisionNotFound(_) => "revision-not-found",
+            Error::MalformedProject { .. } => "malformed-project",
+            Error::ParseFailure { .. } => "parse-failure",
+            Error::TooLarge { .. } => "too-large",
+            Error::IoError(_) => "io-error",
+        }
+    }
+
+    /// Find the first of these in an error's chain of causes, if there is one
+    pub fn find(error: &anyhow::Error) -> Option<&Error> {
+        error
+            .chain()
+            .find_map(|cause| cause.downcast_ref::<Error>())
+    }
+
+    /// The kind of failure an error was caused by, if it was one of these
+    pub fn kind_of(error: &anyhow::Error) -> Option<&'static str> {
+        Error::find(error).map(Error::kind)
+    }
+}
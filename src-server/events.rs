@@ -0,0 +1,24 @@
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Mutex, OnceLock};
+
+use serde_json::Value;
+
+fn subscribers() -> &'static Mutex<Vec<Sender<Value>>> {
+    static SUBSCRIBERS: OnceLock<Mutex<Vec<Sender<Value>>>> = OnceLock::new();
+    SUBSCRIBERS.get_or_init(|| Mutex::new(vec![]))
+}
+
+/// Receive every event broadcast from now on
+pub fn subscribe() -> Receiver<Value> {
+    let (tx, rx) = channel();
+    subscribers().lock().unwrap().push(tx);
+    rx
+}
+
+/// Send an event to every connected client, forgetting clients that disconnected
+pub fn broadcast(event: Value) {
+    subscribers()
+        .lock()
+        .unwrap()
+        .retain(|tx| tx.send(event.clone()).is_ok());
+}
@@ -0,0 +1,185 @@
+//! Trying out an idea without risking the project: start an experiment, then keep it or throw it
+//! away
+//!
+//! An experiment is a Git branch named `experiment/<name>`, made from whichever branch the project
+//! was on. That branch is remembered in the experiment branch's Git config, so keeping the
+//! experiment merges it back there and discarding it switches back there as if it never happened.
+//! Nothing here needs knowing what a branch is, which is the point.
+
+use std::path::PathBuf;
+
+use anyhow::{anyhow, Context, Result};
+use serde::Serialize;
+
+use crate::config;
+use crate::git;
+
+/// What experiment branch names start with
+const PREFIX: &str = "experiment/";
+
+/// Represents the experiment a project is in
+#[derive(Debug, Clone, Serialize)]
+pub struct Experiment {
+    pub name: String,
+    pub branch: String,
+    /// Branch the experiment was started from, which it goes back into when kept
+    pub base: String,
+}
+
+/// Represents what keeping an experiment did
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "status", rename_all = "lowercase")]
+pub enum KeepOutcome {
+    /// The experiment was merged back, with this commit message
+    Kept { message: String },
+    /// The base branch changed the same files since, so nothing was merged and the project is
+    /// still in the experiment
+    Conflicts { files: Vec<String> },
+}
+
+/// Run Git, failing with its error output if it fails
+fn git(pth: &PathBuf, args: Vec<&str>, failure: &str) -> Result<String> {
+    let output = git::run(args, Some(pth)).output()?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "{failure}: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Where a branch's base is kept in Git config
+fn base_key(branch: &str) -> String {
+    format!("branch.{branch}.pixelpioneersBase")
+}
+
+/// The branch a project is on, or `None` if it's on a commit instead
+fn current_branch(pth: &PathBuf) -> Result<Option<String>> {
+    let output = git::run(
+        vec!["symbolic-ref", "--quiet", "--short", "HEAD"],
+        Some(pth),
+    )
+    .output()?;
+    let branch = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    Ok((output.status.success() && !branch.is_empty()).then_some(branch))
+}
+
+/// The experiment a project is in, if it's in one
+pub fn current(pth: &PathBuf) -> Result<Option<Experiment>> {
+    let Some(branch) = current_branch(pth)? else {
+        return Ok(None);
+    };
+    let Some(name) = branch.strip_prefix(PREFIX).map(str::to_string) else {
+        return Ok(None);
+    };
+    let base = git::run(vec!["config", "--get", &base_key(&branch)], Some(pth)).output()?;
+    let base = String::from_utf8_lossy(&base.stdout).trim().to_string();
+    if base.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(Experiment { name, branch, base }))
+}
+
+/// Start an experiment from the branch the project is on, switching to it
+///
+/// Uncommitted changes have to be committed first, so discarding the experiment can always go
+/// back to exactly how the project was.
+pub fn start(pth: &PathBuf, name: &str) -> Result<Experiment> {
+    if git::is_dirty(pth)? {
+        return Err(anyhow!("commit your changes before starting an experiment"));
+    }
+    if let Some(experiment) = current(pth)? {
+        return Err(anyhow!("already trying out {}", experiment.name));
+    }
+    let base = current_branch(pth)?.context("switch to a branch before starting an experiment")?;
+
+    let name = name.trim().replace(char::is_whitespace, "-");
+    let branch = format!("{PREFIX}{name}");
+    git(
+        pth,
+        vec!["check-ref-format", "--branch", &branch],
+        &format!("{name} can't be used as a name"),
+    )?;
+    if git::revision_exists(pth, &format!("refs/heads/{branch}"))? {
+        return Err(anyhow!("there's already an experiment called {name}"));
+    }
+
+    git(
+        pth,
+        vec!["switch", "--create", &branch],
+        "failed to start experiment",
+    )?;
+    git(
+        pth,
+        vec!["config", &base_key(&branch), &base],
+        "failed to remember where the experiment started",
+    )?;
+    Ok(Experiment { name, branch, base })
+}
+
+/// Keep an experiment, merging it back into the branch it was started from and deleting it
+///
+/// Uncommitted changes have to be committed first, so they're kept too. If the base branch
+/// changed the same things since, nothing happens and the conflicting files are returned.
+pub fn keep(pth: &PathBuf) -> Result<KeepOutcome> {
+    let experiment = current(pth)?.context("not trying out an experiment")?;
+    if git::is_dirty(pth)? {
+        return Err(anyhow!("commit your changes before keeping the experiment"));
+    }
+
+    git(
+        pth,
+        vec!["switch", &experiment.base],
+        "failed to switch back",
+    )?;
+    let message = format!("keep experiment {}", experiment.name);
+    let mut merge = git::run(
+        vec!["merge", "--no-ff", "-m", &message, &experiment.branch],
+        Some(pth),
+    );
+    if let Some(identity) = config::settings(Some(pth)).identity.as_ref() {
+        identity.apply(&mut merge);
+    }
+    if !merge.output()?.status.success() {
+        let files = git::conflicted_files(pth)?;
+        let _ = git::run(vec!["merge", "--abort"], Some(pth)).status();
+        git(
+            pth,
+            vec!["switch", &experiment.branch],
+            "failed to go back to the experiment",
+        )?;
+        return Ok(KeepOutcome::Conflicts { files });
+    }
+
+    git(
+        pth,
+        vec!["branch", "--delete", &experiment.branch],
+        "failed to delete experiment",
+    )?;
+    Ok(KeepOutcome::Kept { message })
+}
+
+/// Throw an experiment away, switching back to the branch it was started from as it was
+///
+/// Everything in the experiment is lost, including uncommitted changes.
+pub fn discard(pth: &PathBuf) -> Result<Experiment> {
+    let experiment = current(pth)?.context("not trying out an experiment")?;
+    git(
+        pth,
+        vec!["switch", "--discard-changes", &experiment.base],
+        "failed to switch back",
+    )?;
+    // files the experiment added without committing are left behind by switching
+    git(
+        pth,
+        vec!["clean", "--force", "-d"],
+        "failed to remove the experiment's files",
+    )?;
+    git(
+        pth,
+        vec!["branch", "--delete", "--force", &experiment.branch],
+        "failed to delete experiment",
+    )?;
+    Ok(experiment)
+}
@@ -0,0 +1,58 @@
+//! Which extension a block comes from, judging by its opcode
+//!
+//! Opcodes start with their category or extension ID, like `pen_clear`. Mods like TurboWarp add
+//! extensions Scratch doesn't have, which are listed with their [`formats`](crate::formats), and
+//! custom extensions can be loaded from anywhere, so blocks from extensions that aren't listed
+//! can only be shown by their raw opcodes.
+
+/// Opcode prefixes built into Scratch, besides those of extensions, including the shadow blocks
+/// typed into inputs like `math_number`
+pub const CORE_CATEGORIES: [&str; 13] = [
+    "motion",
+    "looks",
+    "sound",
+    "event",
+    "control",
+    "sensing",
+    "operator",
+    "data",
+    "procedures",
+    "argument",
+    "math",
+    "text",
+    "colour",
+];
+
+/// Extensions built into Scratch, by ID with the name the editor shows
+pub const KNOWN_EXTENSIONS: [(&str, &str); 11] = [
+    ("pen", "Pen"),
+    ("music", "Music"),
+    ("videoSensing", "Video Sensing"),
+    ("text2speech", "Text to Speech"),
+    ("translate", "Translate"),
+    ("makeymakey", "Makey Makey"),
+    ("microbit", "micro:bit"),
+    ("ev3", "LEGO MINDSTORMS EV3"),
+    ("boost", "LEGO BOOST"),
+    ("wedo2", "LEGO Education WeDo 2.0"),
+    ("gdxfor", "Go Direct Force & Acceleration"),
+];
+
+/// The category or extension ID an opcode starts with
+pub fn prefix(opcode: &str) -> &str {
+    opcode.split('_').next().unwrap_or_default()
+}
+
+/// The name the editor shows for an extension, if it's a known one
+pub fn name(id: &str) -> Option<&'static str> {
+    KNOWN_EXTENSIONS
+        .iter()
+        .find(|(known, _)| *known == id)
+        .map(|(_, name)| *name)
+}
+
+/// Check if an opcode is from Scratch itself or one of its extensions
+pub fn is_known(opcode: &str) -> bool {
+    let prefix = prefix(opcode);
+    CORE_CATEGORIES.contains(&prefix) || name(prefix).is_some()
+}
@@ -0,0 +1,141 @@
+//! C bindings for generating diff reports, so desktop apps in other languages can reuse the
+//! engine
+//!
+//! Strings cross over as NUL-terminated UTF-8. Strings returned are owned by the caller, who
+//! frees them with [`pp_string_free`]. Failures return `NULL`, with the reason kept for the
+//! calling thread until its next call, from [`pp_last_error`].
+//!
+//! `include/pixelpioneers.h` is generated from this file with
+//! `cbindgen --config cbindgen.toml --output include/pixelpioneers.h`.
+
+use std::cell::RefCell;
+use std::ffi::{c_char, CStr, CString};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::PathBuf;
+use std::ptr;
+
+use anyhow::{anyhow, Context, Result};
+
+use crate::diff::structs::{Diff, DiffReport};
+use crate::observer::Quiet;
+use crate::ops;
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+/// Read a string argument, which can't be `NULL`
+///
+/// # Safety
+/// `ptr` has to be `NULL` or point to a NUL-terminated string that outlives the call
+unsafe fn argument<'a>(ptr: *const c_char, name: &str) -> Result<&'a str> {
+    if ptr.is_null() {
+        return Err(anyhow!("{name} is NULL"));
+    }
+    CStr::from_ptr(ptr)
+        .to_str()
+        .with_context(|| format!("{name} isn't UTF-8"))
+}
+
+/// Read a string argument that can be `NULL`
+///
+/// # Safety
+/// Same as [`argument`]
+unsafe fn optional_argument<'a>(ptr: *const c_char, name: &str) -> Result<Option<&'a str>> {
+    if ptr.is_null() {
+        return Ok(None);
+    }
+    argument(ptr, name).map(Some)
+}
+
+/// Hand a result over to C, keeping the error for [`pp_last_error`] if it failed
+fn answer(call: impl FnOnce() -> Result<String>) -> *mut c_char {
+    // unwinding into C is undefined, so panics fail the call like errors do
+    let result = panic::catch_unwind(AssertUnwindSafe(call))
+        .unwrap_or_else(|_| Err(anyhow!("the diff engine panicked")))
+        .and_then(|answer| Ok(CString::new(answer)?));
+    let (answer, error) = match result {
+        Ok(answer) => (answer.into_raw(), None),
+        Err(e) => (ptr::null_mut(), CString::new(format!("{e:#}")).ok()),
+    };
+    LAST_ERROR.with(|last| *last.borrow_mut() = error);
+    answer
+}
+
+/// Compare two project.json files, in `repo` if there is one or else in a scratch repository
+/// with default settings
+fn report(old: &str, new: &str, repo: Option<PathBuf>) -> Result<DiffReport> {
+    let old = Diff::new(ops::parse_project(old)?);
+    let new = Diff::new(ops::parse_project(new)?);
+    match repo {
+        Some(repo) => ops::compare(&repo, &old, &new, &mut Quiet),
+        None => ops::compare_detached(&old, &new, &mut Quiet),
+    }
+}
+
+/// Diff two project.json files, returning the report as JSON, or `NULL` if it failed
+///
+/// Scripts are diffed in the Git repository at `repo`, with its settings. If `repo` is `NULL`,
+/// a scratch repository is used with the default settings.
+///
+/// # Safety
+/// `old_json` and `new_json` have to be NUL-terminated strings, and `repo` one too or `NULL`.
+/// The returned string has to be freed with [`pp_string_free`].
+#[no_mangle]
+pub unsafe extern "C" fn pp_diff_report(
+    old_json: *const c_char,
+    new_json: *const c_char,
+    repo: *const c_char,
+) -> *mut c_char {
+    answer(|| {
+        let old = argument(old_json, "old_json")?;
+        let new = argument(new_json, "new_json")?;
+        let repo = optional_argument(repo, "repo")?.map(PathBuf::from);
+        Ok(serde_json::to_string(&report(old, new, repo)?)?)
+    })
+}
+
+/// Diff two project.json files like [`pp_diff_report`], returning a Markdown summary instead,
+/// like for a pull request description
+///
+/// # Safety
+/// Same as [`pp_diff_report`]
+#[no_mangle]
+pub unsafe extern "C" fn pp_diff_markdown(
+    old_json: *const c_char,
+    new_json: *const c_char,
+    repo: *const c_char,
+) -> *mut c_char {
+    answer(|| {
+        let old = argument(old_json, "old_json")?;
+        let new = argument(new_json, "new_json")?;
+        let repo = optional_argument(repo, "repo")?.map(PathBuf::from);
+        Ok(report(old, new, repo)?.to_markdown())
+    })
+}
+
+/// Why the calling thread's last call failed, or `NULL` if it didn't
+///
+/// The string is owned by the library and only valid until the thread's next call, so it
+/// mustn't be freed.
+#[no_mangle]
+pub extern "C" fn pp_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| last.borrow().as_ref().map_or(ptr::null(), |e| e.as_ptr()))
+}
+
+/// Version of the library, like `0.1.0`, which mustn't be freed
+#[no_mangle]
+pub extern "C" fn pp_version() -> *const c_char {
+    concat!(env!("CARGO_PKG_VERSION"), "\0").as_ptr().cast()
+}
+
+/// Free a string returned by the library
+///
+/// # Safety
+/// `string` has to be `NULL` or a string returned by this library that isn't freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn pp_string_free(string: *mut c_char) {
+    if !string.is_null() {
+        drop(CString::from_raw(string));
+    }
+}
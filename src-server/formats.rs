@@ -0,0 +1,125 @@
+//! Editors a project.json can come from, which all save Scratch 3's format with their own
+//! additions
+//!
+//! Mods like TurboWarp and PenguinMod build on Scratch 3, adding extensions and block
+//! categories Scratch doesn't have and fields of their own. They say which editor saved a
+//! project in `meta.platform`, so the format is picked by sniffing that, and a project that
+//! doesn't say is taken to be from Scratch itself.
+
+use serde_json::Value;
+
+use crate::extensions;
+
+/// Represents what sets an editor's project.json apart from vanilla Scratch 3's
+pub trait ProjectFormat: Sync {
+    /// The editor's name, as it's given in `meta.platform.name`
+    fn name(&self) -> &'static str;
+
+    /// Check if a project.json's `meta` says this editor saved it
+    fn detect(&self, meta: &Value) -> bool {
+        meta["platform"]["name"] == self.name()
+    }
+
+    /// Opcode prefixes of block categories the editor has built in besides Scratch's
+    fn categories(&self) -> &'static [&'static str] {
+        &[]
+    }
+
+    /// Extensions built into the editor besides Scratch's, by ID with the name the editor shows
+    fn extensions(&self) -> &'static [(&'static str, &'static str)] {
+        &[]
+    }
+
+    /// Fields that change between saves without the project changing, as paths from the root
+    fn volatile(&self) -> &'static [&'static [&'static str]] {
+        // the editor's user agent
+        &[&["meta", "agent"]]
+    }
+
+    /// The name the editor shows for an extension, if it's built in
+    fn extension_name(&self, id: &str) -> Option<&'static str> {
+        extensions::name(id).or_else(|| {
+            self.extensions()
+                .iter()
+                .find(|(known, _)| *known == id)
+                .map(|(_, name)| *name)
+        })
+    }
+
+    /// Check if an opcode is from a category or extension built into the editor
+    fn is_known(&self, opcode: &str) -> bool {
+        let prefix = extensions::prefix(opcode);
+        extensions::is_known(opcode)
+            || self.categories().contains(&prefix)
+            || self.extension_name(prefix).is_some()
+    }
+}
+
+/// Scratch 3 itself, which every other format builds on
+pub struct Scratch3;
+
+impl ProjectFormat for Scratch3 {
+    fn name(&self) -> &'static str {
+        "Scratch 3"
+    }
+
+    /// Scratch doesn't name itself, so it's assumed when no other editor is named
+    fn detect(&self, meta: &Value) -> bool {
+        meta["platform"]["name"].is_null()
+    }
+}
+
+pub struct TurboWarp;
+
+impl ProjectFormat for TurboWarp {
+    fn name(&self) -> &'static str {
+        "TurboWarp"
+    }
+
+    fn extensions(&self) -> &'static [(&'static str, &'static str)] {
+        &[("tw", "TurboWarp")]
+    }
+}
+
+/// PenguinMod, a mod of TurboWarp, so it has TurboWarp's extension too
+pub struct PenguinMod;
+
+impl ProjectFormat for PenguinMod {
+    fn name(&self) -> &'static str {
+        "PenguinMod"
+    }
+
+    fn categories(&self) -> &'static [&'static str] {
+        &[
+            "pmEventsExpansion",
+            "pmControlsExpansion",
+            "pmSensingExpansion",
+            "pmOperatorsExpansion",
+        ]
+    }
+
+    fn extensions(&self) -> &'static [(&'static str, &'static str)] {
+        &[
+            ("tw", "TurboWarp"),
+            ("jgJSON", "JSON"),
+            ("jgRuntime", "Runtime Modifications"),
+        ]
+    }
+
+    fn volatile(&self) -> &'static [&'static [&'static str]] {
+        // which release of the editor saved it, like `stable` or `develop`
+        &[&["meta", "agent"], &["meta", "platform", "version"]]
+    }
+}
+
+/// Every format, with Scratch 3 last since the others are more specific
+pub const FORMATS: [&dyn ProjectFormat; 3] = [&TurboWarp, &PenguinMod, &Scratch3];
+
+/// Pick the format of a project.json from its `meta`, which is Scratch 3 unless another editor
+/// is named
+pub fn detect(meta: &Value) -> &'static dyn ProjectFormat {
+    FORMATS
+        .into_iter()
+        .find(|format| format.detect(meta))
+        .unwrap_or(&Scratch3)
+}
@@ -1,9 +1,17 @@
-use std::io::Write;
+use std::collections::{HashMap, HashSet};
+use std::io::{BufReader, Write};
 use std::path::PathBuf;
-use std::process::{Command, Stdio};
+use std::process::{ChildStdout, Command, Stdio};
+use std::sync::{Arc, Mutex, OnceLock};
 
 use anyhow::{anyhow, Result};
-use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, instrument, trace, warn};
+
+use crate::cache::{CacheStats, LruCache};
+use crate::error::Error;
+use crate::temp::TempWorkspace;
 
 /// Return a generated blob ID from a string
 fn git_object_id(cwd: &PathBuf, content: String) -> Result<String> {
@@ -135,8 +143,237 @@ pub fn diff(
     })
 }
 
+/// Cached revision contents, keyed by repository and object ID
+struct RevisionCache {
+    /// Shared so a hit doesn't copy what can be a multi-megabyte project.json
+    contents: LruCache<(PathBuf, String), Arc<str>>,
+    /// Revspecs that always name the same object, like `<commit ID>:project.json`, resolved to
+    /// that object's ID
+    resolved: LruCache<(PathBuf, String), String>,
+}
+
+fn revision_cache() -> &'static Mutex<RevisionCache> {
+    static CACHE: OnceLock<Mutex<RevisionCache>> = OnceLock::new();
+    CACHE.get_or_init(|| {
+        Mutex::new(RevisionCache {
+            contents: LruCache::new(64),
+            resolved: LruCache::new(1024),
+        })
+    })
+}
+
+/// Hit rate of the `show_revision` cache
+pub fn revision_cache_stats() -> CacheStats {
+    revision_cache().lock().unwrap().contents.stats()
+}
+
+/// Drop every cached revision
+pub fn clear_revision_cache() {
+    let mut cache = revision_cache().lock().unwrap();
+    cache.contents.clear();
+    cache.resolved.clear();
+}
+
+/// Return the commit HEAD points to by reading `.git` directly, avoiding a process spawn
+fn head_id(cwd: &PathBuf) -> Result<String> {
+    let git_dir = cwd.join(".git");
+    let head = std::fs::read_to_string(git_dir.join("HEAD"))?;
+    let Some(reference) = head.trim().strip_prefix("ref: ") else {
+        // detached HEAD
+        return Ok(head.trim().to_string());
+    };
+
+    if let Ok(id) = std::fs::read_to_string(git_dir.join(reference)) {
+        return Ok(id.trim().to_string());
+    }
+
+    let packed = std::fs::read_to_string(git_dir.join("packed-refs")).unwrap_or_default();
+    if let Some(id) = packed
+        .lines()
+        .filter_map(|line| line.split_once(" "))
+        .find(|(_, name)| *name == reference)
+        .map(|(id, _)| id.to_string())
+    {
+        return Ok(id);
+    }
+
+    // unborn branch, so nothing can be resolved yet
+    Ok(String::new())
+}
+
+/// Check if a revision is a full object ID, which always names the same object
+fn is_full_id(revision: &str) -> bool {
+    matches!(revision.len(), 40 | 64)
+        && revision
+            .bytes()
+            .all(|c| matches!(c, b'0'..=b'9' | b'a'..=b'f'))
+}
+
+/// Spell a revspec so it always names the same object, if it can be, like `HEAD:project.json`
+/// as `<commit ID>:project.json`
+///
+/// Branches, upstreams and index stages like `:2:project.json` can move without HEAD moving,
+/// so they're left alone.
+fn pinned(cwd: &PathBuf, commit: &str) -> Option<String> {
+    let (revision, path) = match commit.split_once(':') {
+        Some((revision, path)) => (revision, Some(path)),
+        None => (commit, None),
+    };
+    let revision = match revision {
+        _ if is_full_id(revision) => revision.to_string(),
+        "HEAD" => head_id(cwd).ok().filter(|id| is_full_id(id))?,
+        _ => return None,
+    };
+    Some(match path {
+        Some(path) => format!("{revision}:{path}"),
+        None => revision,
+    })
+}
+
 /// Fetch the revision of a certain file
+///
+/// Results are cached by object ID, since blob contents never change for an object ID
 pub fn show_revision(cwd: &PathBuf, commit: &str) -> Result<String> {
+    Ok(show_revision_shared(cwd, commit)?.to_string())
+}
+
+/// Fetch the revision of a certain file like [`show_revision`], sharing the cached copy
+/// instead of copying it
+pub fn show_revision_shared(cwd: &PathBuf, commit: &str) -> Result<Arc<str>> {
+    let object_id = object_id(cwd, commit)?;
+
+    // let `git show` produce the same (empty) output as before for bad revisions
+    if object_id.is_empty() {
+        // projects stored one file per sprite have no project.json to show
+        if let Some(revision) = commit.strip_suffix(":project.json") {
+            if let Ok(json) = crate::split::show_revision(cwd, revision) {
+                return Ok(json.into());
+            }
+        }
+        return Ok(show_revision_uncached(cwd, commit)?.into());
+    }
+
+    let key = (cwd.clone(), object_id);
+    if let Some(contents) = revision_cache().lock().unwrap().contents.get(&key) {
+        return Ok(contents);
+    }
+
+    // Git runs without the cache locked, so other projects aren't held up
+    let contents = Arc::<str>::from(show_revision_uncached(cwd, &key.1)?);
+    revision_cache()
+        .lock()
+        .unwrap()
+        .contents
+        .insert(key, contents.clone());
+    Ok(contents)
+}
+
+/// Files bigger than this are parsed as Git reads them out, rather than read whole and cached
+const STREAM_SIZE: u64 = 16 * 1024 * 1024;
+
+/// Parse a JSON file in a revision, like `HEAD:project.json`
+///
+/// Big files that aren't cached yet are parsed straight from `git cat-file`, so a huge
+/// project.json is never held as text next to what's parsed from it
+pub fn parse_revision<T: DeserializeOwned>(cwd: &PathBuf, commit: &str) -> Result<T> {
+    let id = object_id(cwd, commit)?;
+    let cached = revision_cache()
+        .lock()
+        .unwrap()
+        .contents
+        .contains(&(cwd.clone(), id.clone()));
+    if !id.is_empty() && !cached && blob_size(cwd, &id)? > STREAM_SIZE {
+        trace!(commit, "streaming revision");
+        return read_blob(cwd, &id, |reader| {
+            serde_json::from_reader(reader).map_err(|e| Error::parse(commit, e).into())
+        });
+    }
+    let contents = show_revision_shared(cwd, commit)?;
+    // Git shows nothing for revisions that don't exist
+    if contents.is_empty() {
+        return Err(Error::RevisionNotFound(commit.into()).into());
+    }
+    Ok(serde_json::from_str(&contents).map_err(|e| Error::parse(commit, e))?)
+}
+
+/// Resolve a revspec to an object ID, remembering it if it always names the same object
+///
+/// Revisions that don't resolve give an empty ID
+fn object_id(cwd: &PathBuf, commit: &str) -> Result<String> {
+    let pinned = pinned(cwd, commit);
+    let key = pinned.clone().map(|pinned| (cwd.clone(), pinned));
+    if let Some(key) = &key {
+        if let Some(id) = revision_cache().lock().unwrap().resolved.get(key) {
+            return Ok(id);
+        }
+    }
+
+    let revision = pinned.as_deref().unwrap_or(commit);
+    let output = run(
+        vec!["rev-parse", "--verify", "--quiet", revision],
+        Some(cwd),
+    )
+    .output()
+    .map_err(Error::git)?;
+    let id = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    if let Some(key) = key.filter(|_| output.status.success() && !id.is_empty()) {
+        revision_cache()
+            .lock()
+            .unwrap()
+            .resolved
+            .insert(key, id.clone());
+    }
+    Ok(id)
+}
+
+/// Return the size of an object in bytes without reading it
+fn blob_size(cwd: &PathBuf, id: &str) -> Result<u64> {
+    let output = run(vec!["cat-file", "-s", id], Some(cwd)).output()?;
+    if !output.status.success() {
+        return Err(anyhow!("{id} isn't an object"));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().parse()?)
+}
+
+/// Read an object as Git writes it out, so it's never held in memory whole
+fn read_blob<T>(
+    cwd: &PathBuf,
+    id: &str,
+    read: impl FnOnce(BufReader<ChildStdout>) -> Result<T>,
+) -> Result<T> {
+    let mut child = run(vec!["cat-file", "blob", id], Some(cwd))
+        .stderr(Stdio::null())
+        .spawn()?;
+    let stdout = child
+        .stdout
+        .take()
+        .ok_or(anyhow!("could not receive stdout"))?;
+
+    let result = read(BufReader::new(stdout));
+    if result.is_err() {
+        // a parse error stops reading partway, so Git would otherwise block on a full pipe
+        let _ = child.kill();
+    }
+    let status = child.wait()?;
+    if result.is_ok() && !status.success() {
+        return Err(anyhow!("failed to read {id}"));
+    }
+    result
+}
+
+/// Read a file as it was in a revision, byte for byte
+pub fn show_file(cwd: &PathBuf, revision: &str, path: &str) -> Result<Vec<u8>> {
+    let object = format!("{revision}:{path}");
+    let output = run(vec!["cat-file", "blob", &object], Some(cwd))
+        .output()
+        .map_err(Error::git)?;
+    if !output.status.success() {
+        return Err(Error::RevisionNotFound(object).into());
+    }
+    Ok(output.stdout)
+}
+
+fn show_revision_uncached(cwd: &PathBuf, commit: &str) -> Result<String> {
     let proc = if cfg!(target_os = "windows") {
         let mut cmd = Command::new("cmd");
         cmd.args(["/C", "git", "show", commit]);
@@ -148,7 +385,8 @@ pub fn show_revision(cwd: &PathBuf, commit: &str) -> Result<String> {
     }
     .current_dir(cwd)
     .output()?;
-    Ok(String::from_utf8_lossy(&proc.stdout).to_string())
+    Ok(String::from_utf8(proc.stdout)
+        .unwrap_or_else(|e| String::from_utf8_lossy(e.as_bytes()).into_owned()))
 }
 
 pub fn main_branch(cwd: &PathBuf) -> Result<String> {
@@ -166,6 +404,7 @@ pub fn main_branch(cwd: &PathBuf) -> Result<String> {
 
 /// Run a Git command
 pub fn run(args: Vec<&str>, cwd: Option<&PathBuf>) -> Command {
+    trace!(?args, ?cwd, "running git");
     let mut cmd = if cfg!(target_os = "windows") {
         let mut cmd = Command::new("cmd");
         let mut git_args = vec!["/C", "git"];
@@ -186,3 +425,827 @@ pub fn run(args: Vec<&str>, cwd: Option<&PathBuf>) -> Command {
 
     cmd
 }
+
+/// Run a Git command like [`run`], but in its own process group, so Ctrl+C in the terminal
+/// doesn't kill it partway through rewriting the repository
+///
+/// Only for long-running commands the server lets finish before it shuts down, since Git can't
+/// prompt on the terminal from outside its process group
+pub fn run_uninterrupted(args: Vec<&str>, cwd: Option<&PathBuf>) -> Command {
+    let mut cmd = run(args, cwd);
+    #[cfg(unix)]
+    std::os::unix::process::CommandExt::process_group(&mut cmd, 0);
+    cmd
+}
+
+/// Return the repository's Git directory, which isn't `.git` in a worktree
+pub fn git_dir(cwd: &PathBuf) -> Result<PathBuf> {
+    let output = run(vec!["rev-parse", "--absolute-git-dir"], Some(cwd))
+        .output()
+        .map_err(Error::git)?;
+    if !output.status.success() {
+        return Err(anyhow!("{} isn't a Git repository", cwd.display()));
+    }
+    Ok(PathBuf::from(String::from_utf8_lossy(&output.stdout).trim()))
+}
+
+/// Binary asset extensions that are routed through Git LFS
+pub const LFS_EXTENSIONS: [&str; 7] = ["png", "jpg", "jpeg", "gif", "bmp", "wav", "mp3"];
+
+/// Return the installed Git version, e.g. `git version 2.43.0`, or `None` if Git can't be run
+pub fn version() -> Option<String> {
+    let output = run(vec!["--version"], None).output().ok()?;
+    output
+        .status
+        .success()
+        .then(|| String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Check if Git LFS is installed on this system
+pub fn lfs_installed() -> bool {
+    run(vec!["lfs", "version"], None)
+        .status()
+        .is_ok_and(|status| status.success())
+}
+
+/// Track binary assets with Git LFS, writing a `.gitattributes` for asset extensions
+///
+/// Returns `false` if Git LFS is not installed, in which case the repository is left untouched
+pub fn setup_lfs(cwd: &PathBuf) -> Result<bool> {
+    if !lfs_installed() {
+        return Ok(false);
+    }
+
+    // installs the smudge/clean filters and pre-push hook for this repository only
+    if !run(vec!["lfs", "install", "--local"], Some(cwd))
+        .status()?
+        .success()
+    {
+        return Err(anyhow!("failed to install git lfs hooks"));
+    }
+
+    let attributes = cwd.join(".gitattributes");
+    let mut contents = std::fs::read_to_string(&attributes).unwrap_or_default();
+    for ext in LFS_EXTENSIONS {
+        let rule = format!("*.{ext} filter=lfs diff=lfs merge=lfs -text");
+        if !contents.lines().any(|line| line == rule) {
+            if !contents.is_empty() && !contents.ends_with("\n") {
+                contents += "\n";
+            }
+            contents += &rule;
+            contents += "\n";
+        }
+    }
+    std::fs::write(&attributes, contents)?;
+
+    // move assets that are already tracked into LFS on the next commit
+    run(vec!["add", ".gitattributes"], Some(cwd)).status()?;
+    run(vec!["add", "--renormalize", "."], Some(cwd)).status()?;
+
+    Ok(true)
+}
+
+/// Write a pre-commit hook that validates the staged project.json with this executable
+pub fn install_hooks(cwd: &PathBuf) -> Result<()> {
+    let hooks = cwd.join(".git").join("hooks");
+    std::fs::create_dir_all(&hooks)?;
+
+    // git runs hooks with its own sh, even on Windows, so forward slashes are safest
+    let exe = std::env::current_exe()?
+        .to_string_lossy()
+        .replace("\\", "/");
+    let hook = hooks.join("pre-commit");
+    std::fs::write(
+        &hook,
+        format!(
+            "#!/bin/sh\n\
+             # installed by scratch.git: refuse to commit a broken project.json\n\
+             git cat-file -e :project.json 2>/dev/null || exit 0\n\
+             git show :project.json | \"{exe}\" --validate -\n"
+        ),
+    )?;
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(&hook, std::fs::Permissions::from_mode(0o755))?;
+    }
+
+    Ok(())
+}
+
+/// Amend the most recent commit with any new changes, optionally replacing its message
+pub fn amend(cwd: &PathBuf, message: Option<&str>) -> Result<()> {
+    if !run(vec!["add", "."], Some(cwd)).status()?.success() {
+        return Err(anyhow!("failed to stage changes"));
+    }
+
+    let args = match message {
+        Some(message) => vec!["commit", "--amend", "-m", message],
+        None => vec!["commit", "--amend", "--no-edit"],
+    };
+    let output = run(args, Some(cwd)).output()?;
+
+    if !output.status.success() {
+        return Err(anyhow!(
+            "failed to amend commit: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(())
+}
+
+/// How far `git reset` should move the working state back
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ResetMode {
+    /// Keep changes staged
+    Soft,
+    /// Keep changes in the working directory
+    Mixed,
+    /// Discard all changes
+    Hard,
+}
+
+/// Check if a revision resolves to a commit
+pub fn revision_exists(cwd: &PathBuf, rev: &str) -> Result<bool> {
+    Ok(run(
+        vec!["rev-parse", "--verify", "--quiet", &format!("{rev}^{{commit}}")],
+        Some(cwd),
+    )
+    .status()?
+    .success())
+}
+
+/// Find the ID of the commit a revision points to
+pub fn resolve(cwd: &PathBuf, rev: &str) -> Result<String> {
+    let output = run(
+        vec!["rev-parse", "--verify", "--quiet", &format!("{rev}^{{commit}}")],
+        Some(cwd),
+    )
+    .output()
+    .map_err(Error::git)?;
+    if !output.status.success() {
+        return Err(Error::RevisionNotFound(rev.into()).into());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Check if a repository has uncommitted changes
+pub fn is_dirty(cwd: &PathBuf) -> Result<bool> {
+    let status = run(vec!["status", "--porcelain"], Some(cwd)).output()?;
+    Ok(!String::from_utf8_lossy(&status.stdout).trim().is_empty())
+}
+
+/// Move the current branch to a previous revision
+pub fn reset(cwd: &PathBuf, rev: &str, mode: ResetMode) -> Result<()> {
+    let mode = match mode {
+        ResetMode::Soft => "--soft",
+        ResetMode::Mixed => "--mixed",
+        ResetMode::Hard => "--hard",
+    };
+    let output = run(vec!["reset", mode, rev], Some(cwd)).output()?;
+
+    if !output.status.success() {
+        return Err(anyhow!(
+            "failed to reset to {rev}: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(())
+}
+
+/// Commits made as one operation, which are undone together unless it finishes
+///
+/// Dropping a transaction without [`finish`](Transaction::finish) moves the branch back to where
+/// it started, keeping what was committed staged, so an operation that fails halfway through
+/// doesn't leave some of its commits behind.
+#[derive(Debug)]
+pub struct Transaction {
+    cwd: PathBuf,
+    /// HEAD when the transaction started, or `None` before the first commit
+    start: Option<String>,
+    finished: bool,
+}
+
+impl Transaction {
+    pub fn begin(cwd: &PathBuf) -> Self {
+        Transaction {
+            cwd: cwd.clone(),
+            start: resolve(cwd, "HEAD").ok(),
+            finished: false,
+        }
+    }
+
+    /// Keep the commits made since the transaction started
+    pub fn finish(mut self) {
+        self.finished = true;
+    }
+
+    /// Undo the commits made since the transaction started, instead of waiting for it to drop
+    pub fn rollback(mut self) -> Result<()> {
+        self.finished = true;
+        self.undo()
+    }
+
+    fn undo(&self) -> Result<()> {
+        if resolve(&self.cwd, "HEAD").ok() == self.start {
+            return Ok(());
+        }
+        match &self.start {
+            Some(start) => reset(&self.cwd, start, ResetMode::Soft),
+            // without a commit to go back to, the branch the first commit made goes instead
+            None => match run(vec!["update-ref", "-d", "HEAD"], Some(&self.cwd)).status()? {
+                status if status.success() => Ok(()),
+                _ => Err(anyhow!("failed to delete the first commit")),
+            },
+        }
+    }
+}
+
+impl Drop for Transaction {
+    fn drop(&mut self) {
+        if self.finished {
+            return;
+        }
+        match self.undo() {
+            Ok(()) => debug!(start = ?self.start, "rolled back unfinished commits"),
+            Err(e) => warn!("failed to roll back unfinished commits: {e:#}"),
+        }
+    }
+}
+
+/// Key type used to sign commits
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SigningFormat {
+    Gpg,
+    Ssh,
+}
+
+/// Sign every commit in a repository with a GPG key ID or SSH public key path,
+/// or turn signing off if no key is given
+pub fn configure_signing(cwd: &PathBuf, key: Option<(SigningFormat, &str)>) -> Result<()> {
+    let Some((format, key)) = key else {
+        run(vec!["config", "--unset", "commit.gpgsign"], Some(cwd)).status()?;
+        run(vec!["config", "--unset", "tag.gpgsign"], Some(cwd)).status()?;
+        return Ok(());
+    };
+
+    let format = match format {
+        SigningFormat::Gpg => "openpgp",
+        SigningFormat::Ssh => "ssh",
+    };
+
+    for args in [
+        vec!["config", "gpg.format", format],
+        vec!["config", "user.signingkey", key],
+        vec!["config", "commit.gpgsign", "true"],
+        vec!["config", "tag.gpgsign", "true"],
+    ] {
+        if !run(args, Some(cwd)).status()?.success() {
+            return Err(anyhow!("failed to configure commit signing"));
+        }
+    }
+    Ok(())
+}
+
+/// Return the signing key configured for a repository, if signing is enabled
+pub fn signing_key(cwd: &PathBuf) -> Result<Option<String>> {
+    let enabled = run(vec!["config", "--bool", "commit.gpgsign"], Some(cwd)).output()?;
+    if String::from_utf8_lossy(&enabled.stdout).trim() != "true" {
+        return Ok(None);
+    }
+    let key = run(vec!["config", "user.signingkey"], Some(cwd)).output()?;
+    Ok(Some(String::from_utf8_lossy(&key.stdout).trim().to_string()))
+}
+
+/// Represents the name and email commits are attributed to
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Identity {
+    pub name: String,
+    pub email: String,
+}
+
+impl Identity {
+    /// Attribute a Git command's commits to this identity, overriding any configured one
+    pub fn apply(&self, cmd: &mut Command) {
+        cmd.env("GIT_AUTHOR_NAME", &self.name)
+            .env("GIT_AUTHOR_EMAIL", &self.email)
+            .env("GIT_COMMITTER_NAME", &self.name)
+            .env("GIT_COMMITTER_EMAIL", &self.email);
+    }
+}
+
+/// Set the name and email used for commits in a single repository
+pub fn set_identity(cwd: &PathBuf, identity: &Identity) -> Result<()> {
+    for args in [
+        vec!["config", "user.name", &identity.name],
+        vec!["config", "user.email", &identity.email],
+    ] {
+        if !run(args, Some(cwd)).status()?.success() {
+            return Err(anyhow!("failed to set commit identity"));
+        }
+    }
+    Ok(())
+}
+
+/// Return the name and email commits in a repository will use, if both are configured
+///
+/// This includes the global Git config, so `None` means committing will fail
+pub fn identity(cwd: &PathBuf) -> Result<Option<Identity>> {
+    let get = |key| -> Result<String> {
+        let output = run(vec!["config", key], Some(cwd)).output()?;
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    };
+
+    let (name, email) = (get("user.name")?, get("user.email")?);
+    if name.is_empty() || email.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(Identity { name, email }))
+}
+
+/// Represents something wrong with a project repository, and whether it was fixed
+#[derive(Serialize, Debug)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum RepoProblem {
+    /// A lock file left behind by a Git process that crashed or was killed
+    StaleLock { path: String, fixed: bool },
+    /// A merge, rebase, cherry-pick or revert that never finished
+    InterruptedOperation { operation: String, fixed: bool },
+    /// An asset project.json uses that isn't in the working directory
+    MissingAsset { path: String, fixed: bool },
+    /// A block in project.json referred to a block that doesn't exist
+    DanglingBlock {
+        path: String,
+        message: String,
+        fixed: bool,
+    },
+    /// Git reported missing or corrupt objects, which usually needs a fresh clone
+    CorruptObject { message: String },
+}
+
+/// Detect common repository breakage, fixing what can be fixed safely
+///
+/// Interrupted merges, rebases, cherry-picks and reverts are only reported, unless `abort` is
+/// set, since aborting one throws away conflict resolutions. Old lock files are only reported
+/// too, unless `unlock` is set, since a slow Git process may still be holding one
+#[instrument]
+pub fn fsck_and_repair(cwd: &PathBuf, abort: bool, unlock: bool) -> Result<Vec<RepoProblem>> {
+    let git_dir = cwd.join(".git");
+    let mut problems = vec![];
+
+    // git usually only holds locks for a moment, so anything older is likely from a dead process
+    let stale = std::time::Duration::from_secs(60);
+    for entry in walkdir::WalkDir::new(&git_dir)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.path().extension().is_some_and(|ext| ext == "lock"))
+    {
+        let age = entry
+            .metadata()
+            .ok()
+            .and_then(|m| m.modified().ok())
+            .and_then(|m| m.elapsed().ok());
+        if age.is_some_and(|age| age < stale) {
+            continue;
+        }
+        problems.push(RepoProblem::StaleLock {
+            path: entry.path().to_string_lossy().to_string(),
+            fixed: unlock && std::fs::remove_file(entry.path()).is_ok(),
+        });
+    }
+
+    for (marker, operation) in [
+        ("rebase-merge", "rebase"),
+        ("rebase-apply", "rebase"),
+        ("MERGE_HEAD", "merge"),
+        ("CHERRY_PICK_HEAD", "cherry-pick"),
+        ("REVERT_HEAD", "revert"),
+    ] {
+        if !git_dir.join(marker).exists() {
+            continue;
+        }
+        let fixed = abort
+            && run(vec![operation, "--abort"], Some(cwd))
+                .status()
+                .is_ok_and(|status| status.success());
+        problems.push(RepoProblem::InterruptedOperation {
+            operation: operation.to_string(),
+            fixed,
+        });
+    }
+
+    if let Ok(project) = std::fs::File::open(cwd.join("project.json")) {
+        if let Ok(project) = serde_json::from_reader(project) {
+            for asset in crate::sb3::get_assets(&project) {
+                if cwd.join(&asset).exists() {
+                    continue;
+                }
+                let fixed = run(vec!["checkout", "HEAD", "--", &asset], Some(cwd))
+                    .status()
+                    .is_ok_and(|status| status.success());
+                problems.push(RepoProblem::MissingAsset { path: asset, fixed });
+            }
+        }
+    }
+
+    if let Ok(contents) = std::fs::read(cwd.join("project.json")) {
+        if let Ok(mut project) = serde_json::from_slice::<serde_json::Value>(&contents) {
+            let repairs = crate::repair::dangling_blocks(&mut project);
+            if !repairs.is_empty() {
+                let fixed = crate::normalize::normalize(&serde_json::to_vec(&project)?)
+                    .and_then(|json| Ok(std::fs::write(cwd.join("project.json"), json)?))
+                    .is_ok();
+                for repair in repairs {
+                    problems.push(RepoProblem::DanglingBlock {
+                        path: repair.path,
+                        message: repair.message,
+                        fixed,
+                    });
+                }
+            }
+        }
+    }
+
+    let fsck = run(vec!["fsck", "--no-dangling", "--no-progress"], Some(cwd)).output()?;
+    for line in String::from_utf8_lossy(&fsck.stdout)
+        .lines()
+        .chain(String::from_utf8_lossy(&fsck.stderr).lines())
+    {
+        if line.starts_with("missing") || line.starts_with("error") || line.contains("corrupt") {
+            problems.push(RepoProblem::CorruptObject {
+                message: line.to_string(),
+            });
+        }
+    }
+
+    Ok(problems)
+}
+
+/// Check if a repository only materializes part of its tree
+pub fn is_sparse(cwd: &PathBuf) -> Result<bool> {
+    let output = run(vec!["config", "--bool", "core.sparseCheckout"], Some(cwd)).output()?;
+    Ok(String::from_utf8_lossy(&output.stdout).trim() == "true")
+}
+
+/// Only keep project.json (or its split files) and repository config files in the working
+/// directory, leaving assets to be checked out with `checkout_paths` when they're needed
+pub fn enable_sparse_checkout(cwd: &PathBuf) -> Result<()> {
+    for args in [
+        vec!["sparse-checkout", "init", "--no-cone"],
+        vec![
+            "sparse-checkout",
+            "set",
+            "--no-cone",
+            "/project.json",
+            "/stage.json",
+            "/sprites/",
+            "/.gitignore",
+            "/.gitattributes",
+        ],
+    ] {
+        let output = run(args, Some(cwd)).output()?;
+        if !output.status.success() {
+            return Err(anyhow!(
+                "failed to enable sparse checkout: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Materialize every file in the repository again
+pub fn disable_sparse_checkout(cwd: &PathBuf) -> Result<()> {
+    if !run(vec!["sparse-checkout", "disable"], Some(cwd))
+        .status()?
+        .success()
+    {
+        return Err(anyhow!("failed to disable sparse checkout"));
+    }
+    Ok(())
+}
+
+/// Add files to a sparse checkout, materializing them if they're tracked
+pub fn checkout_paths(cwd: &PathBuf, paths: &[String]) -> Result<()> {
+    if paths.is_empty() {
+        return Ok(());
+    }
+
+    let patterns = paths.iter().map(|p| format!("/{p}")).collect::<Vec<_>>();
+    let mut args = vec!["sparse-checkout", "add"];
+    args.extend(patterns.iter().map(|p| p.as_str()));
+
+    let output = run(args, Some(cwd)).output()?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "failed to check out assets: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(())
+}
+
+/// Represents a file Git detected as renamed or copied between two revisions
+#[derive(Serialize, Debug, Clone)]
+pub struct Rename {
+    pub from: String,
+    pub to: String,
+    /// How similar the two files are, from 0 to 100
+    pub similarity: u8,
+    pub copy: bool,
+}
+
+/// Detect renamed and copied files between a revision and another revision
+/// (or the working directory if `new` is `None`) using `git diff -M -C`
+///
+/// Files that aren't tracked yet are in the working directory too, like an edited costume saved
+/// under its new MD5. They're added to a copy of the index, so the real one isn't touched
+pub fn renames(cwd: &PathBuf, old: &str, new: Option<&str>) -> Result<Vec<Rename>> {
+    let mut args = vec!["diff", "--name-status", "-M", "-C", old];
+    let output = match new {
+        Some(new) => {
+            args.push(new);
+            run(args, Some(cwd)).output()?
+        }
+        None => {
+            let scratch = TempWorkspace::new("index")?;
+            let index = scratch.path().join("index");
+            // copying the index keeps what Git knows about unchanged files, so adding is quick
+            let _ = std::fs::copy(git_dir(cwd)?.join("index"), &index);
+            let added = run(vec!["add", "--all"], Some(cwd))
+                .env("GIT_INDEX_FILE", &index)
+                .output()?;
+            if !added.status.success() {
+                return Err(anyhow!(
+                    "failed to detect renames: {}",
+                    String::from_utf8_lossy(&added.stderr).trim()
+                ));
+            }
+            args.push("--cached");
+            run(args, Some(cwd))
+                .env("GIT_INDEX_FILE", &index)
+                .output()?
+        }
+    };
+    if !output.status.success() {
+        return Err(anyhow!(
+            "failed to detect renames: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| {
+            // e.g. R087\told.png\tnew.png
+            let mut parts = line.split('\t');
+            let status = parts.next()?;
+            let (kind, similarity) = status.split_at(1);
+            if kind != "R" && kind != "C" {
+                return None;
+            }
+            Some(Rename {
+                from: parts.next()?.to_string(),
+                to: parts.next()?.to_string(),
+                similarity: similarity.parse().unwrap_or(0),
+                copy: kind == "C",
+            })
+        })
+        .collect())
+}
+
+/// Loose objects a repository can collect before `maintenance` packs them
+const LOOSE_OBJECT_THRESHOLD: u64 = 1000;
+
+/// Return the number of loose objects in a repository
+pub fn loose_objects(cwd: &PathBuf) -> Result<u64> {
+    let output = run(vec!["count-objects", "-v"], Some(cwd)).output()?;
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .find_map(|line| line.strip_prefix("count: "))
+        .and_then(|count| count.trim().parse().ok())
+        .unwrap_or(0))
+}
+
+/// Pack loose objects and prune unreachable ones, if there are enough of them or `force` is set
+///
+/// Returns whether maintenance was run
+#[instrument]
+pub fn maintenance(cwd: &PathBuf, force: bool) -> Result<bool> {
+    if !force && loose_objects(cwd)? < LOOSE_OBJECT_THRESHOLD {
+        return Ok(false);
+    }
+
+    let output = run_uninterrupted(vec!["gc", "--quiet"], Some(cwd)).output()?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "failed to run git gc: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(true)
+}
+
+/// Return how much space a repository's objects take up on disk, in bytes
+pub fn repo_size(cwd: &PathBuf) -> Result<u64> {
+    let output = run(vec!["count-objects", "-v"], Some(cwd)).output()?;
+    // loose objects and packs are counted separately, in KiB
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| {
+            let size = line
+                .strip_prefix("size: ")
+                .or_else(|| line.strip_prefix("size-pack: "))?;
+            size.trim().parse::<u64>().ok()
+        })
+        .sum::<u64>()
+        * 1024)
+}
+
+/// Return every file path in the history of any branch or tag, with how much space its
+/// versions take up on disk
+///
+/// Remote-tracking branches and stashes aren't included, since [`remove_from_history`] doesn't
+/// rewrite them
+pub fn history_files(cwd: &PathBuf) -> Result<HashMap<String, u64>> {
+    let objects = run(
+        vec!["rev-list", "--objects", "--branches", "--tags"],
+        Some(cwd),
+    )
+    .output()?;
+    if !objects.status.success() {
+        return Err(anyhow!("failed to list the repository's objects"));
+    }
+    let mut child = run(
+        vec![
+            "cat-file",
+            "--batch-check=%(objecttype) %(objectsize:disk) %(rest)",
+        ],
+        Some(cwd),
+    )
+    .stdin(Stdio::piped())
+    .spawn()?;
+    let mut stdin = child
+        .stdin
+        .take()
+        .ok_or(anyhow!("could not receive stdin"))?;
+    std::thread::spawn(move || stdin.write_all(&objects.stdout));
+    let output = child.wait_with_output()?;
+
+    let mut files = HashMap::new();
+    for line in String::from_utf8_lossy(&output.stdout).lines() {
+        let mut fields = line.splitn(3, ' ');
+        let (Some("blob"), Some(size), Some(path)) = (fields.next(), fields.next(), fields.next())
+        else {
+            continue;
+        };
+        *files.entry(path.to_string()).or_default() += size.parse::<u64>().unwrap_or(0);
+    }
+    Ok(files)
+}
+
+/// Return the files at the tip of every branch and tag
+pub fn files_at_refs(cwd: &PathBuf) -> Result<HashSet<String>> {
+    let refs = run(
+        vec![
+            "for-each-ref",
+            "--format=%(objectname)",
+            "refs/heads",
+            "refs/tags",
+        ],
+        Some(cwd),
+    )
+    .output()?;
+    let mut files = HashSet::new();
+    for object in String::from_utf8_lossy(&refs.stdout).lines() {
+        let tree = run(vec!["ls-tree", "-r", "--name-only", object], Some(cwd)).output()?;
+        if !tree.status.success() {
+            return Err(anyhow!("failed to list the files in {object}"));
+        }
+        let tree = String::from_utf8_lossy(&tree.stdout);
+        files.extend(tree.lines().map(str::to_string));
+    }
+    Ok(files)
+}
+
+/// Rewrite every local branch and tag without some files, then drop the old objects so the
+/// space they took is freed
+///
+/// Every commit that had one of the files gets a new hash, so other clones of the repository
+/// have to be cloned again, and pushing needs `--force`. Remote-tracking branches and stashes
+/// are left as they are, along with the objects they still use.
+#[instrument(skip(paths))]
+pub fn remove_from_history(cwd: &PathBuf, paths: &[String]) -> Result<()> {
+    let list = git_dir(cwd)?.join("purged-paths");
+    std::fs::write(&list, paths.join("\n"))?;
+    // the list's path goes through the environment, since the filter is run by a shell
+    let filter = "git rm --cached --ignore-unmatch --quiet \
+                  --pathspec-from-file=\"$PIXELPIONEERS_PURGED_PATHS\"";
+    let output = run_uninterrupted(
+        vec![
+            "filter-branch",
+            "--force",
+            "--index-filter",
+            filter,
+            "--tag-name-filter",
+            "cat",
+            "--",
+            "--branches",
+            "--tags",
+        ],
+        Some(cwd),
+    )
+    .env("FILTER_BRANCH_SQUELCH_WARNING", "1")
+    .env("PIXELPIONEERS_PURGED_PATHS", &list)
+    .output()?;
+    let _ = std::fs::remove_file(&list);
+    // revisions resolve to different commits now
+    clear_revision_cache();
+    if !output.status.success() {
+        return Err(anyhow!(
+            "failed to rewrite history: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+
+    // the backups filter-branch makes and the reflog would keep the old objects around
+    let backups = run(
+        vec!["for-each-ref", "--format=%(refname)", "refs/original"],
+        Some(cwd),
+    )
+    .output()?;
+    for backup in String::from_utf8_lossy(&backups.stdout).lines() {
+        run(vec!["update-ref", "-d", backup], Some(cwd)).status()?;
+    }
+    run(vec!["reflog", "expire", "--expire=now", "--all"], Some(cwd)).status()?;
+    let output = run_uninterrupted(vec!["gc", "--prune=now", "--quiet"], Some(cwd)).output()?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "failed to run git gc: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(())
+}
+
+/// A temporary checkout of another revision, removed when dropped
+#[derive(Debug)]
+pub struct Worktree {
+    repo: PathBuf,
+    workspace: TempWorkspace,
+}
+
+impl Worktree {
+    /// Check out a branch or revision into a new temporary directory
+    pub fn new(cwd: &PathBuf, rev: &str) -> Result<Self> {
+        let workspace = TempWorkspace::new("worktree")?;
+        let path = workspace.path().to_string_lossy().to_string();
+
+        let output = run(vec!["worktree", "add", "--detach", &path, rev], Some(cwd)).output()?;
+        if !output.status.success() {
+            return Err(anyhow!(
+                "failed to check out {rev}: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+
+        Ok(Worktree {
+            repo: cwd.clone(),
+            workspace,
+        })
+    }
+
+    /// Directory the revision is checked out in
+    pub fn path(&self) -> &PathBuf {
+        self.workspace.path()
+    }
+}
+
+impl Drop for Worktree {
+    // the workspace removes the directory itself once this has run
+    fn drop(&mut self) {
+        let path = self.path().to_string_lossy().to_string();
+        let removed = run(
+            vec!["worktree", "remove", "--force", &path],
+            Some(&self.repo),
+        )
+        .status()
+        .is_ok_and(|status| status.success());
+
+        if !removed {
+            let _ = run(vec!["worktree", "prune"], Some(&self.repo)).status();
+        }
+    }
+}
+
+/// Return paths that have unresolved merge conflicts
+pub fn conflicted_files(cwd: &PathBuf) -> Result<Vec<String>> {
+    let output = run(vec!["diff", "--name-only", "--diff-filter=U"], Some(cwd)).output()?;
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(|line| line.to_string())
+        .collect())
+}
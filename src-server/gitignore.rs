@@ -0,0 +1,84 @@
+use std::fs;
+use std::path::PathBuf;
+
+use anyhow::Result;
+
+/// Patterns written to every new project's `.gitignore`
+pub const DEFAULT_PATTERNS: [&str; 9] = [
+    // previous project state used for diffing
+    "project.old.json",
+    // temporary sb3 exports
+    "*.sb3",
+    "*.sb3.tmp",
+    // editor caches
+    "*.tmp",
+    "*~",
+    // OS junk
+    ".DS_Store",
+    "Thumbs.db",
+    "desktop.ini",
+    "$RECYCLE.BIN/",
+];
+
+/// Return the patterns in a project's `.gitignore`, skipping comments and blank lines
+pub fn patterns(cwd: &PathBuf) -> Vec<String> {
+    fs::read_to_string(cwd.join(".gitignore"))
+        .unwrap_or_default()
+        .lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty() && !line.starts_with("#"))
+        .map(|line| line.to_string())
+        .collect()
+}
+
+/// Create a project's `.gitignore`, keeping any patterns that were already present
+pub fn write_default(cwd: &PathBuf) -> Result<()> {
+    for pattern in DEFAULT_PATTERNS {
+        add_pattern(cwd, pattern)?;
+    }
+    Ok(())
+}
+
+/// Add a pattern to a project's `.gitignore`
+///
+/// Returns `false` if the pattern was already ignored
+pub fn add_pattern(cwd: &PathBuf, pattern: &str) -> Result<bool> {
+    let pattern = pattern.trim();
+    if pattern.is_empty() || patterns(cwd).iter().any(|p| p == pattern) {
+        return Ok(false);
+    }
+
+    let path = cwd.join(".gitignore");
+    let mut contents = fs::read_to_string(&path).unwrap_or_default();
+    if !contents.is_empty() && !contents.ends_with("\n") {
+        contents += "\n";
+    }
+    contents += pattern;
+    contents += "\n";
+    fs::write(path, contents)?;
+
+    Ok(true)
+}
+
+/// Remove a pattern from a project's `.gitignore`
+///
+/// Returns `false` if the pattern was not found
+pub fn remove_pattern(cwd: &PathBuf, pattern: &str) -> Result<bool> {
+    let pattern = pattern.trim();
+    let path = cwd.join(".gitignore");
+    let Ok(contents) = fs::read_to_string(&path) else {
+        return Ok(false);
+    };
+
+    let kept = contents
+        .lines()
+        .filter(|line| line.trim() != pattern)
+        .collect::<Vec<_>>();
+
+    if kept.len() == contents.lines().count() {
+        return Ok(false);
+    }
+
+    fs::write(path, kept.join("\n") + "\n")?;
+    Ok(true)
+}
@@ -1,6 +1,5 @@
 use std::collections::HashSet;
 use std::fs::{self, File};
-use std::net::TcpStream;
 use std::{
     path::{Path, PathBuf},
     thread::sleep,
@@ -15,17 +14,45 @@ use regex_static::{once_cell::sync::Lazy, Regex};
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
 use tungstenite::{Message, WebSocket};
-use walkdir::WalkDir;
-
-use crate::config::{gh_token, project_config};
+use tracing::{debug, info_span, warn};
+
+use crate::asset_store::{self, AssetStore};
+use crate::backup;
+use crate::bisect;
+use crate::cancel::CancelToken;
+use crate::changelog;
+use crate::config::{self, gh_token, project_config};
+use crate::connection::Connection;
+use crate::diff::conflict;
 use crate::diff::structs::{AssetChange, AssetChangeType, Diff, ScriptChanges};
 use crate::diff::vec_utils::group_costumes;
+use crate::experiment;
 use crate::gh_auth;
 use crate::git;
-
-use crate::sb3::{get_assets, ProjectData};
+use crate::gitignore;
+use crate::history;
+use crate::i18n::message;
+use crate::locks;
+use crate::logging;
+use crate::merge::{self, ConflictRef, MergePlan, Resolution};
+use crate::observer::Quiet;
+use crate::ops::{self, CommitOptions, CommitOutcome};
+use crate::partial::{self, Selection};
+use crate::project::{self, InitOptions, InitOutcome};
+use crate::provenance;
+use crate::release;
+
+use crate::sb2;
+use crate::sb3::{self, get_assets, Project};
+use crate::sessions;
+use crate::shutdown;
+use crate::split;
+use crate::status;
+use crate::thumbnail;
+use crate::timeline;
+use crate::registry;
 use crate::tw_path::turbowarp_path;
-use crate::zipping::{self, extract, zip};
+use crate::validate::Invalid;
 
 macro_rules! here {
     ($error:expr) => {
@@ -66,9 +93,206 @@ enum CmdData<'a> {
         username: String,
         email: String,
     },
+    Remix {
+        url: String,
+        username: String,
+        email: String,
+    },
+    Amend {
+        project_name: String,
+        message: Option<String>,
+    },
+    Reset {
+        project_name: String,
+        revision: String,
+        mode: git::ResetMode,
+        force: bool,
+    },
+    Signing {
+        project_name: String,
+        format: git::SigningFormat,
+        key: Option<String>,
+    },
+    Identity {
+        project_name: String,
+        name: String,
+        email: String,
+    },
+    Roster {
+        project_name: String,
+        roster: Vec<git::Identity>,
+    },
+    Commit {
+        project_name: String,
+        author: Option<git::Identity>,
+        /// Only commit these changes, like whole sprites or single scripts
+        #[serde(default, alias = "sprites", deserialize_with = "partial::selections")]
+        changes: Option<Vec<Selection>>,
+        /// Title for the commit, with the generated message as its body
+        title: Option<String>,
+        /// Display name of whoever's using the editor, attributed from the project's roster
+        user: Option<String>,
+    },
+    Revisions {
+        project_name: String,
+        from: String,
+        to: Option<String>,
+    },
+    Tag {
+        project_name: String,
+        name: String,
+        message: Option<String>,
+    },
+    Bisect {
+        project_name: String,
+        good: Option<String>,
+        bad: Option<String>,
+        predicate: bisect::Predicate,
+    },
+    Toggle {
+        project_name: String,
+        enabled: bool,
+    },
+    Sprite {
+        project_name: String,
+        sprite_name: String,
+        revision: Option<String>,
+    },
+    Revision {
+        project_name: String,
+        revision: String,
+    },
+    IgnorePattern {
+        project_name: String,
+        pattern: String,
+    },
+    AssetSearch {
+        project_name: String,
+        query: String,
+    },
+    RestoreAsset {
+        project_name: String,
+        revision: String,
+        path: String,
+        sprite_name: String,
+    },
+    Asset {
+        project_name: String,
+        path: String,
+    },
+    ResolveConflict {
+        project_name: String,
+        conflict: ConflictRef,
+        resolution: Resolution,
+    },
+    Backup {
+        project_name: String,
+        created: u64,
+    },
+    MergePlan {
+        project_name: String,
+        plan: MergePlan,
+    },
+    Lock {
+        project_name: String,
+        sprite_name: String,
+        /// Why the sprite is being claimed
+        note: Option<String>,
+        /// Release someone else's claim
+        force: bool,
+    },
+    Experiment {
+        project_name: String,
+        /// What the experiment is called, when starting one
+        name: Option<String>,
+        /// Discard an experiment with uncommitted changes
+        force: bool,
+    },
+    Repair {
+        project_name: String,
+        /// Abort an interrupted merge, rebase, cherry-pick or revert, throwing away its progress
+        abort: bool,
+        /// Remove old lock files, once the user has checked no Git process is still running
+        #[serde(default)]
+        unlock: bool,
+    },
+    Purge {
+        project_name: String,
+        /// Only purge assets taking up at least this many bytes
+        min_size: Option<u64>,
+        /// From an earlier listing, to purge what it listed
+        confirmation: Option<String>,
+    },
+    LogLevel {
+        level: String,
+    },
     URL(String),
 }
 
+impl CmdData<'_> {
+    /// The project a command is about, if it's about one
+    fn project_name(&self) -> Option<&str> {
+        match self {
+            CmdData::Project { project_name, .. } => Some(project_name),
+            CmdData::GitDiff { project_name, .. }
+            | CmdData::GitDetails { project_name, .. }
+            | CmdData::Amend { project_name, .. }
+            | CmdData::Reset { project_name, .. }
+            | CmdData::Signing { project_name, .. }
+            | CmdData::Identity { project_name, .. }
+            | CmdData::Roster { project_name, .. }
+            | CmdData::Commit { project_name, .. }
+            | CmdData::Revisions { project_name, .. }
+            | CmdData::Tag { project_name, .. }
+            | CmdData::Bisect { project_name, .. }
+            | CmdData::Toggle { project_name, .. }
+            | CmdData::Sprite { project_name, .. }
+            | CmdData::Revision { project_name, .. }
+            | CmdData::IgnorePattern { project_name, .. }
+            | CmdData::AssetSearch { project_name, .. }
+            | CmdData::RestoreAsset { project_name, .. }
+            | CmdData::Asset { project_name, .. }
+            | CmdData::ResolveConflict { project_name, .. }
+            | CmdData::Backup { project_name, .. }
+            | CmdData::MergePlan { project_name, .. }
+            | CmdData::Lock { project_name, .. }
+            | CmdData::Experiment { project_name, .. }
+            | CmdData::Repair { project_name, .. }
+            | CmdData::Purge { project_name, .. } => Some(project_name),
+            CmdData::ProjectToCreate { .. }
+            | CmdData::Remix { .. }
+            | CmdData::LogLevel { .. }
+            | CmdData::URL(_) => None,
+        }
+    }
+}
+
+/// Zip a project's project.json and assets back into its SB3 file
+fn write_project_sb3(pth: &PathBuf, sb3: &str) -> Result<()> {
+    // Git only updated the split files, so project.json has to catch up
+    if split::is_enabled(pth) {
+        split::restore(pth)?;
+    }
+    let json: Project = serde_json::from_reader(File::open(pth.join("project.json"))?)?;
+    AssetStore::default_store()?.restore(pth, &get_assets(&json))?;
+    sb3::pack(pth, Path::new(sb3))
+}
+
+/// Return the costumes and sounds changed since the last commit, or `None` if the project
+/// hasn't been committed
+fn changed_assets(pth: &PathBuf) -> Result<Option<Vec<AssetChange>>> {
+    let Ok(project_old_json) = fs::read_to_string(pth.join("project.old.json")) else {
+        return Ok(None);
+    };
+    let project_json = fs::read_to_string(pth.join("project.json"))?;
+    let current_diff = Diff::new(serde_json::from_str::<Project>(&project_old_json)?);
+    let new_diff = Diff::new(serde_json::from_str::<Project>(&project_json)?);
+
+    let mut costume_changes = current_diff.assets(&new_diff, Some(AssetChangeType::After))?;
+    costume_changes.extend(new_diff.assets(&current_diff, Some(AssetChangeType::Before))?);
+    Ok(Some(costume_changes))
+}
+
 /// Represents a single command message
 #[derive(Serialize, Deserialize)]
 pub struct Cmd<'a> {
@@ -76,22 +300,29 @@ pub struct Cmd<'a> {
     data: CmdData<'a>,
 }
 
+impl Cmd<'_> {
+    pub fn command(&self) -> &str {
+        self.command
+    }
+
+    pub fn project_name(&self) -> Option<&str> {
+        self.data.project_name()
+    }
+}
+
 /// Command handler for use with WebSocket server
 pub struct CmdHandler<'a> {
-    debug: bool,
-    socket: &'a mut WebSocket<TcpStream>,
+    socket: &'a mut WebSocket<Connection>,
 }
 
 impl CmdHandler<'_> {
-    fn new<'a>(debug: bool, socket: &'a mut WebSocket<TcpStream>) -> CmdHandler<'a> {
-        CmdHandler { debug, socket }
+    fn new(socket: &mut WebSocket<Connection>) -> CmdHandler<'_> {
+        CmdHandler { socket }
     }
 
     fn send_json(&mut self, json: Value) -> Result<()> {
         let message = json.to_string();
-        if self.debug {
-            println!("Sending message: {}", message)
-        }
+        debug!(%message, "sending message");
         self.socket.send(Message::Text(message))?;
         Ok(())
     }
@@ -108,9 +339,10 @@ impl CmdHandler<'_> {
             return self.send_json(json!({}));
         };
 
-        let pth = &project_config().lock().unwrap().project_path(&project_name);
+        let pth = &registry::project_path(&project_name)?;
+        let context = config::settings(Some(pth)).diff.context;
 
-        self.send_json(json!(git::diff(pth, old_content, new_content, 2000)
+        self.send_json(json!(git::diff(pth, old_content, new_content, context)
             .context(here!("failed to get git diff"))?))?;
 
         Ok(())
@@ -123,18 +355,17 @@ impl CmdHandler<'_> {
             return self.send_json(json!({}));
         };
 
-        let mut file_path ;
+        let mut file_path;
+        let title = message("dialog-project-location", &[]);
 
         loop {
             let dialog = if cfg!(target_os = "macos") {
-                FileDialog::new()
-                    .set_title("Select project location")
-                    .set_location("~")
+                FileDialog::new().set_title(&title).set_location("~")
             } else {
                 FileDialog::new()
-                    .set_title("Select project location")
+                    .set_title(&title)
                     .set_location("~")
-                    .add_filter("Scratch Project", &["sb3"])
+                    .add_filter("Scratch Project", &["sb3", "sb2"])
             };
             
             file_path = dialog
@@ -156,9 +387,7 @@ impl CmdHandler<'_> {
 
         let mut config = project_config().lock().unwrap();
 
-        if self.debug {
-            println!("create_project: got project name: {name}")
-        }
+        debug!(%name, "creating project");
 
         let project_path_result = canonicalize(Path::new("projects").join(&name));
         let project_path = match project_path_result {
@@ -171,13 +400,31 @@ impl CmdHandler<'_> {
         };
 
         let Ok(file_path) = canonicalize(&file_path) else {
-            if self.debug {
-                println!("create_project: failed to find project file")
-            }
+            warn!(?file_path, "failed to find project file");
 
             return self.send_json(json!({ "status": "fail" }));
         };
 
+        // Scratch 2 projects are converted once, then versioned as the SB3 next to them
+        let file_path = if file_path.extension().is_some_and(|ext| ext == "sb2") {
+            let sb3_path = file_path.with_extension("sb3");
+            if sb3_path.exists() {
+                warn!(?sb3_path, "not overwriting existing file with converted project");
+                return self.send_json(json!({ "status": "fail" }));
+            }
+            match sb2::convert(&file_path, &sb3_path) {
+                Ok(skipped) if skipped.is_empty() => {}
+                Ok(skipped) => warn!(?skipped, "left out blocks with no Scratch 3 equivalent"),
+                Err(e) => {
+                    warn!("failed to convert Scratch 2 project: {e:#}");
+                    return self.send_json(json!({ "status": "fail" }));
+                }
+            }
+            sb3_path
+        } else {
+            file_path
+        };
+
         match config.projects[&name] {
             Value::Null => {
                 config.projects[name] = json!({
@@ -196,67 +443,183 @@ impl CmdHandler<'_> {
         }
 
         config.save();
+        drop(config);
 
-        let project_to_extract =
-            &config.projects[project_path.file_name().unwrap().to_str().unwrap()];
-        let target_dir = PathBuf::from(Path::new(&project_to_extract["base"].as_str().unwrap()));
+        let identity = git::Identity {
+            name: username,
+            email,
+        };
+        let settings = config::settings(Some(&project_path));
+        let outcome = project::init_project(
+            &file_path,
+            &project_path,
+            InitOptions {
+                identity: (!identity.name.is_empty() && !identity.email.is_empty())
+                    .then_some(&identity)
+                    .or(settings.identity.as_ref()),
+                lfs: false,
+                source: None,
+                message: None,
+            },
+        );
 
-        extract(
-            fs::File::open(Path::new(
-                &project_to_extract["project_file"].as_str().unwrap(),
-            ))
-            .context(here!("failed to open project file to extract"))?,
-            target_dir.clone(),
-        )?;
+        match outcome {
+            Ok(InitOutcome::Created) => {}
+            Ok(InitOutcome::NeedsIdentity) => {
+                return self.send_json(json!({ "status": "needs_info" }));
+            }
+            Err(e) => {
+                warn!("failed to initialize project: {e:#}");
+                return self.send_json(json!({ "status": "fail" }));
+            }
+        }
 
-        let init_repo = git::run(vec!["init"], Some(&project_path))
-            .output()
-            .context(here!(""))?;
-        let response = String::from_utf8(init_repo.stdout)?;
+        let project_name = name.replace("projects/", "");
 
-        if !response.contains("Git repository") {
-            if self.debug {
-                println!("create_project: git init did not state that new repo was made")
-            }
-            return self.send_json(json!({ "status": "fail" }));
+        if let Err(e) = registry::project(&project_name).and_then(|p| p.watch()) {
+            warn!("failed to watch project: {e:#}");
         }
 
-        fs::write(target_dir.join(".gitignore"), "project.old.json")
-            .context(here!("failed to write gitignore"))?;
+        self.send_json(json!({ "project_name": project_name }))
+    }
+
+    /// Start a new project from one shared on the Scratch website, remembering where it came from
+    // ANCHOR[id=remix-project]
+    fn remix_project(&mut self, data: CmdData) -> Result<()> {
+        self.start_from_shared(data, false)
+    }
 
-        if !git::run(vec!["add", "."], Some(&project_path))
-            .status()?
-            .success()
-        {
-            if self.debug {
-                println!("create_project: assets could not be added")
+    /// Start versioning a project shared on the Scratch website, like one made before it was
+    /// tracked, with the import as its first commit
+    // ANCHOR[id=import-project]
+    fn import_project(&mut self, data: CmdData) -> Result<()> {
+        self.start_from_shared(data, true)
+    }
+
+    /// Download a shared project into a new project, committing it as an import or a remix
+    fn start_from_shared(&mut self, data: CmdData, import: bool) -> Result<()> {
+        let CmdData::Remix {
+            url,
+            username,
+            email,
+        } = data
+        else {
+            return self.send_json(json!({}));
+        };
+
+        let Some(project_id) = provenance::parse_id(&url) else {
+            return self.send_json(json!({ "status": "invalid url" }));
+        };
+
+        // downloaded projects are kept next to the server, like cloned ones
+        let partial = PathBuf::from(format!("{project_id}.sb3.tmp"));
+        let source = match provenance::download(project_id, &partial) {
+            Ok(source) => source,
+            Err(e) => {
+                warn!("failed to download project: {e:#}");
+                return self.send_json(json!({ "status": "fail" }));
             }
-            return self.send_json(json!({ "status": "fail" }));
+        };
+
+        // the editor names projects after their file, so the file is named after the title
+        let name = source
+            .title
+            .chars()
+            .map(|c| match c {
+                '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+                c if c.is_control() => '_',
+                c => c,
+            })
+            .collect::<String>();
+        // `.` and `..` would put the project's folder at or above `projects`
+        let name = match name.trim() {
+            name if name.chars().all(|c| c == '.') => project_id.to_string(),
+            name => name.to_string(),
+        };
+
+        let mut config = project_config().lock().unwrap();
+        if !config.projects[&name].is_null() {
+            let _ = fs::remove_file(&partial);
+            return self.send_json(json!({ "status": "exists" }));
         }
 
-        let project_name = name.replace("projects/", "");
+        let file_path = PathBuf::from(format!("{name}.sb3"));
+        fs::rename(&partial, &file_path)?;
+        let file_path = canonicalize(file_path)?;
+        let project_path = Path::new("projects").join(&name);
+        fs::create_dir_all(&project_path)?;
+        let project_path = canonicalize(project_path)?;
 
-        git::run(vec!["config", "user.email", &email], Some(&project_path));
-        git::run(vec!["config", "user.name", &username], Some(&project_path));
+        config.projects[&name] = json!({
+            "base": project_path.to_str().unwrap(),
+            "project_file": file_path.to_str().unwrap()
+        });
+        config.save();
+        drop(config);
 
-        let commit = git::run(vec!["commit", "-m", "Initial commit"], Some(&project_path))
-            .output()
-            .context(here!(""))?;
-        let response = String::from_utf8(commit.stdout)?;
+        let identity = git::Identity {
+            name: username,
+            email,
+        };
+        let settings = config::settings(Some(&project_path));
+        let message = format!("import {} from {}", source.title, source.url());
+        let outcome = project::init_project(
+            &file_path,
+            &project_path,
+            InitOptions {
+                identity: (!identity.name.is_empty() && !identity.email.is_empty())
+                    .then_some(&identity)
+                    .or(settings.identity.as_ref()),
+                lfs: false,
+                source: Some(&source),
+                message: import.then_some(message.as_str()),
+            },
+        );
 
-        if !response.contains("Initial commit") {
-            if self.debug {
-                println!("create_project: initial commit might not have been created")
-            }
-            let stderr = String::from_utf8(commit.stderr)?;
-            if stderr.contains("*** Please tell me who you are.") {
+        match outcome {
+            Ok(InitOutcome::Created) => {}
+            Ok(InitOutcome::NeedsIdentity) => {
                 return self.send_json(json!({ "status": "needs_info" }));
             }
+            Err(e) => {
+                warn!("failed to initialize project: {e:#}");
+                return self.send_json(json!({ "status": "fail" }));
+            }
+        }
 
-            return self.send_json(json!({ "status": "fail" }));
+        if let Err(e) = registry::project(&name).and_then(|p| p.watch()) {
+            warn!("failed to watch project: {e:#}");
         }
 
-        self.send_json(json!({ "project_name": project_name }))
+        self.send_json(json!({ "project_name": name, "path": file_path, "source": source }))
+    }
+
+    /// Send the shared project a project was started from, if it was
+    // ANCHOR[id=get-source]
+    fn get_source(&mut self, data: CmdData) -> Result<()> {
+        let CmdData::Project { project_name, .. } = data else {
+            return self.send_json(json!({}));
+        };
+
+        let pth = &registry::project_path(&project_name)?;
+        self.send_json(json!({ "source": provenance::read(pth) }))
+    }
+
+    /// Diff a project against the current version of the shared project it was started from
+    // ANCHOR[id=diff-upstream]
+    fn diff_upstream(&mut self, data: CmdData) -> Result<()> {
+        let CmdData::Project { project_name, .. } = data else {
+            return self.send_json(json!({}));
+        };
+
+        let pth = &registry::project_path(&project_name)?;
+        if provenance::read(pth).is_none() {
+            return self.send_json(json!({ "status": "not remixed" }));
+        }
+        let report = ops::diff_upstream(pth, &mut Quiet)
+            .context(here!("failed to diff against original"))?;
+
+        self.send_json(json!({ "status": "success", "report": report }))
     }
 
     /// Update a project with new username, email, and repository remote URL
@@ -272,7 +635,7 @@ impl CmdHandler<'_> {
             return self.send_json(json!({}));
         };
 
-        let pth = &project_config().lock().unwrap().project_path(&project_name);
+        let pth = &registry::project_path(&project_name)?;
         let mut success = true;
 
         let mut config_user = git::run(vec!["config", "user.name", &username], Some(&pth));
@@ -318,7 +681,7 @@ impl CmdHandler<'_> {
             return self.send_json(json!({}));
         };
 
-        let pth = &project_config().lock().unwrap().project_path(&project_name);
+        let pth = &registry::project_path(&project_name)?;
 
         let config_user = String::from_utf8(
             git::run(vec!["config", "user.name"], Some(&pth))
@@ -339,9 +702,107 @@ impl CmdHandler<'_> {
                 .stdout,
         )?;
 
-        self.send_json(
-            json!({"username": config_user, "email": config_email, "repository": config_remote}),
-        )
+        self.send_json(json!({
+            "username": config_user,
+            "email": config_email,
+            "repository": config_remote,
+            "signing_key": git::signing_key(pth)?
+        }))
+    }
+
+    /// Set the name and email a project's commits are attributed to
+    // ANCHOR[id=set-identity]
+    fn set_identity(&mut self, data: CmdData) -> Result<()> {
+        let CmdData::Identity {
+            project_name,
+            name,
+            email,
+        } = data
+        else {
+            return self.send_json(json!({}));
+        };
+
+        if name.trim().is_empty() || email.trim().is_empty() {
+            return self.send_json(json!({ "success": false }));
+        }
+
+        let pth = &registry::project_path(&project_name)?;
+        git::set_identity(pth, &git::Identity { name, email })
+            .context(here!("failed to set identity"))?;
+
+        self.send_json(json!({ "success": true }))
+    }
+
+    /// Get the name and email a project's commits will be attributed to, if any
+    // ANCHOR[id=get-identity]
+    fn get_identity(&mut self, data: CmdData) -> Result<()> {
+        let CmdData::Project { project_name, .. } = data else {
+            return self.send_json(json!({}));
+        };
+
+        let pth = &registry::project_path(&project_name)?;
+        match git::identity(pth)? {
+            Some(identity) => self.send_json(json!({ "status": "set", "identity": identity })),
+            None => self.send_json(json!({ "status": "needs_info" })),
+        }
+    }
+
+    /// Set who works on a project, so commits can be attributed to them by the name the editor
+    /// gives
+    // ANCHOR[id=set-roster]
+    fn set_roster(&mut self, data: CmdData) -> Result<()> {
+        let CmdData::Roster {
+            project_name,
+            roster,
+        } = data
+        else {
+            return self.send_json(json!({}));
+        };
+
+        if roster
+            .iter()
+            .any(|person| person.name.trim().is_empty() || person.email.trim().is_empty())
+        {
+            return self.send_json(json!({ "success": false }));
+        }
+
+        project_config()
+            .lock()
+            .unwrap()
+            .set_roster(&project_name, &roster);
+        self.send_json(json!({ "success": true }))
+    }
+
+    /// Get who works on a project
+    // ANCHOR[id=get-roster]
+    fn get_roster(&mut self, data: CmdData) -> Result<()> {
+        let CmdData::Project { project_name, .. } = data else {
+            return self.send_json(json!({}));
+        };
+
+        let roster = project_config().lock().unwrap().roster(&project_name);
+        self.send_json(json!({ "roster": roster }))
+    }
+
+    /// Enable or disable commit signing for a project
+    // ANCHOR[id=set-signing]
+    fn set_signing(&mut self, data: CmdData) -> Result<()> {
+        let CmdData::Signing {
+            project_name,
+            format,
+            key,
+        } = data
+        else {
+            return self.send_json(json!({}));
+        };
+
+        let pth = &registry::project_path(&project_name)?;
+        let key = key.filter(|k| !k.trim().is_empty());
+
+        git::configure_signing(pth, key.as_deref().map(|k| (format, k)))
+            .context(here!("failed to configure signing"))?;
+
+        self.send_json(json!({ "success": true, "key": git::signing_key(pth)? }))
     }
 
     /// Check if a project exists
@@ -387,12 +848,9 @@ impl CmdHandler<'_> {
 
         // TODO: remove sleep?
         sleep(Duration::from_millis(1000));
-        let target_dir = &PathBuf::from(Path::new(&pth));
-        extract(
-            fs::File::open(Path::new(
-                &projects[project_name]["project_file"].as_str().unwrap(),
-            ))?,
-            target_dir.to_path_buf(),
+        sb3::unpack(
+            Path::new(&projects[project_name]["project_file"].as_str().unwrap()),
+            pth,
         )?;
 
         self.send_json(json!({ "status": "success" }))
@@ -409,33 +867,24 @@ impl CmdHandler<'_> {
             return self.send_json(json!({}));
         };
 
-        let pth = &project_config().lock().unwrap().project_path(&project_name);
+        let pth = &registry::project_path(&project_name)?;
 
-        let old_project = serde_json::from_str::<serde_json::Value>(
+        let old_project = serde_json::from_str::<Project>(
             &fs::read_to_string(
                 pth.join(format!("project{}.json", if old { ".old" } else { "" })),
             )?
             .as_str(),
         )?;
-        let targets = old_project["targets"].as_array().unwrap().iter();
+        let targets = &mut old_project.targets.iter();
 
-        let blocks = if sprite_name.unwrap() == "Stage (stage)" {
-            targets
-                .filter(|t| t["isStage"].as_bool().unwrap() == true)
-                .map(|t| t["blocks"].as_object().unwrap())
-                .next()
-        } else {
-            targets
-                .filter(|t| {
-                    t["name"].as_str().unwrap() == sprite_name.unwrap()
-                        && !t["isStage"].as_bool().unwrap()
-                })
-                .map(|t| t["blocks"].as_object().unwrap())
-                .next()
+        let target = match sprite_name {
+            Some("Stage (stage)") => targets.find(|t| t.is_stage),
+            Some(sprite_name) => targets.find(|t| t.name == sprite_name && !t.is_stage),
+            None => None,
         };
 
-        if let Some(bl) = blocks {
-            self.send_json(serde_json::Value::Object(bl.to_owned()))
+        if let Some(target) = target {
+            self.send_json(json!(target.blocks))
         } else {
             self.send_json(json!({}))
         }
@@ -448,42 +897,10 @@ impl CmdHandler<'_> {
             return self.send_json(json!({}));
         };
 
-        let pth = &project_config().lock().unwrap().project_path(&project_name);
-
-        let config_remote = String::from_utf8(
-            git::run(vec!["remote", "get-url", "origin"], Some(&pth))
-                .output()
-                .context(here!(""))?
-                .stdout,
-        )?;
-
-        let mut push = git::run(
-            vec!["push", "--set-upstream", "origin", &git::main_branch(pth)?],
-            Some(pth),
-        );
-
-        if config_remote.contains("github.com") {
-            let mut token = gh_token().lock().unwrap();
-            push.env("GITHUB_TOKEN", token.get());
-        }
+        let pth = &registry::project_path(&project_name)?;
+        let status = ops::push(pth, &mut Quiet).context(here!("failed to push"))?;
 
-        let output = push.output().context(here!(""))?;
-        let stderr = String::from_utf8(output.stderr)?;
-
-        // TODO: these checks might be very brittle
-        self.send_json(
-            if stderr.contains(" ! [") && stderr.contains("git pull ...") {
-                json!({"status": "pull needed"})
-            } else if output.status.success() {
-                if stderr.contains("Everything up-to-date") {
-                    json!({"status": "up to date"})
-                } else {
-                    json!({"status": "success"})
-                }
-            } else {
-                json!({"status": "fail"})
-            },
-        )
+        self.send_json(json!({ "status": status }))
     }
 
     /// Pull new changes from a project's remote URL
@@ -524,14 +941,12 @@ impl CmdHandler<'_> {
                 return self.send_json(json!({"status": "nothing new"}));
             }
 
-            let walkdir = WalkDir::new(&pth);
-            let it = walkdir.into_iter();
-            zipping::zip(
-                &mut it.filter_map(|e| e.ok()),
-                &pth,
-                File::create(Path::new(sb3))?,
-                false,
-            );
+            // new commits can use assets that are only in the remote's store
+            if asset_store::is_used(pth) {
+                asset_store::fetch(pth).context(here!("failed to fetch stored assets"))?;
+            }
+
+            write_project_sb3(pth, sb3)?;
 
             self.send_json(json!({"status": "success"}))
         } else {
@@ -539,117 +954,525 @@ impl CmdHandler<'_> {
 
             if stderr.contains("unrelated histories") {
                 self.send_json(json!({"status": "unrelated histories"}))
+            } else if !git::conflicted_files(pth)?.is_empty() {
+                let conflicts = conflict::from_repo(pth).context(here!("failed to read conflicts"))?;
+                self.send_json(json!({"status": "conflict", "conflicts": conflicts}))
             } else {
                 self.send_json(json!({"status": "Error: ".to_owned() + &stderr}))
             }
         }
     }
 
-    /// Commit new changes to a project
-    // ANCHOR[id=commit]
-    fn commit(&mut self, data: CmdData) -> Result<()> {
+    /// List the conflicts in a project's in-progress merge, with both sides of each
+    // ANCHOR[id=list-conflicts]
+    fn list_conflicts(&mut self, data: CmdData) -> Result<()> {
         let CmdData::Project { project_name, .. } = data else {
             return self.send_json(json!({}));
         };
 
-        let pth = &project_config().lock().unwrap().project_path(&project_name);
-
-        let current_diff = Diff::new(&serde_json::from_str::<serde_json::Value>(
-            &fs::read_to_string(pth.join("project.old.json"))?.as_str(),
-        )?);
-        let current_project = serde_json::from_str::<serde_json::Value>(
-            &fs::read_to_string(pth.join("project.json"))?.as_str(),
-        )?;
-
-        let new_diff = Diff::new(&current_project);
-
-        for change in new_diff.assets(&current_diff, None) {
-            let _ = fs::remove_file(pth.join(change.path));
-        }
-
-        // remove all assets that aren't used in the json
-        let project_assets = get_assets(serde_json::from_value(current_project)?);
-        let unused_assets = fs::read_dir(pth)?
-            .map(|res| res.unwrap().path())
-            .filter(|path| {
-                let ext = path.extension();
-                if ext.is_none() {
-                    return false;
-                };
-                let ext = ext.unwrap();
-                ext == "svg" || ext == "png" || ext == "mp3" || ext == "wav"
-            })
-            .filter(|path| {
-                !project_assets.contains(&path.file_name().unwrap().to_str().unwrap().to_string())
-            });
-
-        for asset in unused_assets {
-            let _ = fs::remove_file(asset);
+        let projects = &project_config().lock().unwrap();
+        let pth = &projects.project_path(&project_name);
+        let sb3 = projects.projects[&project_name]["project_file"]
+            .as_str()
+            .context(here!("project has no project file"))?;
+
+        let conflicts = merge::list_conflicts(pth).context(here!("failed to list conflicts"))?;
+        // the editor shows the merged project while conflicts are resolved
+        if !conflicts.is_empty() {
+            write_project_sb3(pth, sb3)?;
         }
+        self.send_json(json!({ "conflicts": conflicts }))
+    }
 
-        if !git::run(vec!["add", "."], Some(&pth)).status()?.success() {
-            return self.send_json(json!({ "message": -1 }));
-        }
+    /// Resolve a conflict in a project's in-progress merge with our side, their side, or
+    /// something else
+    // ANCHOR[id=resolve-conflict]
+    fn resolve_conflict(&mut self, data: CmdData) -> Result<()> {
+        let CmdData::ResolveConflict {
+            project_name,
+            conflict,
+            resolution,
+        } = data
+        else {
+            return self.send_json(json!({}));
+        };
 
-        let commit = git::run(vec!["commit", "-m", "temporary"], Some(pth))
-            .output()
-            .context(here!(""))?;
+        let projects = &project_config().lock().unwrap();
+        let pth = &projects.project_path(&project_name);
+        let sb3 = projects.projects[&project_name]["project_file"]
+            .as_str()
+            .context(here!("project has no project file"))?;
+
+        let conflicts = merge::resolve_conflict(pth, &conflict, resolution)
+            .context(here!("failed to resolve conflict"))?;
+        write_project_sb3(pth, sb3)?;
+        self.send_json(json!({ "status": "success", "conflicts": conflicts }))
+    }
 
-        if !commit.status.success() {
-            let stderr = String::from_utf8(commit.stderr)?;
-            if stderr.contains("git config --global user.email") {
-                return self.send_json(json!({"message": -2 }));
-            }
+    /// Take whole sprites from one side of a project's in-progress merge, resolving every
+    /// conflict in them
+    // ANCHOR[id=plan-merge]
+    fn plan_merge(&mut self, data: CmdData) -> Result<()> {
+        let CmdData::MergePlan { project_name, plan } = data else {
+            return self.send_json(json!({}));
+        };
 
-            // TODO: (?) make this less generic
-            return self.send_json(json!({ "message": -3 }));
-        }
+        let projects = &project_config().lock().unwrap();
+        let pth = &projects.project_path(&project_name);
+        let sb3 = projects.projects[&project_name]["project_file"]
+            .as_str()
+            .context(here!("project has no project file"))?;
 
-        let previous_revision = Diff::from_revision(&pth, "HEAD~1:project.json")?;
-        let commit_message = previous_revision.commits(&pth, &new_diff)?.join(", ");
+        let conflicts = merge::apply_plan(pth, &plan).context(here!("failed to plan merge"))?;
+        write_project_sb3(pth, sb3)?;
+        self.send_json(json!({ "status": "success", "conflicts": conflicts }))
+    }
 
-        let commit =
-            git::run(vec!["commit", "--amend", "-m", &commit_message], Some(&pth)).output()?;
+    /// Show what merging a branch into the current one would change and which conflicts it
+    /// would leave, without merging it
+    // ANCHOR[id=preview-merge]
+    fn preview_merge(&mut self, data: CmdData) -> Result<()> {
+        let CmdData::Revision {
+            project_name,
+            revision,
+        } = data
+        else {
+            return self.send_json(json!({}));
+        };
 
-        if !commit.status.success() {
-            // TODO: make this less generic
-            return self.send_json(json!({ "message": -4 }));
+        let pth = &registry::project_path(&project_name)?;
+        if !git::revision_exists(pth, &revision)? {
+            return self.send_json(json!({ "status": "unknown revision" }));
         }
-
-        self.send_json(json!({ "message": commit_message }))
+        let preview =
+            merge::preview_merge(pth, &revision).context(here!("failed to preview merge"))?;
+
+        self.send_json(json!({
+            "status": "success",
+            "report": preview.report,
+            "conflicts": preview.conflicts,
+        }))
     }
 
-    /// Get a project's commits
-    // ANCHOR[id=get-commits]
-    fn get_commits(&mut self, data: CmdData) -> Result<()> {
+    /// List who has claimed which sprites in a project
+    // ANCHOR[id=get-locks]
+    fn get_locks(&mut self, data: CmdData) -> Result<()> {
+        let CmdData::Project { project_name, .. } = data else {
+            return self.send_json(json!({}));
+        };
+
+        let pth = &registry::project_path(&project_name)?;
+        self.send_json(json!({ "locks": locks::read(pth) }))
+    }
+
+    /// Claim a sprite so teammates are warned before changing it
+    // ANCHOR[id=claim-sprite]
+    fn claim_sprite(&mut self, data: CmdData) -> Result<()> {
+        let CmdData::Lock {
+            project_name,
+            sprite_name,
+            note,
+            ..
+        } = data
+        else {
+            return self.send_json(json!({}));
+        };
+
+        let pth = &registry::project_path(&project_name)?;
+        let identity = locks::identity(pth)?;
+        match locks::claim(pth, &sprite_name, &identity, note.as_deref()) {
+            Ok(lock) => self.send_json(json!({ "status": "success", "lock": lock })),
+            Err(e) => self.send_json(json!({ "status": "claimed", "message": format!("{e:#}") })),
+        }
+    }
+
+    /// Give up a claim on a sprite, or someone else's with `force`
+    // ANCHOR[id=release-sprite]
+    fn release_sprite(&mut self, data: CmdData) -> Result<()> {
+        let CmdData::Lock {
+            project_name,
+            sprite_name,
+            force,
+            ..
+        } = data
+        else {
+            return self.send_json(json!({}));
+        };
+
+        let pth = &registry::project_path(&project_name)?;
+        let identity = locks::identity(pth)?;
+        match locks::release(pth, &sprite_name, &identity, force) {
+            Ok(true) => self.send_json(json!({ "status": "success" })),
+            Ok(false) => self.send_json(json!({ "status": "not claimed" })),
+            Err(e) => self.send_json(json!({ "status": "claimed", "message": format!("{e:#}") })),
+        }
+    }
+
+    /// List a project's snapshots, oldest first
+    // ANCHOR[id=list-backups]
+    fn list_backups(&mut self, data: CmdData) -> Result<()> {
+        let CmdData::Project { project_name, .. } = data else {
+            return self.send_json(json!({}));
+        };
+
+        let pth = project_config().lock().unwrap().project_path(&project_name);
+        let settings = config::settings(Some(&pth)).backup;
+        let backups =
+            backup::list(&settings, &project_name).context(here!("failed to list snapshots"))?;
+        self.send_json(json!({ "backups": backups }))
+    }
+
+    /// Snapshot a project now instead of waiting for the next one
+    // ANCHOR[id=create-backup]
+    fn create_backup(&mut self, data: CmdData) -> Result<()> {
         let CmdData::Project { project_name, .. } = data else {
             return self.send_json(json!({}));
         };
-        let pth = &project_config().lock().unwrap().project_path(&project_name);
-        let format = "--pretty=format:{\"commit\": \"%H\", \"subject\": \"%s\", \"body\": \"%b\", \"author\": {\"name\": \"%aN\", \"email\": \"%aE\", \"date\": \"%aD\"}},";
 
-        let git_log = String::from_utf8(
-            git::run(vec!["log", format], Some(pth))
+        let pth = project_config().lock().unwrap().project_path(&project_name);
+        let settings = config::settings(Some(&pth)).backup;
+        let backup = backup::snapshot(&settings, &project_name, &pth)
+            .context(here!("failed to snapshot project"))?;
+        self.send_json(json!({ "status": "success", "backup": backup }))
+    }
+
+    /// Put a project back the way it was in one of its snapshots, without touching Git
+    // ANCHOR[id=restore-backup]
+    fn restore_backup(&mut self, data: CmdData) -> Result<()> {
+        let CmdData::Backup {
+            project_name,
+            created,
+        } = data
+        else {
+            return self.send_json(json!({}));
+        };
+
+        let projects = &project_config().lock().unwrap();
+        let pth = &projects.project_path(&project_name);
+        let sb3 = projects.projects[&project_name]["project_file"]
+            .as_str()
+            .context(here!("project has no project file"))?;
+
+        let settings = config::settings(Some(pth)).backup;
+        let Some(snapshot) = backup::list(&settings, &project_name)?
+            .into_iter()
+            .find(|b| b.created == created)
+        else {
+            return self.send_json(json!({ "status": "fail", "message": "no such snapshot" }));
+        };
+        backup::restore(&snapshot.path, pth).context(here!("failed to restore snapshot"))?;
+        write_project_sb3(pth, sb3)?;
+        self.send_json(json!({ "status": "success" }))
+    }
+
+    /// Commit new changes to a project
+    // ANCHOR[id=commit]
+    fn commit(&mut self, data: CmdData) -> Result<()> {
+        let (project_name, author, changes, title, user) = match data {
+            CmdData::Project { project_name, .. } => {
+                (project_name.to_string(), None, None, None, None)
+            }
+            CmdData::Commit {
+                project_name,
+                author,
+                changes,
+                title,
+                user,
+            } => (project_name, author, changes, title, user),
+            _ => return self.send_json(json!({})),
+        };
+
+        let (pth, sb3, asset_store, (roster_author, editor)) = {
+            let config = project_config().lock().unwrap();
+            (
+                config.project_path(&project_name),
+                PathBuf::from(config.project_sb3(&project_name)),
+                config.flag(&project_name, "asset_store"),
+                config.attribute(&project_name, user.as_deref()),
+            )
+        };
+
+        // an author given outright takes precedence over the roster
+        let options = CommitOptions {
+            author: author.as_ref().or(roster_author.as_ref()),
+            asset_store,
+            selection: None,
+            title: title.as_deref(),
+            editor: editor.as_deref().filter(|_| author.is_none()),
+            sb3: Some(&sb3),
+        };
+        let outcome = match &changes {
+            Some(changes) => match ops::diff_report(&pth, &CancelToken::default(), &mut Quiet) {
+                Ok(report) => ops::commit_selection(&pth, &report, changes, options, &mut Quiet),
+                Err(e) => match e.downcast::<Invalid>() {
+                    Ok(Invalid(violations)) => Ok(CommitOutcome::Invalid(violations)),
+                    Err(e) => Err(e),
+                },
+            },
+            None => ops::commit(&pth, options, &mut Quiet),
+        }
+        .context(here!("failed to commit"))?;
+
+        match &outcome {
+            CommitOutcome::Committed(commit_message) => {
+                self.send_json(json!({ "message": commit_message }))
+            }
+            CommitOutcome::Invalid(violations) => {
+                self.send_json(json!({ "message": outcome.code(), "violations": violations }))
+            }
+            // TODO: (?) make these less generic
+            failure => self.send_json(json!({ "message": failure.code() })),
+        }
+    }
+
+    /// Show the commit that committing would make, without committing
+    // ANCHOR[id=preview-commit]
+    fn preview_commit(&mut self, data: CmdData) -> Result<()> {
+        let CmdData::Commit {
+            project_name,
+            author,
+            changes,
+            title,
+            user,
+        } = data
+        else {
+            return self.send_json(json!({}));
+        };
+
+        let pth = &registry::project_path(&project_name)?;
+        let (roster_author, editor) = project_config()
+            .lock()
+            .unwrap()
+            .attribute(&project_name, user.as_deref());
+        let options = CommitOptions {
+            author: author.as_ref().or(roster_author.as_ref()),
+            asset_store: false,
+            selection: changes.as_deref(),
+            title: title.as_deref(),
+            editor: editor.as_deref().filter(|_| author.is_none()),
+            sb3: None,
+        };
+        let preview = changes
+            .as_deref()
+            .map_or(Ok(()), |changes| {
+                let report = ops::diff_report(pth, &CancelToken::default(), &mut Quiet)?;
+                partial::check(changes, &report)
+            })
+            .and_then(|()| ops::preview_commit(pth, options, &mut Quiet));
+
+        match preview {
+            Ok(preview) => self.send_json(json!({ "preview": preview })),
+            Err(e) => match e.downcast::<Invalid>() {
+                Ok(Invalid(violations)) => self.send_json(json!({
+                    "message": CommitOutcome::Invalid(vec![]).code(),
+                    "violations": violations,
+                })),
+                Err(e) => Err(e).context(here!("failed to preview commit")),
+            },
+        }
+    }
+
+    /// Amend the most recent commit with the current changes and an optional new message
+    // ANCHOR[id=amend]
+    fn amend(&mut self, data: CmdData) -> Result<()> {
+        let CmdData::Amend {
+            project_name,
+            message,
+        } = data
+        else {
+            return self.send_json(json!({}));
+        };
+
+        let pth = &registry::project_path(&project_name)?;
+
+        let message = message.filter(|m| !m.trim().is_empty());
+        if let Err(e) = git::amend(pth, message.as_deref()) {
+            warn!("failed to amend: {e:#}");
+            return self.send_json(json!({ "status": "fail" }));
+        }
+
+        let subject = String::from_utf8(
+            git::run(vec!["log", "-1", "--pretty=format:%s"], Some(pth))
                 .output()
                 .context(here!(""))?
                 .stdout,
         )?;
 
-        let binding = if git_log.as_str().matches("\"commit\"").count() > 1 {
-            format!(
-                "[{}]",
-                git_log.replace(" }\n}", " }\n},").as_str()[..git_log.len() - 1].to_string()
+        self.send_json(json!({ "status": "success", "message": subject }))
+    }
+
+    /// Restore a project to a previous point in history, rewriting its SB3 to match
+    // ANCHOR[id=reset]
+    fn reset(&mut self, data: CmdData) -> Result<()> {
+        let CmdData::Reset {
+            project_name,
+            revision,
+            mode,
+            force,
+        } = data
+        else {
+            return self.send_json(json!({}));
+        };
+
+        let config = project_config().lock().unwrap();
+        let pth = &config.project_path(&project_name);
+
+        if !git::revision_exists(pth, &revision)? {
+            return self.send_json(json!({ "status": "unknown revision" }));
+        }
+
+        // a hard reset throws away uncommitted work, so make the frontend ask first
+        if mode == git::ResetMode::Hard && !force && git::is_dirty(pth)? {
+            return self.send_json(json!({ "status": "uncommitted changes" }));
+        }
+
+        git::reset(pth, &revision, mode).context(here!("failed to reset project"))?;
+
+        if mode == git::ResetMode::Hard {
+            write_project_sb3(pth, &config.project_sb3(&project_name))
+                .context(here!("failed to rewrite project file"))?;
+            fs::copy(pth.join("project.json"), pth.join("project.old.json"))?;
+        }
+
+        self.send_json(json!({ "status": "success" }))
+    }
+
+    /// Find which experiment a project is trying out, if any
+    // ANCHOR[id=get-experiment]
+    fn get_experiment(&mut self, data: CmdData) -> Result<()> {
+        let CmdData::Experiment { project_name, .. } = data else {
+            return self.send_json(json!({}));
+        };
+
+        let pth = &registry::project_path(&project_name)?;
+        let experiment = experiment::current(pth).context(here!("failed to find experiment"))?;
+
+        self.send_json(json!({ "status": "success", "experiment": experiment }))
+    }
+
+    /// Try out an idea on a new branch, which can be kept or thrown away later
+    // ANCHOR[id=start-experiment]
+    fn start_experiment(&mut self, data: CmdData) -> Result<()> {
+        let CmdData::Experiment {
+            project_name,
+            name: Some(name),
+            ..
+        } = data
+        else {
+            return self.send_json(json!({}));
+        };
+
+        let pth = &registry::project_path(&project_name)?;
+        match experiment::start(pth, &name) {
+            Ok(experiment) => {
+                self.send_json(json!({ "status": "success", "experiment": experiment }))
+            }
+            Err(e) => self.send_json(json!({ "status": "fail", "message": format!("{e:#}") })),
+        }
+    }
+
+    /// Keep the experiment a project is trying out, merging it back where it started
+    // ANCHOR[id=keep-experiment]
+    fn keep_experiment(&mut self, data: CmdData) -> Result<()> {
+        let CmdData::Experiment { project_name, .. } = data else {
+            return self.send_json(json!({}));
+        };
+
+        // the config is only read here, so other projects aren't held up while Git merges
+        let (pth, sb3) = {
+            let config = project_config().lock().unwrap();
+            (
+                config.project_path(&project_name),
+                config.project_sb3(&project_name),
             )
-        } else {
-            format!("[{git_log}]")
         };
-        let log_output = if binding.ends_with(",]") {
-            binding[..binding.len() - 2].to_owned() + "]"
-        } else {
-            binding
+        let pth = &pth;
+        if git::is_dirty(pth)? {
+            return self.send_json(json!({ "status": "uncommitted changes" }));
+        }
+        let outcome = experiment::keep(pth).context(here!("failed to keep experiment"))?;
+
+        if let experiment::KeepOutcome::Kept { .. } = outcome {
+            write_project_sb3(pth, &sb3)
+                .context(here!("failed to rewrite project file"))?;
+            fs::copy(pth.join("project.json"), pth.join("project.old.json"))?;
+        }
+        self.send_json(json!(outcome))
+    }
+
+    /// Throw away the experiment a project is trying out, going back to how it was before
+    // ANCHOR[id=discard-experiment]
+    fn discard_experiment(&mut self, data: CmdData) -> Result<()> {
+        let CmdData::Experiment {
+            project_name,
+            force,
+            ..
+        } = data
+        else {
+            return self.send_json(json!({}));
+        };
+
+        let (pth, sb3) = {
+            let config = project_config().lock().unwrap();
+            (
+                config.project_path(&project_name),
+                config.project_sb3(&project_name),
+            )
+        };
+        let pth = &pth;
+        // uncommitted work is thrown away too, so make the frontend ask first
+        if !force && git::is_dirty(pth)? {
+            return self.send_json(json!({ "status": "uncommitted changes" }));
+        }
+        let experiment = experiment::discard(pth).context(here!("failed to discard experiment"))?;
+
+        write_project_sb3(pth, &sb3)
+            .context(here!("failed to rewrite project file"))?;
+        fs::copy(pth.join("project.json"), pth.join("project.old.json"))?;
+
+        self.send_json(json!({ "status": "success", "experiment": experiment }))
+    }
+
+    /// Mark the last commit as where a working session starts
+    // ANCHOR[id=start-session]
+    fn start_session(&mut self, data: CmdData) -> Result<()> {
+        let CmdData::Project { project_name, .. } = data else {
+            return self.send_json(json!({}));
+        };
+
+        let pth = &registry::project_path(&project_name)?;
+        let start = ops::start_session(pth).context(here!("failed to start session"))?;
+
+        self.send_json(json!({ "status": "success", "start": start }))
+    }
+
+    /// Squash every commit since the session started into one
+    // ANCHOR[id=finish-session]
+    fn finish_session(&mut self, data: CmdData) -> Result<()> {
+        let CmdData::Project { project_name, .. } = data else {
+            return self.send_json(json!({}));
+        };
+
+        let pth = &registry::project_path(&project_name)?;
+        match ops::finish_session(pth) {
+            Ok(Some(message)) => self.send_json(json!({ "status": "success", "message": message })),
+            Ok(None) => self.send_json(json!({ "status": "nothing to squash" })),
+            Err(e) => {
+                warn!("failed to finish session: {e:#}");
+                self.send_json(json!({ "status": "fail", "message": e.to_string() }))
+            }
+        }
+    }
+
+    /// Get a project's commits
+    // ANCHOR[id=get-commits]
+    fn get_commits(&mut self, data: CmdData) -> Result<()> {
+        let CmdData::Project { project_name, .. } = data else {
+            return self.send_json(json!({}));
         };
+        let pth = &registry::project_path(&project_name)?;
 
-        self.send_json(serde_json::from_str(&log_output)?)
+        self.send_json(json!(ops::log(pth, None).context(here!("failed to read log"))?))
     }
 
     // ANCHOR[id=get-changed-sprites]
@@ -658,7 +1481,7 @@ impl CmdHandler<'_> {
             return self.send_json(json!({}));
         };
 
-        let pth = &project_config().lock().unwrap().project_path(&project_name);
+        let pth = &registry::project_path(&project_name)?;
 
         let binding = &fs::read_to_string(pth.join("project.old.json"));
         let project_old_json = match binding {
@@ -668,14 +1491,10 @@ impl CmdHandler<'_> {
                     .send_json(json!({ "status": "unzip the project first that should do it" }));
             }
         };
-        let _current_project = serde_json::from_str::<serde_json::Value>(project_old_json)?;
-
-        let current_diff = Diff::new(&_current_project);
-        let _new_project = serde_json::from_str::<serde_json::Value>(
+        let current_diff = Diff::new(serde_json::from_str::<Project>(project_old_json)?);
+        let new_diff = Diff::new(serde_json::from_str::<Project>(
             &fs::read_to_string(pth.join("project.json"))?.as_str(),
-        )?;
-
-        let new_diff = Diff::new(&_new_project);
+        )?);
 
         let mut sprites: Vec<_> = current_diff
             .blocks(&pth, &new_diff)?
@@ -692,8 +1511,8 @@ impl CmdHandler<'_> {
 
         sprites.extend(
             [
-                new_diff.assets(&current_diff, None),
-                current_diff.assets(&new_diff, None),
+                new_diff.assets(&current_diff, None)?,
+                current_diff.assets(&new_diff, None)?,
             ]
             .concat()
             .into_iter()
@@ -707,7 +1526,15 @@ impl CmdHandler<'_> {
             }),
         );
 
-        self.send_json(json!({ "sprites": sprites.iter().collect::<HashSet<_>>() }))
+        let sprites = sprites.into_iter().collect::<HashSet<_>>();
+        // changes to sprites teammates have claimed are likely to conflict
+        let locked = match locks::identity(pth) {
+            Ok(identity) => {
+                locks::conflicts(pth, sprites.iter().map(|(s, _)| s.as_str()), &identity)
+            }
+            Err(_) => vec![],
+        };
+        self.send_json(json!({ "sprites": sprites, "locked": locked }))
     }
 
     /// Set up GitHub authentication for use with any configured project
@@ -768,6 +1595,12 @@ impl CmdHandler<'_> {
         name = &name[1..&name.len() - 1];
         let t_project_dir = &project_dir.join(name);
 
+        if split::is_enabled(t_project_dir) && split::restore(t_project_dir).is_err() {
+            self.send_json(json!({"success": false, "reason": -2}))?;
+            let _ = fs::remove_dir_all(t_project_dir);
+            return Ok(());
+        }
+
         let json_path = &canonicalize(t_project_dir.join("project.json"))?;
 
         if !json_path.exists() {
@@ -776,40 +1609,27 @@ impl CmdHandler<'_> {
             return Ok(());
         }
 
-        let json: ProjectData = serde_json::from_reader(File::open(json_path)?)?;
-
-        let mut assets: Vec<_> = get_assets(json);
-        assets.push("project.json".into());
-
-        let mut assets_to_zip: Vec<_> = vec![];
-
-        // this broke me
-        for p in WalkDir::new(t_project_dir).into_iter() {
-            // if the repo contains all assets, push every dir entry whos path exists among assets
-            let p = p?;
-            let path = p.path();
-            if !path.exists() {
-                self.send_json(json!({"success": false, "reason": -3}))?;
-                let _ = fs::remove_dir_all(project_dir)?;
-            }
-            if assets.iter().any(|x| path.ends_with(x)) {
-                assets_to_zip.push(p);
-            }
+        // projects using the asset store keep their assets out of their branches
+        let asset_store = asset_store::fetch(t_project_dir).unwrap_or(false);
+        if asset_store {
+            let json: Project = serde_json::from_reader(File::open(json_path)?)?;
+            AssetStore::default_store()?.restore(t_project_dir, &get_assets(&json))?;
         }
 
-        zip(
-            &mut assets_to_zip.into_iter(),
-            &PathBuf::from("."),
-            File::create(format!("{name}.sb3"))?,
-            true,
-        );
+        // every asset has to be in the repository to make a working SB3
+        if sb3::pack(t_project_dir, Path::new(&format!("{name}.sb3"))).is_err() {
+            self.send_json(json!({"success": false, "reason": -3}))?;
+            let _ = fs::remove_dir_all(t_project_dir);
+            return Ok(());
+        }
 
         let mut config = project_config().lock().unwrap();
         let project_path = &canonicalize(format!("{name}.sb3"))?;
 
         config.projects[name] = json!({
             "base": &canonicalize(t_project_dir)?,
-            "project_file": project_path
+            "project_file": project_path,
+            "asset_store": asset_store
         });
 
         config.save();
@@ -823,39 +1643,35 @@ impl CmdHandler<'_> {
             return self.send_json(json!({}));
         };
 
-        let pth = &project_config().lock().unwrap().project_path(&project_name);
-
-        let binding = &fs::read_to_string(pth.join("project.old.json"));
-        let project_old_json = match binding {
-            Ok(fh) => fh.as_str(),
-            Err(_) => {
-                return self.send_json(json!({ "status": -2 }));
-            }
+        let pth = &registry::project_path(&project_name)?;
+        let Some(costume_changes) = changed_assets(pth)? else {
+            return self.send_json(json!({ "status": -2 }));
         };
-        let _current_project = serde_json::from_str::<serde_json::Value>(project_old_json)?;
-
-        let current_diff = Diff::new(&_current_project);
-        let _new_project = serde_json::from_str::<serde_json::Value>(
-            &fs::read_to_string(pth.join("project.json"))?.as_str(),
-        )?;
+        // contents are left for `asset-contents`, so only previewed assets are read
+        let costume_changes = group_costumes(costume_changes);
 
-        let new_diff = Diff::new(&_new_project);
+        self.send_json(json!({"status": 0, "data": costume_changes}))
+    }
 
-        let mut costume_changes = current_diff.assets(&new_diff, Some(AssetChangeType::After));
-        let newer_changes = new_diff.assets(&current_diff, Some(AssetChangeType::Before));
+    /// Read a changed costume or sound for a preview
+    // ANCHOR[id=asset-contents]
+    fn asset_contents(&mut self, data: CmdData) -> Result<()> {
+        let CmdData::Asset { project_name, path } = data else {
+            return self.send_json(json!({}));
+        };
 
-        costume_changes.extend(newer_changes);
+        let pth = &registry::project_path(&project_name)?;
+        // only changed assets can be read, rather than any path in the project
+        let contents = changed_assets(pth)?
+            .unwrap_or_default()
+            .into_iter()
+            .find(|change| change.path == path)
+            .and_then(|change| change.contents(pth, "HEAD"));
 
-        for change in &mut costume_changes {
-            if !pth.join(change.path.clone()).exists() {
-                return self.send_json(json!({ "status": -1 }));
-            }
-            change.contents = Some(fs::read(pth.join(change.path.clone()))?.into());
+        match contents {
+            Some(contents) => self.send_json(json!({ "status": 0, "contents": contents })),
+            None => self.send_json(json!({ "status": -1 })),
         }
-
-        let costume_changes = group_costumes(costume_changes);
-
-        self.send_json(json!({"status": 0, "data": costume_changes}))
     }
 
     // ANCHOR[id=repo-status]
@@ -864,7 +1680,7 @@ impl CmdHandler<'_> {
             return self.send_json(json!({}));
         };
 
-        let pth = &project_config().lock().unwrap().project_path(&project_name);
+        let pth = &registry::project_path(&project_name)?;
 
         let status = String::from_utf8(git::run(vec!["status"], Some(pth)).output()?.stdout)?;
 
@@ -883,44 +1699,930 @@ impl CmdHandler<'_> {
         }
     }
 
-    fn uninstall(&mut self) -> Result<()> {
-        fs::remove_file(
-            turbowarp_path()
-                .context(here!("failed to get turbowarp path"))?
-                .join("userscript.js"),
-        )?;
+    /// Track a project's binary assets with Git LFS
+    // ANCHOR[id=setup-lfs]
+    fn setup_lfs(&mut self, data: CmdData) -> Result<()> {
+        let CmdData::Project { project_name, .. } = data else {
+            return self.send_json(json!({}));
+        };
 
-        self.send_json(json!({}))
-    }
-}
+        let mut config = project_config().lock().unwrap();
+        let pth = &config.project_path(&project_name);
 
-pub fn handle_command(msg: Cmd, socket: &mut WebSocket<TcpStream>, debug: bool) -> Result<()> {
-    let mut handler = CmdHandler::new(debug, socket);
+        if !git::setup_lfs(pth).context(here!("failed to set up git lfs"))? {
+            return self.send_json(json!({"status": "lfs not installed"}));
+        }
 
-    match msg.command {
-        // static
-        "diff" => handler.get_diff(msg.data),
-        "remote-exists" => handler.remote_exists(msg.data),
-        "exists" => handler.exists(msg.data),
-        "create-project" => handler.create_project(msg.data),
-        "gh-auth" => handler.gh_auth(),
-        "clone-repo" => handler.clone_repo(msg.data),
-        "uninstall" => handler.uninstall(),
+        config.projects[project_name]["lfs"] = json!(true);
+        config.save();
 
-        // project-specific
-        "set-project-details" => handler.set_project_details(msg.data, false),
-        "get-project-details" => handler.get_project_details(msg.data),
-        "unzip" => handler.unzip(msg.data),
-        "commit" => handler.commit(msg.data),
-        "push" => handler.push(msg.data),
-        "pull" => handler.pull(msg.data),
-        "current-project" => handler.get_sprite_scripts(msg.data, false),
-        "previous-project" => handler.get_sprite_scripts(msg.data, true),
-        "get-commits" => handler.get_commits(msg.data),
-        "get-changed-sprites" => handler.get_changed_sprites(msg.data),
-        "get-changed-assets" => handler.get_changed_assets(msg.data),
-        "repo-status" => handler.repo_status(msg.data),
+        self.send_json(json!({"status": "success"}))
+    }
 
-        _ => unreachable!(),
+    /// Get the patterns in a project's .gitignore
+    // ANCHOR[id=get-ignore-patterns]
+    fn get_ignore_patterns(&mut self, data: CmdData) -> Result<()> {
+        let CmdData::Project { project_name, .. } = data else {
+            return self.send_json(json!({}));
+        };
+
+        let pth = &registry::project_path(&project_name)?;
+        self.send_json(json!({ "patterns": gitignore::patterns(pth) }))
+    }
+
+    /// Add or remove a pattern from a project's .gitignore
+    // ANCHOR[id=edit-ignore-pattern]
+    fn edit_ignore_pattern(&mut self, data: CmdData, remove: bool) -> Result<()> {
+        let CmdData::IgnorePattern {
+            project_name,
+            pattern,
+        } = data
+        else {
+            return self.send_json(json!({}));
+        };
+
+        let pth = &registry::project_path(&project_name)?;
+
+        let changed = if remove {
+            gitignore::remove_pattern(pth, &pattern)
+        } else {
+            gitignore::add_pattern(pth, &pattern)
+        }
+        .context(here!("failed to update gitignore"))?;
+
+        self.send_json(json!({ "changed": changed, "patterns": gitignore::patterns(pth) }))
+    }
+
+    /// Switch a project between a full and sparse (assets on demand) checkout
+    // ANCHOR[id=set-sparse]
+    fn set_sparse(&mut self, data: CmdData) -> Result<()> {
+        let CmdData::Toggle {
+            project_name,
+            enabled,
+        } = data
+        else {
+            return self.send_json(json!({}));
+        };
+
+        let pth = &registry::project_path(&project_name)?;
+
+        if enabled {
+            git::enable_sparse_checkout(pth)
+        } else {
+            git::disable_sparse_checkout(pth)
+        }
+        .context(here!("failed to change sparse checkout"))?;
+
+        self.send_json(json!({ "sparse": git::is_sparse(pth)? }))
+    }
+
+    /// Get assets Git detected as renamed or copied between two revisions
+    // ANCHOR[id=get-renames]
+    fn get_renames(&mut self, data: CmdData) -> Result<()> {
+        let CmdData::Revisions {
+            project_name,
+            from,
+            to,
+        } = data
+        else {
+            return self.send_json(json!({}));
+        };
+
+        let pth = &registry::project_path(&project_name)?;
+        let renames = git::renames(pth, &from, to.as_deref())
+            .context(here!("failed to detect renames"))?
+            .into_iter()
+            .filter(|r| r.to != "project.json")
+            .collect::<Vec<_>>();
+
+        self.send_json(json!({ "renames": renames }))
+    }
+
+    /// Summarize the changes between two revisions by sprite, for release notes
+    // ANCHOR[id=changelog]
+    fn changelog(&mut self, data: CmdData) -> Result<()> {
+        let CmdData::Revisions {
+            project_name,
+            from,
+            to,
+        } = data
+        else {
+            return self.send_json(json!({}));
+        };
+
+        let pth = &registry::project_path(&project_name)?;
+        let changelog = changelog::changelog(pth, &from, to.as_deref().unwrap_or("HEAD"))
+            .context(here!("failed to generate changelog"))?;
+
+        let labeler = config::settings(Some(pth)).commit.labeler();
+        let markdown = changelog.markdown(&labeler);
+        self.send_json(json!({ "markdown": markdown, "changelog": changelog }))
+    }
+
+    /// Pack a project's loose objects
+    // ANCHOR[id=maintenance]
+    fn maintenance(&mut self, data: CmdData) -> Result<()> {
+        let CmdData::Project { project_name, .. } = data else {
+            return self.send_json(json!({}));
+        };
+
+        let pth = &registry::project_path(&project_name)?;
+        git::maintenance(pth, true).context(here!("failed to run maintenance"))?;
+
+        self.send_json(json!({ "status": "success", "loose_objects": git::loose_objects(pth)? }))
+    }
+
+    /// Compare the current project against another branch, checked out into a temporary worktree
+    // ANCHOR[id=compare-branch]
+    fn compare_branch(&mut self, data: CmdData) -> Result<()> {
+        let CmdData::Revisions {
+            project_name, from, ..
+        } = data
+        else {
+            return self.send_json(json!({}));
+        };
+
+        let pth = &registry::project_path(&project_name)?;
+
+        let Ok(worktree) = git::Worktree::new(pth, &from) else {
+            return self.send_json(json!({ "status": "unknown revision" }));
+        };
+
+        let branch_diff = Diff::new(serde_json::from_str::<Project>(&fs::read_to_string(
+            worktree.path().join("project.json"),
+        )?)?);
+        let current_diff = Diff::new(serde_json::from_str::<Project>(&fs::read_to_string(
+            pth.join("project.json"),
+        )?)?);
+
+        let changes = branch_diff.commits(worktree.path(), &current_diff)?;
+
+        self.send_json(json!({ "status": "success", "changes": changes }))
+    }
+
+    /// Compare the projects on two branches, with everything that differs between them
+    // ANCHOR[id=diff-branches]
+    fn diff_branches(&mut self, data: CmdData) -> Result<()> {
+        let CmdData::Revisions {
+            project_name,
+            from,
+            to,
+        } = data
+        else {
+            return self.send_json(json!({}));
+        };
+
+        let pth = &registry::project_path(&project_name)?;
+        let to = to.as_deref().unwrap_or("HEAD");
+        for rev in [from.as_str(), to] {
+            if !git::revision_exists(pth, rev)? {
+                return self.send_json(json!({ "status": "unknown revision", "revision": rev }));
+            }
+        }
+        let report =
+            Diff::between_refs(pth, &from, to).context(here!("failed to compare branches"))?;
+
+        self.send_json(json!({ "status": "success", "report": report }))
+    }
+
+    /// Keep a project's binary assets in the asset store, on a ref of their own, instead of its
+    /// branches
+    // ANCHOR[id=enable-asset-store]
+    fn enable_asset_store(&mut self, data: CmdData) -> Result<()> {
+        let CmdData::Project { project_name, .. } = data else {
+            return self.send_json(json!({}));
+        };
+
+        let mut config = project_config().lock().unwrap();
+        let pth = &config.project_path(project_name);
+
+        let json: Project = serde_json::from_reader(File::open(pth.join("project.json"))?)?;
+        AssetStore::default_store()?
+            .enable(pth, &get_assets(&json))
+            .context(here!("failed to enable asset store"))?;
+
+        config.projects[project_name]["asset_store"] = json!(true);
+        config.save();
+
+        self.send_json(json!({ "status": "success" }))
+    }
+
+    /// Store a project in its repository as one file per sprite instead of one project.json
+    // ANCHOR[id=enable-split-storage]
+    fn enable_split_storage(&mut self, data: CmdData) -> Result<()> {
+        let CmdData::Project { project_name, .. } = data else {
+            return self.send_json(json!({}));
+        };
+
+        let pth = &registry::project_path(&project_name)?;
+        split::enable(pth).context(here!("failed to enable split storage"))?;
+
+        self.send_json(json!({ "status": "success" }))
+    }
+
+    /// Find the commit where something about a sprite changed
+    // ANCHOR[id=bisect]
+    fn bisect(&mut self, data: CmdData) -> Result<()> {
+        let CmdData::Bisect {
+            project_name,
+            good,
+            bad,
+            predicate,
+        } = data
+        else {
+            return self.send_json(json!({}));
+        };
+
+        let pth = &registry::project_path(&project_name)?;
+        let result = bisect::bisect(
+            pth,
+            good.as_deref(),
+            bad.as_deref().unwrap_or("HEAD"),
+            &predicate,
+        )
+        .context(here!("failed to bisect"))?;
+
+        match result {
+            Some(result) => self.send_json(json!({ "status": "found", "result": result })),
+            None => self.send_json(json!({ "status": "unchanged" })),
+        }
+    }
+
+    /// Check a project's repository for breakage and repair what can be repaired
+    // ANCHOR[id=repair]
+    fn repair(&mut self, data: CmdData) -> Result<()> {
+        let (project_name, abort, unlock) = match data {
+            CmdData::Project { project_name, .. } => (project_name.to_string(), false, false),
+            CmdData::Repair {
+                project_name,
+                abort,
+                unlock,
+            } => (project_name, abort, unlock),
+            _ => return self.send_json(json!({})),
+        };
+
+        let pth = &registry::project_path(&project_name)?;
+        let problems = git::fsck_and_repair(pth, abort, unlock)
+            .context(here!("failed to check repository"))?;
+
+        self.send_json(json!({ "problems": problems }))
+    }
+
+    /// Send a picture of a commit's stage, rendering it first if needed
+    // ANCHOR[id=get-thumbnail]
+    fn get_thumbnail(&mut self, data: CmdData) -> Result<()> {
+        let CmdData::Revision {
+            project_name,
+            revision,
+        } = data
+        else {
+            return self.send_json(json!({}));
+        };
+
+        let pth = &registry::project_path(&project_name)?;
+        match thumbnail::get(pth, &revision) {
+            Ok(png) => self.send_json(json!({ "status": "success", "thumbnail": png })),
+            Err(e) => {
+                warn!("failed to get thumbnail: {e:#}");
+                self.send_json(json!({ "status": "fail" }))
+            }
+        }
+    }
+
+    /// Save a sprite as a SPRITE3 where the user picks, as it is now or at a revision
+    // ANCHOR[id=export-sprite]
+    fn export_sprite(&mut self, data: CmdData) -> Result<()> {
+        let CmdData::Sprite {
+            project_name,
+            sprite_name,
+            revision,
+        } = data
+        else {
+            return self.send_json(json!({}));
+        };
+
+        let Some(sprite3) = FileDialog::new()
+            .set_title(&message("dialog-export-sprite", &[]))
+            .set_filename(&format!("{sprite_name}.sprite3"))
+            .add_filter("Scratch Sprite", &["sprite3"])
+            .show_save_single_file()?
+        else {
+            return self.send_json(json!({ "status": "cancelled" }));
+        };
+
+        let pth = &registry::project_path(&project_name)?;
+        ops::export_sprite(pth, &sprite_name, revision.as_deref(), &sprite3)
+            .context(here!("failed to export sprite"))?;
+
+        self.send_json(json!({ "status": "success", "path": sprite3 }))
+    }
+
+    /// Bring back a sprite as it was at a revision and commit it, rewriting the SB3 to match
+    // ANCHOR[id=restore-sprite]
+    fn restore_sprite(&mut self, data: CmdData) -> Result<()> {
+        let CmdData::Sprite {
+            project_name,
+            sprite_name,
+            revision: Some(revision),
+        } = data
+        else {
+            return self.send_json(json!({}));
+        };
+
+        let config = project_config().lock().unwrap();
+        let pth = &config.project_path(&project_name);
+        if !git::revision_exists(pth, &revision)? {
+            return self.send_json(json!({ "status": "unknown revision" }));
+        }
+
+        let message = ops::restore_sprite(pth, &revision, &sprite_name)
+            .context(here!("failed to restore sprite"))?;
+        write_project_sb3(pth, &config.project_sb3(&project_name))
+            .context(here!("failed to rewrite project file"))?;
+
+        self.send_json(json!({ "status": "success", "message": message }))
+    }
+
+    /// Save the whole project as an SB3 where the user picks, as it was at a revision
+    // ANCHOR[id=export-project]
+    fn export_project(&mut self, data: CmdData) -> Result<()> {
+        let CmdData::Revision {
+            project_name,
+            revision,
+        } = data
+        else {
+            return self.send_json(json!({}));
+        };
+
+        let Some(sb3) = FileDialog::new()
+            .set_title(&message("dialog-export-project", &[]))
+            .set_filename(&format!("{project_name}.sb3"))
+            .add_filter("Scratch Project", &["sb3"])
+            .show_save_single_file()?
+        else {
+            return self.send_json(json!({ "status": "cancelled" }));
+        };
+
+        let pth = &registry::project_path(&project_name)?;
+        ops::export_revision(pth, &revision, &sb3).context(here!("failed to export project"))?;
+
+        self.send_json(json!({ "status": "success", "path": sb3 }))
+    }
+
+    /// Save the uncommitted changes as an HTML page where the user picks, for sharing
+    // ANCHOR[id=export-diff-html]
+    fn export_diff_html(&mut self, data: CmdData) -> Result<()> {
+        let CmdData::Project { project_name, .. } = data else {
+            return self.send_json(json!({}));
+        };
+
+        let Some(html) = FileDialog::new()
+            .set_title(&message("dialog-export-changes", &[]))
+            .set_filename(&format!("{project_name} changes.html"))
+            .add_filter("Web Page", &["html"])
+            .show_save_single_file()?
+        else {
+            return self.send_json(json!({ "status": "cancelled" }));
+        };
+
+        let pth = &registry::project_path(&project_name)?;
+        let title = message("report-title", &[("project", &project_name)]);
+        let page = ops::diff_html(pth, &title).context(here!("failed to export changes"))?;
+        fs::write(&html, page)?;
+
+        self.send_json(json!({ "status": "success", "path": html }))
+    }
+
+    /// Save every commit's changes, sprite by sprite, as CSV or JSON lines where the user picks
+    // ANCHOR[id=export-history]
+    fn export_history(&mut self, data: CmdData) -> Result<()> {
+        let CmdData::Project { project_name, .. } = data else {
+            return self.send_json(json!({}));
+        };
+
+        let Some(dataset) = FileDialog::new()
+            .set_title(&message("dialog-export-history", &[]))
+            .set_filename(&format!("{project_name} history.csv"))
+            .add_filter("CSV", &["csv"])
+            .add_filter("JSON Lines", &["jsonl"])
+            .show_save_single_file()?
+        else {
+            return self.send_json(json!({ "status": "cancelled" }));
+        };
+
+        // anything other than JSON lines is saved as CSV
+        let format = dataset
+            .extension()
+            .and_then(|ext| history::Format::from_extension(&ext.to_string_lossy()))
+            .unwrap_or(history::Format::Csv);
+        let pth = &registry::project_path(&project_name)?;
+        let rows = history::history(pth).context(here!("failed to export history"))?;
+        fs::write(&dataset, history::format(&rows, format)?)?;
+
+        self.send_json(json!({ "status": "success", "path": dataset, "rows": rows.len() }))
+    }
+
+    /// Return the commits that changed a sprite across every branch, as a graph for drawing its
+    /// timeline
+    // ANCHOR[id=sprite-timeline]
+    fn sprite_timeline(&mut self, data: CmdData) -> Result<()> {
+        let CmdData::Sprite {
+            project_name,
+            sprite_name,
+            ..
+        } = data
+        else {
+            return self.send_json(json!({}));
+        };
+
+        let pth = &registry::project_path(&project_name)?;
+        let timeline = timeline::timeline(pth, &sprite_name)
+            .context(here!("failed to build sprite timeline"))?;
+
+        self.send_json(json!({ "status": "success", "timeline": timeline }))
+    }
+
+    /// Sum up the project's history by author, for seeing who did what in a group project
+    // ANCHOR[id=get-contributors]
+    fn get_contributors(&mut self, data: CmdData) -> Result<()> {
+        let CmdData::Project { project_name, .. } = data else {
+            return self.send_json(json!({}));
+        };
+
+        let pth = &registry::project_path(&project_name)?;
+        let rows = history::history(pth).context(here!("failed to read history"))?;
+
+        self.send_json(json!({
+            "status": "success",
+            "contributors": history::contributors(&rows),
+        }))
+    }
+
+    /// Summarize the uncommitted changes as Markdown, for pasting into a pull request
+    // ANCHOR[id=diff-markdown]
+    fn diff_markdown(&mut self, data: CmdData) -> Result<()> {
+        let CmdData::Project { project_name, .. } = data else {
+            return self.send_json(json!({}));
+        };
+
+        let pth = &registry::project_path(&project_name)?;
+        let markdown = ops::diff_markdown(pth).context(here!("failed to summarize changes"))?;
+
+        self.send_json(json!({ "markdown": markdown }))
+    }
+
+    /// Write the added and changed scripts in scratchblocks markup, for posting on the forums
+    // ANCHOR[id=forum-post]
+    fn forum_post(&mut self, data: CmdData) -> Result<()> {
+        let CmdData::Project { project_name, .. } = data else {
+            return self.send_json(json!({}));
+        };
+
+        let pth = &registry::project_path(&project_name)?;
+        let post = ops::forum_post(pth).context(here!("failed to write forum post"))?;
+
+        self.send_json(json!({ "post": post }))
+    }
+
+    /// Return the IDs of the blocks changed in each sprite since the last commit, so the editor
+    /// can highlight them
+    // ANCHOR[id=changed-blocks]
+    fn changed_blocks(&mut self, data: CmdData) -> Result<()> {
+        let CmdData::Project { project_name, .. } = data else {
+            return self.send_json(json!({}));
+        };
+
+        let pth = &registry::project_path(&project_name)?;
+        let sprites = ops::changed_blocks(pth).context(here!("failed to find changed blocks"))?;
+
+        self.send_json(json!({ "status": "success", "sprites": sprites }))
+    }
+
+    /// Add a SPRITE3 the user picks to a project, then write the project back to its SB3
+    // ANCHOR[id=import-sprite]
+    fn import_sprite(&mut self, data: CmdData) -> Result<()> {
+        let CmdData::Project { project_name, .. } = data else {
+            return self.send_json(json!({}));
+        };
+
+        let Some(sprite3) = FileDialog::new()
+            .set_title(&message("dialog-import-sprite", &[]))
+            .add_filter("Scratch Sprite", &["sprite3"])
+            .show_open_single_file()?
+        else {
+            return self.send_json(json!({ "status": "cancelled" }));
+        };
+
+        let projects = &project_config().lock().unwrap();
+        let pth = &projects.project_path(&project_name);
+        let sb3 = projects.projects[&project_name]["project_file"]
+            .as_str()
+            .context(here!("project has no project file"))?;
+
+        let name = ops::import_sprite(pth, &sprite3).context(here!("failed to import sprite"))?;
+        write_project_sb3(pth, sb3)?;
+
+        self.send_json(json!({ "status": "success", "name": name }))
+    }
+
+    /// Save the changes between two revisions as a patch where the user picks, for applying to
+    /// another copy of the project
+    // ANCHOR[id=export-patch]
+    fn export_patch(&mut self, data: CmdData) -> Result<()> {
+        let CmdData::Revisions {
+            project_name,
+            from,
+            to,
+        } = data
+        else {
+            return self.send_json(json!({}));
+        };
+
+        let Some(bundle) = FileDialog::new()
+            .set_title(&message("dialog-export-patch", &[]))
+            .set_filename(&format!("{project_name}.sgpatch"))
+            .add_filter("Project Patch", &["sgpatch"])
+            .show_save_single_file()?
+        else {
+            return self.send_json(json!({ "status": "cancelled" }));
+        };
+
+        let pth = &registry::project_path(&project_name)?;
+        let to = to.as_deref().unwrap_or("HEAD");
+        ops::export_patch(pth, &from, to, &bundle).context(here!("failed to export patch"))?;
+
+        self.send_json(json!({ "status": "success", "path": bundle }))
+    }
+
+    /// Apply a patch the user picks to a project and commit it, then write the project back to
+    /// its SB3
+    // ANCHOR[id=apply-patch]
+    fn apply_patch(&mut self, data: CmdData) -> Result<()> {
+        let CmdData::Project { project_name, .. } = data else {
+            return self.send_json(json!({}));
+        };
+
+        let Some(bundle) = FileDialog::new()
+            .set_title(&message("dialog-apply-patch", &[]))
+            .add_filter("Project Patch", &["sgpatch"])
+            .show_open_single_file()?
+        else {
+            return self.send_json(json!({ "status": "cancelled" }));
+        };
+
+        let projects = &project_config().lock().unwrap();
+        let pth = &projects.project_path(&project_name);
+        let sb3 = projects.projects[&project_name]["project_file"]
+            .as_str()
+            .context(here!("project has no project file"))?;
+
+        let message = ops::apply_patch(pth, &bundle).context(here!("failed to apply patch"))?;
+        write_project_sb3(pth, sb3)?;
+
+        self.send_json(json!({ "status": "success", "message": message }))
+    }
+
+    /// Tag the last commit as a version, saving a build of it (packaged, if a packager is set
+    /// up) and noting where in the tag
+    // ANCHOR[id=create-tag]
+    fn create_tag(&mut self, data: CmdData) -> Result<()> {
+        let CmdData::Tag {
+            project_name,
+            name,
+            message,
+        } = data
+        else {
+            return self.send_json(json!({}));
+        };
+
+        let pth = &registry::project_path(&project_name)?;
+        let release =
+            release::tag(pth, &name, message.as_deref()).context(here!("failed to tag version"))?;
+
+        self.send_json(json!({ "status": "success", "release": release }))
+    }
+
+    /// Release the last commit as a version, tagging and building it with release notes from
+    /// the changelog since the last tag
+    // ANCHOR[id=create-release]
+    fn create_release(&mut self, data: CmdData) -> Result<()> {
+        let CmdData::Tag {
+            project_name,
+            name,
+            message,
+        } = data
+        else {
+            return self.send_json(json!({}));
+        };
+
+        let pth = &registry::project_path(&project_name)?;
+        let release = release::release(pth, &name, message.as_deref())
+            .context(here!("failed to release version"))?;
+
+        self.send_json(json!({ "status": "success", "release": release }))
+    }
+
+    /// List a project's tagged versions and their builds
+    // ANCHOR[id=get-tags]
+    fn get_tags(&mut self, data: CmdData) -> Result<()> {
+        let CmdData::Project { project_name, .. } = data else {
+            return self.send_json(json!({}));
+        };
+
+        let pth = &registry::project_path(&project_name)?;
+        let releases = release::releases(pth).context(here!("failed to list tags"))?;
+
+        self.send_json(json!({ "releases": releases }))
+    }
+
+    /// Search a project's history for deleted costumes and sounds by name or hash
+    // ANCHOR[id=find-deleted-assets]
+    fn find_deleted_assets(&mut self, data: CmdData) -> Result<()> {
+        let CmdData::AssetSearch {
+            project_name,
+            query,
+        } = data
+        else {
+            return self.send_json(json!({}));
+        };
+
+        let pth = &registry::project_path(&project_name)?;
+        let assets = ops::find_deleted_assets(pth, &query)
+            .context(here!("failed to search for deleted assets"))?;
+
+        self.send_json(json!({ "assets": assets }))
+    }
+
+    /// Add a deleted costume or sound back to a sprite, then write the project back to its SB3
+    // ANCHOR[id=restore-asset]
+    fn restore_asset(&mut self, data: CmdData) -> Result<()> {
+        let CmdData::RestoreAsset {
+            project_name,
+            revision,
+            path,
+            sprite_name,
+        } = data
+        else {
+            return self.send_json(json!({}));
+        };
+
+        let config = project_config().lock().unwrap();
+        let pth = &config.project_path(&project_name);
+        let name = ops::restore_asset(pth, &revision, &path, &sprite_name)
+            .context(here!("failed to restore asset"))?;
+        write_project_sb3(pth, &config.project_sb3(&project_name))?;
+
+        self.send_json(json!({ "status": "success", "name": name }))
+    }
+
+    /// Count a project's sprites, scripts, blocks, variables and assets
+    // ANCHOR[id=project-stats]
+    fn project_stats(&mut self, data: CmdData) -> Result<()> {
+        let CmdData::Project { project_name, .. } = data else {
+            return self.send_json(json!({}));
+        };
+
+        let pth = &registry::project_path(&project_name)?;
+        let project: Project = serde_json::from_reader(File::open(pth.join("project.json"))?)?;
+
+        self.send_json(json!(project.stats(pth)))
+    }
+
+    /// Check that a project's assets are all present and match their MD5 hashes
+    // ANCHOR[id=verify-assets]
+    fn verify_assets(&mut self, data: CmdData) -> Result<()> {
+        let CmdData::Project { project_name, .. } = data else {
+            return self.send_json(json!({}));
+        };
+
+        let pth = &registry::project_path(&project_name)?;
+        let problems = ops::verify_assets(pth).context(here!("failed to verify assets"))?;
+
+        self.send_json(json!({ "problems": problems }))
+    }
+
+    /// Delete assets a project no longer uses and commit their removal
+    // ANCHOR[id=clean-unused-assets]
+    fn clean_unused_assets(&mut self, data: CmdData) -> Result<()> {
+        let CmdData::Project { project_name, .. } = data else {
+            return self.send_json(json!({}));
+        };
+
+        let pth = &registry::project_path(&project_name)?;
+        let removed =
+            ops::clean_unused_assets(pth).context(here!("failed to clean unused assets"))?;
+
+        self.send_json(json!({ "status": "success", "removed": removed }))
+    }
+
+    /// List assets only a project's old commits have, and purge them from its history once the
+    /// listing's confirmation is sent back
+    // ANCHOR[id=purge-assets]
+    fn purge_assets(&mut self, data: CmdData) -> Result<()> {
+        let CmdData::Purge {
+            project_name,
+            min_size,
+            confirmation,
+        } = data
+        else {
+            return self.send_json(json!({}));
+        };
+
+        let pth = &registry::project_path(&project_name)?;
+        let purge = ops::purge_assets(pth, min_size.unwrap_or(0), confirmation.as_deref())
+            .context(here!("failed to purge assets"))?;
+
+        self.send_json(json!({ "status": "success", "purge": purge }))
+    }
+
+    /// Find likely mistakes in a project, like scripts that never run
+    // ANCHOR[id=lint]
+    fn lint(&mut self, data: CmdData) -> Result<()> {
+        let CmdData::Project { project_name, .. } = data else {
+            return self.send_json(json!({}));
+        };
+
+        let pth = &registry::project_path(&project_name)?;
+        let issues = ops::lint(pth).context(here!("failed to lint project"))?;
+
+        self.send_json(json!({ "issues": issues }))
+    }
+
+    fn uninstall(&mut self) -> Result<()> {
+        fs::remove_file(
+            turbowarp_path()
+                .context(here!("failed to get turbowarp path"))?
+                .join("userscript.js"),
+        )?;
+
+        self.send_json(json!({}))
+    }
+
+    /// Report server health for the connection indicator
+    // ANCHOR[id=status]
+    fn status(&mut self) -> Result<()> {
+        self.send_json(json!(status::status()))
+    }
+
+    /// Change the log filter without restarting the server
+    fn set_log_level(&mut self, data: CmdData) -> Result<()> {
+        let CmdData::LogLevel { level } = data else {
+            return self.send_json(json!({}));
+        };
+
+        match logging::set_level(&level) {
+            Ok(()) => self.send_json(json!({ "status": "success" })),
+            Err(e) => self.send_json(json!({ "status": "fail", "message": e.to_string() })),
+        }
+    }
+}
+
+pub fn handle_command(msg: Cmd, socket: &mut WebSocket<Connection>, session: u64) -> Result<()> {
+    let _span = info_span!("command", command = msg.command).entered();
+    let mut handler = CmdHandler::new(socket);
+
+    // state-changing commands finish before the server exits
+    let _operation = match msg.command {
+        "create-project" | "clone-repo" | "unzip" | "commit" | "amend" | "reset" | "push"
+        | "pull" | "repair" | "maintenance" | "enable-asset-store" | "set-sparse" | "bisect"
+        | "setup-lfs" | "import-sprite" | "clean-unused-assets" | "purge-assets"
+        | "enable-split-storage" | "remix-project" | "restore-sprite" | "restore-asset"
+        | "start-session" | "finish-session" | "apply-patch" | "import-project" | "create-tag"
+        | "create-release" | "list-conflicts" | "resolve-conflict" | "plan-merge"
+        | "create-backup" | "restore-backup" | "claim-sprite" | "release-sprite"
+        | "start-experiment" | "keep-experiment" | "discard-experiment" => {
+            Some(shutdown::begin_operation()?)
+        }
+        _ => None,
+    };
+
+    // other tabs may have the same project open, so changes to it take turns
+    let changed_project = match msg.command {
+        "unzip" | "commit" | "amend" | "reset" | "pull" | "repair" | "enable-asset-store"
+        | "set-sparse" | "bisect" | "import-sprite" | "clean-unused-assets" | "purge-assets"
+        | "enable-split-storage" | "restore-sprite" | "restore-asset" | "start-session"
+        | "finish-session" | "apply-patch" | "list-conflicts" | "resolve-conflict"
+        | "plan-merge" | "restore-backup" | "claim-sprite" | "release-sprite"
+        | "start-experiment" | "keep-experiment" | "discard-experiment" => {
+            msg.project_name().map(str::to_string)
+        }
+        _ => None,
+    };
+    let project = match (msg.command, msg.project_name()) {
+        (
+            "unzip" | "commit" | "amend" | "reset" | "push" | "pull" | "repair" | "maintenance"
+            | "enable-asset-store" | "set-sparse" | "bisect" | "setup-lfs" | "import-sprite"
+            | "clean-unused-assets" | "purge-assets" | "enable-split-storage"
+            | "restore-sprite" | "restore-asset" | "start-session" | "finish-session"
+            | "apply-patch"
+            | "create-tag" | "create-release" | "list-conflicts" | "resolve-conflict"
+            | "plan-merge" | "create-backup" | "restore-backup" | "claim-sprite"
+            | "release-sprite" | "start-experiment" | "keep-experiment"
+            | "discard-experiment",
+            Some(project_name),
+        ) => Some(registry::project(project_name)?),
+        _ => None,
+    };
+    let _lock = project.as_ref().map(|project| project.operation.lock().unwrap());
+
+    let result = match msg.command {
+        // static
+        "diff" => handler.get_diff(msg.data),
+        "remote-exists" => handler.remote_exists(msg.data),
+        "exists" => handler.exists(msg.data),
+        "create-project" => handler.create_project(msg.data),
+        "gh-auth" => handler.gh_auth(),
+        "clone-repo" => handler.clone_repo(msg.data),
+        "uninstall" => handler.uninstall(),
+        "set-log-level" => handler.set_log_level(msg.data),
+        "status" => handler.status(),
+
+        // project-specific
+        "set-project-details" => handler.set_project_details(msg.data, false),
+        "get-project-details" => handler.get_project_details(msg.data),
+        "set-signing" => handler.set_signing(msg.data),
+        "set-identity" => handler.set_identity(msg.data),
+        "get-identity" => handler.get_identity(msg.data),
+        "set-roster" => handler.set_roster(msg.data),
+        "get-roster" => handler.get_roster(msg.data),
+        "unzip" => handler.unzip(msg.data),
+        "commit" => handler.commit(msg.data),
+        "preview-commit" => handler.preview_commit(msg.data),
+        "amend" => handler.amend(msg.data),
+        "reset" => handler.reset(msg.data),
+        "get-experiment" => handler.get_experiment(msg.data),
+        "start-experiment" => handler.start_experiment(msg.data),
+        "keep-experiment" => handler.keep_experiment(msg.data),
+        "discard-experiment" => handler.discard_experiment(msg.data),
+        "start-session" => handler.start_session(msg.data),
+        "finish-session" => handler.finish_session(msg.data),
+        "push" => handler.push(msg.data),
+        "pull" => handler.pull(msg.data),
+        "list-conflicts" => handler.list_conflicts(msg.data),
+        "resolve-conflict" => handler.resolve_conflict(msg.data),
+        "plan-merge" => handler.plan_merge(msg.data),
+        "preview-merge" => handler.preview_merge(msg.data),
+        "get-locks" => handler.get_locks(msg.data),
+        "claim-sprite" => handler.claim_sprite(msg.data),
+        "release-sprite" => handler.release_sprite(msg.data),
+        "list-backups" => handler.list_backups(msg.data),
+        "create-backup" => handler.create_backup(msg.data),
+        "restore-backup" => handler.restore_backup(msg.data),
+        "current-project" => handler.get_sprite_scripts(msg.data, false),
+        "previous-project" => handler.get_sprite_scripts(msg.data, true),
+        "get-commits" => handler.get_commits(msg.data),
+        "get-changed-sprites" => handler.get_changed_sprites(msg.data),
+        "get-changed-assets" => handler.get_changed_assets(msg.data),
+        "asset-contents" => handler.asset_contents(msg.data),
+        "repo-status" => handler.repo_status(msg.data),
+        "repair" => handler.repair(msg.data),
+        "verify-assets" => handler.verify_assets(msg.data),
+        "project-stats" => handler.project_stats(msg.data),
+        "clean-unused-assets" => handler.clean_unused_assets(msg.data),
+        "purge-assets" => handler.purge_assets(msg.data),
+        "enable-split-storage" => handler.enable_split_storage(msg.data),
+        "export-project" => handler.export_project(msg.data),
+        "export-diff-html" => handler.export_diff_html(msg.data),
+        "export-history" => handler.export_history(msg.data),
+        "sprite-timeline" => handler.sprite_timeline(msg.data),
+        "get-contributors" => handler.get_contributors(msg.data),
+        "diff-markdown" => handler.diff_markdown(msg.data),
+        "forum-post" => handler.forum_post(msg.data),
+        "changed-blocks" => handler.changed_blocks(msg.data),
+        "lint" => handler.lint(msg.data),
+        "remix-project" => handler.remix_project(msg.data),
+        "import-project" => handler.import_project(msg.data),
+        "get-source" => handler.get_source(msg.data),
+        "diff-upstream" => handler.diff_upstream(msg.data),
+        "export-sprite" => handler.export_sprite(msg.data),
+        "restore-sprite" => handler.restore_sprite(msg.data),
+        "find-deleted-assets" => handler.find_deleted_assets(msg.data),
+        "restore-asset" => handler.restore_asset(msg.data),
+        "get-thumbnail" => handler.get_thumbnail(msg.data),
+        "import-sprite" => handler.import_sprite(msg.data),
+        "export-patch" => handler.export_patch(msg.data),
+        "apply-patch" => handler.apply_patch(msg.data),
+        "create-tag" => handler.create_tag(msg.data),
+        "create-release" => handler.create_release(msg.data),
+        "get-tags" => handler.get_tags(msg.data),
+        "maintenance" => handler.maintenance(msg.data),
+        "enable-asset-store" => handler.enable_asset_store(msg.data),
+        "set-sparse" => handler.set_sparse(msg.data),
+        "get-renames" => handler.get_renames(msg.data),
+        "changelog" => handler.changelog(msg.data),
+        "compare-branch" => handler.compare_branch(msg.data),
+        "diff-branches" => handler.diff_branches(msg.data),
+        "bisect" => handler.bisect(msg.data),
+        "setup-lfs" => handler.setup_lfs(msg.data),
+        "get-ignore-patterns" => handler.get_ignore_patterns(msg.data),
+        "add-ignore-pattern" => handler.edit_ignore_pattern(msg.data, false),
+        "remove-ignore-pattern" => handler.edit_ignore_pattern(msg.data, true),
+
+        _ => unreachable!(),
+    };
+
+    if let (Ok(()), Some(project_name)) = (&result, changed_project) {
+        sessions::project_changed(&project_name, msg.command, Some(session));
     }
+    result
 }
@@ -0,0 +1,276 @@
+//! Exporting a project's whole history as a dataset, with a row for each sprite each commit
+//! changed, for analyzing how a project (or a class's projects) grew over time
+//!
+//! Rows are written as CSV for spreadsheets or as JSON lines for scripts. Commits that didn't
+//! change any sprite, like ones only changing settings, still get a row with no sprite, so
+//! every commit shows up. The same rows add up to who did what, for group projects.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::PathBuf;
+
+use anyhow::{anyhow, Result};
+use itertools::Itertools;
+use serde::Serialize;
+
+use crate::diff::label;
+use crate::diff::structs::{AssetChangeType, Diff};
+use crate::observer::Quiet;
+use crate::ops::{self, LogEntry};
+
+/// How an exported history is written
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Csv,
+    /// A JSON object per line
+    JsonLines,
+}
+
+impl Format {
+    /// Pick a format from a file extension, like `csv` or `jsonl`
+    pub fn from_extension(ext: &str) -> Option<Format> {
+        match ext.to_lowercase().as_str() {
+            "csv" => Some(Format::Csv),
+            "jsonl" | "ndjson" => Some(Format::JsonLines),
+            _ => None,
+        }
+    }
+}
+
+/// Represents the changes a commit made to a single sprite
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HistoryRow {
+    pub commit: String,
+    /// Author date, in RFC 2822 format like the rest of the log
+    pub date: String,
+    pub author: String,
+    pub email: String,
+    pub subject: String,
+    /// The changed sprite, where the stage ends with ` (stage)`, or empty if no sprite changed
+    pub sprite: String,
+    pub blocks_added: usize,
+    pub blocks_removed: usize,
+    pub assets_added: usize,
+    pub assets_removed: usize,
+    pub variables_changed: usize,
+}
+
+impl HistoryRow {
+    const HEADER: [&'static str; 11] = [
+        "commit",
+        "date",
+        "author",
+        "email",
+        "subject",
+        "sprite",
+        "blocks_added",
+        "blocks_removed",
+        "assets_added",
+        "assets_removed",
+        "variables_changed",
+    ];
+
+    fn new(entry: &LogEntry, sprite: String) -> Self {
+        HistoryRow {
+            commit: entry.commit.clone(),
+            date: entry.author.date.clone(),
+            author: entry.author.name.clone(),
+            email: entry.author.email.clone(),
+            subject: entry.subject.clone(),
+            sprite,
+            ..Default::default()
+        }
+    }
+
+    fn csv_fields(&self) -> [String; 11] {
+        [
+            self.commit.clone(),
+            self.date.clone(),
+            self.author.clone(),
+            self.email.clone(),
+            self.subject.clone(),
+            self.sprite.clone(),
+            self.blocks_added.to_string(),
+            self.blocks_removed.to_string(),
+            self.assets_added.to_string(),
+            self.assets_removed.to_string(),
+            self.variables_changed.to_string(),
+        ]
+    }
+}
+
+/// Represents everything one person changed across a project's history
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Contributor {
+    /// The name on their latest commit
+    pub name: String,
+    pub email: String,
+    pub commits: usize,
+    /// Sprites they changed, where the stage ends with ` (stage)`
+    pub sprites: BTreeSet<String>,
+    pub blocks_added: usize,
+    pub blocks_removed: usize,
+    pub assets_added: usize,
+    pub assets_removed: usize,
+    pub variables_changed: usize,
+}
+
+impl Contributor {
+    /// Describe what they did on one line, like `Ada <ada@example.com>: 3 commits, ...`
+    pub fn format(&self) -> String {
+        let plural = |count: usize, word: &str| match count {
+            1 => format!("1 {word}"),
+            _ => format!("{count} {word}s"),
+        };
+        let sprites = self
+            .sprites
+            .iter()
+            .map(|sprite| label::name(sprite))
+            .join(", ");
+        let sprites = match sprites.is_empty() {
+            true => plural(0, "sprite"),
+            false => format!("{} ({sprites})", plural(self.sprites.len(), "sprite")),
+        };
+        format!(
+            "{} <{}>: {}, {sprites}, +{} -{} blocks, {} added, {} removed",
+            self.name,
+            self.email,
+            plural(self.commits, "commit"),
+            self.blocks_added,
+            self.blocks_removed,
+            plural(self.assets_added, "asset"),
+            plural(self.assets_removed, "asset"),
+        )
+    }
+}
+
+/// Add up a project's history by author, most commits first
+///
+/// Authors are told apart by email, ignoring case, since names are often typed differently on
+/// different machines.
+pub fn contributors(rows: &[HistoryRow]) -> Vec<Contributor> {
+    let mut authors = BTreeMap::<String, Contributor>::new();
+    let mut commits = BTreeSet::<(String, &str)>::new();
+    for row in rows {
+        let email = row.email.to_lowercase();
+        let author = authors.entry(email.clone()).or_default();
+        // rows are oldest first, so the latest name wins
+        author.name = row.author.clone();
+        author.email = row.email.clone();
+        if commits.insert((email, &row.commit)) {
+            author.commits += 1;
+        }
+        if !row.sprite.is_empty() {
+            author.sprites.insert(row.sprite.clone());
+        }
+        author.blocks_added += row.blocks_added;
+        author.blocks_removed += row.blocks_removed;
+        author.assets_added += row.assets_added;
+        author.assets_removed += row.assets_removed;
+        author.variables_changed += row.variables_changed;
+    }
+
+    let mut contributors = authors.into_values().collect::<Vec<_>>();
+    contributors.sort_by(|a, b| b.commits.cmp(&a.commits).then(a.name.cmp(&b.name)));
+    contributors
+}
+
+/// Diff every commit against its first parent, oldest first
+///
+/// The first commit is diffed against an empty project, so everything in it counts as added
+pub fn history(pth: &PathBuf) -> Result<Vec<HistoryRow>> {
+    let mut entries = ops::log(pth, None)?;
+    if entries.is_empty() {
+        return Err(anyhow!("project has no commits"));
+    }
+    entries.reverse();
+
+    let empty = ops::parse_project(r#"{"targets": []}"#)?;
+    let mut rows = vec![];
+    for entry in &entries {
+        let commit = &entry.commit;
+        let Ok(new) = Diff::from_revision(pth, &format!("{commit}:project.json")) else {
+            // a commit without a readable project has nothing to count
+            rows.push(HistoryRow::new(entry, String::new()));
+            continue;
+        };
+        let old = Diff::from_revision(pth, &format!("{commit}~1:project.json"))
+            .unwrap_or_else(|_| Diff::new(empty.clone()));
+        let report = ops::compare(pth, &old, &new, &mut Quiet)?;
+
+        let mut sprites = BTreeMap::<String, HistoryRow>::new();
+        for script in &report.scripts {
+            let row = row(
+                &mut sprites,
+                entry,
+                label::key(&script.sprite, script.on_stage),
+            );
+            row.blocks_added += script.added;
+            row.blocks_removed += script.removed;
+        }
+        for asset in &report.assets {
+            let row = row(
+                &mut sprites,
+                entry,
+                label::key(&asset.sprite, asset.on_stage),
+            );
+            match asset.kind {
+                Some(AssetChangeType::Before) => row.assets_removed += 1,
+                _ => row.assets_added += 1,
+            }
+        }
+        for variable in &report.variables {
+            row(&mut sprites, entry, variable.sprite.clone()).variables_changed += 1;
+        }
+
+        if sprites.is_empty() {
+            rows.push(HistoryRow::new(entry, String::new()));
+        }
+        rows.extend(sprites.into_values());
+    }
+    Ok(rows)
+}
+
+/// A commit's row for a sprite, adding it if it's the first change to the sprite
+fn row<'a>(
+    sprites: &'a mut BTreeMap<String, HistoryRow>,
+    entry: &LogEntry,
+    sprite: String,
+) -> &'a mut HistoryRow {
+    sprites
+        .entry(sprite.clone())
+        .or_insert_with(|| HistoryRow::new(entry, sprite))
+}
+
+/// Write rows out in a format, with a header row for CSV
+pub fn format(rows: &[HistoryRow], format: Format) -> Result<String> {
+    let mut out = String::new();
+    match format {
+        Format::Csv => {
+            out += &HistoryRow::HEADER.join(",");
+            out.push('\n');
+            for row in rows {
+                let fields = row.csv_fields().map(|field| csv_field(&field));
+                out += &fields.join(",");
+                out.push('\n');
+            }
+        }
+        Format::JsonLines => {
+            for row in rows {
+                out += &serde_json::to_string(row)?;
+                out.push('\n');
+            }
+        }
+    }
+    Ok(out)
+}
+
+/// Quote a CSV field if it has commas, quotes or newlines, doubling any quotes
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
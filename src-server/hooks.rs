@@ -0,0 +1,228 @@
+//! Commands run around commits and pushes, set up in the server's `pixelpioneers.toml`
+//!
+//! Unlike Git hooks, these only run for commits and pushes the server makes, and get what
+//! changed as a [`DiffReport`] in JSON on stdin, so they don't have to read project.json.
+//! Webhooks get the same report after commits and pushes, along with the project and commit.
+//! Like Git hooks, they aren't versioned: a project's own settings file can't set them, or
+//! cloning a project would be enough to run commands on the next commit.
+
+use std::fmt;
+use std::io::{Read, Write};
+use std::path::Path;
+use std::process::{Command, Output, Stdio};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Context, Result};
+use serde_json::json;
+use tracing::{debug, warn};
+
+use crate::config::{self, HookSettings};
+use crate::diff::structs::DiffReport;
+use crate::git;
+
+/// Seconds a webhook has to answer before it's given up on
+pub(crate) const WEBHOOK_TIMEOUT: u64 = 10;
+
+/// How long a hook command can run before it's killed
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// A point in a commit or push that commands can run at
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Hook {
+    /// Before committing, where a failing command stops the commit
+    PreCommit,
+    PostCommit,
+    PostPush,
+}
+
+impl Hook {
+    fn commands<'a>(&self, settings: &'a HookSettings) -> &'a [String] {
+        match self {
+            Hook::PreCommit => &settings.pre_commit,
+            Hook::PostCommit => &settings.post_commit,
+            Hook::PostPush => &settings.post_push,
+        }
+    }
+}
+
+impl fmt::Display for Hook {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Hook::PreCommit => "pre-commit",
+            Hook::PostCommit => "post-commit",
+            Hook::PostPush => "post-push",
+        })
+    }
+}
+
+/// Check if a project has commands or webhooks for a hook, so reports are only made when
+/// they're used
+pub fn is_set(pth: &Path, hook: Hook) -> bool {
+    let settings = config::settings(Some(pth)).hooks;
+    let webhooks = hook != Hook::PreCommit && !settings.webhooks.is_empty();
+    webhooks || !hook.commands(&settings).is_empty()
+}
+
+/// Run a hook's commands one at a time in the project's folder, stopping at the first to fail
+pub fn run(pth: &Path, hook: Hook, report: &DiffReport) -> Result<()> {
+    let settings = config::settings(Some(pth)).hooks;
+    let commands = hook.commands(&settings);
+    if commands.is_empty() {
+        return Ok(());
+    }
+    let report = serde_json::to_vec(report)?;
+
+    for command in commands {
+        debug!(%hook, command, "running hook");
+        let mut cmd = shell(command);
+        cmd.current_dir(pth)
+            .env("PIXELPIONEERS_HOOK", hook.to_string());
+        let output = run_with_input(cmd, report.clone(), COMMAND_TIMEOUT)
+            .with_context(|| format!("failed to run {hook} command `{command}`"))?;
+
+        if !output.status.success() {
+            return Err(anyhow!(
+                "{hook} command `{command}` failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Run a hook's commands and send its webhooks after something already happened, where
+/// failing is only logged
+pub fn run_after(pth: &Path, hook: Hook, report: &DiffReport) {
+    if let Err(e) = run(pth, hook, report) {
+        warn!("{e:#}");
+    }
+    if let Err(e) = send_webhooks(pth, hook, report) {
+        warn!("{e:#}");
+    }
+}
+
+/// Post a hook's report to each webhook in the background, so a slow server doesn't hold up
+/// the commit or push
+///
+/// The payload has the `event` (the hook's name), the `project`, the `commit` HEAD is at, and
+/// the `changes` as a [`DiffReport`]
+fn send_webhooks(pth: &Path, hook: Hook, report: &DiffReport) -> Result<()> {
+    let urls = config::settings(Some(pth)).hooks.webhooks;
+    if urls.is_empty() {
+        return Ok(());
+    }
+    let project = pth.file_name().unwrap_or_default().to_string_lossy();
+    let commit = git::resolve(&pth.to_path_buf(), "HEAD")?;
+    let payload = serde_json::to_vec(&json!({
+        "event": hook.to_string(),
+        "project": project,
+        "commit": commit,
+        "changes": report,
+    }))?;
+
+    thread::spawn(move || {
+        for url in urls {
+            debug!(%hook, url, "sending webhook");
+            let response = minreq::post(&url)
+                .with_header("Content-Type", "application/json")
+                .with_header("User-Agent", "PixelPioneers")
+                .with_header("X-PixelPioneers-Event", hook.to_string())
+                .with_body(payload.clone())
+                .with_timeout(WEBHOOK_TIMEOUT)
+                .send();
+            match response {
+                Ok(response) if (200..300).contains(&response.status_code) => {}
+                Ok(response) => warn!(%hook, url, status = response.status_code, "webhook failed"),
+                Err(e) => warn!(%hook, url, "webhook failed: {e}"),
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Run a command with `input` on its stdin, killing it if it runs longer than `timeout`
+///
+/// The input is written and the output read on their own threads, so a command that prints a
+/// lot before reading all of its input can't leave both sides waiting on a full pipe
+fn run_with_input(mut command: Command, input: Vec<u8>, timeout: Duration) -> Result<Output> {
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let stdin = child.stdin.take();
+    let writer = thread::spawn(move || {
+        // commands don't have to read the input, so a closed stdin isn't an error
+        if let Some(mut stdin) = stdin {
+            let _ = stdin.write_all(&input);
+        }
+    });
+    let stdout = drain(child.stdout.take());
+    let stderr = drain(child.stderr.take());
+
+    let deadline = Instant::now() + timeout;
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if Instant::now() >= deadline {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(anyhow!("timed out after {}s", timeout.as_secs()));
+        }
+        thread::sleep(Duration::from_millis(50));
+    };
+    let _ = writer.join();
+    Ok(Output {
+        status,
+        stdout: stdout.join().unwrap_or_default(),
+        stderr: stderr.join().unwrap_or_default(),
+    })
+}
+
+/// Read a pipe to its end on another thread
+fn drain(pipe: Option<impl Read + Send + 'static>) -> JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut output = vec![];
+        if let Some(mut pipe) = pipe {
+            let _ = pipe.read_to_end(&mut output);
+        }
+        output
+    })
+}
+
+/// Run a command through the system shell, where each `{name}` in it stands for one of `paths`
+///
+/// The paths reach the shell as environment variables rather than as part of the command, so a
+/// project or tag name with quotes, backticks or `$(...)` in it can't run anything.
+pub fn shell_with_paths(command: &str, paths: &[(&str, &Path)]) -> Command {
+    let mut command = command.to_string();
+    let mut vars = vec![];
+    for (name, path) in paths {
+        let var = format!("PIXELPIONEERS_{}", name.to_uppercase());
+        let value = if cfg!(target_os = "windows") {
+            format!("\"%{var}%\"")
+        } else {
+            format!("\"${var}\"")
+        };
+        command = command.replace(&format!("{{{name}}}"), &value);
+        vars.push((var, *path));
+    }
+    let mut shell = shell(&command);
+    shell.envs(vars);
+    shell
+}
+
+/// Run a command through the system shell, like Git runs its hooks
+pub fn shell(command: &str) -> Command {
+    if cfg!(target_os = "windows") {
+        let mut cmd = Command::new("cmd");
+        cmd.args(["/C", command]);
+        cmd
+    } else {
+        let mut sh = Command::new("sh");
+        sh.args(["-c", command]);
+        sh
+    }
+}
@@ -0,0 +1,209 @@
+//! Rendering a diff as a standalone HTML page, for sharing changes with someone who doesn't
+//! have the project, like a teacher
+//!
+//! Everything the page needs is inlined, with assets as data URIs, so it can be emailed or
+//! uploaded as a single file.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::path::PathBuf;
+
+use anyhow::Result;
+
+use crate::asset_store::AssetStore;
+use crate::diff::label;
+use crate::diff::structs::{AssetChangeType, DiffReport};
+use crate::i18n;
+use crate::ops;
+
+const STYLE: &str = "
+body { font-family: system-ui, sans-serif; max-width: 60rem; margin: 2rem auto; color: #222; }
+h1 { font-size: 1.6rem; }
+section { border: 1px solid #ddd; border-radius: 8px; padding: 0 1rem 1rem; margin: 1rem 0; }
+.summary { color: #555; }
+pre { background: #f7f7f9; padding: 0.5rem; border-radius: 4px; overflow-x: auto; }
+.added { background: #e6ffec; color: #116329; }
+.removed { background: #ffebe9; color: #82071e; }
+.input { color: #0969da; }
+.field { color: #8250df; }
+.assets { display: flex; flex-wrap: wrap; gap: 1rem; }
+figure { margin: 0; text-align: center; }
+figure img { max-width: 8rem; max-height: 8rem; border: 2px solid; border-radius: 4px; }
+figure.after img { border-color: #2da44e; }
+figure.before img { border-color: #cf222e; opacity: 0.6; }
+";
+
+/// Escape text for use in HTML
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Color a line of a script's text, with inputs like `(10)` and fields like `[score v]`
+/// colored differently from the blocks they're in
+fn highlight(line: &str) -> String {
+    let mut html = String::new();
+    let mut open = 0;
+    for c in line.chars() {
+        match c {
+            '(' | '[' => {
+                let class = if c == '(' { "input" } else { "field" };
+                let _ = write!(html, "<span class=\"{class}\">{c}");
+                open += 1;
+            }
+            ')' | ']' if open > 0 => {
+                let _ = write!(html, "{c}</span>");
+                open -= 1;
+            }
+            c => html += &escape(&c.to_string()),
+        }
+    }
+    html + &"</span>".repeat(open)
+}
+
+/// Encode bytes as base64, for assets inlined as data URIs
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, byte)| n | ((*byte as u32) << (16 - 8 * i)));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[((n >> (18 - 6 * i)) & 63) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+fn mime_type(ext: &str) -> &'static str {
+    match ext {
+        "svg" => "image/svg+xml",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "wav" => "audio/wav",
+        "mp3" => "audio/mpeg",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Render a report as a page titled `title`, with script diffs from [`ops::script_diffs`]
+pub fn render(
+    pth: &PathBuf,
+    title: &str,
+    report: &DiffReport,
+    script_diffs: &BTreeMap<String, String>,
+) -> Result<String> {
+    let store = AssetStore::default_store()?;
+    let title = escape(title);
+
+    let lang = i18n::current_locale();
+    let mut html = String::new();
+    let _ = write!(
+        html,
+        "<!DOCTYPE html>\n<html lang=\"{lang}\">\n<head>\n<meta charset=\"utf-8\">\n<title>{title}</title>\n\
+         <style>{STYLE}</style>\n</head>\n<body>\n<h1>{title}</h1>\n"
+    );
+    let summary = i18n::message(
+        "report-summary",
+        &[
+            ("changes", &report.size().to_string()),
+            ("sprites", &report.sprites().len().to_string()),
+        ],
+    );
+    let _ = writeln!(html, "<p class=\"summary\">{summary}</p>");
+
+    for sprite in report.sprites() {
+        let _ = writeln!(html, "<section>\n<h2>{}</h2>", escape(&sprite));
+        let is_sprite = |name: &str| label::name(name) == sprite;
+
+        for script in report.scripts.iter().filter(|s| is_sprite(&s.sprite)) {
+            let heading = i18n::message("report-scripts", &[]);
+            let _ = writeln!(html, "<h3>{heading}: {}</h3>", escape(&script.describe()));
+            let diff = script_diffs.get(&sprite).map_or("", String::as_str);
+
+            html += "<pre>";
+            for line in diff.lines() {
+                let class = match line.chars().next() {
+                    Some('+') => " class=\"added\"",
+                    Some('-') => " class=\"removed\"",
+                    _ => "",
+                };
+                let _ = writeln!(html, "<span{class}>{}</span>", highlight(line));
+            }
+            html += "</pre>\n";
+        }
+
+        let assets = report.assets.iter().filter(|a| is_sprite(&a.sprite));
+        let mut figures = String::new();
+        for asset in assets {
+            let (class, label) = match asset.kind {
+                Some(AssetChangeType::Before) => ("before", "report-asset-removed"),
+                _ => ("after", "report-asset-added"),
+            };
+            let name = escape(&format!("{}.{}", asset.name, asset.ext));
+            let label = i18n::message(label, &[("name", &name)]);
+            let src = ops::read_asset(pth, "HEAD", &asset.path, &store)
+                .map(|bytes| format!("data:{};base64,{}", mime_type(&asset.ext), base64(&bytes)))
+                .unwrap_or_default();
+            let preview = match asset.ext.as_str() {
+                "wav" | "mp3" => format!("<audio controls src=\"{src}\"></audio>"),
+                _ => format!("<img src=\"{src}\" alt=\"{name}\">"),
+            };
+            let _ = writeln!(
+                figures,
+                "<figure class=\"{class}\">{preview}<figcaption>{label}</figcaption></figure>"
+            );
+        }
+        if !figures.is_empty() {
+            let heading = i18n::message("report-costumes-and-sounds", &[]);
+            let _ = writeln!(
+                html,
+                "<h3>{heading}</h3>\n<div class=\"assets\">\n{figures}</div>"
+            );
+        }
+
+        let costumes = report.costumes.iter().filter(|c| is_sprite(&c.sprite));
+        let costumes = costumes
+            .map(|costume| format!("<li>{}</li>", escape(&costume.format())))
+            .collect::<String>();
+        if !costumes.is_empty() {
+            let heading = i18n::message("report-costumes", &[]);
+            let _ = writeln!(html, "<h3>{heading}</h3>\n<ul>{costumes}</ul>");
+        }
+
+        let variables = report.variables.iter().filter(|v| is_sprite(&v.sprite));
+        let variables = variables
+            .map(|variable| format!("<li>{}</li>", escape(&variable.format())))
+            .collect::<String>();
+        if !variables.is_empty() {
+            let heading = i18n::message("report-variables", &[]);
+            let _ = writeln!(html, "<h3>{heading}</h3>\n<ul>{variables}</ul>");
+        }
+        html += "</section>\n";
+    }
+
+    if !report.extensions.is_empty() {
+        let extensions = report
+            .extensions
+            .iter()
+            .map(|extension| format!("<li>{}</li>", escape(&extension.format())))
+            .collect::<String>();
+        let heading = i18n::message("report-extensions", &[]);
+        let _ = writeln!(
+            html,
+            "<section>\n<h2>{heading}</h2>\n<ul>{extensions}</ul>\n</section>"
+        );
+    }
+
+    html += "</body>\n</html>\n";
+    Ok(html)
+}
@@ -0,0 +1,461 @@
+//! Plain HTTP endpoints, served on the same port as the WebSocket server
+//!
+//! - `GET /projects/:id/diff`
+//! - `GET /projects/:id/diff.html`, the diff as a standalone page to share
+//! - `POST /projects/:id/commit` with an optional `{"author": {"name": ..., "email": ...}}` body,
+//!   `"changes": [...]` to only commit those changes, as in [`crate::partial::Selection`] or
+//!   by sprite name, and
+//!   `"title": ...` to title the commit, keeping the generated message as its body
+//! - `GET /projects/:id/log?limit=N`
+//! - `POST /projects/:id/push`
+//! - `GET /server`, the address the server ended up on, which needs no token
+//! - `GET /status`, server health and diagnostics
+//! - `GET /metrics`, counters and histograms in Prometheus' text format
+//! - `POST /rpc`, JSON-RPC 2.0 calls as described in [`crate::jsonrpc`]
+//! - `GET /events`, a server-sent event stream of broadcast events, for when WebSockets are
+//!   blocked
+
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::IpAddr;
+use std::sync::mpsc::RecvTimeoutError;
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use serde_json::{json, Value};
+use tracing::{debug, info_span};
+
+use crate::auth;
+use crate::cancel::CancelToken;
+use crate::error::Error;
+use crate::connection::Connection;
+use crate::discovery;
+use crate::events;
+use crate::i18n;
+use crate::jsonrpc;
+use crate::limits::MAX_JSON_SIZE;
+use crate::metrics;
+use crate::observer;
+use crate::ops;
+use crate::origin;
+use crate::partial;
+use crate::protocol::{self, Pages, Request, Response};
+use crate::ratelimit::{self, Expensive};
+use crate::registry;
+use crate::status;
+
+/// How often to write to an idle event stream
+const SSE_KEEPALIVE: Duration = Duration::from_secs(15);
+
+/// Represents a parsed HTTP request
+#[derive(Debug)]
+pub struct HttpRequest {
+    pub method: String,
+    pub path: String,
+    pub query: HashMap<String, String>,
+    /// Header names are lowercase
+    pub headers: HashMap<String, String>,
+    pub body: Vec<u8>,
+}
+
+/// Represents an HTTP response
+#[derive(Debug)]
+pub struct HttpResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl HttpResponse {
+    pub fn json(status: u16, body: Value) -> Self {
+        HttpResponse {
+            status,
+            headers: vec![("Content-Type".into(), "application/json".into())],
+            body: body.to_string().into_bytes(),
+        }
+    }
+
+    fn reason(&self) -> &'static str {
+        match self.status {
+            200 => "OK",
+            204 => "No Content",
+            400 => "Bad Request",
+            401 => "Unauthorized",
+            403 => "Forbidden",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            413 => "Content Too Large",
+            429 => "Too Many Requests",
+            _ => "Internal Server Error",
+        }
+    }
+
+    pub fn write_to(&self, stream: &mut impl Write) -> Result<()> {
+        let mut head = format!("HTTP/1.1 {} {}\r\n", self.status, self.reason());
+        for (name, value) in &self.headers {
+            head += &format!("{name}: {value}\r\n");
+        }
+        head += &format!(
+            "Content-Length: {}\r\nConnection: close\r\n\r\n",
+            self.body.len()
+        );
+        stream.write_all(head.as_bytes())?;
+        stream.write_all(&self.body)?;
+        stream.flush()?;
+        Ok(())
+    }
+}
+
+/// Decode `%XX` escapes and `+` in a URL component
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' => match s.get(i + 1..i + 3).and_then(|h| u8::from_str_radix(h, 16).ok()) {
+                Some(byte) => {
+                    out.push(byte);
+                    i += 3;
+                    continue;
+                }
+                None => out.push(b'%'),
+            },
+            b'+' => out.push(b' '),
+            byte => out.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).to_string()
+}
+
+/// Read a single HTTP request from a stream
+pub fn read_request(stream: &mut impl Read) -> Result<HttpRequest> {
+    let mut reader = BufReader::new(stream);
+
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let mut parts = line.split_whitespace();
+    let method = parts.next().ok_or(anyhow!("missing method"))?.to_string();
+    let target = parts.next().ok_or(anyhow!("missing path"))?.to_string();
+
+    let mut headers = HashMap::new();
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 || line.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            headers.insert(name.trim().to_lowercase(), value.trim().to_string());
+        }
+    }
+
+    let length = headers
+        .get("content-length")
+        .and_then(|l| l.parse::<u64>().ok())
+        .unwrap_or(0);
+    // the body is read before the request is authorized, so anyone could send this
+    if length > MAX_JSON_SIZE {
+        return Err(Error::TooLarge {
+            what: "request body".into(),
+            detail: format!("{length} bytes, the limit is {MAX_JSON_SIZE}"),
+        }
+        .into());
+    }
+    let mut body = vec![0; length as usize];
+    reader.read_exact(&mut body)?;
+
+    let (path, query) = target.split_once('?').unwrap_or((&target, ""));
+    let query = query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (k, v) = pair.split_once('=').unwrap_or((pair, ""));
+            (percent_decode(k), percent_decode(v))
+        })
+        .collect();
+
+    Ok(HttpRequest {
+        method,
+        path: path.to_string(),
+        query,
+        headers,
+        body,
+    })
+}
+
+/// Check if a request head is a WebSocket handshake
+pub fn is_websocket(head: &[u8]) -> bool {
+    String::from_utf8_lossy(head)
+        .to_lowercase()
+        .contains("upgrade: websocket")
+}
+
+/// Turn a route into a typed protocol request
+fn route(request: &HttpRequest) -> Result<Request, HttpResponse> {
+    let segments = request
+        .path
+        .trim_matches('/')
+        .split('/')
+        .map(percent_decode)
+        .collect::<Vec<_>>();
+
+    let segments = segments.iter().map(|s| s.as_str()).collect::<Vec<_>>();
+    let ["projects", project_name, action] = segments[..] else {
+        return Err(HttpResponse::json(404, json!({"error": "not found"})));
+    };
+    let project_name = project_name.to_string();
+
+    let body = if request.body.is_empty() {
+        json!({})
+    } else {
+        serde_json::from_slice::<Value>(&request.body)
+            .map_err(|e| HttpResponse::json(400, json!({"error": e.to_string()})))?
+    };
+
+    match (request.method.as_str(), action) {
+        ("GET", "diff") => Ok(Request::Diff {
+            project_name,
+            page_size: None,
+        }),
+        ("POST", "commit") => Ok(Request::Commit {
+            project_name,
+            author: serde_json::from_value(body["author"].clone()).unwrap_or(None),
+            // a list that can't be read would otherwise commit everything
+            changes: partial::selections(
+                body.get("changes")
+                    .or(body.get("sprites"))
+                    .unwrap_or(&Value::Null),
+            )
+            .map_err(|e| HttpResponse::json(400, json!({"error": e.to_string()})))?,
+            title: body["title"].as_str().map(str::to_string),
+            user: body["user"].as_str().map(str::to_string),
+        }),
+        ("GET", "log") => Ok(Request::Log {
+            project_name,
+            limit: request.query.get("limit").and_then(|l| l.parse().ok()),
+        }),
+        ("POST", "push") => Ok(Request::Push { project_name }),
+        (_, "diff" | "commit" | "log" | "push") => Err(HttpResponse::json(
+            405,
+            json!({"error": "method not allowed"}),
+        )),
+        _ => Err(HttpResponse::json(404, json!({"error": "not found"}))),
+    }
+}
+
+/// Keep a connection open, streaming broadcast events to it as server-sent events
+fn stream_events(mut stream: Connection, origin: Option<String>) -> Result<()> {
+    let events = events::subscribe();
+
+    let mut head = String::from(
+        "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\n",
+    );
+    if let Some(origin) = origin {
+        head += &format!("Access-Control-Allow-Origin: {origin}\r\nVary: Origin\r\n");
+    }
+    head += "\r\n";
+    stream.write_all(head.as_bytes())?;
+    stream.flush()?;
+
+    loop {
+        let message = match events.recv_timeout(SSE_KEEPALIVE) {
+            Ok(event) => format!(
+                "event: {}\ndata: {event}\n\n",
+                event["type"].as_str().unwrap_or("message")
+            ),
+            // comments keep proxies from timing out, and fail once the client has gone
+            Err(RecvTimeoutError::Timeout) => ":\n\n".to_string(),
+            Err(RecvTimeoutError::Disconnected) => return Ok(()),
+        };
+        stream.write_all(message.as_bytes())?;
+        stream.flush()?;
+    }
+}
+
+/// Tell a client it's made too many expensive requests, and when it can try again
+fn rate_limited(wait: Duration) -> HttpResponse {
+    let mut response = HttpResponse::json(429, json!({"error": "too many requests"}));
+    let retry_after = wait.as_secs_f64().ceil() as u64;
+    response
+        .headers
+        .push(("Retry-After".into(), retry_after.to_string()));
+    response
+}
+
+/// Run a routed request, unless the client has made too many expensive ones lately
+fn answer(request: Request, client: Option<IpAddr>) -> Result<HttpResponse> {
+    if let (Some(kind), Some(client)) = (request.cost(), client) {
+        if let Err(wait) = ratelimit::check_http(client, kind) {
+            return Ok(rate_limited(wait));
+        }
+    }
+
+    // HTTP has nowhere to send progress but the event stream
+    let mut progress = observer::progress(|operation, done, total| {
+        events::broadcast(json!({
+            "type": "progress",
+            "operation": operation,
+            "done": done,
+            "total": total,
+        }));
+        Ok(())
+    });
+
+    Ok(
+        match protocol::respond(request, Pages::http(), &CancelToken::new(), &mut progress) {
+            Ok(Response::Error { message, kind }) => {
+                HttpResponse::json(500, json!({"error": message, "kind": kind}))
+            }
+            Ok(Response::Diff { report }) => HttpResponse::json(200, serde_json::to_value(report)?),
+            Ok(response @ Response::Invalid { .. }) => {
+                HttpResponse::json(422, serde_json::to_value(response)?)
+            }
+            Ok(response) => HttpResponse::json(200, serde_json::to_value(response)?),
+            Err(e) => {
+                let kind = Error::kind_of(&e);
+                HttpResponse::json(500, json!({"error": e.to_string(), "kind": kind}))
+            }
+        },
+    )
+}
+
+/// Render a project's diff as a page, which is too big for the typed protocol to return
+fn diff_html(request: &HttpRequest, client: Option<IpAddr>) -> Result<HttpResponse> {
+    let path = request.path.trim_matches('/');
+    let Some(project_name) = path
+        .strip_prefix("projects/")
+        .and_then(|path| path.strip_suffix("/diff.html"))
+        .map(percent_decode)
+    else {
+        return Ok(HttpResponse::json(404, json!({"error": "not found"})));
+    };
+    if let Some(Err(wait)) = client.map(|c| ratelimit::check_http(c, Expensive::Diff)) {
+        return Ok(rate_limited(wait));
+    }
+
+    let page = registry::project_path(&project_name)
+        .and_then(|pth| ops::diff_html(&pth, &format!("Changes to {project_name}")));
+    Ok(match page {
+        Ok(page) => HttpResponse {
+            status: 200,
+            headers: vec![("Content-Type".into(), "text/html; charset=utf-8".into())],
+            body: page.into_bytes(),
+        },
+        Err(e) => HttpResponse::json(500, json!({"error": e.to_string()})),
+    })
+}
+
+/// Answer a single HTTP request on a connection
+pub fn handle_connection(mut stream: Connection) -> Result<()> {
+    let request = match read_request(&mut stream) {
+        Ok(request) => request,
+        Err(e) => {
+            if let Some(e @ Error::TooLarge { .. }) = e.downcast_ref::<Error>() {
+                return HttpResponse::json(413, json!({"error": e.to_string()}))
+                    .write_to(&mut stream);
+            }
+            return Err(e);
+        }
+    };
+    let _span = info_span!("http", method = %request.method, path = %request.path).entered();
+    debug!("received request");
+
+    let authorized = auth::is_authorized(
+        request.query.get("token").map(|t| t.as_str()),
+        request.headers.get("authorization").map(|a| a.as_str()),
+    );
+
+    let client = stream.peer_addr().ok().map(|addr| addr.ip());
+    let origin = request.headers.get("origin").cloned();
+
+    if authorized
+        && origin::is_allowed(origin.as_deref())
+        && request.method == "GET"
+        && request.path.trim_end_matches('/') == "/events"
+    {
+        return stream_events(stream, origin);
+    }
+
+    let mut response = match route(&request) {
+        _ if !origin::is_allowed(origin.as_deref()) => HttpResponse::json(
+            403,
+            json!({"error": origin::rejection(origin.as_deref().unwrap_or_default())}),
+        ),
+        // CORS preflight, which browsers send without credentials
+        _ if request.method == "OPTIONS" => HttpResponse {
+            status: 204,
+            headers: vec![
+                (
+                    "Access-Control-Allow-Methods".into(),
+                    "GET, POST, OPTIONS".into(),
+                ),
+                (
+                    "Access-Control-Allow-Headers".into(),
+                    "Authorization, Content-Type".into(),
+                ),
+            ],
+            body: vec![],
+        },
+        _ if request.method == "GET" && request.path.trim_end_matches('/') == "/server" => {
+            HttpResponse::json(200, json!(discovery::address()))
+        }
+        _ if !authorized => HttpResponse::json(401, json!({"error": "invalid session token"})),
+        _ if request.method == "POST" && request.path.trim_end_matches('/') == "/rpc" => {
+            let answer = jsonrpc::handle(
+                &String::from_utf8_lossy(&request.body),
+                &mut jsonrpc::Context {
+                    limit: &mut |kind| client.map_or(Ok(()), |c| ratelimit::check_http(c, kind)),
+                    // there's no connection to remember what was agreed on
+                    hello: &mut |features, locales| {
+                        let locale = if locales.is_empty() {
+                            i18n::current_locale()
+                        } else {
+                            i18n::negotiate(&locales.join(","))
+                        };
+                        (protocol::negotiate(features), locale)
+                    },
+                    // progress goes out on the event stream, like other HTTP requests
+                    notify: &mut |notification| events::broadcast(notification),
+                    pages: Pages::http(),
+                },
+            );
+            match answer {
+                Some(answer) => HttpResponse::json(200, answer),
+                None => HttpResponse {
+                    status: 204,
+                    headers: vec![],
+                    body: vec![],
+                },
+            }
+        }
+        _ if request.method == "GET" && request.path.trim_end_matches('/') == "/status" => {
+            HttpResponse::json(200, serde_json::to_value(status::status())?)
+        }
+        _ if request.method == "GET" && request.path.trim_end_matches('/') == "/metrics" => {
+            HttpResponse {
+                status: 200,
+                headers: vec![(
+                    "Content-Type".into(),
+                    "text/plain; version=0.0.4".into(),
+                )],
+                body: metrics::render().into_bytes(),
+            }
+        }
+        _ if request.method == "GET" && request.path.ends_with("/diff.html") => {
+            diff_html(&request, client)?
+        }
+        Ok(request) => answer(request, client)?,
+        Err(response) => response,
+    };
+
+    if let Some(origin) = origin.filter(|_| response.status != 403) {
+        response
+            .headers
+            .push(("Access-Control-Allow-Origin".into(), origin));
+        response.headers.push(("Vary".into(), "Origin".into()));
+    }
+
+    debug!(status = response.status, "sending response");
+    response.write_to(&mut stream)
+}
@@ -0,0 +1,251 @@
+//! Translations of generated commit messages and of what the server shows users
+//!
+//! Commit messages are put together from a small vocabulary, so each locale only needs these
+//! words. Everything else, like report headings, errors and prompts, is looked up by ID in the
+//! Fluent-style catalogs in `locales/`, where `{$name}` stands for a value.
+//!
+//! Locales are matched by language, so `es-MX` uses `es`, and anything without a translation
+//! stays in English. Each connection's locale is negotiated from the `Accept-Language` header
+//! of its WebSocket handshake (or the `locales` of a `hello` request), and requests run with it
+//! as the [current locale](with_locale).
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::env;
+use std::sync::OnceLock;
+
+/// The commit vocabulary, in English
+const WORDS: [&str; 9] = [
+    "add",
+    "remove",
+    "modify",
+    "move",
+    "convert",
+    "from",
+    "blocks",
+    "stage",
+    "update project",
+];
+
+/// Each bundled locale's words, in the order of `WORDS`
+const TRANSLATIONS: &[(&str, [&str; 9])] = &[
+    (
+        "de",
+        [
+            "hinzufügen",
+            "entfernen",
+            "ändern",
+            "verschieben",
+            "umwandeln",
+            "von",
+            "Blöcke",
+            "Bühne",
+            "Projekt aktualisieren",
+        ],
+    ),
+    (
+        "es",
+        [
+            "añadir",
+            "eliminar",
+            "modificar",
+            "mover",
+            "convertir",
+            "desde",
+            "bloques",
+            "escenario",
+            "actualizar proyecto",
+        ],
+    ),
+    (
+        "fr",
+        [
+            "ajouter",
+            "supprimer",
+            "modifier",
+            "déplacer",
+            "convertir",
+            "depuis",
+            "blocs",
+            "scène",
+            "mettre à jour le projet",
+        ],
+    ),
+    (
+        "it",
+        [
+            "aggiungere",
+            "rimuovere",
+            "modificare",
+            "spostare",
+            "convertire",
+            "da",
+            "blocchi",
+            "stage",
+            "aggiornare il progetto",
+        ],
+    ),
+    (
+        "nl",
+        [
+            "toevoegen",
+            "verwijderen",
+            "wijzigen",
+            "verplaatsen",
+            "omzetten",
+            "van",
+            "blokken",
+            "speelveld",
+            "project bijwerken",
+        ],
+    ),
+    (
+        "pt",
+        [
+            "adicionar",
+            "remover",
+            "modificar",
+            "mover",
+            "converter",
+            "de",
+            "blocos",
+            "palco",
+            "atualizar projeto",
+        ],
+    ),
+];
+
+/// Each bundled message catalog, where English has every message
+const CATALOGS: &[(&str, &str)] = &[
+    ("en", include_str!("locales/en.ftl")),
+    ("de", include_str!("locales/de.ftl")),
+    ("es", include_str!("locales/es.ftl")),
+    ("fr", include_str!("locales/fr.ftl")),
+    ("pt", include_str!("locales/pt.ftl")),
+];
+
+thread_local! {
+    /// Locale of the request running on this thread
+    static LOCALE: RefCell<String> = const { RefCell::new(String::new()) };
+}
+
+/// Check if a locale has bundled translations, where English always does
+pub fn is_supported(locale: &str) -> bool {
+    let language = language(locale);
+    language == "en"
+        || TRANSLATIONS.iter().any(|(locale, _)| *locale == language)
+        || CATALOGS.iter().any(|(locale, _)| *locale == language)
+}
+
+/// Pick the first supported locale a client asked for, from an `Accept-Language` header like
+/// `es-MX,es;q=0.9,en;q=0.8`, or English if none are supported
+pub fn negotiate(accept_language: &str) -> String {
+    let mut requested = accept_language
+        .split(',')
+        .filter_map(|range| {
+            let mut parts = range.split(';').map(str::trim);
+            let locale = parts.next().filter(|locale| !locale.is_empty())?;
+            let quality = parts
+                .find_map(|part| part.strip_prefix("q="))
+                .map_or(Some(1.0), |q| q.parse::<f32>().ok())?;
+            Some((locale, quality))
+        })
+        .collect::<Vec<_>>();
+    // sorting is stable, so ranges with the same quality stay in the order they were given
+    requested.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+    requested
+        .into_iter()
+        .filter(|(_, quality)| *quality > 0.0)
+        .map(|(locale, _)| locale)
+        .find(|locale| is_supported(locale))
+        .map_or("en".into(), language)
+}
+
+/// The locale of the user running the server, for prompts in the terminal
+pub fn system_locale() -> String {
+    ["LC_ALL", "LC_MESSAGES", "LANG"]
+        .into_iter()
+        .filter_map(|var| env::var(var).ok())
+        .find(|locale| !locale.is_empty())
+        // like `es_MX.UTF-8`
+        .map_or("en".into(), |locale| {
+            negotiate(locale.split('.').next().unwrap_or_default())
+        })
+}
+
+/// Run `f` with `locale` as the current locale, for a request from a client that negotiated it
+pub fn with_locale<T>(locale: &str, f: impl FnOnce() -> T) -> T {
+    let previous = LOCALE.with(|current| current.replace(locale.to_string()));
+    let result = f();
+    LOCALE.with(|current| *current.borrow_mut() = previous);
+    result
+}
+
+/// The locale of the request running on this thread, which is English outside of requests
+pub fn current_locale() -> String {
+    LOCALE.with(|current| match current.borrow().as_str() {
+        "" => "en".into(),
+        locale => locale.to_string(),
+    })
+}
+
+/// Look up a message in the current locale, filling in its values
+pub fn message(id: &str, args: &[(&str, &str)]) -> String {
+    message_in(&current_locale(), id, args)
+}
+
+/// Look up a message in a locale, falling back to English, then to the ID itself
+pub fn message_in(locale: &str, id: &str, args: &[(&str, &str)]) -> String {
+    let catalogs = catalogs();
+    let text = catalogs
+        .get(language(locale).as_str())
+        .and_then(|catalog| catalog.get(id))
+        .or_else(|| catalogs.get("en").and_then(|catalog| catalog.get(id)))
+        .copied()
+        .unwrap_or(id);
+    args.iter().fold(text.to_string(), |text, (name, value)| {
+        text.replace(&format!("{{${name}}}"), value)
+    })
+}
+
+/// The bundled catalogs, parsed into messages by ID
+fn catalogs() -> &'static HashMap<&'static str, HashMap<&'static str, &'static str>> {
+    static PARSED: OnceLock<HashMap<&str, HashMap<&str, &str>>> = OnceLock::new();
+    PARSED.get_or_init(|| {
+        CATALOGS
+            .iter()
+            .map(|(locale, catalog)| (*locale, parse(catalog)))
+            .collect()
+    })
+}
+
+/// Read the `id = text` lines of a catalog, skipping blank lines and comments
+fn parse(catalog: &str) -> HashMap<&str, &str> {
+    catalog
+        .lines()
+        .filter(|line| !line.trim_start().starts_with('#'))
+        .filter_map(|line| line.split_once(" = "))
+        .map(|(id, text)| (id.trim(), text.trim()))
+        .collect()
+}
+
+/// Translate a word from the commit vocabulary, leaving it as it is if there's no translation
+pub fn translate<'a>(locale: &str, word: &'a str) -> &'a str {
+    let language = language(locale);
+    let Some(index) = WORDS.iter().position(|w| *w == word) else {
+        return word;
+    };
+    TRANSLATIONS
+        .iter()
+        .find(|(locale, _)| *locale == language)
+        .map_or(word, |(_, words)| words[index])
+}
+
+/// The language part of a locale, like `es` in `es-MX`
+fn language(locale: &str) -> String {
+    locale
+        .split(['-', '_'])
+        .next()
+        .unwrap_or_default()
+        .to_lowercase()
+}
@@ -0,0 +1,144 @@
+//! Rules for leaving changes out of diffs and commit messages, from a project's
+//! `.pixelpioneersignore`
+//!
+//! Each line is a rule like `sprite:Debug*`, where `*` matches any run of characters and `?` any
+//! one. Lines without a kind match sprites, like `ignored_sprites` in `pixelpioneers.toml`, and
+//! `#` starts a comment:
+//!
+//! ```text
+//! # sprites only used while testing
+//! Debug*
+//! asset:placeholder?
+//! variable:_*
+//! change:sounds
+//! ```
+//!
+//! `change:` takes a kind of change rather than a pattern: `scripts`, `costumes`, `sounds`,
+//! `variables`, `broadcasts` or `extensions`.
+
+use std::fs;
+use std::path::Path;
+
+use tracing::warn;
+
+use crate::diff::label;
+
+/// File in a project's directory the rules are read from
+pub const IGNORE_FILE: &str = ".pixelpioneersignore";
+
+/// Kinds of change that `change:` rules can leave out
+pub const CHANGE_KINDS: [&str; 6] = [
+    "scripts",
+    "costumes",
+    "sounds",
+    "variables",
+    "broadcasts",
+    "extensions",
+];
+
+/// Represents the rules in a project's ignore file
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct IgnoreRules {
+    pub sprites: Vec<String>,
+    /// Patterns over costume and sound names, without their extension
+    pub assets: Vec<String>,
+    pub variables: Vec<String>,
+    /// Kinds of change, from [`CHANGE_KINDS`]
+    pub changes: Vec<String>,
+}
+
+impl IgnoreRules {
+    /// Read a project's ignore file, where a missing one has no rules
+    pub fn load(project: &Path) -> Self {
+        let Ok(contents) = fs::read_to_string(project.join(IGNORE_FILE)) else {
+            return IgnoreRules::default();
+        };
+        let (rules, problems) = IgnoreRules::parse(&contents);
+        for problem in problems {
+            warn!("{IGNORE_FILE}: {problem}");
+        }
+        rules
+    }
+
+    /// Parse rules, skipping lines that can't be understood and describing what's wrong with them
+    pub fn parse(contents: &str) -> (Self, Vec<String>) {
+        let mut rules = IgnoreRules::default();
+        let mut problems = vec![];
+        for (number, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (kind, pattern) = line.split_once(':').unwrap_or(("sprite", line));
+            let pattern = pattern.trim().to_string();
+            match kind.trim() {
+                "sprite" => rules.sprites.push(pattern),
+                "asset" => rules.assets.push(pattern),
+                "variable" => rules.variables.push(pattern),
+                "change" if CHANGE_KINDS.contains(&pattern.as_str()) => rules.changes.push(pattern),
+                "change" => problems.push(format!(
+                    "line {}: unknown kind of change {pattern}, use one of {}",
+                    number + 1,
+                    CHANGE_KINDS.join(", ")
+                )),
+                kind => problems.push(format!(
+                    "line {}: unknown rule {kind}, use sprite, asset, variable or change",
+                    number + 1
+                )),
+            }
+        }
+        (rules, problems)
+    }
+
+    /// Check if a sprite is ignored, where stage names may end with ` (stage)`
+    pub fn sprite(&self, sprite: &str) -> bool {
+        let sprite = label::name(sprite);
+        self.sprites.iter().any(|pattern| matches(pattern, sprite))
+    }
+
+    /// Check if a costume or sound is ignored by its name
+    pub fn asset(&self, name: &str) -> bool {
+        self.assets.iter().any(|pattern| matches(pattern, name))
+    }
+
+    pub fn variable(&self, name: &str) -> bool {
+        self.variables.iter().any(|pattern| matches(pattern, name))
+    }
+
+    /// Check if a kind of change, from [`CHANGE_KINDS`], is ignored
+    pub fn change(&self, kind: &str) -> bool {
+        self.changes.iter().any(|ignored| ignored == kind)
+    }
+}
+
+/// Check if text matches a pattern where `*` matches any run of characters and `?` any one
+pub fn matches(pattern: &str, text: &str) -> bool {
+    let (pattern, text) = (
+        pattern.chars().collect::<Vec<_>>(),
+        text.chars().collect::<Vec<_>>(),
+    );
+    // where to go back to after the last `*` when what followed it stops matching
+    let mut star = None;
+    let (mut p, mut t) = (0, 0);
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match star {
+                Some((star_p, star_t)) => {
+                    p = star_p + 1;
+                    t = star_t + 1;
+                    star = Some((star_p, star_t + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
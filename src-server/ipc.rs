@@ -0,0 +1,94 @@
+//! Local transport for scripts and tools, which can't clash with other programs' ports or set
+//! off firewall prompts: a Unix domain socket on Linux and macOS, and a named pipe on Windows
+//!
+//! Clients speak the same WebSocket and HTTP protocols as over TCP
+
+use std::io;
+use std::sync::OnceLock;
+use std::thread::spawn;
+
+use tracing::debug;
+
+use crate::connection::Connection;
+
+/// Used by `--socket` when no path or pipe name is given
+#[cfg(unix)]
+pub const DEFAULT_SOCKET: &str = "projects/.server.sock";
+#[cfg(windows)]
+pub const DEFAULT_SOCKET: &str = "scratch-git";
+
+static SOCKET: OnceLock<String> = OnceLock::new();
+
+/// Where the server is listening locally, if it is
+pub fn socket() -> Option<&'static str> {
+    SOCKET.get().map(|s| s.as_str())
+}
+
+/// Accept local connections in the background, handing each to `serve` on its own thread
+#[cfg(unix)]
+pub fn listen(name: &str, serve: fn(Connection)) -> io::Result<()> {
+    use std::os::unix::net::UnixListener;
+
+    // the workspace lock is held, so a leftover socket is from a server that crashed
+    remove_socket(name);
+    let listener = UnixListener::bind(name)?;
+    let _ = SOCKET.set(name.to_string());
+
+    spawn(move || {
+        for stream in listener.incoming() {
+            match stream
+                .map_err(anyhow::Error::from)
+                .and_then(Connection::local)
+            {
+                Ok(connection) => {
+                    spawn(move || serve(connection));
+                }
+                Err(e) => debug!("local connection failed: {e:#}"),
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Accept local connections in the background, handing each to `serve` on its own thread
+#[cfg(windows)]
+pub fn listen(name: &str, serve: fn(Connection)) -> io::Result<()> {
+    use interprocess::local_socket::LocalSocketListener;
+
+    let listener = LocalSocketListener::bind(name)?;
+    let _ = SOCKET.set(format!(r"\\.\pipe\{name}"));
+
+    spawn(move || {
+        for stream in listener.incoming() {
+            match stream
+                .map_err(anyhow::Error::from)
+                .and_then(Connection::local)
+            {
+                Ok(connection) => {
+                    spawn(move || serve(connection));
+                }
+                Err(e) => debug!("local connection failed: {e:#}"),
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Remove the socket file on shutdown, since binding fails while it exists
+pub fn remove() {
+    #[cfg(unix)]
+    if let Some(socket) = socket() {
+        remove_socket(socket);
+    }
+}
+
+/// Remove a socket file, leaving anything else at the path alone, like a project given by mistake
+#[cfg(unix)]
+fn remove_socket(path: &str) {
+    use std::os::unix::fs::FileTypeExt;
+
+    let is_socket = std::fs::symlink_metadata(path).is_ok_and(|meta| meta.file_type().is_socket());
+    if is_socket {
+        let _ = std::fs::remove_file(path);
+    }
+}
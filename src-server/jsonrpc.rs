@@ -0,0 +1,231 @@
+//! JSON-RPC 2.0 over the WebSocket and `POST /rpc`
+//!
+//! Methods are the typed protocol's request types (`diff`, `diff-page`, `commit`, `log`, `push`,
+//! `status`, `hello`), with the request's fields as named params, e.g.
+//! `{"jsonrpc": "2.0", "id": 1, "method": "log", "params": {"project_name": "game", "limit": 5}}`.
+//! Batches are answered with an array, and notifications (calls without an ID) get no answer.
+
+use std::time::Duration;
+
+use serde_json::{json, Map, Value};
+
+use crate::cancel::CancelToken;
+use crate::error::Error;
+use crate::observer;
+use crate::protocol::{self, Request, Response};
+use crate::ratelimit::Expensive;
+
+pub const PARSE_ERROR: i64 = -32700;
+pub const INVALID_REQUEST: i64 = -32600;
+pub const METHOD_NOT_FOUND: i64 = -32601;
+pub const INVALID_PARAMS: i64 = -32602;
+pub const INTERNAL_ERROR: i64 = -32603;
+/// The operation ran but failed, e.g. Git couldn't be run
+pub const OPERATION_FAILED: i64 = -32000;
+pub const CANCELLED: i64 = -32001;
+/// `data.retry_after_ms` says when to try again
+pub const RATE_LIMITED: i64 = -32002;
+/// `data.code` is the commit command's failure code
+pub const COMMIT_FAILED: i64 = -32003;
+/// `data.violations` lists what's wrong with the saved project.json
+pub const INVALID_PROJECT: i64 = -32004;
+
+const METHODS: [&str; 6] = ["diff", "commit", "log", "push", "status", "hello"];
+
+/// What a call needs from the transport it came in on
+pub struct Context<'a> {
+    /// Take a token for an expensive call, or return how long until one is available
+    pub limit: &'a mut dyn FnMut(Expensive) -> Result<(), Duration>,
+    /// Agree on features and a locale from a `hello` call, returning both
+    pub hello: &'a mut dyn FnMut(Vec<String>, Vec<String>) -> (Vec<String>, String),
+    /// Send a notification, used for progress
+    pub notify: &'a mut dyn FnMut(Value),
+    /// Reports being paged through with `diff-page`
+    pub pages: &'a protocol::Pages,
+}
+
+/// Check if a raw message is JSON-RPC, either a single call or a batch
+pub fn is_jsonrpc(message: &Value) -> bool {
+    match message {
+        Value::Array(calls) => calls.first().is_some_and(|call| call.get("jsonrpc").is_some()),
+        message => message.get("jsonrpc").is_some(),
+    }
+}
+
+fn error(id: Value, code: i64, message: &str, data: Option<Value>) -> Value {
+    let mut error = json!({ "code": code, "message": message });
+    if let Some(data) = data {
+        error["data"] = data;
+    }
+    json!({ "jsonrpc": "2.0", "id": id, "error": error })
+}
+
+fn result(id: Value, result: Value) -> Value {
+    json!({ "jsonrpc": "2.0", "id": id, "result": result })
+}
+
+/// Answer a single call, or `None` for a notification
+fn call(message: Value, context: &mut Context) -> Option<Value> {
+    let Value::Object(mut message) = message else {
+        return Some(error(Value::Null, INVALID_REQUEST, "call must be an object", None));
+    };
+
+    let id = message.remove("id");
+    let answer = |response: &dyn Fn(Value) -> Value| id.clone().map(response);
+
+    if message.get("jsonrpc") != Some(&json!("2.0")) {
+        return Some(error(
+            id.clone().unwrap_or_default(),
+            INVALID_REQUEST,
+            "jsonrpc must be \"2.0\"",
+            None,
+        ));
+    }
+    let Some(method) = message
+        .get("method")
+        .and_then(|m| m.as_str())
+        .map(str::to_string)
+    else {
+        return Some(error(
+            id.clone().unwrap_or_default(),
+            INVALID_REQUEST,
+            "method must be a string",
+            None,
+        ));
+    };
+    if !METHODS.contains(&method.as_str()) {
+        return answer(&|id| error(id, METHOD_NOT_FOUND, "method not found", None));
+    }
+
+    let mut params = match message.remove("params") {
+        Some(Value::Object(params)) => params,
+        None => Map::new(),
+        Some(_) => {
+            return answer(&|id| {
+                error(id, INVALID_PARAMS, "params must be an object", None)
+            })
+        }
+    };
+    params.insert("type".into(), json!(method));
+    let request = match serde_json::from_value::<Request>(Value::Object(params)) {
+        Ok(request) => request,
+        Err(e) => {
+            return answer(&|id| error(id, INVALID_PARAMS, &e.to_string(), None));
+        }
+    };
+
+    if let Request::Hello {
+        protocol_version,
+        features,
+        locales,
+    } = request
+    {
+        let (features, locale) = (context.hello)(features, locales);
+        let hello = json!({
+            "protocol_version": protocol_version.min(protocol::PROTOCOL_VERSION),
+            "server_version": env!("CARGO_PKG_VERSION"),
+            "features": features,
+            "locale": locale,
+        });
+        return answer(&|id| result(id, hello.clone()));
+    }
+
+    if let Some(Err(wait)) = request.cost().map(|kind| (context.limit)(kind)) {
+        let data = json!({ "retry_after_ms": wait.as_millis() as u64 });
+        return answer(&|id| error(id, RATE_LIMITED, "too many requests", Some(data.clone())));
+    }
+
+    let request_id = id.clone().unwrap_or_default();
+    let mut progress = observer::progress(|operation, done, total| {
+        (context.notify)(json!({
+            "jsonrpc": "2.0",
+            "method": "progress",
+            "params": {
+                "id": request_id,
+                "operation": operation,
+                "done": done,
+                "total": total,
+            },
+        }));
+        Ok(())
+    });
+    let response = protocol::respond(request, context.pages, &CancelToken::new(), &mut progress);
+
+    answer(&|id| match &response {
+        Ok(Response::Diff { report }) => result(id, json!(report)),
+        Ok(Response::DiffPage { report, cursor }) => {
+            result(id, json!({ "report": report, "cursor": cursor }))
+        }
+        Ok(Response::Commit { message }) => result(id, json!({ "message": message })),
+        Ok(Response::CommitFailed { code }) => error(
+            id,
+            COMMIT_FAILED,
+            "commit failed",
+            Some(json!({ "code": code })),
+        ),
+        Ok(Response::Log { commits }) => result(id, json!(commits)),
+        Ok(Response::Push { status }) => result(id, json!({ "status": status })),
+        Ok(Response::Status { status }) => result(id, json!(status)),
+        Ok(Response::Hello {
+            protocol_version,
+            server_version,
+            features,
+            locale,
+        }) => result(
+            id,
+            json!({
+                "protocol_version": protocol_version,
+                "server_version": server_version,
+                "features": features,
+                "locale": locale,
+            }),
+        ),
+        Ok(Response::Cancel { cancelled }) => result(id, json!({ "cancelled": cancelled })),
+        Ok(Response::Cancelled) => error(id, CANCELLED, "request was cancelled", None),
+        Ok(Response::Invalid { violations }) => error(
+            id,
+            INVALID_PROJECT,
+            "project.json is broken",
+            Some(json!({ "violations": violations })),
+        ),
+        Ok(Response::RateLimited { retry_after_ms }) => error(
+            id,
+            RATE_LIMITED,
+            "too many requests",
+            Some(json!({ "retry_after_ms": retry_after_ms })),
+        ),
+        Ok(Response::Error { message, kind }) => {
+            let data = kind.map(|kind| json!({ "kind": kind }));
+            error(id, OPERATION_FAILED, message, data)
+        }
+        Err(e) => {
+            let data = Error::kind_of(e).map(|kind| json!({ "kind": kind }));
+            error(id, INTERNAL_ERROR, &e.to_string(), data)
+        }
+    })
+}
+
+/// Answer a raw JSON-RPC message, or `None` if there's nothing to send back
+pub fn handle(message: &str, context: &mut Context) -> Option<Value> {
+    let message = match serde_json::from_str::<Value>(message) {
+        Ok(message) => message,
+        Err(e) => return Some(error(Value::Null, PARSE_ERROR, &e.to_string(), None)),
+    };
+
+    match message {
+        Value::Array(calls) if calls.is_empty() => Some(error(
+            Value::Null,
+            INVALID_REQUEST,
+            "batch must not be empty",
+            None,
+        )),
+        Value::Array(calls) => {
+            let answers = calls
+                .into_iter()
+                .filter_map(|message| call(message, context))
+                .collect::<Vec<_>>();
+            (!answers.is_empty()).then_some(Value::Array(answers))
+        }
+        message => call(message, context),
+    }
+}
@@ -0,0 +1,117 @@
+//! The engine behind the server: reading Scratch projects, diffing them, and turning the
+//! changes into Git commits
+//!
+//! The server binary is a thin layer over this, so other tools like CLIs, bots and web services
+//! can embed the same engine. Most tools only need a few entry points:
+//!
+//! - [`sb3`] reads and writes projects, with [`Project`] as the parsed project.json
+//! - [`Diff`] compares two versions of a project, giving a [`DiffReport`]
+//! - [`ops`] runs whole operations on a project directory, like committing or reverting
+//! - [`git`] runs Git, and [`Error`] is what callers can branch on when something fails
+//! - [`async_ops`] has async versions of loading, diffing and committing for async hosts
+//!
+//! Without the default `native` feature, only reading projects and turning scripts into text are
+//! built, which is what the `wasm` feature's browser bindings in [`wasm`] need.
+//!
+//! Settings are read from the same `pixelpioneers.toml` files as the server's, see [`config`].
+
+#[cfg(feature = "native")]
+pub mod asset_store;
+#[cfg(feature = "native")]
+pub mod async_ops;
+#[cfg(feature = "native")]
+pub mod cache;
+#[cfg(feature = "native")]
+pub mod cancel;
+#[cfg(feature = "native")]
+pub mod changelog;
+#[cfg(feature = "native")]
+pub mod chat;
+#[cfg(feature = "native")]
+pub mod config;
+pub mod crdt;
+#[cfg(feature = "native")]
+pub mod diff;
+/// Only comparing what doesn't need Git and turning scripts into text, since the rest of
+/// diffing runs Git
+#[cfg(not(feature = "native"))]
+pub mod diff {
+    pub mod compare;
+    pub mod parse_script;
+}
+pub mod error;
+pub mod extensions;
+pub mod formats;
+#[cfg(feature = "native")]
+pub mod events;
+#[cfg(feature = "native")]
+pub mod ffi;
+#[cfg(feature = "native")]
+pub mod experiment;
+#[cfg(feature = "native")]
+pub mod git;
+#[cfg(feature = "native")]
+pub mod gitignore;
+#[cfg(feature = "native")]
+pub mod history;
+#[cfg(feature = "native")]
+pub mod hooks;
+#[cfg(feature = "native")]
+pub mod html_report;
+#[cfg(feature = "native")]
+pub mod i18n;
+#[cfg(feature = "native")]
+pub mod ignore;
+pub mod limits;
+#[cfg(feature = "native")]
+pub mod lint;
+#[cfg(feature = "native")]
+pub mod locks;
+#[cfg(feature = "native")]
+pub mod mapped;
+#[cfg(feature = "native")]
+pub mod merge;
+#[cfg(feature = "native")]
+pub mod metrics;
+pub mod normalize;
+pub mod observer;
+#[cfg(feature = "native")]
+pub mod optimize;
+#[cfg(feature = "native")]
+pub mod ops;
+#[cfg(feature = "native")]
+pub mod partial;
+#[cfg(feature = "native")]
+pub mod patch;
+#[cfg(feature = "native")]
+pub mod project;
+#[cfg(feature = "native")]
+pub mod provenance;
+#[cfg(feature = "python")]
+pub mod python;
+pub mod repair;
+#[cfg(feature = "native")]
+pub mod review;
+#[cfg(feature = "native")]
+pub mod sb2;
+pub mod sb3;
+#[cfg(feature = "native")]
+pub mod scratchblocks;
+#[cfg(feature = "native")]
+pub mod sessions;
+#[cfg(feature = "native")]
+pub mod split;
+#[cfg(feature = "native")]
+pub mod temp;
+#[cfg(feature = "native")]
+pub mod thumbnail;
+#[cfg(feature = "native")]
+pub mod timeline;
+pub mod validate;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+
+#[cfg(feature = "native")]
+pub use diff::structs::{Diff, DiffReport};
+pub use error::{Error, Result};
+pub use sb3::Project;
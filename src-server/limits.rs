@@ -0,0 +1,251 @@
+//! Limits on what's read from projects, since SB3s and project.json files can come from anyone
+//!
+//! A ZIP entry that claims to be a few kilobytes can inflate to gigabytes, and JSON or scripts
+//! nested thousands deep overflow the stack of anything that walks them recursively. Reading past
+//! these limits fails with [`Error::TooLarge`] instead of running out of memory.
+
+use std::io::{Read, Seek};
+
+use zip::read::ZipFile;
+use zip::ZipArchive;
+
+use crate::error::Error;
+
+/// Largest project.json, sprite.json or project.sb2 JSON that's read
+pub const MAX_JSON_SIZE: u64 = 128 * 1024 * 1024;
+
+/// Largest costume or sound that's read
+pub const MAX_ASSET_SIZE: u64 = 128 * 1024 * 1024;
+
+/// Largest a whole SB3 can be once everything in it is inflated
+pub const MAX_ARCHIVE_SIZE: u64 = 1024 * 1024 * 1024;
+
+/// Most files an SB3 can have, since each one is looked at
+pub const MAX_ENTRIES: usize = 20_000;
+
+/// Most an entry can inflate relative to its compressed size, which JSON and uncompressed
+/// audio stay well under
+pub const MAX_RATIO: u64 = 200;
+
+/// Entries smaller than this aren't held to [`MAX_RATIO`], since tiny files of repeated bytes
+/// compress very well and can't do any harm
+const RATIO_FLOOR: u64 = 1024 * 1024;
+
+/// Deepest arrays and objects can be nested in JSON
+pub const MAX_JSON_DEPTH: usize = 100;
+
+/// Longest a single string in JSON can be, like a list item or a block's text
+pub const MAX_STRING_LENGTH: usize = 16 * 1024 * 1024;
+
+/// Deepest blocks can be nested inside each other, like `if` blocks inside `if` blocks
+pub const MAX_SCRIPT_DEPTH: usize = 256;
+
+fn too_large(what: &str, detail: String) -> Error {
+    Error::TooLarge {
+        what: what.to_string(),
+        detail,
+    }
+}
+
+/// How much of [`MAX_ARCHIVE_SIZE`] a ZIP's entries have really inflated to so far, since the
+/// sizes in its headers can lie
+#[derive(Debug)]
+pub struct ArchiveBudget {
+    what: String,
+    inflated: u64,
+}
+
+/// Check a ZIP's number of files and their inflated size before reading any of them, returning
+/// the budget its entries are then read with
+pub fn check_archive<R: Read + Seek>(
+    archive: &mut ZipArchive<R>,
+    what: &str,
+) -> Result<ArchiveBudget, Error> {
+    if archive.len() > MAX_ENTRIES {
+        let detail = format!("it has {} files, the limit is {MAX_ENTRIES}", archive.len());
+        return Err(too_large(what, detail));
+    }
+    let mut total = 0u64;
+    for i in 0..archive.len() {
+        // entries that can't be read fail when they're actually needed
+        if let Ok(file) = archive.by_index_raw(i) {
+            total = total.saturating_add(file.size());
+        }
+    }
+    if total > MAX_ARCHIVE_SIZE {
+        let detail = format!("it inflates to {total} bytes, the limit is {MAX_ARCHIVE_SIZE}");
+        return Err(too_large(what, detail));
+    }
+    Ok(ArchiveBudget {
+        what: what.to_string(),
+        inflated: 0,
+    })
+}
+
+/// Read a ZIP entry whole, failing if it's bigger than `limit`, inflates suspiciously far, or
+/// takes its archive past [`MAX_ARCHIVE_SIZE`]
+///
+/// Sizes in ZIP headers can lie, so the limits are checked against the bytes really inflated,
+/// and no more than they allow are ever inflated.
+pub fn read_entry(file: ZipFile, limit: u64, budget: &mut ArchiveBudget) -> Result<Vec<u8>, Error> {
+    let what = file.name().to_string();
+    let (size, compressed) = (file.size(), file.compressed_size());
+    if size > limit {
+        let detail = format!("{size} bytes, the limit is {limit}");
+        return Err(too_large(&what, detail));
+    }
+    if size > RATIO_FLOOR && size / compressed.max(1) > MAX_RATIO {
+        let detail = format!("it inflates from {compressed} to {size} bytes");
+        return Err(too_large(&what, detail));
+    }
+
+    // the compressed size is what's actually read from the archive, so it can be trusted
+    let ratio_limit = compressed.max(1).saturating_mul(MAX_RATIO).max(RATIO_FLOOR);
+    let remaining = MAX_ARCHIVE_SIZE.saturating_sub(budget.inflated);
+    let allowed = limit.min(ratio_limit).min(remaining);
+
+    let mut contents = Vec::with_capacity(size.min(allowed) as usize);
+    file.take(allowed + 1).read_to_end(&mut contents)?;
+    let inflated = contents.len() as u64;
+    budget.inflated += inflated.min(allowed);
+    if inflated > allowed {
+        return Err(if allowed == limit {
+            too_large(&what, format!("more than {limit} bytes"))
+        } else if allowed == ratio_limit {
+            let detail = format!("it inflates from {compressed} to more than {ratio_limit} bytes");
+            too_large(&what, detail)
+        } else {
+            let detail = format!("it inflates to more than {MAX_ARCHIVE_SIZE} bytes");
+            too_large(&budget.what, detail)
+        });
+    }
+    Ok(contents)
+}
+
+/// Check JSON's size, how deep it's nested, and how long its strings are, without parsing it
+///
+/// This only scans the text, so it's cheap enough to do before every parse. JSON that's broken
+/// in other ways is left for the parser to report.
+pub fn check_json(json: &[u8], what: &str) -> Result<(), Error> {
+    if json.len() as u64 > MAX_JSON_SIZE {
+        let detail = format!("{} bytes, the limit is {MAX_JSON_SIZE}", json.len());
+        return Err(too_large(what, detail));
+    }
+
+    let (mut depth, mut string) = (0usize, None::<usize>);
+    let mut escaped = false;
+    for (i, &byte) in json.iter().enumerate() {
+        if let Some(start) = string {
+            match byte {
+                _ if escaped => escaped = false,
+                b'\\' => escaped = true,
+                b'"' => string = None,
+                _ if i - start > MAX_STRING_LENGTH => {
+                    let detail = format!("a string is longer than {MAX_STRING_LENGTH} bytes");
+                    return Err(too_large(what, detail));
+                }
+                _ => {}
+            }
+            continue;
+        }
+        match byte {
+            b'"' => string = Some(i),
+            b'[' | b'{' => {
+                depth += 1;
+                if depth > MAX_JSON_DEPTH {
+                    let detail = format!("it's nested more than {MAX_JSON_DEPTH} deep");
+                    return Err(too_large(what, detail));
+                }
+            }
+            b']' | b'}' => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Write};
+
+    use zip::write::FileOptions;
+    use zip::{CompressionMethod, ZipWriter};
+
+    use super::*;
+
+    fn zip_of(entries: &[(&str, &[u8])]) -> ZipArchive<Cursor<Vec<u8>>> {
+        let mut zip = ZipWriter::new(Cursor::new(vec![]));
+        let options = FileOptions::default().compression_method(CompressionMethod::Deflated);
+        for (name, contents) in entries {
+            zip.start_file(*name, options).unwrap();
+            zip.write_all(contents).unwrap();
+        }
+        ZipArchive::new(zip.finish().unwrap()).unwrap()
+    }
+
+    fn read(
+        archive: &mut ZipArchive<Cursor<Vec<u8>>>,
+        name: &str,
+        limit: u64,
+    ) -> Result<Vec<u8>, Error> {
+        let mut budget = check_archive(archive, "project.sb3")?;
+        read_entry(archive.by_name(name).unwrap(), limit, &mut budget)
+    }
+
+    #[test]
+    fn entries_within_limits_are_read() {
+        let json = br#"{"targets": []}"#;
+        let mut archive = zip_of(&[("project.json", json)]);
+        assert_eq!(
+            read(&mut archive, "project.json", MAX_JSON_SIZE).unwrap(),
+            json
+        );
+    }
+
+    #[test]
+    fn entries_over_their_limit_fail() {
+        let mut archive = zip_of(&[("sound.wav", &[1; 2048])]);
+        let error = read(&mut archive, "sound.wav", 1024).unwrap_err();
+        assert!(matches!(error, Error::TooLarge { what, .. } if what == "sound.wav"));
+    }
+
+    #[test]
+    fn zip_bombs_fail() {
+        // zeros deflate about a thousand times smaller, far past the ratio allowed
+        let zeros = vec![0; 4 * RATIO_FLOOR as usize];
+        let mut archive = zip_of(&[("project.json", &zeros)]);
+        let error = read(&mut archive, "project.json", MAX_JSON_SIZE).unwrap_err();
+        assert!(matches!(&error, Error::TooLarge { detail, .. } if detail.contains("inflates")));
+
+        // small files compress just as well, but aren't worth failing over
+        let zeros = vec![0; RATIO_FLOOR as usize / 2];
+        let mut archive = zip_of(&[("project.json", &zeros)]);
+        assert!(read(&mut archive, "project.json", MAX_JSON_SIZE).is_ok());
+    }
+
+    fn nested(depth: usize) -> String {
+        format!("{}{}", "[".repeat(depth), "]".repeat(depth))
+    }
+
+    #[test]
+    fn json_nesting_is_limited() {
+        assert!(check_json(nested(MAX_JSON_DEPTH).as_bytes(), "project.json").is_ok());
+        let error = check_json(nested(MAX_JSON_DEPTH + 1).as_bytes(), "project.json");
+        assert!(matches!(error, Err(Error::TooLarge { .. })));
+        // siblings don't add up
+        let siblings = format!("[{}]", vec![nested(MAX_JSON_DEPTH - 1); 3].join(","));
+        assert!(check_json(siblings.as_bytes(), "project.json").is_ok());
+    }
+
+    #[test]
+    fn json_strings_are_skipped() {
+        // brackets in strings aren't nesting, even after an escaped quote
+        let brackets = "[".repeat(MAX_JSON_DEPTH + 1);
+        let json = format!(r#"{{"text": "say \"{brackets}"}}"#);
+        assert!(check_json(json.as_bytes(), "project.json").is_ok());
+
+        // an escaped backslash doesn't escape the quote after it, so the string ends there
+        let json = format!(r#"["\\", {brackets}"#);
+        assert!(check_json(json.as_bytes(), "project.json").is_err());
+    }
+}
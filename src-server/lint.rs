@@ -0,0 +1,210 @@
+//! Finds likely mistakes in a project that the editor happily saves, like scripts that never
+//! run or blocks that use variables that were deleted
+
+use std::collections::{HashMap, HashSet};
+
+use serde_json::{Map, Value};
+
+use crate::sb3::{Block, BlockEntry, Project, Target};
+use crate::validate::Violation;
+
+/// Primitive types of inputs and loose reporters that refer to something by name and ID
+const BROADCAST_PRIMITIVE: u64 = 11;
+const VARIABLE_PRIMITIVE: u64 = 12;
+const LIST_PRIMITIVE: u64 = 13;
+
+/// Rules issues are found by, as IDs and what they check, for reports that group issues
+pub const RULES: [(&str, &str); 4] = [
+    (
+        "undefined-reference",
+        "Blocks use variables, lists or broadcasts that don't exist",
+    ),
+    (
+        "unreachable-script",
+        "Scripts without a hat block never run",
+    ),
+    ("empty-script", "Scripts with no blocks"),
+    ("long-script", "Scripts too long to follow"),
+];
+
+/// Which of the [`RULES`] found an issue, going by its message
+pub fn rule(issue: &Violation) -> &'static str {
+    let message = &issue.message;
+    if message.ends_with("doesn't exist") {
+        "undefined-reference"
+    } else if message.ends_with("never runs") {
+        "unreachable-script"
+    } else if message.ends_with("is empty") {
+        "empty-script"
+    } else {
+        "long-script"
+    }
+}
+
+/// Options for linting
+#[derive(Debug, Clone, Copy)]
+pub struct Options {
+    /// Scripts with more blocks than this are reported as too long to follow
+    pub max_script_length: usize,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            max_script_length: 200,
+        }
+    }
+}
+
+/// Lint a project, returning every issue found
+pub fn lint(project: &Project, options: Options) -> Vec<Violation> {
+    let mut issues = vec![];
+
+    let stage = project.targets.iter().find(|target| target.is_stage);
+    let ids = |target: Option<&Target>, key: &str| {
+        target
+            .and_then(|target| target.extra.get(key))
+            .and_then(Value::as_object)
+            .into_iter()
+            .flat_map(Map::keys)
+            .cloned()
+            .collect::<HashSet<_>>()
+    };
+    let global_variables = ids(stage, "variables");
+    let global_lists = ids(stage, "lists");
+    let broadcasts = ids(stage, "broadcasts");
+
+    for (i, target) in project.targets.iter().enumerate() {
+        let path = |id: &str| format!("targets[{i}].blocks.{id}");
+        let variables = &ids(Some(target), "variables") | &global_variables;
+        let lists = &ids(Some(target), "lists") | &global_lists;
+        let mut undefined = |id: &str, kind: &str, name: &str, referenced: Option<&str>| {
+            let known = match kind {
+                "variable" => &variables,
+                "list" => &lists,
+                _ => &broadcasts,
+            };
+            if referenced.is_some_and(|referenced| !known.contains(referenced)) {
+                issues.push(Violation::new(
+                    path(id),
+                    format!("{kind} {name} doesn't exist"),
+                ));
+            }
+        };
+
+        for (id, entry) in &target.blocks {
+            match entry {
+                BlockEntry::Block(block) => {
+                    for (field, kind) in [
+                        ("VARIABLE", "variable"),
+                        ("LIST", "list"),
+                        ("BROADCAST_OPTION", "broadcast"),
+                    ] {
+                        if let Some(Value::Array(field)) = block.fields.get(field) {
+                            let name = field.first().and_then(Value::as_str).unwrap_or_default();
+                            undefined(id, kind, name, field.get(1).and_then(Value::as_str));
+                        }
+                    }
+                    for input in block.inputs.values() {
+                        // inputs look like [shadow type, block id or primitive, shadow id?]
+                        let values = input.as_array().into_iter().flatten().skip(1);
+                        for value in values.filter_map(Value::as_array) {
+                            if let Some((kind, name, reference)) = reference(value) {
+                                undefined(id, kind, name, Some(reference));
+                            }
+                        }
+                    }
+                }
+                BlockEntry::Primitive(primitive) => {
+                    if let Some((kind, name, reference)) = reference(primitive) {
+                        undefined(id, kind, name, Some(reference));
+                    }
+                }
+                BlockEntry::Other(_) => {}
+            }
+        }
+
+        let lengths = script_lengths(target);
+        for id in target.top_ids() {
+            let Some(block) = block(target, &id) else {
+                continue;
+            };
+            let length = lengths.get(id.as_str()).copied().unwrap_or(0);
+            if !is_hat(&block.opcode) {
+                // a lone block is usually a reporter kept around to click and see its value
+                if block.next.is_some() {
+                    issues.push(Violation::new(
+                        path(&id),
+                        "script has no hat block, so it never runs",
+                    ));
+                }
+            } else if block.next.is_none() {
+                issues.push(Violation::new(path(&id), "script is empty"));
+            }
+            if length > options.max_script_length {
+                issues.push(Violation::new(
+                    path(&id),
+                    format!("script has {length} blocks, which is hard to follow"),
+                ));
+            }
+        }
+    }
+
+    issues
+}
+
+/// What a primitive refers to, as its kind, name and ID
+fn reference(primitive: &[Value]) -> Option<(&'static str, &str, &str)> {
+    let kind = match primitive.first()?.as_u64()? {
+        BROADCAST_PRIMITIVE => "broadcast",
+        VARIABLE_PRIMITIVE => "variable",
+        LIST_PRIMITIVE => "list",
+        _ => return None,
+    };
+    Some((
+        kind,
+        primitive.get(1)?.as_str()?,
+        primitive.get(2)?.as_str()?,
+    ))
+}
+
+fn block<'a>(target: &'a Target, id: &str) -> Option<&'a Block> {
+    match target.blocks.get(id)? {
+        BlockEntry::Block(block) => Some(block),
+        _ => None,
+    }
+}
+
+/// Check if a block starts a script, like `event_whenflagclicked` or a custom block definition
+fn is_hat(opcode: &str) -> bool {
+    let (_, name) = opcode.split_once('_').unwrap_or_default();
+    name.starts_with("when")
+        || opcode == "control_start_as_clone"
+        || opcode == "procedures_definition"
+}
+
+/// Count the blocks in each script, by the ID of its top block, leaving out shadows
+fn script_lengths(target: &Target) -> HashMap<&str, usize> {
+    let mut lengths = HashMap::new();
+    for (id, block) in target.blocks() {
+        if block.shadow {
+            continue;
+        }
+        // parents are followed up to the top, giving up on cycles
+        let mut top = id.as_str();
+        let mut steps = 0;
+        while let Some(parent) = block_parent(target, top) {
+            top = parent;
+            steps += 1;
+            if steps > target.blocks.len() {
+                break;
+            }
+        }
+        *lengths.entry(top).or_default() += 1;
+    }
+    lengths
+}
+
+fn block_parent<'a>(target: &'a Target, id: &str) -> Option<&'a str> {
+    block(target, id)?.parent.as_deref()
+}
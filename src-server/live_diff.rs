@@ -0,0 +1,174 @@
+//! `pixelpioneers watch`, which prints what changed since the last commit every time the project
+//! is saved, like `git diff --stat` as you work
+//!
+//! Each save prints a line per changed sprite, with added blocks and assets in green and removed
+//! ones in red. Colors are left out when the output isn't a terminal or `NO_COLOR` is set.
+
+use std::collections::BTreeMap;
+use std::env;
+use std::io::{self, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use notify::{RecursiveMode, Watcher};
+use serde_json::json;
+
+use pixelpioneers_core::diff::builder::Source;
+use pixelpioneers_core::diff::label;
+use pixelpioneers_core::diff::structs::{AssetChangeType, Diff, DiffReport};
+use pixelpioneers_core::observer::Quiet;
+use pixelpioneers_core::ops;
+use pixelpioneers_core::sb3;
+
+/// How long the file has to stay untouched before a save is considered finished
+const DEBOUNCE: Duration = Duration::from_millis(300);
+
+/// Represents how to color text, if at all
+#[derive(Debug, Clone, Copy)]
+struct Style {
+    color: bool,
+}
+
+impl Style {
+    fn paint(self, code: &str, text: &str) -> String {
+        match self.color {
+            true => format!("\x1b[{code}m{text}\x1b[0m"),
+            false => text.to_string(),
+        }
+    }
+
+    fn added(self, text: &str) -> String {
+        self.paint("32", text)
+    }
+
+    fn removed(self, text: &str) -> String {
+        self.paint("31", text)
+    }
+
+    fn bold(self, text: &str) -> String {
+        self.paint("1", text)
+    }
+}
+
+/// Summarize a report with a line per sprite, with its name padded so the changes line up
+fn summary(report: &DiffReport, style: Style) -> String {
+    if report.size() == 0 {
+        return "No changes since the last commit\n".to_string();
+    }
+
+    let mut sprites = BTreeMap::<String, Vec<String>>::new();
+    let mut add = |sprite: &str, change: String| {
+        let sprite = label::name(sprite).to_string();
+        sprites.entry(sprite).or_default().push(change);
+    };
+    for script in &report.scripts {
+        let mut counts = vec![];
+        if script.added > 0 {
+            counts.push(style.added(&format!("+{}", script.added)));
+        }
+        if script.removed > 0 {
+            counts.push(style.removed(&format!("-{}", script.removed)));
+        }
+        add(&script.sprite, format!("{} blocks", counts.join(" ")));
+    }
+    for asset in &report.assets {
+        let name = format!("{}.{}", asset.name, asset.ext);
+        let change = match asset.kind {
+            Some(AssetChangeType::Before) => style.removed(&format!("-{name}")),
+            _ => style.added(&format!("+{name}")),
+        };
+        add(&asset.sprite, change);
+    }
+    for costume in &report.costumes {
+        add(&costume.sprite, costume.format());
+    }
+    for variable in &report.variables {
+        add(&variable.sprite, variable.format());
+    }
+
+    let width = sprites.keys().map(|sprite| sprite.chars().count()).max();
+    let width = width.unwrap_or(0);
+    let mut lines = format!(
+        "{} changes in {} sprites\n",
+        report.size(),
+        report.sprites().len()
+    );
+    for (sprite, changes) in sprites {
+        let padded = format!("{sprite:width$}");
+        lines += &format!(" {} | {}\n", style.bold(&padded), changes.join(", "));
+    }
+    for extension in &report.extensions {
+        lines += &format!(" {}\n", extension.format());
+    }
+    for warning in &report.warnings {
+        lines += &format!(" warning: {warning}\n");
+    }
+    lines
+}
+
+/// Compare a saved project to the last commit in `dir`
+fn report(saved: &Path, dir: &Path) -> Result<DiffReport> {
+    let project = match saved.extension().is_some_and(|ext| ext == "sb3") {
+        true => sb3::read_project_json(saved)?,
+        false => ops::parse_project(&std::fs::read_to_string(saved)?)?,
+    };
+    Diff::builder()
+        .repo(dir)
+        .before(Source::Revision("HEAD".into()))
+        .after(Source::Project(project))
+        .build()?
+        .report(&mut Quiet)
+}
+
+/// Wait for saves to stop coming in, or return `false` if the watcher stopped
+fn settle(rx: &Receiver<()>) -> bool {
+    loop {
+        match rx.recv_timeout(DEBOUNCE) {
+            Ok(()) => continue,
+            Err(RecvTimeoutError::Timeout) => return true,
+            Err(RecvTimeoutError::Disconnected) => return false,
+        }
+    }
+}
+
+/// Print the changes in a project file compared to the last commit in `dir`, then again after
+/// every save until interrupted
+///
+/// With `json`, each summary is a line of JSON with the whole report instead.
+pub fn watch(saved: PathBuf, dir: &Path, json: bool) -> Result<()> {
+    let saved = dunce::canonicalize(&saved)
+        .with_context(|| format!("failed to find {}", saved.display()))?;
+    let parent = saved.parent().context("project file has no folder")?;
+
+    let (tx, rx) = channel();
+    let target = saved.clone();
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+        if event.is_ok_and(|e| e.paths.contains(&target)) {
+            let _ = tx.send(());
+        }
+    })?;
+    // editors often replace the file rather than write to it, so its folder is watched
+    watcher.watch(parent, RecursiveMode::NonRecursive)?;
+
+    let style = Style {
+        color: io::stdout().is_terminal() && env::var_os("NO_COLOR").is_none(),
+    };
+    let mut stdout = io::stdout();
+    loop {
+        let printed = match report(&saved, dir) {
+            Ok(report) if json => format!("{}\n", json!(report)),
+            Ok(report) => format!("\n{}", summary(&report, style)),
+            // the editor may still be writing it, so wait for the next save
+            Err(e) if json => format!("{}\n", json!({ "error": format!("{e:#}") })),
+            Err(e) => format!("\n{}\n", style.removed(&format!("error: {e:#}"))),
+        };
+        stdout.write_all(printed.as_bytes())?;
+        stdout.flush()?;
+
+        if rx.recv().is_err() || !settle(&rx) {
+            return Ok(());
+        }
+    }
+}
@@ -0,0 +1,45 @@
+## Diff reports and changelogs
+
+report-title = Änderungen an {$project}
+report-summary = {$changes} Änderungen in {$sprites} Figuren
+report-change = Änderung
+report-details = Details
+report-scripts = Skripte
+report-costumes = Kostüme
+report-sounds = Klänge
+report-costumes-and-sounds = Kostüme und Klänge
+report-variables = Variablen
+report-extensions = Erweiterungen
+report-broadcasts = Nachrichten
+report-warnings = Warnungen
+report-script-changes = Skriptänderungen
+report-asset-added = {$name} hinzugefügt
+report-asset-removed = {$name} entfernt
+report-script-added = Skript hinzugefügt, das mit `{$opcode}` beginnt
+report-script-removed = Skript entfernt, das mit `{$opcode}` beginnt
+report-script-changed = Skript geändert, das mit `{$opcode}` beginnt
+changelog-title = Änderungen von {$from} bis {$to}
+changelog-commit = {$count} Commit
+changelog-commits = {$count} Commits
+changelog-blocks = +{$added}, -{$removed} Blöcke
+changelog-added = {$assets} hinzugefügt
+changelog-removed = {$assets} entfernt
+changelog-changed = {$assets} geändert
+
+## Errors
+
+error-unknown-project = unbekanntes Projekt: {$name}
+error-shutting-down = der Server wird heruntergefahren
+error-invalid-token = ungültiges Sitzungstoken
+
+## Prompts
+
+dialog-project-location = Projektordner auswählen
+dialog-export-sprite = Figur exportieren
+dialog-export-project = Projekt exportieren
+dialog-export-changes = Änderungen exportieren
+dialog-export-history = Verlauf exportieren
+dialog-import-sprite = Figur importieren
+dialog-export-patch = Patch exportieren
+dialog-apply-patch = Patch anwenden
+prompt-turbowarp-path = TurboWarp wurde nicht automatisch gefunden. Bitte füge den richtigen Pfad von hier ein:
@@ -0,0 +1,48 @@
+# Strings shown to users, in English. Every other locale falls back to these.
+# `{$name}` is replaced with a value, like in Fluent.
+
+## Diff reports and changelogs
+
+report-title = Changes to {$project}
+report-summary = {$changes} changes in {$sprites} sprites
+report-change = Change
+report-details = Details
+report-scripts = Scripts
+report-costumes = Costumes
+report-sounds = Sounds
+report-costumes-and-sounds = Costumes and sounds
+report-variables = Variables
+report-extensions = Extensions
+report-broadcasts = Broadcasts
+report-warnings = Warnings
+report-script-changes = Script changes
+report-asset-added = added {$name}
+report-asset-removed = removed {$name}
+report-script-added = added script starting with `{$opcode}`
+report-script-removed = removed script starting with `{$opcode}`
+report-script-changed = changed script starting with `{$opcode}`
+changelog-title = Changes from {$from} to {$to}
+changelog-commit = {$count} commit
+changelog-commits = {$count} commits
+changelog-blocks = +{$added}, -{$removed} blocks
+changelog-added = added {$assets}
+changelog-removed = removed {$assets}
+changelog-changed = changed {$assets}
+
+## Errors
+
+error-unknown-project = unknown project: {$name}
+error-shutting-down = the server is shutting down
+error-invalid-token = invalid session token
+
+## Prompts
+
+dialog-project-location = Select project location
+dialog-export-sprite = Export sprite
+dialog-export-project = Export project
+dialog-export-changes = Export changes
+dialog-export-history = Export history
+dialog-import-sprite = Import sprite
+dialog-export-patch = Export patch
+dialog-apply-patch = Apply patch
+prompt-turbowarp-path = Failed to find TurboWarp path automatically. Please paste the correct path from the following:
@@ -0,0 +1,45 @@
+## Diff reports and changelogs
+
+report-title = Cambios en {$project}
+report-summary = {$changes} cambios en {$sprites} objetos
+report-change = Cambio
+report-details = Detalles
+report-scripts = Programas
+report-costumes = Disfraces
+report-sounds = Sonidos
+report-costumes-and-sounds = Disfraces y sonidos
+report-variables = Variables
+report-extensions = Extensiones
+report-broadcasts = Mensajes
+report-warnings = Advertencias
+report-script-changes = Cambios en los programas
+report-asset-added = se añadió {$name}
+report-asset-removed = se eliminó {$name}
+report-script-added = se añadió un programa que empieza con `{$opcode}`
+report-script-removed = se eliminó un programa que empieza con `{$opcode}`
+report-script-changed = se modificó un programa que empieza con `{$opcode}`
+changelog-title = Cambios de {$from} a {$to}
+changelog-commit = {$count} commit
+changelog-commits = {$count} commits
+changelog-blocks = +{$added}, -{$removed} bloques
+changelog-added = se añadió {$assets}
+changelog-removed = se eliminó {$assets}
+changelog-changed = se modificó {$assets}
+
+## Errors
+
+error-unknown-project = proyecto desconocido: {$name}
+error-shutting-down = el servidor se está apagando
+error-invalid-token = token de sesión no válido
+
+## Prompts
+
+dialog-project-location = Selecciona la ubicación del proyecto
+dialog-export-sprite = Exportar objeto
+dialog-export-project = Exportar proyecto
+dialog-export-changes = Exportar cambios
+dialog-export-history = Exportar historial
+dialog-import-sprite = Importar objeto
+dialog-export-patch = Exportar parche
+dialog-apply-patch = Aplicar parche
+prompt-turbowarp-path = No se encontró TurboWarp automáticamente. Pega la ruta correcta según lo siguiente:
@@ -0,0 +1,45 @@
+## Diff reports and changelogs
+
+report-title = Modifications de {$project}
+report-summary = {$changes} modifications dans {$sprites} sprites
+report-change = Modification
+report-details = Détails
+report-scripts = Scripts
+report-costumes = Costumes
+report-sounds = Sons
+report-costumes-and-sounds = Costumes et sons
+report-variables = Variables
+report-extensions = Extensions
+report-broadcasts = Messages
+report-warnings = Avertissements
+report-script-changes = Modifications des scripts
+report-asset-added = {$name} ajouté
+report-asset-removed = {$name} supprimé
+report-script-added = script ajouté commençant par `{$opcode}`
+report-script-removed = script supprimé commençant par `{$opcode}`
+report-script-changed = script modifié commençant par `{$opcode}`
+changelog-title = Modifications de {$from} à {$to}
+changelog-commit = {$count} commit
+changelog-commits = {$count} commits
+changelog-blocks = +{$added}, -{$removed} blocs
+changelog-added = ajouté : {$assets}
+changelog-removed = supprimé : {$assets}
+changelog-changed = modifié : {$assets}
+
+## Errors
+
+error-unknown-project = projet inconnu : {$name}
+error-shutting-down = le serveur est en cours d'arrêt
+error-invalid-token = jeton de session invalide
+
+## Prompts
+
+dialog-project-location = Choisir l'emplacement du projet
+dialog-export-sprite = Exporter le sprite
+dialog-export-project = Exporter le projet
+dialog-export-changes = Exporter les modifications
+dialog-export-history = Exporter l'historique
+dialog-import-sprite = Importer un sprite
+dialog-export-patch = Exporter le correctif
+dialog-apply-patch = Appliquer un correctif
+prompt-turbowarp-path = Impossible de trouver TurboWarp automatiquement. Collez le bon chemin d'après la page suivante :
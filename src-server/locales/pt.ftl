@@ -0,0 +1,45 @@
+## Diff reports and changelogs
+
+report-title = Alterações em {$project}
+report-summary = {$changes} alterações em {$sprites} atores
+report-change = Alteração
+report-details = Detalhes
+report-scripts = Scripts
+report-costumes = Fantasias
+report-sounds = Sons
+report-costumes-and-sounds = Fantasias e sons
+report-variables = Variáveis
+report-extensions = Extensões
+report-broadcasts = Mensagens
+report-warnings = Avisos
+report-script-changes = Alterações nos scripts
+report-asset-added = {$name} adicionado
+report-asset-removed = {$name} removido
+report-script-added = script adicionado começando com `{$opcode}`
+report-script-removed = script removido começando com `{$opcode}`
+report-script-changed = script alterado começando com `{$opcode}`
+changelog-title = Alterações de {$from} até {$to}
+changelog-commit = {$count} commit
+changelog-commits = {$count} commits
+changelog-blocks = +{$added}, -{$removed} blocos
+changelog-added = adicionado: {$assets}
+changelog-removed = removido: {$assets}
+changelog-changed = alterado: {$assets}
+
+## Errors
+
+error-unknown-project = projeto desconhecido: {$name}
+error-shutting-down = o servidor está sendo desligado
+error-invalid-token = token de sessão inválido
+
+## Prompts
+
+dialog-project-location = Selecione o local do projeto
+dialog-export-sprite = Exportar ator
+dialog-export-project = Exportar projeto
+dialog-export-changes = Exportar alterações
+dialog-export-history = Exportar histórico
+dialog-import-sprite = Importar ator
+dialog-export-patch = Exportar patch
+dialog-apply-patch = Aplicar patch
+prompt-turbowarp-path = Não foi possível encontrar o TurboWarp automaticamente. Cole o caminho correto de acordo com:
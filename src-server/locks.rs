@@ -0,0 +1,148 @@
+//! Advisory locks on sprites, for teams sharing a repository
+//!
+//! Claiming a sprite records who claimed it in `.scratch-locks.json` and commits just that file,
+//! so teammates see the claim once it's pushed and they pull. Claims pushed by others that
+//! haven't been pulled yet are read from the upstream branch too. Locks never stop anyone from
+//! editing, they only warn before someone else's sprite is changed, which heads off most
+//! conflicts before there's anything to merge.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::config;
+use crate::git::{self, Identity};
+
+/// Where a project's locks are kept in its repository
+pub const LOCKS_FILE: &str = ".scratch-locks.json";
+
+/// Represents a claim on a sprite
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Lock {
+    pub owner: Identity,
+    /// Seconds since the Unix epoch when it was claimed
+    pub since: u64,
+    /// Why it was claimed, like what's being worked on
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+}
+
+impl Lock {
+    /// Check if a lock belongs to someone, who's recognized by their email
+    pub fn is_owned_by(&self, identity: &Identity) -> bool {
+        self.owner.email.eq_ignore_ascii_case(&identity.email)
+    }
+}
+
+/// Locks by the name of the sprite they claim, where the stage is `Stage`
+pub type Locks = BTreeMap<String, Lock>;
+
+fn parse(contents: &[u8]) -> Locks {
+    serde_json::from_slice(contents).unwrap_or_default()
+}
+
+/// Read a project's locks, including any on the upstream branch that haven't been pulled yet
+///
+/// Locks in the working directory win over upstream ones for the same sprite.
+pub fn read(cwd: &PathBuf) -> Locks {
+    let mut locks = git::show_file(cwd, "@{upstream}", LOCKS_FILE)
+        .map(|contents| parse(&contents))
+        .unwrap_or_default();
+    if let Ok(contents) = fs::read(cwd.join(LOCKS_FILE)) {
+        locks.extend(parse(&contents));
+    }
+    locks
+}
+
+/// Write a project's own locks and commit only them, leaving anything else staged alone
+fn commit(cwd: &PathBuf, locks: &Locks, message: &str) -> Result<()> {
+    let path = cwd.join(LOCKS_FILE);
+    if locks.is_empty() {
+        fs::remove_file(&path)?;
+    } else {
+        let mut json = serde_json::to_vec_pretty(locks)?;
+        json.push(b'\n');
+        fs::write(&path, json)?;
+    }
+
+    let staged = git::run(vec!["add", "--all", "--", LOCKS_FILE], Some(cwd))
+        .status()?
+        .success();
+    let mut commit = git::run(vec!["commit", "-m", message, "--", LOCKS_FILE], Some(cwd));
+    if let Some(identity) = config::settings(Some(cwd)).identity.as_ref() {
+        identity.apply(&mut commit);
+    }
+    if !staged || !commit.status()?.success() {
+        return Err(anyhow!("failed to commit {LOCKS_FILE}"));
+    }
+    Ok(())
+}
+
+/// Who locks are claimed as in a project, the same as who its commits are made by
+pub fn identity(cwd: &PathBuf) -> Result<Identity> {
+    match config::settings(Some(cwd)).identity {
+        Some(identity) => Ok(identity),
+        None => git::identity(cwd)?.context("set a name and email before claiming sprites"),
+    }
+}
+
+/// Claim a sprite for someone, failing if someone else already has
+///
+/// Claiming a sprite that's already theirs updates its note.
+pub fn claim(cwd: &PathBuf, sprite: &str, owner: &Identity, note: Option<&str>) -> Result<Lock> {
+    if let Some(lock) = read(cwd)
+        .get(sprite)
+        .filter(|lock| !lock.is_owned_by(owner))
+    {
+        return Err(anyhow!("{sprite} is claimed by {}", lock.owner.name));
+    }
+
+    let lock = Lock {
+        owner: owner.clone(),
+        since: SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs(),
+        note: note.map(str::to_string),
+    };
+    let mut locks = parse(&fs::read(cwd.join(LOCKS_FILE)).unwrap_or_default());
+    locks.insert(sprite.to_string(), lock.clone());
+    commit(cwd, &locks, &format!("chore: claim {sprite}"))?;
+    Ok(lock)
+}
+
+/// Release someone's claim on a sprite, or anyone's with `force`, like when a teammate forgot
+///
+/// Returns `false` if the sprite wasn't claimed.
+pub fn release(cwd: &PathBuf, sprite: &str, owner: &Identity, force: bool) -> Result<bool> {
+    let Some(lock) = read(cwd).remove(sprite) else {
+        return Ok(false);
+    };
+    if !force && !lock.is_owned_by(owner) {
+        return Err(anyhow!("{sprite} is claimed by {}", lock.owner.name));
+    }
+
+    let mut locks = parse(&fs::read(cwd.join(LOCKS_FILE)).unwrap_or_default());
+    if locks.remove(sprite).is_none() {
+        return Err(anyhow!(
+            "{sprite} was claimed upstream, pull before releasing it"
+        ));
+    }
+    commit(cwd, &locks, &format!("chore: release {sprite}"))?;
+    Ok(true)
+}
+
+/// Find which of some sprites are claimed by someone else
+pub fn conflicts<'a>(
+    cwd: &PathBuf,
+    sprites: impl IntoIterator<Item = &'a str>,
+    identity: &Identity,
+) -> Vec<(String, Lock)> {
+    let mut locks = read(cwd);
+    sprites
+        .into_iter()
+        .filter_map(|sprite| locks.remove_entry(sprite))
+        .filter(|(_, lock)| !lock.is_owned_by(identity))
+        .collect()
+}
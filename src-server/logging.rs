@@ -0,0 +1,67 @@
+use std::env;
+use std::fs::OpenOptions;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, OnceLock};
+
+use anyhow::{anyhow, Result};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{fmt, reload, EnvFilter, Registry};
+
+/// Environment variable for log directives, e.g. `SCRATCH_GIT_LOG=pixelpioneers_core::git=trace`
+const LOG_ENV: &str = "SCRATCH_GIT_LOG";
+
+/// Log level when none is set, for both the server and the engine it's built on, which logs
+/// under its own crate name
+pub const DEFAULT_LEVEL: &str = "scratch_git=info,pixelpioneers_core=info";
+
+static FILTER: OnceLock<reload::Handle<EnvFilter, Registry>> = OnceLock::new();
+static OVERRIDDEN: AtomicBool = AtomicBool::new(false);
+
+/// Log to the terminal, and as JSON lines to a file if one is given
+///
+/// `--debug` lowers the default level from info to debug, and otherwise `level` from the
+/// settings file is used if given
+pub fn init(debug: bool, level: Option<&str>, log_file: Option<&Path>) -> Result<()> {
+    let default = match level {
+        _ if debug => "scratch_git=debug,pixelpioneers_core=debug",
+        Some(level) => level,
+        None => DEFAULT_LEVEL,
+    };
+    let filter = EnvFilter::try_from_env(LOG_ENV).unwrap_or_else(|_| EnvFilter::new(default));
+    let (filter, handle) = reload::Layer::new(filter);
+
+    let json = match log_file {
+        Some(path) => {
+            let file = OpenOptions::new().create(true).append(true).open(path)?;
+            Some(fmt::layer().json().with_writer(Mutex::new(file)))
+        }
+        None => None,
+    };
+
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(fmt::layer().with_target(false))
+        .with(json)
+        .try_init()?;
+
+    let _ = FILTER.set(handle);
+    OVERRIDDEN.store(debug || env::var_os(LOG_ENV).is_some(), Ordering::Relaxed);
+    Ok(())
+}
+
+/// Check if the log filter was fixed on startup, so the settings file shouldn't change it
+pub fn is_overridden() -> bool {
+    OVERRIDDEN.load(Ordering::Relaxed)
+}
+
+/// Replace the log filter while the server runs, e.g. `debug` or `pixelpioneers_core::diff=trace`
+pub fn set_level(directives: &str) -> Result<()> {
+    let filter = EnvFilter::try_new(directives)?;
+    FILTER
+        .get()
+        .ok_or(anyhow!("logging isn't initialized"))?
+        .reload(filter)?;
+    Ok(())
+}
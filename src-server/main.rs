@@ -1,89 +1,485 @@
-pub mod config;
-pub mod diff;
+pub mod annotations;
+pub mod auth;
+pub mod backup;
+pub mod bisect;
+pub mod connection;
+pub mod daemon;
+pub mod discovery;
 pub mod gh_auth;
-pub mod git;
 pub mod handlers;
-pub mod sb3;
+pub mod http;
+pub mod ipc;
+pub mod jsonrpc;
+pub mod logging;
+pub mod origin;
+pub mod protocol;
+pub mod ratelimit;
+pub mod registry;
+pub mod release;
+pub mod remote;
+pub mod runtime;
+pub mod sarif;
+pub mod shutdown;
+pub mod status;
+pub mod tls;
 pub mod tw_path;
-pub mod zipping;
+pub mod watcher;
 
 use std::{
     env, fs,
-    io::{stdin, BufRead},
-    net::{TcpListener, TcpStream},
-    path::PathBuf,
-    thread::spawn,
+    io::{stdin, BufRead, ErrorKind, Read},
+    path::{Path, PathBuf},
+    sync::mpsc::channel,
+    thread::{sleep, spawn},
+    time::{Duration, Instant},
 };
 
-use serde_json::{from_str, json};
-use tungstenite::{accept, Error, HandshakeError, Message, Result};
+use serde_json::{from_str, json, Value};
+use tracing::{debug, error, info, info_span, warn};
+use tungstenite::handshake::server::{
+    ErrorResponse, Request as HandshakeRequest, Response as HandshakeResponse,
+};
+use tungstenite::http::StatusCode;
+use tungstenite::{accept_hdr, Error, HandshakeError, Message, Result};
+
+use pixelpioneers_core::{
+    asset_store, cache, cancel, changelog, config, diff, error, events, experiment, git, gitignore,
+    history, hooks, i18n, limits, lint, locks, merge, metrics, observer, ops, partial, project,
+    provenance, sb2, sb3, sessions, split, temp, thumbnail, timeline, validate,
+};
 
+use crate::config::project_config;
+use crate::connection::Connection;
+use crate::discovery::ServerAddress;
 use crate::handlers::{handle_command, Cmd};
+use crate::ratelimit::{Expensive, RateLimiter};
+use crate::sessions::Session;
 use crate::tw_path::turbowarp_path;
 
-fn handle_client(stream: TcpStream, debug: bool) -> Result<()> {
-    let mut socket = accept(stream).map_err(|err| match err {
+fn handle_client(stream: Connection) -> Result<()> {
+    let mut locale = "en".to_string();
+    let authorize = |request: &HandshakeRequest, response: HandshakeResponse| {
+        if let Some(accept_language) = request
+            .headers()
+            .get("accept-language")
+            .and_then(|v| v.to_str().ok())
+        {
+            locale = i18n::negotiate(accept_language);
+        }
+
+        let query_token = request.uri().query().and_then(auth::token_from_query);
+        let authorization = request
+            .headers()
+            .get("authorization")
+            .and_then(|v| v.to_str().ok());
+
+        let origin = request
+            .headers()
+            .get("origin")
+            .and_then(|v| v.to_str().ok());
+
+        if !origin::is_allowed(origin) {
+            let mut error = ErrorResponse::new(origin.map(origin::rejection));
+            *error.status_mut() = StatusCode::FORBIDDEN;
+            Err(error)
+        } else if auth::is_authorized(query_token, authorization) {
+            Ok(response)
+        } else {
+            let message = i18n::message_in(&locale, "error-invalid-token", &[]);
+            let mut error = ErrorResponse::new(Some(message));
+            *error.status_mut() = StatusCode::UNAUTHORIZED;
+            Err(error)
+        }
+    };
+
+    let mut socket = accept_hdr(stream, authorize).map_err(|err| match err {
         HandshakeError::Interrupted(_) => panic!("Bug: blocking socket would block"),
         HandshakeError::Failure(f) => f,
     })?;
-        
+    let session = Session::open();
+    let _span = info_span!("connection", session = session.id()).entered();
+    debug!("client connected");
+
+    // wake up regularly to forward events and finished requests, since reads block otherwise
+    socket
+        .get_ref()
+        .set_read_timeout(Some(Duration::from_millis(50)))?;
+    let events = events::subscribe();
+    let (outgoing, responses) = channel::<Value>();
+    // cancels whatever is still running when the client goes away
+    let requests = protocol::Requests::default();
+    requests.set_locale(&locale);
+    let mut limiter = RateLimiter::default();
+    let mut last_ping = Instant::now();
+
     loop {
-        match socket.read()? {
+        if session.is_reaped() {
+            debug!("closing stale session");
+            return Ok(());
+        }
+        if last_ping.elapsed() >= sessions::PING_EVERY {
+            socket.send(Message::Ping(vec![]))?;
+            last_ping = Instant::now();
+        }
+
+        while let Ok(event) = events.try_recv() {
+            // older frontends take every message for a response, so only send events on request,
+            // and the client that caused a change already knows about it
+            if requests.supports("events") && !session.caused(&event) {
+                socket.send(Message::Text(event.to_string()))?;
+            }
+        }
+        while let Ok(response) = responses.try_recv() {
+            debug!(%response, "sending message");
+            socket.send(Message::Text(response.to_string()))?;
+        }
+
+        let message = match socket.read() {
+            Ok(message) => message,
+            Err(Error::Io(e))
+                if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) =>
+            {
+                continue
+            }
+            Err(e) => return Err(e),
+        };
+
+        match message {
             msg @ Message::Text(_) | msg @ Message::Binary(_) => {
                 let msg = msg.to_string();
-                if debug {
-                    println!("<- Received message: {}", &msg);
-                }
-                let cmd = from_str::<Cmd>(&msg).unwrap();
-                handle_command(cmd, &mut socket, debug).unwrap_or_else(|err| {
+                debug!(message = %msg, "received message");
+                let value = from_str::<Value>(&msg).ok();
+                session.touch(value.as_ref().and_then(sessions::project_of));
+                let result = match value {
+                    Some(value) if jsonrpc::is_jsonrpc(&value) => {
+                        requests.spawn_jsonrpc(msg.clone(), outgoing.clone());
+                        Ok(())
+                    }
+                    Some(value) if protocol::is_typed(&value) => {
+                        let id = value["id"].as_u64();
+                        match (serde_json::from_value::<protocol::Envelope>(value), id) {
+                            (Ok(envelope), _) => {
+                                requests.spawn(envelope, outgoing.clone());
+                                Ok(())
+                            }
+                            // probably a request from a newer client, which it can recover from
+                            (Err(e), Some(id)) => {
+                                let response = protocol::Outgoing {
+                                    id,
+                                    body: protocol::Response::Error {
+                                        message: e.to_string(),
+                                        kind: None,
+                                    },
+                                };
+                                socket.send(Message::Text(json!(response).to_string()))?;
+                                Ok(())
+                            }
+                            (Err(e), None) => Err(e.into()),
+                        }
+                    }
+                    _ => match from_str::<Cmd>(&msg) {
+                        Ok(cmd) => {
+                            // commands answer in order, so slow down rather than refuse
+                            if let Some(kind) = Expensive::from_command(cmd.command()) {
+                                limiter.throttle(kind);
+                            }
+                            i18n::with_locale(&requests.locale(), || {
+                                handle_command(cmd, &mut socket, session.id())
+                            })
+                        }
+                        Err(e) => Err(e.into()),
+                    },
+                };
+                result.unwrap_or_else(|err| {
+                    let kind = error::Error::kind_of(&err);
                     socket
                         .send(Message::Text(
-                            json!({"unhandled-error": err.to_string()}).to_string(),
+                            json!({"unhandled-error": err.to_string(), "kind": kind}).to_string(),
                         ))
                         .unwrap()
                 });
             }
-            Message::Ping(_) | Message::Pong(_) | Message::Close(_) | Message::Frame(_) => {}
+            Message::Ping(_) | Message::Pong(_) => session.touch(None),
+            Message::Close(_) | Message::Frame(_) => {}
+        }
+    }
+}
+
+/// Validate a project.json (or `-` for stdin) and exit, for use in Git hooks
+///
+/// `--strict` also rejects opcodes that aren't from Scratch or the project's extensions
+fn validate_and_exit(path: Option<String>) -> ! {
+    let contents = match path.as_deref() {
+        Some("-") | None => {
+            let mut contents = String::new();
+            stdin().read_to_string(&mut contents).unwrap();
+            contents
+        }
+        Some(path) => fs::read_to_string(path).unwrap_or_default(),
+    };
+
+    let options = validate::Options {
+        check_opcodes: has_flag("--strict"),
+    };
+    let violations = validate::validate_str_with(&contents, options);
+    for violation in &violations {
+        eprintln!("{}: {}", violation.path, violation.message);
+    }
+    if !violations.is_empty() {
+        eprintln!("scratch.git: project.json is broken, refusing to commit");
+        std::process::exit(1);
+    }
+    std::process::exit(0);
+}
+
+/// Print diff and lint findings for a project as GitHub Actions annotations and exit, like
+/// `--annotations . --base origin/main`, failing if its project.json is broken
+///
+/// Compares against the previous commit without `--base`
+fn annotations_and_exit() -> ! {
+    let dir = env::args()
+        .nth(2)
+        .filter(|arg| !arg.starts_with("--"))
+        .unwrap_or(".".into());
+    let base = flag_value("--base").unwrap_or("HEAD~1".into());
+
+    match annotations::check(&PathBuf::from(dir), &base) {
+        Ok(annotations) => {
+            for annotation in &annotations {
+                println!("{annotation}");
+            }
+            let failed = annotations
+                .iter()
+                .any(|annotation| annotation.level == annotations::Level::Error);
+            std::process::exit(i32::from(failed));
+        }
+        Err(e) => {
+            eprintln!("::error::{e:#}");
+            std::process::exit(1);
         }
     }
 }
 
+/// Print lint findings for a project as SARIF and exit, like `--sarif . > results.sarif`, for
+/// uploading to code scanning
+fn sarif_and_exit() -> ! {
+    let dir = env::args().nth(2).unwrap_or(".".into());
+
+    match sarif::report(&PathBuf::from(dir)) {
+        Ok(log) => {
+            println!("{log:#}");
+            std::process::exit(0);
+        }
+        Err(e) => {
+            eprintln!("failed to lint project: {e:#}");
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Check if a command line flag was passed
+fn has_flag(name: &str) -> bool {
+    env::args().any(|arg| arg == name)
+}
+
+/// Return the value following a command line flag, e.g. `--tls-cert cert.pem`
+fn flag_value(name: &str) -> Option<String> {
+    env::args().skip_while(|arg| arg != name).nth(1)
+}
+
+/// Ask for TurboWarp's path when it can't be found automatically
+fn prompt_turbowarp_path() -> PathBuf {
+    let prompt = i18n::message_in(&i18n::system_locale(), "prompt-turbowarp-path", &[]);
+    println!("{prompt} \n\thttps://github.com/TurboWarp/desktop#advanced-customizations");
+    if daemon::is_daemon() {
+        error!("no terminal to ask for TurboWarp's path on, pass --turbowarp-path instead");
+        std::process::exit(1);
+    }
+    PathBuf::from(stdin().lock().lines().next().unwrap().unwrap())
+}
+
 fn main() {
-    let mut path = match turbowarp_path() {
+    if env::args().nth(1).is_some_and(|arg| arg == "--validate") {
+        validate_and_exit(env::args().nth(2).filter(|arg| arg != "--strict"));
+    }
+    if env::args().nth(1).is_some_and(|arg| arg == "--annotations") {
+        annotations_and_exit();
+    }
+    if env::args().nth(1).is_some_and(|arg| arg == "--sarif") {
+        sarif_and_exit();
+    }
+
+    let mut path = match flag_value("--turbowarp-path")
+        .map(PathBuf::from)
+        .or_else(turbowarp_path)
+    {
         Some(path) => path,
-        None => {
-            println!("Failed to find TurboWarp path automatically. Please paste the correct path from the following: \n\thttps://github.com/TurboWarp/desktop#advanced-customizations");
-            PathBuf::from(stdin().lock().lines().next().unwrap().unwrap())
+        None => prompt_turbowarp_path(),
+    };
+
+    if has_flag("--daemon") {
+        daemon::daemonize(&path);
+    }
+
+    let log_file = flag_value("--log-file");
+    let level = config::settings(None).log.level;
+    if let Err(e) = logging::init(
+        has_flag("--debug"),
+        level.as_deref(),
+        log_file.as_deref().map(Path::new),
+    ) {
+        println!("Failed to set up logging: {e}");
+    }
+
+    // workspaces are only left behind by crashes, and would otherwise pile up
+    match temp::sweep() {
+        Ok(0) => {}
+        Ok(removed) => info!("removed {removed} stale temporary workspaces"),
+        Err(e) => warn!("failed to sweep temporary workspaces: {e:#}"),
+    }
+
+    // --tls serves over a self-signed certificate, --tls-cert and --tls-key over your own
+    let tls = match (flag_value("--tls-cert"), flag_value("--tls-key")) {
+        (Some(cert), Some(key)) => Some(tls::server_config(Some((cert.as_ref(), key.as_ref())))),
+        _ if has_flag("--tls") => Some(tls::server_config(None)),
+        _ => None,
+    }
+    .transpose()
+    .expect("failed to set up TLS");
+
+    let _ = fs::create_dir("projects");
+    if let Err(e) = daemon::lock_workspace() {
+        error!("{e}");
+        std::process::exit(1);
+    }
+
+    let mut settings = config::settings(None).server;
+    if let Some(host) = flag_value("--host") {
+        settings.host = host;
+    }
+    if let Some(port) = flag_value("--port") {
+        settings.port = port.parse().expect("--port must be a number");
+    }
+
+    let (server, port) = discovery::bind(&settings.host, settings.port)
+        .unwrap_or_else(|e| panic!("failed to bind to {}: {e}", settings.host));
+    if port != settings.port {
+        warn!("port {} is taken, using {port} instead", settings.port);
+    }
+
+    // --socket also listens on a Unix socket or named pipe, for tools that don't want a port
+    if has_flag("--socket") {
+        let name = flag_value("--socket")
+            .filter(|name| !name.starts_with("--"))
+            .unwrap_or(ipc::DEFAULT_SOCKET.to_string());
+        if let Err(e) = ipc::listen(&name, serve) {
+            warn!("failed to listen on {name}: {e}");
         }
+    }
+
+    let address = ServerAddress {
+        host: settings.host,
+        port,
+        tls: tls.is_some(),
+        socket: ipc::socket().map(str::to_string),
+        pid: std::process::id(),
     };
+    let socket_url = address.socket_url();
+    if let Err(e) = discovery::publish(address) {
+        warn!("failed to write server discovery file: {e}");
+    }
 
-    let debug = env::args().nth(1).is_some_and(|arg| arg == "--debug");
+    let install_userscript = |path: &PathBuf| {
+        fs::read_to_string("userscript.js").and_then(|script| {
+            let script = script.replace("ws://localhost:8000", &socket_url);
+            fs::write(path.join("userscript.js"), auth::inject_token(&script))
+        })
+    };
 
-    if let Err(e) = fs::copy("userscript.js", path.join("userscript.js")) {
+    if let Err(e) = install_userscript(&path) {
         println!("Error: {}", e);
-        println!("Failed to find TurboWarp path automatically. Please paste the correct path from the following: \n\thttps://github.com/TurboWarp/desktop#advanced-customizations");
-        path = PathBuf::from(stdin().lock().lines().next().unwrap().unwrap());
+        path = prompt_turbowarp_path();
+        install_userscript(&path).expect("failed to copy userscript");
     }
 
     println!("Script copied to {}", path.to_str().unwrap());
 
-    let _ = fs::create_dir("projects");
-    let server = TcpListener::bind("127.0.0.1:8000").unwrap();
+    if let Err(e) = shutdown::install() {
+        warn!("failed to set up graceful shutdown: {e}");
+    }
+
+    // for scripts and tools that aren't the userscript, e.g. curl -H "Authorization: Bearer ..."
+    if let Err(e) = auth::write_token_file() {
+        warn!("failed to write {}: {e}", auth::TOKEN_PATH);
+    }
+
+    let project_names = project_config().lock().unwrap().project_names();
+    for name in project_names {
+        if let Err(e) = registry::project(&name).and_then(|p| p.watch()) {
+            warn!(project = %name, "failed to watch for saves: {e:#}");
+        }
+    }
+
+    // kept for as long as the server runs
+    let _settings_watcher = watcher::watch_settings()
+        .inspect_err(|e| warn!("failed to watch {}: {e:#}", config::SETTINGS_FILE))
+        .ok();
+
+    remote::start_polling();
+    backup::start();
+    sessions::start_reaper();
+
+    // asset-heavy repositories collect loose objects quickly, so pack them now and then
+    spawn(move || loop {
+        let projects = project_config().lock().unwrap().project_paths();
+        for project in projects {
+            if let Err(e) = git::maintenance(&project, false) {
+                debug!(project = %project.display(), "maintenance failed: {e:#}");
+            }
+        }
+        sleep(Duration::from_secs(60 * 60));
+    });
+
     println!(
         "Open TurboWarp Desktop to begin using scratch.git, and make sure to keep this running!"
     );
 
     for stream in server.incoming() {
-        spawn(move || match stream {
-            Ok(stream) => {
-                if let Err(err) = handle_client(stream, debug) {
-                    match err {
-                        Error::ConnectionClosed | Error::Protocol(_) | Error::Utf8 => (),
-                        e => panic!("{e}"),
-                    }
-                }
+        let tls = tls.clone();
+        spawn(move || {
+            let Ok(stream) = stream else {
+                return;
+            };
+            let connection = match tls {
+                Some(config) => Connection::tls(stream, config),
+                None => Connection::plain(stream),
+            };
+            if let Ok(connection) = connection {
+                serve(connection);
             }
-            Err(_) => (),
         });
     }
 }
+
+/// Serve a connection as WebSocket or HTTP, depending on what its first request asks for
+fn serve(mut connection: Connection) {
+    if shutdown::is_shutting_down() {
+        return;
+    }
+
+    let is_websocket = match connection.peek_head() {
+        Ok(head) => http::is_websocket(head),
+        Err(_) => return,
+    };
+
+    if !is_websocket {
+        if let Err(err) = http::handle_connection(connection) {
+            debug!("HTTP error: {err:#}");
+        }
+    } else if let Err(err) = handle_client(connection) {
+        match err {
+            Error::ConnectionClosed | Error::Protocol(_) | Error::Utf8 => (),
+            e => panic!("{e}"),
+        }
+    }
+}
@@ -0,0 +1,81 @@
+//! Reading assets without loading them into memory, so projects with huge sounds or images don't
+//! make the server's memory spike
+//!
+//! Large files are memory-mapped, so the OS pages them in as they're read and can drop them again
+//! under pressure. Small ones are read as usual, since mapping has a cost of its own.
+
+use std::fs::{self, File};
+use std::io;
+use std::ops::Deref;
+use std::path::Path;
+
+use memmap2::Mmap;
+use serde::{Serialize, Serializer};
+
+/// Files at least this big are mapped rather than read
+const MAP_THRESHOLD: u64 = 1024 * 1024;
+
+/// A file's contents, either mapped or read into memory
+#[derive(Debug)]
+pub enum Contents {
+    Mapped(Mmap),
+    Read(Vec<u8>),
+}
+
+impl Contents {
+    /// Copy the contents into memory, which mapped contents only are once this is called
+    pub fn into_vec(self) -> Vec<u8> {
+        match self {
+            Contents::Mapped(map) => map.to_vec(),
+            Contents::Read(bytes) => bytes,
+        }
+    }
+}
+
+impl Deref for Contents {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            Contents::Mapped(map) => map,
+            Contents::Read(bytes) => bytes,
+        }
+    }
+}
+
+impl AsRef<[u8]> for Contents {
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
+}
+
+impl From<Vec<u8>> for Contents {
+    fn from(bytes: Vec<u8>) -> Self {
+        Contents::Read(bytes)
+    }
+}
+
+/// Serialized like a `Vec<u8>`, as an array of bytes
+impl Serialize for Contents {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.iter())
+    }
+}
+
+/// Read a file, mapping it if it's large
+///
+/// Assets are named by their MD5, and unpacking a project skips the ones that are already there
+/// and moves new ones into place, so a mapped asset is only changed by something outside the
+/// server writing over it. Files that are written in place, like project.json, shouldn't be read
+/// with this.
+pub fn read(path: &Path) -> io::Result<Contents> {
+    let file = File::open(path)?;
+    if file.metadata()?.len() < MAP_THRESHOLD {
+        return fs::read(path).map(Contents::Read);
+    }
+    // SAFETY: the file is only read through the map, and the server never writes over an asset
+    // that's in place, since unpacking skips existing assets and renames new ones into place, so
+    // its contents stay the same while it's mapped
+    let map = unsafe { Mmap::map(&file)? };
+    Ok(Contents::Mapped(map))
+}
@@ -0,0 +1,926 @@
+//! Resolving a merge's conflicts in project terms, one at a time
+//!
+//! Git merges project.json as text, which leaves it broken whenever both sides changed it.
+//! Instead, the merged project starts as our side with the scripts, costumes, sounds and new
+//! variables only their side changed applied to it. What both sides changed differently is
+//! listed as a conflict: a whole sprite that one side deleted or both added, a script, or a
+//! costume or sound. Each conflict keeps our version until it's resolved, and once they all
+//! are, project.json is staged so the merge can be committed. Other sprite properties, like
+//! where a sprite is, keep our version.
+//!
+//! A [`MergePlan`] can take whole sprites from one side instead, which resolves every conflict
+//! in them at once, or merge them automatically with the experimental [`crdt`] engine.
+//!
+//! During a `pull --rebase`, "ours" is the remote version and "theirs" is the local commit.
+//!
+//! [`preview_merge`] works out the same merge for a branch that hasn't been merged yet, without
+//! touching the working tree.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::PathBuf;
+
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::crdt::{self, Stamp};
+use crate::diff::conflict::scripts;
+use crate::diff::parse_script::{parse_sprite, Sprite};
+use crate::diff::structs::{Diff, DiffReport};
+use crate::git;
+use crate::observer::Quiet;
+use crate::ops;
+use crate::partial::{apply_changes, keep_new, Selection};
+use crate::sb3::{Costume, Project, Target};
+
+/// File in the Git directory keeping which conflicts were resolved, until the merge is over
+const RESOLVED: &str = "PIXELPIONEERS_RESOLVED";
+
+/// Identifies something both sides of a merge changed differently
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum ConflictRef {
+    /// A whole sprite, deleted on one side and changed on the other, added on both, or changed
+    /// on both in ways that can't be merged script by script
+    #[serde(rename_all = "camelCase")]
+    Sprite { sprite: String, on_stage: bool },
+    /// A script, by the ID of its top block
+    #[serde(rename_all = "camelCase")]
+    Script {
+        sprite: String,
+        on_stage: bool,
+        script_id: String,
+    },
+    /// A costume, by its name
+    #[serde(rename_all = "camelCase")]
+    Costume {
+        sprite: String,
+        on_stage: bool,
+        name: String,
+    },
+    /// A sound, by its name
+    #[serde(rename_all = "camelCase")]
+    Sound {
+        sprite: String,
+        on_stage: bool,
+        name: String,
+    },
+}
+
+impl ConflictRef {
+    /// Name of the sprite the conflict is in, and whether it's the stage
+    fn sprite(&self) -> (&str, bool) {
+        let (ConflictRef::Sprite { sprite, on_stage }
+        | ConflictRef::Script {
+            sprite, on_stage, ..
+        }
+        | ConflictRef::Costume {
+            sprite, on_stage, ..
+        }
+        | ConflictRef::Sound {
+            sprite, on_stage, ..
+        }) = self;
+        (sprite, *on_stage)
+    }
+}
+
+/// Represents a conflict in an in-progress merge, with both sides' versions of it
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MergeConflict {
+    #[serde(flatten)]
+    pub conflict: ConflictRef,
+    /// Our version, as script text for sprites and scripts or the file name of a costume or
+    /// sound, or `None` if we deleted it
+    pub ours: Option<String>,
+    /// Their version, like [`ours`](Self::ours)
+    pub theirs: Option<String>,
+    pub resolved: bool,
+}
+
+/// How to resolve a conflict
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "choice", content = "content", rename_all = "camelCase")]
+pub enum Resolution {
+    Ours,
+    Theirs,
+    /// Use this instead: a whole sprite, a script's blocks keyed by ID, or a costume or sound
+    /// whose file is in the project, or `null` to delete it
+    Custom(Value),
+}
+
+/// How a sprite is merged
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Strategy {
+    /// Take their changes where ours didn't change the same thing, listing conflicts otherwise
+    #[default]
+    Merge,
+    /// Keep our whole sprite, leaving out their changes to it
+    Ours,
+    /// Take their whole sprite, leaving out our changes to it
+    Theirs,
+    /// Merge both sides' changes with [`crdt`], where whichever side changed a script or
+    /// property last wins instead of conflicting
+    ///
+    /// This is experimental, and a sprite deleted on either side stays deleted.
+    Automatic,
+}
+
+/// Represents how each sprite is merged, like taking ours for Sprite1 and theirs for the stage
+///
+/// ```ignore
+/// let plan = MergePlan::default().ours("Sprite1").theirs("Stage");
+/// merge::apply_plan(&pth, &plan)?;
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MergePlan {
+    /// Strategies by sprite name, where the stage is named as in the project, usually `Stage`
+    pub sprites: BTreeMap<String, Strategy>,
+}
+
+impl MergePlan {
+    /// Keep our whole version of a sprite
+    pub fn ours(mut self, sprite: impl Into<String>) -> Self {
+        self.sprites.insert(sprite.into(), Strategy::Ours);
+        self
+    }
+
+    /// Take their whole version of a sprite
+    pub fn theirs(mut self, sprite: impl Into<String>) -> Self {
+        self.sprites.insert(sprite.into(), Strategy::Theirs);
+        self
+    }
+
+    /// Merge a sprite without conflicts, as in [`Strategy::Automatic`]
+    pub fn automatic(mut self, sprite: impl Into<String>) -> Self {
+        self.sprites.insert(sprite.into(), Strategy::Automatic);
+        self
+    }
+
+    /// How a sprite is merged, which is [`Strategy::Merge`] unless the plan says otherwise
+    pub fn strategy(&self, sprite: &str) -> Strategy {
+        self.sprites.get(sprite).copied().unwrap_or_default()
+    }
+}
+
+/// Represents which conflicts of which merge were resolved
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct State {
+    /// Hash of both sides, so a new merge doesn't pick up an old one's resolutions
+    merge: String,
+    resolved: Vec<ConflictRef>,
+    #[serde(default)]
+    plan: MergePlan,
+}
+
+/// What merging a branch would do, worked out without touching the working tree
+#[derive(Debug, Serialize)]
+pub struct MergePreview {
+    /// Changes the merge would make to our side, keeping our side of every conflict
+    pub report: DiffReport,
+    pub conflicts: Vec<MergeConflict>,
+}
+
+/// Read a project.json from a revision, where one without it has no sprites
+fn read_side(pth: &PathBuf, revision: &str) -> Result<Value> {
+    let json = git::show_revision(pth, &format!("{revision}:project.json")).unwrap_or_default();
+    // like when both sides of a merge added it
+    if json.is_empty() {
+        return Ok(json!({ "targets": [] }));
+    }
+    serde_json::from_str(&json).with_context(|| format!("failed to parse {revision}:project.json"))
+}
+
+/// When the first of some revisions that exists was committed
+fn commit_time(pth: &PathBuf, revisions: &[&str]) -> Option<u64> {
+    revisions.iter().find_map(|revision| {
+        let output = git::run(vec!["log", "-1", "--format=%ct", revision], Some(pth))
+            .output()
+            .ok()
+            .filter(|output| output.status.success())?;
+        String::from_utf8_lossy(&output.stdout).trim().parse().ok()
+    })
+}
+
+/// The three versions of project.json in a conflicted merge
+struct Sides {
+    base: Value,
+    ours: Value,
+    theirs: Value,
+    /// When our and their commits were made
+    times: [u64; 2],
+}
+
+impl Sides {
+    /// Read each side of a merge from the index, or `None` if project.json isn't conflicted
+    fn read(pth: &PathBuf) -> Result<Option<Self>> {
+        if !git::conflicted_files(pth)?
+            .iter()
+            .any(|f| f == "project.json")
+        {
+            return Ok(None);
+        }
+        Ok(Some(Sides {
+            base: read_side(pth, ":1")?,
+            ours: read_side(pth, ":2")?,
+            theirs: read_side(pth, ":3")?,
+            times: [
+                commit_time(pth, &["HEAD"]).unwrap_or_default(),
+                // whichever operation is in progress left its other commit in one of these
+                commit_time(
+                    pth,
+                    &[
+                        "MERGE_HEAD",
+                        "REBASE_HEAD",
+                        "CHERRY_PICK_HEAD",
+                        "REVERT_HEAD",
+                    ],
+                )
+                .unwrap_or_default(),
+            ],
+        }))
+    }
+
+    /// Read each side of merging a branch into `HEAD` from their commits
+    fn from_branch(pth: &PathBuf, branch: &str) -> Result<Self> {
+        if !git::revision_exists(pth, branch)? {
+            return Err(anyhow!("there's no branch {branch}"));
+        }
+        let output = git::run(vec!["merge-base", "HEAD", branch], Some(pth)).output()?;
+        if !output.status.success() {
+            return Err(anyhow!("{branch} has no history in common with this one"));
+        }
+        let base = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        Ok(Sides {
+            base: read_side(pth, &base)?,
+            ours: read_side(pth, "HEAD")?,
+            theirs: read_side(pth, branch)?,
+            times: [
+                commit_time(pth, &["HEAD"]).unwrap_or_default(),
+                commit_time(pth, &[branch]).unwrap_or_default(),
+            ],
+        })
+    }
+
+    /// Merge both sides without conflicts, as in [`Strategy::Automatic`]
+    fn automatic(&self) -> Value {
+        let [ours, theirs] = self.times;
+        crdt::merge(
+            &self.base,
+            [
+                (&self.ours, Stamp::new(ours, "ours")),
+                (&self.theirs, Stamp::new(theirs, "theirs")),
+            ],
+        )
+    }
+
+    fn hash(&self) -> String {
+        format!(
+            "{:x}",
+            md5::compute(format!("{}{}", self.ours, self.theirs))
+        )
+    }
+}
+
+/// Find a sprite (or the stage) in a project.json
+fn find<'a>(project: &'a Value, sprite: &str, on_stage: bool) -> Option<&'a Value> {
+    project["targets"]
+        .as_array()?
+        .iter()
+        .find(|target| target["name"] == sprite && (target["isStage"] == true) == on_stage)
+}
+
+/// Put a sprite in a project.json in place of the one there, or delete it with `None`
+fn set_target(
+    project: &mut Value,
+    sprite: &str,
+    on_stage: bool,
+    target: Option<Value>,
+) -> Result<()> {
+    let targets = project["targets"]
+        .as_array_mut()
+        .context("project has no sprites")?;
+    let position = targets
+        .iter()
+        .position(|target| target["name"] == sprite && (target["isStage"] == true) == on_stage);
+    match (position, target) {
+        (Some(i), Some(target)) => targets[i] = target,
+        (Some(i), None) => {
+            targets.remove(i);
+        }
+        (None, Some(target)) => targets.push(target),
+        (None, None) => {}
+    }
+    Ok(())
+}
+
+/// File names of a sprite's costumes or sounds, keyed by name
+fn assets(target: &Value, key: &str) -> BTreeMap<String, String> {
+    target[key]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|asset| {
+            // sounds have the same fields that make up a costume's file name
+            let asset = serde_json::from_value::<Costume>(asset.clone()).ok()?;
+            Some((asset.name.clone(), asset.path().ok()?))
+        })
+        .collect()
+}
+
+/// Merge their changes to a sprite into ours, returning the merged sprite and the scripts and
+/// assets both sides changed differently
+fn merge_sprite(
+    sides: [&Value; 3],
+    sprite: &str,
+    on_stage: bool,
+) -> Result<(Value, Vec<ConflictRef>)> {
+    let [base, ours, theirs] = sides;
+    let parse = |target: &Value| serde_json::from_value::<Target>(target.clone());
+    let (base_target, our_target, their_target) = (parse(base)?, parse(ours)?, parse(theirs)?);
+    let (base_scripts, our_scripts, their_scripts) = (
+        base_target.scripts(),
+        our_target.scripts(),
+        their_target.scripts(),
+    );
+
+    let (mut changes, mut conflicts) = (vec![], vec![]);
+    let ids = [&base_scripts, &our_scripts, &their_scripts]
+        .into_iter()
+        .flat_map(|scripts| scripts.keys())
+        .collect::<BTreeSet<_>>();
+    for id in ids {
+        let (base, ours, theirs) = (
+            base_scripts.get(id),
+            our_scripts.get(id),
+            their_scripts.get(id),
+        );
+        if ours == theirs || theirs == base {
+            continue;
+        }
+        let script_id = id.to_string();
+        match ours == base {
+            true => changes.push(Selection::Script {
+                sprite: sprite.into(),
+                script_id,
+            }),
+            false => conflicts.push(ConflictRef::Script {
+                sprite: sprite.into(),
+                on_stage,
+                script_id,
+            }),
+        }
+    }
+
+    for key in ["costumes", "sounds"] {
+        let (base, ours, theirs) = (assets(base, key), assets(ours, key), assets(theirs, key));
+        let names = base.keys().chain(ours.keys()).chain(theirs.keys());
+        for name in names.collect::<BTreeSet<_>>() {
+            let (base, ours, theirs) = (base.get(name), ours.get(name), theirs.get(name));
+            if ours == theirs || theirs == base {
+                continue;
+            }
+            if ours == base {
+                // the old file goes and the new one comes in
+                for path in base.into_iter().chain(theirs) {
+                    changes.push(Selection::Asset {
+                        sprite: sprite.into(),
+                        path: path.clone(),
+                    });
+                }
+                continue;
+            }
+            let (sprite, name) = (sprite.to_string(), name.clone());
+            conflicts.push(match key {
+                "costumes" => ConflictRef::Costume {
+                    sprite,
+                    on_stage,
+                    name,
+                },
+                _ => ConflictRef::Sound {
+                    sprite,
+                    on_stage,
+                    name,
+                },
+            });
+        }
+    }
+
+    let mut merged = ours.clone();
+    if !changes.is_empty() {
+        apply_changes(&mut merged, theirs, &changes.iter().collect::<Vec<_>>())?;
+    }
+    keep_new(&mut merged, theirs, &["variables", "lists", "broadcasts"]);
+    Ok((merged, conflicts))
+}
+
+/// Merge their changes into ours, returning the merged project.json and what couldn't be merged
+///
+/// Sprites the plan takes from one side are taken whole, without conflicts.
+fn merge(sides: &Sides, plan: &MergePlan) -> (Value, Vec<ConflictRef>) {
+    let mut merged = sides.ours.clone();
+    let automatic = plan
+        .sprites
+        .values()
+        .any(|strategy| *strategy == Strategy::Automatic)
+        .then(|| sides.automatic());
+    let mut conflicts = vec![];
+    let names = [&sides.base, &sides.ours, &sides.theirs]
+        .into_iter()
+        .flat_map(|project| project["targets"].as_array().into_iter().flatten())
+        .map(|target| {
+            let name = target["name"].as_str().unwrap_or_default().to_string();
+            (name, target["isStage"] == true)
+        })
+        .collect::<BTreeSet<_>>();
+
+    for (sprite, on_stage) in names {
+        let [base, ours, theirs] =
+            [&sides.base, &sides.ours, &sides.theirs].map(|side| find(side, &sprite, on_stage));
+        if ours == theirs || theirs == base {
+            continue;
+        }
+        let merged_sprite = match (base, ours, theirs) {
+            _ if plan.strategy(&sprite) == Strategy::Ours => continue,
+            _ if plan.strategy(&sprite) == Strategy::Theirs => Ok(theirs.cloned()),
+            _ if plan.strategy(&sprite) == Strategy::Automatic => {
+                let automatic = automatic.as_ref().unwrap();
+                Ok(find(automatic, &sprite, on_stage).cloned())
+            }
+            _ if ours == base => Ok(theirs.cloned()),
+            (Some(base), Some(ours), Some(theirs)) => {
+                merge_sprite([base, ours, theirs], &sprite, on_stage).map(|(target, found)| {
+                    conflicts.extend(found);
+                    Some(target)
+                })
+            }
+            _ => Err(anyhow!("{sprite} was deleted or added on both sides")),
+        };
+        match merged_sprite {
+            Ok(target) => {
+                // the project came from Git, so it has a list of sprites to put this in
+                let _ = set_target(&mut merged, &sprite, on_stage, target);
+            }
+            Err(_) => conflicts.push(ConflictRef::Sprite { sprite, on_stage }),
+        }
+    }
+
+    let mut extensions = merged["extensions"].as_array().cloned().unwrap_or_default();
+    for extension in sides.theirs["extensions"].as_array().into_iter().flatten() {
+        if !extensions.contains(extension) {
+            extensions.push(extension.clone());
+        }
+    }
+    merged["extensions"] = extensions.into();
+    (merged, conflicts)
+}
+
+/// One side's version of a conflict, as described in [`MergeConflict`]
+fn version(side: &Value, conflict: &ConflictRef) -> Option<String> {
+    let (sprite, on_stage) = conflict.sprite();
+    let target = serde_json::from_value::<Target>(find(side, sprite, on_stage)?.clone()).ok()?;
+    match conflict {
+        ConflictRef::Sprite { .. } => Some(parse_sprite(Sprite::of(&target)).unwrap_or_default()),
+        ConflictRef::Script { script_id, .. } => {
+            scripts(&target).remove(script_id).map(|(_, text)| text)
+        }
+        ConflictRef::Costume { name, .. } => target
+            .costumes
+            .iter()
+            .find(|costume| &costume.name == name)
+            .and_then(|costume| costume.path().ok()),
+        ConflictRef::Sound { name, .. } => target
+            .sounds
+            .iter()
+            .find(|sound| &sound.name == name)
+            .and_then(|sound| sound.path().ok()),
+    }
+}
+
+fn state_path(pth: &PathBuf) -> PathBuf {
+    pth.join(".git").join(RESOLVED)
+}
+
+fn write_state(pth: &PathBuf, state: &State) -> Result<()> {
+    Ok(fs::write(state_path(pth), serde_json::to_string(state)?)?)
+}
+
+/// Read what's been resolved so far, if it's for this merge
+fn read_state(pth: &PathBuf, sides: &Sides) -> Option<State> {
+    fs::read_to_string(state_path(pth))
+        .ok()
+        .and_then(|state| serde_json::from_str::<State>(&state).ok())
+        .filter(|state| state.merge == sides.hash())
+}
+
+/// Stage the merged project.json if every conflict is resolved, so the merge can be committed
+fn stage_if_resolved(pth: &PathBuf, sides: &Sides, state: &State) -> Result<()> {
+    let conflicts = merge(sides, &state.plan).1;
+    if conflicts
+        .iter()
+        .all(|conflict| state.resolved.contains(conflict))
+        && !git::run(vec!["add", "project.json"], Some(pth))
+            .status()?
+            .success()
+    {
+        return Err(anyhow!("failed to stage the merged project.json"));
+    }
+    Ok(())
+}
+
+/// List the conflicts in a project's in-progress merge, empty if there's no conflicted merge
+///
+/// The first time a merge's conflicts are listed, project.json is replaced with the merged
+/// project, keeping our side of every conflict.
+pub fn list_conflicts(pth: &PathBuf) -> Result<Vec<MergeConflict>> {
+    let Some(sides) = Sides::read(pth)? else {
+        let _ = fs::remove_file(state_path(pth));
+        return Ok(vec![]);
+    };
+    let state = match read_state(pth, &sides) {
+        Some(state) => state,
+        None => {
+            let (merged, _) = merge(&sides, &MergePlan::default());
+            fs::write(pth.join("project.json"), serde_json::to_string(&merged)?)?;
+            let state = State {
+                merge: sides.hash(),
+                ..Default::default()
+            };
+            write_state(pth, &state)?;
+            state
+        }
+    };
+    let (_, conflicts) = merge(&sides, &state.plan);
+
+    Ok(conflicts
+        .into_iter()
+        .map(|conflict| MergeConflict {
+            ours: version(&sides.ours, &conflict),
+            theirs: version(&sides.theirs, &conflict),
+            resolved: state.resolved.contains(&conflict),
+            conflict,
+        })
+        .collect())
+}
+
+/// Replace a script's blocks in a sprite with others, or delete it with `null`
+fn replace_script(target: &mut Value, script_id: &str, blocks: Value) -> Result<()> {
+    let current = serde_json::from_value::<Target>(target.clone())?;
+    let old_ids = current
+        .scripts()
+        .remove(script_id)
+        .map(|blocks| blocks.into_keys().map(str::to_string).collect::<Vec<_>>())
+        .unwrap_or_default();
+    let new_blocks = match blocks {
+        Value::Object(blocks) => blocks,
+        Value::Null => Default::default(),
+        _ => return Err(anyhow!("a script has to be its blocks, keyed by ID")),
+    };
+
+    let target_blocks = target["blocks"]
+        .as_object_mut()
+        .context("sprite has no blocks")?;
+    for id in old_ids {
+        target_blocks.remove(&id);
+    }
+    for (id, block) in new_blocks {
+        if target_blocks.contains_key(&id) {
+            return Err(anyhow!("block {id} is already in another script"));
+        }
+        target_blocks.insert(id, block);
+    }
+    Ok(())
+}
+
+/// Replace a costume or sound in a sprite with another, or delete it with `null`
+fn replace_asset(
+    pth: &PathBuf,
+    target: &mut Value,
+    key: &str,
+    name: &str,
+    asset: Value,
+) -> Result<()> {
+    if !asset.is_null() {
+        let path = serde_json::from_value::<Costume>(asset.clone())?.path()?;
+        if !pth.join(&path).exists() {
+            return Err(anyhow!("{path} isn't in the project"));
+        }
+    }
+    let assets = target[key]
+        .as_array_mut()
+        .with_context(|| format!("sprite has no {key}"))?;
+    let position = assets.iter().position(|asset| asset["name"] == name);
+    match (position, asset) {
+        (Some(i), Value::Null) => {
+            assets.remove(i);
+        }
+        (Some(i), asset) => assets[i] = asset,
+        (None, Value::Null) => {}
+        (None, asset) => assets.push(asset),
+    }
+    if key == "costumes" && assets.is_empty() {
+        return Err(anyhow!("a sprite needs at least one costume"));
+    }
+    Ok(())
+}
+
+/// Apply a resolution to the merged project.json
+fn apply(
+    pth: &PathBuf,
+    merged: &mut Value,
+    sides: &Sides,
+    conflict: &ConflictRef,
+    resolution: Resolution,
+) -> Result<()> {
+    let (sprite, on_stage) = conflict.sprite();
+    if let ConflictRef::Sprite { .. } = conflict {
+        let target = match resolution {
+            Resolution::Ours => find(&sides.ours, sprite, on_stage).cloned(),
+            Resolution::Theirs => find(&sides.theirs, sprite, on_stage).cloned(),
+            Resolution::Custom(Value::Null) => None,
+            Resolution::Custom(target) => Some(target),
+        };
+        return set_target(merged, sprite, on_stage, target);
+    }
+
+    let target = merged["targets"]
+        .as_array_mut()
+        .and_then(|targets| {
+            targets
+                .iter_mut()
+                .find(|target| target["name"] == sprite && (target["isStage"] == true) == on_stage)
+        })
+        .with_context(|| format!("{sprite} isn't in the merged project"))?;
+    let side = match resolution {
+        Resolution::Ours => &sides.ours,
+        Resolution::Theirs => &sides.theirs,
+        Resolution::Custom(value) => {
+            return match conflict {
+                ConflictRef::Script { script_id, .. } => replace_script(target, script_id, value),
+                ConflictRef::Costume { name, .. } => {
+                    replace_asset(pth, target, "costumes", name, value)
+                }
+                ConflictRef::Sound { name, .. } => {
+                    replace_asset(pth, target, "sounds", name, value)
+                }
+                ConflictRef::Sprite { .. } => unreachable!(),
+            };
+        }
+    };
+
+    let source = find(side, sprite, on_stage)
+        .with_context(|| format!("{sprite} isn't on that side"))?
+        .clone();
+    let changes = match conflict {
+        ConflictRef::Script { script_id, .. } => vec![Selection::Script {
+            sprite: sprite.into(),
+            script_id: script_id.clone(),
+        }],
+        ConflictRef::Costume { name, .. } | ConflictRef::Sound { name, .. } => {
+            let key = match conflict {
+                ConflictRef::Costume { .. } => "costumes",
+                _ => "sounds",
+            };
+            // whichever file is there now goes, and the chosen one comes in
+            [assets(target, key), assets(&source, key)]
+                .into_iter()
+                .filter_map(|mut assets| assets.remove(name))
+                .map(|path| Selection::Asset {
+                    sprite: sprite.into(),
+                    path,
+                })
+                .collect()
+        }
+        ConflictRef::Sprite { .. } => vec![],
+    };
+    apply_changes(target, &source, &changes.iter().collect::<Vec<_>>())
+}
+
+/// Resolve a conflict in a project's in-progress merge, returning the conflicts listed again
+///
+/// Once every conflict is resolved, project.json is staged, so the merge can be committed.
+pub fn resolve_conflict(
+    pth: &PathBuf,
+    conflict: &ConflictRef,
+    resolution: Resolution,
+) -> Result<Vec<MergeConflict>> {
+    let conflicts = list_conflicts(pth)?;
+    if !conflicts.iter().any(|listed| &listed.conflict == conflict) {
+        return Err(anyhow!("that isn't a conflict in this merge"));
+    }
+    let sides = Sides::read(pth)?.context("project.json isn't conflicted")?;
+
+    let mut merged = serde_json::from_str(&fs::read_to_string(pth.join("project.json"))?)
+        .context("merged project.json is broken")?;
+    apply(pth, &mut merged, &sides, conflict, resolution)?;
+    fs::write(pth.join("project.json"), serde_json::to_string(&merged)?)?;
+
+    let mut state = read_state(pth, &sides).unwrap_or_default();
+    state.resolved = conflicts
+        .into_iter()
+        .filter(|listed| listed.resolved)
+        .map(|listed| listed.conflict)
+        .collect();
+    if !state.resolved.contains(conflict) {
+        state.resolved.push(conflict.clone());
+    }
+    write_state(pth, &state)?;
+
+    stage_if_resolved(pth, &sides, &state)?;
+    list_conflicts(pth)
+}
+
+/// Merge sprites in a project's in-progress merge as a plan says, returning the conflicts
+/// listed again
+///
+/// Sprites taken from one side replace what's in the merged project.json, along with anything
+/// already resolved in them. Sprites the plan merges keep their resolutions, and a sprite left
+/// out of a later plan is merged again from both sides.
+pub fn apply_plan(pth: &PathBuf, plan: &MergePlan) -> Result<Vec<MergeConflict>> {
+    let conflicts = list_conflicts(pth)?;
+    let sides = Sides::read(pth)?.context("project.json isn't conflicted")?;
+    let mut state = read_state(pth, &sides).unwrap_or_default();
+
+    let mut merged = serde_json::from_str(&fs::read_to_string(pth.join("project.json"))?)
+        .context("merged project.json is broken")?;
+    let (remerged, _) = merge(&sides, &MergePlan::default());
+    let automatic = sides.automatic();
+    let sprites = plan.sprites.keys().chain(state.plan.sprites.keys());
+    for sprite in sprites.cloned().collect::<BTreeSet<_>>() {
+        let strategy = plan.strategy(&sprite);
+        if strategy == state.plan.strategy(&sprite) {
+            continue;
+        }
+        let side = match strategy {
+            Strategy::Merge => &remerged,
+            Strategy::Ours => &sides.ours,
+            Strategy::Theirs => &sides.theirs,
+            Strategy::Automatic => &automatic,
+        };
+        for on_stage in [false, true] {
+            let target = find(side, &sprite, on_stage).cloned();
+            if target.is_some() || find(&merged, &sprite, on_stage).is_some() {
+                set_target(&mut merged, &sprite, on_stage, target)?;
+            }
+        }
+    }
+    fs::write(pth.join("project.json"), serde_json::to_string(&merged)?)?;
+
+    // resolutions in sprites that changed strategy no longer apply
+    state.resolved = conflicts
+        .into_iter()
+        .filter(|listed| {
+            let (sprite, _) = listed.conflict.sprite();
+            listed.resolved && plan.strategy(sprite) == state.plan.strategy(sprite)
+        })
+        .map(|listed| listed.conflict)
+        .collect();
+    state.merge = sides.hash();
+    state.plan = plan.clone();
+    write_state(pth, &state)?;
+
+    stage_if_resolved(pth, &sides, &state)?;
+    list_conflicts(pth)
+}
+
+/// Work out what merging a branch into the current one would do, without changing anything
+///
+/// The merge is the same one [`list_conflicts`] starts from once the branch is merged, so the
+/// report is what the merge would change and the conflicts are what would be left to resolve.
+pub fn preview_merge(pth: &PathBuf, branch: &str) -> Result<MergePreview> {
+    let sides = Sides::from_branch(pth, branch)?;
+    let (merged, conflicts) = merge(&sides, &MergePlan::default());
+    let diff = |project: &Value| -> Result<Diff> {
+        let project = serde_json::from_value::<Project>(project.clone())
+            .context("merged project.json is broken")?;
+        Ok(Diff::new(project))
+    };
+    let report = ops::compare(pth, &diff(&sides.ours)?, &diff(&merged)?, &mut Quiet)?;
+
+    Ok(MergePreview {
+        report,
+        conflicts: conflicts
+            .into_iter()
+            .map(|conflict| MergeConflict {
+                ours: version(&sides.ours, &conflict),
+                theirs: version(&sides.theirs, &conflict),
+                resolved: false,
+                conflict,
+            })
+            .collect(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(opcode: &str) -> Value {
+        json!({
+            "opcode": opcode,
+            "next": null,
+            "parent": null,
+            "inputs": {},
+            "fields": {},
+            "shadow": false,
+            "topLevel": true,
+        })
+    }
+
+    fn sprite(name: &str, opcode: &str) -> Value {
+        json!({"isStage": false, "name": name, "blocks": {"a": block(opcode)}})
+    }
+
+    fn project(sprites: &[Value]) -> Value {
+        let stage = json!({"isStage": true, "name": "Stage", "blocks": {}});
+        let targets = std::iter::once(stage).chain(sprites.iter().cloned());
+        json!({"targets": targets.collect::<Vec<_>>(), "extensions": []})
+    }
+
+    fn opcode<'a>(project: &'a Value, sprite: &str) -> Option<&'a str> {
+        find(project, sprite, false)?["blocks"]["a"]["opcode"].as_str()
+    }
+
+    /// Both sides changed the Cat sprite's script, and only theirs changed the Dog sprite's,
+    /// with their side committed later
+    fn sides() -> Sides {
+        let base = [
+            sprite("Cat", "motion_movesteps"),
+            sprite("Dog", "looks_say"),
+        ];
+        let ours = [
+            sprite("Cat", "motion_turnright"),
+            sprite("Dog", "looks_say"),
+        ];
+        let theirs = [
+            sprite("Cat", "motion_turnleft"),
+            sprite("Dog", "looks_think"),
+        ];
+        Sides {
+            base: project(&base),
+            ours: project(&ours),
+            theirs: project(&theirs),
+            times: [1, 2],
+        }
+    }
+
+    #[test]
+    fn scripts_both_sides_changed_conflict() {
+        let (merged, conflicts) = merge(&sides(), &MergePlan::default());
+        let conflict = ConflictRef::Script {
+            sprite: "Cat".into(),
+            on_stage: false,
+            script_id: "a".into(),
+        };
+        assert_eq!(conflicts, [conflict]);
+        assert_eq!(opcode(&merged, "Cat"), Some("motion_turnright"));
+        assert_eq!(opcode(&merged, "Dog"), Some("looks_think"));
+    }
+
+    #[test]
+    fn whole_sprites_come_from_the_side_planned() {
+        let plan = MergePlan::default().ours("Cat").ours("Dog");
+        let (merged, conflicts) = merge(&sides(), &plan);
+        assert!(conflicts.is_empty());
+        assert_eq!(opcode(&merged, "Cat"), Some("motion_turnright"));
+        assert_eq!(opcode(&merged, "Dog"), Some("looks_say"));
+
+        let (merged, conflicts) = merge(&sides(), &MergePlan::default().theirs("Cat"));
+        assert!(conflicts.is_empty());
+        assert_eq!(opcode(&merged, "Cat"), Some("motion_turnleft"));
+    }
+
+    #[test]
+    fn automatic_sprites_take_the_later_change() {
+        let (merged, conflicts) = merge(&sides(), &MergePlan::default().automatic("Cat"));
+        assert!(conflicts.is_empty());
+        assert_eq!(opcode(&merged, "Cat"), Some("motion_turnleft"));
+        // sprites without a strategy still merge as usual
+        assert_eq!(opcode(&merged, "Dog"), Some("looks_think"));
+
+        let mut sides = sides();
+        sides.times = [2, 1];
+        let (merged, _) = merge(&sides, &MergePlan::default().automatic("Cat"));
+        assert_eq!(opcode(&merged, "Cat"), Some("motion_turnright"));
+    }
+
+    #[test]
+    fn automatic_sprites_deleted_on_one_side_stay_deleted() {
+        let mut sides = sides();
+        sides.ours = project(&[sprite("Dog", "looks_say")]);
+        let (merged, conflicts) = merge(&sides, &MergePlan::default());
+        assert!(conflicts.contains(&ConflictRef::Sprite {
+            sprite: "Cat".into(),
+            on_stage: false,
+        }));
+
+        let (merged_automatically, conflicts) =
+            merge(&sides, &MergePlan::default().automatic("Cat"));
+        assert!(conflicts.is_empty());
+        assert_eq!(find(&merged_automatically, "Cat", false), None);
+        assert_eq!(merged["targets"], merged_automatically["targets"]);
+    }
+}
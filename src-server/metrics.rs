@@ -0,0 +1,133 @@
+//! Counters and histograms served at `/metrics` in Prometheus' text format
+
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use crate::git;
+use crate::sessions;
+
+/// Upper bounds of the diff duration buckets, in seconds
+const DURATION_BUCKETS: [f64; 10] = [0.01, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0];
+
+/// Counts observations into fixed buckets, like a Prometheus histogram
+pub struct Histogram {
+    /// Cumulative counts aren't kept, so each bucket only holds what landed in it
+    buckets: [AtomicU64; DURATION_BUCKETS.len()],
+    count: AtomicU64,
+    sum_micros: AtomicU64,
+}
+
+impl Histogram {
+    const fn new() -> Self {
+        Histogram {
+            buckets: [const { AtomicU64::new(0) }; DURATION_BUCKETS.len()],
+            count: AtomicU64::new(0),
+            sum_micros: AtomicU64::new(0),
+        }
+    }
+
+    pub fn observe(&self, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+        if let Some(bucket) = DURATION_BUCKETS.iter().position(|bound| seconds <= *bound) {
+            self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        }
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum_micros
+            .fetch_add(duration.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    fn render(&self, out: &mut String, name: &str, help: &str) {
+        let _ = writeln!(out, "# HELP {name} {help}\n# TYPE {name} histogram");
+        let mut cumulative = 0;
+        for (bound, bucket) in DURATION_BUCKETS.iter().zip(&self.buckets) {
+            cumulative += bucket.load(Ordering::Relaxed);
+            let _ = writeln!(out, "{name}_bucket{{le=\"{bound}\"}} {cumulative}");
+        }
+        let count = self.count.load(Ordering::Relaxed);
+        let sum = self.sum_micros.load(Ordering::Relaxed) as f64 / 1e6;
+        let _ = writeln!(out, "{name}_bucket{{le=\"+Inf\"}} {count}");
+        let _ = writeln!(out, "{name}_sum {sum}\n{name}_count {count}");
+    }
+}
+
+/// How long diffs between two project versions take
+pub static DIFF_DURATION: Histogram = Histogram::new();
+/// project.json files that couldn't be parsed, e.g. from a half-written save
+pub static PARSE_FAILURES: AtomicU64 = AtomicU64::new(0);
+/// Commits made through the server
+pub static COMMITS: AtomicU64 = AtomicU64::new(0);
+
+fn counter(out: &mut String, name: &str, help: &str, value: u64) {
+    let _ = writeln!(
+        out,
+        "# HELP {name} {help}\n# TYPE {name} counter\n{name} {value}"
+    );
+}
+
+fn gauge(out: &mut String, name: &str, help: &str, value: f64) {
+    let _ = writeln!(
+        out,
+        "# HELP {name} {help}\n# TYPE {name} gauge\n{name} {value}"
+    );
+}
+
+/// Render every metric in Prometheus' text exposition format
+pub fn render() -> String {
+    let mut out = String::new();
+    DIFF_DURATION.render(
+        &mut out,
+        "scratch_git_diff_duration_seconds",
+        "Time taken to diff two versions of a project",
+    );
+    counter(
+        &mut out,
+        "scratch_git_parse_failures_total",
+        "project.json files that failed to parse",
+        PARSE_FAILURES.load(Ordering::Relaxed),
+    );
+    counter(
+        &mut out,
+        "scratch_git_commits_total",
+        "Commits created",
+        COMMITS.load(Ordering::Relaxed),
+    );
+
+    let cache = git::revision_cache_stats();
+    counter(
+        &mut out,
+        "scratch_git_revision_cache_hits_total",
+        "Revision lookups answered from the cache",
+        cache.hits,
+    );
+    counter(
+        &mut out,
+        "scratch_git_revision_cache_misses_total",
+        "Revision lookups that had to run Git",
+        cache.misses,
+    );
+    let lookups = cache.hits + cache.misses;
+    gauge(
+        &mut out,
+        "scratch_git_revision_cache_hit_ratio",
+        "Share of revision lookups answered from the cache",
+        if lookups == 0 {
+            0.0
+        } else {
+            cache.hits as f64 / lookups as f64
+        },
+    );
+    gauge(
+        &mut out,
+        "scratch_git_revision_cache_entries",
+        "Revisions currently cached",
+        cache.entries as f64,
+    );
+    gauge(
+        &mut out,
+        "scratch_git_sessions",
+        "Connected WebSocket clients",
+        sessions::list().len() as f64,
+    );
+    out
+}
@@ -0,0 +1,73 @@
+//! Rewriting project.json into a canonical form before it's written to a repo
+//!
+//! Editors save project.json as one line, with floats that drift by tiny amounts and fields
+//! that change on every save, so Git diffs of it are unreadable. The canonical form has sorted
+//! keys, one value per line, rounded floats and no volatile fields, which means saving a project
+//! again without changing it leaves project.json untouched.
+
+use anyhow::Result;
+use serde_json::{Number, Value};
+
+use crate::formats;
+
+/// Decimal places floats are rounded to, well past what's visible in the editor
+const FLOAT_PRECISION: i32 = 9;
+
+/// Normalize a project.json
+pub fn normalize(json: &[u8]) -> Result<Vec<u8>> {
+    let mut project = serde_json::from_slice::<Value>(json)?;
+    // which fields are volatile depends on the editor that saved it
+    let format = formats::detect(&project["meta"]);
+    for path in format.volatile() {
+        strip(&mut project, path);
+    }
+    round_floats(&mut project);
+
+    // serde_json's maps are sorted by key, so blocks end up ordered by their IDs
+    let mut normalized = serde_json::to_vec_pretty(&project)?;
+    normalized.push(b'\n');
+    Ok(normalized)
+}
+
+fn strip(value: &mut Value, path: &[&str]) {
+    match path {
+        [] => {}
+        [field] => {
+            if let Some(object) = value.as_object_mut() {
+                object.remove(*field);
+            }
+        }
+        [field, rest @ ..] => {
+            if let Some(child) = value.get_mut(*field) {
+                strip(child, rest);
+            }
+        }
+    }
+}
+
+/// Round every float, and write whole ones as integers
+///
+/// Values stored in variables and lists are left exactly as they are, since scripts may
+/// depend on them
+fn round_floats(value: &mut Value) {
+    match value {
+        Value::Number(number) if number.is_f64() => {
+            let scale = 10f64.powi(FLOAT_PRECISION);
+            let rounded = (number.as_f64().unwrap() * scale).round() / scale;
+            if rounded.fract() == 0.0 && rounded.abs() < (1u64 << 53) as f64 {
+                *value = Value::from(rounded as i64);
+            } else if let Some(rounded) = Number::from_f64(rounded) {
+                *number = rounded;
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(round_floats),
+        Value::Object(object) => {
+            for (key, child) in object.iter_mut() {
+                if key != "variables" && key != "lists" {
+                    round_floats(child);
+                }
+            }
+        }
+        _ => {}
+    }
+}
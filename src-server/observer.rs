@@ -0,0 +1,49 @@
+//! Callbacks for following along as projects are diffed and committed
+//!
+//! Diffing and committing take an [`Observer`], so the server, the CLI and the bindings all
+//! hear about progress and warnings the same way, instead of scraping logs. Every callback does
+//! nothing by default, and any `FnMut(&str, usize, usize) -> Result<()>` closure is an observer
+//! that only follows progress.
+
+use anyhow::Result;
+
+/// Follows along with diffing and committing
+pub trait Observer {
+    /// How far along an operation is, as the operation's name, steps done, and total steps
+    ///
+    /// Failing stops operations that can be stopped, like when a client disconnects.
+    fn on_progress(&mut self, _operation: &str, _done: usize, _total: usize) -> Result<()> {
+        Ok(())
+    }
+
+    /// A sprite's scripts were parsed and diffed, named as in the newer project
+    fn on_sprite_parsed(&mut self, _sprite: &str) {}
+
+    /// An asset file was hashed into the repository, like `83a9787d4cb6f3b7632b4ddfebf74367.wav`
+    fn on_asset_hashed(&mut self, _path: &str) {}
+
+    /// A commit was made, with its ID and message
+    fn on_commit_created(&mut self, _commit: &str, _message: &str) {}
+
+    /// Something the user should know about that didn't stop the operation, like a cloud
+    /// variable changing or a thumbnail failing to render
+    fn on_warning(&mut self, _warning: &str) {}
+}
+
+impl<F: FnMut(&str, usize, usize) -> Result<()>> Observer for F {
+    fn on_progress(&mut self, operation: &str, done: usize, total: usize) -> Result<()> {
+        self(operation, done, total)
+    }
+}
+
+/// An observer that ignores everything
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Quiet;
+
+impl Observer for Quiet {}
+
+/// Follow progress with a closure, which is only needed to help closures infer their argument
+/// types
+pub fn progress<F: FnMut(&str, usize, usize) -> Result<()>>(f: F) -> F {
+    f
+}
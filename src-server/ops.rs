@@ -0,0 +1,1927 @@
+//! Project operations shared by every server transport
+
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::Instant;
+
+use anyhow::{anyhow, Context, Result};
+use itertools::Itertools;
+use regex_static::{once_cell::sync::Lazy, Regex};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tracing::{info, warn};
+
+use crate::asset_store::{self, AssetStore, ASSETS_REF};
+use crate::cancel::CancelToken;
+use crate::chat;
+use crate::config::{self, gh_token, CommitStyle, Settings};
+use crate::diff::parse_script::{parse_sprite, Sprite};
+use crate::diff::label;
+use crate::diff::{rename_arguments, rename_variables};
+use crate::diff::structs::{
+    AssetChange, AssetChangeType, BlockChanges, ChangeKind, CommitLine, Diff, DiffReport,
+    ScriptChangeType,
+};
+use crate::diff::template::asset_kind;
+use crate::error::Error;
+use crate::git::{self, Identity};
+use crate::hooks::{self, Hook};
+use crate::html_report;
+use crate::lint;
+use crate::mapped::{self, Contents};
+use crate::metrics;
+use crate::normalize::normalize;
+use crate::observer::{Observer, Quiet};
+use crate::optimize;
+use crate::partial::{self, Selection};
+use crate::patch::{self, Patch};
+use crate::provenance;
+use crate::review;
+use crate::sb3::{self, get_assets, AssetPathError, Project, Target};
+use crate::scratchblocks;
+use crate::split;
+use crate::temp::TempWorkspace;
+use crate::thumbnail;
+use crate::validate::{self, Invalid, Violation};
+
+/// Represents how committing a project's saved changes went
+#[derive(Debug)]
+pub enum CommitOutcome {
+    /// The commit was made with this generated message
+    Committed(String),
+    /// Changes couldn't be staged
+    StageFailed,
+    /// No commit identity is configured
+    NeedsIdentity,
+    /// Git refused to make the commit, e.g. because there was nothing to commit
+    CommitFailed,
+    /// The commit's message couldn't be generated, so nothing was committed
+    MessageFailed,
+    /// The saved project.json is broken, or has lint issues when those block commits, so nothing
+    /// was committed
+    Invalid(Vec<Violation>),
+    /// A pre-commit hook command failed, so nothing was committed
+    HookFailed,
+}
+
+impl CommitOutcome {
+    /// Numeric code used by the `commit` command for failures
+    pub fn code(&self) -> i32 {
+        match self {
+            CommitOutcome::Committed(_) => 0,
+            CommitOutcome::StageFailed => -1,
+            CommitOutcome::NeedsIdentity => -2,
+            CommitOutcome::CommitFailed => -3,
+            CommitOutcome::MessageFailed => -4,
+            CommitOutcome::Invalid(_) => -5,
+            CommitOutcome::HookFailed => -6,
+        }
+    }
+}
+
+/// Follows along with an operation, like a closure taking the operation's name, steps done, and
+/// total steps, or any other [`Observer`]
+pub type Progress<'a> = dyn Observer + 'a;
+
+static PUSH_PROGRESS: Lazy<Regex> =
+    regex_static::lazy_regex!(r"(Counting|Compressing|Writing) objects:\s+\d+% \((\d+)/(\d+)\)");
+
+/// How many assets to stage per `git add`, so hashing them can be reported as it happens
+const STAGE_BATCH: usize = 25;
+
+/// Options for committing a project
+#[derive(Debug, Default)]
+pub struct CommitOptions<'a> {
+    /// Attribute the commit to someone other than the configured identity
+    pub author: Option<&'a Identity>,
+    /// Keep binary assets in the shared asset store instead of the repository
+    pub asset_store: bool,
+    /// Only commit these changes, leaving the rest uncommitted
+    pub selection: Option<&'a [Selection]>,
+    /// Title the commit with this, keeping the generated changes as its body
+    pub title: Option<&'a str>,
+    /// Who made the changes, credited with an `Edited-by:` trailer when they aren't the author
+    pub editor: Option<&'a str>,
+    /// The editor's SB3, repacked when optimizing renames assets so it has what was committed
+    pub sb3: Option<&'a Path>,
+}
+
+/// Trailer crediting whoever made a commit's changes, for when several people share one
+/// identity
+pub const EDITOR_TRAILER: &str = "Edited-by: ";
+
+/// Parse a saved project.json, counting failures for `/metrics`
+pub fn parse_project(contents: &str) -> Result<Project> {
+    Ok(sb3::parse(contents, "project.json").inspect_err(|_| {
+        metrics::PARSE_FAILURES.fetch_add(1, Ordering::Relaxed);
+    })?)
+}
+
+/// Lay out a commit message from generated changes, leaving out ignored sprites
+///
+/// A title replaces the generated subject, with every change listed in the body
+fn commit_message(settings: &Settings, lines: Vec<CommitLine>, title: Option<&str>) -> String {
+    let lines = lines
+        .into_iter()
+        .filter(|line| !settings.is_ignored_line(line))
+        .collect::<Vec<_>>();
+    let changes = lines
+        .iter()
+        .map(|line| line.text.clone())
+        .collect::<Vec<_>>();
+
+    let max = settings.commit.max_subject_length;
+    if let Some(title) = title.map(str::trim).filter(|title| !title.is_empty()) {
+        let message = format!("{title}\n\n{}", changes.join("\n"));
+        return limit_subject(message.trim_end(), max);
+    }
+
+    // everything changed was in ignored sprites
+    if changes.is_empty() {
+        return settings.commit.word("update project").to_string();
+    }
+
+    match settings.commit.style {
+        CommitStyle::List => list_message(&changes, max),
+        CommitStyle::Lines => {
+            let message = format!("{}\n\n{}", changes[0], changes[1..].join("\n"));
+            limit_subject(message.trim_end(), max)
+        }
+        CommitStyle::Conventional => limit_subject(&conventional_message(&lines), max),
+    }
+}
+
+/// Shorten text to at most `max` characters, ending it with `…` if anything was cut
+fn truncate(text: &str, max: usize) -> String {
+    if text.chars().count() <= max {
+        return text.to_string();
+    }
+    let mut truncated = text.chars().take(max.saturating_sub(1)).collect::<String>();
+    truncated.push('…');
+    truncated
+}
+
+/// List every change on one line, or as many as fit in `max` characters with the rest in the
+/// body, like `Sprite1: +3 blocks, …and 12 more`
+fn list_message(changes: &[String], max: usize) -> String {
+    let subject = changes.join(", ");
+    if max == 0 || subject.chars().count() <= max {
+        return subject;
+    }
+    // with nothing else to count, a change that's too long is cut short like any other subject
+    if let [change] = changes {
+        return limit_subject(change, max);
+    }
+
+    let overflow = |shown: usize| format!(", …and {} more", changes.len() - shown);
+    let shown = (1..changes.len())
+        .rev()
+        .find(|&shown| {
+            let subject = changes[..shown].join(", ") + &overflow(shown);
+            subject.chars().count() <= max
+        })
+        .unwrap_or(1);
+    let overflow = overflow(shown);
+    let room = max.saturating_sub(overflow.chars().count());
+    let shown_changes = changes[..shown].join(", ");
+    // a change cut short is listed in full with the rest
+    let rest = match shown_changes.chars().count() <= room {
+        true => &changes[shown..],
+        false => changes,
+    };
+    let subject = truncate(&shown_changes, room);
+    format!("{subject}{overflow}\n\n{}", rest.join("\n"))
+}
+
+/// Keep a message's subject within `max` characters, moving a subject that's cut short to the
+/// start of the body
+fn limit_subject(message: &str, max: usize) -> String {
+    let (subject, body) = message.split_once("\n\n").unwrap_or((message, ""));
+    if max == 0 || subject.chars().count() <= max {
+        return message.to_string();
+    }
+    format!("{}\n\n{subject}\n{body}", truncate(subject, max))
+        .trim_end()
+        .to_string()
+}
+
+/// Lay out changes as a Conventional Commits message, like `feat(Sprite1): +3 blocks`
+///
+/// Anything added makes a `feat`, other script changes a `refactor`, and anything else, like
+/// removed assets, a `chore`. Changes to more than one sprite are summarized in the subject and
+/// listed in the body
+fn conventional_message(lines: &[CommitLine]) -> String {
+    let kind = |kind: ChangeKind| match kind {
+        ChangeKind::Addition => "feat",
+        ChangeKind::Script => "refactor",
+        ChangeKind::Other => "chore",
+    };
+    let parts = |line: &CommitLine| {
+        let scope = match line.scope.as_deref() {
+            Some(sprite) if sprite.ends_with(label::STAGE_SUFFIX) => "stage".to_string(),
+            // a scope ends at the first `)`, and parsers split the subject at the first `:`
+            Some(sprite) => label::clean(sprite).replace([':', '(', ')'], ""),
+            None => "project".to_string(),
+        };
+        (scope, line.changes.join(", "))
+    };
+
+    if let [line] = lines {
+        let (scope, description) = parts(line);
+        return format!("{}({scope}): {description}", kind(line.kind));
+    }
+
+    // the most significant kind of change names the whole commit
+    let kind = kind(ChangeKind::most_significant(
+        lines.iter().map(|line| line.kind),
+    ));
+    let parts = lines.iter().map(parts).collect::<Vec<_>>();
+    let scopes = parts.iter().map(|(scope, _)| scope).join(", ");
+    let body = parts
+        .iter()
+        .map(|(scope, description)| format!("- {scope}: {description}"))
+        .join("\n");
+    format!("{kind}: update {scopes}\n\n{body}")
+}
+
+/// Check that every asset a project uses is present and matches its MD5 hash
+pub fn verify_assets(pth: &PathBuf) -> Result<Vec<Violation>> {
+    let project = parse_project(&fs::read_to_string(pth.join("project.json"))?)?;
+    Ok(validate::verify_assets(pth, &project))
+}
+
+/// Lint a project's saved project.json with its configured options
+pub fn lint(pth: &PathBuf) -> Result<Vec<Violation>> {
+    let project = parse_project(&fs::read_to_string(pth.join("project.json"))?)?;
+    let options = config::settings(Some(pth)).lint.options();
+    Ok(lint::lint(&project, options))
+}
+
+/// Read an asset from the working directory, the asset store, or a revision, in that order
+///
+/// Assets are named by their MD5, so a copy from anywhere has the same contents. Large files are
+/// mapped rather than read into memory
+pub fn read_asset(
+    pth: &PathBuf,
+    revision: &str,
+    asset: &str,
+    store: &AssetStore,
+) -> Option<Contents> {
+    mapped::read(&pth.join(asset))
+        .ok()
+        .or_else(|| store.read(pth, asset))
+        .or_else(|| git::show_file(pth, revision, asset).ok().map(Contents::from))
+}
+
+/// Assets read at once by [`read_assets`], so each doesn't wait on the Git process before it
+const READ_WORKERS: usize = 8;
+
+/// Read many assets like [`read_asset`], a few at a time, keeping them in order
+pub fn read_assets(
+    pth: &PathBuf,
+    revision: &str,
+    assets: Vec<String>,
+    store: &AssetStore,
+) -> Result<Vec<(String, Contents)>> {
+    let next = AtomicUsize::new(0);
+    let workers = READ_WORKERS.min(assets.len());
+    let mut contents = thread::scope(|scope| {
+        let handles = (0..workers)
+            .map(|_| {
+                let (next, assets) = (&next, &assets);
+                scope.spawn(move || {
+                    let mut read = vec![];
+                    while let Some(asset) = assets.get(next.fetch_add(1, Ordering::Relaxed)) {
+                        read.push((asset.clone(), read_asset(pth, revision, asset, store)));
+                    }
+                    read
+                })
+            })
+            .collect::<Vec<_>>();
+        handles
+            .into_iter()
+            .flat_map(|handle| handle.join().unwrap())
+            .collect::<HashMap<_, _>>()
+    });
+
+    let (read, missing): (Vec<_>, Vec<_>) = assets
+        .into_iter()
+        .map(|asset| match contents.remove(&asset).flatten() {
+            Some(read) => Ok((asset, read)),
+            None => Err(asset),
+        })
+        .partition_result();
+    match missing.as_slice() {
+        [] => Ok(read),
+        [asset] => Err(anyhow!("{asset} is missing")),
+        _ => Err(anyhow!(
+            "{} assets are missing: {}",
+            missing.len(),
+            missing.join(", ")
+        )),
+    }
+}
+
+/// Export a sprite and its assets as a SPRITE3, as it is now or as it was in a revision
+pub fn export_sprite(
+    pth: &PathBuf,
+    sprite: &str,
+    revision: Option<&str>,
+    sprite3: &Path,
+) -> Result<()> {
+    let json = match revision {
+        Some(revision) => git::show_revision(pth, &format!("{revision}:project.json"))?,
+        None => fs::read_to_string(pth.join("project.json"))?,
+    };
+    let project = parse_project(&json).context("failed to read project")?;
+    let target = project
+        .target(sprite)
+        .filter(|target| !target.is_stage)
+        .with_context(|| format!("no sprite named {sprite}"))?;
+
+    let store = AssetStore::default_store()?;
+    let paths = target.asset_paths().unique().collect();
+    let assets = read_assets(pth, revision.unwrap_or("HEAD"), paths, &store)?;
+
+    sb3::pack_sprite(target, assets, sprite3)
+}
+
+/// Export a whole project and its assets as an SB3 that opens in the editor, as it was in a
+/// revision
+///
+/// Revisions stored one file per sprite are put back together into a single project.json. Every
+/// asset the project used at that revision has to be found, so nothing is written if any are
+/// missing.
+pub fn export_revision(pth: &PathBuf, revision: &str, sb3: &Path) -> Result<()> {
+    let commit = git::resolve(pth, revision)?;
+    let json = git::show_revision(pth, &format!("{commit}:project.json"))?;
+    let project = parse_project(&json).context("failed to read project")?;
+
+    let store = AssetStore::default_store()?;
+    let paths = get_assets(&project).into_iter().unique().collect();
+    let assets = read_assets(pth, &commit, paths, &store)
+        .with_context(|| format!("failed to export {revision}"))?;
+    for (asset, contents) in &assets {
+        // Git LFS pointers are stored in place of assets that were never fetched
+        if !sb3::is_correctly_named(asset, contents) {
+            warn!("{asset} in {revision} doesn't match its MD5 hash, the editor may not load it");
+        }
+    }
+
+    sb3::write(sb3, json.as_bytes(), assets)
+}
+
+/// Bundle the changes between two revisions with the assets they added, for applying to
+/// another copy of the project with [`apply_patch`]
+pub fn export_patch(pth: &PathBuf, from: &str, to: &str, bundle: &Path) -> Result<()> {
+    let read = |revision: &str| {
+        git::parse_revision::<Value>(pth, &format!("{revision}:project.json"))
+            .with_context(|| format!("failed to parse project.json in {revision}"))
+    };
+    let (old, new) = (read(from)?, read(to)?);
+    let patch = Patch::between(git::resolve(pth, from)?, &old, git::resolve(pth, to)?, &new);
+
+    // assets the project already had are in the other copy too
+    let old_assets = get_assets(&serde_json::from_value(old)?)
+        .into_iter()
+        .collect::<HashSet<_>>();
+    let added = patch
+        .asset_paths()?
+        .into_iter()
+        .filter(|asset| !old_assets.contains(asset))
+        .sorted()
+        .collect();
+    let store = AssetStore::default_store()?;
+    let assets = read_assets(pth, to, added, &store)?;
+
+    patch::write(bundle, &patch, assets)
+}
+
+/// Apply a bundle from [`export_patch`] to a project, committing the changes as
+/// `apply patch from <hash>: Sprite1, Sprite2`
+///
+/// The patch is applied to the last commit for the commit, and to the saved project.json so
+/// other uncommitted changes stay uncommitted. Nothing is written if it conflicts with either,
+/// and if committing fails the saved project.json is put back with nothing left staged.
+/// Returns the commit message
+pub fn apply_patch(pth: &PathBuf, bundle: &Path) -> Result<String> {
+    let (patch, assets) = patch::read(bundle)?;
+    for (asset, contents) in &assets {
+        if !sb3::is_correctly_named(asset, contents) {
+            return Err(anyhow!("{asset} in the patch doesn't match its MD5 hash"));
+        }
+    }
+
+    let head = git::parse_revision::<Value>(pth, "HEAD:project.json")?;
+    let saved_json = fs::read(pth.join("project.json"))?;
+    let saved = serde_json::from_slice::<Value>(&saved_json)?;
+    let head = normalize(&serde_json::to_vec(&patch.apply(&head)?)?)?;
+    let patched = normalize(&serde_json::to_vec(&patch.apply(&saved)?)?)?;
+    validate::check(std::str::from_utf8(&patched)?)?;
+
+    let mut written = vec![];
+    for (asset, contents) in assets {
+        if !pth.join(&asset).exists() {
+            fs::write(pth.join(&asset), contents)?;
+            written.push(asset);
+        }
+    }
+
+    let from = patch.from.get(..7).unwrap_or(&patch.from);
+    let sprites = patch.sprites.iter().map(|sprite| sprite.name.as_str());
+    let message = format!("apply patch from {from}: {}", sprites.format(", "));
+
+    let project_files = if split::is_enabled(pth) {
+        vec![split::STAGE_FILE, split::SPRITES_DIR]
+    } else {
+        vec!["project.json"]
+    };
+    let added = patch.asset_paths()?;
+
+    // the patch is undone if committing fails, so it isn't left staged or half committed
+    let transaction = git::Transaction::begin(pth);
+    let committed = (|| -> Result<bool> {
+        fs::write(pth.join("project.json"), &head)?;
+        if split::is_enabled(pth) {
+            split::write(pth)?;
+        }
+        // stored assets are ignored, so adding them fails and they're left out
+        let mut args = vec!["add", "--"];
+        args.extend(added.iter().map(String::as_str));
+        git::run(args, Some(pth)).status()?;
+        let mut args = vec!["add", "--all", "--"];
+        args.extend(&project_files);
+        if !git::run(args, Some(pth)).status()?.success() {
+            return Ok(false);
+        }
+        let mut commit = git::run(vec!["commit", "-m", &message], Some(pth));
+        if let Some(identity) = config::settings(Some(pth)).identity.as_ref() {
+            identity.apply(&mut commit);
+        }
+        Ok(commit.status()?.success())
+    })();
+
+    if let Ok(true) = committed {
+        transaction.finish();
+        // everything else saved stays uncommitted, as it was before
+        fs::write(pth.join("project.json"), patched)?;
+        if split::is_enabled(pth) {
+            split::write(pth)?;
+        }
+        return Ok(message);
+    }
+
+    transaction.rollback()?;
+    let mut args = vec!["reset", "--quiet", "HEAD", "--"];
+    args.extend(&project_files);
+    args.extend(written.iter().map(String::as_str));
+    git::run(args, Some(pth)).status()?;
+    for asset in &written {
+        fs::remove_file(pth.join(asset))?;
+    }
+    fs::write(pth.join("project.json"), saved_json)?;
+    if split::is_enabled(pth) {
+        split::write(pth)?;
+    }
+    committed?;
+    Err(anyhow!("failed to commit the patch's changes"))
+}
+
+/// Bring back a sprite as it was in a revision, committing it as `restore Sprite1 from <hash>`
+///
+/// Only the restored sprite is committed. It's spliced into the saved project as well, so other
+/// uncommitted changes stay uncommitted. Returns the commit message
+pub fn restore_sprite(pth: &PathBuf, revision: &str, sprite_name: &str) -> Result<String> {
+    let name = label::name(sprite_name);
+    let old = git::parse_revision::<Value>(pth, &format!("{revision}:project.json"))
+        .with_context(|| format!("failed to parse project.json in {revision}"))?;
+    let old_targets = old["targets"].as_array().cloned().unwrap_or_default();
+    let sprite = old_targets
+        .iter()
+        .find(|target| target["name"] == name)
+        .cloned()
+        .with_context(|| format!("{name} isn't in {revision}"))?;
+    let old_stage = old_targets.iter().find(|target| target["isStage"] == true);
+
+    let store = AssetStore::default_store()?;
+    let assets = serde_json::from_value::<Target>(sprite.clone())?
+        .asset_paths()
+        .unique()
+        .collect::<Vec<_>>();
+    let missing = assets
+        .iter()
+        .filter(|asset| !pth.join(asset).exists())
+        .cloned()
+        .collect();
+    for (asset, contents) in read_assets(pth, revision, missing, &store)? {
+        fs::write(pth.join(asset), contents)?;
+    }
+
+    let splice = |json: &str| -> Result<Vec<u8>> {
+        let mut project = serde_json::from_str::<Value>(json)?;
+        let targets = project
+            .get_mut("targets")
+            .and_then(Value::as_array_mut)
+            .context("project has no targets")?;
+        match targets.iter_mut().find(|target| target["name"] == name) {
+            Some(target) => *target = sprite.clone(),
+            None => targets.push(sprite.clone()),
+        }
+        // the sprite's scripts may use global variables that were deleted since
+        let stage = targets.iter_mut().find(|target| target["isStage"] == true);
+        if let (Some(stage), Some(old_stage)) = (stage, old_stage) {
+            partial::keep_new(stage, old_stage, &["variables", "lists", "broadcasts"]);
+        }
+        normalize(&serde_json::to_vec(&project)?)
+    };
+
+    let short = git::run(vec!["rev-parse", "--short", revision], Some(pth)).output()?;
+    let message = format!(
+        "restore {name} from {}",
+        String::from_utf8_lossy(&short.stdout).trim()
+    );
+
+    let saved = fs::read_to_string(pth.join("project.json"))?;
+    let head = git::show_revision(pth, "HEAD:project.json")?;
+    fs::write(pth.join("project.json"), splice(&head)?)?;
+    let project_files = if split::is_enabled(pth) {
+        split::write(pth)?;
+        vec!["add", "--all", "--", split::STAGE_FILE, split::SPRITES_DIR]
+    } else {
+        vec!["add", "--", "project.json"]
+    };
+
+    // stored assets are ignored, so adding them fails and they're left out
+    let mut args = vec!["add", "--"];
+    args.extend(assets.iter().map(String::as_str));
+    git::run(args, Some(pth)).status()?;
+    let mut committed = git::run(project_files, Some(pth)).status()?.success();
+    if committed {
+        let mut commit = git::run(vec!["commit", "-m", &message], Some(pth));
+        if let Some(identity) = config::settings(Some(pth)).identity.as_ref() {
+            identity.apply(&mut commit);
+        }
+        committed = commit.status()?.success();
+    }
+
+    // everything else saved stays uncommitted, as it was before
+    let saved = match committed {
+        true => splice(&saved)?,
+        false => saved.into_bytes(),
+    };
+    fs::write(pth.join("project.json"), saved)?;
+    if split::is_enabled(pth) {
+        split::write(pth)?;
+    }
+    if !committed {
+        return Err(anyhow!("failed to commit restoring {name}"));
+    }
+    // the last unzip gets the sprite too, so diffs don't report it as an uncommitted change
+    if let Ok(unzipped) = fs::read_to_string(pth.join("project.old.json")) {
+        fs::write(pth.join("project.old.json"), splice(&unzipped)?)?;
+    }
+
+    Ok(message)
+}
+
+/// Add a SPRITE3's sprite to a project's working directory, returning the name it was given
+///
+/// Like in the editor, a sprite whose name is taken gets a number added to it
+pub fn import_sprite(pth: &PathBuf, sprite3: &Path) -> Result<String> {
+    let (mut sprite, assets) = sb3::unpack_sprite(sprite3)?;
+    let mut project = parse_project(&fs::read_to_string(pth.join("project.json"))?)?;
+
+    let name = sprite.name.clone();
+    for n in 2.. {
+        if project.target(&sprite.name).is_none() {
+            break;
+        }
+        sprite.name = format!("{name}{n}");
+    }
+    sprite.is_stage = false;
+    let top_layer = project
+        .targets
+        .iter()
+        .filter_map(|target| target.extra.get("layerOrder")?.as_u64())
+        .max()
+        .unwrap_or(0);
+    sprite
+        .extra
+        .insert("layerOrder".into(), (top_layer + 1).into());
+
+    for (asset, contents) in assets {
+        // assets are named by their contents, and one already there may be mapped
+        if !pth.join(&asset).exists() {
+            fs::write(pth.join(asset), contents)?;
+        }
+    }
+    let name = sprite.name.clone();
+    project.targets.push(sprite);
+    fs::write(
+        pth.join("project.json"),
+        normalize(&serde_json::to_vec(&project)?)?,
+    )?;
+    Ok(name)
+}
+
+/// Represents a costume or sound that's in an earlier commit but not the saved project
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeletedAsset {
+    /// The newest commit that still has it
+    pub revision: String,
+    pub sprite: String,
+    pub name: String,
+    pub path: String,
+    pub is_sound: bool,
+}
+
+/// How many commits back deleted assets are searched for
+const ASSET_SEARCH_DEPTH: &str = "500";
+
+/// Search history for costumes and sounds that were deleted, by name or file hash
+///
+/// Each asset is listed once for each sprite it was in, from the newest commit that has it
+pub fn find_deleted_assets(pth: &PathBuf, query: &str) -> Result<Vec<DeletedAsset>> {
+    let query = query.trim().to_lowercase();
+    let saved = parse_project(&fs::read_to_string(pth.join("project.json"))?)?;
+    let mut found = HashSet::new();
+    for target in &saved.targets {
+        for path in target.asset_paths() {
+            found.insert((target.name.clone(), path));
+        }
+    }
+
+    let commits = git::run(
+        vec!["log", "--format=%H", "-n", ASSET_SEARCH_DEPTH],
+        Some(pth),
+    )
+    .output()?;
+    let mut deleted = vec![];
+    for revision in String::from_utf8_lossy(&commits.stdout).lines() {
+        // commits from before the project was added, or with a broken project.json
+        let object = format!("{revision}:project.json");
+        let Ok(project) = git::parse_revision::<Project>(pth, &object) else {
+            continue;
+        };
+        for target in &project.targets {
+            let costumes = target
+                .costumes
+                .iter()
+                .filter_map(|costume| Some((&costume.name, costume.path().ok()?, false)));
+            let sounds = target
+                .sounds
+                .iter()
+                .filter_map(|sound| Some((&sound.name, sound.path().ok()?, true)));
+            for (name, path, is_sound) in costumes.chain(sounds) {
+                let matches = name.to_lowercase().contains(&query) || path.starts_with(&query);
+                if matches && found.insert((target.name.clone(), path.clone())) {
+                    deleted.push(DeletedAsset {
+                        revision: revision.to_string(),
+                        sprite: target.display_name(),
+                        name: name.clone(),
+                        path,
+                        is_sound,
+                    });
+                }
+            }
+        }
+    }
+    Ok(deleted)
+}
+
+/// Add a costume or sound from a revision back to a sprite in the saved project, returning the
+/// name it was given
+///
+/// Like in the editor, an asset whose name is taken in the sprite gets a number added to it
+pub fn restore_asset(pth: &PathBuf, revision: &str, path: &str, sprite: &str) -> Result<String> {
+    let json = git::show_revision(pth, &format!("{revision}:project.json"))?;
+    let old = parse_project(&json).context("failed to read project")?;
+    let mut project = parse_project(&fs::read_to_string(pth.join("project.json"))?)?;
+    let sprite = label::name(sprite);
+
+    // the sprite it was deleted from has the right details if it's still around
+    let targets = || {
+        let same = old.targets.iter().filter(|target| target.name == sprite);
+        same.chain(old.targets.iter().filter(|target| target.name != sprite))
+    };
+    let is_path = |asset_path: Result<String, AssetPathError>| asset_path.is_ok_and(|p| p == path);
+    let costume = targets().find_map(|t| t.costumes.iter().find(|c| is_path(c.path())));
+    let sound = targets().find_map(|t| t.sounds.iter().find(|s| is_path(s.path())));
+
+    let store = AssetStore::default_store()?;
+    let contents =
+        read_asset(pth, revision, path, &store).with_context(|| format!("{path} is missing"))?;
+
+    let target = project
+        .targets
+        .iter_mut()
+        .find(|target| target.name == sprite)
+        .with_context(|| format!("no sprite named {sprite}"))?;
+    let unused_name = |name: &str, taken: Vec<&String>| {
+        (1..)
+            .map(|n| match n {
+                1 => name.to_string(),
+                n => format!("{name}{n}"),
+            })
+            .find(|name| !taken.contains(&name))
+            .unwrap()
+    };
+    let name = match (costume, sound) {
+        (Some(costume), _) => {
+            let mut costume = costume.clone();
+            let taken = target.costumes.iter().map(|c| &c.name).collect();
+            costume.name = unused_name(&costume.name, taken);
+            target.costumes.push(costume.clone());
+            costume.name
+        }
+        (None, Some(sound)) => {
+            let mut sound = sound.clone();
+            let taken = target.sounds.iter().map(|s| &s.name).collect();
+            sound.name = unused_name(&sound.name, taken);
+            target.sounds.push(sound.clone());
+            sound.name
+        }
+        (None, None) => return Err(anyhow!("{path} isn't in {revision}")),
+    };
+
+    // an asset that's already there may be what was read, mapped, so it isn't written over
+    if !pth.join(path).exists() {
+        fs::write(pth.join(path), contents)?;
+    }
+    fs::write(
+        pth.join("project.json"),
+        normalize(&serde_json::to_vec(&project)?)?,
+    )?;
+    Ok(name)
+}
+
+/// Find asset files in a project's repository or working directory that project.json
+/// doesn't use
+pub fn unused_assets(pth: &PathBuf) -> Result<Vec<String>> {
+    let project = parse_project(&fs::read_to_string(pth.join("project.json"))?)?;
+    let used = get_assets(&project).into_iter().collect::<HashSet<_>>();
+
+    let tracked = git::run(vec!["ls-files", "-z"], Some(pth)).output()?.stdout;
+    let tracked = String::from_utf8_lossy(&tracked)
+        .split('\0')
+        .map(str::to_string)
+        .collect::<Vec<_>>();
+    let present = fs::read_dir(pth)?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.file_name().to_string_lossy().to_string());
+
+    Ok(tracked
+        .into_iter()
+        .chain(present)
+        .filter(|name| sb3::is_asset_name(name) && !used.contains(name))
+        .sorted()
+        .dedup()
+        .collect())
+}
+
+/// Delete assets project.json doesn't use and commit their removal, returning what was removed
+pub fn clean_unused_assets(pth: &PathBuf) -> Result<Vec<String>> {
+    let unused = unused_assets(pth)?;
+    if unused.is_empty() {
+        return Ok(unused);
+    }
+
+    let tracked = git::run(vec!["ls-files", "-z"], Some(pth)).output()?.stdout;
+    let tracked = String::from_utf8_lossy(&tracked).to_string();
+    let tracked = tracked.split('\0').collect::<HashSet<_>>();
+    for asset in &unused {
+        let _ = fs::remove_file(pth.join(asset));
+    }
+
+    // assets that were never committed only needed deleting
+    let removed = unused
+        .iter()
+        .map(String::as_str)
+        .filter(|asset| tracked.contains(asset))
+        .collect::<Vec<_>>();
+    if !removed.is_empty() {
+        let mut args = vec!["rm", "--cached", "--quiet", "--"];
+        args.extend(&removed);
+        if !git::run(args, Some(pth)).status()?.success() {
+            return Err(anyhow!("failed to untrack unused assets"));
+        }
+
+        let message = match removed.len() {
+            1 => "remove 1 unused asset".to_string(),
+            n => format!("remove {n} unused assets"),
+        };
+        // only the removals are committed, so other staged changes are left alone
+        let mut args = vec!["commit", "-m", &message, "--"];
+        args.extend(&removed);
+        let mut commit = git::run(args, Some(pth));
+        if let Some(identity) = config::settings(Some(pth)).identity.as_ref() {
+            identity.apply(&mut commit);
+        }
+        if !commit.status()?.success() {
+            return Err(anyhow!("failed to commit removing unused assets"));
+        }
+    }
+
+    Ok(unused)
+}
+
+/// Represents an asset file only old commits have
+#[derive(Debug, Serialize)]
+pub struct PurgedAsset {
+    pub path: String,
+    /// Space its versions take up in the repository, in bytes
+    pub size: u64,
+}
+
+/// Represents assets that can be purged from a project's history, or were
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AssetPurge {
+    /// Largest first
+    pub assets: Vec<PurgedAsset>,
+    /// Space the assets take up, which is roughly what purging them frees
+    pub savings: u64,
+    /// Passed back to purge exactly these assets
+    pub confirmation: String,
+    /// Space that was actually freed, once purged
+    pub freed: Option<u64>,
+}
+
+/// List assets that were removed from every branch and tag but are still in their history,
+/// and purge them from it once confirmed
+///
+/// Without `confirmation` nothing changes, and the assets that would be purged are returned
+/// with how much space purging saves. Passing the returned `confirmation` back purges them,
+/// as long as the list is still the same. Only assets taking at least `min_size` bytes are
+/// listed.
+///
+/// Purging rewrites history, so commits that had the assets get new hashes, other clones have
+/// to be cloned again, and the next push has to be forced. Commits that had them can still be
+/// checked out, but without those costumes and sounds.
+pub fn purge_assets(
+    pth: &PathBuf,
+    min_size: u64,
+    confirmation: Option<&str>,
+) -> Result<AssetPurge> {
+    let project = parse_project(&fs::read_to_string(pth.join("project.json"))?)?;
+    let used = get_assets(&project).into_iter().collect::<HashSet<_>>();
+    // purging keeps what any branch or tag has now, so none of them lose assets
+    let current = git::files_at_refs(pth)?;
+    let assets = git::history_files(pth)?
+        .into_iter()
+        .filter(|(path, size)| {
+            sb3::is_asset_name(path)
+                && *size >= min_size
+                && !used.contains(path)
+                && !current.contains(path)
+        })
+        .map(|(path, size)| PurgedAsset { path, size })
+        .sorted_by(|a, b| b.size.cmp(&a.size).then_with(|| a.path.cmp(&b.path)))
+        .collect::<Vec<_>>();
+
+    let head = git::resolve(pth, "HEAD").unwrap_or_default();
+    let paths = assets
+        .iter()
+        .map(|asset| asset.path.clone())
+        .collect::<Vec<_>>();
+    let mut purge = AssetPurge {
+        savings: assets.iter().map(|asset| asset.size).sum(),
+        confirmation: format!("{:x}", md5::compute(format!("{head}{}", paths.join(",")))),
+        assets,
+        freed: None,
+    };
+    let Some(confirmation) = confirmation else {
+        return Ok(purge);
+    };
+    if confirmation != purge.confirmation {
+        return Err(anyhow!(
+            "the assets to purge changed since they were listed, so list them again"
+        ));
+    }
+    if git::is_dirty(pth)? {
+        return Err(anyhow!("commit or discard changes before purging assets"));
+    }
+    if paths.is_empty() {
+        purge.freed = Some(0);
+        return Ok(purge);
+    }
+
+    let before = git::repo_size(pth)?;
+    git::remove_from_history(pth, &paths)?;
+    purge.freed = Some(before.saturating_sub(git::repo_size(pth)?));
+    Ok(purge)
+}
+
+/// Commit a project's saved changes with a message generated from its diff
+///
+/// Reports `assets` while Git hashes saved assets, then `diff` while generating the message
+pub fn commit(
+    pth: &PathBuf,
+    options: CommitOptions,
+    progress: &mut Progress,
+) -> Result<CommitOutcome> {
+    if options.selection.is_none() {
+        return commit_saved(pth, options, progress);
+    }
+
+    // the partial project.json is only for the commit, so every change is still saved after
+    let saved = fs::read(pth.join("project.json"))?;
+    let outcome = commit_saved(pth, options, progress);
+    fs::write(pth.join("project.json"), saved)?;
+    if split::is_enabled(pth) {
+        split::write(pth).context("failed to split project")?;
+    }
+    outcome
+}
+
+/// Commit only the selected changes from a diff of the saved project, like "just Sprite1" or
+/// one of its scripts
+///
+/// Unselected changes are committed as they were in the last commit, so they're still there to
+/// commit later. Fails if a selected change isn't in `report`, or can't be committed without
+/// others
+pub fn commit_selection(
+    pth: &PathBuf,
+    report: &DiffReport,
+    selection: &[Selection],
+    options: CommitOptions,
+    progress: &mut Progress,
+) -> Result<CommitOutcome> {
+    partial::check(selection, report)?;
+    let options = CommitOptions {
+        selection: Some(selection),
+        ..options
+    };
+    commit(pth, options, progress)
+}
+
+/// Find what stops a saved project.json from being committed, like broken JSON, missing
+/// assets, or lint issues when those block commits
+fn commit_violations(pth: &PathBuf, saved_json: &str) -> Result<Vec<Violation>> {
+    if let Err(Invalid(violations)) = validate::check(saved_json) {
+        return Ok(violations);
+    }
+    let saved = parse_project(saved_json)?;
+    let broken_assets = validate::verify_assets(pth, &saved);
+    if !broken_assets.is_empty() {
+        return Ok(broken_assets);
+    }
+    let lint_settings = config::settings(Some(pth)).lint;
+    if lint_settings.on_commit {
+        return Ok(lint::lint(&saved, lint_settings.options()));
+    }
+    Ok(vec![])
+}
+
+/// Return the project.json a partial commit would have, with only the selected changes
+fn partial_json(pth: &PathBuf, saved_json: &str, selection: &[Selection]) -> Result<String> {
+    let last = git::parse_revision(pth, "HEAD:project.json");
+    let last = last.context("failed to parse last commit")?;
+    let saved = serde_json::from_str(saved_json)?;
+    let project = partial::apply(&last, &saved, selection)?;
+    let json = normalize(&serde_json::to_vec(&project)?)?;
+    Ok(String::from_utf8(json)?)
+}
+
+/// Represents the commit that committing the saved project would make
+#[derive(Debug, Serialize)]
+pub struct CommitPreview {
+    pub message: String,
+    /// Every change in the commit, compared to the last commit
+    pub report: DiffReport,
+    /// Asset files the commit would add
+    pub added_assets: Vec<String>,
+    /// Asset files the commit would delete
+    pub removed_assets: Vec<String>,
+}
+
+/// Work out the commit [`commit`] would make with the same options, without touching the repo
+///
+/// Fails with [`Invalid`] if the project can't be committed
+pub fn preview_commit(
+    pth: &PathBuf,
+    options: CommitOptions,
+    progress: &mut Progress,
+) -> Result<CommitPreview> {
+    let saved_json = fs::read_to_string(pth.join("project.json"))?;
+    let violations = commit_violations(pth, &saved_json)?;
+    if !violations.is_empty() {
+        return Err(Invalid(violations).into());
+    }
+    let json = match options.selection {
+        Some(selection) => partial_json(pth, &saved_json, selection)?,
+        None => saved_json,
+    };
+
+    let last = Diff::from_revision(pth, "HEAD:project.json")?;
+    let new = Diff::new(parse_project(&json)?);
+    let report = compare(pth, &last, &new, progress)?;
+
+    let (last_assets, new_assets) = (get_assets(&last.data), get_assets(&new.data));
+    let difference = |assets: &[String], other: &[String]| {
+        assets
+            .iter()
+            .filter(|asset| !other.contains(asset))
+            .unique()
+            .cloned()
+            .collect::<Vec<_>>()
+    };
+
+    let settings = config::settings(Some(pth));
+    let mut message = commit_message(&settings, report.commits.clone(), options.title);
+    if let Some(editor) = options.editor {
+        message = format!("{message}\n\n{EDITOR_TRAILER}{}", label::clean(editor));
+    }
+    Ok(CommitPreview {
+        message,
+        added_assets: difference(&new_assets, &last_assets),
+        removed_assets: difference(&last_assets, &new_assets),
+        report,
+    })
+}
+
+fn commit_saved(
+    pth: &PathBuf,
+    options: CommitOptions,
+    progress: &mut Progress,
+) -> Result<CommitOutcome> {
+    let saved_json = fs::read_to_string(pth.join("project.json"))?;
+    let violations = commit_violations(pth, &saved_json)?;
+    if !violations.is_empty() {
+        return Ok(CommitOutcome::Invalid(violations));
+    }
+    let settings = config::settings(Some(pth));
+
+    // partial commits restore project.json afterwards, which would undo renamed assets
+    let optimized = match settings.optimize.on_commit && options.selection.is_none() {
+        true => optimize::project(pth, &settings.optimize).context("failed to optimize assets")?,
+        false => optimize::Report::default(),
+    };
+    let saved_json = match optimized.assets.is_empty() {
+        true => saved_json,
+        false => fs::read_to_string(pth.join("project.json"))?,
+    };
+    let saved = parse_project(&saved_json)?;
+
+    let partial = match options.selection {
+        Some(selection) => Some(partial_json(pth, &saved_json, selection)?),
+        None => None,
+    };
+
+    // hooks get the changes in the commit, which are only worked out if there are any
+    let has_hooks = hooks::is_set(pth, Hook::PreCommit) || hooks::is_set(pth, Hook::PostCommit);
+    let hook_report = match has_hooks {
+        true => {
+            let json = partial.as_deref().unwrap_or(&saved_json);
+            let last = Diff::from_revision(pth, "HEAD:project.json")?;
+            let new = Diff::new(parse_project(json)?);
+            Some(compare(pth, &last, &new, progress)?)
+        }
+        false => None,
+    };
+    if let Some(report) = &hook_report {
+        if let Err(e) = hooks::run(pth, Hook::PreCommit, report) {
+            warn!("{e:#}");
+            progress.on_warning(&format!("{e:#}"));
+            return Ok(CommitOutcome::HookFailed);
+        }
+    }
+
+    let current_diff = Diff::new(parse_project(&fs::read_to_string(
+        pth.join("project.old.json"),
+    )?)?);
+    let mut project_assets = get_assets(&saved);
+    let mut unselected_assets = vec![];
+    let new_diff = match &partial {
+        Some(json) => {
+            let partial = parse_project(json)?;
+            let used = get_assets(&partial);
+            // assets only unselected changes use wait for the commit with those changes
+            unselected_assets = project_assets
+                .iter()
+                .filter(|asset| !used.contains(asset))
+                .cloned()
+                .collect::<Vec<_>>();
+            project_assets.extend(used);
+            Diff::new(partial)
+        }
+        None => Diff::new(saved),
+    };
+
+    // remove all assets that aren't used in the json
+    for change in new_diff.assets(&current_diff, None)? {
+        // another sprite may still use the same file
+        if !project_assets.contains(&change.path) {
+            let _ = fs::remove_file(pth.join(change.path));
+        }
+    }
+    let unused_assets = fs::read_dir(pth)?
+        .filter_map(|res| res.ok())
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.extension()
+                .is_some_and(|ext| ext == "svg" || ext == "png" || ext == "mp3" || ext == "wav")
+        })
+        .filter(|path| {
+            !project_assets.contains(&path.file_name().unwrap().to_string_lossy().to_string())
+        });
+
+    for asset in unused_assets {
+        let _ = fs::remove_file(asset);
+    }
+
+    if options.asset_store {
+        AssetStore::default_store()?
+            .store(pth, &project_assets)
+            .context("failed to store assets")?;
+    }
+
+    let saved_assets = project_assets
+        .into_iter()
+        .filter(|asset| pth.join(asset).exists())
+        .collect::<Vec<_>>();
+
+    // assets outside the sparse checkout can't be staged until they're part of it
+    if git::is_sparse(pth)? {
+        git::checkout_paths(pth, &saved_assets).context("failed to add assets")?;
+    }
+
+    // stored assets are ignored, so there's nothing for Git to hash
+    if !options.asset_store {
+        for (i, batch) in saved_assets.chunks(STAGE_BATCH).enumerate() {
+            let _ = progress.on_progress("assets", i * STAGE_BATCH, saved_assets.len());
+            // ignored assets make this fail, but `git add .` below is what decides success
+            let mut args = vec!["add", "--"];
+            args.extend(batch.iter().map(|asset| asset.as_str()));
+            if git::run(args, Some(pth)).status()?.success() {
+                for asset in batch {
+                    progress.on_asset_hashed(asset);
+                }
+            }
+        }
+        let _ = progress.on_progress("assets", saved_assets.len(), saved_assets.len());
+    }
+
+    if let Some(json) = &partial {
+        fs::write(pth.join("project.json"), json)?;
+    }
+    if split::is_enabled(pth) {
+        split::write(pth).context("failed to split project")?;
+    }
+
+    if !git::run(vec!["add", "."], Some(pth)).status()?.success() {
+        return Ok(CommitOutcome::StageFailed);
+    }
+    if !unselected_assets.is_empty() {
+        let mut args = vec!["reset", "--quiet", "HEAD", "--"];
+        args.extend(unselected_assets.iter().map(String::as_str));
+        if !git::run(args, Some(pth)).status()?.success() {
+            return Ok(CommitOutcome::StageFailed);
+        }
+    }
+
+    let author = options.author.or(settings.identity.as_ref());
+
+    // the commit is made, then given its message, and shouldn't be left without one
+    let transaction = git::Transaction::begin(pth);
+    let mut commit = git::run(vec!["commit", "-m", "temporary"], Some(pth));
+    if let Some(author) = author {
+        author.apply(&mut commit);
+    }
+    let commit = commit.output()?;
+
+    if !commit.status.success() {
+        let stderr = String::from_utf8_lossy(&commit.stderr);
+        if stderr.contains("git config --global user.email") {
+            return Ok(CommitOutcome::NeedsIdentity);
+        }
+        return Ok(CommitOutcome::CommitFailed);
+    }
+
+    let previous_revision = Diff::from_revision(pth, "HEAD~1:project.json")?;
+    let changes = previous_revision.commits_with_progress(pth, &new_diff, progress)?;
+    let mut commit_message = commit_message(&settings, changes, options.title);
+    if let Some(summary) = optimized.summary() {
+        commit_message = format!("{commit_message}\n\n{summary}");
+    }
+    if let Some(editor) = options.editor {
+        commit_message = format!(
+            "{commit_message}\n\n{EDITOR_TRAILER}{}",
+            label::clean(editor)
+        );
+    }
+
+    let mut commit = git::run(vec!["commit", "--amend", "-m", &commit_message], Some(pth));
+    if let Some(author) = author {
+        author.apply(&mut commit);
+    }
+
+    if !commit.output()?.status.success() {
+        return Ok(CommitOutcome::MessageFailed);
+    }
+    transaction.finish();
+
+    // the editor still has the original assets, which its next save would bring back
+    if !optimized.assets.is_empty() {
+        if let Some(sb3) = options.sb3 {
+            if options.asset_store {
+                AssetStore::default_store()?.restore(pth, &saved_assets)?;
+            }
+            sb3::pack(pth, sb3).context("failed to repack optimized project")?;
+            progress.on_warning("assets were optimized, so reload the project before saving it");
+        }
+        fs::copy(pth.join("project.json"), pth.join("project.old.json"))?;
+    }
+
+    metrics::COMMITS.fetch_add(1, Ordering::Relaxed);
+    if let Ok(head) = git::resolve(pth, "HEAD") {
+        progress.on_commit_created(&head, &commit_message);
+    }
+    // the history view renders missing thumbnails itself, so this failing isn't fatal
+    if let Err(e) = thumbnail::get(pth, "HEAD") {
+        warn!("failed to render thumbnail: {e:#}");
+        progress.on_warning(&format!("failed to render thumbnail: {e:#}"));
+    }
+    if let Some(report) = &hook_report {
+        hooks::run_after(pth, Hook::PostCommit, report);
+    }
+    if let Err(e) = chat::commit(pth) {
+        warn!("failed to send notifications: {e:#}");
+        progress.on_warning(&format!("failed to send notifications: {e:#}"));
+    }
+    Ok(CommitOutcome::Committed(commit_message))
+}
+
+/// Ref pointing at the commit the current working session started from
+const SESSION_REF: &str = "refs/pixelpioneers/session";
+
+/// Mark the last commit as where a working session starts, returning its ID
+///
+/// Starting a new session replaces the old one's start
+pub fn start_session(pth: &PathBuf) -> Result<String> {
+    let head = git::resolve(pth, "HEAD")?;
+    if !git::run(vec!["update-ref", SESSION_REF, &head], Some(pth))
+        .status()?
+        .success()
+    {
+        return Err(anyhow!("failed to mark session start"));
+    }
+    Ok(head)
+}
+
+/// Squash every commit made since the session started, like auto-commits, into one with a
+/// message for all of their changes, then end the session
+///
+/// Commits that were pushed or merged can't be squashed. Returns the new commit's message, or
+/// `None` if there was nothing to squash
+pub fn finish_session(pth: &PathBuf) -> Result<Option<String>> {
+    let start = git::resolve(pth, SESSION_REF).context("no session was started")?;
+    let head = git::resolve(pth, "HEAD")?;
+    let range = format!("{start}..{head}");
+    let count = |args: &[&str]| -> Result<usize> {
+        let mut command = vec!["rev-list", "--count", &range];
+        command.extend(args);
+        let output = git::run(command, Some(pth)).output()?;
+        Ok(String::from_utf8_lossy(&output.stdout).trim().parse()?)
+    };
+
+    let ancestor = vec!["merge-base", "--is-ancestor", &start, &head];
+    let is_ancestor = git::run(ancestor, Some(pth)).status()?.success();
+    if !is_ancestor {
+        return Err(anyhow!("the session's start is no longer in this branch"));
+    }
+    let commits = count(&[])?;
+    if commits < 2 {
+        git::run(vec!["update-ref", "-d", SESSION_REF], Some(pth)).status()?;
+        return Ok(None);
+    }
+    if count(&["--merges"])? > 0 {
+        return Err(anyhow!("the session has merges, so it can't be squashed"));
+    }
+    if count(&["--not", "--remotes"])? < commits {
+        return Err(anyhow!("the session was pushed, so it can't be squashed"));
+    }
+    // squashing commits whatever is staged, so only committed changes go in
+    if !git::run(vec!["diff", "--cached", "--quiet"], Some(pth))
+        .status()?
+        .success()
+    {
+        return Err(anyhow!("there are staged changes"));
+    }
+
+    let settings = config::settings(Some(pth));
+    let changes = Diff::from_revision(pth, &format!("{start}:project.json"))?
+        .commits(pth, &Diff::from_revision(pth, "HEAD:project.json")?)?;
+    let message = commit_message(&settings, changes, None);
+
+    // the session's commits are put back as they were if squashing fails
+    let transaction = git::Transaction::begin(pth);
+    git::reset(pth, &start, git::ResetMode::Soft)?;
+    let mut commit = git::run(vec!["commit", "-m", &message], Some(pth));
+    if let Some(identity) = settings.identity.as_ref() {
+        identity.apply(&mut commit);
+    }
+    if !commit.status()?.success() {
+        return Err(anyhow!("failed to commit the squashed session"));
+    }
+    transaction.finish();
+
+    git::run(vec!["update-ref", "-d", SESSION_REF], Some(pth)).status()?;
+    Ok(Some(message))
+}
+
+/// Represents how pushing to a project's remote went
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum PushStatus {
+    #[serde(rename = "pull needed")]
+    PullNeeded,
+    #[serde(rename = "up to date")]
+    UpToDate,
+    #[serde(rename = "success")]
+    Success,
+    #[serde(rename = "fail")]
+    Fail,
+}
+
+/// Give a Git command that talks to a remote the GitHub token, if the remote is on GitHub
+pub fn authenticate(command: &mut Command, remote_url: &str) {
+    if remote_url.contains("github.com") {
+        let mut token = gh_token().lock().unwrap();
+        command.env("GITHUB_TOKEN", token.get());
+    }
+}
+
+/// Push a project to its configured remote URL
+///
+/// Reports Git's `counting`, `compressing` and `writing` object counts as they come in
+pub fn push(pth: &PathBuf, progress: &mut Progress) -> Result<PushStatus> {
+    let config_remote = String::from_utf8(
+        git::run(vec!["remote", "get-url", "origin"], Some(pth))
+            .output()?
+            .stdout,
+    )?;
+
+    // what's new on the remote after pushing, for post-push hooks
+    let upstream = git::resolve(pth, "@{u}").ok();
+
+    // commits can't be used without the assets they refer to, so those go first, along with
+    // what other copies of the project stored
+    if asset_store::is_used(pth) {
+        asset_store::fetch(pth).context("failed to fetch stored assets")?;
+        let mut push = git::run(vec!["push", "origin", ASSETS_REF], Some(pth));
+        authenticate(&mut push, &config_remote);
+        if !push.status()?.success() {
+            return Err(anyhow!("failed to push stored assets"));
+        }
+    }
+
+    let mut push = git::run(
+        vec![
+            "push",
+            "--progress",
+            "--set-upstream",
+            "origin",
+            &git::main_branch(pth)?,
+        ],
+        Some(pth),
+    );
+
+    authenticate(&mut push, &config_remote);
+
+    let mut child = push.spawn()?;
+    let mut stderr = String::new();
+    if let Some(output) = child.stderr.take() {
+        // progress lines are rewritten in place with carriage returns
+        let lines = BufReader::new(output).split(b'\r').flat_map(|chunk| {
+            chunk
+                .unwrap_or_default()
+                .split(|b| *b == b'\n')
+                .map(|line| String::from_utf8_lossy(line).to_string())
+                .collect::<Vec<_>>()
+        });
+        for line in lines {
+            if let Some(captures) = PUSH_PROGRESS.captures(&line) {
+                let (done, total) = (captures[2].parse()?, captures[3].parse()?);
+                // keep pushing even if nobody is listening anymore
+                let _ = progress.on_progress(&captures[1].to_lowercase(), done, total);
+            }
+            stderr.push_str(&line);
+            stderr.push('\n');
+        }
+    }
+    let status = child.wait()?;
+
+    let comment = config::settings(Some(pth)).remote.comment_on_push;
+    if status.success() && (comment || hooks::is_set(pth, Hook::PostPush)) {
+        let last = match &upstream {
+            Some(upstream) => Diff::from_revision(pth, &format!("{upstream}:project.json"))?,
+            // everything is new to a remote that was just set up
+            None => Diff::new(Project::default()),
+        };
+        let pushed = Diff::from_revision(pth, "HEAD:project.json")?;
+        let report = compare(pth, &last, &pushed, &mut Quiet)?;
+        hooks::run_after(pth, Hook::PostPush, &report);
+
+        // a failed comment shouldn't fail a push that went through
+        if comment && report.size() > 0 {
+            let diffs = script_diffs(pth, &last, &pushed, &report)?;
+            let branch = git::main_branch(pth)?;
+            match review::comment(&config_remote, &branch, &report.to_markdown_with(&diffs)) {
+                Ok(Some(url)) => info!(url, "commented on pull request"),
+                Ok(None) => {}
+                Err(e) => warn!("failed to comment on pull request: {e:#}"),
+            }
+        }
+    }
+
+    // TODO: these checks might be very brittle
+    Ok(if stderr.contains(" ! [") && stderr.contains("git pull ...") {
+        PushStatus::PullNeeded
+    } else if status.success() {
+        if stderr.contains("Everything up-to-date") {
+            PushStatus::UpToDate
+        } else {
+            PushStatus::Success
+        }
+    } else {
+        PushStatus::Fail
+    })
+}
+
+/// Represents the author of a commit in a project's log
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct LogAuthor {
+    pub name: String,
+    pub email: String,
+    pub date: String,
+}
+
+/// Represents a single commit in a project's log
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct LogEntry {
+    pub commit: String,
+    pub subject: String,
+    pub body: String,
+    pub author: LogAuthor,
+}
+
+/// Return a project's commits, newest first
+pub fn log(pth: &PathBuf, limit: Option<usize>) -> Result<Vec<LogEntry>> {
+    // unit and record separators can't show up in commit messages, unlike quotes
+    let mut args = vec![
+        "log".to_string(),
+        "--pretty=format:%H%x1f%s%x1f%b%x1f%aN%x1f%aE%x1f%aD%x1e".to_string(),
+    ];
+    if let Some(limit) = limit {
+        args.push(format!("-{limit}"));
+    }
+
+    let output = git::run(args.iter().map(|a| a.as_str()).collect(), Some(pth)).output()?;
+
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .split('\x1e')
+        .filter_map(|record| {
+            let fields = record.trim_start_matches('\n').split('\x1f').collect::<Vec<_>>();
+            let [commit, subject, body, name, email, date] = fields.as_slice() else {
+                return None;
+            };
+            Some(LogEntry {
+                commit: commit.to_string(),
+                subject: subject.to_string(),
+                body: body.trim_end().to_string(),
+                author: LogAuthor {
+                    name: name.to_string(),
+                    email: email.to_string(),
+                    date: date.to_string(),
+                },
+            })
+        })
+        .collect())
+}
+
+/// Return the changes between a project's last unzip and its current save
+///
+/// Fails with [`Invalid`] if the saved project.json is broken, stops early with
+/// [`Cancelled`](crate::cancel::Cancelled) if `cancel` is cancelled, and reports `diff` as
+/// sprites are diffed
+pub fn diff_report(
+    pth: &PathBuf,
+    cancel: &CancelToken,
+    progress: &mut Progress,
+) -> Result<DiffReport> {
+    let current_diff = Diff::new(parse_project(
+        &fs::read_to_string(pth.join("project.old.json")).context("project not unzipped")?,
+    )?)
+    .with_cancel(cancel.clone());
+    let saved = fs::read_to_string(pth.join("project.json"))?;
+    validate::check(&saved)?;
+    let new_diff = Diff::new(parse_project(&saved)?);
+
+    let mut report = compare(pth, &current_diff, &new_diff, progress)?;
+    report.unused_assets = unused_assets(pth)?;
+    Ok(report)
+}
+
+/// Diff the scripts of each sprite in a report as text, keyed by the sprite's name without
+/// ` (stage)`
+pub fn script_diffs(
+    pth: &PathBuf,
+    old: &Diff,
+    new: &Diff,
+    report: &DiffReport,
+) -> Result<BTreeMap<String, String>> {
+    let context = old.settings(pth).diff.context;
+    // renamed variables and arguments are reported on their own, so they're renamed in the old
+    // scripts too
+    let renames = old.variable_renames(new);
+    // a sprite that doesn't exist on one side has no scripts there
+    let text = |target: Option<Cow<Target>>| -> Result<String> {
+        match target {
+            Some(target) => Ok(parse_sprite(Sprite::of(&target))?),
+            None => Ok(String::new()),
+        }
+    };
+
+    let mut diffs = BTreeMap::new();
+    for script in &report.scripts {
+        let sprite = label::name(&script.sprite);
+        let new_target = new.data.target(sprite);
+        let old_target = old.data.target(sprite).map(|target| {
+            let target = rename_variables(target, &renames);
+            match new_target {
+                Some(new_target) => rename_arguments(target, new_target),
+                None => target,
+            }
+        });
+        let (old_text, new_text) = (text(old_target)?, text(new_target.map(Cow::Borrowed))?);
+        let diff = git::diff(pth, old_text, new_text, context)?;
+        diffs.insert(sprite.to_string(), diff.diffed);
+    }
+    Ok(diffs)
+}
+
+/// Compare a project's last unzip to its current save, with each sprite's script diff
+fn saved_changes(pth: &PathBuf) -> Result<(DiffReport, BTreeMap<String, String>)> {
+    let current_diff = Diff::new(parse_project(
+        &fs::read_to_string(pth.join("project.old.json")).context("project not unzipped")?,
+    )?);
+    let saved = fs::read_to_string(pth.join("project.json"))?;
+    validate::check(&saved)?;
+    let new_diff = Diff::new(parse_project(&saved)?);
+
+    let report = compare(pth, &current_diff, &new_diff, &mut Quiet)?;
+    let diffs = script_diffs(pth, &current_diff, &new_diff, &report)?;
+    Ok((report, diffs))
+}
+
+/// Render the changes since a project's last unzip as a standalone HTML page
+pub fn diff_html(pth: &PathBuf, title: &str) -> Result<String> {
+    let (report, diffs) = saved_changes(pth)?;
+    html_report::render(pth, title, &report, &diffs)
+}
+
+/// Summarize the changes since a project's last unzip in Markdown, like for a pull request
+pub fn diff_markdown(pth: &PathBuf) -> Result<String> {
+    let (report, diffs) = saved_changes(pth)?;
+    Ok(report.to_markdown_with(&diffs))
+}
+
+/// Write the scripts added or changed since a project's last unzip as a Scratch forum post,
+/// under the name of each sprite they're in
+pub fn forum_post(pth: &PathBuf) -> Result<String> {
+    let old = Diff::new(parse_project(
+        &fs::read_to_string(pth.join("project.old.json")).context("project not unzipped")?,
+    )?);
+    let new = Diff::new(parse_project(&fs::read_to_string(pth.join("project.json"))?)?);
+
+    let mut scripts = BTreeMap::<String, Vec<String>>::new();
+    for script in old.changed_scripts(&new) {
+        if script.kind != ScriptChangeType::Removed {
+            let ids = scripts.entry(script.sprite).or_default();
+            ids.push(script.script_id);
+        }
+    }
+    // every script in a new sprite is new
+    for target in &new.data.targets {
+        let is_new = |old: &Target| old.name == target.name && old.is_stage == target.is_stage;
+        if !old.data.targets.iter().any(is_new) {
+            scripts.insert(target.display_name(), target.top_ids());
+        }
+    }
+
+    let mut post = String::new();
+    for (sprite, ids) in scripts {
+        let Some(target) = new.data.target(label::name(&sprite)) else {
+            continue;
+        };
+        post += &format!("[b]{sprite}[/b]\n");
+        post += &scratchblocks::post(&target.blocks, &ids);
+        post += "\n";
+    }
+    Ok(post.trim_end().to_string())
+}
+
+/// Return the blocks changed in each sprite from the last commit to the saved project.json, for
+/// the editor to highlight
+pub fn changed_blocks(pth: &PathBuf) -> Result<Vec<BlockChanges>> {
+    let last = Diff::from_revision(pth, "HEAD:project.json")?;
+    let saved = fs::read_to_string(pth.join("project.json"))?;
+    Ok(last.changed_blocks(&Diff::new(parse_project(&saved)?)))
+}
+
+/// Return the changes from the current version of the shared project a project was started
+/// from to its saved project.json
+pub fn diff_upstream(pth: &PathBuf, progress: &mut Progress) -> Result<DiffReport> {
+    let source = provenance::read(pth).context("project wasn't started from a shared project")?;
+    let original = Diff::new(provenance::original(&source)?);
+    let saved = fs::read_to_string(pth.join("project.json"))?;
+    let saved = Diff::new(parse_project(&saved)?);
+
+    compare(pth, &original, &saved, progress)
+}
+
+/// Return the changes between two versions of a project, leaving out ignored sprites
+///
+/// Stops early if `current`'s cancel token is cancelled
+pub fn compare(
+    pth: &PathBuf,
+    current: &Diff,
+    new: &Diff,
+    progress: &mut Progress,
+) -> Result<DiffReport> {
+    let started = Instant::now();
+    let mut assets = current.assets(new, Some(AssetChangeType::After))?;
+    assets.extend(new.assets(current, Some(AssetChangeType::Before))?);
+    current.cancel.check()?;
+
+    let scripts = current.blocks_with_progress(pth, new, progress)?;
+    current.cancel.check()?;
+
+    let settings = current.settings(pth);
+    let is_ignored = |asset: &AssetChange| {
+        settings.is_ignored_asset(&asset.sprite, &asset.name, asset_kind(&asset.path))
+    };
+    assets.retain(|asset| !is_ignored(asset));
+    let mut moved_assets = current.asset_changes(pth, new)?.moved;
+    moved_assets.retain(|moved| !is_ignored(&moved.change));
+    let variables = current
+        .variables(new)
+        .into_iter()
+        .filter(|variable| !settings.is_ignored_variable(&variable.sprite, &variable.name))
+        .collect::<Vec<_>>();
+    // cloud variables are shared with everyone running the project, so changing them is risky
+    let cloud_changes = variables.iter().filter(|variable| variable.cloud).count();
+    let mut warnings = match cloud_changes {
+        _ if !settings.commit.warn_cloud_variables => vec![],
+        0 => vec![],
+        1 => vec!["a cloud variable changed".to_string()],
+        n => vec![format!("{n} cloud variables changed")],
+    };
+    // blocks from unknown extensions can only be shown as raw opcodes
+    let unknown_extensions = current
+        .unknown_extensions(new)
+        .into_iter()
+        .filter(|unknown| unknown.sprites.iter().any(|s| !settings.is_ignored(s)))
+        .collect::<Vec<_>>();
+    warnings.extend(unknown_extensions.iter().map(|unknown| unknown.format()));
+    for warning in &warnings {
+        progress.on_warning(warning);
+    }
+    let report = DiffReport {
+        scripts: scripts
+            .into_iter()
+            .filter(|script| !settings.is_ignored_script(&script.sprite))
+            .collect(),
+        changed_scripts: current
+            .changed_scripts(new)
+            .into_iter()
+            .filter(|script| !settings.is_ignored_script(&script.sprite))
+            .collect(),
+        assets,
+        moved_assets,
+        costumes: current
+            .costumes(new)
+            .into_iter()
+            .filter(|costume| {
+                !settings.is_ignored_asset(&costume.sprite, &costume.costume, "costumes")
+            })
+            .collect(),
+        extensions: current
+            .extensions(new)
+            .into_iter()
+            .filter(|_| !settings.is_ignored_kind("extensions"))
+            .collect(),
+        broadcasts: current
+            .broadcasts(new)
+            .into_iter()
+            .filter(|_| !settings.is_ignored_kind("broadcasts"))
+            .collect(),
+        unknown_extensions,
+        variables,
+        argument_renames: current
+            .argument_renames(new)
+            .into_iter()
+            .filter(|rename| !settings.is_ignored_script(&rename.sprite))
+            .collect(),
+        warnings,
+        unused_assets: vec![],
+        commits: current
+            .commits(pth, new)?
+            .into_iter()
+            .filter(|line| !settings.is_ignored_line(line))
+            .collect(),
+    };
+    metrics::DIFF_DURATION.observe(started.elapsed());
+    Ok(report)
+}
+
+/// Return the changes between two versions of a project that aren't in a repository, with the
+/// default settings
+///
+/// Scripts are diffed with Git, so a scratch repository is made for them and removed afterwards.
+pub fn compare_detached(current: &Diff, new: &Diff, progress: &mut Progress) -> Result<DiffReport> {
+    let scratch = TempWorkspace::new("diff")?;
+    git::run(vec!["init", "--quiet"], Some(scratch.path()))
+        .output()
+        .map_err(Error::git)?;
+    compare(scratch.path(), current, new, progress)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn line(scope: Option<&str>, changes: &[&str], kind: ChangeKind) -> CommitLine {
+        let changes = changes.iter().map(|change| change.to_string()).collect();
+        CommitLine {
+            scope: scope.map(String::from),
+            changes,
+            text: String::new(),
+            kind,
+        }
+    }
+
+    #[test]
+    fn conventional_scopes_are_parseable() {
+        let cases = [
+            ("Cat", "feat(Cat): +3 blocks"),
+            ("Cat: 1", "feat(Cat 1): +3 blocks"),
+            ("Cat (old)", "feat(Cat old): +3 blocks"),
+            ("Cat\n\u{202e}1", "feat(Cat 1): +3 blocks"),
+            ("🐱", "feat(🐱): +3 blocks"),
+            ("Backdrop: 1 (stage)", "feat(stage): +3 blocks"),
+        ];
+        for (sprite, message) in cases {
+            let lines = [line(Some(sprite), &["+3 blocks"], ChangeKind::Addition)];
+            assert_eq!(conventional_message(&lines), message);
+        }
+    }
+
+    #[test]
+    fn conventional_type_comes_from_kinds() {
+        let message = |kind| conventional_message(&[line(Some("Cat"), &["anything"], kind)]);
+        assert_eq!(message(ChangeKind::Addition), "feat(Cat): anything");
+        assert_eq!(message(ChangeKind::Script), "refactor(Cat): anything");
+        assert_eq!(message(ChangeKind::Other), "chore(Cat): anything");
+    }
+
+    #[test]
+    fn conventional_lists_every_scope() {
+        let lines = [
+            line(Some("Cat: 1"), &["-2 blocks"], ChangeKind::Script),
+            line(
+                Some("Stage (stage)"),
+                &["remove pop.wav"],
+                ChangeKind::Other,
+            ),
+            line(None, &["add 🐱.svg", "+1 blocks"], ChangeKind::Addition),
+        ];
+        assert_eq!(
+            conventional_message(&lines),
+            "feat: update Cat 1, stage, project\n\n\
+             - Cat 1: -2 blocks\n\
+             - stage: remove pop.wav\n\
+             - project: add 🐱.svg, +1 blocks"
+        );
+    }
+
+    fn svg(fill: &str) -> (String, Vec<u8>) {
+        let svg = format!("<svg fill=\"{fill}\"/>").into_bytes();
+        (format!("{:x}.svg", md5::compute(&svg)), svg)
+    }
+
+    fn project(costumes: &[&str]) -> Value {
+        let costumes = costumes.iter().map(|md5ext| {
+            let (asset_id, _) = md5ext.split_once('.').unwrap();
+            json!({"name": asset_id, "assetId": asset_id, "md5ext": md5ext, "dataFormat": "svg"})
+        });
+        let costumes = costumes.collect::<Vec<_>>();
+        json!({
+            "targets": [
+                {
+                    "isStage": true,
+                    "name": "Stage",
+                    "blocks": {},
+                    "costumes": [costumes[0]],
+                    "sounds": [],
+                },
+                {
+                    "isStage": false,
+                    "name": "Cat",
+                    "blocks": {},
+                    "costumes": costumes,
+                    "sounds": [],
+                },
+            ],
+            "monitors": [],
+            "extensions": [],
+            "meta": {"semver": "3.0.0"},
+        })
+    }
+
+    fn git(cwd: &PathBuf, args: &[&str]) -> String {
+        let output = git::run(args.to_vec(), Some(cwd)).output().unwrap();
+        assert!(output.status.success(), "git {args:?} failed");
+        String::from_utf8(output.stdout).unwrap()
+    }
+
+    /// Commit a project with the Cat sprite's first costume, then a patch adding a second
+    fn repository_and_patch(workspace: &TempWorkspace) -> (PathBuf, PathBuf, Value) {
+        let cwd = workspace.path().join("repo");
+        let bundle = workspace.path().join("patch.zip");
+        fs::create_dir(&cwd).unwrap();
+        let ((walk, walk_svg), (run, run_svg)) = (svg("red"), svg("blue"));
+        git(&cwd, &["init", "--quiet"]);
+        // apply_patch commits too, so the identity can't just be given to this helper
+        git(&cwd, &["config", "user.name", "test"]);
+        git(&cwd, &["config", "user.email", "test@example.com"]);
+        fs::write(cwd.join(&walk), walk_svg).unwrap();
+        let old = project(&[&walk]);
+        fs::write(cwd.join("project.json"), old.to_string()).unwrap();
+        git(&cwd, &["add", "--all"]);
+        git(&cwd, &["commit", "--quiet", "-m", "cat"]);
+
+        let new = project(&[&walk, &run]);
+        let patch = Patch::between("a".repeat(40), &old, "b".repeat(40), &new);
+        patch::write(&bundle, &patch, [(run, run_svg)]).unwrap();
+        (cwd, bundle, new)
+    }
+
+    #[test]
+    fn patches_apply_and_round_trip() {
+        let workspace = TempWorkspace::new("ops-test").unwrap();
+        let (cwd, bundle, new) = repository_and_patch(&workspace);
+        // the saved project has a change of its own, which stays uncommitted
+        let mut saved = project(&[&svg("red").0]);
+        saved["targets"][0]["volume"] = json!(50);
+        fs::write(cwd.join("project.json"), saved.to_string()).unwrap();
+
+        let message = apply_patch(&cwd, &bundle).unwrap();
+        assert_eq!(message, "apply patch from aaaaaaa: Cat");
+        let committed = git::parse_revision::<Value>(&cwd, "HEAD:project.json").unwrap();
+        assert_eq!(committed["targets"], new["targets"]);
+        let (run, _) = svg("blue");
+        git(&cwd, &["cat-file", "-e", &format!("HEAD:{run}")]);
+        assert_eq!(git(&cwd, &["status", "--porcelain"]), " M project.json\n");
+        let saved = fs::read_to_string(cwd.join("project.json")).unwrap();
+        let saved = serde_json::from_str::<Value>(&saved).unwrap();
+        assert_eq!(saved["targets"][0]["volume"], 50);
+
+        // the commit it made is the same patch again
+        let (patch, _) = patch::read(&bundle).unwrap();
+        let old = git::parse_revision::<Value>(&cwd, "HEAD~1:project.json").unwrap();
+        let again = Patch::between(patch.from.clone(), &old, patch.to.clone(), &committed);
+        assert_eq!(json!(again.sprites), json!(patch.sprites));
+    }
+
+    #[test]
+    fn failed_patches_are_undone() {
+        let workspace = TempWorkspace::new("ops-test").unwrap();
+        let (cwd, bundle, _) = repository_and_patch(&workspace);
+        let saved = fs::read(cwd.join("project.json")).unwrap();
+        let head = git::resolve(&cwd, "HEAD").unwrap();
+        let hook = git::git_dir(&cwd).unwrap().join("hooks").join("pre-commit");
+        fs::create_dir_all(hook.parent().unwrap()).unwrap();
+        fs::write(&hook, "#!/bin/sh\nexit 1\n").unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&hook, fs::Permissions::from_mode(0o755)).unwrap();
+        }
+
+        let error = apply_patch(&cwd, &bundle).unwrap_err();
+        assert_eq!(error.to_string(), "failed to commit the patch's changes");
+        assert_eq!(git::resolve(&cwd, "HEAD").unwrap(), head);
+        assert_eq!(git(&cwd, &["status", "--porcelain"]), "");
+        assert_eq!(fs::read(cwd.join("project.json")).unwrap(), saved);
+    }
+}
@@ -0,0 +1,319 @@
+//! Shrinking assets before they're committed, so the repository grows less with each commit
+//!
+//! Assets are named by the MD5 of their contents, so an optimized asset is saved under its new
+//! hash and project.json is updated to point at it. Only assets that aren't in the last commit
+//! are optimized, since committed ones were already optimized when they were added. SVG and PNG
+//! optimizations never change how a costume looks, and WAVs are only turned into MP3s if an
+//! encoder is set, since that loses some quality.
+
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::io::{Read, Write};
+use std::path::PathBuf;
+use std::process::Stdio;
+
+use anyhow::{anyhow, Context, Result};
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use serde::Serialize;
+use serde_json::Value;
+use tracing::warn;
+
+use crate::config::OptimizeSettings;
+use crate::git;
+use crate::hooks;
+use crate::normalize::normalize;
+
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+/// PNG chunks that only hold text or timestamps, which no viewer draws
+const PNG_TEXT_CHUNKS: [&[u8]; 4] = [b"tEXt", b"zTXt", b"iTXt", b"tIME"];
+
+/// WAV chunks Scratch needs to play a sound, where everything else is like tags
+const WAV_CHUNKS: [&[u8]; 3] = [b"fmt ", b"data", b"fact"];
+
+/// Represents an asset that was optimized
+#[derive(Debug, Clone, Serialize)]
+pub struct Optimized {
+    /// The asset's file before, like `a1b2....png`
+    pub from: String,
+    /// The asset's file after, named by its new hash
+    pub to: String,
+    pub before: u64,
+    pub after: u64,
+}
+
+/// Represents the assets optimized before a commit
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Report {
+    pub assets: Vec<Optimized>,
+}
+
+impl Report {
+    /// Bytes saved across every asset
+    pub fn saved(&self) -> u64 {
+        self.assets
+            .iter()
+            .map(|asset| asset.before.saturating_sub(asset.after))
+            .sum()
+    }
+
+    /// Describe what was saved for a commit body, or `None` if nothing was optimized
+    pub fn summary(&self) -> Option<String> {
+        let count = self.assets.len();
+        let plural = if count == 1 { "" } else { "s" };
+        (count > 0).then(|| {
+            format!(
+                "Optimized {count} asset{plural}, saving {:.1} KB",
+                self.saved() as f64 / 1024.0
+            )
+        })
+    }
+}
+
+/// Strip an SVG of comments, `<metadata>` and whitespace between tags
+///
+/// Scratch 2 keeps costume centers in `<!--rotationCenter:x:y-->` comments, so those stay.
+/// Whitespace is kept in SVGs with text, where it can be part of what's shown, and comments in
+/// ones with CDATA sections, where they might not be comments at all.
+pub fn optimize_svg(svg: &[u8]) -> Option<Vec<u8>> {
+    let svg = std::str::from_utf8(svg).ok()?;
+    let mut out = String::with_capacity(svg.len());
+    let keep_comments = svg.contains("<![CDATA[");
+    let keep_whitespace = keep_comments || svg.contains("<text") || svg.contains("xml:space");
+
+    let mut rest = svg;
+    while !rest.is_empty() {
+        if !keep_comments && rest.starts_with("<!--") {
+            let end = rest.find("-->")? + 3;
+            if rest[4..].starts_with("rotationCenter") {
+                out.push_str(&rest[..end]);
+            }
+            rest = &rest[end..];
+        } else if rest.starts_with("<metadata") {
+            let end = match rest.find("</metadata>") {
+                Some(end) => end + "</metadata>".len(),
+                None => rest.find("/>")? + 2,
+            };
+            rest = &rest[end..];
+        } else if rest.starts_with('>') && !keep_whitespace {
+            out.push('>');
+            rest = &rest[1..];
+            // only whitespace between tags goes, anything else is left as it is
+            if let Some(gap) = rest.find('<').map(|end| &rest[..end]) {
+                if gap.trim().is_empty() {
+                    rest = &rest[gap.len()..];
+                }
+            }
+        } else {
+            let next = rest[1..]
+                .find(['<', '>'])
+                .map_or(rest.len(), |next| next + 1);
+            out.push_str(&rest[..next]);
+            rest = &rest[next..];
+        }
+    }
+    Some(out.into_bytes())
+}
+
+/// Append a PNG chunk with its length and checksum
+fn push_chunk(png: &mut Vec<u8>, kind: &[u8], data: &[u8]) {
+    png.extend((data.len() as u32).to_be_bytes());
+    png.extend(kind);
+    png.extend(data);
+    let mut crc = crc32fast::Hasher::new();
+    crc.update(kind);
+    crc.update(data);
+    png.extend(crc.finalize().to_be_bytes());
+}
+
+/// Recompress a PNG's image data as tightly as zlib can and drop its text chunks, leaving
+/// every pixel the same
+///
+/// Returns `None` for anything that isn't a well-formed PNG.
+pub fn optimize_png(png: &[u8]) -> Option<Vec<u8>> {
+    let mut rest = png.strip_prefix(PNG_SIGNATURE)?;
+    let mut chunks = vec![];
+    let mut compressed = vec![];
+    while rest.len() >= 12 {
+        let length = u32::from_be_bytes(rest[..4].try_into().ok()?) as usize;
+        let kind = &rest[4..8];
+        let data = rest.get(8..8 + length)?;
+        rest = rest.get(12 + length..)?;
+        match kind {
+            b"IDAT" => {
+                compressed.extend(data);
+                // image data has to stay where the first IDAT was
+                if !chunks.iter().any(|(kind, _)| *kind == b"IDAT") {
+                    chunks.push((kind, data));
+                }
+            }
+            _ if PNG_TEXT_CHUNKS.contains(&kind) => {}
+            _ => chunks.push((kind, data)),
+        }
+    }
+
+    let mut raw = vec![];
+    ZlibDecoder::new(compressed.as_slice())
+        .read_to_end(&mut raw)
+        .ok()?;
+    let mut encoder = ZlibEncoder::new(vec![], Compression::best());
+    encoder.write_all(&raw).ok()?;
+    let recompressed = encoder.finish().ok()?;
+
+    let mut out = PNG_SIGNATURE.to_vec();
+    for (kind, data) in chunks {
+        match kind {
+            b"IDAT" => push_chunk(&mut out, kind, &recompressed),
+            _ => push_chunk(&mut out, kind, data),
+        }
+    }
+    Some(out)
+}
+
+/// Drop every chunk from a WAV but the ones Scratch needs to play it, like `LIST` tags
+///
+/// Returns `None` for anything that isn't a well-formed WAV.
+pub fn optimize_wav(wav: &[u8]) -> Option<Vec<u8>> {
+    if wav.get(..4)? != b"RIFF" || wav.get(8..12)? != b"WAVE" {
+        return None;
+    }
+    let mut rest = &wav[12..];
+    let mut out = b"RIFF\0\0\0\0WAVE".to_vec();
+    while rest.len() >= 8 {
+        let length = u32::from_le_bytes(rest[4..8].try_into().ok()?) as usize;
+        // chunks are padded to an even length
+        let padded = length + length % 2;
+        let chunk = rest.get(..8 + padded).unwrap_or(rest);
+        if WAV_CHUNKS.contains(&&rest[..4]) {
+            out.extend(chunk);
+        }
+        rest = &rest[chunk.len()..];
+    }
+    let size = (out.len() - 8) as u32;
+    out[4..8].copy_from_slice(&size.to_le_bytes());
+    Some(out)
+}
+
+/// Encode a WAV as an MP3 with the configured encoder
+fn encode_wav(pth: &PathBuf, asset: &str, encoder: &str) -> Result<Vec<u8>> {
+    let output = pth.join(format!("{asset}.mp3"));
+    let input = pth.join(asset);
+    let encoded = hooks::shell_with_paths(encoder, &[("input", &input), ("output", &output)])
+        .current_dir(pth)
+        .stdin(Stdio::null())
+        .output()
+        .with_context(|| format!("failed to run WAV encoder `{encoder}`"))?;
+    let mp3 = fs::read(&output);
+    let _ = fs::remove_file(&output);
+    if !encoded.status.success() {
+        return Err(anyhow!(
+            "WAV encoder `{encoder}` failed: {}",
+            String::from_utf8_lossy(&encoded.stderr).trim()
+        ));
+    }
+    Ok(mp3?)
+}
+
+/// Optimize an asset, returning its new contents and extension if that's any smaller, along
+/// with how big it was
+fn optimize_asset(
+    pth: &PathBuf,
+    asset: &str,
+    settings: &OptimizeSettings,
+) -> Result<Option<(Vec<u8>, &'static str, u64)>> {
+    let contents = fs::read(pth.join(asset))?;
+    let optimized = match asset.rsplit_once('.').map(|(_, ext)| ext) {
+        Some("svg") if settings.svg => optimize_svg(&contents).map(|svg| (svg, "svg")),
+        Some("png") if settings.png => optimize_png(&contents).map(|png| (png, "png")),
+        Some("wav") => match &settings.wav_encoder {
+            Some(encoder) => Some((encode_wav(pth, asset, encoder)?, "mp3")),
+            None => optimize_wav(&contents).map(|wav| (wav, "wav")),
+        },
+        _ => None,
+    };
+    Ok(optimized
+        .filter(|(optimized, _)| optimized.len() < contents.len())
+        .map(|(optimized, ext)| (optimized, ext, contents.len() as u64)))
+}
+
+/// Optimize the assets a project has that aren't in its last commit, saving each under its new
+/// hash and pointing project.json at them
+///
+/// Assets that can't be optimized are left as they are, so this only fails if project.json
+/// can't be read or written.
+pub fn project(pth: &PathBuf, settings: &OptimizeSettings) -> Result<Report> {
+    let committed = git::run(vec!["ls-tree", "--name-only", "HEAD"], Some(pth)).output()?;
+    let committed = String::from_utf8_lossy(&committed.stdout)
+        .lines()
+        .map(str::to_string)
+        .collect::<HashSet<_>>();
+
+    let json = fs::read(pth.join("project.json"))?;
+    let mut project = serde_json::from_slice::<Value>(&json)?;
+    let mut renamed = BTreeMap::<String, (String, &'static str)>::new();
+    let mut report = Report::default();
+
+    let targets = project.get_mut("targets").and_then(Value::as_array_mut);
+    for target in targets
+        .into_iter()
+        .flatten()
+        .filter_map(Value::as_object_mut)
+    {
+        let assets = target
+            .iter_mut()
+            .filter(|(kind, _)| *kind == "costumes" || *kind == "sounds")
+            .flat_map(|(_, assets)| assets.as_array_mut().into_iter().flatten());
+        for asset in assets {
+            let Some(name) = asset["md5ext"].as_str().map(str::to_string) else {
+                continue;
+            };
+            if !renamed.contains_key(&name) {
+                if committed.contains(&name) || !pth.join(&name).exists() {
+                    continue;
+                }
+                let (contents, ext, before) = match optimize_asset(pth, &name, settings) {
+                    Ok(Some(optimized)) => optimized,
+                    Ok(None) => continue,
+                    Err(e) => {
+                        warn!("failed to optimize {name}: {e:#}");
+                        continue;
+                    }
+                };
+                let id = format!("{:x}", md5::compute(&contents));
+                let to = format!("{id}.{ext}");
+                fs::write(pth.join(&to), &contents)?;
+                fs::remove_file(pth.join(&name))?;
+                report.assets.push(Optimized {
+                    from: name.clone(),
+                    to,
+                    before,
+                    after: contents.len() as u64,
+                });
+                renamed.insert(name.clone(), (id, ext));
+            }
+
+            let (id, ext) = &renamed[&name];
+            if *ext == "mp3" {
+                // an MP3's rate and length come from decoding it, and the editor works them out
+                // when it loads the sound
+                if let Some(sound) = asset.as_object_mut() {
+                    sound.remove("rate");
+                    sound.remove("sampleCount");
+                }
+            }
+            asset["assetId"] = id.as_str().into();
+            asset["md5ext"] = format!("{id}.{ext}").into();
+            asset["dataFormat"] = (*ext).into();
+        }
+    }
+
+    if !report.assets.is_empty() {
+        fs::write(
+            pth.join("project.json"),
+            normalize(&serde_json::to_vec(&project)?)?,
+        )?;
+    }
+    Ok(report)
+}
@@ -0,0 +1,44 @@
+use std::sync::OnceLock;
+
+use crate::config;
+
+/// Editors the userscript runs in
+const DEFAULT_ORIGINS: [&str; 4] = [
+    "tw-editor://.",
+    "https://turbowarp.org",
+    "https://scratch.mit.edu",
+    "http://localhost:8601",
+];
+
+/// Origins allowed to connect, the defaults plus `server.allowed_origins` from settings
+fn allowed_origins() -> &'static [String] {
+    static ORIGINS: OnceLock<Vec<String>> = OnceLock::new();
+    ORIGINS.get_or_init(|| {
+        DEFAULT_ORIGINS
+            .iter()
+            .map(|origin| origin.to_string())
+            .chain(config::settings(None).server.allowed_origins)
+            .collect()
+    })
+}
+
+/// Check if a request's `Origin` header is allowed
+///
+/// Requests without one come from scripts and tools rather than web pages, and are left to
+/// the session token
+pub fn is_allowed(origin: Option<&str>) -> bool {
+    origin.is_none_or(|origin| {
+        let origin = origin.trim_end_matches('/');
+        allowed_origins()
+            .iter()
+            .any(|allowed| allowed == "*" || allowed.trim_end_matches('/') == origin)
+    })
+}
+
+/// Message for a rejected origin, telling users how to allow it
+pub fn rejection(origin: &str) -> String {
+    format!(
+        "origin {origin} is not allowed, add it to server.allowed_origins in {}",
+        config::SETTINGS_FILE
+    )
+}
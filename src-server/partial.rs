@@ -0,0 +1,282 @@
+//! Committing only some of a project's changes
+//!
+//! A partial commit's project.json is the last commit's, with the selected changes from the
+//! saved project applied to it. Everything else is committed as it was, so those changes are
+//! still there to commit later.
+
+use std::collections::HashSet;
+
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::Value;
+
+use crate::diff::label;
+use crate::diff::structs::DiffReport;
+use crate::sb3::{Costume, Target};
+
+/// Represents a change picked from a [`DiffReport`] to commit
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum Selection {
+    /// Every change to a sprite, including adding or deleting it
+    Sprite { sprite: String },
+    /// A script, by the ID of its top block
+    #[serde(rename_all = "camelCase")]
+    Script { sprite: String, script_id: String },
+    /// A costume or sound, by its file name
+    Asset { sprite: String, path: String },
+}
+
+/// Read a list of changes to commit, where a plain name picks a whole sprite
+///
+/// Clients from before single scripts and assets could be picked send sprite names, as
+/// `sprites`, which would otherwise be dropped and commit everything.
+pub fn selections<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Vec<Selection>>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Picked {
+        Sprite(String),
+        Change(Selection),
+    }
+
+    let picked = Option::<Vec<Picked>>::deserialize(deserializer)?;
+    Ok(picked.map(|picked| {
+        picked
+            .into_iter()
+            .map(|picked| match picked {
+                Picked::Sprite(sprite) => Selection::Sprite { sprite },
+                Picked::Change(change) => change,
+            })
+            .collect()
+    }))
+}
+
+impl Selection {
+    /// Name of the sprite the change is in, naming the stage without its suffix
+    fn sprite(&self) -> &str {
+        let (Selection::Sprite { sprite }
+        | Selection::Script { sprite, .. }
+        | Selection::Asset { sprite, .. }) = self;
+        label::name(sprite)
+    }
+
+    fn is_in(&self, report: &DiffReport) -> bool {
+        let sprite = |name: &str| label::name(name) == self.sprite();
+        match self {
+            Selection::Sprite { .. } => report.sprites().contains(self.sprite()),
+            Selection::Script { script_id, .. } => report
+                .changed_scripts
+                .iter()
+                .any(|script| sprite(&script.sprite) && &script.script_id == script_id),
+            Selection::Asset { path, .. } => report
+                .assets
+                .iter()
+                .any(|asset| sprite(&asset.sprite) && &asset.path == path),
+        }
+    }
+}
+
+/// Check that something is selected, and that every selected change is in a diff
+pub fn check(selection: &[Selection], report: &DiffReport) -> Result<()> {
+    if selection.is_empty() {
+        return Err(anyhow!("no changes selected"));
+    }
+    match selection.iter().find(|change| !change.is_in(report)) {
+        Some(change) => Err(anyhow!("{} has no such change to commit", change.sprite())),
+        None => Ok(()),
+    }
+}
+
+/// Apply the selected changes from a saved project.json to the last commit's
+///
+/// Changes inside a sprite can only be picked out when both versions have it, so new and
+/// deleted sprites are committed whole. New global variables, lists, broadcasts and extensions
+/// are kept even if the stage isn't selected, since selected scripts may use them
+pub fn apply(last: &Value, saved: &Value, selection: &[Selection]) -> Result<Value> {
+    let mut project = last.clone();
+    let targets = |project: &Value| project["targets"].as_array().cloned().unwrap_or_default();
+    let (last_targets, saved_targets) = (targets(last), targets(saved));
+    let find = |targets: &[Value], target: &Value| {
+        targets
+            .iter()
+            .find(|other| other["name"] == target["name"])
+            .cloned()
+    };
+    let changes = |target: &Value| {
+        selection
+            .iter()
+            .filter(|change| target["name"] == change.sprite())
+            .collect::<Vec<_>>()
+    };
+    let whole = |target: &Value| {
+        changes(target)
+            .iter()
+            .any(|change| matches!(change, Selection::Sprite { .. }))
+    };
+
+    let mut targets = vec![];
+    for target in &saved_targets {
+        let name = target["name"].as_str().unwrap_or_default();
+        match find(&last_targets, target) {
+            _ if whole(target) => targets.push(target.clone()),
+            Some(mut last) => {
+                apply_changes(&mut last, target, &changes(target))
+                    .with_context(|| format!("failed to commit changes to {name}"))?;
+                targets.push(last);
+            }
+            None if changes(target).is_empty() => {}
+            None => return Err(anyhow!("{name} is new, so it can only be committed whole")),
+        }
+    }
+    for last in &last_targets {
+        if find(&saved_targets, last).is_some() || whole(last) {
+            continue;
+        }
+        if !changes(last).is_empty() {
+            let name = last["name"].as_str().unwrap_or_default();
+            return Err(anyhow!(
+                "{name} was deleted, so it can only be committed whole"
+            ));
+        }
+        // deleted sprites stay until they're selected
+        targets.push(last.clone());
+    }
+
+    let saved_stage = saved_targets
+        .iter()
+        .find(|target| target["isStage"] == true);
+    if let (Some(stage), Some(saved_stage)) = (
+        targets.iter_mut().find(|target| target["isStage"] == true),
+        saved_stage,
+    ) {
+        keep_new(stage, saved_stage, &["variables", "lists", "broadcasts"]);
+    }
+
+    let mut extensions = project["extensions"]
+        .as_array()
+        .cloned()
+        .unwrap_or_default();
+    for extension in saved["extensions"].as_array().into_iter().flatten() {
+        if !extensions.contains(extension) {
+            extensions.push(extension.clone());
+        }
+    }
+
+    let object = project.as_object_mut().context("project isn't an object")?;
+    object.insert("targets".to_string(), Value::from(targets));
+    object.insert("extensions".to_string(), Value::from(extensions));
+    Ok(project)
+}
+
+/// Apply selected scripts and assets from a sprite's saved version to its last committed one
+pub(crate) fn apply_changes(
+    target: &mut Value,
+    saved: &Value,
+    changes: &[&Selection],
+) -> Result<()> {
+    let scripts = changes
+        .iter()
+        .filter_map(|change| match change {
+            Selection::Script { script_id, .. } => Some(script_id.as_str()),
+            _ => None,
+        })
+        .collect::<HashSet<_>>();
+    let assets = changes
+        .iter()
+        .filter_map(|change| match change {
+            Selection::Asset { path, .. } => Some(path.as_str()),
+            _ => None,
+        })
+        .collect::<HashSet<_>>();
+
+    if !scripts.is_empty() {
+        apply_scripts(target, saved, &scripts)?;
+        // selected scripts may use the sprite's new variables and lists
+        keep_new(target, saved, &["variables", "lists"]);
+    }
+    for key in ["costumes", "sounds"] {
+        apply_assets(target, saved, key, &assets);
+    }
+
+    let costumes = target["costumes"].as_array().map_or(0, Vec::len);
+    if costumes == 0 {
+        return Err(anyhow!("a sprite needs at least one costume"));
+    }
+    if target["currentCostume"].as_u64().unwrap_or(0) as usize >= costumes {
+        target["currentCostume"] = Value::from(costumes - 1);
+    }
+    Ok(())
+}
+
+/// Replace selected scripts' blocks with their saved versions, removing deleted scripts
+fn apply_scripts(target: &mut Value, saved: &Value, scripts: &HashSet<&str>) -> Result<()> {
+    let blocks_in = |target: &Value| -> Result<HashSet<String>> {
+        let target = serde_json::from_value::<Target>(target.clone())?;
+        let ids = target
+            .scripts()
+            .into_iter()
+            .filter(|(top, _)| scripts.contains(top))
+            .flat_map(|(_, blocks)| blocks.into_keys())
+            .map(str::to_string)
+            .collect();
+        Ok(ids)
+    };
+    let (last_ids, saved_ids) = (blocks_in(target)?, blocks_in(saved)?);
+
+    let blocks = target
+        .get_mut("blocks")
+        .and_then(Value::as_object_mut)
+        .context("sprite has no blocks")?;
+    blocks.retain(|id, _| !last_ids.contains(id));
+    for id in saved_ids {
+        // a block dragged between scripts is in both, so the scripts have to go together
+        if blocks.contains_key(&id) {
+            return Err(anyhow!(
+                "block {id} moved between scripts, so select both of them"
+            ));
+        }
+        blocks.insert(id.clone(), saved["blocks"][&id].clone());
+    }
+    Ok(())
+}
+
+/// Add or remove selected costumes or sounds, putting added ones where they are when saved
+fn apply_assets(target: &mut Value, saved: &Value, key: &str, paths: &HashSet<&str>) {
+    // sounds have the same fields that make up a costume's file name
+    let path = |asset: &Value| {
+        serde_json::from_value::<Costume>(asset.clone())
+            .ok()
+            .and_then(|asset| asset.path().ok())
+            .unwrap_or_default()
+    };
+    let saved_assets = saved[key].as_array().cloned().unwrap_or_default();
+    let Some(assets) = target.get_mut(key).and_then(Value::as_array_mut) else {
+        return;
+    };
+
+    assets.retain(|asset| {
+        !paths.contains(path(asset).as_str())
+            || saved_assets.iter().any(|saved| path(saved) == path(asset))
+    });
+    for (i, asset) in saved_assets.iter().enumerate() {
+        let added = !assets.iter().any(|last| path(last) == path(asset));
+        if added && paths.contains(path(asset).as_str()) {
+            assets.insert(i.min(assets.len()), asset.clone());
+        }
+    }
+}
+
+/// Copy entries only the saved version of a target has, like new variables, into it
+pub(crate) fn keep_new(target: &mut Value, saved: &Value, keys: &[&str]) {
+    for key in keys {
+        let entries = target.get_mut(*key).and_then(Value::as_object_mut);
+        let (Some(entries), Some(saved)) = (entries, saved[*key].as_object()) else {
+            continue;
+        };
+        for (id, value) in saved {
+            entries.entry(id.clone()).or_insert_with(|| value.clone());
+        }
+    }
+}
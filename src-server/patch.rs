@@ -0,0 +1,269 @@
+//! Patch bundles, for passing changes between copies of a project without sharing the repo
+//!
+//! A bundle is a ZIP of a `patch.json` and the costumes and sounds the changes added. Changes
+//! are stored sprite by sprite, with each changed sprite as it started and as it ends up. A
+//! sprite that's the same in the other copy as it started is replaced outright. One that was
+//! changed there too only has the patch's changed scripts and assets applied, as long as the
+//! other copy didn't change those same scripts, so nobody's work is silently overwritten.
+
+use std::collections::HashSet;
+use std::fs::File;
+use std::path::Path;
+
+use anyhow::{anyhow, Context, Result};
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use zip::ZipArchive;
+
+use crate::limits::{self, MAX_ASSET_SIZE, MAX_JSON_SIZE};
+use crate::partial::{apply_changes, keep_new, Selection};
+use crate::sb3::{self, Target};
+
+/// Name of the manifest in a bundle
+pub const MANIFEST: &str = "patch.json";
+
+/// Represents the changes between two versions of a project
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Patch {
+    /// Commit the changes start from
+    pub from: String,
+    /// Commit the changes end at
+    pub to: String,
+    pub sprites: Vec<SpritePatch>,
+    /// Extensions the changes started using
+    pub extensions: Vec<Value>,
+}
+
+/// Represents a sprite (or the stage) that was added, deleted or changed
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SpritePatch {
+    pub name: String,
+    pub is_stage: bool,
+    /// The sprite as it started, or `None` if it's new
+    pub before: Option<Value>,
+    /// The sprite as it ends up, or `None` if it was deleted
+    pub after: Option<Value>,
+}
+
+fn targets(project: &Value) -> Vec<Value> {
+    project["targets"].as_array().cloned().unwrap_or_default()
+}
+
+/// Find the same sprite (or the stage) in another version of a project
+fn find<'a>(targets: &'a [Value], target: &Value) -> Option<&'a Value> {
+    targets
+        .iter()
+        .find(|other| other["name"] == target["name"] && other["isStage"] == target["isStage"])
+}
+
+impl Patch {
+    /// Work out the changes from one project.json to another
+    pub fn between(from: String, old: &Value, to: String, new: &Value) -> Self {
+        let (old_targets, new_targets) = (targets(old), targets(new));
+        let mut sprites = vec![];
+        for target in &new_targets {
+            let before = find(&old_targets, target);
+            if before == Some(target) {
+                continue;
+            }
+            sprites.push(SpritePatch {
+                name: target["name"].as_str().unwrap_or_default().into(),
+                is_stage: target["isStage"] == true,
+                before: before.cloned(),
+                after: Some(target.clone()),
+            });
+        }
+        for target in &old_targets {
+            if find(&new_targets, target).is_none() {
+                sprites.push(SpritePatch {
+                    name: target["name"].as_str().unwrap_or_default().into(),
+                    is_stage: target["isStage"] == true,
+                    before: Some(target.clone()),
+                    after: None,
+                });
+            }
+        }
+
+        let old_extensions = old["extensions"].as_array().cloned().unwrap_or_default();
+        let extensions = new["extensions"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter(|extension| !old_extensions.contains(extension))
+            .cloned()
+            .collect();
+
+        Patch {
+            from,
+            to,
+            sprites,
+            extensions,
+        }
+    }
+
+    /// Asset files the changed sprites use, some of which the other copy may not have
+    pub fn asset_paths(&self) -> Result<HashSet<String>> {
+        let mut paths = HashSet::new();
+        for after in self
+            .sprites
+            .iter()
+            .filter_map(|sprite| sprite.after.as_ref())
+        {
+            let target = serde_json::from_value::<Target>(after.clone())?;
+            paths.extend(target.asset_paths());
+        }
+        Ok(paths)
+    }
+
+    /// Apply the changes to a project.json
+    ///
+    /// Fails without changing anything if a sprite in the patch conflicts with `project`, like
+    /// when a script the patch changes was changed differently there, or a sprite the patch
+    /// changes was deleted
+    pub fn apply(&self, project: &Value) -> Result<Value> {
+        let mut targets = targets(project);
+        let mut conflicts = vec![];
+        for sprite in &self.sprites {
+            let position = targets.iter().position(|target| {
+                target["name"] == sprite.name && target["isStage"] == sprite.is_stage
+            });
+            let current = position.map(|i| &targets[i]);
+            let name = &sprite.name;
+            // already applied
+            if current == sprite.after.as_ref() {
+                continue;
+            }
+            match (position, &sprite.before, &sprite.after) {
+                (Some(i), Some(before), Some(after)) if &targets[i] == before => {
+                    let mut after = after.clone();
+                    // the stage keeps variables the patch doesn't know about
+                    if sprite.is_stage {
+                        keep_new(
+                            &mut after,
+                            &targets[i],
+                            &["variables", "lists", "broadcasts"],
+                        );
+                    }
+                    targets[i] = after;
+                }
+                (Some(i), Some(before), Some(after)) => {
+                    if let Err(e) = merge(&mut targets[i], before, after, &sprite.name) {
+                        conflicts.push(format!("{e:#}"));
+                    }
+                }
+                (Some(i), Some(before), None) if &targets[i] == before => {
+                    targets.remove(i);
+                }
+                (None, None, Some(after)) => targets.push(after.clone()),
+                // deleted on both sides
+                (None, Some(_), None) => {}
+                (Some(_), None, _) => conflicts.push(format!("{name} was added here too")),
+                (Some(_), Some(_), None) => {
+                    conflicts.push(format!("{name} was deleted but changed here"))
+                }
+                (None, Some(_), Some(_)) => conflicts.push(format!("{name} isn't in this project")),
+                (None, None, None) => {}
+            }
+        }
+        if !conflicts.is_empty() {
+            return Err(anyhow!(
+                "the patch conflicts with this project: {}",
+                conflicts.join(", ")
+            ));
+        }
+
+        let mut project = project.clone();
+        let mut extensions = project["extensions"]
+            .as_array()
+            .cloned()
+            .unwrap_or_default();
+        for extension in &self.extensions {
+            if !extensions.contains(extension) {
+                extensions.push(extension.clone());
+            }
+        }
+        let object = project.as_object_mut().context("project isn't an object")?;
+        object.insert("targets".into(), targets.into());
+        object.insert("extensions".into(), extensions.into());
+        Ok(project)
+    }
+}
+
+/// Apply the scripts and assets changed from `before` to `after` to a sprite that was changed
+/// in other ways too, failing if any of those scripts was changed in it as well
+fn merge(target: &mut Value, before: &Value, after: &Value, name: &str) -> Result<()> {
+    let parse = |target: &Value| serde_json::from_value::<Target>(target.clone());
+    let (current, old, new) = (parse(&*target)?, parse(before)?, parse(after)?);
+    let (current_scripts, old_scripts, new_scripts) =
+        (current.scripts(), old.scripts(), new.scripts());
+
+    let mut changes = vec![];
+    for id in old_scripts.keys().chain(new_scripts.keys()).unique() {
+        if old_scripts.get(id) == new_scripts.get(id) {
+            continue;
+        }
+        if current_scripts.get(id) != old_scripts.get(id) {
+            return Err(anyhow!("a script in {name} was changed here too"));
+        }
+        changes.push(Selection::Script {
+            sprite: name.into(),
+            script_id: id.to_string(),
+        });
+    }
+    let (old_assets, new_assets) = (
+        old.asset_paths().collect::<HashSet<_>>(),
+        new.asset_paths().collect::<HashSet<_>>(),
+    );
+    for path in old_assets.symmetric_difference(&new_assets) {
+        changes.push(Selection::Asset {
+            sprite: name.into(),
+            path: path.clone(),
+        });
+    }
+
+    let changes = changes.iter().collect::<Vec<_>>();
+    apply_changes(target, after, &changes).with_context(|| format!("can't apply to {name}"))
+}
+
+/// Zip a patch and the assets its changes use into a bundle
+pub fn write<A: AsRef<[u8]>>(
+    bundle: &Path,
+    patch: &Patch,
+    assets: impl IntoIterator<Item = (String, A)>,
+) -> Result<()> {
+    sb3::write_zip(bundle, MANIFEST, &serde_json::to_vec(patch)?, assets)
+}
+
+/// Read a bundle's patch and the assets in it
+pub fn read(bundle: &Path) -> Result<(Patch, sb3::Assets)> {
+    let mut archive = ZipArchive::new(File::open(bundle).context("failed to open patch")?)
+        .context("patch isn't a ZIP file")?;
+    let mut budget = limits::check_archive(&mut archive, "patch")?;
+
+    let entry = archive
+        .by_name(MANIFEST)
+        .with_context(|| format!("patch has no {MANIFEST}"))?;
+    let json = limits::read_entry(entry, MAX_JSON_SIZE, &mut budget)?;
+    limits::check_json(&json, MANIFEST)?;
+    let patch = serde_json::from_slice::<Patch>(&json)?;
+
+    let mut assets = vec![];
+    for asset in patch.asset_paths()? {
+        // asset names come from the patch, so make sure they can't escape the directory
+        if asset.contains(['/', '\\']) || asset.starts_with('.') {
+            return Err(anyhow!("invalid asset name {asset}"));
+        }
+        // assets the other copy already had are left out of the bundle
+        let Ok(file) = archive.by_name(&asset) else {
+            continue;
+        };
+        assets.push((
+            asset,
+            limits::read_entry(file, MAX_ASSET_SIZE, &mut budget)?,
+        ));
+    }
+
+    Ok((patch, assets))
+}
@@ -0,0 +1,81 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Context, Result};
+
+use crate::git::{self, Identity};
+use crate::gitignore;
+use crate::provenance::{self, Source};
+use crate::sb3;
+
+/// Represents how initializing a project repository went
+#[derive(Debug, PartialEq)]
+pub enum InitOutcome {
+    /// The repository was created with an initial commit
+    Created,
+    /// No commit identity is configured, so the initial commit couldn't be made
+    NeedsIdentity,
+}
+
+/// Options for setting up a new project repository
+#[derive(Debug, Default)]
+pub struct InitOptions<'a> {
+    /// Name and email to commit with, stored in the repository config
+    pub identity: Option<&'a Identity>,
+    /// Track binary assets with Git LFS if it's installed
+    pub lfs: bool,
+    /// The shared project this one was started from, recorded in the repository
+    pub source: Option<&'a Source>,
+    /// Message for the initial commit, instead of `Initial commit`
+    pub message: Option<&'a str>,
+}
+
+/// Create a Git repository from an SB3 file in one call
+///
+/// This unpacks project.json and assets into `repo_path`, writes a .gitignore,
+/// installs hooks and .gitattributes, and makes an initial commit
+pub fn init_project(sb3_path: &Path, repo_path: &PathBuf, options: InitOptions) -> Result<InitOutcome> {
+    fs::create_dir_all(repo_path)?;
+
+    sb3::unpack(sb3_path, repo_path)?;
+
+    let init_repo = git::run(vec!["init"], Some(repo_path)).output()?;
+    if !init_repo.status.success() {
+        return Err(anyhow!(
+            "git init failed: {}",
+            String::from_utf8_lossy(&init_repo.stderr).trim()
+        ));
+    }
+
+    gitignore::write_default(repo_path).context("failed to write gitignore")?;
+    git::install_hooks(repo_path).context("failed to install git hooks")?;
+    if options.lfs {
+        git::setup_lfs(repo_path).context("failed to set up git lfs")?;
+    }
+
+    if let Some(source) = options.source {
+        provenance::write(repo_path, source).context("failed to record project source")?;
+    }
+
+    if let Some(identity) = options.identity {
+        git::set_identity(repo_path, identity)?;
+    }
+    if git::identity(repo_path)?.is_none() {
+        return Ok(InitOutcome::NeedsIdentity);
+    }
+
+    if !git::run(vec!["add", "."], Some(repo_path)).status()?.success() {
+        return Err(anyhow!("assets could not be added"));
+    }
+
+    let message = options.message.unwrap_or("Initial commit");
+    let commit = git::run(vec!["commit", "-m", message], Some(repo_path)).output()?;
+    if !commit.status.success() {
+        return Err(anyhow!(
+            "initial commit failed: {}",
+            String::from_utf8_lossy(&commit.stderr).trim()
+        ));
+    }
+
+    Ok(InitOutcome::Created)
+}
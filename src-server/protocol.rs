@@ -0,0 +1,697 @@
+//! Typed WebSocket messages for diff, commit, log, and push
+//!
+//! Messages with a `type` field use this protocol, while messages with a `command` field
+//! are handled by the original command handler. Every response and event carries the ID
+//! of the request it belongs to, so clients can have several requests in flight.
+//!
+//! Clients should start with a `hello` request naming the protocol version and features they
+//! understand. Until then the server only answers requests, so frontends from before events
+//! existed never see a message they don't expect.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex, OnceLock};
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use tokio::task;
+use tracing::{info_span, warn};
+
+use crate::auth;
+use crate::cache::LruCache;
+use crate::cancel::{CancelToken, Cancelled};
+use crate::config::project_config;
+use crate::registry;
+use crate::diff::structs::DiffReport;
+use crate::error::Error;
+use crate::events::broadcast;
+use crate::git::{self, Identity};
+use crate::i18n;
+use crate::jsonrpc;
+use crate::observer;
+use crate::ops::{self, CommitOptions, CommitOutcome, LogEntry, Progress, PushStatus};
+use crate::partial::{self, Selection};
+use crate::ratelimit::{Expensive, RateLimiter};
+use crate::runtime::runtime;
+use crate::sessions;
+use crate::shutdown;
+use crate::status::{self, Status};
+use crate::validate::{Invalid, Violation};
+
+/// Version of the typed protocol, raised when existing messages change incompatibly
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// Features this server supports, for clients to check before relying on them
+pub fn features() -> Vec<&'static str> {
+    let mut features = vec![
+        "events", "progress", "cancel", "jsonrpc", "merge", "bisect", "asset-store", "diff-pages",
+    ];
+    if git::lfs_installed() {
+        features.push("lfs");
+    }
+    features
+}
+
+/// The features a client asked for that this server supports
+pub fn negotiate(features: Vec<String>) -> Vec<String> {
+    let supported = self::features();
+    features
+        .into_iter()
+        .filter(|feature| supported.contains(&feature.as_str()))
+        .collect()
+}
+
+/// Represents a typed request from a client
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum Request {
+    Diff {
+        project_name: String,
+        /// Split the report into pages of at most this many changes, for huge change sets
+        #[serde(default)]
+        page_size: Option<usize>,
+    },
+    /// Get the next page of a paginated diff
+    DiffPage {
+        cursor: String,
+    },
+    Commit {
+        project_name: String,
+        author: Option<Identity>,
+        /// Only commit these changes, like whole sprites or single scripts
+        #[serde(default, alias = "sprites", deserialize_with = "partial::selections")]
+        changes: Option<Vec<Selection>>,
+        /// Title for the commit, with the generated message as its body
+        title: Option<String>,
+        /// Display name of whoever's using the editor, attributed from the project's roster
+        user: Option<String>,
+    },
+    Log {
+        project_name: String,
+        limit: Option<usize>,
+    },
+    Push {
+        project_name: String,
+    },
+    Status,
+    /// Agree on a protocol version and features, sent first
+    Hello {
+        protocol_version: u32,
+        #[serde(default)]
+        features: Vec<String>,
+        /// Locales the user prefers, best first, overriding the handshake's `Accept-Language`
+        #[serde(default)]
+        locales: Vec<String>,
+    },
+    /// Stop a diff that's still running
+    Cancel {
+        request_id: u64,
+    },
+}
+
+impl Request {
+    /// Which rate limit a request counts against, if any
+    pub fn cost(&self) -> Option<Expensive> {
+        match self {
+            Request::Diff { .. } => Some(Expensive::Diff),
+            Request::Log { .. } => Some(Expensive::History),
+            _ => None,
+        }
+    }
+}
+
+/// Represents a request and the ID its responses should be tagged with
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Envelope {
+    pub id: u64,
+    #[serde(flatten)]
+    pub request: Request,
+}
+
+/// Represents the final answer to a request
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum Response {
+    Diff { report: DiffReport },
+    /// A page of a diff report, with every section but only some of their changes. The next
+    /// page can be fetched with the cursor until it's `None`
+    DiffPage {
+        report: Value,
+        cursor: Option<String>,
+    },
+    Commit { message: String },
+    CommitFailed { code: i32 },
+    Log { commits: Vec<LogEntry> },
+    Push { status: PushStatus },
+    Status { status: Status },
+    /// The protocol version both sides understand, the features both support, and the locale
+    /// reports, errors and prompts will be in
+    Hello {
+        protocol_version: u32,
+        server_version: &'static str,
+        features: Vec<String>,
+        locale: String,
+    },
+    /// Answers a cancel request, with whether the request was still running
+    Cancel { cancelled: bool },
+    /// Sent for a request that was cancelled before it finished
+    Cancelled,
+    /// The saved project.json is broken, so it can't be diffed or committed
+    Invalid { violations: Vec<Violation> },
+    /// Too many expensive requests were made too quickly
+    RateLimited { retry_after_ms: u64 },
+    /// The request failed, with the [kind](crate::error::Error::kind) of failure if it's one
+    /// clients can handle
+    Error {
+        message: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        kind: Option<&'static str>,
+    },
+}
+
+/// Represents an update sent while a request is still running
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum Event {
+    Progress {
+        operation: String,
+        done: usize,
+        total: usize,
+    },
+}
+
+/// Represents any message sent to a client, tagged with the request ID
+#[derive(Debug, Serialize)]
+pub struct Outgoing<T> {
+    pub id: u64,
+    #[serde(flatten)]
+    pub body: T,
+}
+
+/// Check if a raw message uses the typed protocol
+pub fn is_typed(message: &Value) -> bool {
+    message.get("type").is_some()
+}
+
+/// Send a request's final response
+fn reply(outgoing: &Sender<Value>, id: u64, response: Response) {
+    if let Ok(response) = serde_json::to_value(Outgoing { id, body: response }) {
+        // the client may have disconnected while the request ran
+        let _ = outgoing.send(response);
+    }
+}
+
+/// Requests a connection has in flight, so they can be cancelled by ID or on disconnect
+#[derive(Debug, Default)]
+pub struct Requests {
+    running: Arc<Mutex<HashMap<u64, CancelToken>>>,
+    limiter: Arc<Mutex<RateLimiter>>,
+    negotiated: Arc<Negotiated>,
+    pages: Arc<Pages>,
+}
+
+/// What a connection agreed on in its `hello`, shared with the JSON-RPC calls it runs
+#[derive(Debug, Default)]
+struct Negotiated {
+    /// Features agreed on in the `hello` request, or `None` for clients that never sent one
+    features: Mutex<Option<Vec<String>>>,
+    /// Locale negotiated with the client, which requests run in
+    locale: Mutex<String>,
+}
+
+impl Negotiated {
+    fn locale(&self) -> String {
+        match self.locale.lock().unwrap().as_str() {
+            "" => "en".into(),
+            locale => locale.to_string(),
+        }
+    }
+
+    /// Agree on features and a locale from a `hello` request, returning both
+    fn hello(&self, features: Vec<String>, locales: &[String]) -> (Vec<String>, String) {
+        if !locales.is_empty() {
+            *self.locale.lock().unwrap() = i18n::negotiate(&locales.join(","));
+        }
+        let features = negotiate(features);
+        *self.features.lock().unwrap() = Some(features.clone());
+        (features, self.locale())
+    }
+}
+
+impl Requests {
+    /// Check if the client said it understands a feature
+    pub fn supports(&self, feature: &str) -> bool {
+        self.negotiated
+            .features
+            .lock()
+            .unwrap()
+            .as_ref()
+            .is_some_and(|features| features.iter().any(|f| f == feature))
+    }
+
+    /// The locale negotiated with the client, or English if there wasn't one
+    pub fn locale(&self) -> String {
+        self.negotiated.locale()
+    }
+
+    /// Set the locale requests run in, like from the handshake's `Accept-Language`
+    pub fn set_locale(&self, locale: &str) {
+        *self.negotiated.locale.lock().unwrap() = locale.to_string();
+    }
+
+    /// Run a request on the worker pool, sending its progress and response through `outgoing`
+    ///
+    /// Returns immediately, so a slow diff doesn't hold up other requests on the same connection
+    pub fn spawn(&self, envelope: Envelope, outgoing: Sender<Value>) {
+        let id = envelope.id;
+        if let Request::Cancel { request_id } = envelope.request {
+            let cancelled = match self.running.lock().unwrap().get(&request_id) {
+                Some(token) => {
+                    token.cancel();
+                    true
+                }
+                None => false,
+            };
+            return reply(&outgoing, id, Response::Cancel { cancelled });
+        }
+
+        if let Request::Hello {
+            protocol_version,
+            features,
+            locales,
+        } = envelope.request
+        {
+            let (features, locale) = self.negotiated.hello(features, &locales);
+            let response = Response::Hello {
+                protocol_version: protocol_version.min(PROTOCOL_VERSION),
+                server_version: env!("CARGO_PKG_VERSION"),
+                features,
+                locale,
+            };
+            return reply(&outgoing, id, response);
+        }
+
+        if let Some(kind) = envelope.request.cost() {
+            if let Err(wait) = self.limiter.lock().unwrap().check(kind) {
+                let retry_after_ms = wait.as_millis() as u64;
+                return reply(&outgoing, id, Response::RateLimited { retry_after_ms });
+            }
+        }
+
+        let cancel = CancelToken::new();
+        self.running.lock().unwrap().insert(id, cancel.clone());
+        let running = self.running.clone();
+        let pages = self.pages.clone();
+        let locale = self.locale();
+
+        runtime().spawn(async move {
+            let progress_outgoing = outgoing.clone();
+            let result = task::spawn_blocking(move || {
+                let _span = info_span!("request", id).entered();
+                i18n::with_locale(&locale, || {
+                    let mut progress = observer::progress(|operation, done, total| {
+                        progress_outgoing.send(serde_json::to_value(Outgoing {
+                            id,
+                            body: Event::Progress {
+                                operation: operation.to_string(),
+                                done,
+                                total,
+                            },
+                        })?)?;
+                        Ok(())
+                    });
+                    respond(envelope.request, &pages, &cancel, &mut progress)
+                })
+            })
+            .await;
+            running.lock().unwrap().remove(&id);
+
+            let response = match result {
+                Ok(Ok(response)) => response,
+                Ok(Err(e)) if e.is::<Cancelled>() => Response::Cancelled,
+                Ok(Err(e)) => {
+                    warn!(id, "request failed: {e:#}");
+                    Response::Error {
+                        message: e.to_string(),
+                        kind: Error::kind_of(&e),
+                    }
+                }
+                Err(e) => {
+                    warn!(id, "request panicked: {e}");
+                    Response::Error {
+                        message: e.to_string(),
+                        kind: None,
+                    }
+                }
+            };
+            reply(&outgoing, id, response);
+        });
+    }
+}
+
+impl Requests {
+    /// Answer a JSON-RPC message on the worker pool, sending notifications and the answer
+    /// through `outgoing`
+    pub fn spawn_jsonrpc(&self, message: String, outgoing: Sender<Value>) {
+        let limiter = self.limiter.clone();
+        let negotiated = self.negotiated.clone();
+        let pages = self.pages.clone();
+        let locale = self.locale();
+        runtime().spawn(async move {
+            let notify_outgoing = outgoing.clone();
+            let answer = task::spawn_blocking(move || {
+                i18n::with_locale(&locale, || {
+                    jsonrpc::handle(
+                        &message,
+                        &mut jsonrpc::Context {
+                            limit: &mut |kind| limiter.lock().unwrap().check(kind),
+                            hello: &mut |features, locales| negotiated.hello(features, &locales),
+                            notify: &mut |notification| {
+                                let _ = notify_outgoing.send(notification);
+                            },
+                            pages: &pages,
+                        },
+                    )
+                })
+            })
+            .await;
+
+            match answer {
+                Ok(Some(answer)) => {
+                    let _ = outgoing.send(answer);
+                }
+                Ok(None) => {}
+                Err(e) => warn!("JSON-RPC call panicked: {e}"),
+            }
+        });
+    }
+}
+
+impl Drop for Requests {
+    /// Nobody is left to read the results once the connection closes
+    fn drop(&mut self) {
+        for token in self.running.lock().unwrap().values() {
+            token.cancel();
+        }
+    }
+}
+
+/// Run a typed request to completion, reporting progress through a callback
+pub fn respond(
+    request: Request,
+    pages: &Pages,
+    cancel: &CancelToken,
+    progress: &mut Progress,
+) -> Result<Response> {
+    Ok(match request {
+        Request::Diff {
+            project_name,
+            page_size,
+        } => {
+            let pth = registry::project_path(&project_name)?;
+            let report = match ops::diff_report(&pth, cancel, progress) {
+                Ok(report) => report,
+                Err(e) => {
+                    return match e.downcast::<Invalid>() {
+                        Ok(Invalid(violations)) => Ok(Response::Invalid { violations }),
+                        Err(e) => Err(e),
+                    }
+                }
+            };
+            broadcast(json!({ "type": "diff-ready", "project_name": project_name }));
+            match page_size {
+                Some(page_size) => {
+                    let (report, cursor) =
+                        pages.paginate(serde_json::to_value(report)?, page_size)?;
+                    Response::DiffPage { report, cursor }
+                }
+                None => Response::Diff { report },
+            }
+        }
+        Request::DiffPage { cursor } => {
+            let (report, cursor) = pages.next_page(&cursor)?;
+            Response::DiffPage { report, cursor }
+        }
+        Request::Commit {
+            project_name,
+            author,
+            changes,
+            title,
+            user,
+        } => {
+            let (pth, sb3, asset_store, (roster_author, editor)) = {
+                let config = project_config().lock().unwrap();
+                (
+                    config.project_path(&project_name),
+                    PathBuf::from(config.project_sb3(&project_name)),
+                    config.flag(&project_name, "asset_store"),
+                    config.attribute(&project_name, user.as_deref()),
+                )
+            };
+            let _operation = shutdown::begin_operation()?;
+            // requests run concurrently, but two commits to one repository can't
+            let project = registry::project(&project_name)?;
+            let _lock = project.operation.lock().unwrap();
+            progress.on_progress("commit", 0, 1)?;
+            let options = CommitOptions {
+                author: author.as_ref().or(roster_author.as_ref()),
+                asset_store,
+                selection: None,
+                title: title.as_deref(),
+                editor: editor.as_deref().filter(|_| author.is_none()),
+                sb3: Some(&sb3),
+            };
+            let outcome = match &changes {
+                Some(changes) => {
+                    let report = match ops::diff_report(&pth, cancel, progress) {
+                        Ok(report) => report,
+                        Err(e) => {
+                            return match e.downcast::<Invalid>() {
+                                Ok(Invalid(violations)) => Ok(Response::Invalid { violations }),
+                                Err(e) => Err(e),
+                            }
+                        }
+                    };
+                    ops::commit_selection(&pth, &report, changes, options, progress)?
+                }
+                None => ops::commit(&pth, options, progress)?,
+            };
+            progress.on_progress("commit", 1, 1)?;
+            match outcome {
+                CommitOutcome::Committed(message) => {
+                    sessions::project_changed(&project_name, "commit", None);
+                    Response::Commit { message }
+                }
+                CommitOutcome::Invalid(violations) => Response::Invalid { violations },
+                failure => Response::CommitFailed {
+                    code: failure.code(),
+                },
+            }
+        }
+        Request::Log {
+            project_name,
+            limit,
+        } => {
+            let pth = registry::project_path(&project_name)?;
+            Response::Log {
+                commits: ops::log(&pth, limit)?,
+            }
+        }
+        Request::Push { project_name } => {
+            let project = registry::project(&project_name)?;
+            let _operation = shutdown::begin_operation()?;
+            let _lock = project.operation.lock().unwrap();
+            let pth = project.path.clone();
+            progress.on_progress("push", 0, 1)?;
+            let status = ops::push(&pth, progress)?;
+            progress.on_progress("push", 1, 1)?;
+            Response::Push { status }
+        }
+        Request::Status => Response::Status {
+            status: status::status(),
+        },
+        // answered by `Requests::spawn` without running anything
+        Request::Cancel { .. } => Response::Cancel { cancelled: false },
+        Request::Hello {
+            protocol_version, ..
+        } => Response::Hello {
+            protocol_version: protocol_version.min(PROTOCOL_VERSION),
+            server_version: env!("CARGO_PKG_VERSION"),
+            features: features().into_iter().map(str::to_string).collect(),
+            locale: i18n::current_locale(),
+        },
+    })
+}
+
+/// A diff report split into its changes, kept between pages
+#[derive(Debug)]
+struct PagedReport {
+    /// Sections that aren't lists, sent whole on every page
+    fixed: Map<String, Value>,
+    /// Every section that's a list, so each page has all of them
+    sections: Vec<String>,
+    /// Each change, with the index of the section it's in
+    changes: Vec<(usize, Value)>,
+    page_size: usize,
+}
+
+/// Reports a client is still paging through, keyed by the first part of their cursors
+///
+/// Each connection has its own, so cursors from one can't be used on another. Only a few are
+/// kept, so reports clients stopped paging through don't pile up
+#[derive(Debug)]
+pub struct Pages(Mutex<LruCache<String, Arc<PagedReport>>>);
+
+impl Default for Pages {
+    fn default() -> Self {
+        Pages(Mutex::new(LruCache::new(16)))
+    }
+}
+
+impl Pages {
+    /// Pages for HTTP clients, which have no connection to keep them with
+    ///
+    /// Cursors are random, so clients still can't guess each other's
+    pub fn http() -> &'static Pages {
+        static PAGES: OnceLock<Pages> = OnceLock::new();
+        PAGES.get_or_init(Pages::default)
+    }
+
+    /// Return the first page of a report, and a cursor for the next if there's more
+    fn paginate(&self, report: Value, page_size: usize) -> Result<(Value, Option<String>)> {
+        let Value::Object(report) = report else {
+            return Err(anyhow!("diff report isn't an object"));
+        };
+
+        let mut paged = PagedReport {
+            fixed: Map::new(),
+            sections: vec![],
+            changes: vec![],
+            page_size: page_size.max(1),
+        };
+        for (section, changes) in report {
+            let Value::Array(changes) = changes else {
+                paged.fixed.insert(section, changes);
+                continue;
+            };
+            let index = paged.sections.len();
+            paged.sections.push(section);
+            let changes = changes.into_iter().map(|change| (index, change));
+            paged.changes.extend(changes);
+        }
+
+        let key = auth::random_hex();
+        let paged = Arc::new(paged);
+        self.0.lock().unwrap().insert(key.clone(), paged.clone());
+        Ok(self.page(&paged, &key, 0))
+    }
+
+    /// Return the page a cursor points to, and a cursor for the one after it
+    fn next_page(&self, cursor: &str) -> Result<(Value, Option<String>)> {
+        let (key, offset) = cursor
+            .split_once(':')
+            .and_then(|(key, offset)| Some((key, offset.parse().ok()?)))
+            .ok_or_else(|| anyhow!("{cursor} isn't a cursor"))?;
+        let paged = self.0.lock().unwrap().get(&key.to_string());
+        let paged = paged.ok_or_else(|| anyhow!("{cursor} has expired, so diff again"))?;
+        Ok(self.page(&paged, key, offset))
+    }
+
+    /// Lay out the changes from `offset` on as a report, with the sections they're not in empty
+    fn page(&self, paged: &PagedReport, key: &str, offset: usize) -> (Value, Option<String>) {
+        let mut sections = vec![vec![]; paged.sections.len()];
+        let end = (offset + paged.page_size).min(paged.changes.len());
+        for (index, change) in paged.changes.get(offset..end).unwrap_or_default() {
+            sections[*index].push(change.clone());
+        }
+        let sections = sections.into_iter().map(Value::Array);
+        let mut report = paged.fixed.clone();
+        report.extend(paged.sections.iter().cloned().zip(sections));
+
+        let cursor = if end < paged.changes.len() {
+            Some(format!("{key}:{end}"))
+        } else {
+            // the last page was sent, so the report isn't needed anymore
+            self.0.lock().unwrap().retain(|cached| cached != key);
+            None
+        };
+        (Value::Object(report), cursor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report() -> Value {
+        json!({
+            "assets": ["a", "b", "c"],
+            "scripts": ["d"],
+            "warnings": [],
+            "summary": {"changes": 4},
+        })
+    }
+
+    #[test]
+    fn pages_split_changes_and_keep_other_sections() {
+        let pages = Pages::default();
+        let (first, cursor) = pages.paginate(report(), 2).unwrap();
+        assert_eq!(first["assets"], json!(["a", "b"]));
+        assert_eq!(first["scripts"], json!([]));
+        assert_eq!(first["warnings"], json!([]));
+        assert_eq!(first["summary"], json!({"changes": 4}));
+
+        let (second, cursor) = pages.next_page(&cursor.unwrap()).unwrap();
+        assert_eq!(second["assets"], json!(["c"]));
+        assert_eq!(second["scripts"], json!(["d"]));
+        assert_eq!(second["summary"], json!({"changes": 4}));
+        assert_eq!(cursor, None);
+    }
+
+    #[test]
+    fn pages_end_on_a_boundary() {
+        let pages = Pages::default();
+        let (_, cursor) = pages.paginate(report(), 4).unwrap();
+        assert_eq!(cursor, None);
+
+        let (first, cursor) = pages.paginate(report(), 3).unwrap();
+        assert_eq!(first["assets"], json!(["a", "b", "c"]));
+        let (last, cursor) = pages.next_page(&cursor.unwrap()).unwrap();
+        assert_eq!(last["scripts"], json!(["d"]));
+        assert_eq!(cursor, None);
+
+        let (only, cursor) = pages.paginate(json!({"assets": []}), 0).unwrap();
+        assert_eq!((only, cursor), (json!({"assets": []}), None));
+    }
+
+    #[test]
+    fn cursors_expire() {
+        let pages = Pages::default();
+        let (_, cursor) = pages.paginate(report(), 3).unwrap();
+        let cursor = cursor.unwrap();
+        pages.next_page(&cursor).unwrap();
+        // the last page was sent, so the report is gone
+        assert!(pages.next_page(&cursor).is_err());
+
+        let (_, oldest) = pages.paginate(report(), 1).unwrap();
+        for _ in 0..16 {
+            pages.paginate(report(), 1).unwrap();
+        }
+        assert!(pages.next_page(&oldest.unwrap()).is_err());
+        assert!(pages.next_page("not a cursor").is_err());
+    }
+
+    #[test]
+    fn cursors_belong_to_their_connection() {
+        let (pages, other) = (Pages::default(), Pages::default());
+        let (_, cursor) = pages.paginate(report(), 1).unwrap();
+        let cursor = cursor.unwrap();
+        assert!(other.next_page(&cursor).is_err());
+        assert!(pages.next_page(&cursor).is_ok());
+
+        let (_, second) = pages.paginate(report(), 1).unwrap();
+        assert_ne!(cursor.split(':').next(), second.unwrap().split(':').next());
+    }
+}
@@ -0,0 +1,222 @@
+//! Starting projects from ones shared on the Scratch website, and remembering where they came
+//! from
+//!
+//! A project started this way keeps its source in `.scratch-source.json`, which is committed
+//! with it, so clones know what the project is a remix of and can diff against the original.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+use anyhow::{anyhow, Context, Result};
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tracing::warn;
+
+use crate::sb2;
+use crate::sb3::{self, get_assets, Project};
+
+/// Where a project's source is kept in its repository
+pub const SOURCE_FILE: &str = ".scratch-source.json";
+
+const API_URL: &str = "https://api.scratch.mit.edu/projects";
+const PROJECTS_URL: &str = "https://projects.scratch.mit.edu";
+const ASSETS_URL: &str = "https://assets.scratch.mit.edu/internalapi/asset";
+
+/// Assets downloaded at once, since each is its own request
+const DOWNLOAD_WORKERS: usize = 8;
+
+/// Represents the shared project a project was started from
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Source {
+    /// ID of the shared project, as in `scratch.mit.edu/projects/<id>`
+    pub project_id: u64,
+    pub title: String,
+    /// Username of whoever shared it
+    pub author: String,
+    /// The project the shared one was itself remixed from
+    #[serde(default)]
+    pub remix_parent: Option<u64>,
+    /// The project at the start of the chain of remixes
+    #[serde(default)]
+    pub remix_root: Option<u64>,
+}
+
+impl Source {
+    pub fn url(&self) -> String {
+        format!("https://scratch.mit.edu/projects/{}", self.project_id)
+    }
+}
+
+/// Get a project ID from a link to a shared project, or from the ID itself
+pub fn parse_id(url: &str) -> Option<u64> {
+    let url = url.trim().trim_end_matches('/');
+    let id = url.rsplit_once("/projects/").map_or(url, |(_, rest)| {
+        rest.split(['/', '?', '#']).next().unwrap_or_default()
+    });
+    id.parse().ok()
+}
+
+/// Look up a shared project, returning its source and the token needed to download it
+fn lookup(project_id: u64) -> Result<(Source, String)> {
+    let response = minreq::get(format!("{API_URL}/{project_id}"))
+        .with_header("User-Agent", "scratch.git")
+        .send()?;
+    if response.status_code == 404 {
+        return Err(anyhow!("project {project_id} isn't shared"));
+    }
+    let info = response.json::<Value>()?;
+
+    let source = Source {
+        project_id,
+        title: info["title"].as_str().unwrap_or_default().to_string(),
+        author: info["author"]["username"]
+            .as_str()
+            .unwrap_or_default()
+            .to_string(),
+        remix_parent: info["remix"]["parent"].as_u64(),
+        remix_root: info["remix"]["root"].as_u64(),
+    };
+    let token = info["project_token"]
+        .as_str()
+        .context("project has no download token")?
+        .to_string();
+    Ok((source, token))
+}
+
+fn download_json(project_id: u64, token: &str) -> Result<Vec<u8>> {
+    let response = minreq::get(format!("{PROJECTS_URL}/{project_id}?token={token}"))
+        .with_header("User-Agent", "scratch.git")
+        .send()?;
+    if response.status_code != 200 {
+        return Err(anyhow!(
+            "failed to download project {project_id}: HTTP {}",
+            response.status_code
+        ));
+    }
+    Ok(response.into_bytes())
+}
+
+/// Download a shared project with its assets into an SB3, returning where it came from
+///
+/// Projects last saved in Scratch 2 are converted to Scratch 3 on the way
+pub fn download(project_id: u64, sb3: &Path) -> Result<Source> {
+    let (source, token) = lookup(project_id)?;
+    let json = download_json(project_id, &token)?;
+    let project = serde_json::from_slice::<Value>(&json).context("project isn't JSON")?;
+    // Scratch 2 projects are a stage with sprites as its children
+    if project.get("targets").is_none() && project.get("objName").is_some() {
+        let skipped = download_sb2(project, sb3)?;
+        if !skipped.is_empty() {
+            warn!("left out Scratch 2 blocks: {}", skipped.join(", "));
+        }
+        return Ok(source);
+    }
+
+    let project =
+        serde_json::from_value::<Project>(project).context("project isn't a Scratch 3 project")?;
+    let assets = download_assets(get_assets(&project).into_iter().unique().collect())?;
+    sb3::write(sb3, &json, assets)?;
+    Ok(source)
+}
+
+/// Convert a project last saved in Scratch 2, returning the blocks that were left out
+///
+/// Shared Scratch 2 projects refer to their assets by MD5, so each asset is numbered the way
+/// an SB2 file stores them first
+fn download_sb2(mut stage: Value, sb3: &Path) -> Result<Vec<String>> {
+    let mut files = vec![];
+    let mut number = |object: &mut Value| {
+        for (list, index_key, md5_key) in [
+            ("costumes", "baseLayerID", "baseLayerMD5"),
+            ("sounds", "soundID", "md5"),
+        ] {
+            let assets = object.get_mut(list).and_then(Value::as_array_mut);
+            for asset in assets.into_iter().flatten() {
+                let Some(md5ext) = asset[md5_key].as_str().map(str::to_string) else {
+                    continue;
+                };
+                let ext = md5ext.rsplit_once('.').map_or("", |(_, ext)| ext);
+                let index = files.len();
+                files.push((format!("{index}.{ext}"), md5ext.clone()));
+                asset[index_key] = index.into();
+            }
+        }
+    };
+    number(&mut stage);
+    let children = stage.get_mut("children").and_then(Value::as_array_mut);
+    for child in children.into_iter().flatten() {
+        number(child);
+    }
+
+    let assets = files.iter().map(|(_, md5ext)| md5ext.clone()).unique();
+    let contents = download_assets(assets.collect())?
+        .into_iter()
+        .collect::<HashMap<_, _>>();
+    let mut sources = files
+        .into_iter()
+        .map(|(file, md5ext)| (file, contents[&md5ext].clone()))
+        .collect::<BTreeMap<_, _>>();
+    sources.insert("project.json".into(), serde_json::to_vec(&stage)?);
+
+    sb2::convert_files(sources, sb3)
+}
+
+/// Download assets from the Scratch website by file name, a few at a time
+fn download_assets(assets: Vec<String>) -> Result<Vec<(String, Vec<u8>)>> {
+    let next = AtomicUsize::new(0);
+    let workers = DOWNLOAD_WORKERS.min(assets.len());
+    thread::scope(|scope| {
+        let handles = (0..workers)
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut downloaded = vec![];
+                    while let Some(asset) = assets.get(next.fetch_add(1, Ordering::Relaxed)) {
+                        downloaded.push((asset.clone(), download_asset(asset)?));
+                    }
+                    Ok::<_, anyhow::Error>(downloaded)
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let mut downloaded = vec![];
+        for handle in handles {
+            downloaded.extend(handle.join().unwrap()?);
+        }
+        Ok(downloaded)
+    })
+}
+
+fn download_asset(asset: &str) -> Result<Vec<u8>> {
+    let response = minreq::get(format!("{ASSETS_URL}/{asset}/get/"))
+        .with_header("User-Agent", "scratch.git")
+        .send()?;
+    if response.status_code != 200 {
+        return Err(anyhow!("failed to download {asset}"));
+    }
+    Ok(response.into_bytes())
+}
+
+/// Download the current version of a project's original, without its assets
+pub fn original(source: &Source) -> Result<Project> {
+    let (_, token) = lookup(source.project_id)?;
+    let json = download_json(source.project_id, &token)?;
+    Ok(serde_json::from_slice(&json)?)
+}
+
+/// Read where a project came from, if it was started from a shared project
+pub fn read(cwd: &PathBuf) -> Option<Source> {
+    serde_json::from_slice(&fs::read(cwd.join(SOURCE_FILE)).ok()?).ok()
+}
+
+/// Record where a project came from in its repository
+pub fn write(cwd: &PathBuf, source: &Source) -> Result<()> {
+    let mut json = serde_json::to_vec_pretty(source)?;
+    json.push(b'\n');
+    fs::write(cwd.join(SOURCE_FILE), json)?;
+    Ok(())
+}
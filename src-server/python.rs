@@ -0,0 +1,120 @@
+//! Python bindings, for scripting batch analysis of many projects, like a class's repositories
+//! in a notebook
+//!
+//! Built as the `pixelpioneers_core` extension module with `maturin develop --features python`.
+//! Results come back as plain dicts and lists, shaped like the server's JSON:
+//!
+//! ```python
+//! import pixelpioneers_core as pp
+//!
+//! project = pp.load_project("students/ada")
+//! report = pp.diff("students/ada", "HEAD~1", "HEAD")
+//! rows = pp.history("students/ada")
+//! ```
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use pyo3::exceptions::PyRuntimeError;
+use pyo3::prelude::*;
+use serde::Serialize;
+
+use crate::diff::builder::Source;
+use crate::diff::structs::Diff;
+use crate::error::Error;
+use crate::observer::Quiet;
+use crate::ops;
+use crate::sb3::{self, Project};
+
+/// Raise an error as a `RuntimeError`, with its kind from [`Error::kind`] in front if it has one
+fn raise(e: anyhow::Error) -> PyErr {
+    match Error::kind_of(&e) {
+        Some(kind) => PyRuntimeError::new_err(format!("{kind}: {e:#}")),
+        None => PyRuntimeError::new_err(format!("{e:#}")),
+    }
+}
+
+/// Convert to Python objects by way of JSON, so results look like the server's responses
+fn to_python(py: Python<'_>, value: &impl Serialize) -> PyResult<PyObject> {
+    let json = serde_json::to_string(value).map_err(|e| raise(e.into()))?;
+    let json_module = py.import_bound("json")?;
+    Ok(json_module.call_method1("loads", (json,))?.unbind())
+}
+
+/// Run work without holding the GIL, so other Python threads can run in the meantime
+fn run<T: Serialize + Send>(
+    py: Python<'_>,
+    work: impl FnOnce() -> Result<T> + Send,
+) -> PyResult<PyObject> {
+    let result = py.allow_threads(work).map_err(raise)?;
+    to_python(py, &result)
+}
+
+/// Read a project from a project directory, a project.json or an SB3
+fn read_project(path: &Path) -> Result<Project> {
+    if path.is_dir() {
+        return ops::parse_project(&fs::read_to_string(path.join("project.json"))?);
+    }
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("sb3") => sb3::read_project_json(path),
+        _ => ops::parse_project(&fs::read_to_string(path)?),
+    }
+}
+
+/// Load a project as a dict, from a project directory, a project.json or an SB3
+#[pyfunction]
+fn load_project(py: Python<'_>, path: PathBuf) -> PyResult<PyObject> {
+    run(py, || read_project(&path))
+}
+
+/// Count a project's sprites, scripts, blocks by category, variables and assets
+#[pyfunction]
+fn stats(py: Python<'_>, path: PathBuf) -> PyResult<PyObject> {
+    run(py, || {
+        // asset sizes are read from the directory the project is in
+        let dir = if path.is_dir() {
+            path.as_path()
+        } else {
+            path.parent().unwrap_or(Path::new("."))
+        };
+        Ok(read_project(&path)?.stats(dir))
+    })
+}
+
+/// Diff two revisions of a project's repository, or a revision and the saved project when
+/// `new` is `None`
+#[pyfunction]
+#[pyo3(signature = (repo, old = "HEAD".to_string(), new = None))]
+fn diff(py: Python<'_>, repo: PathBuf, old: String, new: Option<String>) -> PyResult<PyObject> {
+    run(py, || {
+        let mut builder = Diff::builder().repo(&repo).before(Source::Revision(old));
+        if let Some(new) = new {
+            builder = builder.after(Source::Revision(new));
+        }
+        builder.build()?.report(&mut Quiet)
+    })
+}
+
+/// Commits in a project's repository, newest first
+#[pyfunction]
+#[pyo3(signature = (repo, limit = None))]
+fn log(py: Python<'_>, repo: PathBuf, limit: Option<usize>) -> PyResult<PyObject> {
+    run(py, || ops::log(&repo, limit))
+}
+
+/// What each commit changed in each sprite, oldest first, as rows ready for a data frame
+#[pyfunction]
+fn history(py: Python<'_>, repo: PathBuf) -> PyResult<PyObject> {
+    run(py, || crate::history::history(&repo))
+}
+
+#[pymodule]
+fn pixelpioneers_core(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_function(wrap_pyfunction!(load_project, m)?)?;
+    m.add_function(wrap_pyfunction!(stats, m)?)?;
+    m.add_function(wrap_pyfunction!(diff, m)?)?;
+    m.add_function(wrap_pyfunction!(log, m)?)?;
+    m.add_function(wrap_pyfunction!(history, m)?)?;
+    Ok(())
+}
@@ -0,0 +1,98 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+/// Kinds of requests expensive enough to limit, each with its own budget
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Expensive {
+    /// Parsing and diffing whole projects
+    Diff,
+    /// Walking commit history
+    History,
+}
+
+impl Expensive {
+    /// Classify a command from the original command handler
+    pub fn from_command(command: &str) -> Option<Self> {
+        match command {
+            "get-changed-sprites"
+            | "get-changed-assets"
+            | "current-project"
+            | "previous-project"
+            | "get-renames"
+            | "compare-branch"
+            | "diff-branches"
+            | "preview-merge"
+            | "export-diff-html"
+            | "diff-markdown"
+            | "changed-blocks" => Some(Expensive::Diff),
+            "get-commits" | "bisect" | "export-history" | "sprite-timeline"
+            | "get-contributors" => Some(Expensive::History),
+            _ => None,
+        }
+    }
+
+    /// How many requests can be made back to back, and how often one more is allowed after
+    fn budget(self) -> (f64, Duration) {
+        match self {
+            Expensive::Diff => (5.0, Duration::from_millis(500)),
+            Expensive::History => (5.0, Duration::from_secs(1)),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// Token buckets for one client
+#[derive(Debug, Default)]
+pub struct RateLimiter {
+    buckets: HashMap<Expensive, Bucket>,
+}
+
+impl RateLimiter {
+    /// Take a token for a request, or return how long until one is available
+    pub fn check(&mut self, kind: Expensive) -> Result<(), Duration> {
+        let (capacity, interval) = kind.budget();
+        let now = Instant::now();
+        let bucket = self.buckets.entry(kind).or_insert(Bucket {
+            tokens: capacity,
+            updated: now,
+        });
+
+        let refilled = now.duration_since(bucket.updated).as_secs_f64() / interval.as_secs_f64();
+        bucket.tokens = (bucket.tokens + refilled).min(capacity);
+        bucket.updated = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(interval.mul_f64(1.0 - bucket.tokens))
+        }
+    }
+
+    /// Wait until a request is allowed, for transports with no way to say "try again later"
+    pub fn throttle(&mut self, kind: Expensive) {
+        while let Err(wait) = self.check(kind) {
+            std::thread::sleep(wait);
+        }
+    }
+}
+
+/// Take a token for an HTTP client, which opens a new connection per request so can't keep
+/// its own limiter
+pub fn check_http(client: IpAddr, kind: Expensive) -> Result<(), Duration> {
+    static LIMITERS: OnceLock<Mutex<HashMap<IpAddr, RateLimiter>>> = OnceLock::new();
+    LIMITERS
+        .get_or_init(Default::default)
+        .lock()
+        .unwrap()
+        .entry(client)
+        .or_default()
+        .check(kind)
+}
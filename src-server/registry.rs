@@ -0,0 +1,104 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, OnceLock};
+
+use anyhow::{anyhow, Result};
+use notify::RecommendedWatcher;
+
+use crate::config::project_config;
+use crate::i18n::message;
+use crate::watcher;
+
+/// Represents a project the server manages, along with its runtime state
+#[derive(Debug)]
+pub struct Project {
+    pub name: String,
+    /// Path to the project's Git repository
+    pub path: PathBuf,
+    /// Path to the SB3 the editor saves to
+    pub sb3: PathBuf,
+    /// Held while a state-changing Git operation runs, so two can't overlap
+    pub operation: Mutex<()>,
+    watcher: Mutex<Option<RecommendedWatcher>>,
+}
+
+impl Project {
+    /// Start broadcasting pending changes whenever the project's SB3 is saved
+    pub fn watch(&self) -> Result<()> {
+        let mut watcher = self.watcher.lock().unwrap();
+        if watcher.is_none() {
+            *watcher = Some(watcher::watch(
+                &self.name,
+                self.path.clone(),
+                self.sb3.clone(),
+            )?);
+        }
+        Ok(())
+    }
+
+    pub fn unwatch(&self) {
+        *self.watcher.lock().unwrap() = None;
+    }
+
+    pub fn is_watching(&self) -> bool {
+        self.watcher.lock().unwrap().is_some()
+    }
+}
+
+/// Every project the server has touched, keyed by project name
+#[derive(Debug, Default)]
+pub struct ProjectRegistry {
+    projects: HashMap<String, Arc<Project>>,
+}
+
+impl ProjectRegistry {
+    /// Return a project's state, loading it from the project config on first use
+    pub fn get(&mut self, name: &str) -> Result<Arc<Project>> {
+        if let Some(project) = self.projects.get(name) {
+            return Ok(project.clone());
+        }
+
+        let config = project_config().lock().unwrap();
+        if !config.projects[name].is_object() {
+            return Err(anyhow!(message("error-unknown-project", &[("name", name)])));
+        }
+
+        let project = Arc::new(Project {
+            name: name.to_string(),
+            path: config.project_path(name),
+            sb3: PathBuf::from(config.project_sb3(name)),
+            operation: Mutex::new(()),
+            watcher: Mutex::new(None),
+        });
+        self.projects.insert(name.to_string(), project.clone());
+        Ok(project)
+    }
+
+    /// Stop managing a project, dropping its watcher and state
+    pub fn remove(&mut self, name: &str) {
+        if let Some(project) = self.projects.remove(name) {
+            project.unwatch();
+        }
+    }
+
+    /// Return every project that has been loaded
+    pub fn projects(&self) -> Vec<Arc<Project>> {
+        self.projects.values().cloned().collect()
+    }
+}
+
+/// Singleton project registry
+pub fn registry() -> &'static Mutex<ProjectRegistry> {
+    static REGISTRY: OnceLock<Mutex<ProjectRegistry>> = OnceLock::new();
+    REGISTRY.get_or_init(|| Mutex::new(ProjectRegistry::default()))
+}
+
+/// Return a project's state by its name
+pub fn project(name: &str) -> Result<Arc<Project>> {
+    registry().lock().unwrap().get(name)
+}
+
+/// Return the path to a project's Git repository by its name
+pub fn project_path(name: &str) -> Result<PathBuf> {
+    Ok(project(name)?.path.clone())
+}
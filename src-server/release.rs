@@ -0,0 +1,173 @@
+//! Tagging versions of a project, with a build of each one ready to share
+//!
+//! Tagging exports the tagged commit as an SB3 and, if `release.packager` is set, packages it
+//! with a command like the TurboWarp Packager's CLI. The build's path is kept in the tag's
+//! message as a `Build:` trailer, so it can be found again from the tag alone.
+//!
+//! A release is a tag whose message is release notes, made from the changelog since the last
+//! tag, and that's only made once the project is valid.
+
+use std::fs;
+use std::path::PathBuf;
+use std::process::Stdio;
+
+use anyhow::{anyhow, Context, Result};
+use serde::Serialize;
+
+use crate::changelog;
+use crate::config;
+use crate::git;
+use crate::hooks;
+use crate::ops;
+use crate::validate;
+
+/// Trailer in a tag's message giving the path of its build
+const BUILD_TRAILER: &str = "Build: ";
+
+/// Represents a tagged version and where its build is
+#[derive(Debug, Clone, Serialize)]
+pub struct Release {
+    pub tag: String,
+    pub commit: String,
+    pub message: String,
+    pub build: Option<PathBuf>,
+}
+
+/// Tag the last commit as a version, building it first
+///
+/// Nothing is tagged if the build fails, so every tag made here has a build
+pub fn tag(pth: &PathBuf, name: &str, message: Option<&str>) -> Result<Release> {
+    let valid = git::run(vec!["check-ref-format", &format!("refs/tags/{name}")], None)
+        .status()?
+        .success();
+    if !valid {
+        return Err(anyhow!("{name} isn't a valid tag name"));
+    }
+    if git::resolve(pth, &format!("refs/tags/{name}")).is_ok() {
+        return Err(anyhow!("{name} is already a tag"));
+    }
+
+    let build = build(pth, name)?;
+    let message = message.filter(|m| !m.trim().is_empty()).unwrap_or(name);
+    let annotation = format!("{}\n\n{BUILD_TRAILER}{}", message.trim(), build.display());
+    // messages can be Markdown, whose headings Git would strip as comments
+    let tagged = git::run(
+        vec!["tag", "-a", "--cleanup=whitespace", name, "-m", &annotation],
+        Some(pth),
+    )
+    .output()?;
+    if !tagged.status.success() {
+        return Err(anyhow!(
+            "failed to tag {name}: {}",
+            String::from_utf8_lossy(&tagged.stderr).trim()
+        ));
+    }
+
+    Ok(Release {
+        tag: name.into(),
+        commit: git::resolve(pth, name)?,
+        message: message.trim().into(),
+        build: Some(build),
+    })
+}
+
+/// Release the last commit as a version, with notes on what changed since the last tag
+///
+/// `notes` go before the generated ones. Nothing is tagged if the project doesn't validate.
+pub fn release(pth: &PathBuf, version: &str, notes: Option<&str>) -> Result<Release> {
+    validate::check(&git::show_revision(pth, "HEAD:project.json")?)
+        .with_context(|| format!("can't release {version}"))?;
+
+    // the first release covers everything after the first commit
+    let from = match releases(pth)?.into_iter().next() {
+        Some(last) => last.tag,
+        None => {
+            let output =
+                git::run(vec!["rev-list", "--max-parents=0", "HEAD"], Some(pth)).output()?;
+            let roots = String::from_utf8_lossy(&output.stdout);
+            roots
+                .lines()
+                .next()
+                .context("project has no commits")?
+                .to_string()
+        }
+    };
+    let labeler = config::settings(Some(pth)).commit.labeler();
+    let mut changes = changelog::changelog(pth, &from, "HEAD")?;
+    // HEAD is about to be the new version
+    changes.to = version.into();
+    let changes = changes.markdown(&labeler);
+    let message = match notes.map(str::trim).filter(|notes| !notes.is_empty()) {
+        Some(notes) => format!("{version}\n\n{notes}\n\n{changes}"),
+        None => format!("{version}\n\n{changes}"),
+    };
+    tag(pth, version, Some(&message))
+}
+
+/// Export the last commit as `<project>-<tag>.sb3` and package it, returning the build's path
+fn build(pth: &PathBuf, name: &str) -> Result<PathBuf> {
+    let settings = config::settings(Some(pth)).release;
+    fs::create_dir_all(&settings.output_dir)?;
+    // the packager runs in the project's folder, so it needs absolute paths
+    let output_dir = dunce::canonicalize(&settings.output_dir)?;
+    let project = pth.file_name().unwrap_or_default().to_string_lossy();
+    let stem = format!("{project}-{name}").replace(['/', '\\'], "-");
+
+    let sb3 = output_dir.join(format!("{stem}.sb3"));
+    ops::export_revision(pth, "HEAD", &sb3).context("failed to export project")?;
+    let Some(packager) = settings.packager else {
+        return Ok(sb3);
+    };
+
+    let output = output_dir.join(format!("{stem}.{}", settings.packager_ext));
+    let packaged = hooks::shell_with_paths(&packager, &[("input", &sb3), ("output", &output)])
+        .current_dir(pth)
+        .stdin(Stdio::null())
+        .output()
+        .with_context(|| format!("failed to run packager `{packager}`"))?;
+    if !packaged.status.success() || !output.exists() {
+        return Err(anyhow!(
+            "packager `{packager}` failed: {}",
+            String::from_utf8_lossy(&packaged.stderr).trim()
+        ));
+    }
+    Ok(output)
+}
+
+/// List a project's tagged versions, newest first
+pub fn releases(pth: &PathBuf) -> Result<Vec<Release>> {
+    // unit and record separators can't show up in tag names
+    let output = git::run(
+        vec![
+            "for-each-ref",
+            "--sort=-creatordate",
+            "--format=%(refname:short)%1f%(objectname)%(*objectname)%1f%(contents)%1e",
+            "refs/tags",
+        ],
+        Some(pth),
+    )
+    .output()?;
+
+    let releases = String::from_utf8_lossy(&output.stdout)
+        .split('\x1e')
+        .filter_map(|record| {
+            let mut fields = record.trim_start().splitn(3, '\x1f');
+            let (tag, objects, contents) = (fields.next()?, fields.next()?, fields.next()?);
+            // annotated tags give the tag object then the commit, lightweight ones the commit
+            let commit = objects
+                .get(objects.len().saturating_sub(40)..)
+                .unwrap_or(objects);
+            let (message, build) = match contents.rsplit_once(BUILD_TRAILER) {
+                Some((message, build)) => (message, Some(PathBuf::from(build.trim()))),
+                None => (contents, None),
+            };
+            Some(Release {
+                tag: tag.into(),
+                commit: commit.into(),
+                message: message.trim().into(),
+                build,
+            })
+        })
+        .collect();
+    Ok(releases)
+}
@@ -0,0 +1,104 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::thread::{sleep, spawn};
+use std::time::Duration;
+
+use anyhow::Result;
+use serde_json::json;
+use tracing::debug;
+
+use crate::config::{self, project_config};
+use crate::diff::structs::Diff;
+use crate::events::broadcast;
+use crate::git;
+use crate::observer::Quiet;
+use crate::ops;
+use crate::registry;
+
+/// Fetch a project's remote and return the remote branch's commit if it's ahead of HEAD,
+/// with how many commits it's ahead by
+fn fetch_ahead(pth: &PathBuf) -> Result<Option<(String, usize)>> {
+    let remote_url = git::run(vec!["remote", "get-url", "origin"], Some(pth)).output()?;
+    if !remote_url.status.success() {
+        return Ok(None);
+    }
+
+    let branch = git::main_branch(pth)?;
+    let mut fetch = git::run(vec!["fetch", "--quiet", "origin", &branch], Some(pth));
+    ops::authenticate(&mut fetch, &String::from_utf8_lossy(&remote_url.stdout));
+    // nobody is around to answer a credential prompt
+    fetch.env("GIT_TERMINAL_PROMPT", "0");
+    if !fetch.status()?.success() {
+        return Ok(None);
+    }
+
+    let remote_branch = format!("origin/{branch}");
+    let ahead = git::run(
+        vec!["rev-list", "--count", &format!("HEAD..{remote_branch}")],
+        Some(pth),
+    )
+    .output()?;
+    let ahead = String::from_utf8_lossy(&ahead.stdout).trim().parse().unwrap_or(0);
+    if ahead == 0 {
+        return Ok(None);
+    }
+
+    let head = git::run(vec!["rev-parse", &remote_branch], Some(pth)).output()?;
+    Ok(Some((
+        String::from_utf8_lossy(&head.stdout).trim().to_string(),
+        ahead,
+    )))
+}
+
+/// Tell clients about incoming commits on a project's remote, returning the remote commit
+/// that was announced
+fn check_project(project_name: &str, announced: Option<&String>) -> Result<Option<String>> {
+    let pth = registry::project_path(project_name)?;
+    let Some((remote_head, ahead)) = fetch_ahead(&pth)? else {
+        return Ok(None);
+    };
+    if announced == Some(&remote_head) {
+        return Ok(Some(remote_head));
+    }
+
+    let local = Diff::from_revision(&pth, "HEAD:project.json")?;
+    let remote = Diff::from_revision(&pth, &format!("{remote_head}:project.json"))?;
+    let report = ops::compare(&pth, &local, &remote, &mut Quiet)?;
+
+    broadcast(json!({
+        "type": "remote-updated",
+        "project_name": project_name,
+        "commits_behind": ahead,
+        "report": report,
+    }));
+    Ok(Some(remote_head))
+}
+
+/// Periodically fetch every project's remote and broadcast when one has new commits
+pub fn start_polling() {
+    let minutes = config::settings(None).remote.poll_minutes;
+    if minutes == 0 {
+        return;
+    }
+
+    spawn(move || {
+        // remote commits already announced, so clients aren't told about the same ones twice
+        let mut announced = HashMap::<String, String>::new();
+        loop {
+            sleep(Duration::from_secs(minutes * 60));
+
+            let project_names = project_config().lock().unwrap().project_names();
+            for name in project_names {
+                match check_project(&name, announced.get(&name)) {
+                    Ok(Some(remote_head)) => {
+                        announced.insert(name, remote_head);
+                    }
+                    Ok(None) => {
+                        announced.remove(&name);
+                    }
+                    Err(e) => debug!(project = %name, "failed to check remote: {e:#}"),
+                }
+            }
+        }
+    });
+}
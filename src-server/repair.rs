@@ -0,0 +1,107 @@
+//! Fixing block references left pointing at blocks that don't exist
+//!
+//! Editor crashes and some mods save projects where a block's `parent`, `next` or inputs name
+//! a block that was deleted. The editor copes, but parsing scripts and diffing them doesn't, so
+//! such references are cut: a block with a missing parent becomes a script of its own, and an
+//! input falls back to its shadow, or is emptied if that's missing too.
+
+use std::collections::HashSet;
+
+use serde_json::{Map, Value};
+
+use crate::validate::Violation;
+
+/// Input types, as the first element of an input's array
+const INPUT_SHADOW: u64 = 1;
+const INPUT_NO_SHADOW: u64 = 2;
+
+/// Cut every dangling block reference in a parsed project.json, returning what was changed
+pub fn dangling_blocks(project: &mut Value) -> Vec<Violation> {
+    let mut repairs = vec![];
+    let Some(targets) = project.get_mut("targets").and_then(Value::as_array_mut) else {
+        return repairs;
+    };
+
+    for (i, target) in targets.iter_mut().enumerate() {
+        let Some(blocks) = target.get_mut("blocks").and_then(Value::as_object_mut) else {
+            continue;
+        };
+        let ids = blocks.keys().cloned().collect::<HashSet<_>>();
+
+        for (id, block) in blocks.iter_mut() {
+            let Some(block) = block.as_object_mut() else {
+                continue;
+            };
+            let path = format!("targets[{i}].blocks.{id}");
+            let missing = |key: &str, block: &Map<String, Value>| {
+                block
+                    .get(key)
+                    .and_then(Value::as_str)
+                    .filter(|reference| !ids.contains(*reference))
+                    .map(str::to_string)
+            };
+
+            if let Some(next) = missing("next", block) {
+                block.insert("next".to_string(), Value::Null);
+                repairs.push(Violation::new(
+                    format!("{path}.next"),
+                    format!("removed missing next block {next}"),
+                ));
+            }
+
+            if let Some(parent) = missing("parent", block) {
+                block.insert("parent".to_string(), Value::Null);
+                block.insert("topLevel".to_string(), Value::Bool(true));
+                for key in ["x", "y"] {
+                    block.entry(key).or_insert(Value::from(0));
+                }
+                repairs.push(Violation::new(
+                    format!("{path}.parent"),
+                    format!("made a script of its own after missing parent {parent}"),
+                ));
+            }
+
+            if let Some(inputs) = block.get_mut("inputs").and_then(Value::as_object_mut) {
+                // an input with nothing left in it is the same as no input
+                inputs.retain(|name, input| match repair_input(input, &ids) {
+                    Some((message, kept)) => {
+                        repairs.push(Violation::new(format!("{path}.inputs.{name}"), message));
+                        kept
+                    }
+                    None => true,
+                });
+            }
+        }
+    }
+
+    repairs
+}
+
+/// Point an input's array (`[type, block or primitive, shadow?]`) only at blocks that exist,
+/// describing what was changed and whether anything is left of the input
+fn repair_input(input: &mut Value, ids: &HashSet<String>) -> Option<(String, bool)> {
+    let input = input.as_array_mut()?;
+    // primitives and empty inputs are stored in place, so only IDs can be missing
+    let missing = |index: usize| match input.get(index) {
+        Some(Value::String(id)) if !ids.contains(id) => Some(id.clone()),
+        _ => None,
+    };
+
+    match (missing(1), missing(2)) {
+        (None, None) => None,
+        (None, Some(shadow)) => {
+            input.truncate(2);
+            input[0] = Value::from(INPUT_NO_SHADOW);
+            Some((format!("removed missing shadow {shadow}"), true))
+        }
+        (Some(block), None) if input.get(2).is_some_and(|shadow| !shadow.is_null()) => {
+            let shadow = input[2].clone();
+            *input = vec![Value::from(INPUT_SHADOW), shadow];
+            Some((
+                format!("replaced missing block {block} with its shadow"),
+                true,
+            ))
+        }
+        (Some(block), _) => Some((format!("emptied after missing block {block}"), false)),
+    }
+}
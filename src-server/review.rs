@@ -0,0 +1,150 @@
+//! Commenting on the pull request (or GitLab merge request) for a pushed branch, so the
+//! project changes in it can be reviewed like code
+//!
+//! GitHub remotes use the token from signing in with GitHub, and GitLab remotes use the
+//! `GITLAB_TOKEN` environment variable. Any remote with `gitlab` in its host is taken to be
+//! GitLab, which covers self-hosted instances like `gitlab.example.com`.
+
+use std::env;
+
+use anyhow::{anyhow, Context, Result};
+use serde_json::{json, Value};
+
+use crate::config::gh_token;
+
+/// Environment variable holding a GitLab personal or project access token
+pub const GITLAB_TOKEN: &str = "GITLAB_TOKEN";
+
+/// Represents the hosted repository a remote URL points at
+#[derive(Debug, Clone, PartialEq)]
+pub enum Forge {
+    GitHub {
+        owner: String,
+        repo: String,
+    },
+    /// `path` is the project's full path, like `group/subgroup/project`
+    GitLab {
+        host: String,
+        path: String,
+    },
+}
+
+impl Forge {
+    /// Work out the forge from a remote URL, like `https://github.com/owner/repo.git` or
+    /// `git@gitlab.com:group/project.git`
+    pub fn from_remote(url: &str) -> Option<Forge> {
+        let url = url.trim();
+        let rest = match url.split_once("://") {
+            Some((_, rest)) => rest.to_string(),
+            // scp-like SSH URLs separate the host from the path with a colon
+            None => url.replacen(':', "/", 1),
+        };
+        // credentials and ports aren't part of the host
+        let rest = rest
+            .rsplit_once('@')
+            .map_or(rest.as_str(), |(_, rest)| rest);
+        let (host, path) = rest.split_once('/')?;
+        let host = host.split(':').next()?;
+        let path = path.trim_end_matches('/').trim_end_matches(".git");
+
+        if host == "github.com" {
+            let (owner, repo) = path.split_once('/')?;
+            Some(Forge::GitHub {
+                owner: owner.into(),
+                repo: repo.into(),
+            })
+        } else if host.contains("gitlab") {
+            Some(Forge::GitLab {
+                host: host.into(),
+                path: path.into(),
+            })
+        } else {
+            None
+        }
+    }
+}
+
+/// Post a comment on the open pull or merge request for `branch`, returning the comment's URL
+///
+/// Returns `None` if the branch has no open request, which is normal for a project's main
+/// branch
+pub fn comment(remote_url: &str, branch: &str, body: &str) -> Result<Option<String>> {
+    let forge = Forge::from_remote(remote_url)
+        .ok_or_else(|| anyhow!("{remote_url} isn't a GitHub or GitLab remote"))?;
+    match forge {
+        Forge::GitHub { owner, repo } => {
+            let token = gh_token().lock().unwrap().get().clone();
+            if token.is_empty() {
+                return Err(anyhow!("not signed in to GitHub"));
+            }
+            let api = format!("https://api.github.com/repos/{owner}/{repo}");
+            let github = |request: minreq::Request| {
+                request
+                    .with_header("User-Agent", "bot")
+                    .with_header("Accept", "application/vnd.github+json")
+                    .with_header("Authorization", format!("Bearer {token}"))
+                    .with_header("X-GitHub-Api-Version", "2022-11-28")
+            };
+
+            let pulls = format!("{api}/pulls?state=open&head={owner}:{}", encode(branch));
+            let pulls = send(github(minreq::get(pulls)))?;
+            let Some(number) = pulls.get(0).and_then(|pull| pull["number"].as_u64()) else {
+                return Ok(None);
+            };
+
+            let url = format!("{api}/issues/{number}/comments");
+            let request = github(minreq::post(url)).with_json(&json!({ "body": body }))?;
+            let comment = send(request)?;
+            Ok(comment["html_url"].as_str().map(String::from))
+        }
+        Forge::GitLab { host, path } => {
+            let token = env::var(GITLAB_TOKEN).context("GITLAB_TOKEN isn't set")?;
+            let api = format!("https://{host}/api/v4/projects/{}", encode(&path));
+            let gitlab = |request: minreq::Request| request.with_header("PRIVATE-TOKEN", &token);
+
+            let requests = format!(
+                "{api}/merge_requests?state=opened&source_branch={}",
+                encode(branch)
+            );
+            let requests = send(gitlab(minreq::get(requests)))?;
+            let Some(iid) = requests.get(0).and_then(|request| request["iid"].as_u64()) else {
+                return Ok(None);
+            };
+
+            let url = format!("{api}/merge_requests/{iid}/notes");
+            let request = gitlab(minreq::post(url)).with_json(&json!({ "body": body }))?;
+            let note = send(request)?;
+            // notes don't have their own URL, so link to the merge request they're on
+            let web_url = requests[0]["web_url"].as_str();
+            let note_id = note["id"].as_u64();
+            Ok(web_url
+                .zip(note_id)
+                .map(|(url, id)| format!("{url}#note_{id}")))
+        }
+    }
+}
+
+/// Send an API request, failing on error statuses with the message the API gave
+fn send(request: minreq::Request) -> Result<Value> {
+    let response = request.send()?;
+    let body = response.json::<Value>().unwrap_or_default();
+    if !(200..300).contains(&response.status_code) {
+        let message = body["message"]
+            .as_str()
+            .unwrap_or(response.reason_phrase.as_str());
+        return Err(anyhow!("{} {message}", response.status_code));
+    }
+    Ok(body)
+}
+
+/// Percent-encode a URL path segment or query value
+fn encode(text: &str) -> String {
+    text.bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (byte as char).to_string()
+            }
+            byte => format!("%{byte:02X}"),
+        })
+        .collect()
+}
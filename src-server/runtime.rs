@@ -0,0 +1,18 @@
+use std::sync::OnceLock;
+
+use tokio::runtime::{Builder, Runtime};
+
+/// Shared async runtime that schedules typed requests
+///
+/// Request work itself (diffing, running Git) blocks, so it runs on the runtime's blocking
+/// pool while the runtime only waits on results
+pub fn runtime() -> &'static Runtime {
+    static RUNTIME: OnceLock<Runtime> = OnceLock::new();
+    RUNTIME.get_or_init(|| {
+        Builder::new_multi_thread()
+            .thread_name("scratch-git-worker")
+            .enable_all()
+            .build()
+            .expect("failed to start async runtime")
+    })
+}
@@ -0,0 +1,86 @@
+//! Reporting lint findings in SARIF, the format code-scanning dashboards like GitHub's take,
+//! so they show up next to a project's other alerts
+//!
+//! Run with `scratch-git --sarif [project dir] > results.sarif`, then upload the file. Every
+//! result points at project.json, with the exact block in the result's logical location.
+
+use std::fs;
+use std::path::PathBuf;
+
+use anyhow::Result;
+use serde_json::{json, Value};
+
+use crate::config;
+use crate::lint;
+use crate::ops;
+use crate::validate::{self, Violation};
+
+/// The file every result points at, since scripts and assets all live in it
+const PROJECT_FILE: &str = "project.json";
+
+/// Rule for a project.json that's too broken to lint
+const INVALID_PROJECT: (&str, &str) = ("invalid-project", "project.json is broken");
+
+/// Lint a project's saved project.json, returning a SARIF log of what was found
+///
+/// A broken project.json is reported as errors without linting, like the editor would fail to
+/// load it
+pub fn report(pth: &PathBuf) -> Result<Value> {
+    let saved = fs::read_to_string(pth.join(PROJECT_FILE))?;
+    let violations = validate::validate_str(&saved);
+    let results = if violations.is_empty() {
+        let project = ops::parse_project(&saved)?;
+        let options = config::settings(Some(pth)).lint.options();
+        lint::lint(&project, options)
+            .iter()
+            .map(|issue| result(issue, lint::rule(issue), "warning"))
+            .collect()
+    } else {
+        violations
+            .iter()
+            .map(|violation| result(violation, INVALID_PROJECT.0, "error"))
+            .collect::<Vec<_>>()
+    };
+
+    let rules = lint::RULES
+        .iter()
+        .chain([&INVALID_PROJECT])
+        .map(|(id, description)| json!({ "id": id, "shortDescription": { "text": description } }))
+        .collect::<Vec<_>>();
+    Ok(json!({
+        "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+        "version": "2.1.0",
+        "runs": [{
+            "tool": {
+                "driver": {
+                    "name": "scratch-git",
+                    "version": env!("CARGO_PKG_VERSION"),
+                    "rules": rules,
+                }
+            },
+            "results": results,
+        }],
+    }))
+}
+
+/// A single SARIF result for an issue at a path in project.json
+fn result(violation: &Violation, rule: &str, level: &str) -> Value {
+    json!({
+        "ruleId": rule,
+        "level": level,
+        "message": { "text": violation.message },
+        "locations": [{
+            "physicalLocation": {
+                "artifactLocation": { "uri": PROJECT_FILE }
+            },
+            "logicalLocations": [{
+                "fullyQualifiedName": violation.path,
+                "kind": "object"
+            }]
+        }],
+        // SARIF viewers tell results apart by these, so the same issue isn't reported twice
+        "partialFingerprints": {
+            "path/v1": format!("{rule}:{}", violation.path)
+        }
+    })
+}
@@ -0,0 +1,1102 @@
+//! Converting Scratch 2 projects (SB2 files) into SB3 files, so old projects can be versioned
+//!
+//! An SB2 is also a ZIP file, but its project.json nests sprites under the stage, names assets
+//! by their index in the file, and stores scripts as nested arrays instead of a flat block map.
+//! Blocks without a Scratch 3 equivalent are left out and reported, and the blocks around them
+//! are joined up instead.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs::File;
+use std::path::Path;
+
+use anyhow::{anyhow, Context, Result};
+use serde_json::{json, Map, Value};
+use zip::ZipArchive;
+
+use crate::limits::{self, MAX_ASSET_SIZE};
+use crate::sb3;
+
+/// Scratch 2 scripts are laid out more tightly than Scratch 3 ones
+const WORKSPACE_SCALE: f64 = 1.5;
+
+/// How a Scratch 2 block argument becomes part of a Scratch 3 block
+enum Arg {
+    /// A number input
+    Number(&'static str),
+    /// A positive number input
+    Positive(&'static str),
+    /// A whole number input
+    Whole(&'static str),
+    /// An integer input
+    Integer(&'static str),
+    /// An angle input
+    Angle(&'static str),
+    /// A color picker input
+    Color(&'static str),
+    /// A text input
+    Text(&'static str),
+    /// A boolean input, which has no shadow
+    Boolean(&'static str),
+    /// A C-block's mouth
+    Substack(&'static str),
+    /// A dropdown that can't hold reporters
+    Field(&'static str),
+    /// A dropdown that can hold reporters, kept in a shadow block of the given opcode
+    Menu(&'static str, &'static str),
+    /// A broadcast message input
+    Broadcast(&'static str),
+    /// A variable dropdown
+    Variable,
+    /// A list dropdown
+    List,
+}
+
+use Arg::*;
+
+/// Scratch 2 blocks and the Scratch 3 blocks they become
+const BLOCKS: &[(&str, &str, &[Arg])] = &[
+    // motion
+    ("forward:", "motion_movesteps", &[Number("STEPS")]),
+    ("turnRight:", "motion_turnright", &[Number("DEGREES")]),
+    ("turnLeft:", "motion_turnleft", &[Number("DEGREES")]),
+    ("heading:", "motion_pointindirection", &[Angle("DIRECTION")]),
+    (
+        "pointTowards:",
+        "motion_pointtowards",
+        &[Menu("TOWARDS", "motion_pointtowards_menu")],
+    ),
+    ("gotoX:y:", "motion_gotoxy", &[Number("X"), Number("Y")]),
+    (
+        "gotoSpriteOrMouse:",
+        "motion_goto",
+        &[Menu("TO", "motion_goto_menu")],
+    ),
+    (
+        "glideSecs:toX:y:elapsed:from:",
+        "motion_glidesecstoxy",
+        &[Number("SECS"), Number("X"), Number("Y")],
+    ),
+    ("changeXposBy:", "motion_changexby", &[Number("DX")]),
+    ("xpos:", "motion_setx", &[Number("X")]),
+    ("changeYposBy:", "motion_changeyby", &[Number("DY")]),
+    ("ypos:", "motion_sety", &[Number("Y")]),
+    ("bounceOffEdge", "motion_ifonedgebounce", &[]),
+    (
+        "setRotationStyle",
+        "motion_setrotationstyle",
+        &[Field("STYLE")],
+    ),
+    ("xpos", "motion_xposition", &[]),
+    ("ypos", "motion_yposition", &[]),
+    ("heading", "motion_direction", &[]),
+    // looks
+    (
+        "say:duration:elapsed:from:",
+        "looks_sayforsecs",
+        &[Text("MESSAGE"), Number("SECS")],
+    ),
+    ("say:", "looks_say", &[Text("MESSAGE")]),
+    (
+        "think:duration:elapsed:from:",
+        "looks_thinkforsecs",
+        &[Text("MESSAGE"), Number("SECS")],
+    ),
+    ("think:", "looks_think", &[Text("MESSAGE")]),
+    ("show", "looks_show", &[]),
+    ("hide", "looks_hide", &[]),
+    (
+        "lookLike:",
+        "looks_switchcostumeto",
+        &[Menu("COSTUME", "looks_costume")],
+    ),
+    ("nextCostume", "looks_nextcostume", &[]),
+    (
+        "startScene",
+        "looks_switchbackdropto",
+        &[Menu("BACKDROP", "looks_backdrops")],
+    ),
+    ("nextScene", "looks_nextbackdrop", &[]),
+    (
+        "changeGraphicEffect:by:",
+        "looks_changeeffectby",
+        &[Field("EFFECT"), Number("CHANGE")],
+    ),
+    (
+        "setGraphicEffect:to:",
+        "looks_seteffectto",
+        &[Field("EFFECT"), Number("VALUE")],
+    ),
+    ("filterReset", "looks_cleargraphiceffects", &[]),
+    ("changeSizeBy:", "looks_changesizeby", &[Number("CHANGE")]),
+    ("setSizeTo:", "looks_setsizeto", &[Number("SIZE")]),
+    ("comeToFront", "looks_gotofrontback", &[]),
+    (
+        "goBackByLayers:",
+        "looks_goforwardbackwardlayers",
+        &[Integer("NUM")],
+    ),
+    ("costumeIndex", "looks_costumenumbername", &[]),
+    ("sceneName", "looks_backdropnumbername", &[]),
+    ("scale", "looks_size", &[]),
+    // sound
+    (
+        "playSound:",
+        "sound_play",
+        &[Menu("SOUND_MENU", "sound_sounds_menu")],
+    ),
+    (
+        "doPlaySoundAndWait",
+        "sound_playuntildone",
+        &[Menu("SOUND_MENU", "sound_sounds_menu")],
+    ),
+    ("stopAllSounds", "sound_stopallsounds", &[]),
+    (
+        "changeVolumeBy:",
+        "sound_changevolumeby",
+        &[Number("VOLUME")],
+    ),
+    ("setVolumeTo:", "sound_setvolumeto", &[Number("VOLUME")]),
+    ("volume", "sound_volume", &[]),
+    // events
+    ("whenGreenFlag", "event_whenflagclicked", &[]),
+    (
+        "whenKeyPressed",
+        "event_whenkeypressed",
+        &[Field("KEY_OPTION")],
+    ),
+    ("whenClicked", "event_whenthisspriteclicked", &[]),
+    (
+        "whenSceneStarts",
+        "event_whenbackdropswitchesto",
+        &[Field("BACKDROP")],
+    ),
+    ("whenIReceive", "event_whenbroadcastreceived", &[]),
+    (
+        "broadcast:",
+        "event_broadcast",
+        &[Broadcast("BROADCAST_INPUT")],
+    ),
+    (
+        "doBroadcastAndWait",
+        "event_broadcastandwait",
+        &[Broadcast("BROADCAST_INPUT")],
+    ),
+    // control
+    (
+        "wait:elapsed:from:",
+        "control_wait",
+        &[Positive("DURATION")],
+    ),
+    (
+        "doRepeat",
+        "control_repeat",
+        &[Whole("TIMES"), Substack("SUBSTACK")],
+    ),
+    ("doForever", "control_forever", &[Substack("SUBSTACK")]),
+    (
+        "doIf",
+        "control_if",
+        &[Boolean("CONDITION"), Substack("SUBSTACK")],
+    ),
+    (
+        "doIfElse",
+        "control_if_else",
+        &[
+            Boolean("CONDITION"),
+            Substack("SUBSTACK"),
+            Substack("SUBSTACK2"),
+        ],
+    ),
+    ("doWaitUntil", "control_wait_until", &[Boolean("CONDITION")]),
+    (
+        "doUntil",
+        "control_repeat_until",
+        &[Boolean("CONDITION"), Substack("SUBSTACK")],
+    ),
+    ("stopScripts", "control_stop", &[Field("STOP_OPTION")]),
+    ("whenCloned", "control_start_as_clone", &[]),
+    (
+        "createCloneOf",
+        "control_create_clone_of",
+        &[Menu("CLONE_OPTION", "control_create_clone_of_menu")],
+    ),
+    ("deleteClone", "control_delete_this_clone", &[]),
+    // sensing
+    (
+        "touching:",
+        "sensing_touchingobject",
+        &[Menu("TOUCHINGOBJECTMENU", "sensing_touchingobjectmenu")],
+    ),
+    ("touchingColor:", "sensing_touchingcolor", &[Color("COLOR")]),
+    (
+        "color:sees:",
+        "sensing_coloristouchingcolor",
+        &[Color("COLOR"), Color("COLOR2")],
+    ),
+    (
+        "distanceTo:",
+        "sensing_distanceto",
+        &[Menu("DISTANCETOMENU", "sensing_distancetomenu")],
+    ),
+    ("doAsk", "sensing_askandwait", &[Text("QUESTION")]),
+    ("answer", "sensing_answer", &[]),
+    (
+        "keyPressed:",
+        "sensing_keypressed",
+        &[Menu("KEY_OPTION", "sensing_keyoptions")],
+    ),
+    ("mousePressed", "sensing_mousedown", &[]),
+    ("mouseX", "sensing_mousex", &[]),
+    ("mouseY", "sensing_mousey", &[]),
+    ("timer", "sensing_timer", &[]),
+    ("timerReset", "sensing_resettimer", &[]),
+    (
+        "getAttribute:of:",
+        "sensing_of",
+        &[Field("PROPERTY"), Menu("OBJECT", "sensing_of_object_menu")],
+    ),
+    ("timeAndDate", "sensing_current", &[Field("CURRENTMENU")]),
+    ("timestamp", "sensing_dayssince2000", &[]),
+    ("getUserName", "sensing_username", &[]),
+    ("soundLevel", "sensing_loudness", &[]),
+    // operators
+    ("+", "operator_add", &[Number("NUM1"), Number("NUM2")]),
+    ("-", "operator_subtract", &[Number("NUM1"), Number("NUM2")]),
+    ("*", "operator_multiply", &[Number("NUM1"), Number("NUM2")]),
+    ("/", "operator_divide", &[Number("NUM1"), Number("NUM2")]),
+    (
+        "randomFrom:to:",
+        "operator_random",
+        &[Number("FROM"), Number("TO")],
+    ),
+    ("<", "operator_lt", &[Text("OPERAND1"), Text("OPERAND2")]),
+    (
+        "=",
+        "operator_equals",
+        &[Text("OPERAND1"), Text("OPERAND2")],
+    ),
+    (">", "operator_gt", &[Text("OPERAND1"), Text("OPERAND2")]),
+    (
+        "&",
+        "operator_and",
+        &[Boolean("OPERAND1"), Boolean("OPERAND2")],
+    ),
+    (
+        "|",
+        "operator_or",
+        &[Boolean("OPERAND1"), Boolean("OPERAND2")],
+    ),
+    ("not", "operator_not", &[Boolean("OPERAND")]),
+    (
+        "concatenate:with:",
+        "operator_join",
+        &[Text("STRING1"), Text("STRING2")],
+    ),
+    (
+        "letter:of:",
+        "operator_letter_of",
+        &[Whole("LETTER"), Text("STRING")],
+    ),
+    ("stringLength:", "operator_length", &[Text("STRING")]),
+    ("%", "operator_mod", &[Number("NUM1"), Number("NUM2")]),
+    ("rounded", "operator_round", &[Number("NUM")]),
+    (
+        "computeFunction:of:",
+        "operator_mathop",
+        &[Field("OPERATOR"), Number("NUM")],
+    ),
+    // data
+    ("readVariable", "data_variable", &[Variable]),
+    (
+        "setVar:to:",
+        "data_setvariableto",
+        &[Variable, Text("VALUE")],
+    ),
+    (
+        "changeVar:by:",
+        "data_changevariableby",
+        &[Variable, Number("VALUE")],
+    ),
+    ("showVariable:", "data_showvariable", &[Variable]),
+    ("hideVariable:", "data_hidevariable", &[Variable]),
+    ("contentsOfList:", "data_listcontents", &[List]),
+    ("append:toList:", "data_addtolist", &[Text("ITEM"), List]),
+    (
+        "deleteLine:ofList:",
+        "data_deleteoflist",
+        &[Integer("INDEX"), List],
+    ),
+    (
+        "insert:at:ofList:",
+        "data_insertatlist",
+        &[Text("ITEM"), Integer("INDEX"), List],
+    ),
+    (
+        "setLine:ofList:to:",
+        "data_replaceitemoflist",
+        &[Integer("INDEX"), List, Text("ITEM")],
+    ),
+    (
+        "getLine:ofList:",
+        "data_itemoflist",
+        &[Integer("INDEX"), List],
+    ),
+    ("lineCountOfList:", "data_lengthoflist", &[List]),
+    (
+        "list:contains:",
+        "data_listcontainsitem",
+        &[List, Text("ITEM")],
+    ),
+    ("showList:", "data_showlist", &[List]),
+    ("hideList:", "data_hidelist", &[List]),
+    // pen
+    ("clearPenTrails", "pen_clear", &[]),
+    ("stampCostume", "pen_stamp", &[]),
+    ("putPenDown", "pen_penDown", &[]),
+    ("putPenUp", "pen_penUp", &[]),
+    ("penColor:", "pen_setPenColorToColor", &[Color("COLOR")]),
+    ("changePenSizeBy:", "pen_changePenSizeBy", &[Number("SIZE")]),
+    ("penSize:", "pen_setPenSizeTo", &[Number("SIZE")]),
+];
+
+/// Fields Scratch 3 blocks have that their Scratch 2 counterparts implied
+const FIXED_FIELDS: &[(&str, &str, &str)] = &[
+    ("looks_gotofrontback", "FRONT_BACK", "front"),
+    (
+        "looks_goforwardbackwardlayers",
+        "FORWARD_BACKWARD",
+        "backward",
+    ),
+    ("looks_costumenumbername", "NUMBER_NAME", "number"),
+    ("looks_backdropnumbername", "NUMBER_NAME", "name"),
+];
+
+/// Convert an SB2 into an SB3, returning the Scratch 2 blocks that were left out
+///
+/// Costumes with text drawn in the Scratch 2 paint editor lose the text, since it's stored
+/// as a separate layer that only the old editor could flatten
+pub fn convert(sb2: &Path, sb3: &Path) -> Result<Vec<String>> {
+    let mut archive = ZipArchive::new(File::open(sb2).context("failed to open project file")?)
+        .context("project file isn't a ZIP file")?;
+    let mut budget = limits::check_archive(&mut archive, "project file")?;
+
+    let mut sources = BTreeMap::new();
+    for i in 0..archive.len() {
+        let file = archive.by_index(i)?;
+        let name = file.name().to_string();
+        sources.insert(name, limits::read_entry(file, MAX_ASSET_SIZE, &mut budget)?);
+    }
+    convert_files(sources, sb3)
+}
+
+/// Convert an SB2's files into an SB3, like [`convert`], for projects that aren't in a ZIP,
+/// like ones downloaded from the Scratch website
+pub fn convert_files(mut sources: BTreeMap<String, Vec<u8>>, sb3: &Path) -> Result<Vec<String>> {
+    let json = sources
+        .remove("project.json")
+        .context("project file has no project.json")?;
+    limits::check_json(&json, "project.json")?;
+    let stage = serde_json::from_slice::<Value>(&json).context("failed to parse project.json")?;
+
+    let mut converter = Converter {
+        sources,
+        ..Default::default()
+    };
+    let project = converter.project(&stage)?;
+
+    sb3::write(sb3, &serde_json::to_vec(&project)?, converter.files)?;
+    Ok(converter.skipped.into_iter().collect())
+}
+
+#[derive(Default)]
+struct Converter {
+    /// Every file in the SB2, where assets are named by their index
+    sources: BTreeMap<String, Vec<u8>>,
+    next_id: usize,
+    /// Assets to write, keyed by their MD5-based name
+    files: BTreeMap<String, Vec<u8>>,
+    extensions: BTreeSet<String>,
+    skipped: BTreeSet<String>,
+    /// Broadcast names and IDs, which all live on the stage
+    broadcasts: Map<String, Value>,
+    stage_variables: Map<String, Value>,
+    stage_lists: Map<String, Value>,
+    // the target being converted
+    is_stage: bool,
+    blocks: Map<String, Value>,
+    variables: Map<String, Value>,
+    lists: Map<String, Value>,
+    /// Custom block argument IDs and whether they run without screen refresh, by proccode
+    procedures: HashMap<String, (Vec<String>, bool)>,
+}
+
+impl Converter {
+    fn id(&mut self) -> String {
+        self.next_id += 1;
+        format!("sb2-{}", self.next_id)
+    }
+
+    /// Convert the stage and every sprite under it
+    fn project(&mut self, stage: &Value) -> Result<Value> {
+        self.is_stage = true;
+        self.stage_variables = self.declare_variables(stage);
+        self.stage_lists = self.declare_lists(stage);
+        let mut stage_target = self.target(stage)?;
+
+        let mut targets = vec![];
+        let sprites = stage["children"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            // watchers are children too, but only sprites have names
+            .filter(|child| child["objName"].is_string());
+        for (layer, sprite) in sprites.enumerate() {
+            self.is_stage = false;
+            self.variables = self.declare_variables(sprite);
+            self.lists = self.declare_lists(sprite);
+
+            let mut target = self.target(sprite)?;
+            let rotation_style = match sprite["rotationStyle"].as_str() {
+                Some("leftRight") => "left-right",
+                Some("none") => "don't rotate",
+                _ => "all around",
+            };
+            extend(
+                &mut target,
+                json!({
+                    "isStage": false,
+                    "name": sprite["objName"],
+                    "variables": std::mem::take(&mut self.variables),
+                    "lists": std::mem::take(&mut self.lists),
+                    "broadcasts": {},
+                    "layerOrder": layer + 1,
+                    "visible": sprite["visible"].as_bool().unwrap_or(true),
+                    "x": sprite["scratchX"].as_f64().unwrap_or(0.0),
+                    "y": sprite["scratchY"].as_f64().unwrap_or(0.0),
+                    "size": sprite["scale"].as_f64().unwrap_or(1.0) * 100.0,
+                    "direction": sprite["direction"].as_f64().unwrap_or(90.0),
+                    "draggable": sprite["isDraggable"].as_bool().unwrap_or(false),
+                    "rotationStyle": rotation_style,
+                }),
+            );
+            targets.push(target);
+        }
+
+        // the stage goes last, since sprites may declare broadcasts or use undeclared variables
+        extend(
+            &mut stage_target,
+            json!({
+                "isStage": true,
+                "name": "Stage",
+                "variables": std::mem::take(&mut self.stage_variables),
+                "lists": std::mem::take(&mut self.stage_lists),
+                "broadcasts": std::mem::take(&mut self.broadcasts),
+                "layerOrder": 0,
+                "tempo": stage["tempoBPM"].as_f64().unwrap_or(60.0),
+                "videoTransparency": stage["videoAlpha"].as_f64().map_or(50.0, |a| (1.0 - a) * 100.0),
+                "videoState": "off",
+                "textToSpeechLanguage": null,
+            }),
+        );
+        targets.insert(0, stage_target);
+
+        Ok(json!({
+            "targets": targets,
+            "monitors": [],
+            "extensions": self.extensions,
+            "meta": {
+                "semver": "3.0.0",
+                "vm": "0.2.0",
+                "agent": concat!("scratch-git/", env!("CARGO_PKG_VERSION")),
+            },
+        }))
+    }
+
+    /// Convert what sprites and the stage have in common: scripts, costumes and sounds
+    fn target(&mut self, target: &Value) -> Result<Value> {
+        let costumes = target["costumes"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .map(|costume| {
+                let (asset_id, data_format, md5ext) =
+                    self.asset(&costume["baseLayerID"], &costume["baseLayerMD5"])?;
+                Ok(json!({
+                    "name": costume["costumeName"],
+                    "bitmapResolution": costume["bitmapResolution"].as_u64().unwrap_or(1),
+                    "dataFormat": data_format,
+                    "assetId": asset_id,
+                    "md5ext": md5ext,
+                    "rotationCenterX": costume["rotationCenterX"],
+                    "rotationCenterY": costume["rotationCenterY"],
+                }))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let sounds = target["sounds"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .map(|sound| {
+                let (asset_id, data_format, md5ext) =
+                    self.asset(&sound["soundID"], &sound["md5"])?;
+                Ok(json!({
+                    "name": sound["soundName"],
+                    "assetId": asset_id,
+                    "dataFormat": data_format,
+                    "format": sound["format"].as_str().unwrap_or(""),
+                    "rate": sound["rate"],
+                    "sampleCount": sound["sampleCount"],
+                    "md5ext": md5ext,
+                }))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let scripts = target["scripts"].as_array().cloned().unwrap_or_default();
+        self.procedures.clear();
+        for script in &scripts {
+            let Some(definition) = script[2][0].as_array() else {
+                continue;
+            };
+            if definition.first().and_then(Value::as_str) == Some("procDef") {
+                let proccode = definition.get(1).and_then(Value::as_str).unwrap_or("");
+                let warp = definition.get(4).and_then(Value::as_bool).unwrap_or(false);
+                self.procedure(proccode).1 = warp;
+            }
+        }
+        for script in &scripts {
+            let Some(stack) = script[2].as_array() else {
+                continue;
+            };
+            let x = script[0].as_f64().unwrap_or(0.0) * WORKSPACE_SCALE;
+            let y = script[1].as_f64().unwrap_or(0.0) * WORKSPACE_SCALE;
+            if let Some(top) = self.stack(stack, None) {
+                extend(
+                    &mut self.blocks[&top],
+                    json!({ "topLevel": true, "x": x.round(), "y": y.round() }),
+                );
+            }
+        }
+
+        Ok(json!({
+            "blocks": std::mem::take(&mut self.blocks),
+            "comments": {},
+            "currentCostume": target["currentCostumeIndex"].as_u64().unwrap_or(0),
+            "costumes": costumes,
+            "sounds": sounds,
+            "volume": 100,
+        }))
+    }
+
+    /// Rename an asset by its MD5 hash, returning its asset ID, format and file name
+    fn asset(&mut self, index: &Value, md5ext: &Value) -> Result<(String, String, String)> {
+        let md5ext = md5ext.as_str().unwrap_or("");
+        let format = md5ext
+            .rsplit_once('.')
+            .map_or("", |(_, ext)| ext)
+            .to_string();
+        let file = match index.as_i64() {
+            Some(index) if index >= 0 => format!("{index}.{format}"),
+            // assets from the online editor are stored on its servers instead
+            _ => return Err(anyhow!("{md5ext} isn't included in the project file")),
+        };
+
+        let contents = self
+            .sources
+            .get(&file)
+            .with_context(|| format!("project file is missing {file}"))?
+            .clone();
+
+        let asset_id = format!("{:x}", md5::compute(&contents));
+        let md5ext = format!("{asset_id}.{format}");
+        self.files.insert(md5ext.clone(), contents);
+        Ok((asset_id, format, md5ext))
+    }
+
+    fn declare_variables(&mut self, target: &Value) -> Map<String, Value> {
+        let mut variables = Map::new();
+        for variable in target["variables"].as_array().into_iter().flatten() {
+            variables.insert(self.id(), json!([variable["name"], variable["value"]]));
+        }
+        variables
+    }
+
+    fn declare_lists(&mut self, target: &Value) -> Map<String, Value> {
+        let mut lists = Map::new();
+        for list in target["lists"].as_array().into_iter().flatten() {
+            let contents = list["contents"].as_array().cloned().unwrap_or_default();
+            lists.insert(self.id(), json!([list["listName"], contents]));
+        }
+        lists
+    }
+
+    /// Find the ID of a variable or list, preferring the sprite's own over the stage's
+    ///
+    /// Scratch 2 allowed using variables that were never declared, so those are declared on
+    /// the stage
+    fn variable(&mut self, name: &str, list: bool) -> String {
+        let (local, global) = if list {
+            (&self.lists, &self.stage_lists)
+        } else {
+            (&self.variables, &self.stage_variables)
+        };
+        let found = local
+            .iter()
+            .chain(global)
+            .find(|(_, variable)| variable[0] == name)
+            .map(|(id, _)| id.clone());
+        if let Some(id) = found {
+            return id;
+        }
+
+        let id = self.id();
+        if list {
+            self.stage_lists.insert(id.clone(), json!([name, []]));
+        } else {
+            self.stage_variables.insert(id.clone(), json!([name, 0]));
+        }
+        id
+    }
+
+    /// Find the ID of a broadcast, declaring it if it's new
+    fn broadcast(&mut self, name: &str) -> String {
+        let id = format!("broadcastMsgId-{name}");
+        self.broadcasts.insert(id.clone(), json!(name));
+        id
+    }
+
+    /// Find a custom block's argument IDs and whether it runs without screen refresh
+    fn procedure(&mut self, proccode: &str) -> &mut (Vec<String>, bool) {
+        if !self.procedures.contains_key(proccode) {
+            let ids = argument_kinds(proccode).map(|_| self.id()).collect();
+            self.procedures.insert(proccode.to_string(), (ids, false));
+        }
+        self.procedures.get_mut(proccode).unwrap()
+    }
+
+    /// Convert a stack of blocks, returning the first one's ID
+    fn stack(&mut self, stack: &[Value], parent: Option<&str>) -> Option<String> {
+        let mut first = None;
+        let mut previous: Option<String> = None;
+        for block in stack {
+            let Some(id) = self.block(block, previous.as_deref().or(parent)) else {
+                continue;
+            };
+            match &previous {
+                Some(previous) => self.blocks[previous]["next"] = json!(id),
+                None => first = Some(id.clone()),
+            }
+            previous = Some(id);
+        }
+        first
+    }
+
+    /// Convert a block along with its inputs, returning its ID unless it was left out
+    fn block(&mut self, block: &Value, parent: Option<&str>) -> Option<String> {
+        let block = block.as_array()?;
+        let sb2_opcode = block.first()?.as_str()?;
+        let args = &block[1..];
+
+        match sb2_opcode {
+            "procDef" => return Some(self.definition(args)),
+            "call" => return Some(self.call(args, parent)),
+            "getParam" => {
+                let opcode = if args.get(1).and_then(Value::as_str) == Some("b") {
+                    "argument_reporter_boolean"
+                } else {
+                    "argument_reporter_string_number"
+                };
+                let id = self.id();
+                let fields = json!({ "VALUE": [literal(args.first()), null] });
+                self.insert(&id, opcode, parent, json!({}), fields, false);
+                return Some(id);
+            }
+            _ => {}
+        }
+
+        let Some((_, opcode, spec)) = BLOCKS.iter().find(|(name, ..)| *name == sb2_opcode) else {
+            self.skipped.insert(sb2_opcode.to_string());
+            return None;
+        };
+        let opcode = match *opcode {
+            "event_whenthisspriteclicked" if self.is_stage => "event_whenstageclicked",
+            opcode => opcode,
+        };
+        if opcode.starts_with("pen_") {
+            self.extensions.insert("pen".to_string());
+        }
+
+        let id = self.id();
+        let mut inputs = Map::new();
+        let mut fields = Map::new();
+        for (arg, value) in spec
+            .iter()
+            .zip(args.iter().chain(std::iter::repeat(&Value::Null)))
+        {
+            // primitives are stored as [type, value], e.g. [4, "10"] for a number
+            let primitive = |kind: u8| Some(json!([kind, literal(Some(value))]));
+            match arg {
+                Number(name) => self.input(&mut inputs, name, value, &id, primitive(4)),
+                Positive(name) => self.input(&mut inputs, name, value, &id, primitive(5)),
+                Whole(name) => self.input(&mut inputs, name, value, &id, primitive(6)),
+                Integer(name) => self.input(&mut inputs, name, value, &id, primitive(7)),
+                Angle(name) => self.input(&mut inputs, name, value, &id, primitive(8)),
+                Text(name) => self.input(&mut inputs, name, value, &id, primitive(10)),
+                Color(name) => self.input(
+                    &mut inputs,
+                    name,
+                    value,
+                    &id,
+                    Some(json!([9, color(value)])),
+                ),
+                Boolean(name) => self.input(&mut inputs, name, value, &id, None),
+                Substack(name) => {
+                    let stack = value.as_array().map(Vec::as_slice).unwrap_or_default();
+                    if let Some(first) = self.stack(stack, Some(&id)) {
+                        inputs.insert(name.to_string(), json!([2, first]));
+                    }
+                }
+                Field(name) => {
+                    fields.insert(name.to_string(), json!([field(name, value), null]));
+                }
+                Menu(name, menu) => {
+                    let shadow = self.id();
+                    let menu_fields = json!({ *name: [literal(Some(value)), null] });
+                    self.insert(&shadow, menu, Some(&id), json!({}), menu_fields, true);
+                    self.input(&mut inputs, name, value, &id, Some(json!(shadow)));
+                }
+                Broadcast(name) => {
+                    let message = value.as_str().unwrap_or("message1");
+                    let shadow = json!([11, message, self.broadcast(message)]);
+                    self.input(&mut inputs, name, value, &id, Some(shadow));
+                }
+                Variable => {
+                    let name = literal(Some(value));
+                    let variable = self.variable(&name, false);
+                    fields.insert("VARIABLE".to_string(), json!([name, variable]));
+                }
+                List => {
+                    let name = literal(Some(value));
+                    let list = self.variable(&name, true);
+                    fields.insert("LIST".to_string(), json!([name, list]));
+                }
+            }
+        }
+        for (_, field, value) in FIXED_FIELDS.iter().filter(|(o, ..)| *o == opcode) {
+            fields.insert(field.to_string(), json!([value, null]));
+        }
+        // the message a hat listens for is a field, but it still refers to a broadcast by ID
+        if sb2_opcode == "whenIReceive" {
+            let name = literal(args.first());
+            let broadcast = self.broadcast(&name);
+            fields.insert("BROADCAST_OPTION".to_string(), json!([name, broadcast]));
+        }
+
+        self.insert(
+            &id,
+            opcode,
+            parent,
+            Value::Object(inputs),
+            Value::Object(fields),
+            false,
+        );
+        if opcode == "control_stop" {
+            let option = literal(args.first());
+            let has_next = option.starts_with("other scripts");
+            self.blocks[&id]["mutation"] =
+                json!({ "tagName": "mutation", "children": [], "hasnext": has_next.to_string() });
+            if has_next {
+                self.blocks[&id]["fields"]["STOP_OPTION"] =
+                    json!(["other scripts in sprite", null]);
+            }
+        }
+        Some(id)
+    }
+
+    /// Convert an input, which either holds a reporter over its shadow or just the shadow
+    fn input(
+        &mut self,
+        inputs: &mut Map<String, Value>,
+        name: &str,
+        value: &Value,
+        parent: &str,
+        shadow: Option<Value>,
+    ) {
+        let reporter = is_block(value)
+            .then(|| self.block(value, Some(parent)))
+            .flatten();
+        let input = match (reporter, shadow) {
+            (Some(reporter), Some(shadow)) => json!([3, reporter, shadow]),
+            (Some(reporter), None) => json!([2, reporter]),
+            (None, Some(shadow)) => json!([1, shadow]),
+            (None, None) => return,
+        };
+        inputs.insert(name.to_string(), input);
+    }
+
+    /// Convert a custom block definition hat along with its prototype
+    fn definition(&mut self, args: &[Value]) -> String {
+        let proccode = literal(args.first());
+        let names = args.get(1).cloned().unwrap_or(json!([]));
+        let defaults = args.get(2).cloned().unwrap_or(json!([]));
+        let (ids, warp) = self.procedure(&proccode).clone();
+
+        let id = self.id();
+        let prototype = self.id();
+        let mut arguments = Map::new();
+        for ((arg, kind), name) in ids.iter().zip(argument_kinds(&proccode)).zip(
+            names
+                .as_array()
+                .into_iter()
+                .flatten()
+                .chain(std::iter::repeat(&Value::Null)),
+        ) {
+            let reporter = self.id();
+            let opcode = if kind == 'b' {
+                "argument_reporter_boolean"
+            } else {
+                "argument_reporter_string_number"
+            };
+            let fields = json!({ "VALUE": [literal(Some(name)), null] });
+            self.insert(&reporter, opcode, Some(&prototype), json!({}), fields, true);
+            arguments.insert(arg.clone(), json!([1, reporter]));
+        }
+
+        let inputs = json!({ "custom_block": [1, prototype] });
+        self.insert(&id, "procedures_definition", None, inputs, json!({}), false);
+        self.insert(
+            &prototype,
+            "procedures_prototype",
+            Some(&id),
+            Value::Object(arguments),
+            json!({}),
+            true,
+        );
+        self.blocks[&prototype]["mutation"] = json!({
+            "tagName": "mutation",
+            "children": [],
+            "proccode": proccode,
+            "argumentids": json!(ids).to_string(),
+            "argumentnames": names.to_string(),
+            "argumentdefaults": defaults.to_string(),
+            "warp": warp.to_string(),
+        });
+        id
+    }
+
+    /// Convert a custom block call, matching its arguments to the definition's
+    fn call(&mut self, args: &[Value], parent: Option<&str>) -> String {
+        let proccode = literal(args.first());
+        let (ids, warp) = self.procedure(&proccode).clone();
+
+        let id = self.id();
+        let mut inputs = Map::new();
+        let values = args.iter().skip(1).chain(std::iter::repeat(&Value::Null));
+        for ((arg, kind), value) in ids.iter().zip(argument_kinds(&proccode)).zip(values) {
+            let shadow = (kind != 'b').then(|| json!([10, literal(Some(value))]));
+            self.input(&mut inputs, arg, value, &id, shadow);
+        }
+
+        self.insert(
+            &id,
+            "procedures_call",
+            parent,
+            Value::Object(inputs),
+            json!({}),
+            false,
+        );
+        self.blocks[&id]["mutation"] = json!({
+            "tagName": "mutation",
+            "children": [],
+            "proccode": proccode,
+            "argumentids": json!(ids).to_string(),
+            "warp": warp.to_string(),
+        });
+        id
+    }
+
+    fn insert(
+        &mut self,
+        id: &str,
+        opcode: &str,
+        parent: Option<&str>,
+        inputs: Value,
+        fields: Value,
+        shadow: bool,
+    ) {
+        self.blocks.insert(
+            id.to_string(),
+            json!({
+                "opcode": opcode,
+                "next": null,
+                "parent": parent,
+                "inputs": inputs,
+                "fields": fields,
+                "shadow": shadow,
+                "topLevel": false,
+            }),
+        );
+    }
+}
+
+/// Copy every field of an object into another
+fn extend(target: &mut Value, fields: Value) {
+    if let (Some(target), Value::Object(fields)) = (target.as_object_mut(), fields) {
+        target.extend(fields);
+    }
+}
+
+/// Whether an argument is a nested reporter rather than a literal or a stack
+fn is_block(value: &Value) -> bool {
+    value
+        .as_array()
+        .and_then(|block| block.first())
+        .is_some_and(Value::is_string)
+}
+
+/// Turn a literal argument into the string Scratch 3 stores, with reporters becoming empty
+fn literal(value: Option<&Value>) -> String {
+    match value {
+        Some(Value::String(s)) => s.clone(),
+        Some(Value::Number(n)) => n.to_string(),
+        Some(Value::Bool(b)) => b.to_string(),
+        _ => String::new(),
+    }
+}
+
+/// Turn a Scratch 2 dropdown value into its Scratch 3 spelling
+fn field(name: &str, value: &Value) -> String {
+    let value = literal(Some(value));
+    match name {
+        // "ghost" and "day of week" become "GHOST" and "DAYOFWEEK"
+        "EFFECT" | "CURRENTMENU" => value.to_uppercase().replace(' ', ""),
+        _ => value,
+    }
+}
+
+/// Turn a Scratch 2 color, stored as a number, into a hex color
+fn color(value: &Value) -> String {
+    let color = value.as_i64().unwrap_or(0) & 0xffffff;
+    format!("#{color:06x}")
+}
+
+/// The kinds of a custom block's arguments, 's' and 'n' for text and numbers or 'b' for booleans
+fn argument_kinds(proccode: &str) -> impl Iterator<Item = char> + '_ {
+    proccode
+        .split('%')
+        .skip(1)
+        .filter_map(|arg| arg.chars().next())
+        .filter(|kind| matches!(kind, 's' | 'n' | 'b'))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use super::*;
+    use crate::temp::TempWorkspace;
+
+    /// Convert a stage with these scripts, returning the SB3's project.json and the blocks that
+    /// were left out
+    fn convert_scripts(scripts: Value) -> Result<(Value, Vec<String>)> {
+        let stage = json!({
+            "objName": "Stage",
+            "scripts": [],
+            "children": [{"objName": "Sprite1", "scripts": scripts}],
+        });
+        let sources = BTreeMap::from([("project.json".to_string(), serde_json::to_vec(&stage)?)]);
+        let workspace = TempWorkspace::new("sb2-test")?;
+        let sb3 = workspace.path().join("project.sb3");
+        let skipped = convert_files(sources, &sb3)?;
+
+        let mut archive = ZipArchive::new(File::open(&sb3)?)?;
+        let mut json = String::new();
+        archive.by_name("project.json")?.read_to_string(&mut json)?;
+        Ok((serde_json::from_str(&json)?, skipped))
+    }
+
+    fn opcodes(project: &Value) -> Vec<String> {
+        let mut opcodes = project["targets"][1]["blocks"]
+            .as_object()
+            .into_iter()
+            .flatten()
+            .filter_map(|(_, block)| block["opcode"].as_str().map(String::from))
+            .collect::<Vec<_>>();
+        opcodes.sort();
+        opcodes
+    }
+
+    #[test]
+    fn converts_scripts() {
+        let scripts = json!([
+            [
+                10,
+                20,
+                [
+                    ["whenGreenFlag"],
+                    ["forward:", 10],
+                    ["doRepeat", 3, [["turnRight:", 15]]]
+                ]
+            ],
+            [0, 0, [["someRemovedBlock"]]],
+        ]);
+        let (project, skipped) = convert_scripts(scripts).unwrap();
+        assert_eq!(
+            opcodes(&project),
+            [
+                "control_repeat",
+                "event_whenflagclicked",
+                "motion_movesteps",
+                "motion_turnright",
+            ]
+        );
+        assert_eq!(skipped, ["someRemovedBlock"]);
+    }
+
+    #[test]
+    fn converts_custom_blocks() {
+        let scripts = json!([
+            [
+                0,
+                0,
+                [
+                    ["procDef", "jump %n", ["height"], [10], true],
+                    ["changeYposBy:", 1]
+                ]
+            ],
+            [0, 100, [["call", "jump %n", 5]]],
+        ]);
+        let (project, _) = convert_scripts(scripts).unwrap();
+        let blocks = project["targets"][1]["blocks"].as_object().unwrap();
+        let prototype = blocks
+            .values()
+            .find(|block| block["opcode"] == "procedures_prototype")
+            .unwrap();
+        assert_eq!(prototype["mutation"]["proccode"], "jump %n");
+        assert_eq!(prototype["mutation"]["warp"], "true");
+        assert!(blocks
+            .values()
+            .any(|block| block["opcode"] == "procedures_call"));
+    }
+
+    #[test]
+    fn malformed_scripts_dont_panic() {
+        let scripts = [
+            json!([[0, 0, [[]]]]),
+            json!([[0, 0, []]]),
+            json!([[0, 0, "not a stack"]]),
+            json!([[]]),
+            json!(["not a script"]),
+            json!([[0, 0, [["procDef"]]]]),
+            json!([[0, 0, [["procDef", 5, "names", null, "warp"]]]]),
+            json!([[0, 0, [["call"]]]]),
+            json!([[0, 0, [["getParam"]]]]),
+            json!([[0, 0, [[42], [null], ["forward:"]]]]),
+            json!([[0, 0, [["doRepeat", [["forward:"]], "not a substack"]]]]),
+            json!([["x", "y", [["doForever", [[[]], ["forward:", ["xpos"]]]]]]]),
+            json!("not a list of scripts"),
+        ];
+        for scripts in scripts {
+            assert!(convert_scripts(scripts.clone()).is_ok(), "{scripts}");
+        }
+    }
+}
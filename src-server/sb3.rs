@@ -1,44 +1,712 @@
-use serde::Deserialize;
+//! Reading and writing SB3 files, which are ZIP files holding project.json and every asset
+//! named by its MD5 hash, all at the top level
 
-#[derive(Debug, Deserialize)]
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::Path;
+
+use anyhow::{anyhow, Context, Result};
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use tracing::warn;
+use zip::write::FileOptions;
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
+
+use crate::error::Error;
+use crate::formats::{self, ProjectFormat};
+use crate::limits::{self, MAX_ASSET_SIZE, MAX_JSON_SIZE};
+use crate::normalize::normalize;
+use crate::repair;
+
+/// Represents a project.json
+///
+/// Only what the server reads is typed. Everything else is kept in `extra`, so a project
+/// survives a round trip even with fields from newer editors or extensions
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct Project {
+    pub targets: Vec<Target>,
+    /// IDs of the extensions the project uses, including custom ones
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub extensions: Option<Vec<String>>,
+    /// Where custom extensions are loaded from, which mods like TurboWarp and PenguinMod add
+    #[serde(
+        default,
+        rename = "extensionURLs",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub extension_urls: Option<Map<String, Value>>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+impl Project {
+    /// Find a sprite (or the stage) by name
+    pub fn target(&self, name: &str) -> Option<&Target> {
+        self.targets.iter().find(|t| t.name == name)
+    }
+
+    /// IDs of the extensions the project uses
+    pub fn extensions(&self) -> &[String] {
+        self.extensions.as_deref().unwrap_or_default()
+    }
+
+    /// The editor the project was saved by, judging by its `meta`
+    pub fn format(&self) -> &'static dyn ProjectFormat {
+        formats::detect(self.extra.get("meta").unwrap_or(&Value::Null))
+    }
+
+    /// Where a custom extension is loaded from, if it isn't built into the editor
+    pub fn extension_url(&self, id: &str) -> Option<&str> {
+        self.extension_urls.as_ref()?.get(id)?.as_str()
+    }
+
+    /// Count what the project is made of, with asset sizes read from the directory it's in
+    pub fn stats(&self, dir: &Path) -> ProjectStats {
+        let mut stats = ProjectStats::default();
+        for target in &self.targets {
+            if !target.is_stage {
+                stats.sprites += 1;
+            }
+            stats.scripts += target.top_ids().len();
+            for (_, block) in target.blocks() {
+                // menus are part of the block they're in
+                if block.opcode.ends_with("_menu") {
+                    continue;
+                }
+                let category = block.opcode.split('_').next().unwrap_or_default();
+                *stats.blocks.entry(category.to_string()).or_default() += 1;
+            }
+            let count = |key| {
+                target
+                    .extra
+                    .get(key)
+                    .and_then(Value::as_object)
+                    .map_or(0, Map::len)
+            };
+            stats.variables += count("variables");
+            stats.lists += count("lists");
+        }
+
+        let assets = get_assets(self);
+        stats.assets = assets.len();
+        stats.asset_bytes = assets
+            .iter()
+            .filter_map(|asset| fs::metadata(dir.join(asset)).ok())
+            .map(|metadata| metadata.len())
+            .sum();
+        stats
+    }
+}
+
+/// Represents how big a project is
+#[derive(Debug, Default, Serialize)]
+pub struct ProjectStats {
+    pub sprites: usize,
+    pub scripts: usize,
+    /// Block counts by category, e.g. `motion` or `pen`
+    pub blocks: BTreeMap<String, usize>,
+    pub variables: usize,
+    pub lists: usize,
+    /// Distinct asset files, so an asset shared by sprites counts once
+    pub assets: usize,
+    /// Total size of those files, leaving out any that aren't in the working directory
+    pub asset_bytes: u64,
+}
+
+/// Represents a sprite or the stage
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
 #[serde(rename_all = "camelCase")]
-pub struct CostumeOrSound {
-    pub data_format: String,
+pub struct Target {
+    #[serde(default)]
+    pub is_stage: bool,
+    pub name: String,
+    #[serde(default)]
+    pub blocks: BTreeMap<String, BlockEntry>,
+    #[serde(default)]
+    pub costumes: Vec<Costume>,
+    #[serde(default)]
+    pub sounds: Vec<Sound>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+/// Suffix marking the stage in sprite names from diffs, so a sprite named `Stage` isn't mixed
+/// up with it
+pub const STAGE_SUFFIX: &str = " (stage)";
+
+impl Target {
+    /// The target's name, marked with [`STAGE_SUFFIX`] for the stage
+    pub fn display_name(&self) -> String {
+        if self.is_stage {
+            format!("{}{STAGE_SUFFIX}", self.name)
+        } else {
+            self.name.clone()
+        }
+    }
+
+    /// Every block in the target, leaving out reporters dropped straight onto the workspace
+    pub fn blocks(&self) -> impl Iterator<Item = (&String, &Block)> {
+        self.blocks.iter().filter_map(|(id, entry)| match entry {
+            BlockEntry::Block(block) => Some((id, block)),
+            BlockEntry::Primitive(_) | BlockEntry::Other(_) => None,
+        })
+    }
+
+    /// IDs of the blocks that start scripts
+    pub fn top_ids(&self) -> Vec<String> {
+        self.blocks()
+            .filter(|(_, block)| block.top_level)
+            .map(|(id, _)| id.clone())
+            .collect()
+    }
+
+    /// Every block grouped into scripts, by the ID of each script's top block
+    pub fn scripts(&self) -> BTreeMap<&str, BTreeMap<&str, &BlockEntry>> {
+        let mut scripts = BTreeMap::<_, BTreeMap<_, _>>::new();
+        for (id, entry) in &self.blocks {
+            // parents are followed up to the top, giving up on cycles
+            let mut top = id.as_str();
+            for _ in 0..self.blocks.len() {
+                match self.blocks.get(top) {
+                    Some(BlockEntry::Block(Block {
+                        parent: Some(parent),
+                        ..
+                    })) if self.blocks.contains_key(parent) => top = parent,
+                    _ => break,
+                }
+            }
+            scripts.entry(top).or_default().insert(id.as_str(), entry);
+        }
+        scripts
+    }
+
+    /// The target's variables, leaving out lists
+    pub fn variables(&self) -> impl Iterator<Item = Variable<'_>> {
+        self.extra
+            .get("variables")
+            .and_then(Value::as_object)
+            .into_iter()
+            .flatten()
+            .filter_map(|(id, variable)| {
+                // variables are stored as [name, value] or [name, value, is cloud]
+                let variable = variable.as_array()?;
+                let name = variable.first()?.as_str()?;
+                Some(Variable {
+                    id,
+                    name,
+                    cloud: variable.get(2) == Some(&Value::Bool(true))
+                        || name.starts_with(CLOUD_PREFIX),
+                })
+            })
+    }
+
+    /// Every broadcast the target defines, by ID, which Scratch keeps on the stage
+    pub fn broadcasts(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.extra
+            .get("broadcasts")
+            .and_then(Value::as_object)
+            .into_iter()
+            .flatten()
+            .filter_map(|(id, name)| Some((id.as_str(), name.as_str()?)))
+    }
+
+    /// ID of the top block of the script a block is in
+    pub fn script_of<'a>(&'a self, id: &'a str) -> &'a str {
+        let mut id = id;
+        // a broken project can have parents pointing in a loop
+        for _ in 0..self.blocks.len() {
+            match self.blocks.get(id) {
+                Some(BlockEntry::Block(Block {
+                    parent: Some(parent),
+                    ..
+                })) => id = parent,
+                _ => break,
+            }
+        }
+        id
+    }
+
+    /// File names of the target's sounds and costumes, in that order
+    ///
+    /// Assets whose names can't be worked out are left out with a warning
+    pub fn asset_paths(&self) -> impl Iterator<Item = String> + '_ {
+        let sounds = self.sounds.iter().map(|s| (&s.name, s.path()));
+        let costumes = self.costumes.iter().map(|c| (&c.name, c.path()));
+        sounds.chain(costumes).filter_map(|(name, path)| {
+            path.inspect_err(|e| warn!(sprite = self.name, asset = name, "{e}"))
+                .ok()
+        })
+    }
+}
+
+/// Prefix the editor gives cloud variables' names
+pub const CLOUD_PREFIX: &str = "☁ ";
+
+/// Represents a variable's definition, without its value
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Variable<'a> {
+    pub id: &'a str,
+    pub name: &'a str,
+    /// Shared with everyone running the project through the cloud server
+    pub cloud: bool,
+}
+
+/// Represents an entry in a target's blocks
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(untagged)]
+pub enum BlockEntry {
+    Block(Block),
+    /// A variable or list reporter dropped straight onto the workspace, stored as an array
+    Primitive(Vec<Value>),
+    /// Anything else, like blocks from mods that don't follow the usual structure
+    Other(Value),
+}
+
+/// Represents a single block
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Block {
+    #[serde(default)]
+    pub opcode: String,
+    #[serde(default)]
+    pub next: Option<String>,
+    #[serde(default)]
+    pub parent: Option<String>,
+    #[serde(default)]
+    pub inputs: Map<String, Value>,
+    #[serde(default)]
+    pub fields: Map<String, Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mutation: Option<Value>,
+    #[serde(default)]
+    pub shadow: bool,
+    #[serde(default)]
+    pub top_level: bool,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+impl Block {
+    /// ID of the first block in a C-shaped input like `SUBSTACK`, or a boolean input like
+    /// `CONDITION`
+    pub fn input_block(&self, name: &str) -> Option<&str> {
+        self.inputs.get(name)?.as_array()?.get(1)?.as_str()
+    }
+
+    /// ID of the broadcast a block sends or receives, if it's picked from the menu rather than
+    /// covered by a reporter
+    pub fn broadcast(&self) -> Option<&str> {
+        // receivers keep it as [name, ID], and senders as an input holding [11, name, ID]
+        let broadcast = match self.opcode.as_str() {
+            "event_whenbroadcastreceived" => self.fields.get("BROADCAST_OPTION")?,
+            "event_broadcast" | "event_broadcastandwait" => {
+                let input = self.inputs.get("BROADCAST_INPUT")?.as_array()?;
+                let primitive = input.get(1)?.as_array()?;
+                if input.first()? != 1 || primitive.first()? != 11 {
+                    return None;
+                }
+                return primitive.get(2)?.as_str();
+            }
+            _ => return None,
+        };
+        broadcast.as_array()?.get(1)?.as_str()
+    }
+}
+
+/// Why an asset's file name couldn't be worked out
+#[derive(Debug, Clone, PartialEq, thiserror::Error)]
+pub enum AssetPathError {
+    /// `assetId` or `dataFormat` is empty, with no `md5ext` to name the file instead
+    #[error("asset has no {0}")]
+    Missing(&'static str),
+    /// The name isn't an MD5 hash and an extension, like `../project.json`
+    #[error("{0} isn't an asset file name")]
+    InvalidName(String),
+}
+
+/// File name of an asset, which older projects leave to be worked out from its ID and format
+///
+/// Names have to be an MD5 hash and an extension, so a project can't refer to files outside the
+/// assets it stores, like project.json itself
+pub fn asset_path(
+    md5ext: Option<&str>,
+    asset_id: &str,
+    data_format: &str,
+) -> Result<String, AssetPathError> {
+    let path = match md5ext {
+        Some(md5ext) => md5ext.to_string(),
+        None if asset_id.is_empty() => return Err(AssetPathError::Missing("assetId")),
+        None if data_format.is_empty() => return Err(AssetPathError::Missing("dataFormat")),
+        None => format!("{asset_id}.{data_format}"),
+    };
+    if is_asset_name(&path) {
+        Ok(path)
+    } else {
+        Err(AssetPathError::InvalidName(path))
+    }
+}
+
+/// Represents a costume
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Costume {
+    pub name: String,
     pub asset_id: String,
+    pub data_format: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub md5ext: Option<String>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
 }
 
-#[derive(Debug, Deserialize)]
-pub struct Target {
-    pub costumes: Vec<CostumeOrSound>,
-    pub sounds: Vec<CostumeOrSound>,
+impl Costume {
+    /// File name of the costume's asset
+    pub fn path(&self) -> Result<String, AssetPathError> {
+        asset_path(self.md5ext.as_deref(), &self.asset_id, &self.data_format)
+    }
 }
 
-#[derive(Debug, Deserialize)]
-pub struct ProjectData {
-    pub targets: Vec<Target>,
+/// Represents a sound
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Sound {
+    pub name: String,
+    pub asset_id: String,
+    pub data_format: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub md5ext: Option<String>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+impl Sound {
+    /// File name of the sound's asset
+    pub fn path(&self) -> Result<String, AssetPathError> {
+        asset_path(self.md5ext.as_deref(), &self.asset_id, &self.data_format)
+    }
 }
 
-pub fn get_assets(project: ProjectData) -> Vec<String> {
+/// File names of every asset a project uses
+///
+/// Assets used by several sprites have the same file name, so they're only listed once
+pub fn get_assets(project: &Project) -> Vec<String> {
     project
         .targets
         .iter()
-        .map(|t| {
-            let sounds = t.sounds.iter().map(|s| {
-                s.md5ext
-                    .clone()
-                    .unwrap_or(format!("{}.{}", s.asset_id, s.data_format))
-                    .to_string()
-            });
-            let costumes = t.costumes.iter().map(|s| {
-                s.md5ext
-                    .clone()
-                    .unwrap_or(format!("{}.{}", s.asset_id, s.data_format))
-                    .to_string()
-            });
-
-            sounds.chain(costumes).collect::<Vec<String>>()
-        })
-        .flatten()
+        .flat_map(Target::asset_paths)
+        .unique()
         .collect()
 }
+
+/// Read project.json straight out of an SB3 without extracting anything
+pub fn read_project_json(sb3: &Path) -> Result<Project> {
+    let mut archive = ZipArchive::new(File::open(sb3)?)?;
+    let mut budget = limits::check_archive(&mut archive, "project file")?;
+    let json = limits::read_entry(archive.by_name("project.json")?, MAX_JSON_SIZE, &mut budget)?;
+    let contents = String::from_utf8(json).context("project.json isn't text")?;
+    Ok(parse(&contents, "project.json").inspect_err(|_| count_parse_failure())?)
+}
+
+/// Count a project.json that failed to parse, for `/metrics` in builds with the server
+fn count_parse_failure() {
+    #[cfg(feature = "native")]
+    crate::metrics::PARSE_FAILURES.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+}
+
+/// Find the sprite and field that stop a project.json from parsing, since serde only says where
+/// in the text it gave up
+///
+/// Fields are named by where they are in the sprite, like `costumes[2].dataFormat`. `None` if
+/// nothing typed is missing or the wrong type, like when the JSON itself is cut off
+pub fn malformed(project: &Value) -> Option<Error> {
+    let broken = |sprite: &str, field: &str| {
+        Some(Error::MalformedProject {
+            sprite: sprite.to_string(),
+            field: field.to_string(),
+        })
+    };
+    let Some(targets) = project.get("targets").and_then(Value::as_array) else {
+        return broken("project", "targets");
+    };
+    let project_fields: [Field; 2] = [
+        ("extensions", false, |v| v.is_array() || v.is_null()),
+        ("extensionURLs", false, |v| v.is_object() || v.is_null()),
+    ];
+    if let Some(field) = bad_field(project, &project_fields) {
+        return broken("project", field);
+    }
+
+    let target_fields: [Field; 5] = [
+        ("name", true, Value::is_string),
+        ("isStage", false, Value::is_boolean),
+        ("blocks", false, Value::is_object),
+        ("costumes", false, Value::is_array),
+        ("sounds", false, Value::is_array),
+    ];
+    let asset_fields: [Field; 4] = [
+        ("name", true, Value::is_string),
+        ("assetId", true, Value::is_string),
+        ("dataFormat", true, Value::is_string),
+        ("md5ext", false, |v| v.is_string() || v.is_null()),
+    ];
+    for (i, target) in targets.iter().enumerate() {
+        let sprite = target
+            .get("name")
+            .and_then(Value::as_str)
+            .map_or_else(|| format!("targets[{i}]"), String::from);
+        if !target.is_object() {
+            return broken(&sprite, "target");
+        }
+        if let Some(field) = bad_field(target, &target_fields) {
+            return broken(&sprite, field);
+        }
+        for key in ["costumes", "sounds"] {
+            let assets = target
+                .get(key)
+                .and_then(Value::as_array)
+                .into_iter()
+                .flatten();
+            for (j, asset) in assets.enumerate() {
+                if !asset.is_object() {
+                    return broken(&sprite, &format!("{key}[{j}]"));
+                }
+                if let Some(field) = bad_field(asset, &asset_fields) {
+                    return broken(&sprite, &format!("{key}[{j}].{field}"));
+                }
+            }
+        }
+    }
+    None
+}
+
+/// A typed field's key, if it has to be there, and what its value has to be
+type Field = (&'static str, bool, fn(&Value) -> bool);
+
+/// The first field of an object that's missing or the wrong type
+fn bad_field(object: &Value, fields: &[Field]) -> Option<&'static str> {
+    fields
+        .iter()
+        .find_map(|&(key, required, valid)| match object.get(key) {
+            Some(value) => (!valid(value)).then_some(key),
+            None => required.then_some(key),
+        })
+}
+
+/// Parse a project.json, naming the sprite and field that broke it if it doesn't parse
+///
+/// Fails with [`Error::TooLarge`] without parsing anything if it's past the [`limits`].
+pub fn parse(contents: &str, what: &str) -> Result<Project, Error> {
+    limits::check_json(contents.as_bytes(), what)?;
+    serde_json::from_str(contents).map_err(|e| {
+        serde_json::from_str(contents)
+            .ok()
+            .and_then(|project| malformed(&project))
+            .unwrap_or_else(|| Error::parse(what, e))
+    })
+}
+
+/// Check if a file is named like an asset, by a lowercase MD5 hash and an extension
+///
+/// Extensions can only have letters and digits, so a name can't hold a path like
+/// `<hash>.svg/../../project.json`
+pub fn is_asset_name(name: &str) -> bool {
+    name.split_once('.').is_some_and(|(hash, ext)| {
+        hash.len() == 32
+            && hash.bytes().all(|c| matches!(c, b'0'..=b'9' | b'a'..=b'f'))
+            && !ext.is_empty()
+            && ext.bytes().all(|c| c.is_ascii_alphanumeric())
+    })
+}
+
+/// Check that an asset's contents hash to the name the project refers to it by
+pub(crate) fn is_correctly_named(asset: &str, contents: &[u8]) -> bool {
+    let Some((hash, _)) = asset.split_once('.') else {
+        return false;
+    };
+    format!("{:x}", md5::compute(contents)) == hash
+}
+
+/// Check that an asset file hashes to the name the project refers to it by, hashing it a chunk
+/// at a time so large assets aren't read into memory
+pub(crate) fn is_correctly_named_file(asset: &str, path: &Path) -> io::Result<bool> {
+    let Some((hash, _)) = asset.split_once('.') else {
+        return Ok(false);
+    };
+    let mut context = md5::Context::new();
+    io::copy(&mut File::open(path)?, &mut context)?;
+    Ok(format!("{:x}", context.compute()) == hash)
+}
+
+/// Extract an SB3's project.json and the assets it uses into a directory, returning the assets
+///
+/// Anything else in the SB3, like folders or files the project doesn't use, is left out, and project.json is
+/// normalized so saving an unchanged project doesn't change it
+pub fn unpack(sb3: &Path, dir: &Path) -> Result<Vec<String>> {
+    let mut archive = ZipArchive::new(File::open(sb3).context("failed to open project file")?)
+        .context("project file isn't a ZIP file")?;
+    let mut budget = limits::check_archive(&mut archive, "project file")?;
+    fs::create_dir_all(dir)?;
+
+    let entry = archive
+        .by_name("project.json")
+        .context("project file has no project.json")?;
+    let mut json = limits::read_entry(entry, MAX_JSON_SIZE, &mut budget)?;
+    limits::check_json(&json, "project.json")?;
+    let project =
+        serde_json::from_slice::<Project>(&json).inspect_err(|_| count_parse_failure())?;
+
+    // editor crashes can leave blocks pointing at blocks that are gone
+    let mut repaired = serde_json::from_slice::<Value>(&json)?;
+    let repairs = repair::dangling_blocks(&mut repaired);
+    if !repairs.is_empty() {
+        warn!(count = repairs.len(), "repaired dangling block references");
+        json = serde_json::to_vec(&repaired)?;
+    }
+    fs::write(dir.join("project.json"), normalize(&json)?)?;
+
+    let assets = get_assets(&project).into_iter().collect::<BTreeSet<_>>();
+    for asset in &assets {
+        // asset names come from project.json, so make sure they can't escape the directory
+        if asset.contains(['/', '\\']) || asset.starts_with('.') {
+            return Err(anyhow!("invalid asset name {asset}"));
+        }
+        let file = archive
+            .by_name(asset)
+            .with_context(|| format!("project file is missing {asset}"))?;
+        // assets are named by their contents, so one that's already there is the same file
+        let path = dir.join(asset);
+        if is_correctly_named_file(asset, &path).unwrap_or(false) {
+            continue;
+        }
+        write_asset(
+            &path,
+            &limits::read_entry(file, MAX_ASSET_SIZE, &mut budget)?,
+        )?;
+    }
+
+    Ok(assets.into_iter().collect())
+}
+
+/// Write an asset next to where it goes and move it into place
+///
+/// Assets can be mapped while they're read, and writing over a mapped file would change it
+/// under whatever's reading it, or fail on Windows
+pub(crate) fn write_asset(path: &Path, contents: &[u8]) -> Result<()> {
+    let mut partial = path.as_os_str().to_owned();
+    partial.push(".partial");
+    let partial = Path::new(&partial);
+    fs::write(partial, contents)?;
+    fs::rename(partial, path).inspect_err(|_| {
+        let _ = fs::remove_file(partial);
+    })?;
+    Ok(())
+}
+
+/// Zip a directory's project.json and the assets it uses into an SB3
+pub fn pack(dir: &Path, sb3: &Path) -> Result<()> {
+    let json = fs::read(dir.join("project.json")).context("failed to read project.json")?;
+    let project = serde_json::from_slice::<Project>(&json)?;
+
+    let assets = get_assets(&project)
+        .into_iter()
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .map(|asset| {
+            let contents =
+                fs::read(dir.join(&asset)).with_context(|| format!("{asset} is missing"))?;
+            if !is_correctly_named(&asset, &contents) {
+                warn!("{asset} doesn't match its MD5 hash, the editor may not load it");
+            }
+            Ok((asset, contents))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    write(sb3, &json, assets)
+}
+
+/// Zip a project.json and its assets into an SB3
+pub fn write<A: AsRef<[u8]>>(
+    sb3: &Path,
+    json: &[u8],
+    assets: impl IntoIterator<Item = (String, A)>,
+) -> Result<()> {
+    write_zip(sb3, "project.json", json, assets)
+}
+
+/// Zip a sprite and its assets into a SPRITE3, which is laid out like an SB3 but with
+/// sprite.json in place of project.json
+pub fn pack_sprite<A: AsRef<[u8]>>(
+    sprite: &Target,
+    assets: impl IntoIterator<Item = (String, A)>,
+    sprite3: &Path,
+) -> Result<()> {
+    write_zip(sprite3, "sprite.json", &serde_json::to_vec(sprite)?, assets)
+}
+
+/// Assets read out of a ZIP, by file name
+pub type Assets = Vec<(String, Vec<u8>)>;
+
+/// Read a SPRITE3's sprite and the assets it uses
+pub fn unpack_sprite(sprite3: &Path) -> Result<(Target, Assets)> {
+    let mut archive = ZipArchive::new(File::open(sprite3).context("failed to open sprite file")?)
+        .context("sprite file isn't a ZIP file")?;
+    let mut budget = limits::check_archive(&mut archive, "sprite file")?;
+
+    let entry = archive
+        .by_name("sprite.json")
+        .context("sprite file has no sprite.json")?;
+    let json = limits::read_entry(entry, MAX_JSON_SIZE, &mut budget)?;
+    limits::check_json(&json, "sprite.json")?;
+    let sprite = serde_json::from_slice::<Target>(&json)?;
+
+    let mut assets = vec![];
+    for asset in sprite.asset_paths().unique() {
+        if asset.contains(['/', '\\']) || asset.starts_with('.') {
+            return Err(anyhow!("invalid asset name {asset}"));
+        }
+        let file = archive
+            .by_name(&asset)
+            .with_context(|| format!("sprite file is missing {asset}"))?;
+        let contents = limits::read_entry(file, MAX_ASSET_SIZE, &mut budget)?;
+        assets.push((asset, contents));
+    }
+
+    Ok((sprite, assets))
+}
+
+/// Zip a JSON file and assets, all at the top level
+///
+/// The ZIP is written next to its destination first, so a failure leaves the old one intact
+pub(crate) fn write_zip<A: AsRef<[u8]>>(
+    path: &Path,
+    json_name: &str,
+    json: &[u8],
+    assets: impl IntoIterator<Item = (String, A)>,
+) -> Result<()> {
+    let mut partial = path.as_os_str().to_owned();
+    partial.push(".partial");
+    let partial = Path::new(&partial);
+    let mut zip = ZipWriter::new(File::create(partial)?);
+    let options = FileOptions::default().compression_method(CompressionMethod::Deflated);
+
+    let written = (|| -> Result<()> {
+        zip.start_file(json_name, options)?;
+        zip.write_all(json)?;
+        for (asset, contents) in assets {
+            zip.start_file(asset, options)?;
+            zip.write_all(contents.as_ref())?;
+        }
+        zip.finish()?;
+        Ok(())
+    })();
+
+    if let Err(e) = written {
+        drop(zip);
+        let _ = fs::remove_file(partial);
+        return Err(e);
+    }
+    fs::rename(partial, path)?;
+    Ok(())
+}
@@ -0,0 +1,409 @@
+//! Writing scripts in scratchblocks markup, which the Scratch forums render as blocks
+//!
+//! Blocks are written from templates where `%NAME` stands for the input or field called
+//! `NAME`. Blocks without a template, like ones from extensions, are written as their opcode
+//! followed by their inputs, so they still show up as something.
+
+use std::collections::BTreeMap;
+
+use serde_json::Value;
+
+use crate::sb3::{Block, BlockEntry};
+
+/// Blocks and how they're written, with reporters in `()` and booleans in `<>`
+const BLOCKS: &[(&str, &str)] = &[
+    // motion
+    ("motion_movesteps", "move %STEPS steps"),
+    ("motion_turnright", "turn right %DEGREES degrees"),
+    ("motion_turnleft", "turn left %DEGREES degrees"),
+    ("motion_goto", "go to %TO"),
+    ("motion_gotoxy", "go to x: %X y: %Y"),
+    ("motion_glideto", "glide %SECS secs to %TO"),
+    ("motion_glidesecstoxy", "glide %SECS secs to x: %X y: %Y"),
+    ("motion_pointindirection", "point in direction %DIRECTION"),
+    ("motion_pointtowards", "point towards %TOWARDS"),
+    ("motion_changexby", "change x by %DX"),
+    ("motion_setx", "set x to %X"),
+    ("motion_changeyby", "change y by %DY"),
+    ("motion_sety", "set y to %Y"),
+    ("motion_ifonedgebounce", "if on edge, bounce"),
+    ("motion_setrotationstyle", "set rotation style %STYLE"),
+    ("motion_xposition", "(x position)"),
+    ("motion_yposition", "(y position)"),
+    ("motion_direction", "(direction)"),
+    // looks
+    ("looks_sayforsecs", "say %MESSAGE for %SECS seconds"),
+    ("looks_say", "say %MESSAGE"),
+    ("looks_thinkforsecs", "think %MESSAGE for %SECS seconds"),
+    ("looks_think", "think %MESSAGE"),
+    ("looks_switchcostumeto", "switch costume to %COSTUME"),
+    ("looks_nextcostume", "next costume"),
+    ("looks_switchbackdropto", "switch backdrop to %BACKDROP"),
+    (
+        "looks_switchbackdroptoandwait",
+        "switch backdrop to %BACKDROP and wait",
+    ),
+    ("looks_nextbackdrop", "next backdrop"),
+    ("looks_changesizeby", "change size by %CHANGE"),
+    ("looks_setsizeto", "set size to %SIZE %"),
+    ("looks_changeeffectby", "change %EFFECT effect by %CHANGE"),
+    ("looks_seteffectto", "set %EFFECT effect to %VALUE"),
+    ("looks_cleargraphiceffects", "clear graphic effects"),
+    ("looks_show", "show"),
+    ("looks_hide", "hide"),
+    ("looks_gotofrontback", "go to %FRONT_BACK layer"),
+    (
+        "looks_goforwardbackwardlayers",
+        "go %FORWARD_BACKWARD %NUM layers",
+    ),
+    ("looks_costumenumbername", "(costume %NUMBER_NAME)"),
+    ("looks_backdropnumbername", "(backdrop %NUMBER_NAME)"),
+    ("looks_size", "(size)"),
+    // sound
+    ("sound_playuntildone", "play sound %SOUND_MENU until done"),
+    ("sound_play", "start sound %SOUND_MENU"),
+    ("sound_stopallsounds", "stop all sounds"),
+    ("sound_changeeffectby", "change %EFFECT effect by %VALUE"),
+    ("sound_seteffectto", "set %EFFECT effect to %VALUE"),
+    ("sound_cleareffects", "clear sound effects"),
+    ("sound_changevolumeby", "change volume by %VOLUME"),
+    ("sound_setvolumeto", "set volume to %VOLUME %"),
+    ("sound_volume", "(volume)"),
+    // events
+    ("event_whenflagclicked", "when flag clicked"),
+    ("event_whenkeypressed", "when %KEY_OPTION key pressed"),
+    ("event_whenthisspriteclicked", "when this sprite clicked"),
+    ("event_whenstageclicked", "when stage clicked"),
+    (
+        "event_whenbackdropswitchesto",
+        "when backdrop switches to %BACKDROP",
+    ),
+    (
+        "event_whengreaterthan",
+        "when %WHENGREATERTHANMENU > %VALUE",
+    ),
+    (
+        "event_whenbroadcastreceived",
+        "when I receive %BROADCAST_OPTION",
+    ),
+    ("event_broadcast", "broadcast %BROADCAST_INPUT"),
+    (
+        "event_broadcastandwait",
+        "broadcast %BROADCAST_INPUT and wait",
+    ),
+    // control
+    ("control_wait", "wait %DURATION seconds"),
+    ("control_repeat", "repeat %TIMES"),
+    ("control_forever", "forever"),
+    ("control_if", "if %CONDITION then"),
+    ("control_if_else", "if %CONDITION then"),
+    ("control_wait_until", "wait until %CONDITION"),
+    ("control_repeat_until", "repeat until %CONDITION"),
+    ("control_stop", "stop %STOP_OPTION"),
+    ("control_start_as_clone", "when I start as a clone"),
+    ("control_create_clone_of", "create clone of %CLONE_OPTION"),
+    ("control_delete_this_clone", "delete this clone"),
+    // sensing
+    ("sensing_touchingobject", "<touching %TOUCHINGOBJECTMENU ?>"),
+    ("sensing_touchingcolor", "<touching %COLOR ?>"),
+    (
+        "sensing_coloristouchingcolor",
+        "<color %COLOR is touching %COLOR2 ?>",
+    ),
+    ("sensing_distanceto", "(distance to %DISTANCETOMENU)"),
+    ("sensing_askandwait", "ask %QUESTION and wait"),
+    ("sensing_answer", "(answer)"),
+    ("sensing_keypressed", "<key %KEY_OPTION pressed?>"),
+    ("sensing_mousedown", "<mouse down?>"),
+    ("sensing_mousex", "(mouse x)"),
+    ("sensing_mousey", "(mouse y)"),
+    ("sensing_setdragmode", "set drag mode %DRAG_MODE"),
+    ("sensing_loudness", "(loudness)"),
+    ("sensing_timer", "(timer)"),
+    ("sensing_resettimer", "reset timer"),
+    ("sensing_of", "(%PROPERTY of %OBJECT)"),
+    ("sensing_current", "(current %CURRENTMENU)"),
+    ("sensing_dayssince2000", "(days since 2000)"),
+    ("sensing_username", "(username)"),
+    // operators
+    ("operator_add", "(%NUM1 + %NUM2)"),
+    ("operator_subtract", "(%NUM1 - %NUM2)"),
+    ("operator_multiply", "(%NUM1 * %NUM2)"),
+    ("operator_divide", "(%NUM1 / %NUM2)"),
+    ("operator_random", "(pick random %FROM to %TO)"),
+    ("operator_gt", "<%OPERAND1 > %OPERAND2>"),
+    ("operator_lt", "<%OPERAND1 < %OPERAND2>"),
+    ("operator_equals", "<%OPERAND1 = %OPERAND2>"),
+    ("operator_and", "<%OPERAND1 and %OPERAND2>"),
+    ("operator_or", "<%OPERAND1 or %OPERAND2>"),
+    ("operator_not", "<not %OPERAND>"),
+    ("operator_join", "(join %STRING1 %STRING2)"),
+    ("operator_letter_of", "(letter %LETTER of %STRING)"),
+    ("operator_length", "(length of %STRING)"),
+    ("operator_contains", "<%STRING1 contains %STRING2 ?>"),
+    ("operator_mod", "(%NUM1 mod %NUM2)"),
+    ("operator_round", "(round %NUM)"),
+    ("operator_mathop", "(%OPERATOR of %NUM)"),
+    // variables and lists
+    ("data_setvariableto", "set %VARIABLE to %VALUE"),
+    ("data_changevariableby", "change %VARIABLE by %VALUE"),
+    ("data_showvariable", "show variable %VARIABLE"),
+    ("data_hidevariable", "hide variable %VARIABLE"),
+    ("data_addtolist", "add %ITEM to %LIST"),
+    ("data_deleteoflist", "delete %INDEX of %LIST"),
+    ("data_deletealloflist", "delete all of %LIST"),
+    ("data_insertatlist", "insert %ITEM at %INDEX of %LIST"),
+    (
+        "data_replaceitemoflist",
+        "replace item %INDEX of %LIST with %ITEM",
+    ),
+    ("data_itemoflist", "(item %INDEX of %LIST)"),
+    ("data_itemnumoflist", "(item # of %ITEM in %LIST)"),
+    ("data_lengthoflist", "(length of %LIST)"),
+    ("data_listcontainsitem", "<%LIST contains %ITEM ?>"),
+    ("data_showlist", "show list %LIST"),
+    ("data_hidelist", "hide list %LIST"),
+    // pen
+    ("pen_clear", "erase all"),
+    ("pen_stamp", "stamp"),
+    ("pen_penDown", "pen down"),
+    ("pen_penUp", "pen up"),
+    ("pen_setPenColorToColor", "set pen color to %COLOR"),
+    ("pen_changePenSizeBy", "change pen size by %SIZE"),
+    ("pen_setPenSizeTo", "set pen size to %SIZE"),
+];
+
+/// Inputs that hold booleans, written as `<>` when they're empty
+const BOOLEAN_INPUTS: &[&str] = &["CONDITION", "OPERAND", "OPERAND1", "OPERAND2"];
+
+/// Write scripts as a post for the Scratch forums, each in its own `[scratchblocks]` tag
+///
+/// Scripts are given by the ID of their top block, and ones that don't exist are left out
+pub fn post(blocks: &BTreeMap<String, BlockEntry>, top_ids: &[String]) -> String {
+    top_ids
+        .iter()
+        .filter(|id| blocks.contains_key(*id))
+        .map(|id| format!("[scratchblocks]\n{}[/scratchblocks]\n", script(blocks, id)))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Write the script starting at a block, one line per block
+pub fn script(blocks: &BTreeMap<String, BlockEntry>, id: &str) -> String {
+    let mut lines = vec![];
+    stack(blocks, id, 0, &mut lines);
+    lines
+        .into_iter()
+        .map(|line| line + "\n")
+        .collect::<String>()
+}
+
+/// Write a stack of blocks into `lines`, with the blocks in C-blocks indented under them
+fn stack(blocks: &BTreeMap<String, BlockEntry>, id: &str, depth: usize, lines: &mut Vec<String>) {
+    let indent = "\t".repeat(depth);
+    let mut current = Some(id);
+    // a stack can't be longer than the sprite, even if `next` loops back on itself
+    for _ in 0..blocks.len() {
+        let Some(id) = current else {
+            break;
+        };
+        let block = match blocks.get(id) {
+            Some(BlockEntry::Block(block)) => block,
+            Some(BlockEntry::Primitive(primitive)) => {
+                lines.push(format!("{indent}{}", literal(primitive)));
+                break;
+            }
+            _ => break,
+        };
+        lines.push(format!("{indent}{}", write_block(blocks, block)));
+
+        let mouths: &[&str] = match block.opcode.as_str() {
+            "control_if_else" => &["SUBSTACK", "SUBSTACK2"],
+            "control_repeat" | "control_forever" | "control_if" | "control_repeat_until" => {
+                &["SUBSTACK"]
+            }
+            _ => &[],
+        };
+        for (i, mouth) in mouths.iter().enumerate() {
+            if i > 0 {
+                lines.push(format!("{indent}else"));
+            }
+            if let Some(id) = block.input_block(mouth) {
+                stack(blocks, id, depth + 1, lines);
+            }
+        }
+        if !mouths.is_empty() {
+            lines.push(format!("{indent}end"));
+        }
+
+        current = block.next.as_deref();
+    }
+}
+
+/// Write a single block, with the blocks in its inputs but not the ones in its mouths
+fn write_block(blocks: &BTreeMap<String, BlockEntry>, block: &Block) -> String {
+    let field = |name: &str| block.fields.get(name).and_then(|f| f.get(0)?.as_str());
+    match block.opcode.as_str() {
+        "data_variable" => return format!("({})", escape(field("VARIABLE").unwrap_or(""))),
+        "data_listcontents" => return format!("({} :: list)", escape(field("LIST").unwrap_or(""))),
+        "argument_reporter_string_number" => {
+            return format!("({} :: custom-arg)", escape(field("VALUE").unwrap_or("")))
+        }
+        "argument_reporter_boolean" => {
+            return format!("<{} :: custom-arg>", escape(field("VALUE").unwrap_or("")))
+        }
+        "procedures_definition" => {
+            let prototype = block
+                .input_block("custom_block")
+                .and_then(|id| match blocks.get(id) {
+                    Some(BlockEntry::Block(prototype)) => Some(prototype),
+                    _ => None,
+                });
+            return match prototype {
+                Some(prototype) => format!("define {}", custom_block(blocks, prototype)),
+                None => "define".into(),
+            };
+        }
+        "procedures_call" => return custom_block(blocks, block),
+        _ => {}
+    }
+
+    let Some((_, template)) = BLOCKS.iter().find(|(opcode, _)| *opcode == block.opcode) else {
+        // blocks without a template show their opcode and what's in them
+        let inputs = block
+            .inputs
+            .keys()
+            .chain(block.fields.keys())
+            .map(|name| argument(blocks, block, name));
+        let words = std::iter::once(block.opcode.clone()).chain(inputs);
+        return format!("{} :: grey", words.collect::<Vec<_>>().join(" "));
+    };
+
+    let mut text = String::new();
+    let mut rest = *template;
+    while let Some(start) = rest.find('%') {
+        text += &rest[..start];
+        let name_len = rest[start + 1..]
+            .find(|c: char| !(c.is_ascii_uppercase() || c.is_ascii_digit() || c == '_'))
+            .unwrap_or(rest.len() - start - 1);
+        let name = &rest[start + 1..start + 1 + name_len];
+        // a lone `%`, like in `set size to %SIZE %`
+        if name.is_empty() {
+            text.push('%');
+        } else {
+            text += &argument(blocks, block, name);
+        }
+        rest = &rest[start + 1 + name_len..];
+    }
+    text + rest
+}
+
+/// Write a custom block's definition or call, like `jump (height) <fast?>`
+///
+/// Arguments come from the call's inputs, or the names of the definition's arguments
+fn custom_block(blocks: &BTreeMap<String, BlockEntry>, block: &Block) -> String {
+    let mutation = |key: &str| {
+        block
+            .mutation
+            .as_ref()
+            .and_then(|mutation| mutation.get(key)?.as_str())
+            .unwrap_or_default()
+    };
+    let list = |key: &str| serde_json::from_str::<Vec<String>>(mutation(key)).unwrap_or_default();
+    let (ids, names) = (list("argumentids"), list("argumentnames"));
+    let is_call = block.opcode == "procedures_call";
+
+    let mut args = 0;
+    let mut text = String::new();
+    let mut chars = mutation("proccode").chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, chars.peek()) {
+            ('%', Some(&kind @ ('s' | 'n' | 'b'))) => {
+                chars.next();
+                text += &match (is_call, ids.get(args)) {
+                    (true, Some(id)) => argument(blocks, block, id),
+                    _ => {
+                        let name = escape(names.get(args).map_or("", String::as_str));
+                        match kind {
+                            'b' => format!("<{name}>"),
+                            _ => format!("({name})"),
+                        }
+                    }
+                };
+                args += 1;
+            }
+            (c, _) => text.push(c),
+        }
+    }
+    text + " :: custom"
+}
+
+/// Write one of a block's inputs or fields by name
+fn argument(blocks: &BTreeMap<String, BlockEntry>, block: &Block, name: &str) -> String {
+    if let Some(field) = block.fields.get(name) {
+        let value = field.get(0).map(display).unwrap_or_default();
+        return format!("[{} v]", escape(&value));
+    }
+    let empty = if BOOLEAN_INPUTS.contains(&name) {
+        "<>"
+    } else {
+        "()"
+    };
+    let Some(input) = block.inputs.get(name).and_then(Value::as_array) else {
+        return empty.into();
+    };
+
+    match input.get(1) {
+        Some(Value::String(id)) => match blocks.get(id) {
+            // menus are shadow blocks with a single field
+            Some(BlockEntry::Block(menu)) if menu.shadow => {
+                let value = menu.fields.values().next().and_then(|f| f.get(0));
+                format!("({} v)", escape(&value.map(display).unwrap_or_default()))
+            }
+            Some(BlockEntry::Block(reporter)) => {
+                let text = write_block(blocks, reporter);
+                if text.starts_with(['(', '<']) {
+                    text
+                } else {
+                    format!("({text})")
+                }
+            }
+            Some(BlockEntry::Primitive(primitive)) => literal(primitive),
+            _ => empty.into(),
+        },
+        Some(Value::Array(primitive)) => literal(primitive),
+        _ => empty.into(),
+    }
+}
+
+/// Write a literal input like `[4, "10"]` as a number, text, color, variable or list
+fn literal(primitive: &[Value]) -> String {
+    let value = escape(&primitive.get(1).map(display).unwrap_or_default());
+    match primitive.first().and_then(Value::as_u64) {
+        Some(4..=8) => format!("({value})"),
+        Some(11) => format!("({value} v)"),
+        Some(12) => format!("({value})"),
+        Some(13) => format!("({value} :: list)"),
+        _ => format!("[{value}]"),
+    }
+}
+
+/// A JSON value as it appears in the editor, without quotes around strings
+fn display(value: &Value) -> String {
+    match value {
+        Value::String(text) => text.clone(),
+        Value::Null => String::new(),
+        value => value.to_string(),
+    }
+}
+
+/// Escape the characters scratchblocks reads as the edges of inputs
+fn escape(text: &str) -> String {
+    text.chars()
+        .flat_map(|c| match c {
+            '[' | ']' | '(' | ')' | '<' | '>' | '\\' => vec!['\\', c],
+            '\n' => vec![' '],
+            c => vec![c],
+        })
+        .collect()
+}
@@ -0,0 +1,148 @@
+use std::collections::{BTreeSet, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::thread::{sleep, spawn};
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+use serde_json::{json, Value};
+use tracing::debug;
+
+use crate::events::broadcast;
+
+/// Sessions that haven't sent anything, not even a pong, for this long are closed
+const STALE_AFTER: Duration = Duration::from_secs(5 * 60);
+
+/// How often clients are pinged, so a live but quiet client isn't mistaken for a stale one
+pub const PING_EVERY: Duration = Duration::from_secs(30);
+
+#[derive(Debug)]
+struct Entry {
+    projects: BTreeSet<String>,
+    connected: Instant,
+    last_seen: Instant,
+}
+
+fn sessions() -> &'static Mutex<HashMap<u64, Entry>> {
+    static SESSIONS: OnceLock<Mutex<HashMap<u64, Entry>>> = OnceLock::new();
+    SESSIONS.get_or_init(Default::default)
+}
+
+/// Represents a connected client, for diagnostics
+#[derive(Debug, Clone, Serialize)]
+pub struct SessionInfo {
+    pub id: u64,
+    /// Projects the client has made requests for
+    pub projects: Vec<String>,
+    pub connected_secs: u64,
+    pub idle_secs: u64,
+}
+
+/// A connected client, which stops being tracked when dropped
+#[derive(Debug)]
+pub struct Session {
+    id: u64,
+}
+
+impl Session {
+    pub fn open() -> Self {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        let now = Instant::now();
+        sessions().lock().unwrap().insert(
+            id,
+            Entry {
+                projects: BTreeSet::new(),
+                connected: now,
+                last_seen: now,
+            },
+        );
+        Session { id }
+    }
+
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Record that the client is alive, and which project it's working on if known
+    pub fn touch(&self, project_name: Option<&str>) {
+        if let Some(entry) = sessions().lock().unwrap().get_mut(&self.id) {
+            entry.last_seen = Instant::now();
+            if let Some(project_name) = project_name {
+                entry.projects.insert(project_name.to_string());
+            }
+        }
+    }
+
+    /// Check if the reaper closed this session for being stale
+    pub fn is_reaped(&self) -> bool {
+        !sessions().lock().unwrap().contains_key(&self.id)
+    }
+
+    /// Check if an event was caused by this session, so it doesn't need telling
+    pub fn caused(&self, event: &Value) -> bool {
+        event["session"] == json!(self.id)
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        sessions().lock().unwrap().remove(&self.id);
+    }
+}
+
+/// Every connected client
+pub fn list() -> Vec<SessionInfo> {
+    let mut sessions = sessions()
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|(id, entry)| SessionInfo {
+            id: *id,
+            projects: entry.projects.iter().cloned().collect(),
+            connected_secs: entry.connected.elapsed().as_secs(),
+            idle_secs: entry.last_seen.elapsed().as_secs(),
+        })
+        .collect::<Vec<_>>();
+    sessions.sort_by_key(|session| session.id);
+    sessions
+}
+
+/// Find the project a raw message is about, wherever its command put `project_name`
+pub fn project_of(message: &Value) -> Option<&str> {
+    fn find(value: &Value, depth: usize) -> Option<&str> {
+        let object = value.as_object()?;
+        if let Some(name) = object.get("project_name").and_then(|n| n.as_str()) {
+            return Some(name).filter(|name| !name.is_empty());
+        }
+        if depth == 0 {
+            return None;
+        }
+        object.values().find_map(|value| find(value, depth - 1))
+    }
+    find(message, 2)
+}
+
+/// Tell every other client that a project changed under them
+pub fn project_changed(project_name: &str, operation: &str, session: Option<u64>) {
+    broadcast(json!({
+        "type": "project-changed",
+        "project_name": project_name,
+        "operation": operation,
+        "session": session,
+    }));
+}
+
+/// Periodically forget sessions that stopped responding
+pub fn start_reaper() {
+    spawn(|| loop {
+        sleep(PING_EVERY);
+        sessions().lock().unwrap().retain(|id, entry| {
+            let stale = entry.last_seen.elapsed() > STALE_AFTER;
+            if stale {
+                debug!(session = id, "reaping stale session");
+            }
+            !stale
+        });
+    });
+}
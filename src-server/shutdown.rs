@@ -0,0 +1,91 @@
+use std::fs;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Condvar, Mutex};
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use tracing::{info, warn};
+
+use crate::auth;
+use crate::diff;
+use crate::git;
+use crate::i18n::message;
+use crate::ipc;
+use crate::registry::registry;
+
+pub const PID_PATH: &str = "projects/.server.pid";
+
+/// Files that only make sense while the server is running
+const RUNTIME_FILES: [&str; 3] = [PID_PATH, "projects/.server.json", auth::TOKEN_PATH];
+
+/// How long to wait for in-flight operations before giving up on them
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(120);
+
+static SHUTTING_DOWN: AtomicBool = AtomicBool::new(false);
+static IN_FLIGHT: (Mutex<usize>, Condvar) = (Mutex::new(0), Condvar::new());
+
+/// Held while a state-changing operation runs, so shutdown waits for it to finish
+pub struct Operation;
+
+impl Drop for Operation {
+    fn drop(&mut self) {
+        let (count, finished) = &IN_FLIGHT;
+        *count.lock().unwrap() -= 1;
+        finished.notify_all();
+    }
+}
+
+/// Register a state-changing operation, refusing new ones once shutdown has started
+pub fn begin_operation() -> Result<Operation> {
+    let mut count = IN_FLIGHT.0.lock().unwrap();
+    if SHUTTING_DOWN.load(Ordering::SeqCst) {
+        return Err(anyhow!(message("error-shutting-down", &[])));
+    }
+    *count += 1;
+    Ok(Operation)
+}
+
+pub fn is_shutting_down() -> bool {
+    SHUTTING_DOWN.load(Ordering::SeqCst)
+}
+
+/// Write the PID file and stop cleanly on SIGINT or SIGTERM
+pub fn install() -> Result<()> {
+    fs::write(PID_PATH, std::process::id().to_string())?;
+    ctrlc::set_handler(shutdown)?;
+    Ok(())
+}
+
+/// Let in-flight operations finish, then release everything the server holds and exit
+fn shutdown() {
+    if SHUTTING_DOWN.swap(true, Ordering::SeqCst) {
+        // a second Ctrl+C while waiting means stop now
+        std::process::exit(130);
+    }
+
+    let (count, finished) = &IN_FLIGHT;
+    let count = count.lock().unwrap();
+    if *count > 0 {
+        info!("waiting for {} operation(s) to finish", *count);
+    }
+    let (count, timeout) = finished
+        .wait_timeout_while(count, DRAIN_TIMEOUT, |count| *count > 0)
+        .unwrap();
+    if timeout.timed_out() {
+        warn!("gave up waiting for {} operation(s)", *count);
+    }
+
+    for project in registry().lock().unwrap().projects() {
+        project.unwatch();
+    }
+    git::clear_revision_cache();
+    // parsed scripts spilled to disk are in a temporary workspace, removed with the cache
+    diff::clear_script_cache();
+    ipc::remove();
+    for file in RUNTIME_FILES {
+        let _ = fs::remove_file(file);
+    }
+
+    info!("stopped");
+    std::process::exit(0);
+}
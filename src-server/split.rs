@@ -0,0 +1,324 @@
+//! Storing project.json in a repository as one file per sprite
+//!
+//! In split storage, a repository tracks `stage.json` and `sprites/<name>.json` instead of
+//! project.json, so Git's history, blame and merges work on one sprite at a time. The stage file
+//! is project.json with only the stage left in `targets`, plus a `sprites` list of the sprite
+//! files in their original order. project.json is still kept in the working directory, ignored,
+//! since everything else reads it from there.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use anyhow::{anyhow, Context, Result};
+use serde_json::Value;
+
+use crate::git;
+use crate::gitignore;
+use crate::normalize::normalize;
+
+/// The stage and everything about the project outside its sprites
+pub const STAGE_FILE: &str = "stage.json";
+
+/// Where each sprite's file goes
+pub const SPRITES_DIR: &str = "sprites";
+
+/// Key in the stage file listing sprite files, which isn't used by Scratch
+const SPRITES_KEY: &str = "sprites";
+
+/// Check if a project is stored one file per sprite
+pub fn is_enabled(cwd: &Path) -> bool {
+    cwd.join(STAGE_FILE).exists()
+}
+
+/// Split a project.json into the files it's stored as, by path from the repository's root
+///
+/// Fails if the files wouldn't join back into the same project, so nothing is ever stored in a
+/// way that can't be reassembled
+pub fn split(json: &[u8]) -> Result<BTreeMap<String, Vec<u8>>> {
+    let files = split_unchecked(json)?;
+
+    let joined = join(&files[STAGE_FILE], |path| {
+        files.get(path).cloned().context("sprite file is missing")
+    })?;
+    if serde_json::from_slice::<Value>(&joined)?
+        != serde_json::from_slice::<Value>(&normalize(json)?)?
+    {
+        return Err(anyhow!("split project doesn't join back together the same"));
+    }
+
+    Ok(files)
+}
+
+fn split_unchecked(json: &[u8]) -> Result<BTreeMap<String, Vec<u8>>> {
+    let mut project = serde_json::from_slice::<Value>(json)?;
+    let targets = project
+        .get_mut("targets")
+        .and_then(Value::as_array_mut)
+        .map(std::mem::take)
+        .context("project has no targets")?;
+
+    let mut files = BTreeMap::new();
+    let mut stage = vec![];
+    let mut sprite_files = vec![];
+    for target in targets {
+        if target["isStage"] == true {
+            stage.push(target);
+            continue;
+        }
+
+        let name = file_name(target["name"].as_str().unwrap_or("sprite"));
+        // names only collide once unsafe characters are replaced, or on case-insensitive
+        // file systems
+        let path = (1..)
+            .map(|n| match n {
+                1 => format!("{SPRITES_DIR}/{name}.json"),
+                n => format!("{SPRITES_DIR}/{name} {n}.json"),
+            })
+            .find(|path| {
+                !files
+                    .keys()
+                    .any(|taken: &String| taken.eq_ignore_ascii_case(path))
+            })
+            .unwrap();
+        files.insert(path.clone(), normalize(&serde_json::to_vec(&target)?)?);
+        sprite_files.push(Value::from(path));
+    }
+
+    let object = project.as_object_mut().context("project isn't an object")?;
+    object.insert("targets".to_string(), Value::from(stage));
+    object.insert(SPRITES_KEY.to_string(), Value::from(sprite_files));
+    files.insert(
+        STAGE_FILE.to_string(),
+        normalize(&serde_json::to_vec(&project)?)?,
+    );
+
+    Ok(files)
+}
+
+/// Put a project.json back together from its stage file, reading sprite files with `read`
+pub fn join(stage: &[u8], read: impl Fn(&str) -> Result<Vec<u8>>) -> Result<Vec<u8>> {
+    let mut project = serde_json::from_slice::<Value>(stage).context("failed to parse stage")?;
+    let object = project.as_object_mut().context("stage isn't an object")?;
+    let sprite_files = object.remove(SPRITES_KEY).unwrap_or_default();
+
+    let mut targets = match object.remove("targets") {
+        Some(Value::Array(targets)) => targets,
+        _ => vec![],
+    };
+    for path in sprite_files.as_array().into_iter().flatten() {
+        let path = path.as_str().context("sprite file isn't a path")?;
+        let sprite = read(path).with_context(|| format!("failed to read {path}"))?;
+        targets.push(
+            serde_json::from_slice(&sprite).with_context(|| format!("failed to parse {path}"))?,
+        );
+    }
+    object.insert("targets".to_string(), Value::from(targets));
+
+    normalize(&serde_json::to_vec(&project)?)
+}
+
+/// Write the working directory's project.json out as split files, removing sprites that are gone
+pub fn write(cwd: &Path) -> Result<()> {
+    let files = split(&fs::read(cwd.join("project.json"))?)?;
+
+    fs::create_dir_all(cwd.join(SPRITES_DIR))?;
+    for entry in fs::read_dir(cwd.join(SPRITES_DIR))?.filter_map(|entry| entry.ok()) {
+        let path = format!("{SPRITES_DIR}/{}", entry.file_name().to_string_lossy());
+        if path.ends_with(".json") && !files.contains_key(&path) {
+            fs::remove_file(entry.path())?;
+        }
+    }
+    for (path, contents) in files {
+        // unchanged files are left alone so their modification times don't change
+        if fs::read(cwd.join(&path)).ok().as_ref() != Some(&contents) {
+            fs::write(cwd.join(&path), contents)?;
+        }
+    }
+    Ok(())
+}
+
+/// Rewrite the working directory's project.json from its split files, after Git changed them
+pub fn restore(cwd: &Path) -> Result<()> {
+    let json = join(&fs::read(cwd.join(STAGE_FILE))?, |path| {
+        Ok(fs::read(cwd.join(path))?)
+    })?;
+    fs::write(cwd.join("project.json"), json)?;
+    Ok(())
+}
+
+/// Put a project.json together as it was in a revision
+pub fn show_revision(cwd: &Path, revision: &str) -> Result<String> {
+    let cwd = &cwd.to_path_buf();
+    let json = join(&git::show_file(cwd, revision, STAGE_FILE)?, |path| {
+        git::show_file(cwd, revision, path)
+    })?;
+    Ok(String::from_utf8(json)?)
+}
+
+/// Start storing a project one file per sprite, leaving project.json untracked
+///
+/// Earlier commits keep their project.json, which is still read as usual
+pub fn enable(cwd: &Path) -> Result<()> {
+    let cwd = &cwd.to_path_buf();
+    write(cwd)?;
+    gitignore::add_pattern(cwd, "/project.json")?;
+
+    let args = vec![
+        "rm",
+        "--cached",
+        "--quiet",
+        "--ignore-unmatch",
+        "project.json",
+    ];
+    if !git::run(args, Some(cwd)).status()?.success() {
+        return Err(anyhow!("failed to untrack project.json"));
+    }
+    Ok(())
+}
+
+/// Make a sprite's name safe to use as a file name on every platform
+fn file_name(name: &str) -> String {
+    let name = name
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect::<String>();
+    let name = name.trim().trim_end_matches('.');
+    match name {
+        "" => "sprite".to_string(),
+        name if name.starts_with('.') => format!("_{name}"),
+        name => name.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::temp::TempWorkspace;
+
+    fn project() -> Vec<u8> {
+        let sprite = |name: &str| {
+            json!({
+                "isStage": false,
+                "name": name,
+                "blocks": {
+                    "a": {"opcode": "motion_movesteps", "next": null, "parent": null,
+                          "inputs": {"STEPS": [1, [4, "10"]]}, "fields": {},
+                          "topLevel": true, "x": 0.1, "y": 2}
+                },
+                "costumes": [],
+                "sounds": [],
+                "x": 1.5
+            })
+        };
+        let project = json!({
+            "targets": [
+                {"isStage": true, "name": "Stage", "variables": {"v": ["my variable", 0]}},
+                // these only differ in characters that can't be in file names, or by case
+                sprite("Cat/1"),
+                sprite("Cat?1"),
+                sprite("cat_1"),
+                sprite("🐱 Cat"),
+                sprite(".hidden"),
+                sprite(""),
+                sprite("Stage"),
+                sprite("a"),
+            ],
+            "monitors": [],
+            "extensions": ["pen"],
+            "meta": {"semver": "3.0.0", "vm": "2.3.0", "agent": "test"}
+        });
+        serde_json::to_vec(&project).unwrap()
+    }
+
+    #[test]
+    fn split_joins_back_into_the_same_project() {
+        let json = project();
+        let files = split(&json).unwrap();
+        let joined = join(&files[STAGE_FILE], |path| Ok(files[path].clone())).unwrap();
+        assert_eq!(
+            String::from_utf8(joined).unwrap(),
+            String::from_utf8(normalize(&json).unwrap()).unwrap()
+        );
+    }
+
+    #[test]
+    fn split_gives_each_sprite_its_own_file() {
+        let files = split(&project()).unwrap();
+        let paths = files.keys().map(String::as_str).collect::<Vec<_>>();
+        assert_eq!(
+            paths,
+            [
+                "sprites/Cat_1 2.json",
+                "sprites/Cat_1.json",
+                "sprites/Stage.json",
+                "sprites/_.hidden.json",
+                "sprites/a.json",
+                "sprites/cat_1 3.json",
+                "sprites/sprite.json",
+                "sprites/🐱 Cat.json",
+                "stage.json",
+            ]
+        );
+    }
+
+    #[test]
+    fn split_refuses_projects_it_cant_join_back() {
+        // sprites are put back after the stage, and the list of sprite files would replace this
+        let sprite_first =
+            json!({"targets": [{"name": "Cat"}, {"isStage": true, "name": "Stage"}]});
+        let sprites_key = json!({"targets": [{"isStage": true, "name": "Stage"}], "sprites": 1});
+        for project in [sprite_first, sprites_key] {
+            assert!(split(&serde_json::to_vec(&project).unwrap()).is_err());
+        }
+    }
+
+    #[test]
+    fn written_files_restore_the_same_project() {
+        let workspace = TempWorkspace::new("split-test").unwrap();
+        let cwd = workspace.path();
+        let json = project();
+        fs::write(cwd.join("project.json"), &json).unwrap();
+        write(cwd).unwrap();
+
+        // a sprite that's gone from project.json has its file removed
+        fs::write(cwd.join(SPRITES_DIR).join("Removed.json"), "{}").unwrap();
+        write(cwd).unwrap();
+        assert!(!cwd.join(SPRITES_DIR).join("Removed.json").exists());
+
+        fs::remove_file(cwd.join("project.json")).unwrap();
+        restore(cwd).unwrap();
+        assert_eq!(
+            fs::read(cwd.join("project.json")).unwrap(),
+            normalize(&json).unwrap()
+        );
+    }
+
+    #[test]
+    fn committed_files_show_the_same_project() {
+        let workspace = TempWorkspace::new("split-test").unwrap();
+        let cwd = workspace.path();
+        let json = project();
+        fs::write(cwd.join("project.json"), &json).unwrap();
+        write(cwd).unwrap();
+
+        let git = |args: Vec<&str>| git::run(args, Some(cwd)).status().unwrap().success();
+        assert!(git(vec!["init", "--quiet"]));
+        assert!(git(vec!["add", STAGE_FILE, SPRITES_DIR]));
+        let identity = ["-c", "user.name=test", "-c", "user.email=test@example.com"];
+        assert!(git(
+            [&identity[..], &["commit", "--quiet", "-m", "split"]].concat()
+        ));
+
+        assert_eq!(
+            show_revision(cwd, "HEAD").unwrap().as_bytes(),
+            normalize(&json).unwrap()
+        );
+    }
+}
@@ -0,0 +1,79 @@
+use serde::Serialize;
+
+use crate::cache::CacheStats;
+use crate::config::project_config;
+use crate::diff;
+use crate::discovery::{self, ServerAddress};
+use crate::git;
+use crate::registry::registry;
+use crate::sessions::{self, SessionInfo};
+
+/// Represents a configured project and what the server is doing with it
+#[derive(Debug, Serialize)]
+pub struct ProjectStatus {
+    pub name: String,
+    /// Whether the project has been used since the server started
+    pub loaded: bool,
+    /// Whether saves to the project's SB3 are being watched
+    pub watching: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GitStatus {
+    /// `None` if Git couldn't be run at all
+    pub version: Option<String>,
+    pub lfs: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CachesStatus {
+    pub revisions: CacheStats,
+    /// Sprites' parsed scripts, reused when their blocks haven't changed
+    pub scripts: CacheStats,
+}
+
+/// Represents the server's health, for connection indicators and diagnostics
+#[derive(Debug, Serialize)]
+pub struct Status {
+    pub version: &'static str,
+    pub address: Option<&'static ServerAddress>,
+    pub projects: Vec<ProjectStatus>,
+    pub git: GitStatus,
+    pub caches: CachesStatus,
+    /// Connected WebSocket clients
+    pub sessions: Vec<SessionInfo>,
+}
+
+/// Gather the server's current status
+pub fn status() -> Status {
+    // collect names first so the config lock isn't held while locking the registry
+    let names = project_config().lock().unwrap().project_names();
+    let loaded = registry().lock().unwrap().projects();
+
+    let projects = names
+        .into_iter()
+        .map(|name| {
+            let project = loaded.iter().find(|p| p.name == name);
+            ProjectStatus {
+                loaded: project.is_some(),
+                watching: project.is_some_and(|p| p.is_watching()),
+                name,
+            }
+        })
+        .collect();
+
+    Status {
+        version: env!("CARGO_PKG_VERSION"),
+        address: discovery::address(),
+        projects,
+        git: GitStatus {
+            version: git::version(),
+            lfs: git::lfs_installed(),
+        },
+        caches: CachesStatus {
+            revisions: git::revision_cache_stats(),
+            scripts: diff::script_cache_stats(),
+        },
+        sessions: sessions::list(),
+    }
+}
@@ -0,0 +1,166 @@
+//! Temporary directories for work that shouldn't touch a project, like diffing outside a
+//! repository or checking out another revision
+//!
+//! Every workspace is made in its own folder under [`root`], named after the process that made
+//! it, and is removed when it's dropped. A crash skips that, so the server sweeps up workspaces
+//! whose processes aren't running anymore when it starts. The root can be moved with `temp.root`
+//! for machines where the system's temporary folder is locked down or too small.
+
+use std::fs;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use tracing::{debug, warn};
+
+use crate::config;
+use crate::git;
+
+/// Folder in the temporary root that every workspace goes in, so sweeping never touches
+/// anything else
+const WORKSPACES_DIR: &str = "pixelpioneers";
+
+/// Where workspaces are made, from `temp.root` or the system's temporary folder
+pub fn root() -> PathBuf {
+    let base = config::settings(None)
+        .temp
+        .root
+        .unwrap_or_else(std::env::temp_dir);
+    base.join(WORKSPACES_DIR)
+}
+
+/// A temporary directory, removed with everything in it when dropped
+#[derive(Debug)]
+pub struct TempWorkspace {
+    path: PathBuf,
+}
+
+impl TempWorkspace {
+    /// Make an empty workspace, where `kind` says what it's for, like `diff`
+    pub fn new(kind: &str) -> Result<Self> {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let path = root().join(format!(
+            "{kind}-{}-{}",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        // one left by an earlier process with the same ID is stale
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).with_context(|| format!("failed to make {}", path.display()))?;
+        Ok(TempWorkspace { path })
+    }
+
+    /// Directory the workspace is in
+    pub fn path(&self) -> &PathBuf {
+        &self.path
+    }
+}
+
+impl Drop for TempWorkspace {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_dir_all(&self.path) {
+            warn!("failed to remove {}: {e}", self.path.display());
+        }
+    }
+}
+
+/// The ID of the process that made a workspace, from its name
+fn owner(name: &str) -> Option<u32> {
+    name.rsplit('-').nth(1)?.parse().ok()
+}
+
+/// Check if a process is still running, assuming it is if that can't be told
+#[cfg(unix)]
+fn is_running(pid: u32) -> bool {
+    // this fails for other users' processes too, whose workspaces couldn't be removed anyway
+    Command::new("kill")
+        .args(["-0", &pid.to_string()])
+        .stderr(Stdio::null())
+        .status()
+        .map_or(true, |status| status.success())
+}
+
+/// Check if a process is still running, assuming it is if that can't be told
+#[cfg(windows)]
+fn is_running(pid: u32) -> bool {
+    Command::new("tasklist")
+        .args(["/FI", &format!("PID eq {pid}"), "/FO", "CSV", "/NH"])
+        .output()
+        .map_or(true, |output| {
+            String::from_utf8_lossy(&output.stdout).contains(&format!("\"{pid}\""))
+        })
+}
+
+/// Check if a workspace was left behind: its process is gone, or for a workspace not named after
+/// one, it's been untouched for `stale`
+fn is_abandoned(entry: &fs::DirEntry, stale: Duration) -> bool {
+    let name = entry.file_name().to_string_lossy().to_string();
+    if let Some(pid) = owner(&name) {
+        return pid != std::process::id() && !is_running(pid);
+    }
+    entry
+        .metadata()
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .and_then(|modified| modified.elapsed().ok())
+        .is_some_and(|age| age >= stale)
+}
+
+/// Remove workspaces left behind by processes that aren't running anymore, returning how many
+/// were removed
+///
+/// Workspaces only last as long as an operation, so ones whose process is gone are from crashes.
+/// Worktrees checked out into them are still registered in their projects, so those are pruned
+/// once their workspaces are gone.
+pub fn sweep() -> Result<usize> {
+    let stale = Duration::from_secs(config::settings(None).temp.stale_minutes * 60);
+    let Ok(entries) = fs::read_dir(root()) else {
+        return Ok(0);
+    };
+
+    let mut removed = 0;
+    let mut worktrees = false;
+    for entry in entries.filter_map(|entry| entry.ok()) {
+        if !is_abandoned(&entry, stale) {
+            continue;
+        }
+        let name = entry.file_name().to_string_lossy().to_string();
+        match fs::remove_dir_all(entry.path()) {
+            Ok(()) => {
+                debug!("removed stale workspace {name}");
+                removed += 1;
+                worktrees |= name.starts_with("worktree-");
+            }
+            Err(e) => warn!("failed to remove stale workspace {name}: {e}"),
+        }
+    }
+
+    if worktrees {
+        let projects = config::project_config().lock().unwrap().project_paths();
+        for project in projects.iter().filter(|project| project.exists()) {
+            let _ = git::run(vec!["worktree", "prune"], Some(project)).status();
+        }
+    }
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sweep_only_removes_workspaces_of_processes_that_are_gone() {
+        let ours = TempWorkspace::new("sweep-test").unwrap();
+        // past the largest process ID Linux hands out
+        let gone = root().join("sweep-test-4194999-0");
+        fs::create_dir_all(&gone).unwrap();
+
+        assert!(is_running(std::process::id()));
+        assert!(!is_running(4194999));
+        sweep().unwrap();
+        assert!(ours.path().exists());
+        assert!(!gone.exists());
+    }
+}
@@ -0,0 +1,165 @@
+//! Rendering stage thumbnails of commits for the history view
+//!
+//! A thumbnail is the stage's backdrop with every visible sprite drawn on top, in layer order,
+//! at its position, size and direction. Thumbnails are cached in the repository's Git
+//! directory, named by commit ID, since a commit never changes.
+
+use std::fs;
+use std::path::PathBuf;
+
+use anyhow::{anyhow, Context, Result};
+use resvg::tiny_skia::{Pixmap, PixmapPaint, Transform};
+use resvg::usvg;
+use serde_json::{Map, Value};
+use tracing::warn;
+
+use crate::asset_store::AssetStore;
+use crate::git;
+use crate::mapped::Contents;
+use crate::ops;
+use crate::sb3::{Costume, Project, Target};
+
+const STAGE_WIDTH: u32 = 480;
+const STAGE_HEIGHT: u32 = 360;
+
+/// Thumbnails are drawn at half the stage's size
+const SCALE: f32 = 0.5;
+
+fn cache_dir(cwd: &PathBuf) -> PathBuf {
+    cwd.join(".git").join("scratch-git").join("thumbnails")
+}
+
+/// Get a commit's thumbnail as a PNG, rendering and caching it if it hasn't been yet
+pub fn get(cwd: &PathBuf, revision: &str) -> Result<Vec<u8>> {
+    let commit = git::resolve(cwd, revision)?;
+    let cached = cache_dir(cwd).join(format!("{commit}.png"));
+    if let Ok(png) = fs::read(&cached) {
+        return Ok(png);
+    }
+
+    let project = ops::parse_project(&git::show_revision(cwd, &format!("{commit}:project.json"))?)
+        .context("failed to read project")?;
+    let store = AssetStore::default_store()?;
+    let png = render(&project, |asset| {
+        ops::read_asset(cwd, &commit, asset, &store)
+    })?;
+
+    fs::create_dir_all(cache_dir(cwd))?;
+    fs::write(cached, &png)?;
+    Ok(png)
+}
+
+/// Draw a project's stage as a PNG, reading assets with `read`
+///
+/// Costumes that can't be read or decoded are left out, as are bitmaps other than PNGs
+pub fn render(project: &Project, read: impl Fn(&str) -> Option<Contents>) -> Result<Vec<u8>> {
+    let width = (STAGE_WIDTH as f32 * SCALE) as u32;
+    let height = (STAGE_HEIGHT as f32 * SCALE) as u32;
+    let mut canvas = Pixmap::new(width, height).context("failed to create canvas")?;
+    canvas.fill(resvg::tiny_skia::Color::WHITE);
+
+    let mut targets = project
+        .targets
+        .iter()
+        .filter(|target| target.is_stage || field(&target.extra, "visible") != false)
+        .collect::<Vec<_>>();
+    targets.sort_by_key(|target| field(&target.extra, "layerOrder").as_u64().unwrap_or(0));
+
+    for target in targets {
+        let Some(costume) = current_costume(target) else {
+            continue;
+        };
+        let Ok(path) = costume.path() else {
+            continue;
+        };
+        let Some(contents) = read(&path) else {
+            warn!(asset = %path, "asset missing from thumbnail");
+            continue;
+        };
+        let transform = placement(target, costume);
+        if let Err(e) = draw(&mut canvas, costume, &contents, transform) {
+            warn!(asset = %path, "failed to draw asset in thumbnail: {e}");
+        }
+    }
+
+    canvas
+        .encode_png()
+        .map_err(|e| anyhow!("failed to encode thumbnail: {e}"))
+}
+
+/// A field of a sprite or costume, which is null if it's missing
+fn field<'a>(extra: &'a Map<String, Value>, key: &str) -> &'a Value {
+    extra.get(key).unwrap_or(&Value::Null)
+}
+
+fn current_costume(target: &Target) -> Option<&Costume> {
+    let index = field(&target.extra, "currentCostume").as_u64().unwrap_or(0) as usize;
+    target.costumes.get(index)
+}
+
+/// Where a costume goes on the canvas, from the stage's center with Y going up
+fn placement(target: &Target, costume: &Costume) -> Transform {
+    let number = |key: &str, default: f32| {
+        field(&target.extra, key)
+            .as_f64()
+            .map_or(default, |value| value as f32)
+    };
+    let center = |key: &str| field(&costume.extra, key).as_f64().unwrap_or(0.0) as f32;
+
+    let resolution = field(&costume.extra, "bitmapResolution")
+        .as_f64()
+        .filter(|_| costume.data_format != "svg")
+        .unwrap_or(1.0) as f32;
+    let size = if target.is_stage {
+        1.0
+    } else {
+        number("size", 100.0) / 100.0
+    } / resolution;
+
+    // a direction of 90 is upright
+    let direction = number("direction", 90.0);
+    let (flip, rotation) = match field(&target.extra, "rotationStyle").as_str() {
+        _ if target.is_stage => (1.0, 0.0),
+        Some("left-right") if direction < 0.0 => (-1.0, 0.0),
+        Some("left-right") | Some("don't rotate") => (1.0, 0.0),
+        _ => (1.0, direction - 90.0),
+    };
+
+    let x = STAGE_WIDTH as f32 / 2.0 + number("x", 0.0);
+    let y = STAGE_HEIGHT as f32 / 2.0 - number("y", 0.0);
+    Transform::from_scale(SCALE, SCALE)
+        .pre_translate(x, y)
+        .pre_concat(Transform::from_rotate(rotation))
+        .pre_scale(size * flip, size)
+        .pre_translate(
+            -center("rotationCenterX") * resolution,
+            -center("rotationCenterY") * resolution,
+        )
+}
+
+fn draw(
+    canvas: &mut Pixmap,
+    costume: &Costume,
+    contents: &[u8],
+    transform: Transform,
+) -> Result<()> {
+    match costume.data_format.as_str() {
+        "svg" => {
+            let tree = usvg::Tree::from_data(contents, &usvg::Options::default())?;
+            resvg::render(&tree, transform, &mut canvas.as_mut());
+        }
+        "png" => {
+            let image = Pixmap::decode_png(contents)?;
+            canvas.draw_pixmap(
+                0,
+                0,
+                image.as_ref(),
+                &PixmapPaint::default(),
+                transform,
+                None,
+            );
+        }
+        format => return Err(anyhow!("can't draw {format} costumes")),
+    }
+    Ok(())
+}
@@ -0,0 +1,194 @@
+//! A sprite's history as a graph, for drawing a timeline of it across branches
+//!
+//! Nodes are the commits that changed the sprite, and edges point from each of them to the
+//! nearest commits before it that changed the sprite too, skipping the commits in between that
+//! left it alone. A merge is only a node when the sprite ends up different from every side, so
+//! branches that changed it show up splitting off and joining back in, and branches that didn't
+//! aren't drawn at all.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use anyhow::Result;
+use itertools::Itertools;
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::git;
+use crate::ops::LogAuthor;
+
+/// Represents a commit that changed a sprite
+#[derive(Debug, Clone, Serialize)]
+pub struct TimelineNode {
+    pub commit: String,
+    pub subject: String,
+    pub author: LogAuthor,
+    /// Local branches whose latest change to the sprite is this commit
+    pub branches: Vec<String>,
+    /// Whether the sprite was deleted, rather than added or changed
+    pub deleted: bool,
+}
+
+/// Represents a commit's nearest earlier change to the same sprite
+#[derive(Debug, Clone, Serialize)]
+pub struct TimelineEdge {
+    pub child: String,
+    pub parent: String,
+}
+
+/// Represents a sprite's changes across every local branch
+#[derive(Debug, Clone, Serialize)]
+pub struct Timeline {
+    pub sprite: String,
+    /// Commits that changed the sprite, with parents always before their children
+    pub nodes: Vec<TimelineNode>,
+    pub edges: Vec<TimelineEdge>,
+}
+
+/// Identify a sprite as it was in a commit by the hash of its JSON, or `None` if the commit
+/// has no sprite by that name
+fn version(pth: &PathBuf, commit: &str, sprite: &str) -> Option<String> {
+    let project = git::parse_revision::<Value>(pth, &format!("{commit}:project.json")).ok()?;
+    let target = project["targets"]
+        .as_array()?
+        .iter()
+        .find(|target| target["name"] == sprite)?;
+    Some(format!(
+        "{:x}",
+        md5::compute(serde_json::to_vec(target).ok()?)
+    ))
+}
+
+/// Represents a commit as listed by [`commits`]
+struct Commit {
+    id: String,
+    parents: Vec<String>,
+    subject: String,
+    author: LogAuthor,
+}
+
+/// List the commits on every local branch, with parents always before their children
+fn commits(pth: &PathBuf) -> Result<Vec<Commit>> {
+    let output = git::run(
+        vec![
+            "log",
+            "--branches",
+            "--topo-order",
+            "--reverse",
+            "--pretty=format:%H%x1f%P%x1f%s%x1f%aN%x1f%aE%x1f%aD%x1e",
+        ],
+        Some(pth),
+    )
+    .output()?;
+
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .split('\x1e')
+        .filter_map(|record| {
+            let fields = record
+                .trim_start_matches('\n')
+                .split('\x1f')
+                .collect::<Vec<_>>();
+            let [id, parents, subject, name, email, date] = fields.as_slice() else {
+                return None;
+            };
+            Some(Commit {
+                id: id.to_string(),
+                parents: parents.split_whitespace().map(str::to_string).collect(),
+                subject: subject.to_string(),
+                author: LogAuthor {
+                    name: name.to_string(),
+                    email: email.to_string(),
+                    date: date.to_string(),
+                },
+            })
+        })
+        .collect())
+}
+
+/// List the local branches with the commit each one is at
+fn branches(pth: &PathBuf) -> Result<Vec<(String, String)>> {
+    let output = git::run(
+        vec![
+            "for-each-ref",
+            "--format=%(objectname) %(refname:short)",
+            "refs/heads",
+        ],
+        Some(pth),
+    )
+    .output()?;
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| line.split_once(' '))
+        .map(|(commit, branch)| (commit.to_string(), branch.to_string()))
+        .collect())
+}
+
+/// Build the graph of a sprite's changes across every local branch
+///
+/// The stage is named `Stage`, like in project.json. Sprites are matched by name, so a renamed
+/// sprite starts a new timeline.
+pub fn timeline(pth: &PathBuf, sprite: &str) -> Result<Timeline> {
+    let mut nodes = vec![];
+    let mut edges = vec![];
+    // each commit's version of the sprite, and the nearest commits up to it that changed it
+    let mut versions = HashMap::<String, Option<String>>::new();
+    let mut nearest = HashMap::<String, Vec<String>>::new();
+
+    for commit in commits(pth)? {
+        let current = version(pth, &commit.id, sprite);
+        // parents can be missing from shallow clones
+        let parent_version = |parent: &String| versions.get(parent).cloned().flatten();
+        let unchanged = match commit.parents.as_slice() {
+            [] => current.is_none().then_some(None),
+            parents => parents
+                .iter()
+                .find(|&parent| parent_version(parent) == current)
+                .map(Some),
+        };
+
+        let reached = match unchanged {
+            Some(parent) => parent
+                .and_then(|parent| nearest.get(parent))
+                .cloned()
+                .unwrap_or_default(),
+            None => {
+                let parents = commit
+                    .parents
+                    .iter()
+                    .flat_map(|parent| nearest.get(parent).into_iter().flatten())
+                    .unique();
+                edges.extend(parents.map(|parent| TimelineEdge {
+                    child: commit.id.clone(),
+                    parent: parent.clone(),
+                }));
+                nodes.push(TimelineNode {
+                    commit: commit.id.clone(),
+                    subject: commit.subject,
+                    author: commit.author,
+                    branches: vec![],
+                    deleted: current.is_none(),
+                });
+                vec![commit.id.clone()]
+            }
+        };
+        versions.insert(commit.id.clone(), current);
+        nearest.insert(commit.id, reached);
+    }
+
+    let index = nodes
+        .iter()
+        .enumerate()
+        .map(|(i, node)| (node.commit.clone(), i))
+        .collect::<HashMap<_, _>>();
+    for (commit, branch) in branches(pth)? {
+        for node in nearest.get(&commit).into_iter().flatten() {
+            nodes[index[node]].branches.push(branch.clone());
+        }
+    }
+
+    Ok(Timeline {
+        sprite: sprite.to_string(),
+        nodes,
+        edges,
+    })
+}
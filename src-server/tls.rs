@@ -0,0 +1,46 @@
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::{anyhow, Result};
+use rustls::ServerConfig;
+
+const SELF_SIGNED_CERT: &str = "projects/tls/cert.pem";
+const SELF_SIGNED_KEY: &str = "projects/tls/key.pem";
+
+/// Create a self-signed certificate for localhost, reusing the one from a previous run
+///
+/// Browsers need to be told to trust it once, by visiting https://localhost:8000 once
+fn self_signed() -> Result<(&'static Path, &'static Path)> {
+    let (cert, key) = (Path::new(SELF_SIGNED_CERT), Path::new(SELF_SIGNED_KEY));
+    if cert.exists() && key.exists() {
+        return Ok((cert, key));
+    }
+
+    let generated =
+        rcgen::generate_simple_self_signed(vec!["localhost".to_string(), "127.0.0.1".to_string()])?;
+    fs::create_dir_all(cert.parent().unwrap())?;
+    fs::write(cert, generated.serialize_pem()?)?;
+    fs::write(key, generated.serialize_private_key_pem())?;
+    Ok((cert, key))
+}
+
+/// Load a TLS server config from PEM files, or a self-signed certificate if none are given
+pub fn server_config(cert_and_key: Option<(&Path, &Path)>) -> Result<Arc<ServerConfig>> {
+    let (cert, key) = match cert_and_key {
+        Some(paths) => paths,
+        None => self_signed()?,
+    };
+
+    let certs = rustls_pemfile::certs(&mut BufReader::new(File::open(cert)?))
+        .collect::<Result<Vec<_>, _>>()?;
+    let key = rustls_pemfile::private_key(&mut BufReader::new(File::open(key)?))?
+        .ok_or(anyhow!("no private key found in {}", key.display()))?;
+
+    Ok(Arc::new(
+        ServerConfig::builder()
+            .with_no_client_auth()
+            .with_single_cert(certs, key)?,
+    ))
+}
@@ -0,0 +1,481 @@
+//! Checks a project.json against the parts of the SB3 format the editor relies on, so
+//! broken saves are caught before they're diffed or committed
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::path::Path;
+
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+use crate::extensions::CORE_CATEGORIES;
+use crate::formats;
+use crate::sb3::{self, Project};
+
+/// Represents a single problem found in a project.json
+#[derive(Debug, Serialize)]
+pub struct Violation {
+    /// Location of the problem, e.g. `targets[1].blocks.abc`
+    pub path: String,
+    pub message: String,
+}
+
+impl Violation {
+    pub(crate) fn new(path: impl Into<String>, message: impl Into<String>) -> Self {
+        Violation {
+            path: path.into(),
+            message: message.into(),
+        }
+    }
+}
+
+/// Returned when a project.json has problems, so callers can pass them on as they are
+#[derive(Debug)]
+pub struct Invalid(pub Vec<Violation>);
+
+impl fmt::Display for Invalid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "project.json is broken")?;
+        if let Some(first) = self.0.first() {
+            write!(f, ": {}: {}", first.path, first.message)?;
+        }
+        if self.0.len() > 1 {
+            write!(f, " (and {} more)", self.0.len() - 1)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Invalid {}
+
+/// Options for stricter checks
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Options {
+    /// Report opcodes that aren't from Scratch or one of the project's extensions
+    pub check_opcodes: bool,
+}
+
+/// Validate a project.json string, returning every problem found
+pub fn validate_str(contents: &str) -> Vec<Violation> {
+    validate_str_with(contents, Options::default())
+}
+
+/// Validate a project.json string with stricter checks, returning every problem found
+pub fn validate_str_with(contents: &str, options: Options) -> Vec<Violation> {
+    match serde_json::from_str::<Value>(contents) {
+        Ok(project) => validate_with(&project, options),
+        Err(e) => vec![Violation::new("", format!("invalid JSON: {e}"))],
+    }
+}
+
+/// Validate a project.json string, failing with every problem found
+pub fn check(contents: &str) -> Result<(), Invalid> {
+    let violations = validate_str(contents);
+    if violations.is_empty() {
+        Ok(())
+    } else {
+        Err(Invalid(violations))
+    }
+}
+
+/// Re-hash every asset a project uses, returning those that are missing or don't match the
+/// MD5 hash project.json names them by
+pub fn verify_assets(dir: &Path, project: &Project) -> Vec<Violation> {
+    let mut violations = vec![];
+    // assets shared by several sprites only need hashing once
+    let mut checked: HashMap<String, Option<String>> = HashMap::new();
+
+    for (i, target) in project.targets.iter().enumerate() {
+        let costumes = target
+            .costumes
+            .iter()
+            .enumerate()
+            .map(|(j, costume)| (format!("targets[{i}].costumes[{j}]"), costume.path()));
+        let sounds = target
+            .sounds
+            .iter()
+            .enumerate()
+            .map(|(j, sound)| (format!("targets[{i}].sounds[{j}]"), sound.path()));
+
+        for (path, asset) in costumes.chain(sounds) {
+            let asset = match asset {
+                Ok(asset) => asset,
+                Err(e) => {
+                    violations.push(Violation::new(path, e.to_string()));
+                    continue;
+                }
+            };
+            let problem = checked.entry(asset.clone()).or_insert_with(|| {
+                match sb3::is_correctly_named_file(&asset, &dir.join(&asset)) {
+                    Ok(true) => None,
+                    Ok(false) => Some(format!("{asset} doesn't match its MD5 hash")),
+                    Err(_) => Some(format!("{asset} is missing")),
+                }
+            });
+            if let Some(problem) = problem {
+                violations.push(Violation::new(path, problem.clone()));
+            }
+        }
+    }
+    violations
+}
+
+/// Validate a parsed project.json, returning every problem found
+pub fn validate(project: &Value) -> Vec<Violation> {
+    validate_with(project, Options::default())
+}
+
+/// Validate a parsed project.json with stricter checks, returning every problem found
+pub fn validate_with(project: &Value, options: Options) -> Vec<Violation> {
+    let mut violations = vec![];
+
+    let Some(targets) = project["targets"].as_array() else {
+        violations.push(Violation::new("targets", "missing targets array"));
+        return violations;
+    };
+
+    let extensions = match &project["extensions"] {
+        Value::Array(extensions) => extensions.iter().filter_map(|e| e.as_str()).collect(),
+        Value::Null => vec![],
+        _ => {
+            violations.push(Violation::new("extensions", "must be an array"));
+            vec![]
+        }
+    };
+    let categories = options.check_opcodes.then(|| {
+        let format = formats::detect(&project["meta"]);
+        CORE_CATEGORIES
+            .into_iter()
+            .chain(format.categories().iter().copied())
+            .chain(extensions)
+            .collect::<HashSet<_>>()
+    });
+
+    for key in ["monitors", "extensions"] {
+        if !project[key].is_array() && !project[key].is_null() {
+            violations.push(Violation::new(key, "must be an array"));
+        }
+    }
+    if !project["meta"]["semver"].is_string() {
+        violations.push(Violation::new("meta.semver", "missing format version"));
+    }
+
+    match targets.iter().filter(|t| t["isStage"] == true).count() {
+        1 => {}
+        0 => violations.push(Violation::new("targets", "project has no stage")),
+        _ => violations.push(Violation::new("targets", "project has more than one stage")),
+    }
+
+    for (i, target) in targets.iter().enumerate() {
+        let path = format!("targets[{i}]");
+        if !target.is_object() {
+            violations.push(Violation::new(path, "target must be an object"));
+            continue;
+        }
+        if !target["name"].is_string() {
+            violations.push(Violation::new(
+                format!("{path}.name"),
+                "missing sprite name",
+            ));
+        }
+        if !target["isStage"].is_boolean() {
+            violations.push(Violation::new(
+                format!("{path}.isStage"),
+                "missing isStage flag",
+            ));
+        }
+        for key in ["variables", "lists", "broadcasts"] {
+            if !target[key].is_object() && !target[key].is_null() {
+                violations.push(Violation::new(format!("{path}.{key}"), "must be an object"));
+            }
+        }
+        if !target["currentCostume"].is_number() && !target["currentCostume"].is_null() {
+            violations.push(Violation::new(
+                format!("{path}.currentCostume"),
+                "must be a number",
+            ));
+        }
+
+        match target["costumes"].as_array() {
+            // the editor can't show a sprite without a costume
+            Some(costumes) if costumes.is_empty() => violations.push(Violation::new(
+                format!("{path}.costumes"),
+                "needs at least one costume",
+            )),
+            Some(costumes) => validate_assets(&path, "costumes", costumes, &mut violations),
+            None => violations.push(Violation::new(
+                format!("{path}.costumes"),
+                "missing costumes",
+            )),
+        }
+        match target["sounds"].as_array() {
+            Some(sounds) => validate_assets(&path, "sounds", sounds, &mut violations),
+            None => violations.push(Violation::new(format!("{path}.sounds"), "missing sounds")),
+        }
+
+        match target["blocks"].as_object() {
+            Some(blocks) => validate_blocks(&path, blocks, categories.as_ref(), &mut violations),
+            None => violations.push(Violation::new(format!("{path}.blocks"), "missing blocks")),
+        }
+    }
+
+    violations
+}
+
+/// Check that every costume or sound says which file holds it
+fn validate_assets(
+    target_path: &str,
+    kind: &str,
+    assets: &[Value],
+    violations: &mut Vec<Violation>,
+) {
+    for (i, asset) in assets.iter().enumerate() {
+        let path = format!("{target_path}.{kind}[{i}]");
+        for key in ["name", "assetId", "dataFormat"] {
+            if !asset[key].is_string() {
+                violations.push(Violation::new(
+                    format!("{path}.{key}"),
+                    format!("missing {key}"),
+                ));
+            }
+        }
+        if !asset["md5ext"].is_string() && !asset["md5ext"].is_null() {
+            violations.push(Violation::new(format!("{path}.md5ext"), "must be a string"));
+        }
+    }
+}
+
+/// Check that every block is well formed and every block reference in a sprite points to a
+/// block that exists
+fn validate_blocks(
+    target_path: &str,
+    blocks: &Map<String, Value>,
+    categories: Option<&HashSet<&str>>,
+    violations: &mut Vec<Violation>,
+) {
+    let ids = blocks.keys().map(|k| k.as_str()).collect::<HashSet<_>>();
+
+    for (id, block) in blocks {
+        // top-level variable and list reporters are stored as arrays
+        if !block.is_object() {
+            continue;
+        }
+        let path = format!("{target_path}.blocks.{id}");
+
+        match block["opcode"].as_str() {
+            None => violations.push(Violation::new(&path, "block has no opcode")),
+            Some(opcode) => {
+                let category = opcode.split('_').next().unwrap_or_default();
+                if categories.is_some_and(|categories| !categories.contains(category)) {
+                    violations.push(Violation::new(
+                        format!("{path}.opcode"),
+                        format!("unknown opcode {opcode}"),
+                    ));
+                }
+            }
+        }
+        for key in ["topLevel", "shadow"] {
+            if !block[key].is_boolean() && !block[key].is_null() {
+                violations.push(Violation::new(
+                    format!("{path}.{key}"),
+                    "must be true or false",
+                ));
+            }
+        }
+        for key in ["inputs", "fields"] {
+            if !block[key].is_object() && !block[key].is_null() {
+                violations.push(Violation::new(format!("{path}.{key}"), "must be an object"));
+            }
+        }
+
+        for key in ["next", "parent"] {
+            if !block[key].is_string() && !block[key].is_null() {
+                violations.push(Violation::new(
+                    format!("{path}.{key}"),
+                    "must be a block ID or null",
+                ));
+            }
+            if let Some(reference) = block[key].as_str() {
+                if !ids.contains(reference) {
+                    violations.push(Violation::new(
+                        format!("{path}.{key}"),
+                        format!("references missing block {reference}"),
+                    ));
+                }
+            }
+        }
+
+        if let Some(inputs) = block["inputs"].as_object() {
+            for (name, input) in inputs {
+                // inputs look like [shadow type, block id or primitive, shadow id?]
+                let Some(input) = input.as_array() else {
+                    continue;
+                };
+                for reference in input.iter().skip(1).filter_map(|v| v.as_str()) {
+                    if !ids.contains(reference) {
+                        violations.push(Violation::new(
+                            format!("{path}.inputs.{name}"),
+                            format!("references missing block {reference}"),
+                        ));
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use serde_json::json;
+
+    use super::*;
+    use crate::temp::TempWorkspace;
+
+    fn costume(md5: &str) -> Value {
+        json!({"name": "costume", "assetId": md5, "md5ext": format!("{md5}.svg"), "dataFormat": "svg"})
+    }
+
+    fn project(blocks: Value) -> Value {
+        let target = |name: &str, blocks: Value| {
+            json!({
+                "isStage": name == "Stage",
+                "name": name,
+                "variables": {},
+                "lists": {},
+                "broadcasts": {},
+                "blocks": blocks,
+                "costumes": [costume("0123456789abcdef0123456789abcdef")],
+                "sounds": [],
+            })
+        };
+        json!({
+            "targets": [target("Stage", json!({})), target("Cat", blocks)],
+            "monitors": [],
+            "extensions": ["pen"],
+            "meta": {"semver": "3.0.0"},
+        })
+    }
+
+    fn paths(violations: &[Violation]) -> Vec<&str> {
+        violations.iter().map(|v| v.path.as_str()).collect()
+    }
+
+    #[test]
+    fn valid_projects_pass() {
+        let blocks = json!({
+            "a": {"opcode": "event_whenflagclicked", "next": "b", "parent": null, "topLevel": true},
+            "b": {
+                "opcode": "pen_clear",
+                "next": null,
+                "parent": "a",
+                "inputs": {"STEPS": [1, [4, "10"]]},
+                "fields": {},
+                "topLevel": false,
+            },
+            // a variable reporter dropped on the workspace
+            "c": [12, "score", "id", 0, 0],
+        });
+        let project = project(blocks);
+        assert!(paths(&validate(&project)).is_empty());
+        let strict = Options {
+            check_opcodes: true,
+        };
+        assert!(paths(&validate_with(&project, strict)).is_empty());
+        assert!(check(&project.to_string()).is_ok());
+    }
+
+    #[test]
+    fn broken_structure_is_reported() {
+        let mut project = project(json!({}));
+        project["targets"][0]["isStage"] = json!(false);
+        project["targets"][1]["costumes"] = json!([]);
+        project["targets"][1]["sounds"] = json!(null);
+        project["targets"][1]["variables"] = json!([]);
+        project["meta"] = json!({});
+        let violations = validate(&project);
+        assert_eq!(
+            paths(&violations),
+            [
+                "meta.semver",
+                "targets",
+                "targets[1].variables",
+                "targets[1].costumes",
+                "targets[1].sounds",
+            ]
+        );
+
+        assert_eq!(paths(&validate(&json!({}))), ["targets"]);
+        let violations = validate_str("{");
+        assert!(violations[0].message.starts_with("invalid JSON"));
+    }
+
+    #[test]
+    fn references_to_missing_blocks_are_reported() {
+        let blocks = json!({
+            "a": {"opcode": "motion_movesteps", "next": "gone", "parent": null, "topLevel": true},
+            "b": {"opcode": "motion_turnright", "inputs": {"DEGREES": [3, "also gone", [4, "15"]]}},
+            "c": {"next": 5},
+        });
+        let violations = validate(&project(blocks));
+        assert_eq!(
+            paths(&violations),
+            [
+                "targets[1].blocks.a.next",
+                "targets[1].blocks.b.inputs.DEGREES",
+                "targets[1].blocks.c",
+                "targets[1].blocks.c.next",
+            ]
+        );
+        assert_eq!(violations[0].message, "references missing block gone");
+    }
+
+    #[test]
+    fn unknown_opcodes_are_only_reported_when_strict() {
+        let blocks = json!({"a": {"opcode": "madeup_block", "topLevel": true}});
+        let project = project(blocks);
+        assert!(validate(&project).is_empty());
+        let strict = Options {
+            check_opcodes: true,
+        };
+        let violations = validate_with(&project, strict);
+        assert_eq!(paths(&violations), ["targets[1].blocks.a.opcode"]);
+    }
+
+    #[test]
+    fn errors_count_the_rest() {
+        let error = check(r#"{"targets": [], "meta": {}}"#).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "project.json is broken: meta.semver: missing format version (and 1 more)"
+        );
+    }
+
+    #[test]
+    fn assets_are_checked_against_their_names() {
+        let workspace = TempWorkspace::new("validate-test").unwrap();
+        let svg = b"<svg/>";
+        let md5 = format!("{:x}", md5::compute(svg));
+        fs::write(workspace.path().join(format!("{md5}.svg")), svg).unwrap();
+        let wrong = "0123456789abcdef0123456789abcdef";
+        fs::write(workspace.path().join(format!("{wrong}.svg")), svg).unwrap();
+
+        let mut project = project(json!({}));
+        project["targets"][0]["costumes"] = json!([costume(&md5)]);
+        let missing = "fedcba9876543210fedcba9876543210";
+        let costumes = [costume(wrong), costume(missing), costume("../project")];
+        project["targets"][1]["costumes"] = json!(costumes);
+        let project = serde_json::from_value(project).unwrap();
+        let violations = verify_assets(workspace.path(), &project);
+        let messages = violations.iter().map(|v| v.message.as_str());
+        assert_eq!(
+            messages.collect::<Vec<_>>(),
+            [
+                format!("{wrong}.svg doesn't match its MD5 hash"),
+                format!("{missing}.svg is missing"),
+                "../project.svg isn't an asset file name".to_string(),
+            ]
+        );
+    }
+}
@@ -0,0 +1,212 @@
+//! Diffing in the browser, so the editor extension can preview changes without a local server
+//!
+//! Built for `wasm32-unknown-unknown` with `--no-default-features --features wasm`, e.g. with
+//! `wasm-pack build --target web -- --no-default-features --features wasm`. There's no Git to
+//! diff scripts with here, so changed lines are counted in-process, in order like Git would.
+//! Assets and extensions are compared the same way as for a commit.
+
+use std::collections::{BTreeMap, HashMap};
+
+use serde::Serialize;
+use wasm_bindgen::prelude::*;
+
+use crate::diff::compare::{self, AssetChange, AssetShare, ExtensionChange};
+use crate::diff::parse_script::{parse_sprite, Sprite};
+use crate::error::{Error, Result};
+use crate::sb3::{self, Project, Target};
+
+/// Represents the changes to a sprite (or the stage) between two versions of a project
+#[derive(Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SpriteChanges {
+    pub sprite: String,
+    pub on_stage: bool,
+    pub blocks_added: usize,
+    pub blocks_removed: usize,
+    /// Costumes and sounds, by name and format like `cat.svg`
+    pub assets_added: Vec<String>,
+    pub assets_removed: Vec<String>,
+    /// Costumes and sounds whose file changed while they kept their name
+    pub assets_modified: Vec<String>,
+    pub variables_added: Vec<String>,
+    pub variables_removed: Vec<String>,
+}
+
+/// Represents the changes between two versions of a project
+#[derive(Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProjectChanges {
+    /// Every sprite that changed
+    pub sprites: Vec<SpriteChanges>,
+    pub extensions: Vec<ExtensionChange>,
+}
+
+/// Diff two project.json files, returning a JSON [`ProjectChanges`]
+#[wasm_bindgen(js_name = diffProjects)]
+pub fn diff_projects(old: &str, new: &str) -> Result<String, JsError> {
+    let old = sb3::parse(old, "old project.json")?;
+    let new = sb3::parse(new, "new project.json")?;
+    Ok(serde_json::to_string(&changes(&old, &new)?)?)
+}
+
+/// Turn each sprite's scripts in a project.json into text, returned as a JSON object keyed by
+/// sprite name, for showing a script diff
+#[wasm_bindgen(js_name = scriptText)]
+pub fn script_text(project: &str) -> Result<String, JsError> {
+    let project = sb3::parse(project, "project.json")?;
+    let scripts = project
+        .targets
+        .iter()
+        .map(|target| Ok((target.name.clone(), parse_sprite(Sprite::of(target))?)))
+        .collect::<Result<BTreeMap<_, _>>>()?;
+    Ok(serde_json::to_string(&scripts)?)
+}
+
+/// Compare every sprite in two projects, matching sprites by name
+///
+/// Assets are compared by file like in a commit, so a costume is only changed if its file is
+pub fn changes(old: &Project, new: &Project) -> Result<ProjectChanges> {
+    let mut sprites = new
+        .targets
+        .iter()
+        .map(|target| &target.name)
+        .collect::<Vec<_>>();
+    let removed = old.targets.iter().map(|target| &target.name);
+    sprites.extend(removed.filter(|name| new.target(name).is_none()));
+
+    let assets = compare::asset_changes(old, new).map_err(|e| Error::MalformedProject {
+        sprite: "project".into(),
+        field: format!("asset path ({e})"),
+    })?;
+    let named = |change: &AssetChange| format!("{}.{}", change.name, change.ext);
+    // shared assets are listed once for every sprite they're in
+    let shared = |shares: &[AssetShare]| {
+        shares
+            .iter()
+            .flat_map(|share| {
+                share
+                    .sprites
+                    .iter()
+                    .map(|sprite| (sprite.clone(), named(&share.change)))
+            })
+            .collect::<Vec<_>>()
+    };
+    let by_sprite = |changes: Vec<(String, String)>| {
+        let mut sprites = HashMap::<String, Vec<String>>::new();
+        for (sprite, asset) in changes {
+            sprites.entry(sprite).or_default().push(asset);
+        }
+        sprites.values_mut().for_each(|assets| assets.sort());
+        sprites
+    };
+    let mut added = by_sprite(
+        assets
+            .added
+            .iter()
+            .map(|change| (change.sprite.clone(), named(change)))
+            .chain(shared(&assets.shared_added))
+            .chain(
+                assets
+                    .moved
+                    .iter()
+                    .map(|m| (m.change.sprite.clone(), named(&m.change))),
+            )
+            .collect(),
+    );
+    let mut removed = by_sprite(
+        assets
+            .removed
+            .iter()
+            .map(|change| (change.sprite.clone(), named(change)))
+            .chain(shared(&assets.shared_removed))
+            .chain(
+                assets
+                    .moved
+                    .iter()
+                    .map(|m| (m.from_sprite.clone(), named(&m.change))),
+            )
+            .collect(),
+    );
+    let mut modified = by_sprite(
+        assets
+            .merged
+            .iter()
+            .chain(assets.converted.iter().map(|c| &c.change))
+            .map(|change| (change.sprite.clone(), named(change)))
+            .collect(),
+    );
+
+    let mut changes = vec![];
+    for sprite in sprites {
+        let (before, after) = (old.target(sprite), new.target(sprite));
+        let on_stage = before.or(after).is_some_and(|target| target.is_stage);
+        let (blocks_added, blocks_removed) =
+            compare::changed_lines(&script(before)?, &script(after)?);
+        // assets are reported by the name shown in the editor, which marks the stage
+        let name = before
+            .or(after)
+            .map(Target::display_name)
+            .unwrap_or_default();
+        let (variables_added, variables_removed) =
+            added_removed(variables(before), variables(after));
+
+        let change = SpriteChanges {
+            sprite: sprite.clone(),
+            on_stage,
+            blocks_added,
+            blocks_removed,
+            assets_added: added.remove(&name).unwrap_or_default(),
+            assets_removed: removed.remove(&name).unwrap_or_default(),
+            assets_modified: modified.remove(&name).unwrap_or_default(),
+            variables_added,
+            variables_removed,
+        };
+        let changed = change.blocks_added + change.blocks_removed > 0
+            || !change.assets_added.is_empty()
+            || !change.assets_removed.is_empty()
+            || !change.assets_modified.is_empty()
+            || !change.variables_added.is_empty()
+            || !change.variables_removed.is_empty();
+        if changed {
+            changes.push(change);
+        }
+    }
+    Ok(ProjectChanges {
+        sprites: changes,
+        extensions: compare::extensions(old, new),
+    })
+}
+
+/// A sprite's scripts as text, which is empty for a sprite that doesn't exist
+fn script(target: Option<&Target>) -> Result<String> {
+    target.map_or(Ok(String::new()), |target| parse_sprite(Sprite::of(target)))
+}
+
+fn variables(target: Option<&Target>) -> Vec<String> {
+    target
+        .into_iter()
+        .flat_map(Target::variables)
+        .map(|variable| variable.name.to_string())
+        .collect()
+}
+
+/// Items only in `new` and only in `old`, counting duplicates
+fn added_removed(old: Vec<String>, new: Vec<String>) -> (Vec<String>, Vec<String>) {
+    let mut counts = HashMap::<String, isize>::new();
+    for item in &new {
+        *counts.entry(item.clone()).or_default() += 1;
+    }
+    for item in &old {
+        *counts.entry(item.clone()).or_default() -= 1;
+    }
+    let (mut added, mut removed) = (vec![], vec![]);
+    for (item, count) in counts {
+        let side = if count > 0 { &mut added } else { &mut removed };
+        for _ in 0..count.unsigned_abs() {
+            side.push(item.clone());
+        }
+    }
+    added.sort();
+    removed.sort();
+    (added, removed)
+}
@@ -0,0 +1,196 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError};
+use std::thread::spawn;
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use serde_json::json;
+use tracing::{debug, info, warn};
+
+use crate::config::{self, project_config, Settings, SETTINGS_FILE};
+use crate::diff::structs::Diff;
+use crate::events::broadcast;
+use crate::logging;
+use crate::observer::Quiet;
+use crate::ops::{self, CommitOptions, CommitOutcome};
+use crate::registry;
+use crate::sb3::{self, read_project_json};
+use crate::shutdown;
+
+/// How long the SB3 has to stay untouched before a save is considered finished
+const DEBOUNCE: Duration = Duration::from_millis(1000);
+
+/// Compare a saved SB3 against the last commit and tell clients what changed
+fn notify_changes(project_name: &str, pth: &PathBuf, sb3: &PathBuf) -> Result<()> {
+    let saved = Diff::new(read_project_json(sb3)?);
+    let committed = Diff::from_revision(pth, "HEAD:project.json")?;
+    let settings = config::settings(Some(pth));
+    let changes = committed
+        .commits(pth, &saved)?
+        .into_iter()
+        .filter(|line| !settings.is_ignored_line(line))
+        .collect::<Vec<_>>();
+
+    broadcast(json!({
+        "type": "changes-pending",
+        "project_name": project_name,
+        "changes": changes,
+    }));
+    Ok(())
+}
+
+/// Unzip a saved SB3 and commit it, if it has enough changes since the last commit
+///
+/// Runs like the `unzip` and `commit` commands would, taking turns with them
+fn auto_commit(project_name: &str, pth: &PathBuf, sb3: &PathBuf, min_changes: usize) -> Result<()> {
+    let _operation = shutdown::begin_operation()?;
+    let project = registry::project(project_name)?;
+    let _lock = project.operation.lock().unwrap();
+
+    let saved = Diff::new(read_project_json(sb3)?);
+    let committed = Diff::from_revision(pth, "HEAD:project.json")?;
+    let changes = ops::compare(pth, &committed, &saved, &mut Quiet)?.size();
+    if changes < min_changes.max(1) {
+        debug!(project = %project_name, changes, "too few changes to auto-commit");
+        return Ok(());
+    }
+
+    fs::copy(pth.join("project.json"), pth.join("project.old.json"))?;
+    sb3::unpack(sb3, pth)?;
+    let asset_store = project_config()
+        .lock()
+        .unwrap()
+        .flag(project_name, "asset_store");
+    let options = CommitOptions {
+        author: None,
+        asset_store,
+        selection: None,
+        title: None,
+        editor: None,
+        sb3: Some(sb3.as_path()),
+    };
+    match ops::commit(pth, options, &mut Quiet)? {
+        CommitOutcome::Committed(message) => {
+            info!(project = %project_name, "auto-committed: {message}");
+            broadcast(json!({
+                "type": "auto-committed",
+                "project_name": project_name,
+                "message": message,
+            }));
+        }
+        failure => warn!(project = %project_name, code = failure.code(), "failed to auto-commit"),
+    }
+    Ok(())
+}
+
+/// Wait until nothing has been sent for `quiet`, returning false once the sender is dropped
+fn settle(rx: &Receiver<()>, quiet: Duration) -> bool {
+    loop {
+        match rx.recv_timeout(quiet) {
+            Ok(()) => continue,
+            Err(RecvTimeoutError::Timeout) => return true,
+            Err(RecvTimeoutError::Disconnected) => return false,
+        }
+    }
+}
+
+/// Watch a project's SB3 for saves, broadcasting a `changes-pending` event after each one
+///
+/// With `auto_commit` turned on, the project is also committed once saves stop for its
+/// debounce time. The project is watched until the returned watcher is dropped
+pub fn watch(project_name: &str, pth: PathBuf, sb3: PathBuf) -> Result<RecommendedWatcher> {
+    let (tx, rx) = channel();
+    let target = sb3.clone();
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+        if event.is_ok_and(|e| e.paths.contains(&target)) {
+            let _ = tx.send(());
+        }
+    })?;
+
+    // editors often save by writing a new file and renaming it, so watch the whole folder
+    let folder = sb3.parent().ok_or(anyhow!("project file has no folder"))?;
+    watcher.watch(folder, RecursiveMode::NonRecursive)?;
+
+    let name = project_name.to_string();
+    spawn(move || {
+        // ends once the watcher (and the sender it owns) is dropped
+        let mut saved = rx.recv().is_ok();
+        while saved {
+            if !settle(&rx, DEBOUNCE) {
+                return;
+            }
+            if let Err(e) = notify_changes(&name, &pth, &sb3) {
+                warn!(project = %name, "failed to check for changes: {e:#}");
+            }
+
+            let settings = config::settings(Some(&pth)).auto_commit;
+            if settings.enabled {
+                match rx.recv_timeout(Duration::from_secs(settings.debounce_seconds)) {
+                    // saved again before the debounce ran out
+                    Ok(()) => continue,
+                    Err(RecvTimeoutError::Timeout) => {
+                        if let Err(e) = auto_commit(&name, &pth, &sb3, settings.min_changes) {
+                            warn!(project = %name, "failed to auto-commit: {e:#}");
+                        }
+                    }
+                    Err(RecvTimeoutError::Disconnected) => return,
+                }
+            }
+            saved = rx.recv().is_ok();
+        }
+    });
+
+    Ok(watcher)
+}
+
+/// Apply what can change without a restart, and tell clients settings changed
+///
+/// Ignored sprites and commit styles are read fresh whenever they're used, so only the log
+/// level needs applying here
+fn apply_settings(previous: &Settings) -> Settings {
+    let settings = config::settings(None);
+
+    if settings.log != previous.log && !logging::is_overridden() {
+        let level = settings.log.level.as_deref().unwrap_or(logging::DEFAULT_LEVEL);
+        if let Err(e) = logging::set_level(level) {
+            warn!("invalid log level {level}: {e:#}");
+        }
+    }
+    if settings.server != previous.server {
+        warn!("server settings only take effect after restarting");
+    }
+
+    info!("reloaded {SETTINGS_FILE}");
+    broadcast(json!({ "type": "settings-changed" }));
+    settings
+}
+
+/// Watch the global settings file, applying changes as it's saved
+///
+/// The file is watched until the returned watcher is dropped
+pub fn watch_settings() -> Result<RecommendedWatcher> {
+    let (tx, rx) = channel();
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+        let is_settings = |path: &PathBuf| path.file_name().is_some_and(|n| n == SETTINGS_FILE);
+        if event.is_ok_and(|e| e.paths.iter().any(is_settings)) {
+            let _ = tx.send(());
+        }
+    })?;
+
+    // the file may not exist yet, and is often replaced rather than written to
+    watcher.watch(Path::new("."), RecursiveMode::NonRecursive)?;
+
+    spawn(move || {
+        let mut settings = config::settings(None);
+        while rx.recv().is_ok() {
+            if !settle(&rx, DEBOUNCE) {
+                return;
+            }
+            settings = apply_settings(&settings);
+        }
+    });
+
+    Ok(watcher)
+}